@@ -1,8 +1,14 @@
-use crate::hittable::{HitRecord, Hittable};
+#[cfg(feature = "std")]
+use crate::hittable::{HitRecord, Hittable, UNIFORM_SPHERE_PDF, uniform_sphere_direction};
 use crate::interval::Interval;
+use crate::point3::Point3;
 use crate::ray::Ray;
+use crate::vec3::Vec3;
+#[cfg(feature = "std")]
+use rand::{Rng, RngCore};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Aabb {
     x: Interval,
     y: Interval,
@@ -34,6 +40,33 @@ impl Aabb {
         }
     }
 
+    /// Returns the point at the middle of the box.
+    #[inline]
+    pub fn center(&self) -> Point3 {
+        Point3::new(
+            (self.x.min() + self.x.max()) / 2.0,
+            (self.y.min() + self.y.max()) / 2.0,
+            (self.z.min() + self.z.max()) / 2.0,
+        )
+    }
+
+    /// Returns the vector from the minimum corner of the box to the maximum corner.
+    #[inline]
+    pub fn diagonal(&self) -> Vec3 {
+        Vec3::new(
+            self.x.max() - self.x.min(),
+            self.y.max() - self.y.min(),
+            self.z.max() - self.z.min(),
+        )
+    }
+
+    /// Returns the total area of the box's six faces.
+    #[inline]
+    pub fn surface_area(&self) -> f64 {
+        let d = self.diagonal();
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
     #[inline]
     pub fn axis_interval(&self, axis: usize) -> Interval {
         match axis {
@@ -43,11 +76,11 @@ impl Aabb {
             _ => panic!("Invalid axis index"),
         }
     }
-}
 
-impl Hittable for Aabb {
+    /// Returns the distance along `ray` at which it enters the box within `ray_t`,
+    /// or `None` if it misses.
     #[inline]
-    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    pub fn intersect(&self, ray: &Ray, ray_t: Interval) -> Option<f64> {
         let ray_origin = ray.origin();
         let ray_direction = ray.direction();
 
@@ -69,7 +102,7 @@ impl Hittable for Aabb {
             let mut t1 = (axis_interval.max() - origin_component) * inv_d;
 
             if inv_d < 0.0 {
-                std::mem::swap(&mut t0, &mut t1);
+                core::mem::swap(&mut t0, &mut t1);
             }
 
             // Update interval
@@ -81,10 +114,43 @@ impl Hittable for Aabb {
             }
         }
 
-        // If we've made it here, there is a hit
+        Some(t_min)
+    }
+
+    /// Returns the faces of the box that face `origin`, as the axis each is
+    /// perpendicular to and the coordinate of its plane, with their total area.
+    ///
+    /// A point inside the box sees no faces from outside.
+    #[cfg(feature = "std")]
+    fn visible_faces(&self, origin: &Point3) -> (Vec<(usize, f64)>, f64) {
+        let origin = origin.as_vec3();
+        let extent = self.diagonal();
+        let mut faces = Vec::with_capacity(3);
+        let mut area = 0.0;
+        for axis in 0..3 {
+            let interval = self.axis_interval(axis);
+            let plane = if origin[axis] < interval.min() {
+                interval.min()
+            } else if origin[axis] > interval.max() {
+                interval.max()
+            } else {
+                continue;
+            };
+            faces.push((axis, plane));
+            area += extent[(axis + 1) % 3] * extent[(axis + 2) % 3];
+        }
+        (faces, area)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Hittable for Aabb {
+    #[inline]
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let t = self.intersect(ray, ray_t)?;
         Some(HitRecord {
-            t: t_min,
-            position: ray.at_time(t_min),
+            t,
+            position: ray.at_time(t),
             ..Default::default()
         })
     }
@@ -92,15 +158,76 @@ impl Hittable for Aabb {
     fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
         Some(*self)
     }
+
+    fn is_sampleable(&self) -> bool {
+        true
+    }
+
+    /// Points are sampled uniformly over the faces visible from `origin`, which
+    /// every ray into the box enters through exactly one of.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let (faces, area) = self.visible_faces(origin);
+        if faces.is_empty() {
+            return UNIFORM_SPHERE_PDF;
+        }
+        if area <= 0.0 {
+            return 0.0;
+        }
+
+        let origin = origin.as_vec3();
+        let direction = direction.unit();
+        for (axis, plane) in faces {
+            let t = (plane - origin[axis]) / direction[axis];
+            if t.is_nan() || t <= 0.0 {
+                continue;
+            }
+            let point = origin + direction * t;
+            let on_face = (1..3).all(|offset| {
+                let other = (axis + offset) % 3;
+                let interval = self.axis_interval(other);
+                (interval.min()..=interval.max()).contains(&point[other])
+            });
+            if on_face {
+                return t * t / (direction[axis].abs() * area);
+            }
+        }
+        0.0
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        let (faces, area) = self.visible_faces(origin);
+        if faces.is_empty() {
+            return uniform_sphere_direction((rng.random(), rng.random()));
+        }
+
+        let extent = self.diagonal();
+        let face_area = |axis: usize| extent[(axis + 1) % 3] * extent[(axis + 2) % 3];
+        let mut pick = rng.random::<f64>() * area;
+        let &(axis, plane) = faces
+            .iter()
+            .find(|(axis, _)| {
+                pick -= face_area(*axis);
+                pick < 0.0
+            })
+            .unwrap_or(&faces[faces.len() - 1]);
+
+        let mut point = Vec3::default();
+        point[axis] = plane;
+        for offset in 1..3 {
+            let other = (axis + offset) % 3;
+            let interval = self.axis_interval(other);
+            point[other] = interval.min() + rng.random::<f64>() * extent[other];
+        }
+        (Point3::from(point) - *origin).unit()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "std")]
     use crate::hittable::Hittable;
-    use crate::point3::Point3;
     use crate::ray::Ray;
-    use crate::vec3::Vec3;
 
     #[test]
     fn test_default() {
@@ -135,6 +262,18 @@ mod tests {
         assert_eq!(aabb.axis_interval(2), Interval::new(5.0, 6.0));
     }
 
+    #[test]
+    fn test_center_and_diagonal() {
+        let aabb = Aabb::new(
+            Interval::new(1.0, 3.0),
+            Interval::new(-2.0, 2.0),
+            Interval::new(5.0, 6.0),
+        );
+        assert_eq!(aabb.center(), Point3::new(2.0, 0.0, 5.5));
+        assert_eq!(aabb.diagonal(), Vec3::new(2.0, 4.0, 1.0));
+        assert_eq!(aabb.surface_area(), 28.0);
+    }
+
     #[test]
     #[should_panic(expected = "Invalid axis index")]
     fn test_axis_interval_invalid() {
@@ -142,6 +281,27 @@ mod tests {
         aabb.axis_interval(3); // Should panic
     }
 
+    #[test]
+    fn test_intersect() {
+        let aabb = Aabb::new(
+            Interval::new(-1.0, 1.0),
+            Interval::new(-1.0, 1.0),
+            Interval::new(-1.0, 1.0),
+        );
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -2.0), 0.0);
+        assert_eq!(
+            aabb.intersect(&ray, Interval::new(0.0, f64::INFINITY)),
+            Some(2.0)
+        );
+        assert_eq!(aabb.intersect(&ray, Interval::new(0.0, 1.0)), None);
+        let away = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert_eq!(
+            aabb.intersect(&away, Interval::new(0.0, f64::INFINITY)),
+            None
+        );
+    }
+
+    #[cfg(feature = "std")]
     #[test]
     fn test_hit_inside_box() {
         let aabb = Aabb::new(
@@ -155,6 +315,7 @@ mod tests {
         assert!(hit.is_some());
     }
 
+    #[cfg(feature = "std")]
     #[test]
     fn test_hit_from_outside() {
         let aabb = Aabb::new(
@@ -168,6 +329,7 @@ mod tests {
         assert!(hit.is_some());
     }
 
+    #[cfg(feature = "std")]
     #[test]
     fn test_miss() {
         let aabb = Aabb::new(
@@ -185,6 +347,7 @@ mod tests {
         assert!(hit.is_none());
     }
 
+    #[cfg(feature = "std")]
     #[test]
     fn test_hit_with_t_interval() {
         let aabb = Aabb::new(
@@ -204,6 +367,7 @@ mod tests {
         assert!(hit2.is_none());
     }
 
+    #[cfg(feature = "std")]
     #[test]
     fn test_hit_negative_direction() {
         let aabb = Aabb::new(
@@ -217,6 +381,7 @@ mod tests {
         assert!(hit.is_some());
     }
 
+    #[cfg(feature = "std")]
     #[test]
     fn test_hit_parallel_to_axis() {
         let aabb = Aabb::new(
@@ -245,4 +410,41 @@ mod tests {
                 .is_some()
         );
     }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_sampling_directions_towards_box() {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+
+        let aabb = Aabb::new(
+            Interval::new(1.0, 2.0),
+            Interval::new(-0.5, 1.5),
+            Interval::new(2.0, 5.0),
+        );
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let mut rng = StdRng::seed_from_u64(4);
+
+        for _ in 0..100 {
+            let direction = aabb.random(&origin, &mut rng);
+            let ray = Ray::new(origin, direction, 0.0);
+            assert!(
+                aabb.hit(&ray, Interval::new(0.001, f64::INFINITY))
+                    .is_some()
+            );
+            assert!(aabb.pdf_value(&origin, &direction) > 0.0);
+        }
+        assert_eq!(aabb.pdf_value(&origin, &Vec3::new(0.0, 0.0, -1.0)), 0.0);
+
+        // The density integrates to one over the sphere of directions
+        let samples = 200_000;
+        let total: f64 = (0..samples)
+            .map(|_| {
+                let direction = uniform_sphere_direction((rng.random(), rng.random()));
+                aabb.pdf_value(&origin, &direction) / UNIFORM_SPHERE_PDF
+            })
+            .sum();
+        let integral = total / samples as f64;
+        assert!((integral - 1.0).abs() < 0.05, "integral {}", integral);
+    }
 }
@@ -0,0 +1,162 @@
+//! Exposure analysis of a finished render: luminance histogram and false-color map.
+
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use std::fmt;
+
+/// Number of histogram bins across the displayable luminance range.
+const HISTOGRAM_BINS: usize = 16;
+/// Width in characters of the longest histogram bar.
+const HISTOGRAM_WIDTH: usize = 50;
+/// Linear luminance of middle gray, the reference for exposure stops.
+const MIDDLE_GRAY: f64 = 0.18;
+/// Linear values below this all round to a zero byte in the PPM output.
+const CRUSHED_THRESHOLD: f64 = 1.0 / (256.0 * 256.0);
+
+/// Distribution of display luminance over an image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuminanceHistogram {
+    /// Pixel counts for equal-width bins of gamma-encoded luminance in [0, 1)
+    bins: [u32; HISTOGRAM_BINS],
+    /// Pixels that display as pure black
+    crushed: u32,
+    /// Pixels with at least one channel at or past full brightness
+    clipped: u32,
+    total: u32,
+}
+
+impl LuminanceHistogram {
+    pub fn new(framebuffer: &Framebuffer) -> Self {
+        let mut histogram = LuminanceHistogram {
+            bins: [0; HISTOGRAM_BINS],
+            crushed: 0,
+            clipped: 0,
+            total: 0,
+        };
+
+        for pixel in framebuffer.pixels() {
+            histogram.total += 1;
+            if is_clipped(pixel) {
+                histogram.clipped += 1;
+            } else if pixel.luminance() < CRUSHED_THRESHOLD {
+                histogram.crushed += 1;
+            }
+            let display = Color::linear_to_gamma(pixel.luminance()).min(1.0);
+            let bin = ((display * HISTOGRAM_BINS as f64) as usize).min(HISTOGRAM_BINS - 1);
+            histogram.bins[bin] += 1;
+        }
+
+        histogram
+    }
+
+    fn percent(&self, count: u32) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            100.0 * count as f64 / self.total as f64
+        }
+    }
+}
+
+impl fmt::Display for LuminanceHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let largest = self.bins.iter().copied().max().unwrap_or(0).max(1);
+        for (i, &count) in self.bins.iter().enumerate() {
+            let bar = count as usize * HISTOGRAM_WIDTH / largest as usize;
+            writeln!(
+                f,
+                "{:.3}-{:.3} {:>6.2}% {}",
+                i as f64 / HISTOGRAM_BINS as f64,
+                (i + 1) as f64 / HISTOGRAM_BINS as f64,
+                self.percent(count),
+                "#".repeat(bar)
+            )?;
+        }
+        writeln!(f, "Crushed shadows: {:.2}%", self.percent(self.crushed))?;
+        write!(f, "Clipped highlights: {:.2}%", self.percent(self.clipped))
+    }
+}
+
+/// Replaces every pixel with a color coding its exposure relative to middle gray.
+///
+/// Crushed blacks are purple, deep shadows blue, shadows cyan, mid-tones gray with
+/// middle gray itself green, highlights yellow and clipped pixels red.
+pub fn false_color(framebuffer: &Framebuffer) -> Framebuffer {
+    let pixels = framebuffer.pixels().iter().map(exposure_color).collect();
+    Framebuffer::new(framebuffer.width(), framebuffer.height(), pixels)
+}
+
+fn is_clipped(pixel: &Color) -> bool {
+    pixel.r() >= 1.0 || pixel.g() >= 1.0 || pixel.b() >= 1.0
+}
+
+fn exposure_color(pixel: &Color) -> Color {
+    if is_clipped(pixel) {
+        return Color::new(1.0, 0.0, 0.0);
+    }
+    let luminance = pixel.luminance();
+    if luminance < CRUSHED_THRESHOLD {
+        return Color::new(0.25, 0.0, 0.4);
+    }
+
+    let stops = (luminance / MIDDLE_GRAY).log2();
+    match stops {
+        s if s < -4.0 => Color::new(0.0, 0.0, 0.8),
+        s if s < -2.0 => Color::new(0.0, 0.5, 0.6),
+        s if s.abs() <= 0.25 => Color::new(0.0, 0.6, 0.0),
+        s if s < 1.5 => {
+            let gray = Color::gamma_to_linear(0.5);
+            Color::new(gray, gray, gray)
+        }
+        _ => Color::new(0.8, 0.8, 0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(pixels: Vec<Color>) -> Framebuffer {
+        Framebuffer::new(pixels.len() as u32, 1, pixels)
+    }
+
+    #[test]
+    fn test_histogram_counts() {
+        let histogram = LuminanceHistogram::new(&image(vec![
+            Color::new(0.0, 0.0, 0.0),
+            Color::new(0.18, 0.18, 0.18),
+            Color::new(1.0, 1.0, 1.0),
+            Color::new(2.0, 0.0, 0.0),
+        ]));
+
+        assert_eq!(histogram.total, 4);
+        assert_eq!(histogram.crushed, 1);
+        assert_eq!(histogram.clipped, 2);
+        assert_eq!(histogram.bins.iter().sum::<u32>(), 4);
+        assert_eq!(histogram.bins[0], 1);
+        assert_eq!(histogram.bins[HISTOGRAM_BINS - 1], 1);
+    }
+
+    #[test]
+    fn test_histogram_display() {
+        let histogram = LuminanceHistogram::new(&image(vec![Color::new(1.0, 1.0, 1.0); 2]));
+        let text = histogram.to_string();
+        assert_eq!(text.lines().count(), HISTOGRAM_BINS + 2);
+        assert!(text.ends_with("Clipped highlights: 100.00%"));
+    }
+
+    #[test]
+    fn test_false_color_bands() {
+        let mapped = false_color(&image(vec![
+            Color::new(0.0, 0.0, 0.0),
+            Color::new(0.18, 0.18, 0.18),
+            Color::new(0.005, 0.005, 0.005),
+            Color::new(1.5, 0.5, 0.5),
+        ]));
+        let pixels = mapped.pixels();
+        assert_eq!(pixels[0], Color::new(0.25, 0.0, 0.4));
+        assert_eq!(pixels[1], Color::new(0.0, 0.6, 0.0));
+        assert_eq!(pixels[2], Color::new(0.0, 0.0, 0.8));
+        assert_eq!(pixels[3], Color::new(1.0, 0.0, 0.0));
+    }
+}
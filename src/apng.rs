@@ -0,0 +1,323 @@
+//! PNG output for single images, and animated PNG for frame sequences such as
+//! turntables.
+//!
+//! APNG needs no external tools and plays in every current web browser, unlike
+//! GIF it keeps full 24-bit color. Rows are Sub filtered and deflated with the
+//! fixed Huffman codes and a single-candidate LZ77 match search, which is far
+//! from optimal but needs no dependency; noisy renders compress least.
+
+use crate::framebuffer::{Framebuffer, ImageError};
+use std::io::{self, Write};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+/// Distance the LZ77 search may reach back, the most deflate can encode.
+const WINDOW: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Writes the frame as a PNG, encoded with its display transform.
+pub fn write_png(frame: &Framebuffer, out: &mut impl Write) -> io::Result<()> {
+    out.write_all(&SIGNATURE)?;
+    write_chunk(out, b"IHDR", &image_header(frame.width(), frame.height()))?;
+    write_chunk(out, b"IDAT", &zlib(&filtered_rows(frame)))?;
+    write_chunk(out, b"IEND", &[])
+}
+
+/// Writes equally sized frames as an animated PNG that loops forever at `fps`.
+///
+/// Each frame is encoded with its own display transform, as its PPM would be.
+pub fn write_apng(
+    frames: &[Framebuffer],
+    fps: u32,
+    out: &mut impl Write,
+) -> Result<(), ImageError> {
+    let first = frames.first().ok_or(ImageError::NoImages)?;
+    let (width, height) = (first.width(), first.height());
+    if frames
+        .iter()
+        .any(|f| f.width() != width || f.height() != height)
+    {
+        return Err(ImageError::SizeMismatch);
+    }
+
+    out.write_all(&SIGNATURE)?;
+    write_chunk(out, b"IHDR", &image_header(width, height))?;
+
+    let mut animation = Vec::new();
+    animation.extend((frames.len() as u32).to_be_bytes());
+    // Loop forever
+    animation.extend(0u32.to_be_bytes());
+    write_chunk(out, b"acTL", &animation)?;
+
+    let delay_denominator = fps.clamp(1, u16::MAX as u32) as u16;
+    let mut sequence = 0u32;
+    for (index, frame) in frames.iter().enumerate() {
+        let mut control = Vec::new();
+        control.extend(sequence.to_be_bytes());
+        control.extend(width.to_be_bytes());
+        control.extend(height.to_be_bytes());
+        // No offset, shown for 1/fps seconds, no disposal and replacing what was there
+        control.extend(0u32.to_be_bytes());
+        control.extend(0u32.to_be_bytes());
+        control.extend(1u16.to_be_bytes());
+        control.extend(delay_denominator.to_be_bytes());
+        control.extend([0, 0]);
+        write_chunk(out, b"fcTL", &control)?;
+        sequence += 1;
+
+        let data = zlib(&filtered_rows(frame));
+        if index == 0 {
+            write_chunk(out, b"IDAT", &data)?;
+        } else {
+            let mut frame_data = sequence.to_be_bytes().to_vec();
+            frame_data.extend(data);
+            write_chunk(out, b"fdAT", &frame_data)?;
+            sequence += 1;
+        }
+    }
+    write_chunk(out, b"IEND", &[])?;
+    Ok(())
+}
+
+/// The contents of the IHDR chunk for an 8-bit RGB image.
+fn image_header(width: u32, height: u32) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // 8 bits per channel RGB, deflate, adaptive filtering, no interlacing
+    header.extend([8, 2, 0, 0, 0]);
+    header
+}
+
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data));
+    out.write_all(&crc.to_be_bytes())
+}
+
+/// Encodes the frame's rows as bytes, each row Sub filtered so smooth gradients
+/// become runs of small repeating differences.
+fn filtered_rows(frame: &Framebuffer) -> Vec<u8> {
+    let row_bytes = frame.width() as usize * 3;
+    let mut data = Vec::with_capacity((row_bytes + 1) * frame.height() as usize);
+    for row in frame.pixels().chunks(frame.width() as usize) {
+        let bytes: Vec<u8> = row.iter().flat_map(|pixel| frame.encode(*pixel)).collect();
+        data.push(1);
+        data.extend(
+            (0..row_bytes).map(|i| bytes[i].wrapping_sub(if i >= 3 { bytes[i - 3] } else { 0 })),
+        );
+    }
+    data
+}
+
+fn crc32<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u32 {
+    !bytes.into_iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (a, b) = bytes.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
+
+/// Wraps the deflated `data` in a zlib stream.
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    stream.extend(deflate(data));
+    stream.extend(adler32(data).to_be_bytes());
+    stream
+}
+
+/// Writes bits least significant first, as deflate packs them.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.buffer |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which deflate stores most significant bit first.
+    fn code(&mut self, code: u32, length: u32) {
+        self.bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    /// Writes a literal byte or end-of-block symbol with the fixed codes.
+    fn symbol(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Compresses `data` as a single deflate block with the fixed Huffman codes.
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    // Final block, fixed Huffman codes
+    writer.bits(1, 1);
+    writer.bits(1, 2);
+
+    let hash = |i: usize| {
+        let key = u32::from_le_bytes([data[i], data[i + 1], data[i + 2], 0]);
+        (key.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    };
+    let mut latest = vec![usize::MAX; 1 << HASH_BITS];
+    let mut i = 0;
+    while i < data.len() {
+        let mut length = 0;
+        let mut distance = 0;
+        if i + MIN_MATCH <= data.len() {
+            let candidate = latest[hash(i)];
+            if candidate != usize::MAX && i - candidate <= WINDOW {
+                let limit = MAX_MATCH.min(data.len() - i);
+                length = (0..limit)
+                    .take_while(|&k| data[candidate + k] == data[i + k])
+                    .count();
+                distance = i - candidate;
+            }
+        }
+
+        let step = if length >= MIN_MATCH {
+            let code = LENGTH_BASE.partition_point(|&base| base as usize <= length) - 1;
+            writer.symbol(257 + code as u32);
+            writer.bits(
+                (length - LENGTH_BASE[code] as usize) as u32,
+                LENGTH_EXTRA[code] as u32,
+            );
+            let code = DISTANCE_BASE.partition_point(|&base| base as usize <= distance) - 1;
+            writer.code(code as u32, 5);
+            writer.bits(
+                (distance - DISTANCE_BASE[code] as usize) as u32,
+                DISTANCE_EXTRA[code] as u32,
+            );
+            length
+        } else {
+            writer.symbol(data[i] as u32);
+            1
+        };
+        for j in i..(i + step).min(data.len().saturating_sub(MIN_MATCH - 1)) {
+            latest[hash(j)] = j;
+        }
+        i += step;
+    }
+    writer.symbol(256);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn test_deflate_fixed_codes() {
+        // A literal, a run back-referencing it and the end of block
+        assert_eq!(deflate(b"aaaa"), [0x4b, 0x04, 0x02, 0x00]);
+        // Long inputs compress to a fraction of their size
+        let repetitive: Vec<u8> = (0..10_000).map(|i| (i % 7) as u8).collect();
+        assert!(deflate(&repetitive).len() < 200);
+    }
+
+    #[test]
+    fn test_write_png() {
+        let frame = Framebuffer::new(2, 1, vec![Color::new(1.0, 0.0, 0.0); 2]);
+        let mut out = Vec::new();
+        write_png(&frame, &mut out).unwrap();
+
+        assert_eq!(out[..8], SIGNATURE);
+        assert_eq!(&out[12..16], b"IHDR");
+        assert_eq!(out[16..29], [0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+        assert_eq!(&out[37..41], b"IDAT");
+        assert_eq!(
+            out[out.len() - 12..],
+            [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]
+        );
+    }
+
+    #[test]
+    fn test_write_apng() {
+        let frame = |v: f64| Framebuffer::new(2, 1, vec![Color::new(v, v, v); 2]);
+        let mut out = Vec::new();
+        write_apng(&[frame(0.0), frame(1.0), frame(0.5)], 24, &mut out).unwrap();
+
+        assert_eq!(out[..8], SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut rest = &out[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            chunks.push((rest[4..8].to_vec(), rest[8..8 + length].to_vec()));
+            rest = &rest[12 + length..];
+        }
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| kind.as_slice()).collect();
+        assert_eq!(
+            kinds,
+            [
+                b"IHDR", b"acTL", b"fcTL", b"IDAT", b"fcTL", b"fdAT", b"fcTL", b"fdAT", b"IEND"
+            ]
+        );
+        // Three frames, and sequence numbers counting up through the frame chunks
+        assert_eq!(chunks[1].1[..4], 3u32.to_be_bytes());
+        assert_eq!(chunks[6].1[..4], 3u32.to_be_bytes());
+        assert_eq!(chunks[7].1[..4], 4u32.to_be_bytes());
+
+        assert!(matches!(
+            write_apng(&[], 24, &mut Vec::new()),
+            Err(ImageError::NoImages)
+        ));
+    }
+}
@@ -0,0 +1,177 @@
+//! What rays that escape the scene see.
+
+use crate::color::Color;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+const WHITE: Color = Color::new(1.0, 1.0, 1.0);
+const SKY_BLUE: Color = Color::new(0.5, 0.7, 1.0);
+/// Cells across each axis of the grid the starfield is scattered over.
+const STAR_GRID: f64 = 128.0;
+/// Angular radius of a star, in radians.
+const STAR_RADIUS: f64 = 0.002;
+
+/// Radiance arriving from infinitely far away in every direction.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Background {
+    /// A vertical gradient from white at the horizon to blue overhead
+    #[default]
+    Sky,
+    /// The same color in every direction, e.g. white for a furnace test
+    Uniform(Color),
+    /// A dark sky with a procedural starfield and a moon
+    Night(NightSky),
+}
+
+/// A night sky: a faint glow, scattered stars and the disk of the moon.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NightSky {
+    /// Radiance of the sky between the stars
+    pub sky: Color,
+    /// Fraction of the starfield's cells that hold a star, in [0, 1]
+    pub star_density: f64,
+    /// Radiance of the brightest stars
+    pub star_brightness: f64,
+    /// Direction towards the center of the moon
+    pub moon_direction: Vec3,
+    /// Angular radius of the moon, in radians
+    pub moon_radius: f64,
+    /// Radiance of the moon's disk
+    pub moon: Color,
+}
+
+impl Default for NightSky {
+    fn default() -> Self {
+        Self {
+            sky: Color::new(0.002, 0.003, 0.008),
+            star_density: 0.05,
+            star_brightness: 2.0,
+            moon_direction: Vec3::new(-0.4, 0.5, -1.0),
+            moon_radius: 0.02,
+            moon: Color::new(2.0, 2.0, 1.8),
+        }
+    }
+}
+
+impl NightSky {
+    /// Returns the radiance seen looking in `direction`.
+    pub fn color(&self, direction: &Vec3) -> Color {
+        let direction = direction.unit();
+        if direction.dot(&self.moon_direction.unit()) > self.moon_radius.cos() {
+            return self.moon;
+        }
+        self.sky + self.star(&direction)
+    }
+
+    /// Returns the radiance of the star, if any, in the grid cell `direction` falls in.
+    ///
+    /// Each cell hashes to whether it holds a star, where in the cell it sits and how
+    /// bright it is, so the starfield needs no storage and never changes.
+    fn star(&self, direction: &Vec3) -> Color {
+        let scaled = *direction * STAR_GRID;
+        let cell = (
+            scaled.x().floor() as i64,
+            scaled.y().floor() as i64,
+            scaled.z().floor() as i64,
+        );
+        let mut hasher = DefaultHasher::new();
+        cell.hash(&mut hasher);
+        let hash = hasher.finish();
+        // Successive 16-bit slices of the hash give independent values in [0, 1)
+        let value = |slice: u32| ((hash >> (16 * slice)) & 0xffff) as f64 / 65536.0;
+
+        if value(0) >= self.star_density {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        // Stars keep away from the cell walls so none is cut off by its neighbor
+        let offset = |slice: u32| 0.25 + 0.5 * value(slice);
+        let star = Vec3::new(
+            cell.0 as f64 + offset(1),
+            cell.1 as f64 + offset(2),
+            cell.2 as f64 + offset(3),
+        );
+        if direction.dot(&star.unit()) > STAR_RADIUS.cos() {
+            WHITE * (self.star_brightness * value(1))
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+impl Background {
+    /// Returns the radiance seen along the ray's direction.
+    pub fn color(&self, ray: &Ray) -> Color {
+        match self {
+            Background::Sky => {
+                let unit_direction = ray.direction().unit();
+                let t = 0.5 * (unit_direction.y() + 1.0);
+                WHITE * (1.0 - t) + SKY_BLUE * t
+            }
+            Background::Uniform(color) => *color,
+            Background::Night(night) => night.color(ray.direction()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point3::Point3;
+
+    #[test]
+    fn test_sky_gradient() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let up = Ray::new(origin, Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let down = Ray::new(origin, Vec3::new(0.0, -1.0, 0.0), 0.0);
+        assert_eq!(Background::Sky.color(&up), SKY_BLUE);
+        assert_eq!(Background::Sky.color(&down), WHITE);
+    }
+
+    #[test]
+    fn test_uniform() {
+        let color = Color::new(0.2, 0.3, 0.4);
+        let ray = Ray::new(Point3::new(1.0, 2.0, 3.0), Vec3::new(0.3, -0.2, 0.9), 0.0);
+        assert_eq!(Background::Uniform(color).color(&ray), color);
+    }
+
+    #[test]
+    fn test_night_sky_moon() {
+        let night = NightSky::default();
+        assert_eq!(night.color(&night.moon_direction), night.moon);
+        assert_eq!(night.color(&(night.moon_direction * 3.0)), night.moon);
+    }
+
+    #[test]
+    fn test_night_sky_stars() {
+        let night = NightSky::default();
+        let black = Color::new(0.0, 0.0, 0.0);
+
+        // Scan the sky finely enough to land on stars
+        let mut directions = 0;
+        let mut stars = 0;
+        for i in 0..400 {
+            for j in 0..400 {
+                let direction = Vec3::new(i as f64 / 400.0 - 0.5, 1.0, j as f64 / 400.0 - 0.5);
+                let color = night.color(&direction);
+                assert!(color.r() >= night.sky.r() && color.r() <= night.star_brightness + 1e-9);
+                directions += 1;
+                if color != night.sky {
+                    stars += 1;
+                    // The same direction always sees the same star
+                    assert_eq!(night.color(&direction), color);
+                }
+            }
+        }
+        assert!(stars > 0 && stars < directions / 100, "{} stars", stars);
+
+        let starless = NightSky {
+            star_density: 0.0,
+            ..NightSky::default()
+        };
+        assert_eq!(starless.star(&Vec3::new(0.0, 1.0, 0.0)), black);
+    }
+}
@@ -1,30 +1,120 @@
 use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::interval::Interval;
+use crate::material::Material;
+use crate::point3::Point3;
 use crate::ray::Ray;
+use crate::vec3::Vec3;
+use rand::{Rng, RngCore};
+use std::cell::Cell;
 use std::cmp::Ordering;
 use std::error::Error;
 use std::fmt;
+use std::sync::Arc;
 
-/// A Bounding Volume Hierarchy (BVH) acceleration structure for ray tracing.
-/// This structure organizes objects in a binary tree to accelerate ray-object intersection tests.
-pub enum BvhNode {
+thread_local! {
+    static TRAVERSAL_STATS: Cell<TraversalStats> = const {
+        Cell::new(TraversalStats {
+            node_visits: 0,
+            intersection_tests: 0,
+        })
+    };
+}
+
+/// Work done traversing BVHs on the current thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TraversalStats {
+    /// Nodes whose bounding box was tested against a ray
+    pub node_visits: u64,
+    /// Objects in leaves tested against a ray
+    pub intersection_tests: u64,
+}
+
+impl TraversalStats {
+    /// Returns the work counted on this thread since the last call, resetting the counts.
+    pub fn take() -> Self {
+        TRAVERSAL_STATS.take()
+    }
+
+    fn record_node_visit() {
+        TRAVERSAL_STATS.with(|stats| {
+            let mut counts = stats.get();
+            counts.node_visits += 1;
+            stats.set(counts);
+        });
+    }
+
+    fn record_intersection_test() {
+        TRAVERSAL_STATS.with(|stats| {
+            let mut counts = stats.get();
+            counts.intersection_tests += 1;
+            stats.set(counts);
+        });
+    }
+}
+
+/// Bucket count used by [`BvhBuildStrategy::default`].
+pub const DEFAULT_SAH_BUCKETS: usize = 12;
+
+/// How a [`Bvh`] chooses where to divide objects between a node's children.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BvhBuildStrategy {
+    /// Sort along the axis with the largest spread and split at the middle object.
+    Median,
+    /// Bin object centroids into `buckets` slots along their widest axis and split
+    /// between the slots where the surface area heuristic estimates the cheapest
+    /// traversal. Falls back to a median split when the centroids can't be told
+    /// apart or fewer than two buckets are asked for.
+    Sah { buckets: usize },
+}
+
+impl Default for BvhBuildStrategy {
+    fn default() -> Self {
+        BvhBuildStrategy::Sah {
+            buckets: DEFAULT_SAH_BUCKETS,
+        }
+    }
+}
+
+/// Greatest depth at which nodes are split by the surface area heuristic.
+///
+/// Deeper nodes are split at the median, which halves them, so no tree grows
+/// deeper than [`MAX_DEPTH`] for any number of objects.
+const MAX_SAH_DEPTH: usize = 32;
+/// Greatest depth of a tree, bounding the stacks used to traverse it.
+const MAX_DEPTH: usize = 64;
+
+/// A node of a [`Bvh`], stored in depth-first order so a branch's left child
+/// directly follows it.
+enum FlatNode {
     Branch {
-        left: Box<BvhNode>,
-        right: Box<BvhNode>,
         bbox: Aabb,
+        /// Index of the right child
+        right: usize,
     },
     Leaf {
-        object: Box<dyn Hittable>,
         bbox: Aabb,
+        /// Index of the object in the BVH's object list
+        object: usize,
     },
 }
 
-/// A node in the BVH tree structure. Can be either a branch (containing two child nodes)
-/// or a leaf (containing a single hittable object).
+impl FlatNode {
+    fn bbox(&self) -> &Aabb {
+        match self {
+            FlatNode::Branch { bbox, .. } | FlatNode::Leaf { bbox, .. } => bbox,
+        }
+    }
+}
+
+/// A Bounding Volume Hierarchy (BVH) acceleration structure for ray tracing.
+///
+/// Objects are organized into a binary tree to accelerate ray-object intersection
+/// tests. The tree is flattened into a list of nodes and traversed with a fixed
+/// stack, so deep trees neither chase pointers nor recurse.
 pub struct Bvh {
-    tree: BvhNode,
-    bbox: Aabb,
+    nodes: Vec<FlatNode>,
+    objects: Vec<Box<dyn Hittable>>,
 }
 
 #[derive(Debug)]
@@ -47,20 +137,53 @@ impl Error for BvhError {}
 impl Bvh {
     /// Creates a new BVH from a list of hittable objects.
     /// The objects are organized into a binary tree structure for efficient ray intersection tests.
-    pub fn new(mut objects: Vec<Box<dyn Hittable>>) -> Result<Self, BvhError> {
+    pub fn new(objects: Vec<Box<dyn Hittable>>) -> Result<Self, BvhError> {
+        Bvh::with_strategy(objects, BvhBuildStrategy::default())
+    }
+
+    /// Creates a new BVH, dividing the objects between nodes with `strategy`.
+    pub fn with_strategy(
+        mut objects: Vec<Box<dyn Hittable>>,
+        strategy: BvhBuildStrategy,
+    ) -> Result<Self, BvhError> {
         if objects.is_empty() {
             return Err(BvhError::EmptyObjectList);
         }
-        let tree = Bvh::build(&mut objects)?;
-        let bbox = tree.bounding_box().ok_or(BvhError::MissingBoundingBox)?;
-        Ok(Self { tree, bbox })
+        let mut nodes = Vec::with_capacity(2 * objects.len() - 1);
+        Bvh::build(&mut objects, 0, 0, strategy, &mut nodes)?;
+        Ok(Self { nodes, objects })
+    }
+
+    /// Returns the box enclosing every object in the scene.
+    pub fn bounds(&self) -> Aabb {
+        *self.nodes[0].bbox()
     }
 
-    fn build(objects: &mut [Box<dyn Hittable>]) -> Result<BvhNode, BvhError> {
+    /// Appends the nodes for `objects`, which start at `offset` in the object list
+    /// and hang `depth` levels below the root, returning the box enclosing them.
+    ///
+    /// The objects are reordered so each leaf's object index is its final position.
+    fn build(
+        objects: &mut [Box<dyn Hittable>],
+        offset: usize,
+        depth: usize,
+        strategy: BvhBuildStrategy,
+        nodes: &mut Vec<FlatNode>,
+    ) -> Result<Aabb, BvhError> {
         let len = objects.len();
         if len == 0 {
             return Err(BvhError::EmptyObjectList);
         }
+        if len == 1 {
+            let bbox = objects[0]
+                .bounding_box(0.0, 1.0)
+                .ok_or(BvhError::MissingBoundingBox)?;
+            nodes.push(FlatNode::Leaf {
+                bbox,
+                object: offset,
+            });
+            return Ok(bbox);
+        }
 
         // Find the axis with the largest spread
         let mut min_bounds = [f64::INFINITY; 3];
@@ -99,105 +222,242 @@ impl Bvh {
                 .unwrap_or(Ordering::Equal))
         };
 
-        match len {
-            1 => {
-                let bbox = objects[0]
-                    .bounding_box(0.0, 1.0)
-                    .ok_or(BvhError::MissingBoundingBox)?;
-                Ok(BvhNode::Leaf {
-                    object: std::mem::replace(&mut objects[0], Box::new(DummyHittable)),
-                    bbox,
-                })
-            }
-            2 => {
-                let mut objs = vec![
-                    std::mem::replace(&mut objects[0], Box::new(DummyHittable)),
-                    std::mem::replace(&mut objects[1], Box::new(DummyHittable)),
-                ];
-                objs.sort_by(|a, b| comparator(a.as_ref(), b.as_ref()).unwrap_or(Ordering::Equal));
-                let left = Bvh::build(&mut [objs.remove(0)])?;
-                let right = Bvh::build(&mut [objs.remove(0)])?;
-                let bbox = Aabb::surrounding(
-                    &left.bounding_box().ok_or(BvhError::MissingBoundingBox)?,
-                    &right.bounding_box().ok_or(BvhError::MissingBoundingBox)?,
-                );
-                Ok(BvhNode::Branch {
-                    left: Box::new(left),
-                    right: Box::new(right),
-                    bbox,
-                })
+        let split = match strategy {
+            BvhBuildStrategy::Sah { buckets } if len > 2 && depth < MAX_SAH_DEPTH => {
+                Bvh::sah_split(objects, buckets)?
             }
-            _ => {
+            _ => None,
+        };
+        let mid = match split {
+            Some(mid) => mid,
+            None => {
                 objects
                     .sort_by(|a, b| comparator(a.as_ref(), b.as_ref()).unwrap_or(Ordering::Equal));
-                let mid = len / 2;
-                let (left_objs, right_objs) = objects.split_at_mut(mid);
-                let left = Bvh::build(left_objs)?;
-                let right = Bvh::build(right_objs)?;
-                let bbox = Aabb::surrounding(
-                    &left.bounding_box().ok_or(BvhError::MissingBoundingBox)?,
-                    &right.bounding_box().ok_or(BvhError::MissingBoundingBox)?,
-                );
-                Ok(BvhNode::Branch {
-                    left: Box::new(left),
-                    right: Box::new(right),
-                    bbox,
-                })
+                len / 2
             }
-        }
+        };
+
+        // The left child follows its parent; the right child's index is known
+        // once the left subtree has been laid out
+        let index = nodes.len();
+        nodes.push(FlatNode::Leaf {
+            bbox: Aabb::default(),
+            object: 0,
+        });
+        let (left_objs, right_objs) = objects.split_at_mut(mid);
+        let left = Bvh::build(left_objs, offset, depth + 1, strategy, nodes)?;
+        let right_index = nodes.len();
+        let right = Bvh::build(right_objs, offset + mid, depth + 1, strategy, nodes)?;
+        let bbox = Aabb::surrounding(&left, &right);
+        nodes[index] = FlatNode::Branch {
+            bbox,
+            right: right_index,
+        };
+        Ok(bbox)
     }
 }
 
-impl Hittable for Bvh {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        self.tree.hit(r, ray_t)
-    }
-    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
-        Some(self.bbox)
+impl Bvh {
+    /// Sorts `objects` by centroid along their widest centroid axis and returns how
+    /// many belong on the left, or `None` when no bucket boundary separates them.
+    ///
+    /// Each candidate split between buckets is costed as the number of objects on
+    /// each side weighted by the surface area of that side's bounds, which is
+    /// proportional to the chance a ray passing through the parent enters it.
+    fn sah_split(
+        objects: &mut [Box<dyn Hittable>],
+        buckets: usize,
+    ) -> Result<Option<usize>, BvhError> {
+        if buckets < 2 {
+            return Ok(None);
+        }
+        let centroid = |object: &dyn Hittable| -> Result<Point3, BvhError> {
+            Ok(object
+                .bounding_box(0.0, 1.0)
+                .ok_or(BvhError::MissingBoundingBox)?
+                .center())
+        };
+
+        let mut min_centroid = [f64::INFINITY; 3];
+        let mut max_centroid = [f64::NEG_INFINITY; 3];
+        for obj in objects.iter() {
+            let c = centroid(obj.as_ref())?;
+            for axis in 0..3 {
+                min_centroid[axis] = min_centroid[axis].min(c[axis]);
+                max_centroid[axis] = max_centroid[axis].max(c[axis]);
+            }
+        }
+        let axis = (0..3)
+            .max_by(|&a, &b| {
+                let spread_a = max_centroid[a] - min_centroid[a];
+                let spread_b = max_centroid[b] - min_centroid[b];
+                spread_a.partial_cmp(&spread_b).unwrap_or(Ordering::Equal)
+            })
+            .unwrap_or(0);
+        let extent = max_centroid[axis] - min_centroid[axis];
+        if extent <= 0.0 || !extent.is_finite() {
+            return Ok(None);
+        }
+        let bucket_of = |c: &Point3| -> usize {
+            let offset = (c[axis] - min_centroid[axis]) / extent;
+            ((offset * buckets as f64) as usize).min(buckets - 1)
+        };
+
+        let mut counts = vec![0usize; buckets];
+        let mut bounds: Vec<Option<Aabb>> = vec![None; buckets];
+        for obj in objects.iter() {
+            let bbox = obj
+                .bounding_box(0.0, 1.0)
+                .ok_or(BvhError::MissingBoundingBox)?;
+            let bucket = bucket_of(&bbox.center());
+            counts[bucket] += 1;
+            bounds[bucket] = Some(match bounds[bucket] {
+                Some(b) => Aabb::surrounding(&b, &bbox),
+                None => bbox,
+            });
+        }
+
+        // Sweep from the right so each split can combine its right-hand bounds
+        // with a running left-hand union.
+        let mut right_cost = vec![0.0; buckets];
+        let mut right_count = 0;
+        let mut right_bounds: Option<Aabb> = None;
+        for i in (1..buckets).rev() {
+            right_count += counts[i];
+            right_bounds = union(right_bounds, bounds[i]);
+            right_cost[i] = right_bounds.map_or(0.0, |b| right_count as f64 * b.surface_area());
+        }
+        let mut best: Option<(usize, f64)> = None;
+        let mut left_count = 0;
+        let mut left_bounds: Option<Aabb> = None;
+        for i in 1..buckets {
+            left_count += counts[i - 1];
+            left_bounds = union(left_bounds, bounds[i - 1]);
+            if left_count == 0 || left_count == objects.len() {
+                continue;
+            }
+            let cost =
+                left_bounds.map_or(0.0, |b| left_count as f64 * b.surface_area()) + right_cost[i];
+            if best.is_none_or(|(_, best_cost)| cost < best_cost) {
+                best = Some((left_count, cost));
+            }
+        }
+        let Some((mid, _)) = best else {
+            return Ok(None);
+        };
+
+        objects.sort_by(|a, b| match (centroid(a.as_ref()), centroid(b.as_ref())) {
+            (Ok(a), Ok(b)) => a[axis].partial_cmp(&b[axis]).unwrap_or(Ordering::Equal),
+            _ => Ordering::Equal,
+        });
+        Ok(Some(mid))
     }
 }
 
-impl BvhNode {
-    pub fn bounding_box(&self) -> Option<Aabb> {
-        match self {
-            BvhNode::Branch { bbox, .. } => Some(*bbox),
-            BvhNode::Leaf { bbox, .. } => Some(*bbox),
-        }
+fn union(a: Option<Aabb>, b: Option<Aabb>) -> Option<Aabb> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(Aabb::surrounding(&a, &b)),
+        (a, b) => a.or(b),
     }
 }
 
-impl Hittable for BvhNode {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        match self {
-            BvhNode::Branch { left, right, bbox } => {
-                bbox.hit(r, ray_t)?;
-                let hit_left = left.hit(r, ray_t);
-                let t_max = if let Some(ref rec) = hit_left {
-                    Interval::new(ray_t.min(), rec.t)
-                } else {
-                    ray_t
-                };
-                let hit_right = right.hit(r, t_max);
-                hit_right.or(hit_left)
+impl Hittable for Bvh {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let mut closest = None;
+        let mut ray_t = ray_t;
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut pending = 0;
+        let mut index = 0;
+        loop {
+            TraversalStats::record_node_visit();
+            match &self.nodes[index] {
+                FlatNode::Branch { bbox, right } => {
+                    if bbox.intersect(r, ray_t).is_some() {
+                        stack[pending] = *right;
+                        pending += 1;
+                        index += 1;
+                        continue;
+                    }
+                }
+                FlatNode::Leaf { bbox, object } => {
+                    if bbox.intersect(r, ray_t).is_some() {
+                        TraversalStats::record_intersection_test();
+                        if let Some(rec) = self.objects[*object].hit(r, ray_t) {
+                            ray_t = Interval::new(ray_t.min(), rec.t);
+                            closest = Some(rec);
+                        }
+                    }
+                }
             }
-            BvhNode::Leaf { object, bbox } => {
-                bbox.hit(r, ray_t)?;
-                object.hit(r, ray_t)
+            if pending == 0 {
+                return closest;
             }
+            pending -= 1;
+            index = stack[pending];
         }
     }
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
-        self.bounding_box()
+        Some(self.bounds())
+    }
+    fn is_sampleable(&self) -> bool {
+        self.objects.iter().all(|object| object.is_sampleable())
     }
-}
 
-struct DummyHittable;
-impl Hittable for DummyHittable {
-    fn hit(&self, _r: &Ray, _ray_t: Interval) -> Option<HitRecord> {
-        None
+    fn visit_materials(&mut self, visit: &mut dyn FnMut(&mut Arc<Material>)) {
+        for object in &mut self.objects {
+            object.visit_materials(visit);
+        }
     }
-    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
-        None
+
+    fn degenerate_reason(&self) -> Option<&'static str> {
+        self.objects
+            .iter()
+            .find_map(|object| object.degenerate_reason())
+    }
+
+    fn degenerate_primitives(&self) -> usize {
+        self.objects
+            .iter()
+            .map(|object| object.degenerate_primitives())
+            .sum()
+    }
+
+    /// Each branch picks either side with equal probability, so the density is
+    /// the average of both sides'.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let mut sum = 0.0;
+        let mut stack = [(0usize, 1.0); MAX_DEPTH + 1];
+        let mut pending = 1;
+        while pending > 0 {
+            pending -= 1;
+            let (index, weight) = stack[pending];
+            match &self.nodes[index] {
+                FlatNode::Branch { right, .. } => {
+                    stack[pending] = (*right, 0.5 * weight);
+                    stack[pending + 1] = (index + 1, 0.5 * weight);
+                    pending += 2;
+                }
+                FlatNode::Leaf { object, .. } => {
+                    sum += weight * self.objects[*object].pdf_value(origin, direction);
+                }
+            }
+        }
+        sum
+    }
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        let mut index = 0;
+        loop {
+            match &self.nodes[index] {
+                FlatNode::Branch { right, .. } => {
+                    index = if rng.random::<bool>() {
+                        index + 1
+                    } else {
+                        *right
+                    };
+                }
+                FlatNode::Leaf { object, .. } => return self.objects[*object].random(origin, rng),
+            }
+        }
     }
 }
 
@@ -331,4 +591,196 @@ mod tests {
         assert!((min_x - 0.0).abs() < 1e-6);
         assert!((max_x - 2.0).abs() < 1e-6);
     }
+
+    #[test]
+    fn test_traversal_stats() {
+        let small = SphereBuilder::new()
+            .center(Point3::new(0.0, 0.0, -1.0))
+            .radius(0.5)
+            .material(test_material())
+            .build()
+            .unwrap();
+        let large = SphereBuilder::new()
+            .center(Point3::new(0.0, -100.5, -1.0))
+            .radius(100.0)
+            .material(test_material())
+            .build()
+            .unwrap();
+        let objects: Vec<Box<dyn Hittable>> = vec![Box::new(small), Box::new(large)];
+        let bvh = Bvh::new(objects).unwrap();
+        let interval = Interval::new(0.001, f64::INFINITY);
+        TraversalStats::take();
+
+        // A ray missing the root's bounding box only visits the root
+        let miss = Ray::new(
+            Point3::new(500.0, 500.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        assert!(bvh.hit(&miss, interval).is_none());
+        assert_eq!(
+            TraversalStats::take(),
+            TraversalStats {
+                node_visits: 1,
+                intersection_tests: 0
+            }
+        );
+
+        // A ray into the small sphere visits both leaves, but the hit shortens the
+        // ray so the other leaf's box is missed before its object is tested
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(bvh.hit(&ray, interval).is_some());
+        assert_eq!(
+            TraversalStats::take(),
+            TraversalStats {
+                node_visits: 3,
+                intersection_tests: 1
+            }
+        );
+    }
+
+    /// Seven small spheres bunched near the origin and one far along x.
+    fn clustered_spheres() -> Vec<Box<dyn Hittable>> {
+        let mut objects: Vec<Box<dyn Hittable>> = (0..7)
+            .map(|i| {
+                Box::new(
+                    SphereBuilder::new()
+                        .center(Point3::new(i as f64 * 0.1, 0.0, -1.0))
+                        .radius(0.04)
+                        .material(test_material())
+                        .build()
+                        .unwrap(),
+                ) as Box<dyn Hittable>
+            })
+            .collect();
+        objects.push(Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(50.0, 0.0, -1.0))
+                .radius(0.04)
+                .material(test_material())
+                .build()
+                .unwrap(),
+        ));
+        objects
+    }
+
+    #[test]
+    fn test_sah_matches_median_hits() {
+        let sah = Bvh::new(clustered_spheres()).unwrap();
+        let median = Bvh::with_strategy(clustered_spheres(), BvhBuildStrategy::Median).unwrap();
+        let interval = Interval::new(0.001, f64::INFINITY);
+        for x in [0.0, 0.1, 0.3, 0.6, 0.65, 25.0, 50.0] {
+            let ray = Ray::new(Point3::new(x, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+            let a = sah.hit(&ray, interval).map(|rec| rec.t);
+            let b = median.hit(&ray, interval).map(|rec| rec.t);
+            assert_eq!(a, b, "x = {x}");
+        }
+        assert_eq!(sah.bounds(), median.bounds());
+    }
+
+    #[test]
+    fn test_sah_isolates_outlier() {
+        let sah = Bvh::new(clustered_spheres()).unwrap();
+        let median = Bvh::with_strategy(clustered_spheres(), BvhBuildStrategy::Median).unwrap();
+        let interval = Interval::new(0.001, f64::INFINITY);
+        // A ray through the empty gap between the cluster and the outlier
+        let ray = Ray::new(Point3::new(25.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+
+        TraversalStats::take();
+        assert!(sah.hit(&ray, interval).is_none());
+        let sah_visits = TraversalStats::take().node_visits;
+        assert!(median.hit(&ray, interval).is_none());
+        let median_visits = TraversalStats::take().node_visits;
+
+        // The root splits the cluster from the outlier, so both children are missed
+        assert_eq!(sah_visits, 3);
+        assert!(median_visits > sah_visits);
+    }
+
+    #[test]
+    fn test_sah_falls_back_to_median() {
+        // Concentric spheres share a centroid, so no bucket boundary separates them
+        let objects: Vec<Box<dyn Hittable>> = [0.5, 1.0, 1.5, 2.0]
+            .into_iter()
+            .map(|radius| {
+                Box::new(
+                    SphereBuilder::new()
+                        .center(Point3::new(0.0, 0.0, -5.0))
+                        .radius(radius)
+                        .material(test_material())
+                        .build()
+                        .unwrap(),
+                ) as Box<dyn Hittable>
+            })
+            .collect();
+        let bvh = Bvh::with_strategy(objects, BvhBuildStrategy::Sah { buckets: 4 }).unwrap();
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = bvh.hit(&ray, Interval::new(0.001, f64::INFINITY)).unwrap();
+        assert!((rec.t - 3.0).abs() < 1e-9);
+    }
+
+    /// Spheres scattered deterministically through a 10-unit cube.
+    fn scattered_spheres(count: usize) -> Vec<Box<dyn Hittable>> {
+        (0..count)
+            .map(|i| {
+                let f = i as f64;
+                Box::new(
+                    SphereBuilder::new()
+                        .center(Point3::new(
+                            (f * 3.7) % 10.0,
+                            (f * 5.3) % 10.0,
+                            -(f * 7.1) % 10.0 - 1.0,
+                        ))
+                        .radius(0.1 + (f * 0.37) % 0.3)
+                        .material(test_material())
+                        .build()
+                        .unwrap(),
+                ) as Box<dyn Hittable>
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_flat_layout() {
+        let bvh = Bvh::new(scattered_spheres(37)).unwrap();
+        assert_eq!(bvh.nodes.len(), 2 * 37 - 1);
+        let mut leaves: Vec<usize> = Vec::new();
+        for (index, node) in bvh.nodes.iter().enumerate() {
+            match node {
+                FlatNode::Branch { bbox, right } => {
+                    // Children lie after their parent and inside its box
+                    assert!(*right > index + 1);
+                    for child in [index + 1, *right] {
+                        let child = bvh.nodes[child].bbox();
+                        assert_eq!(&Aabb::surrounding(bbox, child), bbox);
+                    }
+                }
+                FlatNode::Leaf { object, .. } => leaves.push(*object),
+            }
+        }
+        // Depth-first order visits the objects in list order
+        assert_eq!(leaves, (0..37).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_hits_match_every_object() {
+        let objects = scattered_spheres(200);
+        for strategy in [BvhBuildStrategy::Median, BvhBuildStrategy::default()] {
+            let bvh = Bvh::with_strategy(scattered_spheres(200), strategy).unwrap();
+            let interval = Interval::new(0.001, f64::INFINITY);
+            for i in 0..100 {
+                let f = i as f64;
+                let ray = Ray::new(
+                    Point3::new(5.0, 5.0, 5.0),
+                    Vec3::new((f * 0.13).sin(), (f * 0.29).cos(), -1.0),
+                    0.0,
+                );
+                let expected = objects
+                    .iter()
+                    .filter_map(|object| object.hit(&ray, interval).map(|rec| rec.t))
+                    .min_by(|a, b| a.total_cmp(b));
+                assert_eq!(bvh.hit(&ray, interval).map(|rec| rec.t), expected);
+            }
+        }
+    }
 }
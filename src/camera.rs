@@ -1,21 +1,320 @@
+use crate::background::Background;
+use crate::bvh::{Bvh, BvhError, TraversalStats};
 use crate::color::Color;
+use crate::display::DisplayTransform;
+use crate::error::Error;
+use crate::framebuffer::{self, Diagnostic, Framebuffer, ImageError, TileSamples};
+use crate::guiding::PathGuide;
+use crate::hittable::{HitRecord, Hittable, SelfHitExclusion, UNIFORM_SPHERE_PDF};
 use crate::interval::Interval;
+use crate::light::Light;
+use crate::light_linking::LightLink;
+use crate::light_tree::LightTree;
+use crate::lut::Lut;
+use crate::material::{Material, Scatter};
+use crate::medium::{Medium, MediumStack};
+use crate::onb::Onb;
+use crate::parallel::*;
+use crate::path_trace::{PathEnd, PathEvent, PathTrace, PathVertex};
+use crate::pdf::{CosinePdf, HittablePdf, LightObjects, MixturePdf, Pdf, ScatterPdf, SpherePdf};
 use crate::point3::Point3;
-use crate::random_double;
-use crate::ray::Ray;
-use crate::utilities::degrees_to_radians;
+use crate::postprocess::{Fog, HighlightRolloff};
+use crate::preprocess::{self, PreprocessReport};
+use crate::progress::{Progress, ProgressReporter, SharedReporter};
+use crate::ray::{Ray, RayDifferentials, RayKind};
+use crate::sampler::{AdaptiveSampling, CameraSample, PixelSampler, PixelSampling, SampleStats};
+use crate::units::Units;
+use crate::utilities::{
+    degrees_to_radians, mix_seed, random_double, seed_thread_rng, with_thread_rng,
+};
 use crate::vec3::Vec3;
 
-use indicatif::{ProgressBar, ProgressStyle};
-use rayon::prelude::*;
 use std::f64;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
 
 // Constants for common values
 const BLACK: Color = Color::new(0.0, 0.0, 0.0);
-const WHITE: Color = Color::new(1.0, 1.0, 1.0);
-const SKY_BLUE: Color = Color::new(0.5, 0.7, 1.0);
 const MIN_IMAGE_HEIGHT: u32 = 1;
+/// Closest hit accepted along a ray, in meters, so rays don't re-hit their origin.
 const RAY_T_MIN: f64 = 0.001;
+/// Focus distance, in meters, of a camera that doesn't set one.
+const DEFAULT_FOCUS_DIST: f64 = 1.0;
+const MIN_DIFFERENTIAL_SCALE: f64 = 0.125;
+/// Fraction of the distance to a light that a shadow ray stops short of.
+const SHADOW_RAY_MARGIN: f64 = 1e-6;
+/// Transparent surfaces a shadow ray may pass through before it counts as blocked.
+const MAX_SHADOW_SURFACES: u32 = 16;
+/// Samples per pixel traced to train the path guide before the real render.
+const GUIDE_TRAINING_SAMPLES: u32 = 4;
+/// Path guide cells across the width of the view at the focus distance.
+const GUIDE_CELLS_ACROSS_VIEW: f64 = 32.0;
+/// Probability of following the path guide rather than the BSDF at a diffuse bounce.
+const GUIDE_FRACTION: f64 = 0.5;
+/// Width and height in pixels of the tiles rendered on one thread at a time.
+const DEFAULT_TILE_SIZE: u32 = 16;
+/// Mixed into the seeds of path guide training, so it draws other numbers than the render.
+const GUIDE_TRAINING_KEY: u64 = u64::MAX;
+/// Key mixed into the seed of each progressive pass after the first.
+const PROGRESSIVE_PASS_KEY: u64 = u64::MAX - 1;
+
+/// What the camera writes into each pixel.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RenderPass {
+    /// The fully lit image
+    #[default]
+    Beauty,
+    /// An unlit flat color per material, black where nothing is hit
+    MaterialId,
+    /// A heatmap of BVH nodes visited per sample, over every bounce
+    BvhCost,
+    /// A heatmap of the wall time spent rendering each pixel
+    Time,
+}
+
+/// What `Camera::bake` records for each texel.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BakeMode {
+    /// Light leaving the surface, direct and indirect
+    #[default]
+    Lighting,
+    /// Fraction of the hemisphere above the surface not blocked by the scene
+    Occlusion,
+    /// The diffuse surface color, unlit
+    Albedo,
+}
+
+/// Why `Camera::bake_object` could not bake an object.
+#[derive(Debug)]
+pub enum BakeError {
+    /// The scene has fewer objects than the index asked for
+    NoObject {
+        index: usize,
+        objects: usize,
+    },
+    /// The object at this index has no texture coordinates to bake into
+    NoTextureSpace(usize),
+    Bvh(BvhError),
+}
+
+impl fmt::Display for BakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BakeError::NoObject { index, objects } => write!(
+                f,
+                "scene has {} objects, so there is no object {}",
+                objects, index
+            ),
+            BakeError::NoTextureSpace(index) => {
+                write!(f, "object {} has no texture space to bake into", index)
+            }
+            BakeError::Bvh(e) => write!(f, "Failed to build BVH: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BakeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BakeError::Bvh(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<BvhError> for BakeError {
+    fn from(error: BvhError) -> Self {
+        BakeError::Bvh(error)
+    }
+}
+
+/// How the camera schedules the work of tracing paths.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Renderer {
+    /// Trace each pixel's paths to completion before moving on
+    #[default]
+    Megakernel,
+    /// Advance one sample of every pixel together, a stage at a time
+    Wavefront,
+}
+
+/// How the camera picks which lights to sample at each diffuse bounce.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LightSampling {
+    /// Sample every light, which is exact but costs a shadow ray per light
+    #[default]
+    All,
+    /// Sample one light chosen from a light tree by its estimated contribution
+    Tree,
+}
+
+/// How the camera maps the scene onto the image.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Projection {
+    /// A pinhole or thin-lens view of what is in front of the camera
+    #[default]
+    Perspective,
+    /// Omnidirectional stereo for VR video: equirectangular panoramas for the left
+    /// eye over the right, with the eyes `ipd` meters apart
+    OmniStereo { ipd: f64 },
+}
+
+/// A named viewpoint a scene offers, so several consistent angles can be rendered
+/// from one build of the scene.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct View {
+    pub name: String,
+    pub look_from: Point3,
+    pub look_at: Point3,
+}
+
+/// The camera's frame and eye separation for omnidirectional stereo.
+#[derive(Clone, Copy, Debug)]
+struct OmniStereo {
+    /// Half the distance between the eyes, in scene units
+    half_ipd: f64,
+    right: Vec3,
+    up: Vec3,
+    forward: Vec3,
+}
+
+/// Everything rendered for one pixel.
+#[derive(Clone, Copy)]
+struct PixelValue {
+    color: Color,
+    /// Average distance of the samples that hit a surface, not weighted by coverage
+    depth: f64,
+    /// Fraction of the samples that hit a surface
+    coverage: f64,
+    /// Samples taken, fewer than the camera's when sampling adaptively
+    samples: u32,
+    traversal: TraversalStats,
+    time: Duration,
+}
+
+/// A pixel's samples so far, while it is being rendered.
+struct PixelSamples {
+    pixel: (u32, u32),
+    sampler: PixelSampler,
+    color: Color,
+    depth_sum: f64,
+    depth_hits: u32,
+    stats: SampleStats,
+    traversal: TraversalStats,
+    time: Duration,
+    /// Set once adaptive sampling has taken enough samples
+    converged: bool,
+}
+
+impl PixelSamples {
+    /// Averages the samples into the pixel's value.
+    fn finish(self) -> PixelValue {
+        let depth = if self.depth_hits > 0 {
+            self.depth_sum / self.depth_hits as f64
+        } else {
+            f64::INFINITY
+        };
+        let samples = self.stats.count();
+        let scale = 1.0 / samples.max(1) as f64;
+
+        PixelValue {
+            // Scale the color by the number of samples
+            color: self.color * scale,
+            depth,
+            coverage: self.depth_hits as f64 * scale,
+            samples,
+            traversal: self.traversal,
+            time: self.time,
+        }
+    }
+}
+
+impl PixelValue {
+    /// Stands in for a pixel whose tile isn't finished yet: black and transparent.
+    fn unrendered() -> PixelValue {
+        PixelValue {
+            color: BLACK,
+            depth: f64::INFINITY,
+            coverage: 0.0,
+            samples: 0,
+            traversal: TraversalStats::default(),
+            time: Duration::ZERO,
+        }
+    }
+
+    /// Combines this value with `other`, rendered separately, into the average over
+    /// both one's samples.
+    fn add(self, other: PixelValue) -> PixelValue {
+        let (n, m) = (self.samples as f64, other.samples as f64);
+        let average = |a: f64, b: f64| (a * n + b * m) / (n + m);
+        // Depth is averaged over the samples that hit something
+        let (hits, other_hits) = (self.coverage * n, other.coverage * m);
+        let depth = match (hits > 0.0, other_hits > 0.0) {
+            (true, true) => (self.depth * hits + other.depth * other_hits) / (hits + other_hits),
+            (true, false) => self.depth,
+            _ => other.depth,
+        };
+        PixelValue {
+            color: (self.color * n + other.color * m) * (1.0 / (n + m)),
+            depth,
+            coverage: average(self.coverage, other.coverage),
+            samples: self.samples + other.samples,
+            traversal: TraversalStats {
+                node_visits: self.traversal.node_visits + other.traversal.node_visits,
+                intersection_tests: self.traversal.intersection_tests
+                    + other.traversal.intersection_tests,
+            },
+            time: self.time + other.time,
+        }
+    }
+}
+
+/// A path in flight in the wavefront renderer.
+struct Path<'a> {
+    /// Index of the path's pixel in the image
+    index: usize,
+    id: SampleId,
+    /// The ray to trace for the path's next bounce
+    ray: Ray,
+    throughput: Color,
+    /// The media the ray travels through
+    media: MediumStack<'a>,
+}
+
+/// Identifies the camera sample a path belongs to, for diagnostics, and the pixel
+/// sampler that stratifies its light samples.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct SampleId {
+    pixel: (u32, u32),
+    sample: u32,
+    sampler: PixelSampler,
+}
+
+/// How a surface reflects light it is lit by directly.
+#[derive(Clone, Copy, Debug)]
+enum Lobe {
+    /// Lambertian reflection with the surface's albedo
+    Diffuse(Color),
+    /// Scattering inside a medium, evenly in every direction, with its albedo
+    Isotropic(Color),
+    /// A regularized specular bounce, reflecting `attenuation` evenly over the
+    /// directions within `cos_max` of `axis`
+    Cone {
+        attenuation: Color,
+        axis: Vec3,
+        cos_max: f64,
+    },
+}
 
 /// Camera for rendering a scene.
 ///
@@ -26,6 +325,7 @@ pub struct Camera {
     image_width: u32,
     pixel_samples_scale: f64,
     samples_per_pixel: u32,
+    differential_scale: f64,
     center: Point3,
     pixel00_loc: Point3,
     pixel_delta_u: Vec3,
@@ -34,12 +334,71 @@ pub struct Camera {
     defocus_angle: f64,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
+    /// Point on the plane of focus straight ahead of the camera
+    focus_center: Point3,
+    /// Normal of the plane of focus when it is tilted away from the image plane
+    focus_tilt_normal: Option<Vec3>,
+    lights: Vec<Light>,
+    /// Which objects each light illuminates, in the same order as `lights`
+    light_links: Vec<LightLink>,
+    /// Emissive objects in the world that diffuse bounces aim half their rays at
+    light_objects: LightObjects,
+    fog: Option<Fog>,
+    /// Medium filling the space around every object, camera included
+    medium: Option<Medium>,
+    /// How far rays that hit nothing travel through `medium`, if not forever
+    medium_extent: Option<f64>,
+    highlight_rolloff: Option<HighlightRolloff>,
+    pixel_sampling: PixelSampling,
+    nan_guard: bool,
+    /// Samples the NaN guard dropped during the current render, shared with the
+    /// cameras a render derives from this one for its passes
+    non_finite: Arc<Mutex<Vec<Diagnostic>>>,
+    background: Background,
+    pass: RenderPass,
+    renderer: Renderer,
+    light_sampling: LightSampling,
+    light_tree: LightTree,
+    path_guiding: bool,
+    /// Whether shadow rays pass through transparent surfaces, taking their color
+    transparent_shadows: bool,
+    /// Which primitives rays spawned from a hit skip instead of being offset
+    self_hit_exclusion: SelfHitExclusion,
+    /// Incident radiance learned by a training render, once path guiding has run
+    guide: Option<Arc<PathGuide>>,
+    /// Closest hit accepted along a ray, in scene units
+    ray_t_min: f64,
+    /// Material shaded in place of every surface's own, such as clay
+    material_override: Option<Material>,
+    /// Seed the scene was generated from, recorded in rendered images
+    scene_seed: Option<u64>,
+    /// How rendered images are encoded for display
+    display: DisplayTransform,
+    /// Look applied to rendered images after the display transform
+    lut: Option<Arc<Lut>>,
+    /// How many times the output resolution is rendered along each axis
+    supersample: u32,
+    /// Width and height in pixels of the tiles the image is rendered in
+    tile_size: u32,
+    /// When pixels stop sampling early, if they may
+    adaptive_sampling: Option<AdaptiveSampling>,
+    /// Time each tile may spend sampling before it stops, if limited
+    tile_time_budget: Option<Duration>,
+    /// Told how far each render has got, besides the progress bar
+    progress: Option<SharedReporter>,
+    /// Seed every pixel's random numbers are derived from, if renders should repeat
+    seed: Option<u64>,
+    /// Cosine of the half-angle specular bounces after the first are blurred over
+    regularization: Option<f64>,
+    /// Set when rendering an omnidirectional stereo panorama instead of a perspective view
+    omni_stereo: Option<OmniStereo>,
 }
 
 /// Builder for creating a customized camera.
 ///
 /// Uses the builder pattern to configure camera parameters.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CameraBuilder {
     aspect_ratio: f64,
     image_width: u32,
@@ -50,7 +409,48 @@ pub struct CameraBuilder {
     look_at: Point3,
     vup: Vec3,
     defocus_angle: f64,
-    focus_dist: f64,
+    focus_dist: Option<f64>,
+    tilt: (f64, f64),
+    lens_shift: (f64, f64),
+    units: Units,
+    lights: Vec<Light>,
+    /// Which objects each light illuminates, in the same order as `lights`
+    light_links: Vec<LightLink>,
+    /// Shared with the world, so not part of the saved settings
+    #[cfg_attr(feature = "serde", serde(skip))]
+    light_objects: LightObjects,
+    fog: Option<Fog>,
+    /// Medium filling the space around every object, camera included
+    medium: Option<Medium>,
+    /// How far rays that hit nothing travel through `medium`, if not forever
+    medium_extent: Option<f64>,
+    highlight_rolloff: Option<HighlightRolloff>,
+    pixel_sampling: PixelSampling,
+    nan_guard: bool,
+    background: Background,
+    pass: RenderPass,
+    renderer: Renderer,
+    light_sampling: LightSampling,
+    path_guiding: bool,
+    transparent_shadows: bool,
+    self_hit_exclusion: SelfHitExclusion,
+    material_override: Option<Material>,
+    scene_seed: Option<u64>,
+    display: DisplayTransform,
+    /// Loaded from a file, so not part of the saved settings
+    #[cfg_attr(feature = "serde", serde(skip))]
+    lut: Option<Arc<Lut>>,
+    supersample: u32,
+    tile_size: u32,
+    adaptive_sampling: Option<AdaptiveSampling>,
+    tile_time_budget: Option<Duration>,
+    /// Shared with the caller, so not part of the saved settings
+    #[cfg_attr(feature = "serde", serde(skip))]
+    progress: Option<SharedReporter>,
+    seed: Option<u64>,
+    regularization: Option<f64>,
+    projection: Projection,
+    views: Vec<View>,
 }
 
 impl Default for Camera {
@@ -71,7 +471,39 @@ impl Default for CameraBuilder {
             look_at: Point3::new(0.0, 0.0, -1.0),
             vup: Vec3::new(0.0, 1.0, 0.0),
             defocus_angle: 0.0,
-            focus_dist: 1.0,
+            focus_dist: None,
+            tilt: (0.0, 0.0),
+            lens_shift: (0.0, 0.0),
+            units: Units::default(),
+            lights: Vec::new(),
+            light_links: Vec::new(),
+            light_objects: LightObjects::default(),
+            fog: None,
+            medium: None,
+            medium_extent: None,
+            highlight_rolloff: None,
+            pixel_sampling: PixelSampling::default(),
+            nan_guard: false,
+            background: Background::default(),
+            pass: RenderPass::default(),
+            renderer: Renderer::default(),
+            light_sampling: LightSampling::default(),
+            path_guiding: false,
+            transparent_shadows: false,
+            self_hit_exclusion: SelfHitExclusion::default(),
+            material_override: None,
+            scene_seed: None,
+            display: DisplayTransform::default(),
+            lut: None,
+            supersample: 1,
+            tile_size: DEFAULT_TILE_SIZE,
+            adaptive_sampling: None,
+            tile_time_budget: None,
+            progress: None,
+            seed: None,
+            regularization: None,
+            projection: Projection::default(),
+            views: Vec::new(),
         }
     }
 }
@@ -96,6 +528,76 @@ impl CameraBuilder {
         self
     }
 
+    /// Renders at `factor` times the image width and height, then filters the
+    /// result down to size.
+    ///
+    /// This smooths edges and fine detail independently of `samples_per_pixel`, which
+    /// still applies to every internal pixel. A factor of 1 renders directly.
+    pub fn supersample(mut self, factor: u32) -> Self {
+        self.supersample = factor.max(1);
+        self
+    }
+
+    /// Renders the image in square tiles `size` pixels across, each on one thread.
+    ///
+    /// Smaller tiles balance the work between threads better; larger ones cost less
+    /// to schedule and keep more of the scene in cache. Only the beauty pass of the
+    /// wavefront renderer ignores this.
+    pub fn tile_size(mut self, size: u32) -> Self {
+        self.tile_size = size.max(1);
+        self
+    }
+
+    /// Lets each pixel stop sampling once its brightness is known well enough, taking
+    /// between the minimum samples of `adaptive` and `samples_per_pixel`, so flat
+    /// areas such as sky cost less than noisy ones. `None` takes every sample.
+    pub fn adaptive_sampling(mut self, adaptive: Option<AdaptiveSampling>) -> Self {
+        self.adaptive_sampling = adaptive;
+        self
+    }
+
+    /// Gives each tile `budget` to sample in, taking every pixel's samples a round at
+    /// a time and stopping after the round that runs out of time, so a render's
+    /// length depends on its tile count rather than on how costly the scene is.
+    ///
+    /// Every pixel takes at least one sample. Images record how many samples each
+    /// tile averaged, and are rendered tile by tile even when the camera is set to
+    /// the wavefront renderer. `None` takes every sample however long it takes.
+    pub fn tile_time_budget(mut self, budget: Option<Duration>) -> Self {
+        self.tile_time_budget = budget;
+        self
+    }
+
+    /// Tells `reporter` how far each render has got as it goes, as the progress bar
+    /// shows it, such as to post it to a [`WebhookReporter`].
+    ///
+    /// [`WebhookReporter`]: crate::progress::WebhookReporter
+    pub fn progress_reporter(mut self, reporter: Arc<dyn ProgressReporter>) -> Self {
+        self.progress = Some(SharedReporter(reporter));
+        self
+    }
+
+    /// Blurs mirror and glass bounces after the first into a cone, widening with
+    /// `roughness` from 0 to 1 up to the whole hemisphere, so caustics seen through
+    /// a diffuse bounce converge.
+    ///
+    /// Small lights reflected or refracted onto a diffuse surface are otherwise only
+    /// found by chance. Blurring brightens them out of noise at the cost of softening
+    /// what the specular surfaces show; `None` renders them unbiased.
+    pub fn regularization(mut self, roughness: Option<f64>) -> Self {
+        self.regularization = roughness;
+        self
+    }
+
+    /// Selects how the scene is mapped onto the image.
+    ///
+    /// An omnidirectional stereo image is always twice as tall as a single 2:1
+    /// panorama, whatever the aspect ratio, and ignores defocus and tilt.
+    pub fn projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
     pub fn max_depth(mut self, max_depth: u32) -> Self {
         self.max_depth = max_depth;
         self
@@ -127,24 +629,293 @@ impl CameraBuilder {
     }
 
     pub fn focus_dist(mut self, focus_dist: f64) -> Self {
-        self.focus_dist = focus_dist;
+        self.focus_dist = Some(focus_dist);
+        self
+    }
+
+    /// Offers a named viewpoint from `look_from` towards `look_at`, besides the
+    /// camera's own, for rendering several angles of the scene in one run.
+    pub fn view(mut self, name: &str, look_from: Point3, look_at: Point3) -> Self {
+        self.views.push(View {
+            name: name.to_string(),
+            look_from,
+            look_at,
+        });
+        self
+    }
+
+    /// Returns the named viewpoints the scene offers.
+    pub fn views(&self) -> &[View] {
+        &self.views
+    }
+
+    /// Returns this camera moved to `view`, focused on what it looks at and with
+    /// every other setting kept.
+    pub fn through(&self, view: &View) -> Self {
+        let focus_dist = (view.look_at - view.look_from).length();
+        self.clone()
+            .look_from(view.look_from)
+            .look_at(view.look_at)
+            .focus_dist(focus_dist)
+    }
+
+    /// Returns this camera swung `degrees` around the up vector through the point it
+    /// looks at, anticlockwise seen from above, for turntable animations.
+    pub fn orbit(&self, degrees: f64) -> Self {
+        let theta = degrees_to_radians(degrees);
+        let axis = self.vup.unit();
+        let offset = self.look_from - self.look_at;
+        // Rodrigues' rotation formula
+        let rotated = offset * theta.cos()
+            + axis.cross(&offset) * theta.sin()
+            + axis * axis.dot(&offset) * (1.0 - theta.cos());
+        self.clone().look_from(self.look_at + rotated)
+    }
+
+    /// Adds a light that is sampled directly from diffuse surfaces.
+    pub fn light(self, light: Light) -> Self {
+        self.linked_light(light, LightLink::All)
+    }
+
+    /// Adds a light that only illuminates the light groups `link` allows.
+    pub fn linked_light(mut self, light: Light, link: LightLink) -> Self {
+        self.lights.push(light);
+        self.light_links.push(link);
+        self
+    }
+
+    /// Adds an emissive object, also placed in the world, that diffuse surfaces and
+    /// media aim half of their bounces at.
+    ///
+    /// Small emitters such as glowing spheres are otherwise only found by bouncing
+    /// into them by chance, which leaves the surfaces they light full of fireflies.
+    ///
+    /// Fails with [`Error::Build`] for objects that cannot be sampled, such as
+    /// meshes and instances, whose bounces would otherwise all be aimed one way.
+    pub fn light_object(mut self, object: Arc<dyn Hittable>) -> Result<Self, Error> {
+        if !object.is_sampleable() {
+            return Err(Error::Build("light object cannot be sampled"));
+        }
+        self.light_objects.push(object);
+        Ok(self)
+    }
+
+    /// Compresses highlights of the finished image into a soft shoulder below white.
+    pub fn highlight_rolloff(mut self, rolloff: Option<HighlightRolloff>) -> Self {
+        self.highlight_rolloff = rolloff;
+        self
+    }
+
+    /// Applies distance fog to the finished image using its depth.
+    pub fn fog(mut self, fog: Fog) -> Self {
+        self.fog = Some(fog);
+        self
+    }
+
+    /// Fills the space around every object with `medium`, so camera rays haze
+    /// with distance even where no fog volume has been modelled.
+    pub fn medium(mut self, medium: Option<Medium>) -> Self {
+        self.medium = medium;
+        self
+    }
+
+    /// Limits how far rays that hit nothing travel through the camera medium
+    /// before reaching the background, which otherwise a scattering medium hides.
+    pub fn medium_extent(mut self, extent: Option<f64>) -> Self {
+        self.medium_extent = extent;
+        self
+    }
+
+    /// Sets how samples are placed within each pixel and on the lens.
+    pub fn pixel_sampling(mut self, pixel_sampling: PixelSampling) -> Self {
+        self.pixel_sampling = pixel_sampling;
+        self
+    }
+
+    /// Replaces NaN or infinite radiance with black, noting where it came from in
+    /// the rendered image's diagnostics.
+    pub fn nan_guard(mut self, nan_guard: bool) -> Self {
+        self.nan_guard = nan_guard;
+        self
+    }
+
+    /// Sets the radiance seen by rays that leave the scene.
+    pub fn background(mut self, background: Background) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// Shades every surface with `material` instead of its own, keeping the lights
+    /// and the fog and smoke of participating media.
+    pub fn material_override(mut self, material: Option<Material>) -> Self {
+        self.material_override = material;
+        self
+    }
+
+    /// Makes renders repeat exactly, by drawing each pixel's random numbers from a
+    /// generator seeded with `seed` and the pixel's coordinates.
+    ///
+    /// The image then no longer depends on how many threads render it or in which
+    /// order, so renders can be compared against golden images. Without a seed
+    /// every render is different.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Records the seed the scene was generated from in rendered images.
+    pub fn scene_seed(mut self, scene_seed: u64) -> Self {
+        self.scene_seed = Some(scene_seed);
+        self
+    }
+
+    /// Selects how rendered images are encoded for display, the last step of the
+    /// color pipeline.
+    pub fn display(mut self, display: DisplayTransform) -> Self {
+        self.display = display;
+        self
+    }
+
+    /// Applies a lookup table, such as a film-stock look read from a `.cube` file, to
+    /// rendered images after the display transform.
+    pub fn lut(mut self, lut: Option<Arc<Lut>>) -> Self {
+        self.lut = lut;
+        self
+    }
+
+    /// Selects what is rendered into each pixel.
+    pub fn pass(mut self, pass: RenderPass) -> Self {
+        self.pass = pass;
+        self
+    }
+
+    /// Selects how the work of tracing paths is scheduled.
+    pub fn renderer(mut self, renderer: Renderer) -> Self {
+        self.renderer = renderer;
+        self
+    }
+
+    /// Selects how lights are picked for sampling at each diffuse bounce.
+    pub fn light_sampling(mut self, light_sampling: LightSampling) -> Self {
+        self.light_sampling = light_sampling;
+        self
+    }
+
+    /// Learns where light comes from in a short training render, then uses it to
+    /// steer diffuse bounces towards the brightest directions.
+    pub fn path_guiding(mut self, path_guiding: bool) -> Self {
+        self.path_guiding = path_guiding;
+        self
+    }
+
+    /// Lets shadow rays pass straight through glass and other transparent surfaces,
+    /// filtered by their color, instead of stopping at them.
+    ///
+    /// Light through stained glass then reaches diffuse surfaces by direct lighting
+    /// rather than only as a caustic found by chance, at the cost of ignoring how
+    /// the glass bends it; caustics from area lights are counted twice.
+    pub fn transparent_shadows(mut self, transparent_shadows: bool) -> Self {
+        self.transparent_shadows = transparent_shadows;
+        self
+    }
+
+    /// Makes rays scattered from a surface skip the primitive they leave, rather
+    /// than starting a small distance off it, for the primitives `exclusion` covers.
+    ///
+    /// Offsets scaled to the scene can still land on the wrong side of very small
+    /// or very distant geometry; skipping the primitive cannot.
+    pub fn self_hit_exclusion(mut self, exclusion: SelfHitExclusion) -> Self {
+        self.self_hit_exclusion = exclusion;
+        self
+    }
+
+    /// Tilts the plane of focus away from the image plane, as the front standard of
+    /// a view camera does, by `tilt` degrees about the horizontal axis and `swing`
+    /// degrees about the vertical one.
+    ///
+    /// Positive angles tip the top and right of the plane away from the camera, so a
+    /// ground plane can be sharp from near to far, or a thin band sharp for a
+    /// miniature effect. The image itself is unchanged; only what is in focus moves.
+    pub fn tilt(mut self, tilt: f64, swing: f64) -> Self {
+        self.tilt = (tilt, swing);
+        self
+    }
+
+    /// Slides the lens across the image plane by fractions of the view's width and
+    /// height, moving the view without turning the camera.
+    ///
+    /// Shifting up instead of tilting the camera up keeps vertical lines parallel
+    /// in architectural renders.
+    pub fn lens_shift(mut self, x: f64, y: f64) -> Self {
+        self.lens_shift = (x, y);
+        self
+    }
+
+    /// Sets the units the scene is modeled in, so distance tolerances and the
+    /// default focus distance keep their physical size.
+    pub fn units(mut self, units: Units) -> Self {
+        self.units = units;
+        self
+    }
+
+    /// Points the camera at the middle of `scene` from its current direction, moving
+    /// it back just far enough for the whole scene to fit in view.
+    ///
+    /// `padding` widens the margin around the scene as a fraction of its size. The
+    /// focus distance is set to the scene's center. Unlike the other setters this
+    /// depends on the aspect ratio and field of view, so set those first.
+    pub fn frame_scene(mut self, scene: &Bvh, padding: f64) -> Self {
+        let bounds = scene.bounds();
+        let center = bounds.center();
+        let radius =
+            (bounds.diagonal().length() / 2.0 * (1.0 + padding.max(0.0))).max(f64::EPSILON);
+
+        let view = self.look_from - self.look_at;
+        let direction = if view.near_zero() {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            view.unit()
+        };
+
+        // The bounding sphere must fit within the narrower of the two fields of view
+        let vertical = degrees_to_radians(self.vertical_fov) / 2.0;
+        let horizontal = (vertical.tan() * self.aspect_ratio).atan();
+        let distance = radius / vertical.min(horizontal).sin();
+
+        self.look_at = center;
+        self.look_from = center + direction * distance;
+        self.focus_dist = Some(distance);
         self
     }
 
     /// Build the camera with the configured parameters.
     pub fn build(self) -> Camera {
         // Calculate image height based on aspect ratio, ensuring it's at least 1
-        let image_height =
-            ((self.image_width as f64 / self.aspect_ratio) as u32).max(MIN_IMAGE_HEIGHT);
+        let image_height = match self.projection {
+            Projection::Perspective => {
+                ((self.image_width as f64 / self.aspect_ratio) as u32).max(MIN_IMAGE_HEIGHT)
+            }
+            // One 2:1 panorama per eye
+            Projection::OmniStereo { .. } => 2 * (self.image_width / 2).max(MIN_IMAGE_HEIGHT),
+        };
+        // Supersampling renders every output pixel as a block of internal pixels
+        let image_width = self.image_width * self.supersample;
+        let image_height = image_height * self.supersample;
 
         let pixel_samples_scale = 1.0 / (self.samples_per_pixel as f64);
+        // Each sample only needs to cover its share of the pixel
+        let differential_scale =
+            (1.0 / (self.samples_per_pixel as f64).sqrt()).max(MIN_DIFFERENTIAL_SCALE);
         let center = self.look_from;
+        let focus_dist = self
+            .focus_dist
+            .unwrap_or(self.units.scene_length(DEFAULT_FOCUS_DIST));
 
         // Calculate viewport dimensions
         let theta = degrees_to_radians(self.vertical_fov);
         let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h * self.focus_dist;
-        let viewport_width = viewport_height * (self.image_width as f64 / image_height as f64);
+        let viewport_height = 2.0 * h * focus_dist;
+        let viewport_width = viewport_height * (image_width as f64 / image_height as f64);
 
         // Calculate camera basis vectors
         let w = (self.look_from - self.look_at).unit();
@@ -156,33 +927,93 @@ impl CameraBuilder {
         let view_port_v = viewport_height * -v;
 
         // Calculate pixel delta vectors
-        let pixel_delta_u = view_port_u / self.image_width as f64;
+        let pixel_delta_u = view_port_u / image_width as f64;
         let pixel_delta_v = view_port_v / image_height as f64;
 
-        // Calculate location of upper-left pixel
+        // Calculate location of upper-left pixel, moved by any lens shift
+        let shift = self.lens_shift.0 * view_port_u - self.lens_shift.1 * view_port_v;
         let viewport_upper_left =
-            center.as_vec3() - self.focus_dist * w - view_port_u / 2.0 - view_port_v / 2.0;
+            center.as_vec3() - focus_dist * w - view_port_u / 2.0 - view_port_v / 2.0 + shift;
         let pixel00_loc =
             Point3::from(viewport_upper_left + 0.5 * pixel_delta_u + 0.5 * pixel_delta_v);
 
         // Calculate defocus disk vectors
-        let defocus_radius = self.focus_dist * (degrees_to_radians(self.defocus_angle) / 2.0).tan();
+        let defocus_radius = focus_dist * (degrees_to_radians(self.defocus_angle) / 2.0).tan();
         let defocus_disk_u = defocus_radius * u;
         let defocus_disk_v = defocus_radius * v;
 
+        // Tipping the plane's top away turns its normal from w towards v
+        let focus_tilt_normal = (self.tilt != (0.0, 0.0)).then(|| {
+            let (tilt, swing) = (
+                degrees_to_radians(self.tilt.0),
+                degrees_to_radians(self.tilt.1),
+            );
+            let tilted = w * tilt.cos() + v * tilt.sin();
+            (tilted * swing.cos() + u * swing.sin()).unit()
+        });
+
         Camera {
             image_height,
-            image_width: self.image_width,
+            image_width,
             center,
             pixel00_loc,
             pixel_delta_u,
             pixel_delta_v,
             pixel_samples_scale,
             samples_per_pixel: self.samples_per_pixel,
+            differential_scale,
             max_depth: self.max_depth,
             defocus_angle: self.defocus_angle,
             defocus_disk_u,
             defocus_disk_v,
+            focus_center: center + -focus_dist * w,
+            focus_tilt_normal,
+            fog: self.fog,
+            medium: self.medium,
+            medium_extent: self.medium_extent,
+            highlight_rolloff: self.highlight_rolloff,
+            pixel_sampling: self.pixel_sampling,
+            nan_guard: self.nan_guard,
+            non_finite: Arc::default(),
+            background: self.background,
+            pass: self.pass,
+            renderer: self.renderer,
+            light_sampling: self.light_sampling,
+            light_tree: match self.light_sampling {
+                LightSampling::All => LightTree::default(),
+                LightSampling::Tree => LightTree::new(&self.lights),
+            },
+            lights: self.lights,
+            light_links: self.light_links,
+            light_objects: self.light_objects,
+            path_guiding: self.path_guiding,
+            transparent_shadows: self.transparent_shadows,
+            self_hit_exclusion: self.self_hit_exclusion,
+            guide: None,
+            ray_t_min: self.units.scene_length(RAY_T_MIN),
+            material_override: self.material_override,
+            scene_seed: self.scene_seed,
+            display: self.display,
+            lut: self.lut,
+            supersample: self.supersample,
+            tile_size: self.tile_size,
+            adaptive_sampling: self.adaptive_sampling,
+            tile_time_budget: self.tile_time_budget,
+            progress: self.progress,
+            seed: self.seed,
+            omni_stereo: match self.projection {
+                Projection::Perspective => None,
+                Projection::OmniStereo { ipd } => Some(OmniStereo {
+                    half_ipd: self.units.scene_length(ipd) / 2.0,
+                    right: u,
+                    up: v,
+                    forward: -w,
+                }),
+            },
+            regularization: self
+                .regularization
+                .filter(|&roughness| roughness > 0.0)
+                .map(|roughness| (roughness.clamp(0.0, 1.0) * f64::consts::FRAC_PI_2).cos()),
         }
     }
 }
@@ -194,9 +1025,13 @@ impl Camera {
     ///
     /// * `i` - The x-coordinate of the pixel
     /// * `j` - The y-coordinate of the pixel
-    fn get_ray(&self, i: u32, j: u32) -> Ray {
-        // Get a random offset within the pixel for anti-aliasing
-        let offset = Vec3::sample_square();
+    /// * `sample` - Where the sample lands within the pixel and on the lens
+    fn get_ray(&self, i: u32, j: u32, sample: &CameraSample) -> Ray {
+        // Offset within the pixel for anti-aliasing
+        let offset = sample.pixel_offset;
+        if let Some(stereo) = &self.omni_stereo {
+            return self.omni_stereo_ray(stereo, i, j, offset);
+        }
 
         // Calculate the exact position on the viewport
         let pixel_sample = *self.pixel00_loc
@@ -207,120 +1042,1585 @@ impl Camera {
         let ray_origin = if self.defocus_angle <= 0.0 {
             self.center
         } else {
-            Point3::from(self.defocus_disk_sample())
+            Point3::from(self.defocus_disk_sample(&sample.lens))
         };
 
-        let ray_direction = pixel_sample - *ray_origin;
+        let ray_direction = self.focus_point(pixel_sample) - *ray_origin;
         let ray_time = random_double();
-        Ray::new(ray_origin, ray_direction, ray_time)
+
+        // Offset rays through the neighbouring pixels, sharing the lens sample
+        let differentials = RayDifferentials {
+            rx_origin: ray_origin,
+            rx_direction: ray_direction + self.pixel_delta_u,
+            ry_origin: ray_origin,
+            ry_direction: ray_direction + self.pixel_delta_v,
+        };
+
+        Ray::new(ray_origin, ray_direction, ray_time).with_differentials(Some(
+            differentials.scaled(&ray_origin, &ray_direction, self.differential_scale),
+        ))
+    }
+
+    /// Generate the ray through a pixel of an omnidirectional stereo image.
+    ///
+    /// Each column looks out at one longitude, starting behind the camera, and
+    /// each eye's rows run from straight up to straight down. Rays start on a
+    /// circle with the eyes' separation as its diameter, on the side of the center
+    /// each eye would be on when facing that longitude.
+    fn omni_stereo_ray(&self, stereo: &OmniStereo, i: u32, j: u32, offset: Vec3) -> Ray {
+        let eye_height = self.image_height / 2;
+        let (eye, row) = if j < eye_height {
+            (-1.0, j)
+        } else {
+            (1.0, j - eye_height)
+        };
+        let ray_at = |x: f64, y: f64| {
+            let longitude = (x / self.image_width as f64 - 0.5) * 2.0 * f64::consts::PI;
+            let latitude = (0.5 - y / eye_height as f64) * f64::consts::PI;
+            let heading = stereo.right * longitude.sin() + stereo.forward * longitude.cos();
+            let side = stereo.right * longitude.cos() - stereo.forward * longitude.sin();
+            (
+                self.center + side * (eye * stereo.half_ipd),
+                heading * latitude.cos() + stereo.up * latitude.sin(),
+            )
+        };
+
+        let (x, y) = (i as f64 + 0.5 + offset.x(), row as f64 + 0.5 + offset.y());
+        let (origin, direction) = ray_at(x, y);
+        let (rx_origin, rx_direction) = ray_at(x + 1.0, y);
+        let (ry_origin, ry_direction) = ray_at(x, y + 1.0);
+        let differentials = RayDifferentials {
+            rx_origin,
+            rx_direction,
+            ry_origin,
+            ry_direction,
+        };
+
+        Ray::new(origin, direction, random_double()).with_differentials(Some(differentials.scaled(
+            &origin,
+            &direction,
+            self.differential_scale,
+        )))
     }
 
-    /// Sample a point on the defocus disk for depth-of-field effect.
-    fn defocus_disk_sample(&self) -> Vec3 {
-        let p = Vec3::random_in_unit_disk();
+    /// Returns where the pinhole ray through `pixel_sample` meets the plane of focus.
+    ///
+    /// Untilted, the viewport lies on the plane of focus, so that is the sample itself.
+    fn focus_point(&self, pixel_sample: Vec3) -> Vec3 {
+        let Some(normal) = self.focus_tilt_normal else {
+            return pixel_sample;
+        };
+        let direction = pixel_sample - self.center.as_vec3();
+        let t = (self.focus_center - self.center).dot(&normal) / direction.dot(&normal);
+        // Rays parallel to, or pointing away from, the plane never reach focus
+        if t.is_finite() && t > 0.0 {
+            self.center.as_vec3() + t * direction
+        } else {
+            pixel_sample
+        }
+    }
+
+    /// Map a point in the unit disk onto the defocus disk for depth-of-field effect.
+    fn defocus_disk_sample(&self, p: &Vec3) -> Vec3 {
         self.center.as_vec3() + (p.x() * self.defocus_disk_u) + (p.y() * self.defocus_disk_v)
     }
 
+    /// Finds the closest surface `ray` hits, marking whether rays spawned from it
+    /// skip the primitive hit.
+    fn intersect<'a>(&self, world: &'a dyn Hittable, ray: &Ray) -> Option<HitRecord<'a>> {
+        let mut hit_record = world.hit(ray, Interval::new(self.ray_t_min, f64::INFINITY))?;
+        hit_record.exclude_primitive = hit_record
+            .primitive
+            .is_some_and(|primitive| self.self_hit_exclusion.excludes(primitive));
+        Some(hit_record)
+    }
+
+    /// Finds where `ray` next interacts while travelling through the innermost of
+    /// `media`: a surface, or a particle of the medium if it scatters first.
+    ///
+    /// Also returns the fraction of the ray's light the medium lets through on the way.
+    fn intersect_in<'a>(
+        &self,
+        world: &'a dyn Hittable,
+        ray: &Ray,
+        media: &MediumStack<'a>,
+    ) -> (Color, Option<HitRecord<'a>>) {
+        let hit = self.intersect(world, ray);
+        let Some(medium) = media.current() else {
+            return (Color::new(1.0, 1.0, 1.0), hit);
+        };
+        let t_max = hit.as_ref().map_or_else(
+            || {
+                self.medium_extent
+                    .map_or(f64::INFINITY, |extent| extent / ray.direction().length())
+            },
+            |hit_record| hit_record.t,
+        );
+        let (transmittance, scattered) = medium.sample(ray, t_max);
+        (transmittance, scattered.or(hit))
+    }
+
+    /// Returns the media a path leaving the camera starts out in.
+    fn media(&self) -> MediumStack<'_> {
+        MediumStack::new(self.medium.as_ref())
+    }
+
+    /// Trace a primary ray, returning its color and the distance to the first hit.
+    fn sample(&self, ray: &Ray, world: &dyn Hittable, id: SampleId) -> (Color, Option<f64>) {
+        if self.max_depth == 0 {
+            return (BLACK, None);
+        }
+
+        let (transmittance, hit) = match self.pass {
+            RenderPass::MaterialId => (Color::new(1.0, 1.0, 1.0), self.intersect(world, ray)),
+            _ => self.intersect_in(world, ray, &self.media()),
+        };
+        let distance = hit
+            .as_ref()
+            .map(|hit_record| hit_record.t * ray.direction().length());
+        let color = match self.pass {
+            RenderPass::Beauty | RenderPass::BvhCost | RenderPass::Time => {
+                transmittance * self.ray_color(ray, hit, self.max_depth, world, id)
+            }
+            RenderPass::MaterialId => hit
+                .and_then(|hit_record| hit_record.material)
+                .map_or(BLACK, Material::id_color),
+        };
+        (color, distance)
+    }
+
     /// Calculate the color for a ray in the scene.
     ///
+    /// The path is followed iteratively, carrying the product of the attenuations so far
+    /// as its throughput, so `depth` is not limited by the stack.
+    ///
     /// # Arguments
     ///
     /// * `ray` - The ray to trace
-    /// * `depth` - The maximum recursion depth remaining
+    /// * `hit` - Where the ray first hits the world
+    /// * `depth` - The maximum number of bounces
     /// * `world` - The scene to render
-    fn ray_color(ray: &Ray, depth: u32, world: &dyn crate::hittable::Hittable) -> Color {
-        // If we've exceeded the ray bounce limit, no more light is gathered
-        if depth == 0 {
-            return BLACK;
-        }
+    /// * `id` - The camera sample the ray's path started from
+    fn ray_color<'a>(
+        &self,
+        ray: &Ray,
+        hit: Option<HitRecord<'a>>,
+        depth: u32,
+        world: &'a dyn Hittable,
+        id: SampleId,
+    ) -> Color {
+        let mut radiance = BLACK;
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        let mut hit = hit;
+        let mut media = self.media();
+
+        for bounce in 0..depth {
+            let Some(hit_record) = hit else {
+                radiance += throughput * self.background.color(&ray);
+                break;
+            };
+            let (contribution, next) =
+                self.bounce(&ray, &hit_record, throughput, world, id, bounce);
+            radiance += contribution;
+            let Some((next_throughput, scatter)) = next else {
+                break;
+            };
 
-        // Check if the ray hits anything in the world
-        if let Some(hit_record) = world.hit(ray, Interval::new(RAY_T_MIN, f64::INFINITY)) {
-            // If there's a material, calculate scattered ray
-            if let Some(material) = &hit_record.material {
-                let (attenuation, scatter) = material.scatter(ray, &hit_record);
-                return Self::ray_color(&scatter, depth - 1, world) * attenuation;
+            throughput = next_throughput;
+            if bounce + 1 == depth {
+                break;
             }
-            return BLACK;
+            media.cross(&hit_record, &scatter);
+            ray = scatter;
+            let (transmittance, next_hit) = self.intersect_in(world, &ray, &media);
+            throughput = throughput * transmittance;
+            hit = next_hit;
         }
 
-        // Background - a simple gradient
-        let unit_direction = ray.direction().unit();
-        let t = 0.5 * (unit_direction.y() + 1.0);
-        WHITE * (1.0 - t) + SKY_BLUE * t
+        radiance
     }
 
-    /// Render the scene to PPM format on stdout.
-    ///
-    /// # Arguments
+    /// Shade one bounce of a path that has reached `hit_record` with `throughput`.
     ///
-    /// * `world` - The scene to render (any object implementing Hittable)
-    pub fn render(&self, world: &dyn crate::hittable::Hittable) {
-        // Create a progress bar for tracking scanlines
-        let progress_bar = ProgressBar::new(self.image_height as u64);
-        progress_bar.set_style(
-            ProgressStyle::default_bar()
-                .template("[{elapsed_precise}] [{bar:80.cyan/blue}] {pos}/{len} scanlines ({eta})")
-                .expect("Invalid progress bar template")
-                .progress_chars("#>-"),
-        );
+    /// Returns the light gathered here, and the path's new throughput and scattered
+    /// ray unless the path ends at this surface.
+    fn bounce(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        throughput: Color,
+        world: &dyn Hittable,
+        id: SampleId,
+        bounce: u32,
+    ) -> (Color, Option<(Color, Ray)>) {
+        // Without a material the surface absorbs everything
+        let Some(material) = self.material(hit_record) else {
+            return (BLACK, None);
+        };
 
-        // Process scanlines in parallel
-        let image: Vec<Vec<Color>> = (0..self.image_height)
-            .into_par_iter() // Parallelize over scanlines
-            .map(|j| {
-                // Process each pixel in the current scanline
-                let row: Vec<Color> = (0..self.image_width)
-                    .into_par_iter() // Parallelize over pixels in the scanline
-                    .map(|i| {
-                        // Start with black
-                        let mut pixel_color = BLACK;
-
-                        // Sample each pixel multiple times for anti-aliasing
-                        for _ in 0..self.samples_per_pixel {
-                            let ray = self.get_ray(i, j);
-                            pixel_color += Self::ray_color(&ray, self.max_depth, world);
-                        }
+        let (u, v) = hit_record.texture_coords;
+        let emitted = throughput * material.emitted(u, v, &hit_record.position);
+        let albedo = material.diffuse_reflectance(hit_record);
+        let lobe = albedo
+            .map(Lobe::Diffuse)
+            .or_else(|| material.phase_albedo(hit_record).map(Lobe::Isotropic));
+        let direct = lobe.map_or(BLACK, |lobe| {
+            self.direct_light(hit_record, ray, lobe, world, id, bounce)
+        });
+        let scattered = match (&self.guide, albedo, lobe) {
+            (Some(guide), Some(albedo), _) => material
+                .scatter(ray, hit_record)
+                .map(|(_, bsdf)| self.guided_scatter(guide, ray, hit_record, albedo, bsdf)),
+            // Materials that don't say how they scatter are taken to follow their lobe
+            (_, _, Some(lobe)) if !self.light_objects.is_empty() => {
+                let (albedo, pdf) = match lobe {
+                    Lobe::Isotropic(albedo) => (albedo, ScatterPdf::Sphere(SpherePdf)),
+                    Lobe::Diffuse(albedo)
+                    | Lobe::Cone {
+                        attenuation: albedo,
+                        ..
+                    } => (
+                        albedo,
+                        ScatterPdf::Cosine(CosinePdf::new(&hit_record.normal)),
+                    ),
+                };
+                let pdf = material.scatter_pdf(hit_record).unwrap_or(pdf);
+                Some(self.light_object_scatter(ray, hit_record, albedo, pdf))
+            }
+            _ => material.scatter(ray, hit_record),
+        };
+        let (contribution, next) = match scattered {
+            None => (emitted + throughput * direct, None),
+            Some((attenuation, scatter)) => {
+                let (direct, scatter) = match self.regularization {
+                    Some(cos_max) if lobe.is_none() && bounce > 0 => {
+                        let lobe = Lobe::Cone {
+                            attenuation,
+                            axis: scatter.direction().unit(),
+                            cos_max,
+                        };
+                        let direct = self.direct_light(hit_record, ray, lobe, world, id, bounce);
+                        (direct, regularize(hit_record, &scatter, cos_max))
+                    }
+                    _ => (direct, scatter),
+                };
+                // The whole path sees the levels of detail its camera sample picked
+                let scatter = scatter.with_viewpoint(*ray.viewpoint());
+                let next_throughput = throughput * attenuation;
+                let contribution = emitted
+                    + throughput * direct
+                    + next_throughput * self.scattered_emission(&scatter, hit_record, lobe, world);
+                (contribution, Some((next_throughput, scatter)))
+            }
+        };
 
-                        // Scale the color by the number of samples
-                        pixel_color * self.pixel_samples_scale
-                    })
-                    .collect();
+        // Earlier bounces were finite, so this surface is the culprit
+        let next_finite = next.is_none_or(|(throughput, _)| throughput.is_finite());
+        if self.nan_guard && !(contribution.is_finite() && next_finite) {
+            let diagnostic = Diagnostic::NonFinite {
+                pixel: id.pixel,
+                sample: id.sample,
+                bounce,
+                value: match next {
+                    Some((throughput, _)) if contribution.is_finite() => throughput,
+                    _ => contribution,
+                },
+                material: material.name(),
+                position: hit_record.position,
+            };
+            self.non_finite
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(diagnostic);
+            return (BLACK, None);
+        }
 
-                // Increment the progress bar for each completed scanline
-                progress_bar.inc(1);
-                row
-            })
-            .collect();
+        (contribution, next)
+    }
+
+    /// Light from an area light that the scattered ray reaches directly.
+    ///
+    /// Diffuse surfaces and media, which scatter into `lobe`, also sample the lights,
+    /// so what the ray finds is weighted against that by the power heuristic; other
+    /// surfaces only find lights this way. Either way the light must be linked to
+    /// the surface the ray leaves.
+    fn scattered_emission(
+        &self,
+        scatter: &Ray,
+        hit_record: &HitRecord,
+        lobe: Option<Lobe>,
+        world: &dyn Hittable,
+    ) -> Color {
+        let ray_t = Interval::new(self.ray_t_min, f64::INFINITY);
+        let Some((index, t, radiance)) = self
+            .lights
+            .iter()
+            .enumerate()
+            .filter_map(|(index, light)| light.hit(scatter, ray_t).map(|(t, l)| (index, t, l)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+        else {
+            return BLACK;
+        };
+        if !self.light_links[index].illuminates(hit_record.light_group) {
+            return BLACK;
+        }
+
+        let shadow_ray = scatter.with_kind(RayKind::Shadow);
+        let shadow_t = Interval::new(self.ray_t_min, t * (1.0 - SHADOW_RAY_MARGIN));
+        let Some(transmittance) = self.shadow_transmittance(&shadow_ray, shadow_t, world) else {
+            return BLACK;
+        };
+        let radiance = radiance * transmittance;
+        let Some(lobe) = lobe else {
+            return radiance;
+        };
 
-        // Finish the progress bar
-        progress_bar.finish_with_message("Rendering complete");
+        let direction = scatter.direction().unit();
+        let light_pdf = self.light_selection_pdf(index, &hit_record.position)
+            * self.lights[index]
+                .pdf(&hit_record.position, &direction)
+                .unwrap_or(0.0);
+        radiance * power_heuristic(self.lobe_pdf(lobe, hit_record, &direction), light_pdf)
+    }
 
-        // Output PPM header
-        println!("P3");
-        println!("{} {}", self.image_width, self.image_height);
-        println!("255");
+    /// Probability that direct lighting at `position` samples light `index`.
+    fn light_selection_pdf(&self, index: usize, position: &Point3) -> f64 {
+        match self.light_sampling {
+            LightSampling::All => 1.0,
+            LightSampling::Tree if self.light_tree.distant().contains(&index) => 1.0,
+            LightSampling::Tree => self.light_tree.pdf(position, index),
+        }
+    }
 
-        // Output all scanlines
-        for scanline in image {
-            for pixel in scanline {
-                println!("{}", pixel.write_color());
+    /// Density over solid angle with which a bounce into `lobe` leaves in `direction`.
+    fn lobe_pdf(&self, lobe: Lobe, hit_record: &HitRecord, direction: &Vec3) -> f64 {
+        match lobe {
+            Lobe::Diffuse(_) => self.diffuse_pdf(hit_record, direction),
+            Lobe::Isotropic(_) => self.mix_light_objects(hit_record, direction, UNIFORM_SPHERE_PDF),
+            Lobe::Cone { axis, cos_max, .. } => {
+                if direction.unit().dot(&axis) < cos_max {
+                    0.0
+                } else {
+                    1.0 / (2.0 * f64::consts::PI * (1.0 - cos_max))
+                }
             }
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::bvh::Bvh;
-    use crate::material::TestMaterial;
-    use crate::point3::Point3;
-    use crate::ray::Ray;
+    /// Density over solid angle with which a diffuse bounce leaves in `direction`,
+    /// following the path guide when there is one.
+    fn diffuse_pdf(&self, hit_record: &HitRecord, direction: &Vec3) -> f64 {
+        let bsdf_pdf = direction.unit().dot(&hit_record.normal).max(0.0) / f64::consts::PI;
+        match &self.guide {
+            Some(guide) if guide.covers(&hit_record.position) => {
+                GUIDE_FRACTION * guide.pdf(&hit_record.position, direction)
+                    + (1.0 - GUIDE_FRACTION) * bsdf_pdf
+            }
+            Some(_) => bsdf_pdf,
+            None => self.mix_light_objects(hit_record, direction, bsdf_pdf),
+        }
+    }
+
+    /// Density over solid angle of a bounce that draws from a distribution with
+    /// density `pdf` half the time and aims at the light objects otherwise, if
+    /// there are any.
+    fn mix_light_objects(&self, hit_record: &HitRecord, direction: &Vec3, pdf: f64) -> f64 {
+        if self.light_objects.is_empty() {
+            return pdf;
+        }
+        let towards_lights = HittablePdf::new(&self.light_objects, hit_record.position);
+        0.5 * (towards_lights.value(direction) + pdf)
+    }
+
+    /// Picks a bounce direction from an even mix of the material's own distribution,
+    /// `pdf`, and one aimed at the light objects.
+    ///
+    /// `albedo` is the reflectance of the diffuse surface or medium. The returned
+    /// attenuation weights the chosen direction by the mixed density.
+    fn light_object_scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        albedo: Color,
+        pdf: ScatterPdf,
+    ) -> (Color, Ray) {
+        let towards_lights = HittablePdf::new(&self.light_objects, hit_record.position);
+        let mixture = MixturePdf::new(&towards_lights, &pdf);
+        let direction = with_thread_rng(|rng| mixture.generate(rng));
+        let density = mixture.value(&direction);
+
+        match pdf {
+            // Media scatter from a point inside, with no surface to escape
+            ScatterPdf::Sphere(_) => {
+                let scatter = Ray::new(hit_record.position, direction, ray.time())
+                    .with_kind(RayKind::Secondary);
+                (albedo * (UNIFORM_SPHERE_PDF / density), scatter)
+            }
+            ScatterPdf::Cosine(_) => {
+                let scatter = hit_record.spawn_ray(direction, ray.time());
+                let cosine = direction.dot(&hit_record.normal);
+                if cosine <= 0.0 || direction.dot(&hit_record.geometric_normal) <= 0.0 {
+                    return (BLACK, scatter);
+                }
+                (albedo * (cosine / f64::consts::PI / density), scatter)
+            }
+        }
+    }
+
+    /// Returns the material a hit is shaded with, honoring any override.
+    fn material<'a>(&'a self, hit_record: &HitRecord<'a>) -> Option<&'a Material> {
+        hit_record.material.map(|material| match material {
+            Material::Isotropic(_) => material,
+            _ => self.material_override.as_ref().unwrap_or(material),
+        })
+    }
+
+    /// Picks a diffuse bounce direction from either the path guide or the BSDF.
+    ///
+    /// `bsdf` is the ray the material scattered by itself. The returned attenuation
+    /// weights the chosen direction by the combined density of both strategies.
+    fn guided_scatter(
+        &self,
+        guide: &PathGuide,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        albedo: Color,
+        bsdf: Ray,
+    ) -> (Color, Ray) {
+        let position = &hit_record.position;
+        let Some(guided) =
+            guide.sample(position, random_double(), random_double(), random_double())
+        else {
+            return (albedo, bsdf);
+        };
+
+        let direction = if random_double() < GUIDE_FRACTION {
+            guided
+        } else {
+            bsdf.direction().unit()
+        };
+        let scatter = hit_record.spawn_ray(direction, ray.time());
+        let cosine = direction.dot(&hit_record.normal);
+        if cosine <= 0.0 || direction.dot(&hit_record.geometric_normal) <= 0.0 {
+            return (BLACK, scatter);
+        }
+
+        let bsdf_pdf = cosine / f64::consts::PI;
+        let pdf =
+            GUIDE_FRACTION * guide.pdf(position, &direction) + (1.0 - GUIDE_FRACTION) * bsdf_pdf;
+        (albedo * (bsdf_pdf / pdf), scatter)
+    }
+
+    /// Light arriving directly from the analytic lights and reflected into `lobe`.
+    ///
+    /// Each sampled light is tested for occlusion with a shadow ray. With the light
+    /// tree, one positioned light is picked per call and weighted by the inverse of
+    /// its probability, while distant lights are always sampled. Lights whose link
+    /// leaves out the surface's light group contribute nothing.
+    ///
+    /// Where on each light the sample lands is stratified across the samples of the
+    /// pixel `id`, separately for every light and `bounce`. Shadow rays share the
+    /// time and viewpoint of `ray`, the ray that reached the surface.
+    fn direct_light(
+        &self,
+        hit_record: &HitRecord,
+        ray: &Ray,
+        lobe: Lobe,
+        world: &dyn Hittable,
+        id: SampleId,
+        bounce: u32,
+    ) -> Color {
+        let contribution = |index: usize| {
+            // Lights not linked to the surface's group are skipped once picked
+            if !self.light_links[index].illuminates(hit_record.light_group) {
+                return BLACK;
+            }
+            let dimension = bounce * self.lights.len() as u32 + index as u32;
+            let u = id.sampler.light(id.sample, dimension);
+            self.light_contribution(index, hit_record, ray, lobe, world, u)
+        };
+        match self.light_sampling {
+            LightSampling::All => {
+                (0..self.lights.len()).fold(BLACK, |direct, index| direct + contribution(index))
+            }
+            LightSampling::Tree => {
+                let mut direct = self
+                    .light_tree
+                    .distant()
+                    .iter()
+                    .fold(BLACK, |direct, &index| direct + contribution(index));
+                if let Some((index, pdf)) = self.light_tree.sample(&hit_record.position) {
+                    direct += contribution(index) * (1.0 / pdf);
+                }
+                direct
+            }
+        }
+    }
+
+    /// Light reflected into `lobe` from one sample of light `index`, placed on the
+    /// light by `u`.
+    ///
+    /// Samples of area lights are weighted against the diffuse bounce finding the
+    /// same light by the power heuristic. Blurred specular bounces leave area lights
+    /// to their scattered ray alone.
+    fn light_contribution(
+        &self,
+        index: usize,
+        hit_record: &HitRecord,
+        ray: &Ray,
+        lobe: Lobe,
+        world: &dyn Hittable,
+        u: (f64, f64),
+    ) -> Color {
+        let light = &self.lights[index];
+        if matches!(lobe, Lobe::Cone { .. }) && light.is_reachable() {
+            return BLACK;
+        }
+        let Some(sample) = light.sample(&hit_record.position, u) else {
+            return BLACK;
+        };
+
+        let side = sample.direction.dot(&hit_record.geometric_normal);
+        let reflectance = match lobe {
+            Lobe::Diffuse(albedo) => {
+                let cosine = sample.direction.dot(&hit_record.normal);
+                if cosine <= 0.0 || side <= 0.0 {
+                    return BLACK;
+                }
+                albedo * (cosine / f64::consts::PI)
+            }
+            // Media have no surface to be lit from behind
+            Lobe::Isotropic(albedo) => albedo * UNIFORM_SPHERE_PDF,
+            Lobe::Cone {
+                attenuation,
+                axis,
+                cos_max,
+            } => {
+                if sample.direction.dot(&axis) < cos_max
+                    || side * axis.dot(&hit_record.geometric_normal) <= 0.0
+                {
+                    return BLACK;
+                }
+                attenuation * (1.0 / (2.0 * f64::consts::PI * (1.0 - cos_max)))
+            }
+        };
+
+        let shadow_ray = hit_record
+            .spawn_ray(sample.direction, ray.time())
+            .with_kind(RayKind::Shadow)
+            .with_viewpoint(*ray.viewpoint());
+        let shadow_t = Interval::new(self.ray_t_min, sample.distance * (1.0 - SHADOW_RAY_MARGIN));
+        let Some(transmittance) = self.shadow_transmittance(&shadow_ray, shadow_t, world) else {
+            return BLACK;
+        };
+
+        let weight = light
+            .pdf(&hit_record.position, &sample.direction)
+            .map_or(1.0, |pdf| {
+                let light_pdf = self.light_selection_pdf(index, &hit_record.position) * pdf;
+                power_heuristic(
+                    light_pdf,
+                    self.lobe_pdf(lobe, hit_record, &sample.direction),
+                )
+            });
+        reflectance * sample.irradiance * transmittance * weight
+    }
+
+    /// Fraction of light that reaches the end of `shadow_ray` within `shadow_t`, or
+    /// `None` if something blocks it.
+    ///
+    /// Without transparent shadows any hit blocks the ray. With them the ray passes
+    /// through surfaces that let light through, taking their color, and is only
+    /// blocked by opaque ones or after [`MAX_SHADOW_SURFACES`].
+    fn shadow_transmittance(
+        &self,
+        shadow_ray: &Ray,
+        shadow_t: Interval,
+        world: &dyn Hittable,
+    ) -> Option<Color> {
+        let mut transmittance = Color::new(1.0, 1.0, 1.0);
+        let mut t_min = shadow_t.min();
+        for _ in 0..MAX_SHADOW_SURFACES {
+            let Some(hit_record) = world.hit(shadow_ray, Interval::new(t_min, shadow_t.max()))
+            else {
+                return Some(transmittance);
+            };
+            if !self.transparent_shadows {
+                return None;
+            }
+            transmittance = transmittance
+                * self
+                    .material(&hit_record)?
+                    .shadow_transmittance(shadow_ray, &hit_record)?;
+            t_min = hit_record.t + self.ray_t_min;
+        }
+        None
+    }
+
+    /// Renders the scene and writes it to `path` in the format its extension names,
+    /// returning the image so callers can inspect or write it again.
+    pub fn render_to_file(
+        &self,
+        world: &dyn Hittable,
+        path: impl AsRef<path::Path>,
+    ) -> Result<Framebuffer, ImageError> {
+        // Fail before a long render, not after it
+        framebuffer::format_for(path.as_ref())?;
+        let frame = self.render_frame(world);
+        frame.save(path)?;
+        Ok(frame)
+    }
+
+    /// Render the scene into a framebuffer with its depth and alpha AOVs, applying
+    /// any fog and highlight rolloff.
+    ///
+    /// Fog is applied before a supersampled image is filtered down, so it follows
+    /// the depth of every internal pixel, and the rolloff after.
+    pub fn render_frame(&self, world: &dyn Hittable) -> Framebuffer {
+        self.clear_non_finite();
+        if self.path_guiding && self.guide.is_none() {
+            let guided = Camera {
+                guide: Some(Arc::new(self.train_guide(world))),
+                ..self.clone()
+            };
+            return guided.render_frame(world);
+        }
+        self.finish_frame(&self.render_values(world))
+    }
+
+    /// Renders the scene in passes of `samples_per_pass` samples per pixel, adding
+    /// each to the ones before, until the camera's samples per pixel are taken.
+    ///
+    /// `on_pass` is given the image so far after every pass, finished as
+    /// [`Camera::render_frame`] finishes it, and returns `ControlFlow::Break` to stop
+    /// early. Returns the last image. Passes after the first draw fresh random numbers,
+    /// derived from the seed if there is one, so a single pass renders exactly what
+    /// `render_frame` does.
+    pub fn render_progressive(
+        &self,
+        world: &dyn Hittable,
+        samples_per_pass: u32,
+        mut on_pass: impl FnMut(&Framebuffer) -> ControlFlow<()>,
+    ) -> Framebuffer {
+        self.clear_non_finite();
+        if self.path_guiding && self.guide.is_none() {
+            let guided = Camera {
+                guide: Some(Arc::new(self.train_guide(world))),
+                ..self.clone()
+            };
+            return guided.render_progressive(world, samples_per_pass, on_pass);
+        }
+
+        let mut values: Vec<PixelValue> = Vec::new();
+        let mut taken = 0;
+        for pass in 0.. {
+            let count = samples_per_pass
+                .max(1)
+                .min(self.samples_per_pixel.max(1) - taken);
+            // Ray differentials stay those of the whole render, so textures are
+            // filtered alike in every pass
+            let pass_camera = Camera {
+                samples_per_pixel: count,
+                pixel_samples_scale: 1.0 / count as f64,
+                seed: match pass {
+                    0 => self.seed,
+                    _ => self
+                        .seed
+                        .map(|seed| mix_seed(seed, &[PROGRESSIVE_PASS_KEY, pass])),
+                },
+                ..self.clone()
+            };
+            let pass_values = pass_camera.render_values(world);
+            values = if taken == 0 {
+                pass_values
+            } else {
+                values
+                    .into_iter()
+                    .zip(pass_values)
+                    .map(|(sum, value)| sum.add(value))
+                    .collect()
+            };
+            taken += count;
+
+            let so_far = Camera {
+                samples_per_pixel: taken,
+                ..self.clone()
+            };
+            let frame = so_far.finish_frame(&values);
+            if on_pass(&frame).is_break() || taken >= self.samples_per_pixel {
+                return frame;
+            }
+        }
+        unreachable!("passes run until every sample is taken")
+    }
+
+    /// Renders the scene like [`Camera::render_frame`], calling `on_snapshot` every
+    /// `interval` with the image so far, so a long render can be watched while it runs.
+    ///
+    /// Snapshots show the tiles finished so far, with the rest black and transparent,
+    /// and are taken on a thread of their own so rendering never waits on encoding
+    /// them. The image is rendered tile by tile even when the camera is set to the
+    /// wavefront renderer, which finishes no pixel until its last bounce.
+    pub fn render_streaming(
+        &self,
+        world: &dyn Hittable,
+        interval: Duration,
+        on_snapshot: impl FnMut(&Framebuffer) + Send,
+    ) -> Framebuffer {
+        self.clear_non_finite();
+        if self.path_guiding && self.guide.is_none() {
+            let guided = Camera {
+                guide: Some(Arc::new(self.train_guide(world))),
+                ..self.clone()
+            };
+            return guided.render_streaming(world, interval, on_snapshot);
+        }
+
+        let finished = Mutex::new(vec![None; (self.image_width * self.image_height) as usize]);
+        let (done, stopped) = mpsc::channel::<()>();
+        let values = thread::scope(|scope| {
+            let finished = &finished;
+            scope.spawn(move || {
+                let mut on_snapshot = on_snapshot;
+                // Dropping the sender when rendering ends wakes the thread to stop
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let values: Vec<PixelValue> = finished
+                        .lock()
+                        .expect("tiles are only copied while locked")
+                        .iter()
+                        .map(|value: &Option<PixelValue>| {
+                            value.unwrap_or_else(PixelValue::unrendered)
+                        })
+                        .collect();
+                    on_snapshot(&self.finish_frame(&values));
+                }
+            });
+            let values = self.render_tiles(world, &|x0, y0, pixels| {
+                let mut image = finished.lock().expect("tiles are only copied while locked");
+                self.place_tile(&mut image, x0, y0, pixels);
+            });
+            drop(done);
+            values
+        });
+        self.finish_frame(&values)
+    }
+
+    /// Renders every pixel with the renderer the camera is set to use.
+    fn render_values(&self, world: &dyn Hittable) -> Vec<PixelValue> {
+        // Per-pixel costs can only be measured when each pixel is rendered on its own
+        match (self.renderer, self.pass) {
+            (Renderer::Wavefront, RenderPass::Beauty) if self.tile_time_budget.is_none() => {
+                self.render_wavefront(world)
+            }
+            _ => self.render_pixels(world),
+        }
+    }
+
+    /// Turns rendered pixels into a framebuffer, applying any fog, supersampling and
+    /// highlight rolloff, with what the render noticed attached.
+    fn finish_frame(&self, values: &[PixelValue]) -> Framebuffer {
+        let mut diagnostics = Vec::new();
+        if let Some(guide) = &self.guide {
+            diagnostics.push(Diagnostic::PathGuide {
+                samples: GUIDE_TRAINING_SAMPLES,
+                cells: guide.cell_count(),
+            });
+        }
+        if self.adaptive_sampling.is_some() || self.tile_time_budget.is_some() {
+            let samples: u64 = values.iter().map(|value| value.samples as u64).sum();
+            diagnostics.push(Diagnostic::SamplesTaken {
+                average: samples as f64 / values.len().max(1) as f64,
+                max: self.samples_per_pixel,
+            });
+        }
+        let pixels = match self.pass {
+            RenderPass::BvhCost => self.bvh_cost_heatmap(values, &mut diagnostics),
+            RenderPass::Time => self.time_heatmap(values, &mut diagnostics),
+            _ => values.iter().map(|value| value.color).collect(),
+        };
+        diagnostics.extend(self.non_finite());
+        if let Some(reporter) = &self.progress {
+            diagnostics.extend(
+                reporter
+                    .0
+                    .finish()
+                    .into_iter()
+                    .map(Diagnostic::ProgressNotReported),
+            );
+        }
+        let depth = values.iter().map(|value| value.depth).collect();
+        let alpha = values.iter().map(|value| value.coverage).collect();
+        let mut framebuffer = Framebuffer::new(self.image_width, self.image_height, pixels)
+            .with_depth(depth)
+            .with_alpha(alpha)
+            .with_samples_per_pixel(self.samples_per_pixel)
+            .with_scene_seed(self.scene_seed)
+            .with_display(self.display)
+            .with_lut(self.lut.clone())
+            .with_tile_samples(self.tile_time_budget.map(|_| self.tile_samples(values)))
+            .with_diagnostics(diagnostics);
+        if let Some(fog) = &self.fog {
+            fog.apply(&mut framebuffer);
+        }
+        if self.supersample > 1 {
+            framebuffer = framebuffer.downsample(self.supersample);
+        }
+        if let Some(rolloff) = &self.highlight_rolloff {
+            rolloff.apply(&mut framebuffer);
+        }
+        framebuffer
+    }
+
+    /// Averages the samples per pixel of each tile of `values`.
+    fn tile_samples(&self, values: &[PixelValue]) -> TileSamples {
+        let tile_size = self.tile_size;
+        let tiles_across = self.image_width.div_ceil(tile_size);
+        let tiles_down = self.image_height.div_ceil(tile_size);
+        let mut totals = vec![(0u64, 0u64); (tiles_across * tiles_down) as usize];
+        for (index, value) in values.iter().enumerate() {
+            let (i, j) = (
+                index as u32 % self.image_width,
+                index as u32 / self.image_width,
+            );
+            let total = &mut totals[((j / tile_size) * tiles_across + i / tile_size) as usize];
+            total.0 += value.samples as u64;
+            total.1 += 1;
+        }
+        TileSamples {
+            tile_size,
+            samples: totals
+                .into_iter()
+                .map(|(samples, pixels)| samples as f64 / pixels.max(1) as f64)
+                .collect(),
+        }
+    }
+
+    /// Traces one camera sample through pixel (`x`, `y`), recording every bounce.
+    ///
+    /// The sample is drawn from a fresh pixel sampler, so it follows the same
+    /// distribution as sample number `sample` of a render without repeating it exactly,
+    /// unless the camera has a seed, when it repeats that sample of a render by the
+    /// default renderer. When supersampling, the path goes through the middle internal
+    /// pixel of the block that makes up output pixel (`x`, `y`).
+    pub fn trace_pixel(&self, world: &dyn Hittable, x: u32, y: u32, sample: u32) -> PathTrace {
+        let middle = self.supersample / 2;
+        let (i, j) = (x * self.supersample + middle, y * self.supersample + middle);
+        self.seed_rng(&[i as u64, j as u64]);
+        let sampler = PixelSampler::new(self.pixel_sampling, self.samples_per_pixel);
+        let id = SampleId {
+            pixel: (x, y),
+            sample,
+            sampler,
+        };
+        let mut trace = PathTrace {
+            pixel: (x, y),
+            sample,
+            vertices: Vec::new(),
+            end: PathEnd::DepthLimit,
+            background: BLACK,
+            radiance: BLACK,
+        };
+
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        self.seed_rng(&[i as u64, j as u64, sample as u64]);
+        let mut ray = self.get_ray(i, j, &sampler.sample(sample));
+        let mut media = self.media();
+        for bounce in 0..self.max_depth {
+            let (transmittance, hit) = self.intersect_in(world, &ray, &media);
+            throughput = throughput * transmittance;
+            let Some(hit_record) = hit else {
+                trace.background = throughput * self.background.color(&ray);
+                trace.radiance += trace.background;
+                trace.end = PathEnd::Escaped;
+                break;
+            };
+
+            let material = self.material(&hit_record).cloned();
+            let mut vertex = PathVertex {
+                position: hit_record.position,
+                normal: hit_record.normal,
+                event: PathEvent::Absorb,
+                throughput,
+                contribution: BLACK,
+                material,
+            };
+            let (contribution, next) =
+                self.bounce(&ray, &hit_record, throughput, world, id, bounce);
+            trace.radiance += contribution;
+            vertex.contribution = contribution;
+            let Some((next_throughput, scatter)) = next else {
+                trace.vertices.push(vertex);
+                trace.end = PathEnd::Absorbed;
+                break;
+            };
+
+            let material = vertex.material.as_ref();
+            vertex.event = if material
+                .and_then(|material| material.diffuse_reflectance(&hit_record))
+                .is_some()
+            {
+                PathEvent::Diffuse
+            } else if material
+                .and_then(|material| material.phase_albedo(&hit_record))
+                .is_some()
+            {
+                PathEvent::Scatter
+            } else if scatter.direction().dot(&hit_record.geometric_normal) < 0.0 {
+                PathEvent::Transmit
+            } else {
+                PathEvent::Reflect
+            };
+            trace.vertices.push(vertex);
+
+            throughput = next_throughput;
+            media.cross(&hit_record, &scatter);
+            ray = scatter;
+        }
+
+        trace
+    }
+
+    /// Render the lighting on `target` into its texture space instead of through the lens.
+    ///
+    /// Each texel of the `size` × `size` image is a point on the target's surface,
+    /// found from its texture coordinates, with `v` increasing up the image. The
+    /// camera's sample count, depth, lights and background are used as for a render.
+    /// Texels the target has no surface for stay black.
+    pub fn bake(
+        &self,
+        world: &dyn Hittable,
+        target: &dyn Hittable,
+        size: u32,
+        mode: BakeMode,
+    ) -> Framebuffer {
+        self.clear_non_finite();
+        let pixels = (0..size * size)
+            .into_par_iter()
+            .map(|index| {
+                let (x, y) = (index % size, index / size);
+                self.seed_rng(&[x as u64, y as u64]);
+                let sampler = PixelSampler::new(self.pixel_sampling, self.samples_per_pixel);
+                let mut color = BLACK;
+                for s in 0..self.samples_per_pixel {
+                    let uv = (
+                        (x as f64 + random_double()) / size as f64,
+                        1.0 - (y as f64 + random_double()) / size as f64,
+                    );
+                    let Some(record) = target.surface_at(uv) else {
+                        continue;
+                    };
+
+                    color += match mode {
+                        BakeMode::Lighting => {
+                            // Diffuse surfaces look the same from any direction, so
+                            // view each texel straight on
+                            let ray = Ray::new(
+                                record.position + record.normal,
+                                -record.normal,
+                                random_double(),
+                            );
+                            let id = SampleId {
+                                pixel: (x, y),
+                                sample: s,
+                                sampler,
+                            };
+                            self.ray_color(&ray, Some(record), self.max_depth, world, id)
+                        }
+                        BakeMode::Occlusion => {
+                            let ray = record
+                                .spawn_ray(record.normal + Vec3::random_unit(), random_double());
+                            match world.hit(&ray, Interval::new(self.ray_t_min, f64::INFINITY)) {
+                                Some(_) => BLACK,
+                                None => Color::new(1.0, 1.0, 1.0),
+                            }
+                        }
+                        BakeMode::Albedo => record
+                            .material
+                            .and_then(|material| material.diffuse_reflectance(&record))
+                            .unwrap_or(BLACK),
+                    };
+                }
+                color * self.pixel_samples_scale
+            })
+            .collect();
+
+        Framebuffer::new(size, size, pixels)
+            .with_samples_per_pixel(self.samples_per_pixel)
+            .with_diagnostics(self.non_finite())
+    }
+
+    /// Forgets the samples the NaN guard dropped in earlier renders, so each image
+    /// notes only its own. Renders sharing one camera at once would mix theirs.
+    fn clear_non_finite(&self) {
+        self.non_finite
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    /// The samples the NaN guard has dropped so far in the current render.
+    fn non_finite(&self) -> Vec<Diagnostic> {
+        self.non_finite
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Bakes the object at `index` among a scene's `objects`, lit by all of them.
+    ///
+    /// The objects are preprocessed, dropping degenerate ones if `drop_degenerate`
+    /// is set, and built into a BVH with the target shared so it can be baked while
+    /// also lighting the rest of the world. `index` is the target's place in the
+    /// list as given, before anything is dropped.
+    pub fn bake_object(
+        &self,
+        mut objects: Vec<Box<dyn Hittable>>,
+        index: usize,
+        size: u32,
+        mode: BakeMode,
+        drop_degenerate: bool,
+    ) -> Result<(Framebuffer, PreprocessReport), BakeError> {
+        if index >= objects.len() {
+            return Err(BakeError::NoObject {
+                index,
+                objects: objects.len(),
+            });
+        }
+        let target: Arc<dyn Hittable> = Arc::from(objects.remove(index));
+        if !target.has_texture_space() {
+            return Err(BakeError::NoTextureSpace(index));
+        }
+        objects.push(Box::new(Arc::clone(&target)));
+        let report = preprocess::prepare(&mut objects, drop_degenerate);
+        let world = Bvh::new(objects)?;
+        Ok((self.bake(&world, target.as_ref(), size, mode), report))
+    }
+
+    /// Traces a few samples per pixel, recording where the light at each diffuse
+    /// bounce came from, and returns what was learned.
+    fn train_guide(&self, world: &dyn Hittable) -> PathGuide {
+        let cell_size =
+            (self.pixel_delta_u * self.image_width as f64).length() / GUIDE_CELLS_ACROSS_VIEW;
+
+        let train = |mut guide: PathGuide, (i, j): (u32, u32)| {
+            self.seed_rng(&[GUIDE_TRAINING_KEY, i as u64, j as u64]);
+            let sampler = PixelSampler::new(self.pixel_sampling, GUIDE_TRAINING_SAMPLES);
+            for s in 0..GUIDE_TRAINING_SAMPLES {
+                let ray = self.get_ray(i, j, &sampler.sample(s));
+                let id = SampleId {
+                    pixel: (i, j),
+                    sample: s,
+                    sampler,
+                };
+                self.train_path(&ray, world, id, &mut guide);
+            }
+            guide
+        };
+        // Each row trains its own guide, and the guides are merged in order at the end
+        // so the sums come out the same however the rows were scheduled
+        let rows: Vec<PathGuide> = (0..self.image_height)
+            .into_par_iter()
+            .map(|j| {
+                (0..self.image_width)
+                    .map(|i| (i, j))
+                    .fold(PathGuide::new(cell_size), train)
+            })
+            .collect();
+        let mut guide = rows
+            .into_iter()
+            .fold(PathGuide::new(cell_size), PathGuide::merge);
+        guide.finish();
+        guide
+    }
+
+    /// Traces one path, recording the light arriving at each diffuse bounce from the
+    /// direction the path left in.
+    fn train_path(&self, ray: &Ray, world: &dyn Hittable, id: SampleId, guide: &mut PathGuide) {
+        // Each diffuse vertex's position, outgoing direction, throughput beyond it and
+        // the luminance gathered along that direction so far
+        let mut vertices: Vec<(Point3, Vec3, Color, f64)> = Vec::new();
+        let gather = |vertices: &mut Vec<(Point3, Vec3, Color, f64)>, light: Color| {
+            for (_, _, throughput, radiance) in vertices.iter_mut() {
+                if throughput.luminance() > 0.0 {
+                    *radiance += light.luminance() / throughput.luminance();
+                }
+            }
+        };
+
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        let mut media = self.media();
+        for bounce in 0..self.max_depth {
+            let (transmittance, hit) = self.intersect_in(world, &ray, &media);
+            throughput = throughput * transmittance;
+            let Some(hit_record) = hit else {
+                gather(&mut vertices, throughput * self.background.color(&ray));
+                break;
+            };
+            let (contribution, next) =
+                self.bounce(&ray, &hit_record, throughput, world, id, bounce);
+            // Light sampled or emitted here arrives from the lights, not along the path
+            gather(&mut vertices, contribution);
+            let Some((next_throughput, scatter)) = next else {
+                break;
+            };
+
+            if self
+                .material(&hit_record)
+                .and_then(|material| material.diffuse_reflectance(&hit_record))
+                .is_some()
+            {
+                vertices.push((
+                    hit_record.position,
+                    *scatter.direction(),
+                    next_throughput,
+                    0.0,
+                ));
+            }
+            throughput = next_throughput;
+            media.cross(&hit_record, &scatter);
+            ray = scatter;
+        }
+
+        for (position, direction, _, radiance) in &vertices {
+            guide.record(position, direction, *radiance);
+        }
+    }
+
+    /// Reseeds this thread's generator from the camera's seed and `keys`, if it has
+    /// a seed, so what is drawn next depends only on them.
+    #[inline]
+    fn seed_rng(&self, keys: &[u64]) {
+        if let Some(seed) = self.seed {
+            seed_thread_rng(mix_seed(seed, keys));
+        }
+    }
+
+    /// Reseeds this thread's generator for a stage of a wavefront path's bounce.
+    #[inline]
+    fn seed_path_rng(&self, path: &Path, bounce: u32, stage: u64) {
+        let (x, y) = path.id.pixel;
+        self.seed_rng(&[
+            x as u64,
+            y as u64,
+            path.id.sample as u64,
+            bounce as u64,
+            stage,
+        ]);
+    }
+
+    /// Render every pixel's samples to completion in turn, one path at a time.
+    ///
+    /// The image is split into square tiles, each rendered on one thread, so
+    /// scheduling costs little and neighbouring pixels reuse the same parts of the
+    /// scene while they are still cached.
+    fn render_pixels(&self, world: &dyn Hittable) -> Vec<PixelValue> {
+        self.render_tiles(world, &|_, _, _| {})
+    }
+
+    /// Renders every pixel tile by tile, calling `on_tile` with the top left corner
+    /// and the pixels of each tile as soon as it is finished.
+    fn render_tiles(
+        &self,
+        world: &dyn Hittable,
+        on_tile: &(dyn Fn(u32, u32, &[PixelValue]) + Sync),
+    ) -> Vec<PixelValue> {
+        let (width, height) = (self.image_width, self.image_height);
+        let tile_size = self.tile_size;
+        let tiles_across = width.div_ceil(tile_size);
+        let tile_count = tiles_across * height.div_ceil(tile_size);
+        let progress_bar = Progress::new(tile_count as u64, "tiles", self.progress.as_ref());
+
+        let tiles: Vec<(u32, u32, Vec<PixelValue>)> = (0..tile_count)
+            .into_par_iter()
+            .map(|tile| {
+                let x0 = (tile % tiles_across) * tile_size;
+                let y0 = (tile / tiles_across) * tile_size;
+                let x1 = (x0 + tile_size).min(width);
+                let y1 = (y0 + tile_size).min(height);
+                let coords = (y0..y1).flat_map(|j| (x0..x1).map(move |i| (i, j)));
+                let pixels = match self.tile_time_budget {
+                    Some(budget) => self.render_budgeted_tile(coords, world, budget),
+                    None => coords
+                        .map(|(i, j)| self.render_pixel(i, j, world))
+                        .collect::<Vec<_>>(),
+                };
+                progress_bar.inc();
+                on_tile(x0, y0, &pixels);
+                (x0, y0, pixels)
+            })
+            .collect();
+        progress_bar.finish();
+
+        let mut image: Vec<Option<PixelValue>> = vec![None; (width * height) as usize];
+        for (x0, y0, pixels) in tiles {
+            self.place_tile(&mut image, x0, y0, &pixels);
+        }
+        image
+            .into_iter()
+            .map(|pixel| pixel.expect("every pixel is in a tile"))
+            .collect()
+    }
+
+    /// Copies the rows of the tile whose top left corner is (`x0`, `y0`) into place
+    /// in `image`.
+    fn place_tile(
+        &self,
+        image: &mut [Option<PixelValue>],
+        x0: u32,
+        y0: u32,
+        pixels: &[PixelValue],
+    ) {
+        let width = self.image_width;
+        let tile_width = (x0 + self.tile_size).min(width) - x0;
+        for (index, pixel) in pixels.iter().enumerate() {
+            let (i, j) = (
+                x0 + index as u32 % tile_width,
+                y0 + index as u32 / tile_width,
+            );
+            image[(j * width + i) as usize] = Some(*pixel);
+        }
+    }
+
+    /// Traces all of pixel (`i`, `j`)'s samples.
+    fn render_pixel(&self, i: u32, j: u32, world: &dyn Hittable) -> PixelValue {
+        let mut pixel = self.start_pixel(i, j);
+        // Sample each pixel multiple times for anti-aliasing
+        for s in 0..self.samples_per_pixel {
+            self.sample_pixel(&mut pixel, s, world);
+            if pixel.converged {
+                break;
+            }
+        }
+        pixel.finish()
+    }
+
+    /// Traces the pixels of a tile a sample of each at a time, until they have all
+    /// their samples or the tile has used up `budget`.
+    fn render_budgeted_tile(
+        &self,
+        coords: impl Iterator<Item = (u32, u32)>,
+        world: &dyn Hittable,
+        budget: Duration,
+    ) -> Vec<PixelValue> {
+        let start = Instant::now();
+        let mut pixels: Vec<PixelSamples> = coords.map(|(i, j)| self.start_pixel(i, j)).collect();
+        for s in 0..self.samples_per_pixel {
+            for pixel in pixels.iter_mut().filter(|pixel| !pixel.converged) {
+                self.sample_pixel(pixel, s, world);
+            }
+            if start.elapsed() >= budget || pixels.iter().all(|pixel| pixel.converged) {
+                break;
+            }
+        }
+        pixels.into_iter().map(PixelSamples::finish).collect()
+    }
+
+    /// Prepares pixel (`i`, `j`) for sampling.
+    fn start_pixel(&self, i: u32, j: u32) -> PixelSamples {
+        self.seed_rng(&[i as u64, j as u64]);
+        PixelSamples {
+            pixel: (i, j),
+            sampler: PixelSampler::new(self.pixel_sampling, self.samples_per_pixel),
+            color: BLACK,
+            depth_sum: 0.0,
+            depth_hits: 0,
+            stats: SampleStats::default(),
+            traversal: TraversalStats::default(),
+            time: Duration::ZERO,
+            converged: false,
+        }
+    }
+
+    /// Traces sample `s` of `pixel`, adding it to the samples before.
+    fn sample_pixel(&self, pixel: &mut PixelSamples, s: u32, world: &dyn Hittable) {
+        let (i, j) = pixel.pixel;
+        // Discard traversal work counted on this thread for other pixels
+        TraversalStats::take();
+        let start = Instant::now();
+
+        self.seed_rng(&[i as u64, j as u64, s as u64]);
+        let ray = self.get_ray(i, j, &pixel.sampler.sample(s));
+        let id = SampleId {
+            pixel: (i, j),
+            sample: s,
+            sampler: pixel.sampler,
+        };
+        let (color, distance) = self.sample(&ray, world, id);
+        pixel.color += color;
+        if let Some(distance) = distance {
+            pixel.depth_sum += distance;
+            pixel.depth_hits += 1;
+        }
+        pixel.stats.add(color.luminance());
+        pixel.converged = self
+            .adaptive_sampling
+            .is_some_and(|adaptive| adaptive.converged(&pixel.stats));
+
+        let traversal = TraversalStats::take();
+        pixel.traversal.node_visits += traversal.node_visits;
+        pixel.traversal.intersection_tests += traversal.intersection_tests;
+        pixel.time += start.elapsed();
+    }
+
+    /// Render one sample of every pixel at a time, advancing all paths a bounce per stage.
+    ///
+    /// Each wave generates a camera ray per pixel, then alternates between intersecting
+    /// every live path and shading every hit until all paths have ended. Paths are
+    /// grouped by direction before intersection so neighbouring rays walk the BVH alike.
+    fn render_wavefront(&self, world: &dyn Hittable) -> Vec<PixelValue> {
+        let width = self.image_width as usize;
+        let pixel_count = width * self.image_height as usize;
+        let samplers: Vec<PixelSampler> = (0..pixel_count)
+            .map(|index| {
+                self.seed_rng(&[(index % width) as u64, (index / width) as u64]);
+                PixelSampler::new(self.pixel_sampling, self.samples_per_pixel)
+            })
+            .collect();
+        let mut radiance = vec![BLACK; pixel_count];
+        let mut depth_sums = vec![(0.0, 0); pixel_count];
+        // What each pixel's current sample has gathered, and its samples so far
+        let mut sample_radiance = vec![BLACK; pixel_count];
+        let mut stats = vec![SampleStats::default(); pixel_count];
+        let mut converged = vec![false; pixel_count];
+
+        let progress_bar = Progress::new(
+            self.samples_per_pixel as u64,
+            "samples",
+            self.progress.as_ref(),
+        );
+
+        for s in 0..self.samples_per_pixel {
+            // Generate: one camera ray per pixel still sampling
+            let mut paths: Vec<Path> = samplers
+                .par_iter()
+                .enumerate()
+                .filter(|&(index, _)| !converged[index])
+                .map(|(index, sampler)| {
+                    let pixel = ((index % width) as u32, (index / width) as u32);
+                    self.seed_rng(&[pixel.0 as u64, pixel.1 as u64, s as u64]);
+                    Path {
+                        index,
+                        id: SampleId {
+                            pixel,
+                            sample: s,
+                            sampler: *sampler,
+                        },
+                        ray: self.get_ray(pixel.0, pixel.1, &sampler.sample(s)),
+                        throughput: Color::new(1.0, 1.0, 1.0),
+                        media: self.media(),
+                    }
+                })
+                .collect();
+            let sampled: Vec<usize> = paths.iter().map(|path| path.index).collect();
+
+            for bounce in 0..self.max_depth {
+                paths.sort_by_key(|path| path.ray.direction().octant());
+
+                // Intersect every live path
+                let hits: Vec<Option<HitRecord>> = paths
+                    .par_iter_mut()
+                    .map(|path| {
+                        self.seed_path_rng(path, bounce, 0);
+                        let (transmittance, hit) = self.intersect_in(world, &path.ray, &path.media);
+                        path.throughput = path.throughput * transmittance;
+                        hit
+                    })
+                    .collect();
+                if bounce == 0 {
+                    for (path, hit) in paths.iter().zip(&hits) {
+                        if let Some(hit_record) = hit {
+                            let sum = &mut depth_sums[path.index];
+                            sum.0 += hit_record.t * path.ray.direction().length();
+                            sum.1 += 1;
+                        }
+                    }
+                }
+
+                // Shade every hit, ending paths that miss, are absorbed or run out of depth
+                let last = bounce + 1 == self.max_depth;
+                let shaded: Vec<(Color, bool)> = paths
+                    .par_iter_mut()
+                    .zip(hits)
+                    .map(|(path, hit)| {
+                        self.seed_path_rng(path, bounce, 1);
+                        let Some(hit_record) = hit else {
+                            return (path.throughput * self.background.color(&path.ray), false);
+                        };
+                        let (contribution, next) = self.bounce(
+                            &path.ray,
+                            &hit_record,
+                            path.throughput,
+                            world,
+                            path.id,
+                            bounce,
+                        );
+                        match next {
+                            Some((throughput, scatter)) => {
+                                path.throughput = throughput;
+                                path.media.cross(&hit_record, &scatter);
+                                path.ray = scatter;
+                                (contribution, !last)
+                            }
+                            None => (contribution, false),
+                        }
+                    })
+                    .collect();
+
+                let mut live = shaded.iter().map(|&(_, live)| live);
+                for (path, (contribution, _)) in paths.iter().zip(&shaded) {
+                    sample_radiance[path.index] += *contribution;
+                }
+                paths.retain(|_| live.next().unwrap_or(false));
+                if paths.is_empty() {
+                    break;
+                }
+            }
+
+            for index in sampled {
+                let color = std::mem::replace(&mut sample_radiance[index], BLACK);
+                radiance[index] += color;
+                stats[index].add(color.luminance());
+                converged[index] = self
+                    .adaptive_sampling
+                    .is_some_and(|adaptive| adaptive.converged(&stats[index]));
+            }
+            progress_bar.inc();
+            if converged.iter().all(|&done| done) {
+                break;
+            }
+        }
+        progress_bar.finish();
+
+        radiance
+            .into_iter()
+            .zip(depth_sums)
+            .zip(stats)
+            .map(|((color, (depth_sum, depth_hits)), stats)| {
+                let samples = stats.count();
+                let scale = 1.0 / samples.max(1) as f64;
+                PixelValue {
+                    color: color * scale,
+                    depth: if depth_hits > 0 {
+                        depth_sum / depth_hits as f64
+                    } else {
+                        f64::INFINITY
+                    },
+                    coverage: depth_hits as f64 * scale,
+                    samples,
+                    traversal: TraversalStats::default(),
+                    time: Duration::ZERO,
+                }
+            })
+            .collect()
+    }
+
+    /// Colors each pixel by the BVH nodes visited per sample, noting the totals.
+    fn bvh_cost_heatmap(
+        &self,
+        values: &[PixelValue],
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Vec<Color> {
+        let visits: Vec<f64> = values
+            .iter()
+            .map(|value| value.traversal.node_visits as f64 / value.samples.max(1) as f64)
+            .collect();
+
+        let pixel_count = values.len().max(1) as f64;
+        let samples = values.iter().map(|value| value.samples as f64).sum::<f64>() / pixel_count;
+        let samples = samples.max(1.0);
+        let total_tests: u64 = values
+            .iter()
+            .map(|value| value.traversal.intersection_tests)
+            .sum();
+        diagnostics.push(Diagnostic::BvhCost {
+            average_visits: visits.iter().sum::<f64>() / pixel_count,
+            max_visits: visits.iter().copied().fold(0.0, f64::max),
+            average_tests: total_tests as f64 / samples / pixel_count,
+        });
+
+        heatmap(&visits)
+    }
+
+    /// Colors each pixel by the wall time spent rendering it, noting the slowest.
+    fn time_heatmap(&self, values: &[PixelValue], diagnostics: &mut Vec<Diagnostic>) -> Vec<Color> {
+        let millis: Vec<f64> = values
+            .iter()
+            .map(|value| value.time.as_secs_f64() * 1000.0)
+            .collect();
+
+        let (slowest, max_millis) =
+            millis
+                .iter()
+                .copied()
+                .enumerate()
+                .fold(
+                    (0, 0.0),
+                    |max, (index, ms)| if ms > max.1 { (index, ms) } else { max },
+                );
+        let width = self.image_width as usize;
+        diagnostics.push(Diagnostic::PixelTime {
+            average: millis.iter().sum::<f64>() / values.len().max(1) as f64,
+            max: max_millis,
+            slowest: ((slowest % width) as u32, (slowest / width) as u32),
+        });
+
+        heatmap(&millis)
+    }
+}
+
+/// Maps values onto the heatmap ramp, with the largest value white.
+fn heatmap(values: &[f64]) -> Vec<Color> {
+    let max = values.iter().copied().fold(0.0, f64::max);
+    values
+        .iter()
+        .map(|&value| {
+            let t = if max > 0.0 { value / max } else { 0.0 };
+            Color::heat(t)
+        })
+        .collect()
+}
+
+/// Blurs a specular bounce into the cone of directions within `cos_max` of the
+/// scattered ray, keeping the surface side the material sent it to.
+fn regularize(hit_record: &HitRecord, scatter: &Ray, cos_max: f64) -> Ray {
+    let axis = scatter.direction().unit();
+    let z = 1.0 - random_double() * (1.0 - cos_max);
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * f64::consts::PI * random_double();
+    let direction = Onb::new(&axis).transform(&Vec3::new(r * phi.cos(), r * phi.sin(), z));
+
+    let normal = hit_record.geometric_normal;
+    if direction.dot(&normal) * axis.dot(&normal) <= 0.0 {
+        return *scatter;
+    }
+    hit_record.spawn_ray(direction, scatter.time())
+}
+
+/// Veach's power heuristic: the weight of a sample drawn by the strategy with
+/// density `pdf` when another strategy could have drawn it with density `other`.
+fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other * other);
+    if a + b > 0.0 { a / (a + b) } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::Bvh;
+    use crate::material::TestMaterial;
+    use crate::point3::Point3;
+    use crate::ray::Ray;
     use crate::sphere::SphereBuilder;
     use crate::utilities::random_double;
     use crate::vec3::Vec3;
 
+    /// A ray reaching a surface at `time`, for lighting the surface directly.
+    fn incoming(time: f64) -> Ray {
+        Ray::new(Point3::default(), Vec3::new(0.0, 0.0, -1.0), time)
+    }
+
     #[test]
     fn test_camera_builder_defaults() {
         let camera = CameraBuilder::default().build();
@@ -342,11 +2642,51 @@ mod tests {
         assert_eq!(camera.max_depth, 5);
     }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_camera_builder_serde_round_trip() {
+        use crate::light::PointLight;
+
+        let builder = CameraBuilder::new()
+            .image_width(200)
+            .look_from(Point3::new(13.0, 2.0, 3.0))
+            .light(PointLight::new(
+                Point3::new(0.0, 4.0, 0.0),
+                Color::new(5.0, 5.0, 5.0),
+            ))
+            .background(Background::Night(Default::default()))
+            .view(
+                "top",
+                Point3::new(0.0, 5.0, 1.0),
+                Point3::new(0.0, 1.0, 1.0),
+            );
+        let json = serde_json::to_string(&builder).unwrap();
+        let restored: CameraBuilder = serde_json::from_str(&json).unwrap();
+        assert_eq!(format!("{:?}", restored), format!("{:?}", builder));
+    }
+
+    #[test]
+    fn test_through_view() {
+        let builder = CameraBuilder::new().vertical_fov(35.0).view(
+            "top",
+            Point3::new(0.0, 5.0, 1.0),
+            Point3::new(0.0, 1.0, 1.0),
+        );
+        assert_eq!(builder.views().len(), 1);
+        assert_eq!(builder.views()[0].name, "top");
+
+        let top = builder.through(&builder.views()[0]);
+        assert_eq!(top.look_from, Point3::new(0.0, 5.0, 1.0));
+        assert_eq!(top.look_at, Point3::new(0.0, 1.0, 1.0));
+        assert_eq!(top.focus_dist, Some(4.0));
+        assert_eq!(top.vertical_fov, 35.0);
+    }
+
     #[test]
     fn test_random_double_range() {
         for _ in 0..100 {
             let v = random_double();
-            assert!(v >= 0.0 && v < 1.0, "random_double out of range: {}", v);
+            assert!((0.0..1.0).contains(&v), "random_double out of range: {}", v);
         }
     }
 
@@ -363,7 +2703,8 @@ mod tests {
     #[test]
     fn test_get_ray() {
         let camera = CameraBuilder::default().build();
-        let ray = camera.get_ray(0, 0);
+        let sampler = PixelSampler::new(camera.pixel_sampling, camera.samples_per_pixel);
+        let ray = camera.get_ray(0, 0, &sampler.sample(0));
         // The ray's origin should be at the camera center
         assert_eq!(ray.origin(), &camera.center);
         // The direction should be normalized (or close to)
@@ -373,17 +2714,2017 @@ mod tests {
     }
 
     #[test]
-    fn test_ray_color_depth_zero() {
-        let ray = Ray::new(Point3::default(), Vec3::new(1.0, 0.0, 0.0), 0.0);
-        // Create a sphere that the ray will miss
-        let sphere = SphereBuilder::new()
-            .center(Point3::new(0.0, 0.0, -1.0))
-            .radius(0.5)
-            .material(TestMaterial::new())
-            .build()
-            .unwrap();
-        let world = Bvh::new(vec![Box::new(sphere)]).unwrap();
-        let color = Camera::ray_color(&ray, 0, &world as &dyn crate::hittable::Hittable);
-        assert_eq!(color, Color::new(0.0, 0.0, 0.0));
+    fn test_get_ray_differentials() {
+        let camera = CameraBuilder::new().samples_per_pixel(1).build();
+        let sampler = PixelSampler::new(PixelSampling::Independent, 1);
+        let ray = camera.get_ray(10, 10, &sampler.sample(0));
+        let differentials = ray
+            .differentials()
+            .expect("Camera rays carry differentials");
+
+        // With one sample per pixel the offset rays reach exactly one pixel over
+        assert_eq!(differentials.rx_origin, *ray.origin());
+        let dx = differentials.rx_direction - *ray.direction();
+        let dy = differentials.ry_direction - *ray.direction();
+        assert!((dx - camera.pixel_delta_u).near_zero());
+        assert!((dy - camera.pixel_delta_v).near_zero());
+    }
+
+    #[test]
+    fn test_omni_stereo_rays() {
+        let camera = CameraBuilder::new()
+            .image_width(64)
+            .aspect_ratio(16.0 / 9.0)
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .projection(Projection::OmniStereo { ipd: 0.064 })
+            .build();
+        assert_eq!((camera.image_width, camera.image_height), (64, 64));
+
+        // A sample on the corner shared by four pixels hits it exactly
+        let sample = CameraSample {
+            pixel_offset: Vec3::new(-0.5, -0.5, 0.0),
+            lens: Vec3::default(),
+        };
+        let ray = |i, j| camera.get_ray(i, j, &sample);
+        let close = |a: Vec3, b: Vec3| (a - b).near_zero();
+
+        // The middle of each eye looks straight ahead, the eyes to either side
+        let left = ray(32, 16);
+        let right = ray(32, 48);
+        assert!(close(left.direction().unit(), Vec3::new(0.0, 0.0, -1.0)));
+        assert!(close(right.direction().unit(), Vec3::new(0.0, 0.0, -1.0)));
+        assert!(close(left.origin().as_vec3(), Vec3::new(-0.032, 0.0, 0.0)));
+        assert!(close(right.origin().as_vec3(), Vec3::new(0.032, 0.0, 0.0)));
+
+        // A quarter turn to the right, the eyes are in front and behind
+        let turned = ray(48, 16);
+        assert!(close(turned.direction().unit(), Vec3::new(1.0, 0.0, 0.0)));
+        assert!(close(
+            turned.origin().as_vec3(),
+            Vec3::new(0.0, 0.0, -0.032)
+        ));
+
+        // Each eye's top row looks up and the edges look backwards
+        assert!(ray(32, 0).direction().unit().y() > 0.99);
+        assert!(ray(32, 32).direction().unit().y() > 0.99);
+        assert!(close(
+            ray(0, 16).direction().unit(),
+            Vec3::new(0.0, 0.0, 1.0)
+        ));
+    }
+
+    #[test]
+    fn test_get_ray_lens_sample() {
+        let camera = CameraBuilder::new()
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .defocus_angle(10.0)
+            .focus_dist(2.0)
+            .build();
+        let sample = CameraSample {
+            pixel_offset: Vec3::default(),
+            lens: Vec3::new(1.0, 0.0, 0.0),
+        };
+        let ray = camera.get_ray(50, 50, &sample);
+
+        // The edge of the lens is offset by the defocus radius along the camera's u axis
+        let radius = 2.0 * (5.0_f64).to_radians().tan();
+        assert!((ray.origin().x() - radius).abs() < 1e-9);
+        assert_eq!(ray.origin().y(), 0.0);
+    }
+
+    #[test]
+    fn test_tilted_focus_plane() {
+        let camera = CameraBuilder::new()
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .defocus_angle(10.0)
+            .focus_dist(2.0)
+            .tilt(30.0, 0.0)
+            .build();
+        let focus = |x: u32, y: u32, lens: Vec3| {
+            let ray = camera.get_ray(
+                x,
+                y,
+                &CameraSample {
+                    pixel_offset: Vec3::default(),
+                    lens,
+                },
+            );
+            *ray.origin() + *ray.direction()
+        };
+        let normal = camera.focus_tilt_normal.unwrap();
+
+        for (x, y) in [(50, 10), (50, 50), (50, 90), (10, 30)] {
+            // Every lens sample of a pixel converges on the same point of the tilted plane
+            let a = focus(x, y, Vec3::new(1.0, 0.0, 0.0));
+            let b = focus(x, y, Vec3::new(-0.5, 0.5, 0.0));
+            assert!((a - b).near_zero(), "{:?} vs {:?}", a, b);
+            assert!(((a - camera.focus_center).dot(&normal)).abs() < 1e-9);
+        }
+        // The top of the plane is tipped away from the camera
+        let top = focus(50, 10, Vec3::default());
+        let bottom = focus(50, 90, Vec3::default());
+        assert!(top.z() < -2.0 && bottom.z() > -2.0);
+    }
+
+    #[test]
+    fn test_lens_shift() {
+        let builder = CameraBuilder::new()
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0));
+        let center_ray = |camera: &Camera| {
+            *camera
+                .get_ray(
+                    50,
+                    50,
+                    &CameraSample {
+                        pixel_offset: Vec3::default(),
+                        lens: Vec3::default(),
+                    },
+                )
+                .direction()
+        };
+        let straight = center_ray(&builder.clone().build());
+        let shifted = center_ray(&builder.lens_shift(0.0, 0.5).build());
+
+        // Half the view's height up, without turning the camera
+        assert!((shifted.y() - straight.y() - 1.0).abs() < 1e-9);
+        assert_eq!(shifted.x(), straight.x());
+        assert_eq!(shifted.z(), straight.z());
+    }
+
+    #[test]
+    fn test_supersample() {
+        let camera = CameraBuilder::new()
+            .image_width(4)
+            .samples_per_pixel(2)
+            .supersample(3)
+            .build();
+        // Rendered at three times the size, with the pixel grid scaled to match
+        assert_eq!((camera.image_width, camera.image_height), (12, 12));
+        let direct = CameraBuilder::new().image_width(12).build();
+        assert_eq!(camera.pixel_delta_u, direct.pixel_delta_u);
+
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, -1.0))
+                .radius(0.5)
+                .material(crate::material::Lambertian::clay())
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let framebuffer = camera.render_frame(&world);
+        assert_eq!((framebuffer.width(), framebuffer.height()), (4, 4));
+        assert_eq!(framebuffer.samples_per_pixel(), 18);
+        assert_eq!(framebuffer.depth().unwrap().len(), 16);
+    }
+
+    #[test]
+    fn test_nested_dielectrics_conserve_energy() {
+        use crate::material::Dielectric;
+        crate::utilities::seed_thread_rng(2024);
+
+        // Russian doll: glass containing an air bubble containing a glass core
+        let center = Point3::new(0.0, 0.0, -3.0);
+        let doll = |radius: f64, refraction_index: f64| -> Box<dyn Hittable> {
+            Box::new(
+                SphereBuilder::new()
+                    .center(center)
+                    .radius(radius)
+                    .material(Dielectric::new(refraction_index))
+                    .build()
+                    .unwrap(),
+            )
+        };
+        let world = Bvh::new(vec![doll(1.0, 1.5), doll(0.7, 1.0 / 1.5), doll(0.4, 1.5)]).unwrap();
+        let camera = CameraBuilder::new().max_depth(50).build();
+
+        // Dielectrics neither absorb nor emit, so no path can be brighter than the
+        // sky it ends on and few should run out of bounces
+        let samples = 2000;
+        let mut total = 0.0;
+        for _ in 0..samples {
+            let direction = Vec3::new(
+                random_double() * 0.6 - 0.3,
+                random_double() * 0.6 - 0.3,
+                -1.0,
+            );
+            let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), direction, 0.0);
+            let (color, _) = camera.sample(&ray, &world, SampleId::default());
+            assert!(color.r() <= 1.0 + 1e-9 && color.g() <= 1.0 + 1e-9 && color.b() <= 1.0 + 1e-9);
+            total += color.luminance();
+        }
+        let sky_min = Color::new(0.5, 0.7, 1.0).luminance();
+        assert!(total / samples as f64 > 0.95 * sky_min);
+    }
+
+    #[test]
+    fn test_furnace() {
+        use crate::material::{Dielectric, Lambertian};
+        use crate::texture::{SolidColor, TextureEnum};
+
+        // Objects that neither absorb nor emit vanish under a uniform white sky
+        let white = Lambertian::new(Box::new(TextureEnum::SolidColor(SolidColor::new(
+            Color::new(1.0, 1.0, 1.0),
+        ))));
+        let world = Bvh::new(vec![
+            Box::new(
+                SphereBuilder::new()
+                    .center(Point3::new(-0.6, 0.0, -2.0))
+                    .radius(0.5)
+                    .material(white)
+                    .build()
+                    .unwrap(),
+            ) as Box<dyn Hittable>,
+            Box::new(
+                SphereBuilder::new()
+                    .center(Point3::new(0.6, 0.0, -2.0))
+                    .radius(0.5)
+                    .material(Dielectric::new(1.5))
+                    .build()
+                    .unwrap(),
+            ),
+        ])
+        .unwrap();
+        let camera = CameraBuilder::new()
+            .image_width(16)
+            .samples_per_pixel(4)
+            .max_depth(50)
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .background(Background::Uniform(Color::new(1.0, 1.0, 1.0)))
+            .build();
+
+        let mut framebuffer = camera.render_frame(&world);
+        for pixel in framebuffer.pixels_mut() {
+            let error = (pixel.r() - 1.0).abs().max((pixel.g() - 1.0).abs());
+            assert!(
+                error.max((pixel.b() - 1.0).abs()) < 0.05,
+                "pixel was {}",
+                pixel
+            );
+        }
+    }
+
+    #[test]
+    fn test_material_id_pass() {
+        use crate::material::Metal;
+
+        let material = Metal::new(Color::new(0.7, 0.6, 0.5), 0.3);
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, -2.0))
+                .radius(1.0)
+                .material(material.clone())
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let camera = CameraBuilder::new().pass(RenderPass::MaterialId).build();
+
+        let hit = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let (color, _) = camera.sample(&hit, &world, SampleId::default());
+        assert_eq!(color, material.id_color());
+
+        let miss = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let (color, _) = camera.sample(&miss, &world, SampleId::default());
+        assert_eq!(color, BLACK);
+    }
+
+    #[test]
+    fn test_bvh_cost_pass() {
+        let sphere = SphereBuilder::new()
+            .center(Point3::new(0.0, 0.0, -3.0))
+            .radius(1.0)
+            .material(TestMaterial::new())
+            .build()
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(sphere)]).unwrap();
+        let camera = CameraBuilder::new()
+            .image_width(9)
+            .samples_per_pixel(2)
+            .max_depth(3)
+            .vertical_fov(90.0)
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .pass(RenderPass::BvhCost)
+            .build();
+
+        let mut framebuffer = camera.render_frame(&world);
+        let pixels = framebuffer.pixels_mut();
+        // Paths that bounce off the sphere cost the most; the corners miss everything
+        assert_eq!(pixels[4 * 9 + 4], Color::heat(1.0));
+        assert!(pixels[0].luminance() < pixels[4 * 9 + 4].luminance());
+        assert!(matches!(
+            framebuffer.diagnostics(),
+            [Diagnostic::BvhCost { max_visits, .. }] if *max_visits > 0.0
+        ));
+    }
+
+    #[test]
+    fn test_heatmap() {
+        assert_eq!(
+            heatmap(&[0.0, 1.0, 2.0]),
+            vec![Color::heat(0.0), Color::heat(0.5), Color::heat(1.0)]
+        );
+        assert_eq!(heatmap(&[0.0, 0.0]), vec![Color::heat(0.0); 2]);
+    }
+
+    #[test]
+    fn test_time_pass() {
+        let camera = CameraBuilder::new()
+            .image_width(4)
+            .samples_per_pixel(1)
+            .pass(RenderPass::Time)
+            .build();
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .material(TestMaterial::new())
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+
+        // Timings vary, but the slowest pixel is always at the top of the ramp
+        let mut framebuffer = camera.render_frame(&world);
+        assert!(framebuffer.pixels_mut().contains(&Color::heat(1.0)));
+        assert!(matches!(
+            framebuffer.diagnostics(),
+            [Diagnostic::PixelTime {
+                slowest: (0..4, 0..4),
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_wavefront_matches_megakernel() {
+        use crate::material::Metal;
+
+        // A perfect mirror under a uniform sky reflects the sky exactly, so both
+        // renderers must produce a white image whatever paths they trace
+        let white = Color::new(1.0, 1.0, 1.0);
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, -2.0))
+                .radius(1.0)
+                .material(Metal::new(white, 0.0))
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let camera = |renderer| {
+            CameraBuilder::new()
+                .image_width(8)
+                .samples_per_pixel(4)
+                .max_depth(4)
+                .look_from(Point3::new(0.0, 0.0, 0.0))
+                .look_at(Point3::new(0.0, 0.0, -1.0))
+                .background(Background::Uniform(white))
+                .renderer(renderer)
+                .build()
+        };
+
+        let megakernel = camera(Renderer::Megakernel).render_frame(&world);
+        let wavefront = camera(Renderer::Wavefront).render_frame(&world);
+        for framebuffer in [&megakernel, &wavefront] {
+            assert!(
+                framebuffer
+                    .pixels()
+                    .iter()
+                    .all(|p| (p.r() - 1.0).abs() < 1e-9 && (p.b() - 1.0).abs() < 1e-9)
+            );
+        }
+
+        // Both see the front of the sphere in the center and nothing in the corner
+        let (a, b) = (megakernel.depth().unwrap(), wavefront.depth().unwrap());
+        assert!((a[4 * 8 + 4] - b[4 * 8 + 4]).abs() < 0.05);
+        assert_eq!(b[0], f64::INFINITY);
+    }
+
+    #[test]
+    fn test_seeded_renders_repeat() {
+        use crate::light::PointLight;
+        use crate::material::{Dielectric, Lambertian, Metal};
+
+        let sphere = |x: f64, material: Material| -> Box<dyn Hittable> {
+            Box::new(
+                SphereBuilder::new()
+                    .center(Point3::new(x, 0.0, -2.0))
+                    .radius(0.6)
+                    .material(material)
+                    .build()
+                    .unwrap(),
+            )
+        };
+        let world = Bvh::new(vec![
+            sphere(-1.2, Lambertian::clay()),
+            sphere(0.0, Metal::new(Color::new(0.8, 0.7, 0.6), 0.4)),
+            sphere(1.2, Dielectric::frosted(1.5, 0.2)),
+        ])
+        .unwrap();
+        let render = |seed: Option<u64>, tile_size: u32, renderer: Renderer| {
+            let camera = CameraBuilder::new()
+                .image_width(12)
+                .samples_per_pixel(2)
+                .max_depth(4)
+                .look_from(Point3::new(0.0, 0.0, 0.0))
+                .look_at(Point3::new(0.0, 0.0, -1.0))
+                .light(PointLight::new(
+                    Point3::new(0.0, 3.0, 0.0),
+                    Color::new(10.0, 10.0, 10.0),
+                ))
+                .renderer(renderer)
+                .tile_size(tile_size);
+            let camera = match seed {
+                Some(seed) => camera.seed(seed),
+                None => camera,
+            };
+            camera.build().render_frame(&world).pixels().to_vec()
+        };
+
+        // The same seed gives the same image however it is split into tiles
+        let golden = render(Some(42), 16, Renderer::Megakernel);
+        assert_eq!(render(Some(42), 5, Renderer::Megakernel), golden);
+        assert_ne!(render(Some(43), 16, Renderer::Megakernel), golden);
+        assert_ne!(
+            render(None, 16, Renderer::Megakernel),
+            render(None, 16, Renderer::Megakernel)
+        );
+        let wavefront = render(Some(42), 16, Renderer::Wavefront);
+        assert_eq!(render(Some(42), 16, Renderer::Wavefront), wavefront);
+
+        // Tracing a sample of a seeded render repeats it
+        let camera = CameraBuilder::new()
+            .image_width(12)
+            .samples_per_pixel(1)
+            .max_depth(4)
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .seed(7)
+            .build();
+        let pixels = camera.render_frame(&world);
+        let trace = camera.trace_pixel(&world, 6, 6, 0);
+        assert_eq!(trace.radiance, pixels.pixels()[6 * 12 + 6]);
+    }
+
+    #[test]
+    fn test_progressive_render_adds_up_passes() {
+        use crate::material::Lambertian;
+
+        let sphere = SphereBuilder::new()
+            .center(Point3::new(0.0, 0.0, -2.0))
+            .radius(0.8)
+            .material(Lambertian::clay())
+            .build()
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(sphere)]).unwrap();
+        let camera = CameraBuilder::new()
+            .image_width(16)
+            .samples_per_pixel(32)
+            .max_depth(4)
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .seed(9)
+            .build();
+        let mean = |frame: &Framebuffer| {
+            frame.pixels().iter().map(|c| c.luminance()).sum::<f64>() / frame.pixels().len() as f64
+        };
+
+        // Each pass's image holds every sample so far
+        let mut counts = Vec::new();
+        let last = camera.render_progressive(&world, 12, |frame| {
+            counts.push(frame.samples_per_pixel());
+            ControlFlow::Continue(())
+        });
+        assert_eq!(counts, vec![12, 24, 32]);
+        assert_eq!(last.samples_per_pixel(), 32);
+        let whole = camera.render_frame(&world);
+        assert!((mean(&last) - mean(&whole)).abs() < 0.02 * mean(&whole));
+        let depth = last.depth().unwrap();
+        let nearest = depth.iter().copied().fold(f64::INFINITY, f64::min);
+        assert!((nearest - 1.2).abs() < 0.05, "{}", nearest);
+        assert!(depth[0].is_infinite());
+
+        // A single pass is the same render
+        let single = camera.render_progressive(&world, 32, |_| ControlFlow::Continue(()));
+        assert_eq!(single.pixels(), whole.pixels());
+
+        // Breaking stops after that pass
+        let mut passes = 0;
+        let preview = camera.render_progressive(&world, 4, |_| {
+            passes += 1;
+            ControlFlow::Break(())
+        });
+        assert_eq!((passes, preview.samples_per_pixel()), (1, 4));
+    }
+
+    #[test]
+    fn test_streaming_render_snapshots_finished_tiles() {
+        use crate::material::Lambertian;
+        use std::sync::Mutex;
+
+        let sphere = SphereBuilder::new()
+            .center(Point3::new(0.0, 0.0, -2.0))
+            .radius(0.8)
+            .material(Lambertian::clay())
+            .build()
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(sphere)]).unwrap();
+        let camera = CameraBuilder::new()
+            .image_width(32)
+            .samples_per_pixel(64)
+            .max_depth(4)
+            .tile_size(8)
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .seed(9)
+            .build();
+
+        let snapshots = Mutex::new(Vec::new());
+        let last = camera.render_streaming(&world, Duration::from_millis(5), |frame| {
+            snapshots.lock().unwrap().push(frame.clone());
+        });
+        let whole = camera.render_frame(&world);
+        assert_eq!(last.pixels(), whole.pixels());
+
+        // Snapshots hold finished pixels as they will end up, and black elsewhere
+        let snapshots = snapshots.into_inner().unwrap();
+        assert!(!snapshots.is_empty());
+        for snapshot in &snapshots {
+            assert_eq!(
+                (snapshot.width(), snapshot.height()),
+                (whole.width(), whole.height())
+            );
+            for (pixel, finished) in snapshot.pixels().iter().zip(whole.pixels()) {
+                assert!(*pixel == BLACK || pixel == finished);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tile_time_budget() {
+        use crate::material::Lambertian;
+
+        let sphere = SphereBuilder::new()
+            .center(Point3::new(0.0, 0.0, -2.0))
+            .radius(0.8)
+            .material(Lambertian::clay())
+            .build()
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(sphere)]).unwrap();
+        let camera = CameraBuilder::new()
+            .image_width(24)
+            .samples_per_pixel(16)
+            .max_depth(4)
+            .tile_size(16)
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .seed(4);
+        let unlimited = camera.clone().build().render_frame(&world);
+        assert!(unlimited.tile_samples().is_none());
+
+        // A budget that is never reached renders the same image, sample by sample
+        let generous = camera
+            .clone()
+            .tile_time_budget(Some(Duration::from_secs(3600)))
+            .build()
+            .render_frame(&world);
+        assert_eq!(generous.pixels(), unlimited.pixels());
+        let tiles = generous.tile_samples().unwrap();
+        assert_eq!(tiles.tile_size, 16);
+        assert_eq!(tiles.samples, vec![16.0; 4]);
+
+        // One that is always exceeded still takes a sample of every pixel
+        let rushed = camera
+            .tile_time_budget(Some(Duration::from_nanos(1)))
+            .build()
+            .render_frame(&world);
+        assert_eq!(rushed.tile_samples().unwrap().samples, vec![1.0; 4]);
+        assert!(rushed.pixels().iter().all(|pixel| pixel.is_finite()));
+        assert_ne!(rushed.pixels(), unlimited.pixels());
+    }
+
+    #[test]
+    fn test_progress_reporter_hears_every_tile() {
+        use crate::material::Lambertian;
+        use crate::progress::ProgressUpdate;
+        use std::sync::Mutex;
+
+        struct Recorder(Mutex<Vec<ProgressUpdate>>);
+        impl ProgressReporter for Recorder {
+            fn report(&self, update: &ProgressUpdate) {
+                self.0.lock().unwrap().push(*update);
+            }
+
+            fn finish(&self) -> Vec<String> {
+                vec!["server offline".to_string()]
+            }
+        }
+
+        let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, -2.0))
+                .radius(0.8)
+                .material(Lambertian::clay())
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let frame = CameraBuilder::new()
+            .image_width(20)
+            .samples_per_pixel(1)
+            .tile_size(8)
+            .progress_reporter(recorder.clone())
+            .build()
+            .render_frame(&world);
+        assert_eq!(
+            frame.diagnostics(),
+            [Diagnostic::ProgressNotReported(
+                "server offline".to_string()
+            )]
+        );
+
+        // Three tiles across and down, with one update as the render starts
+        let updates = recorder.0.lock().unwrap();
+        assert_eq!(updates.len(), 10);
+        assert!(updates.iter().all(|update| update.total == 9));
+        assert_eq!(
+            updates.iter().filter(|update| update.is_finished()).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_adaptive_sampling_spends_samples_on_noise() {
+        use crate::material::Lambertian;
+        use crate::sampler::AdaptiveSampling;
+
+        let sphere = SphereBuilder::new()
+            .center(Point3::new(0.0, 0.0, -2.0))
+            .radius(0.5)
+            .material(Lambertian::clay())
+            .build()
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(sphere)]).unwrap();
+        let camera = |renderer: Renderer, adaptive: Option<AdaptiveSampling>| {
+            CameraBuilder::new()
+                .image_width(16)
+                .samples_per_pixel(64)
+                .max_depth(4)
+                .look_from(Point3::new(0.0, 0.0, 0.0))
+                .look_at(Point3::new(0.0, 0.0, -1.0))
+                .renderer(renderer)
+                .adaptive_sampling(adaptive)
+                .seed(3)
+                .build()
+        };
+        let adaptive = Some(AdaptiveSampling::new(0.02, 8));
+
+        for renderer in [Renderer::Megakernel, Renderer::Wavefront] {
+            let values = camera(renderer, adaptive).render_values(&world);
+            // The sky is smooth and stops at the minimum; the sphere needs more
+            assert_eq!(values[0].samples, 8);
+            let center = &values[8 * 16 + 8];
+            assert!(center.samples > 8, "{}", center.samples);
+            assert!(values.iter().all(|value| value.samples <= 64));
+
+            let every = camera(renderer, None).render_values(&world);
+            assert!(every.iter().all(|value| value.samples == 64));
+            for (a, b) in values.iter().zip(&every) {
+                let error = (a.color.luminance() - b.color.luminance()).abs();
+                assert!(error < 0.1 * b.color.luminance().max(0.05), "{}", error);
+            }
+        }
+    }
+
+    #[test]
+    fn test_wavefront_respects_max_depth() {
+        use crate::material::Metal;
+
+        // Inside a mirror sphere no path escapes, so only the depth limit ends them
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .radius(5.0)
+                .material(Metal::new(Color::new(1.0, 1.0, 1.0), 0.0))
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let camera = CameraBuilder::new()
+            .image_width(4)
+            .samples_per_pixel(2)
+            .max_depth(8)
+            .renderer(Renderer::Wavefront)
+            .build();
+
+        let framebuffer = camera.render_frame(&world);
+        assert!(framebuffer.pixels().iter().all(|&p| p == BLACK));
+    }
+
+    #[test]
+    fn test_path_guiding_conserves_energy() {
+        use crate::material::Lambertian;
+        use crate::texture::{SolidColor, TextureEnum};
+
+        // A white diffuse sphere under a uniform white sky reflects all the light it
+        // receives, so however bounces are steered the image must stay white
+        let white = Color::new(1.0, 1.0, 1.0);
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, -2.0))
+                .radius(1.0)
+                .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
+                    SolidColor::new(white),
+                ))))
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let camera = CameraBuilder::new()
+            .image_width(16)
+            .samples_per_pixel(16)
+            .max_depth(4)
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .background(Background::Uniform(white))
+            .path_guiding(true)
+            .build();
+
+        let framebuffer = camera.render_frame(&world);
+        let pixels = framebuffer.pixels();
+        let mean = pixels.iter().map(|p| p.g()).sum::<f64>() / pixels.len() as f64;
+        assert!((mean - 1.0).abs() < 0.05, "mean was {}", mean);
+    }
+
+    #[test]
+    fn test_bake_sphere() {
+        use crate::material::Lambertian;
+        use crate::texture::{SolidColor, TextureEnum};
+
+        let red = Color::new(0.8, 0.1, 0.1);
+        let sphere: Arc<dyn Hittable> = Arc::new(
+            SphereBuilder::new()
+                .radius(1.0)
+                .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
+                    SolidColor::new(red),
+                ))))
+                .build()
+                .unwrap(),
+        );
+        // A floor just below the sphere hides the bottom of its hemispheres
+        let floor = SphereBuilder::new()
+            .center(Point3::new(0.0, -1001.0, 0.0))
+            .radius(1000.0)
+            .material(TestMaterial::new())
+            .build()
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(Arc::clone(&sphere)), Box::new(floor)]).unwrap();
+        let camera = CameraBuilder::new()
+            .samples_per_pixel(64)
+            .max_depth(2)
+            .background(Background::Uniform(Color::new(1.0, 1.0, 1.0)))
+            .build();
+
+        let albedo = camera.bake(&world, sphere.as_ref(), 4, BakeMode::Albedo);
+        assert_eq!((albedo.width(), albedo.height()), (4, 4));
+        assert!(albedo.pixels().iter().all(|&p| (p.r() - 0.8).abs() < 1e-9));
+
+        // The top row of the texture is the top of the sphere, open to the sky,
+        // while the bottom row faces the floor
+        let occlusion = camera.bake(&world, sphere.as_ref(), 4, BakeMode::Occlusion);
+        let (top, bottom) = (occlusion.pixels()[0], occlusion.pixels()[15]);
+        assert!(top.r() > 0.9, "top was {}", top);
+        assert!(bottom.r() < 0.5, "bottom was {}", bottom);
+
+        let lighting = camera.bake(&world, sphere.as_ref(), 4, BakeMode::Lighting);
+        assert!(lighting.pixels()[0].r() > lighting.pixels()[15].r());
+    }
+
+    #[test]
+    fn test_bake_object() {
+        use crate::medium::ConstantMedium;
+
+        let sphere = |radius: f64| -> Box<dyn Hittable> {
+            Box::new(
+                SphereBuilder::new()
+                    .radius(radius)
+                    .material(TestMaterial::new())
+                    .build()
+                    .unwrap(),
+            )
+        };
+        let camera = CameraBuilder::new().samples_per_pixel(1).build();
+
+        // The degenerate sphere ahead of the target is dropped, but the index still
+        // counts it
+        let (baked, report) = camera
+            .bake_object(vec![sphere(0.0), sphere(1.0)], 1, 4, BakeMode::Albedo, true)
+            .unwrap();
+        assert_eq!((baked.width(), baked.height()), (4, 4));
+        assert_eq!(report.degenerate.objects.len(), 1);
+
+        assert!(matches!(
+            camera.bake_object(vec![sphere(1.0)], 1, 4, BakeMode::Albedo, true),
+            Err(BakeError::NoObject {
+                index: 1,
+                objects: 1
+            })
+        ));
+        let fog: Box<dyn Hittable> =
+            Box::new(ConstantMedium::new(sphere(1.0), 0.1, TestMaterial::new()));
+        assert!(matches!(
+            camera.bake_object(vec![fog], 0, 4, BakeMode::Albedo, true),
+            Err(BakeError::NoTextureSpace(0))
+        ));
+    }
+
+    #[test]
+    fn test_bake_mesh() {
+        use crate::material::Lambertian;
+        use crate::mesh::{Mesh, TriangleMesh};
+        use crate::texture::{SolidColor, TextureEnum};
+
+        // One triangle mapped to the lower left corner of texture space, away from
+        // its middle, and one with no area
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 2 0 0\nvt 0 0\nvt 0.5 0\nvt 0 0.5\n\
+                   f 1/1 2/2 3/3\nf 1/1 2/2 4/3\n";
+        let red = Color::new(0.8, 0.1, 0.1);
+        let mesh: Box<dyn Hittable> = Box::new(
+            TriangleMesh::new(
+                Mesh::parse_obj(obj.as_bytes()).unwrap(),
+                Lambertian::new(Box::new(TextureEnum::SolidColor(SolidColor::new(red)))),
+            )
+            .unwrap(),
+        );
+        let camera = CameraBuilder::new().samples_per_pixel(4).build();
+
+        let (baked, report) = camera
+            .bake_object(vec![mesh], 0, 4, BakeMode::Albedo, true)
+            .unwrap();
+        assert_eq!(report.degenerate.primitives, vec![(0, 1)]);
+        // Rows run down from v = 1, so the covered corner is the bottom left
+        assert_eq!(baked.pixels()[12], red);
+        assert_eq!(baked.pixels()[3], BLACK);
+    }
+
+    #[test]
+    fn test_trace_pixel() {
+        use crate::material::Metal;
+        use crate::path_trace::PathEnd;
+
+        let sky = Color::new(0.25, 0.5, 1.0);
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, -3.0))
+                .radius(1.0)
+                .material(Metal::new(Color::new(0.5, 0.5, 0.5), 0.0))
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let camera = CameraBuilder::new()
+            .image_width(9)
+            .max_depth(4)
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .background(Background::Uniform(sky))
+            .build();
+
+        // The center pixel reflects off the front of the mirror and escapes
+        let trace = camera.trace_pixel(&world, 4, 4, 0);
+        assert_eq!(trace.vertices.len(), 1);
+        let vertex = &trace.vertices[0];
+        assert_eq!(vertex.event, PathEvent::Reflect);
+        assert_eq!(vertex.material.as_ref().map(Material::name), Some("metal"));
+        assert!((vertex.position.z() + 2.0).abs() < 0.1);
+        assert_eq!(trace.end, PathEnd::Escaped);
+        assert_eq!(trace.radiance, sky * Color::new(0.5, 0.5, 0.5));
+
+        // A corner pixel misses the sphere entirely
+        let trace = camera.trace_pixel(&world, 0, 0, 0);
+        assert!(trace.vertices.is_empty());
+        assert_eq!(trace.radiance, sky);
+    }
+
+    #[test]
+    fn test_material_override() {
+        use crate::material::{Lambertian, Metal};
+
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, -3.0))
+                .radius(1.0)
+                .material(Metal::new(Color::new(0.5, 0.5, 0.5), 0.0))
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let camera = CameraBuilder::new()
+            .image_width(9)
+            .max_depth(1)
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .material_override(Some(Lambertian::clay()))
+            .build();
+
+        // The mirror is shaded as clay, so the center pixel bounces diffusely
+        let trace = camera.trace_pixel(&world, 4, 4, 0);
+        let vertex = &trace.vertices[0];
+        assert_eq!(vertex.event, PathEvent::Diffuse);
+        assert_eq!(vertex.material, Some(Lambertian::clay()));
+    }
+
+    #[test]
+    fn test_orbit() {
+        let builder = CameraBuilder::new()
+            .look_from(Point3::new(0.0, 1.0, 2.0))
+            .look_at(Point3::new(0.0, 1.0, 0.0));
+        let quarter = builder.orbit(90.0);
+        assert!((quarter.look_from - Point3::new(2.0, 1.0, 0.0)).length() < 1e-9);
+        assert_eq!(quarter.look_at, builder.look_at);
+        let full = builder.orbit(360.0);
+        assert!((full.look_from - builder.look_from).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_frame_scene() {
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(10.0, 0.0, 0.0))
+                .radius(2.0)
+                .material(TestMaterial::new())
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let builder = CameraBuilder::new()
+            .aspect_ratio(2.0)
+            .vertical_fov(60.0)
+            .look_from(Point3::new(0.0, 0.0, 5.0))
+            .look_at(Point3::new(0.0, 0.0, 0.0))
+            .frame_scene(&world, 0.0);
+
+        // The box's bounding sphere has radius 2√3 and must fit the 60° vertical view
+        let center = Point3::new(10.0, 0.0, 0.0);
+        let distance = 2.0 * 3.0_f64.sqrt() / 30.0_f64.to_radians().sin();
+        assert_eq!(builder.look_at, center);
+        assert!((builder.look_from - (center + Vec3::new(0.0, 0.0, distance))).near_zero());
+        assert!((builder.focus_dist.unwrap() - distance).abs() < 1e-9);
+
+        // The sphere is centered and the view's corners miss it
+        let camera = builder.image_width(10).pass(RenderPass::MaterialId).build();
+        let id = |x, y| {
+            let ray = camera.get_ray(
+                x,
+                y,
+                &PixelSampler::new(PixelSampling::Independent, 1).sample(0),
+            );
+            camera.sample(&ray, &world, SampleId::default()).0
+        };
+        assert_ne!(id(5, 2), BLACK);
+        assert_eq!(id(0, 0), BLACK);
+        assert_eq!(id(9, 4), BLACK);
+    }
+
+    #[test]
+    fn test_units_scale_ray_t_min() {
+        // A kilometer-scale scene with a sphere half a meter in front of the camera
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, -0.0015))
+                .radius(0.001)
+                .material(TestMaterial::new())
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+
+        // Read as meters, the near side is closer than the minimum hit distance
+        let meters = CameraBuilder::new().build();
+        let (_, distance) = meters.sample(&ray, &world, SampleId::default());
+        assert!((distance.unwrap() - 0.0025).abs() < 1e-12);
+
+        let kilometers = CameraBuilder::new().units(Units::Kilometers).build();
+        assert!((kilometers.ray_t_min - 1e-6).abs() < 1e-18);
+        let (_, distance) = kilometers.sample(&ray, &world, SampleId::default());
+        assert!((distance.unwrap() - 0.0005).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_units_scale_default_focus_distance() {
+        let camera = CameraBuilder::new()
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .units(Units::Centimeters)
+            .build();
+        // The viewport sits at the focus distance, a meter away
+        assert!((camera.pixel00_loc.z() + 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_nan_guard_substitutes_black() {
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, -2.0))
+                .radius(1.0)
+                .material(crate::material::Metal::new(
+                    Color::new(f64::NAN, 0.5, 0.5),
+                    0.0,
+                ))
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+
+        let unguarded = CameraBuilder::new().max_depth(2).build();
+        let (color, _) = unguarded.sample(&ray, &world, SampleId::default());
+        assert!(!color.is_finite());
+
+        let guarded = CameraBuilder::new().max_depth(2).nan_guard(true).build();
+        let (color, distance) = guarded.sample(&ray, &world, SampleId::default());
+        assert_eq!(color, BLACK);
+        assert!(distance.is_some());
+    }
+
+    #[test]
+    fn test_nan_guard_notes_non_finite_samples() {
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, -2.0))
+                .radius(1.0)
+                .material(crate::material::Metal::new(
+                    Color::new(f64::NAN, 0.5, 0.5),
+                    0.0,
+                ))
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let camera = CameraBuilder::new()
+            .image_width(4)
+            .aspect_ratio(1.0)
+            .samples_per_pixel(1)
+            .max_depth(2)
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .seed(7)
+            .nan_guard(true)
+            .build();
+
+        let frame = camera.render_frame(&world);
+        let noted = frame.diagnostics().len();
+        assert!(noted > 0);
+        assert!(frame.diagnostics().iter().all(|diagnostic| matches!(
+            diagnostic,
+            Diagnostic::NonFinite {
+                material: "metal",
+                bounce: 0,
+                ..
+            }
+        )));
+        assert!(frame.diagnostics()[0].is_warning());
+        // Each render notes only its own samples
+        assert_eq!(camera.render_frame(&world).diagnostics().len(), noted);
+    }
+
+    #[test]
+    fn test_ray_color_depth_zero() {
+        let ray = Ray::new(Point3::default(), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        // Create a sphere that the ray will miss
+        let sphere = SphereBuilder::new()
+            .center(Point3::new(0.0, 0.0, -1.0))
+            .radius(0.5)
+            .material(TestMaterial::new())
+            .build()
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(sphere)]).unwrap();
+        let camera = Camera::default();
+        let hit = world.hit(&ray, Interval::new(camera.ray_t_min, f64::INFINITY));
+        let color = camera.ray_color(&ray, hit, 0, &world, SampleId::default());
+        assert_eq!(color, Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_ray_color_deep_paths_do_not_overflow() {
+        use crate::material::Metal;
+
+        // A ray trapped inside a mirror sphere bounces until the depth limit
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .radius(1.0)
+                .material(Metal::new(Color::new(1.0, 1.0, 1.0), 0.0))
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let ray = Ray::new(Point3::default(), Vec3::new(0.3, 0.4, 0.5), 0.0);
+        let camera = Camera::default();
+        let hit = world.hit(&ray, Interval::new(camera.ray_t_min, f64::INFINITY));
+        let color = camera.ray_color(&ray, hit, 100_000, &world, SampleId::default());
+        assert_eq!(color, BLACK);
+    }
+
+    #[test]
+    fn test_render_frame_depth() {
+        let sphere = SphereBuilder::new()
+            .center(Point3::new(0.0, 0.0, -3.0))
+            .radius(1.0)
+            .material(TestMaterial::new())
+            .build()
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(sphere)]).unwrap();
+        let camera = CameraBuilder::new()
+            .image_width(9)
+            .samples_per_pixel(4)
+            .max_depth(2)
+            .vertical_fov(90.0)
+            .look_from(Point3::new(0.0, 0.0, 0.0))
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .build();
+
+        let framebuffer = camera.render_frame(&world);
+        assert_eq!(framebuffer.samples_per_pixel(), 4);
+        let depth = framebuffer.depth().unwrap();
+        // The center pixel sees the front of the sphere about 2 units away
+        let center = depth[4 * 9 + 4];
+        assert!((center - 2.0).abs() < 0.1, "center depth was {}", center);
+        // The corners see only the background
+        assert_eq!(depth[0], f64::INFINITY);
+    }
+
+    #[test]
+    fn test_tiles_cover_the_image() {
+        use crate::material::Metal;
+        use crate::mesh::{Mesh, TriangleMesh};
+
+        // A wall split into quadrants of different materials, meeting at the center
+        // of the view on pixel boundaries
+        let quadrant = |x: f64, y: f64, shade: f64| {
+            let min = Point3::new(x.min(0.0), y.min(0.0), -1.1);
+            let max = Point3::new(x.max(0.0), y.max(0.0), -1.0);
+            let material = Metal::new(Color::new(shade, 0.5, 0.5), 0.0);
+            (
+                material.id_color(),
+                Box::new(TriangleMesh::new(Mesh::cuboid(min, max), material).unwrap())
+                    as Box<dyn Hittable>,
+            )
+        };
+        let (colors, objects): (Vec<Color>, Vec<Box<dyn Hittable>>) = [
+            quadrant(-5.0, 5.0, 0.1),
+            quadrant(5.0, 5.0, 0.3),
+            quadrant(-5.0, -5.0, 0.6),
+            quadrant(5.0, -5.0, 0.9),
+        ]
+        .into_iter()
+        .unzip();
+        let world = Bvh::new(objects).unwrap();
+
+        let size = 10;
+        for tile_size in [1, 3, 4, 16] {
+            let camera = CameraBuilder::new()
+                .image_width(size)
+                .samples_per_pixel(2)
+                .vertical_fov(90.0)
+                .look_from(Point3::new(0.0, 0.0, 0.0))
+                .look_at(Point3::new(0.0, 0.0, -1.0))
+                .pass(RenderPass::MaterialId)
+                .tile_size(tile_size)
+                .build();
+            let framebuffer = camera.render_frame(&world);
+            for (index, pixel) in framebuffer.pixels().iter().enumerate() {
+                let (i, j) = (index as u32 % size, index as u32 / size);
+                let expected = colors[(i >= size / 2) as usize + 2 * (j >= size / 2) as usize];
+                assert_eq!(
+                    *pixel,
+                    expected,
+                    "tile size {} pixel {:?}",
+                    tile_size,
+                    (i, j)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_direct_light_from_point_light() {
+        use crate::light::PointLight;
+        use crate::material::Lambertian;
+        use crate::texture::{SolidColor, TextureEnum};
+
+        let floor = SphereBuilder::new()
+            .center(Point3::new(0.0, -1000.0, 0.0))
+            .radius(1000.0)
+            .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
+                SolidColor::new(Color::new(1.0, 1.0, 1.0)),
+            ))))
+            .build()
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(floor)]).unwrap();
+        let hit_record = HitRecord {
+            position: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            geometric_normal: Vec3::new(0.0, 1.0, 0.0),
+            ..Default::default()
+        };
+
+        let camera = CameraBuilder::new()
+            .light(PointLight::new(
+                Point3::new(0.0, 2.0, 0.0),
+                Color::new(4.0, 4.0, 4.0) * f64::consts::PI,
+            ))
+            .build();
+        let direct = camera.direct_light(
+            &hit_record,
+            &incoming(0.0),
+            Lobe::Diffuse(Color::new(0.5, 0.5, 0.5)),
+            &world,
+            SampleId::default(),
+            0,
+        );
+        assert_eq!(direct, Color::new(0.5, 0.5, 0.5));
+
+        // A light below the surface contributes nothing
+        let camera = CameraBuilder::new()
+            .light(PointLight::new(
+                Point3::new(0.0, -2.0, 0.0),
+                Color::new(1.0, 1.0, 1.0),
+            ))
+            .build();
+        let direct = camera.direct_light(
+            &hit_record,
+            &incoming(0.0),
+            Lobe::Diffuse(Color::new(0.5, 0.5, 0.5)),
+            &world,
+            SampleId::default(),
+            0,
+        );
+        assert_eq!(direct, Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_shadow_rays_see_moving_objects_at_the_ray_time() {
+        use crate::light::PointLight;
+
+        // A ball passes between the surface and the light halfway through the shutter
+        let ball = SphereBuilder::new()
+            .center(Point3::new(-4.0, 1.0, 0.0))
+            .center_end(Point3::new(4.0, 1.0, 0.0))
+            .time_range(0.0, 1.0)
+            .radius(0.5)
+            .material(TestMaterial::new())
+            .build()
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(ball)]).unwrap();
+        let hit_record = HitRecord {
+            position: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            geometric_normal: Vec3::new(0.0, 1.0, 0.0),
+            ..Default::default()
+        };
+        let camera = CameraBuilder::new()
+            .light(PointLight::new(
+                Point3::new(0.0, 2.0, 0.0),
+                Color::new(1.0, 1.0, 1.0),
+            ))
+            .build();
+        let direct = |time| {
+            camera.direct_light(
+                &hit_record,
+                &incoming(time),
+                Lobe::Diffuse(Color::new(0.5, 0.5, 0.5)),
+                &world,
+                SampleId::default(),
+                0,
+            )
+        };
+
+        assert!(direct(0.0).g() > 0.0);
+        assert_eq!(direct(0.5), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_shadow_rays_see_the_camera_samples_level_of_detail() {
+        use crate::light::PointLight;
+        use crate::lod::LodGroup;
+
+        // Up close, light passes between two small balls; from afar they are
+        // simplified to one large ball in the way
+        let ball = |x: f64, radius: f64| -> Box<dyn Hittable> {
+            Box::new(
+                SphereBuilder::new()
+                    .center(Point3::new(x, 1.0, 0.0))
+                    .radius(radius)
+                    .material(TestMaterial::new())
+                    .build()
+                    .unwrap(),
+            )
+        };
+        let group = LodGroup::new(vec![ball(-0.6, 0.3), ball(0.6, 0.3)])
+            .and_then(|group| group.level(10.0, vec![ball(0.0, 0.8)]))
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(group)]).unwrap();
+        let hit_record = HitRecord {
+            position: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            geometric_normal: Vec3::new(0.0, 1.0, 0.0),
+            ..Default::default()
+        };
+        let camera = CameraBuilder::new()
+            .light(PointLight::new(
+                Point3::new(0.0, 2.0, 0.0),
+                Color::new(1.0, 1.0, 1.0),
+            ))
+            .build();
+        let direct = |ray: Ray| {
+            camera.direct_light(
+                &hit_record,
+                &ray,
+                Lobe::Diffuse(Color::new(0.5, 0.5, 0.5)),
+                &world,
+                SampleId::default(),
+                0,
+            )
+        };
+
+        assert!(direct(incoming(0.0)).g() > 0.0);
+        let from_afar = incoming(0.0).with_viewpoint(Point3::new(0.0, 1.0, 50.0));
+        assert_eq!(direct(from_afar), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_transparent_shadows() {
+        use crate::light::PointLight;
+        use crate::material::{Dielectric, Lambertian};
+
+        // A ball of green glass hangs between the surface and the light
+        let tint = Color::new(0.2, 0.8, 0.4);
+        let ball = SphereBuilder::new()
+            .center(Point3::new(0.0, 1.0, 0.0))
+            .radius(0.4)
+            .material(Dielectric::tinted(1.5, tint))
+            .build()
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(ball)]).unwrap();
+        let hit_record = HitRecord {
+            position: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            geometric_normal: Vec3::new(0.0, 1.0, 0.0),
+            ..Default::default()
+        };
+        let direct = |transparent_shadows| {
+            CameraBuilder::new()
+                .light(PointLight::new(
+                    Point3::new(0.0, 2.0, 0.0),
+                    Color::new(4.0, 4.0, 4.0) * f64::consts::PI,
+                ))
+                .transparent_shadows(transparent_shadows)
+                .build()
+                .direct_light(
+                    &hit_record,
+                    &incoming(0.0),
+                    Lobe::Diffuse(Color::new(0.5, 0.5, 0.5)),
+                    &world,
+                    SampleId::default(),
+                    0,
+                )
+        };
+
+        assert_eq!(direct(false), Color::new(0.0, 0.0, 0.0));
+        // The light passes in and out of the ball, tinted and losing 4% each time
+        let lit = direct(true);
+        let expected = tint * tint * (0.5 * 0.96 * 0.96);
+        for (lit, expected) in [
+            (lit.r(), expected.r()),
+            (lit.g(), expected.g()),
+            (lit.b(), expected.b()),
+        ] {
+            assert!((lit - expected).abs() < 1e-9, "{} != {}", lit, expected);
+        }
+
+        // Opaque surfaces still block the light
+        let ball = SphereBuilder::new()
+            .center(Point3::new(0.0, 1.0, 0.0))
+            .radius(0.4)
+            .material(Lambertian::clay())
+            .build()
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(ball)]).unwrap();
+        let camera = CameraBuilder::new()
+            .light(PointLight::new(
+                Point3::new(0.0, 2.0, 0.0),
+                Color::new(1.0, 1.0, 1.0),
+            ))
+            .transparent_shadows(true)
+            .build();
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        assert_eq!(
+            camera.shadow_transmittance(&ray, Interval::new(0.001, 2.0), &world),
+            None
+        );
+    }
+
+    #[test]
+    fn test_dielectric_interior_medium() {
+        use crate::material::Dielectric;
+        use crate::medium::Medium;
+
+        // Glass matching the air's index neither bends nor reflects light head on,
+        // so only the water inside colors the background seen through it
+        let glass = |interior| {
+            let mut material = Dielectric::new(1.0);
+            if let Material::Dielectric(dielectric) = &mut material {
+                dielectric.set_interior(interior);
+            }
+            let sphere = SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, -3.0))
+                .radius(1.0)
+                .material(material)
+                .build()
+                .unwrap();
+            Bvh::new(vec![Box::new(sphere)]).unwrap()
+        };
+        let camera = CameraBuilder::new()
+            .look_from(Point3::default())
+            .look_at(Point3::new(0.0, 0.0, -1.0))
+            .background(Background::Uniform(Color::new(1.0, 1.0, 1.0)))
+            .max_depth(5)
+            .build();
+        let ray = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, -1.0), 0.0);
+
+        let (clear, _) = camera.sample(&ray, &glass(None), SampleId::default());
+        assert_eq!(clear, Color::new(1.0, 1.0, 1.0));
+
+        let water = glass(Some(Medium::absorbing(Color::new(1.0, 0.25, 0.0))));
+        let (tinted, _) = camera.sample(&ray, &water, SampleId::default());
+        // The ray crosses two units of water
+        let expected = Color::new((-2.0f64).exp(), (-0.5f64).exp(), 1.0);
+        for (tinted, expected) in [
+            (tinted.r(), expected.r()),
+            (tinted.g(), expected.g()),
+            (tinted.b(), expected.b()),
+        ] {
+            assert!(
+                (tinted - expected).abs() < 1e-6,
+                "{} != {}",
+                tinted,
+                expected
+            );
+        }
+
+        let (x, y) = (camera.image_width / 2, camera.image_height / 2);
+        let trace = camera.trace_pixel(&water, x, y, 0);
+        assert!(
+            trace.radiance.b() > 2.0 * trace.radiance.r(),
+            "{:?}",
+            trace.radiance
+        );
+    }
+
+    #[test]
+    fn test_camera_medium() {
+        use crate::material::DiffuseLight;
+        use crate::texture::TextureEnum;
+
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, -3.0))
+                .radius(1.0)
+                .material(DiffuseLight::new(Box::new(TextureEnum::SolidColor(
+                    Color::new(1.0, 1.0, 1.0).into(),
+                ))))
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let haze = Medium::absorbing(Color::new(0.25, 0.0, 0.0));
+        let camera = |extent| {
+            CameraBuilder::new()
+                .background(Background::Uniform(Color::new(1.0, 1.0, 1.0)))
+                .medium(Some(haze.clone()))
+                .medium_extent(extent)
+                .build()
+        };
+        let at_light = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let at_sky = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, 1.0), 0.0);
+
+        // Light from the surface crosses two units of haze
+        let (color, distance) = camera(None).sample(&at_light, &world, SampleId::default());
+        assert_eq!(distance, Some(2.0));
+        assert!((color.r() - (-0.5f64).exp()).abs() < 1e-9, "{:?}", color);
+        assert_eq!((color.g(), color.b()), (1.0, 1.0));
+
+        // The background is as far away as the medium extends
+        let (color, distance) = camera(Some(4.0)).sample(&at_sky, &world, SampleId::default());
+        assert_eq!(distance, None);
+        assert!((color.r() - (-1.0f64).exp()).abs() < 1e-9, "{:?}", color);
+        let (color, _) = camera(None).sample(&at_sky, &world, SampleId::default());
+        assert_eq!(color, Color::new(0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_self_hit_exclusion() {
+        use crate::material::Lambertian;
+        use crate::quad::Quad;
+
+        let floor = Quad::new(
+            Point3::new(-1.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Lambertian::clay(),
+        );
+        let ball = SphereBuilder::new()
+            .center(Point3::new(3.0, 1.0, 0.0))
+            .radius(0.5)
+            .material(Lambertian::clay())
+            .build()
+            .unwrap();
+        let world = Bvh::new(vec![Box::new(floor), Box::new(ball)]).unwrap();
+        let onto_floor = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let onto_ball = Ray::new(Point3::new(3.0, 3.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+
+        for (exclusion, floor_excluded, ball_excluded) in [
+            (SelfHitExclusion::Off, false, false),
+            (SelfHitExclusion::Flat, true, false),
+            (SelfHitExclusion::All, true, true),
+        ] {
+            let camera = CameraBuilder::new().self_hit_exclusion(exclusion).build();
+            let floor_hit = camera.intersect(&world, &onto_floor).unwrap();
+            let ball_hit = camera.intersect(&world, &onto_ball).unwrap();
+            assert_eq!(floor_hit.exclude_primitive, floor_excluded);
+            assert_eq!(ball_hit.exclude_primitive, ball_excluded);
+            assert_eq!(
+                floor_hit
+                    .spawn_ray(Vec3::new(0.0, 1.0, 0.0), 0.0)
+                    .excluded_primitive()
+                    .is_some(),
+                floor_excluded
+            );
+        }
+    }
+
+    #[test]
+    fn test_direct_light_in_a_medium() {
+        use crate::light::PointLight;
+
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, -1000.0, 0.0))
+                .radius(1000.0)
+                .material(TestMaterial::new())
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        // A point in the air, whose normal only faces the ray that found it
+        let hit_record = HitRecord {
+            position: Point3::new(0.0, 5.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            geometric_normal: Vec3::new(0.0, 0.0, 1.0),
+            ..Default::default()
+        };
+        let albedo = Color::new(1.0, 1.0, 1.0);
+
+        // Lights on either side scatter a 1 / 4 pi share of their irradiance
+        for light in [Point3::new(0.0, 7.0, 0.0), Point3::new(0.0, 5.0, -2.0)] {
+            let camera = CameraBuilder::new()
+                .light(PointLight::new(
+                    light,
+                    Color::new(16.0, 16.0, 16.0) * f64::consts::PI,
+                ))
+                .build();
+            let direct = camera.direct_light(
+                &hit_record,
+                &incoming(0.0),
+                Lobe::Isotropic(albedo),
+                &world,
+                SampleId::default(),
+                0,
+            );
+            assert!((direct.g() - 1.0).abs() < 1e-12, "{}", direct);
+        }
+    }
+
+    #[test]
+    fn test_light_links_limit_direct_light() {
+        use crate::light::PointLight;
+
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, -1000.0, 0.0))
+                .radius(1000.0)
+                .material(TestMaterial::new())
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let camera = CameraBuilder::new()
+            .light(PointLight::new(
+                Point3::new(0.0, 2.0, 0.0),
+                Color::new(1.0, 1.0, 1.0),
+            ))
+            .linked_light(
+                PointLight::new(Point3::new(0.0, 2.0, 0.0), Color::new(2.0, 2.0, 2.0)),
+                LightLink::Include(vec![1]),
+            )
+            .light_sampling(LightSampling::All)
+            .build();
+        let direct = |light_group| {
+            let hit_record = HitRecord {
+                position: Point3::new(0.0, 0.0, 0.0),
+                normal: Vec3::new(0.0, 1.0, 0.0),
+                geometric_normal: Vec3::new(0.0, 1.0, 0.0),
+                light_group,
+                ..Default::default()
+            };
+            camera.direct_light(
+                &hit_record,
+                &incoming(0.0),
+                Lobe::Diffuse(Color::new(1.0, 1.0, 1.0)),
+                &world,
+                SampleId::default(),
+                0,
+            )
+        };
+
+        // Only the grouped surface is lit by the linked light as well
+        let unlinked = direct(None);
+        assert!(unlinked.g() > 0.0);
+        assert_eq!(direct(Some(2)), unlinked);
+        assert!((direct(Some(1)).g() - 3.0 * unlinked.g()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_light_tree_matches_sampling_every_light() {
+        use crate::light::PointLight;
+        use crate::utilities::seed_thread_rng;
+
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, -1000.0, 0.0))
+                .radius(1000.0)
+                .material(TestMaterial::new())
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let hit_record = HitRecord {
+            position: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            geometric_normal: Vec3::new(0.0, 1.0, 0.0),
+            ..Default::default()
+        };
+        let lit = |light_sampling| {
+            (0..16)
+                .fold(CameraBuilder::new(), |camera, i| {
+                    let x = i as f64 - 8.0;
+                    camera.light(PointLight::new(
+                        Point3::new(x, 2.0, 1.0),
+                        Color::new(1.0, 0.5, 0.25) * (i + 1) as f64,
+                    ))
+                })
+                .light_sampling(light_sampling)
+                .build()
+        };
+        let albedo = Color::new(1.0, 1.0, 1.0);
+
+        let exact = lit(LightSampling::All).direct_light(
+            &hit_record,
+            &incoming(0.0),
+            Lobe::Diffuse(albedo),
+            &world,
+            SampleId::default(),
+            0,
+        );
+        seed_thread_rng(3);
+        let tree = lit(LightSampling::Tree);
+        let samples = 20_000;
+        let estimate = (0..samples).fold(BLACK, |sum, _| {
+            sum + tree.direct_light(
+                &hit_record,
+                &incoming(0.0),
+                Lobe::Diffuse(albedo),
+                &world,
+                SampleId::default(),
+                0,
+            )
+        }) * (1.0 / samples as f64);
+
+        assert!(
+            (estimate.r() / exact.r() - 1.0).abs() < 0.05,
+            "estimated {} for {}",
+            estimate,
+            exact
+        );
+    }
+
+    #[test]
+    fn test_power_heuristic() {
+        assert_eq!(power_heuristic(1.0, 0.0), 1.0);
+        assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+        assert_eq!(power_heuristic(2.0, 1.0) + power_heuristic(1.0, 2.0), 1.0);
+        assert!(power_heuristic(3.0, 1.0) > 0.75);
+    }
+
+    #[test]
+    fn test_metal_reflects_quad_light() {
+        use crate::light::QuadLight;
+        use crate::material::Metal;
+
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, -1000.0, 0.0))
+                .radius(1000.0)
+                .material(Metal::new(Color::new(0.8, 0.8, 0.8), 0.0))
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let camera = CameraBuilder::new()
+            .background(Background::Uniform(BLACK))
+            .light(QuadLight::new(
+                Point3::new(-1.0, 1.0, -1.0),
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, 2.0),
+                Color::new(5.0, 5.0, 5.0),
+            ))
+            .max_depth(4)
+            .build();
+
+        // Light sampling never reaches a mirror, so only the reflected ray finds the light
+        let ray = Ray::new(Point3::new(0.0, 0.5, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let hit = world.hit(&ray, Interval::new(camera.ray_t_min, f64::INFINITY));
+        let color = camera.ray_color(&ray, hit, camera.max_depth, &world, SampleId::default());
+        assert!((color.g() - 4.0).abs() < 1e-9, "reflected {}", color);
+    }
+
+    #[test]
+    fn test_emissive_sphere_lights_the_scene() {
+        use crate::material::{DiffuseLight, Lambertian};
+        use crate::texture::TextureEnum;
+
+        let solid = |value: f64| {
+            Box::new(TextureEnum::SolidColor(
+                Color::new(value, value, value).into(),
+            ))
+        };
+        // A grey ball inside a glowing shell, which every bounce off the ball reaches
+        let world = Bvh::new(vec![
+            Box::new(
+                SphereBuilder::new()
+                    .center(Point3::new(0.0, 0.0, 0.0))
+                    .radius(1.0)
+                    .material(Lambertian::new(solid(0.5)))
+                    .build()
+                    .unwrap(),
+            ),
+            Box::new(
+                SphereBuilder::new()
+                    .center(Point3::new(0.0, 0.0, 0.0))
+                    .radius(10.0)
+                    .material(DiffuseLight::new(solid(2.0)))
+                    .build()
+                    .unwrap(),
+            ),
+        ])
+        .unwrap();
+        let camera = CameraBuilder::new()
+            .background(Background::Uniform(BLACK))
+            .max_depth(8)
+            .build();
+
+        let color = |ray: Ray, depth: u32| {
+            let hit = world.hit(&ray, Interval::new(camera.ray_t_min, f64::INFINITY));
+            camera.ray_color(&ray, hit, depth, &world, SampleId::default())
+        };
+        let at_ball = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let at_shell = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert_eq!(color(at_shell, 8), Color::new(2.0, 2.0, 2.0));
+        assert_eq!(color(at_ball, 8), Color::new(1.0, 1.0, 1.0));
+        // The light is only found by bouncing into it
+        assert_eq!(color(at_ball, 1), BLACK);
+    }
+
+    #[test]
+    fn test_light_objects_must_be_sampleable() {
+        use crate::instance::Instance;
+        use crate::quad::Quad;
+
+        let lamp: Arc<dyn Hittable> = Arc::new(Quad::new(
+            Point3::new(0.0, 2.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            TestMaterial::new(),
+        ));
+        assert!(CameraBuilder::new().light_object(Arc::clone(&lamp)).is_ok());
+
+        // An instance leaves sampling to the default, which aims nowhere
+        let placed: Arc<dyn Hittable> = Arc::new(Instance::new(lamp, Vec3::new(0.0, 1.0, 0.0)));
+        assert!(!placed.is_sampleable());
+        assert!(matches!(
+            CameraBuilder::new().light_object(placed),
+            Err(Error::Build(_))
+        ));
+    }
+
+    #[test]
+    fn test_light_objects_reduce_noise() {
+        use crate::material::{DiffuseLight, Lambertian};
+        use crate::texture::TextureEnum;
+        use crate::utilities::seed_thread_rng;
+
+        let solid = |value: f64| {
+            Box::new(TextureEnum::SolidColor(
+                Color::new(value, value, value).into(),
+            ))
+        };
+        let lamp: Arc<dyn Hittable> = Arc::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 2.0, 0.0))
+                .radius(0.25)
+                .material(DiffuseLight::new(solid(10.0)))
+                .build()
+                .unwrap(),
+        );
+        let world = Bvh::new(vec![
+            Box::new(
+                SphereBuilder::new()
+                    .center(Point3::new(0.0, -1000.0, 0.0))
+                    .radius(1000.0)
+                    .material(Lambertian::new(solid(0.5)))
+                    .build()
+                    .unwrap(),
+            ),
+            Box::new(lamp.clone()),
+        ])
+        .unwrap();
+        let builder = CameraBuilder::new()
+            .background(Background::Uniform(BLACK))
+            .max_depth(2);
+
+        // Mean and variance of the light reflected straight up from below the lamp
+        let estimate = |camera: &Camera| {
+            seed_thread_rng(11);
+            let ray = Ray::new(Point3::new(0.0, 0.5, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+            let samples = 20_000;
+            let values: Vec<f64> = (0..samples)
+                .map(|_| {
+                    let hit = world.hit(&ray, Interval::new(camera.ray_t_min, f64::INFINITY));
+                    camera
+                        .ray_color(&ray, hit, camera.max_depth, &world, SampleId::default())
+                        .g()
+                })
+                .collect();
+            let mean = values.iter().sum::<f64>() / samples as f64;
+            let variance =
+                values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / samples as f64;
+            (mean, variance)
+        };
+        let (_, unaimed_variance) = estimate(&builder.clone().build());
+        let (mean, variance) = estimate(&builder.light_object(lamp).unwrap().build());
+
+        // A sphere straight above gives irradiance pi * L * sin² of its half-angle
+        let expected = 0.5 / f64::consts::PI * f64::consts::PI * 10.0 * (0.25 / 2.0_f64).powi(2);
+        assert!(
+            (mean / expected - 1.0).abs() < 0.03,
+            "estimated {} for {}",
+            mean,
+            expected
+        );
+        assert!(
+            variance * 10.0 < unaimed_variance,
+            "variance {} against {} without aiming",
+            variance,
+            unaimed_variance
+        );
+    }
+
+    #[test]
+    fn test_mis_matches_quad_light_form_factor() {
+        use crate::light::QuadLight;
+        use crate::material::Lambertian;
+        use crate::texture::{SolidColor, TextureEnum};
+        use crate::utilities::seed_thread_rng;
+
+        let world = Bvh::new(vec![Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, -1000.0, 0.0))
+                .radius(1000.0)
+                .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
+                    SolidColor::new(Color::new(0.5, 0.5, 0.5)),
+                ))))
+                .build()
+                .unwrap(),
+        )])
+        .unwrap();
+        let camera = CameraBuilder::new()
+            .background(Background::Uniform(BLACK))
+            .light(QuadLight::new(
+                Point3::new(-1.0, 1.0, -1.0),
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, 2.0),
+                Color::new(1.0, 1.0, 1.0),
+            ))
+            .max_depth(2)
+            .build();
+
+        seed_thread_rng(7);
+        let ray = Ray::new(Point3::new(0.0, 0.5, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let samples = 20_000;
+        let sampler = PixelSampler::new(PixelSampling::CorrelatedMultiJittered, samples);
+        let estimate = (0..samples)
+            .map(|sample| {
+                let id = SampleId {
+                    pixel: (0, 0),
+                    sample,
+                    sampler,
+                };
+                let hit = world.hit(&ray, Interval::new(camera.ray_t_min, f64::INFINITY));
+                camera
+                    .ray_color(&ray, hit, camera.max_depth, &world, id)
+                    .g()
+            })
+            .sum::<f64>()
+            / samples as f64;
+
+        // Reflected irradiance below the center of a square light, from the form factor
+        let x = 1.0 / 2.0_f64.sqrt();
+        let irradiance = 4.0 * x * x.atan();
+        let expected = 0.5 / f64::consts::PI * irradiance;
+        assert!(
+            (estimate / expected - 1.0).abs() < 0.03,
+            "estimated {} for {}",
+            estimate,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_regularization_finds_reflected_point_light() {
+        use crate::light::PointLight;
+        use crate::material::{Lambertian, Metal};
+        use crate::utilities::seed_thread_rng;
+
+        let sphere = |center: Point3, material: Material| {
+            Box::new(
+                SphereBuilder::new()
+                    .center(center)
+                    .radius(1000.0)
+                    .material(material)
+                    .build()
+                    .unwrap(),
+            ) as Box<dyn Hittable>
+        };
+        let world = Bvh::new(vec![
+            sphere(Point3::new(0.0, -1000.0, 0.0), Lambertian::clay()),
+            sphere(
+                Point3::new(0.0, 0.0, -1002.0),
+                Metal::new(Color::new(1.0, 1.0, 1.0), 0.0),
+            ),
+            // Shades the floor below the camera from the light itself
+            Box::new(
+                SphereBuilder::new()
+                    .center(Point3::new(0.0, 0.5, 0.5))
+                    .radius(0.2)
+                    .material(Lambertian::clay())
+                    .build()
+                    .unwrap(),
+            ),
+        ])
+        .unwrap();
+        let render = |regularization| {
+            seed_thread_rng(11);
+            let camera = CameraBuilder::new()
+                .background(Background::Uniform(BLACK))
+                .light(PointLight::new(
+                    Point3::new(0.0, 1.0, 0.5),
+                    Color::new(1.0, 1.0, 1.0),
+                ))
+                .max_depth(3)
+                .regularization(regularization)
+                .build();
+            let ray = Ray::new(Point3::new(0.0, 0.1, 0.5), Vec3::new(0.0, -1.0, 0.0), 0.0);
+            let samples = 4000;
+            (0..samples)
+                .map(|_| {
+                    let hit = world.hit(&ray, Interval::new(camera.ray_t_min, f64::INFINITY));
+                    camera
+                        .ray_color(&ray, hit, camera.max_depth, &world, SampleId::default())
+                        .g()
+                })
+                .sum::<f64>()
+                / samples as f64
+        };
+
+        // The light's reflection in the mirror only reaches the floor once blurred
+        let sharp = render(None);
+        let blurred = render(Some(0.5));
+        assert!(blurred > sharp * 1.5, "{} vs {}", blurred, sharp);
     }
 }
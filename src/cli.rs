@@ -0,0 +1,1709 @@
+//! Command-line argument parsing for the renderer binary.
+
+use raytrace::camera::{BakeMode, LightSampling, Projection, RenderPass, Renderer};
+use raytrace::display::DisplayTransform;
+use raytrace::framebuffer::ImageFormat;
+use raytrace::hittable::SelfHitExclusion;
+use raytrace::mesh::{AxisConvention, Handedness, UpAxis};
+use raytrace::overrides::{Override, Sweep};
+use raytrace::placement::Placement;
+use raytrace::postprocess::RolloffStart;
+use raytrace::sampler::PixelSampling;
+use raytrace::units::Units;
+use std::time::Duration;
+
+/// Largest sample count a convergence run renders unless told otherwise.
+const DEFAULT_CONVERGENCE_SAMPLES: u32 = 64;
+/// Width and height of a baked texture unless told otherwise.
+const DEFAULT_BAKE_SIZE: u32 = 512;
+/// Frames in a turntable revolution or a simulation unless told otherwise.
+const DEFAULT_ANIMATION_FRAMES: u32 = 36;
+/// Playback rate of an animation unless told otherwise.
+const DEFAULT_ANIMATION_FPS: u32 = 24;
+
+pub const USAGE: &str = "\
+Usage: raytrace [SCENE] [OPTIONS]
+       raytrace merge <IMAGE.pfm|exr|hdr|ppm>...
+       raytrace diff <A.ppm> <B.ppm>
+       raytrace scene-diff <A.json> <B.json>
+       raytrace analyze <IMAGE.ppm>
+       raytrace list <scenes|materials|textures|media>
+       raytrace convergence [SCENE] [OPTIONS] [--max-samples <N>] [--reference <IMAGE.ppm>]
+       raytrace trace-pixel [SCENE] [OPTIONS] --pixel <X,Y> [--sample <N>]
+       raytrace bake [SCENE] [OPTIONS] --object <INDEX> [--bake <lighting|occlusion|albedo>] [--size <N>]
+       raytrace sweep [SCENE] [OPTIONS] --sweep <KEY=START..END:STEPS>... [--contact-sheet <FILE.ppm>]
+       raytrace turntable [SCENE] [OPTIONS] [--frames <N>] [--apng <FILE.png>] [--fps <N>]
+       raytrace simulate bouncing_spheres [OPTIONS] [--frames <N>] [--apng <FILE.png>] [--fps <N>]
+
+Scenes:
+    checkered_spheres (default), bouncing_spheres, lit_spheres, furnace, many_lights, night,
+    softbox, glowing_spheres, city, smoke, stained_glass, or a scene file ending in .json
+
+Options:
+    --scene <NAME|FILE.json>        The scene to render, as an alternative to naming it first
+    --width <N>                     Image width in pixels, keeping the scene's aspect ratio
+                                    [default: set by the scene]
+    --samples <N>                   Samples per pixel [default: set by the scene]
+    --adaptive <TOLERANCE>          Stop sampling a pixel once its brightness is known to within
+                                    TOLERANCE, e.g. 0.05 for 5%, taking --samples at most
+    --min-samples <N>               Samples every pixel takes before --adaptive may stop it
+                                    [default: 16]
+    --sampling <independent|cmj>    How pixel and lens samples are placed [default: cmj]
+    --pass <beauty|material-id|bvh-cost|time>
+                                    What to render into each pixel [default: beauty]
+    --renderer <megakernel|wavefront>
+                                    Trace each pixel in turn, or all pixels a bounce at a
+                                    time for the beauty pass [default: megakernel]
+    --lights <all|tree>             Sample every light, or one picked from a light tree
+                                    [default: set by the scene]
+    --units <mm|cm|m|km|in|ft>      Length of one scene unit [default: m]
+    --placement <grid|poisson|golden>
+                                    Spread generated objects on a jittered grid, randomly
+                                    without overlaps, or on a golden-angle spiral [default: grid]
+    --mesh <FILE.obj>               Replace the glass sphere at the center of bouncing_spheres
+                                    with the mesh in FILE, scaled to the same size
+    --mesh-up <y|z>                 Axis pointing up in the mesh file [default: y]
+    --mesh-left-handed              Read the mesh file's axes as left-handed, mirroring it
+    --display <gamma2|srgb|rec709|p3|agx>
+                                    How the image is encoded for the screen; agx rolls
+                                    highlights off filmically [default: gamma2]
+    --lut <FILE.cube>               Apply a 1D or 3D lookup table, such as a film-stock look,
+                                    to the image after the display transform
+    --format <ppm|pam|pfm|png|exr|hdr>
+                                    Image format written to stdout: pam adds an alpha channel
+                                    of surface coverage, pfm, exr and hdr keep linear HDR
+                                    values, exr with alpha, png is compressed [default: ppm]
+    --output <FILE>                 Write the image to FILE instead of stdout, in the format
+                                    its extension names
+    --progressive <N>               Render N samples per pixel at a time, rewriting --output
+                                    after each pass so it can be previewed while it refines
+    --preview-every <SECONDS>       Rewrite --output with the tiles finished so far every
+                                    SECONDS while rendering, so a long render can be watched
+                                    by refreshing the file
+    --highlight-rolloff <LUMINANCE|PERCENTILE%>
+                                    Compress highlights above a linear luminance, or above
+                                    the brightness of that percentile of pixels, into a soft
+                                    shoulder instead of clipping them to white
+    --supersample <N>               Render at N times the width and height and filter the image
+                                    down, smoothing edges without more samples per pixel
+                                    [default: 1]
+    --regularize <ROUGHNESS>        Blur mirror and glass bounces after the first by a roughness
+                                    from 0 to 1, so caustics converge at the cost of some bias
+    --ods <IPD>                     Render an over-under omnidirectional stereo panorama for VR
+                                    video, with the eyes IPD meters apart, e.g. 0.064
+    --tilt <TILT,SWING>             Tip the plane of focus away at the top and right by
+                                    these degrees, for depth of field scenes
+    --lens-shift <X,Y>              Slide the view by fractions of its width and height
+                                    without turning the camera
+    --scene-seed <N>                Recreate the random arrangement of an earlier render, whose
+                                    seed is logged and stored in its image [default: random]
+    --seed <N>                      Draw every pixel's random numbers from N, so renders repeat
+                                    exactly on any number of threads [default: random]
+    --set <KEY=VALUE>               Override a scene file parameter by its path, e.g.
+                                    camera.vertical_fov=35, materials.glass.refraction_index=1.45
+                                    or objects.2.radius=0.5; built-in scenes take only camera
+                                    settings; may be repeated
+    --clay                          Render every surface as neutral grey clay, keeping the lights
+    --frame                         Move the camera back until the whole scene is in view
+    --cameras <all|NAME,...>        Render the scene's named cameras, building it only once,
+                                    each to SCENE-NAME.ppm instead of stdout
+    --path-guiding                  Learn where light comes from and steer diffuse bounces to it
+    --transparent-shadows           Let shadow rays through glass, colored by it, so light
+                                    through stained glass shows without waiting for caustics
+                                    [default: set by the scene]
+    --self-hit <off|flat|all>       Make scattered rays skip the primitive they leave instead
+                                    of starting just off it; flat covers quads and triangles,
+                                    all adds spheres [default: off]
+    --keep-degenerate               Report degenerate objects but render them anyway
+    --nan-guard                     Replace NaN/infinite radiance with black and log its source
+    --threads <N>                   Render on N threads [default: one per core]
+    --tile-size <N>                 Render the image in N×N pixel tiles, one per thread at a
+                                    time [default: 16]
+    --tile-budget <SECONDS>         Stop sampling each tile after SECONDS, finishing the
+                                    round of samples underway, so render time is predictable
+    --output-dir <DIR>              Where named cameras, sweeps and turntables write their
+                                    images, created if missing [default: .]
+    --report <FILE|->               Write a JSON report of a render, sweep or turntable: its
+                                    settings, timings, and each image's statistics and hash.
+                                    - is stdout, when the images go to files
+    --progress-webhook <URL>        POST render progress and the time left as JSON to an
+                                    http:// URL every few seconds, to follow a headless
+                                    render remotely
+    --watch <FILE>                  Re-render a quick preview to SCENE-preview.ppm whenever FILE
+                                    or the JSON scene changes. FILE holds options like
+                                    raytracing.toml, applied over the command line; the BVH
+                                    is kept when only the camera changed
+
+Defaults for these options can be set in raytracing.toml in the working directory, or
+the file named by RAYTRACE_CONFIG, as KEY = VALUE lines such as display = \"agx\", and
+in RAYTRACE_* environment variables such as RAYTRACE_THREADS=4. The environment
+overrides the file, and the command line both.
+
+Commands:
+    merge    Average independent renders of a scene, weighted by sample count, to stdout in
+             the format of the first. Merge linear PFM, EXR or HDR renders; PPM renders
+             are refused if their highlights were clipped at white
+    diff     Report RMSE and SSIM between two renders and write a difference heatmap to stdout
+    scene-diff
+             Print where two scene files differ in their objects, materials and camera, as
+             built rather than as written, one difference per line
+    analyze  Print a luminance histogram and write a false-color exposure map to stdout
+    list     Print the built-in scenes, the kinds of material or texture and their
+             parameters, or the named media presets
+    convergence
+             Render at 1, 2, 4, ... samples per pixel up to --max-samples [default: 64] and
+             write CSV of the error against the reference to stdout. Without --reference
+             the reference is rendered at four times the maximum sample count
+    trace-pixel
+             Trace one sample of a pixel and print every bounce of its path
+    bake     Render the lighting, ambient occlusion or albedo on the scene's object number
+             INDEX into its texture space and write the texture to stdout [default size: 512]
+    sweep    Render every combination of the swept --set parameters, e.g.
+             materials.steel.roughness=0..1:11, each to SCENE-KEY=VALUE.ppm, and optionally tile
+             them into a contact sheet with a row per value of the first sweep
+    turntable
+             Render frames circling the camera once around the point it looks at, each to
+             SCENE-turntable-NNN.ppm, and optionally assemble them into a looping animated
+             PNG [default: 36 frames at 24 fps]
+    simulate Drop the small spheres of bouncing_spheres and render them bouncing off the
+             ground, the large spheres and each other, a frame every 1/FPS seconds with
+             motion blur, each to SCENE-simulate-NNN.ppm, and optionally assemble them into
+             an animated PNG [default: 36 frames at 24 fps]";
+
+/// What the binary has been asked to do.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Render a built-in scene as PPM to stdout
+    Render(RenderOptions),
+    /// Merge renders of the same scene into one image on stdout
+    Merge(Vec<String>),
+    /// Compare two PPM renders, writing a heatmap to stdout
+    Diff(String, String),
+    /// Compare two scene files, printing their differences
+    SceneDiff(String, String),
+    /// Report the exposure of a PPM render, writing a false-color map to stdout
+    Analyze(String),
+    /// Print what is available of one kind of thing
+    List(Listing),
+    /// Measure how quickly a scene converges, writing CSV to stdout
+    Convergence(ConvergenceOptions),
+    /// Bake an object's lighting into a texture on stdout
+    Bake(BakeOptions),
+    /// Print the path followed by one sample of a pixel
+    TracePixel(TracePixelOptions),
+    /// Render a grid of parameter values, each to its own file
+    Sweep(SweepOptions),
+    /// Render frames circling the scene, optionally as an animation
+    Turntable(AnimationOptions),
+    /// Render frames of a scene's objects moved by a physics simulation
+    Simulate(AnimationOptions),
+}
+
+/// What `list` prints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Listing {
+    Scenes,
+    Materials,
+    Textures,
+    Media,
+}
+
+/// Which of a scene's named cameras to render.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ViewSelection {
+    All,
+    Named(Vec<String>),
+}
+
+/// Options for rendering a scene.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderOptions {
+    pub scene: String,
+    /// Overrides the scene's image width
+    pub width: Option<u32>,
+    /// Overrides the scene's samples per pixel
+    pub samples: Option<u32>,
+    /// Relative error at which pixels stop sampling, if sampling adaptively
+    pub adaptive: Option<f64>,
+    /// Samples every pixel takes before adaptive sampling may stop it
+    pub min_samples: Option<u32>,
+    pub pixel_sampling: PixelSampling,
+    pub pass: RenderPass,
+    pub renderer: Renderer,
+    /// Overrides the scene's choice of light sampling
+    pub light_sampling: Option<LightSampling>,
+    pub path_guiding: bool,
+    /// Let shadow rays through transparent surfaces, even if the scene does not
+    pub transparent_shadows: bool,
+    pub self_hit_exclusion: SelfHitExclusion,
+    /// Reposition the camera to fit the whole scene in view
+    pub frame: bool,
+    pub units: Units,
+    /// How generated scenes spread their objects
+    pub placement: Placement,
+    /// OBJ file placed in the scene in place of its centerpiece
+    pub mesh: Option<String>,
+    /// Axis convention the OBJ file was written in
+    pub mesh_axes: AxisConvention,
+    pub display: DisplayTransform,
+    /// `.cube` lookup table applied after the display transform
+    pub lut: Option<String>,
+    /// Format of the image written to stdout
+    pub format: ImageFormat,
+    /// File to write the image to instead of stdout, its format set by its extension
+    pub output: Option<String>,
+    pub highlight_rolloff: Option<RolloffStart>,
+    /// How many times the output resolution to render along each axis
+    pub supersample: u32,
+    /// Roughness specular bounces after the first are blurred to, if any
+    pub regularization: Option<f64>,
+    pub projection: Projection,
+    /// Named cameras to render to files instead of the scene's own camera
+    pub views: Option<ViewSelection>,
+    /// Scene parameters changed from the command line, applied in order
+    pub overrides: Vec<Override>,
+    /// Tilt and swing of the plane of focus, in degrees
+    pub tilt: (f64, f64),
+    /// Lens shift as fractions of the view's width and height
+    pub lens_shift: (f64, f64),
+    /// Seed for the scene's random arrangement, picked at random when not given
+    pub scene_seed: Option<u64>,
+    /// Seed for the render's random numbers, so renders repeat exactly
+    pub seed: Option<u64>,
+    /// Shade every surface with neutral clay instead of its own material
+    pub clay: bool,
+    /// Keep objects that would produce NaN hit records instead of dropping them
+    pub keep_degenerate: bool,
+    /// Replace non-finite radiance with black and log where it came from
+    pub nan_guard: bool,
+    /// Threads to render on, or one per core
+    pub threads: Option<usize>,
+    /// Width and height of the tiles rendered on one thread, or the camera's default
+    pub tile_size: Option<u32>,
+    /// Time each tile may spend sampling, if limited
+    pub tile_budget: Option<Duration>,
+    /// Samples per pixel of each pass of a progressive render, if rendering progressively
+    pub progressive: Option<u32>,
+    /// How often to rewrite the output with the tiles finished so far, if at all
+    pub preview_every: Option<Duration>,
+    /// Directory that generated image files are written to
+    pub output_dir: Option<String>,
+    /// Where to write a JSON report of the run, `-` for stdout
+    pub report: Option<String>,
+    /// URL that render progress is posted to, if any
+    pub progress_webhook: Option<String>,
+    /// Options file to re-render a preview from whenever it changes
+    pub watch: Option<String>,
+}
+
+/// Options for measuring convergence against a reference image.
+#[derive(Debug, PartialEq)]
+pub struct ConvergenceOptions {
+    pub render: RenderOptions,
+    /// Highest sample count rendered; counts double from 1 up to this
+    pub max_samples: u32,
+    /// Reference image to compare against instead of rendering one
+    pub reference: Option<String>,
+}
+
+/// Options for baking an object's lighting into its texture space.
+#[derive(Debug, PartialEq)]
+pub struct BakeOptions {
+    pub render: RenderOptions,
+    /// Index of the object in the scene's object list
+    pub object: usize,
+    pub mode: BakeMode,
+    /// Width and height of the texture in texels
+    pub size: u32,
+}
+
+/// Options for tracing a single camera sample.
+#[derive(Debug, PartialEq)]
+pub struct TracePixelOptions {
+    pub render: RenderOptions,
+    pub pixel: (u32, u32),
+    pub sample: u32,
+}
+
+/// Options for rendering a scene over a grid of parameter values.
+#[derive(Debug, PartialEq)]
+pub struct SweepOptions {
+    pub render: RenderOptions,
+    pub sweeps: Vec<Sweep>,
+    /// Where to write every render tiled into one image, if anywhere
+    pub contact_sheet: Option<String>,
+}
+
+/// Options for rendering an animation, a turntable or a simulation.
+#[derive(Debug, PartialEq)]
+pub struct AnimationOptions {
+    pub render: RenderOptions,
+    /// Frames in one full revolution of a turntable, or in a simulation
+    pub frames: u32,
+    /// Where to write the frames as an animated PNG, if anywhere
+    pub apng: Option<String>,
+    /// Frames per second the animation plays at
+    pub fps: u32,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            scene: "checkered_spheres".to_string(),
+            width: None,
+            samples: None,
+            adaptive: None,
+            min_samples: None,
+            pixel_sampling: PixelSampling::default(),
+            pass: RenderPass::default(),
+            renderer: Renderer::default(),
+            light_sampling: None,
+            path_guiding: false,
+            transparent_shadows: false,
+            self_hit_exclusion: SelfHitExclusion::default(),
+            frame: false,
+            units: Units::default(),
+            placement: Placement::default(),
+            mesh: None,
+            mesh_axes: AxisConvention::default(),
+            display: DisplayTransform::default(),
+            lut: None,
+            format: ImageFormat::default(),
+            output: None,
+            highlight_rolloff: None,
+            supersample: 1,
+            regularization: None,
+            projection: Projection::default(),
+            views: None,
+            overrides: Vec::new(),
+            tilt: (0.0, 0.0),
+            lens_shift: (0.0, 0.0),
+            scene_seed: None,
+            seed: None,
+            clay: false,
+            keep_degenerate: false,
+            nan_guard: false,
+            threads: None,
+            tile_size: None,
+            tile_budget: None,
+            progressive: None,
+            preview_every: None,
+            output_dir: None,
+            report: None,
+            progress_webhook: None,
+            watch: None,
+        }
+    }
+}
+
+/// Parses the arguments following the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+    let subcommand = args.peek().cloned();
+    match subcommand.as_deref() {
+        Some("merge") => parse_merge(args.skip(1)),
+        Some("diff") => parse_diff(args.skip(1)),
+        Some("scene-diff") => parse_scene_diff(args.skip(1)),
+        Some("analyze") => parse_analyze(args.skip(1)),
+        Some("list") => parse_list(args.skip(1)),
+        Some("convergence") => parse_convergence(args.skip(1)),
+        Some("bake") => parse_bake(args.skip(1)),
+        Some("trace-pixel") => parse_trace_pixel(args.skip(1)),
+        Some("sweep") => parse_sweep(args.skip(1)),
+        Some("turntable") => parse_animation(args.skip(1)).map(Command::Turntable),
+        Some("simulate") => parse_animation(args.skip(1)).map(Command::Simulate),
+        _ => parse_render(args).map(Command::Render),
+    }
+}
+
+/// Parses the command line with `defaults` from the config file and environment
+/// given ahead of its own options, so the command line wins.
+///
+/// Commands that don't render a scene take no defaults.
+pub fn parse_with_defaults(
+    args: impl IntoIterator<Item = String>,
+    defaults: &[String],
+) -> Result<Command, String> {
+    let mut args: Vec<String> = args.into_iter().collect();
+    let start = match args.first().map(String::as_str) {
+        Some("merge" | "diff" | "scene-diff" | "analyze" | "list") => return parse(args),
+        Some("convergence" | "bake" | "trace-pixel" | "sweep" | "turntable" | "simulate") => 1,
+        _ => 0,
+    };
+    args.splice(start..start, defaults.iter().cloned());
+    parse(args)
+}
+
+impl Command {
+    /// The options for rendering the scene, for commands that render one.
+    pub fn render_options(&self) -> Option<&RenderOptions> {
+        match self {
+            Command::Render(options) => Some(options),
+            Command::Convergence(options) => Some(&options.render),
+            Command::Bake(options) => Some(&options.render),
+            Command::TracePixel(options) => Some(&options.render),
+            Command::Sweep(options) => Some(&options.render),
+            Command::Turntable(options) | Command::Simulate(options) => Some(&options.render),
+            Command::Merge(_)
+            | Command::Diff(..)
+            | Command::SceneDiff(..)
+            | Command::Analyze(_)
+            | Command::List(_) => None,
+        }
+    }
+}
+
+fn parse_render(args: impl Iterator<Item = String>) -> Result<RenderOptions, String> {
+    apply_render_args(RenderOptions::default(), args)
+}
+
+/// Applies render options given as command-line arguments on top of `options`.
+pub fn apply_render_args(
+    mut options: RenderOptions,
+    mut args: impl Iterator<Item = String>,
+) -> Result<RenderOptions, String> {
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scene" => {
+                options.scene = args.next().ok_or("--scene requires a name or file")?;
+            }
+            "--width" => {
+                let value = args.next().ok_or("--width requires a value")?;
+                options.width = match value.parse() {
+                    Ok(width) if width > 0 => Some(width),
+                    _ => return Err(format!("invalid width '{}'", value)),
+                };
+            }
+            "--samples" => {
+                let value = args.next().ok_or("--samples requires a value")?;
+                options.samples = match value.parse() {
+                    Ok(samples) if samples > 0 => Some(samples),
+                    _ => return Err(format!("invalid sample count '{}'", value)),
+                };
+            }
+            "--adaptive" => {
+                let value = args.next().ok_or("--adaptive requires a tolerance")?;
+                options.adaptive = match value.parse::<f64>() {
+                    Ok(tolerance) if tolerance > 0.0 && tolerance.is_finite() => Some(tolerance),
+                    _ => return Err(format!("invalid tolerance '{}'", value)),
+                };
+            }
+            "--min-samples" => {
+                let value = args.next().ok_or("--min-samples requires a value")?;
+                options.min_samples = match value.parse() {
+                    Ok(samples) if samples > 0 => Some(samples),
+                    _ => return Err(format!("invalid sample count '{}'", value)),
+                };
+            }
+            "--sampling" => {
+                let value = args.next().ok_or("--sampling requires a value")?;
+                options.pixel_sampling = match value.as_str() {
+                    "independent" => PixelSampling::Independent,
+                    "cmj" => PixelSampling::CorrelatedMultiJittered,
+                    _ => return Err(format!("unknown sampling '{}'", value)),
+                };
+            }
+            "--pass" => {
+                let value = args.next().ok_or("--pass requires a value")?;
+                options.pass = match value.as_str() {
+                    "beauty" => RenderPass::Beauty,
+                    "material-id" => RenderPass::MaterialId,
+                    "bvh-cost" => RenderPass::BvhCost,
+                    "time" => RenderPass::Time,
+                    _ => return Err(format!("unknown pass '{}'", value)),
+                };
+            }
+            "--renderer" => {
+                let value = args.next().ok_or("--renderer requires a value")?;
+                options.renderer = match value.as_str() {
+                    "megakernel" => Renderer::Megakernel,
+                    "wavefront" => Renderer::Wavefront,
+                    _ => return Err(format!("unknown renderer '{}'", value)),
+                };
+            }
+            "--lights" => {
+                let value = args.next().ok_or("--lights requires a value")?;
+                options.light_sampling = match value.as_str() {
+                    "all" => Some(LightSampling::All),
+                    "tree" => Some(LightSampling::Tree),
+                    _ => return Err(format!("unknown light sampling '{}'", value)),
+                };
+            }
+            "--path-guiding" => options.path_guiding = true,
+            "--transparent-shadows" => options.transparent_shadows = true,
+            "--self-hit" => {
+                let value = args.next().ok_or("--self-hit requires a value")?;
+                options.self_hit_exclusion = match value.as_str() {
+                    "off" => SelfHitExclusion::Off,
+                    "flat" => SelfHitExclusion::Flat,
+                    "all" => SelfHitExclusion::All,
+                    _ => return Err(format!("unknown self-hit exclusion '{}'", value)),
+                };
+            }
+            "--frame" => options.frame = true,
+            "--units" => {
+                let value = args.next().ok_or("--units requires a value")?;
+                options.units = match value.as_str() {
+                    "mm" => Units::Millimeters,
+                    "cm" => Units::Centimeters,
+                    "m" => Units::Meters,
+                    "km" => Units::Kilometers,
+                    "in" => Units::Inches,
+                    "ft" => Units::Feet,
+                    _ => return Err(format!("unknown units '{}'", value)),
+                };
+            }
+            "--placement" => {
+                let value = args.next().ok_or("--placement requires a value")?;
+                options.placement = match value.as_str() {
+                    "grid" => Placement::JitteredGrid,
+                    "poisson" => Placement::PoissonDisk,
+                    "golden" => Placement::GoldenSpiral,
+                    _ => return Err(format!("unknown placement '{}'", value)),
+                };
+            }
+            "--display" => {
+                let value = args.next().ok_or("--display requires a value")?;
+                options.display = DisplayTransform::from_name(&value)
+                    .ok_or_else(|| format!("unknown display transform '{}'", value))?;
+            }
+            "--lut" => {
+                options.lut = Some(args.next().ok_or("--lut requires a file")?);
+            }
+            "--format" => {
+                let value = args.next().ok_or("--format requires a value")?;
+                options.format = ImageFormat::from_name(&value)
+                    .ok_or_else(|| format!("unknown image format '{}'", value))?;
+            }
+            "--highlight-rolloff" => {
+                let value = args.next().ok_or("--highlight-rolloff requires a value")?;
+                let invalid = || format!("invalid highlight rolloff start '{}'", value);
+                options.highlight_rolloff = Some(match value.strip_suffix('%') {
+                    Some(percentile) => match percentile.parse() {
+                        Ok(p) if (0.0..=100.0).contains(&p) => RolloffStart::Percentile(p),
+                        _ => return Err(invalid()),
+                    },
+                    None => match value.parse() {
+                        Ok(l) if l >= 0.0 => RolloffStart::Luminance(l),
+                        _ => return Err(invalid()),
+                    },
+                });
+            }
+            "--supersample" => {
+                let value = args.next().ok_or("--supersample requires a value")?;
+                options.supersample = match value.parse() {
+                    Ok(factor) if factor > 0 => factor,
+                    _ => return Err(format!("invalid supersample factor '{}'", value)),
+                };
+            }
+            "--regularize" => {
+                let value = args.next().ok_or("--regularize requires a value")?;
+                options.regularization = match value.parse() {
+                    Ok(roughness) if (0.0..=1.0).contains(&roughness) => Some(roughness),
+                    _ => return Err(format!("invalid regularization roughness '{}'", value)),
+                };
+            }
+            "--ods" => {
+                let value = args.next().ok_or("--ods requires a value")?;
+                options.projection = match value.parse() {
+                    Ok(ipd) if ipd >= 0.0 => Projection::OmniStereo { ipd },
+                    _ => return Err(format!("invalid interpupillary distance '{}'", value)),
+                };
+            }
+            "--tilt" => {
+                let value = args.next().ok_or("--tilt requires a value")?;
+                options.tilt = parse_pair(&value).ok_or(format!("invalid tilt '{}'", value))?;
+            }
+            "--lens-shift" => {
+                let value = args.next().ok_or("--lens-shift requires a value")?;
+                options.lens_shift =
+                    parse_pair(&value).ok_or(format!("invalid lens shift '{}'", value))?;
+            }
+            "--seed" => {
+                let value = args.next().ok_or("--seed requires a value")?;
+                options.seed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid seed '{}'", value))?,
+                );
+            }
+            "--scene-seed" => {
+                let value = args.next().ok_or("--scene-seed requires a value")?;
+                options.scene_seed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid scene seed '{}'", value))?,
+                );
+            }
+            "--cameras" => {
+                let value = args.next().ok_or("--cameras requires a value")?;
+                options.views = Some(match value.as_str() {
+                    "all" => ViewSelection::All,
+                    _ if value.split(',').any(str::is_empty) => {
+                        return Err(format!("invalid camera list '{}'", value));
+                    }
+                    _ => ViewSelection::Named(value.split(',').map(String::from).collect()),
+                });
+            }
+            "--set" => {
+                let value = args.next().ok_or("--set requires a value")?;
+                options.overrides.push(value.parse()?);
+            }
+            "--clay" => options.clay = true,
+            "--keep-degenerate" => options.keep_degenerate = true,
+            "--nan-guard" => options.nan_guard = true,
+            "--threads" => {
+                let value = args.next().ok_or("--threads requires a value")?;
+                options.threads = match value.parse() {
+                    Ok(threads) if threads > 0 => Some(threads),
+                    _ => return Err(format!("invalid thread count '{}'", value)),
+                };
+            }
+            "--tile-size" => {
+                let value = args.next().ok_or("--tile-size requires a value")?;
+                options.tile_size = match value.parse() {
+                    Ok(size) if size > 0 => Some(size),
+                    _ => return Err(format!("invalid tile size '{}'", value)),
+                };
+            }
+            "--tile-budget" => {
+                let value = args.next().ok_or("--tile-budget requires a value")?;
+                options.tile_budget = match value.parse::<f64>() {
+                    Ok(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                        Some(Duration::from_secs_f64(seconds))
+                    }
+                    _ => return Err(format!("invalid tile budget '{}'", value)),
+                };
+            }
+            "--progressive" => {
+                let value = args.next().ok_or("--progressive requires a value")?;
+                options.progressive = match value.parse() {
+                    Ok(samples) if samples > 0 => Some(samples),
+                    _ => return Err(format!("invalid samples per pass '{}'", value)),
+                };
+            }
+            "--preview-every" => {
+                let value = args.next().ok_or("--preview-every requires a value")?;
+                options.preview_every = match value.parse::<f64>() {
+                    Ok(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                        Some(Duration::from_secs_f64(seconds))
+                    }
+                    _ => return Err(format!("invalid preview interval '{}'", value)),
+                };
+            }
+            "--watch" => {
+                options.watch = Some(args.next().ok_or("--watch requires a file")?);
+            }
+            "--report" => {
+                options.report = Some(args.next().ok_or("--report requires a file")?);
+            }
+            "--progress-webhook" => {
+                options.progress_webhook =
+                    Some(args.next().ok_or("--progress-webhook requires a URL")?);
+            }
+            "--output" => {
+                let value = args.next().ok_or("--output requires a file")?;
+                if ImageFormat::from_path(&value).is_none() {
+                    return Err(format!("unknown image format for '{}'", value));
+                }
+                options.output = Some(value);
+            }
+            "--mesh" => {
+                options.mesh = Some(args.next().ok_or("--mesh requires a file")?);
+            }
+            "--mesh-up" => {
+                let value = args.next().ok_or("--mesh-up requires an axis")?;
+                options.mesh_axes.up = match value.as_str() {
+                    "y" => UpAxis::Y,
+                    "z" => UpAxis::Z,
+                    _ => return Err(format!("unknown up axis '{}'", value)),
+                };
+            }
+            "--mesh-left-handed" => options.mesh_axes.handedness = Handedness::Left,
+            "--output-dir" => {
+                options.output_dir = Some(args.next().ok_or("--output-dir requires a directory")?);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg)),
+            _ => options.scene = arg,
+        }
+    }
+
+    Ok(options)
+}
+
+/// Parses two comma-separated numbers, such as `1.5,-2`.
+fn parse_pair<T: std::str::FromStr>(value: &str) -> Option<(T, T)> {
+    let (a, b) = value.split_once(',')?;
+    Some((a.parse().ok()?, b.parse().ok()?))
+}
+
+fn parse_convergence(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut max_samples = DEFAULT_CONVERGENCE_SAMPLES;
+    let mut reference = None;
+    let mut render_args = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-samples" => {
+                let value = args.next().ok_or("--max-samples requires a value")?;
+                max_samples = match value.parse() {
+                    Ok(samples) if samples > 0 => samples,
+                    _ => return Err(format!("invalid sample count '{}'", value)),
+                };
+            }
+            "--reference" => {
+                reference = Some(args.next().ok_or("--reference requires an image")?);
+            }
+            _ => render_args.push(arg),
+        }
+    }
+
+    Ok(Command::Convergence(ConvergenceOptions {
+        render: parse_render(render_args.into_iter())?,
+        max_samples,
+        reference,
+    }))
+}
+
+fn parse_bake(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut object = None;
+    let mut mode = BakeMode::default();
+    let mut size = DEFAULT_BAKE_SIZE;
+    let mut render_args = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--object" => {
+                let value = args.next().ok_or("--object requires an index")?;
+                object = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid object index '{}'", value))?,
+                );
+            }
+            "--bake" => {
+                let value = args.next().ok_or("--bake requires a value")?;
+                mode = match value.as_str() {
+                    "lighting" => BakeMode::Lighting,
+                    "occlusion" => BakeMode::Occlusion,
+                    "albedo" => BakeMode::Albedo,
+                    _ => return Err(format!("unknown bake mode '{}'", value)),
+                };
+            }
+            "--size" => {
+                let value = args.next().ok_or("--size requires a value")?;
+                size = match value.parse() {
+                    Ok(size) if size > 0 => size,
+                    _ => return Err(format!("invalid texture size '{}'", value)),
+                };
+            }
+            _ => render_args.push(arg),
+        }
+    }
+
+    Ok(Command::Bake(BakeOptions {
+        render: parse_render(render_args.into_iter())?,
+        object: object.ok_or("bake requires --object")?,
+        mode,
+        size,
+    }))
+}
+
+fn parse_trace_pixel(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut pixel = None;
+    let mut sample = 0;
+    let mut render_args = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--pixel" => {
+                let value = args.next().ok_or("--pixel requires a value")?;
+                pixel = Some(parse_pair(&value).ok_or(format!("invalid pixel '{}'", value))?);
+            }
+            "--sample" => {
+                let value = args.next().ok_or("--sample requires a value")?;
+                sample = value
+                    .parse()
+                    .map_err(|_| format!("invalid sample '{}'", value))?;
+            }
+            _ => render_args.push(arg),
+        }
+    }
+
+    Ok(Command::TracePixel(TracePixelOptions {
+        render: parse_render(render_args.into_iter())?,
+        pixel: pixel.ok_or("trace-pixel requires --pixel")?,
+        sample,
+    }))
+}
+
+fn parse_animation(mut args: impl Iterator<Item = String>) -> Result<AnimationOptions, String> {
+    let mut frames = DEFAULT_ANIMATION_FRAMES;
+    let mut apng = None;
+    let mut fps = DEFAULT_ANIMATION_FPS;
+    let mut render_args = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => {
+                let value = args.next().ok_or("--frames requires a value")?;
+                frames = match value.parse() {
+                    Ok(frames) if frames > 0 => frames,
+                    _ => return Err(format!("invalid frame count '{}'", value)),
+                };
+            }
+            "--apng" => {
+                apng = Some(args.next().ok_or("--apng requires an image")?);
+            }
+            "--fps" => {
+                let value = args.next().ok_or("--fps requires a value")?;
+                fps = match value.parse() {
+                    Ok(fps) if (1..=u16::MAX as u32).contains(&fps) => fps,
+                    _ => return Err(format!("invalid frame rate '{}'", value)),
+                };
+            }
+            _ => render_args.push(arg),
+        }
+    }
+
+    Ok(AnimationOptions {
+        render: parse_render(render_args.into_iter())?,
+        frames,
+        apng,
+        fps,
+    })
+}
+
+fn parse_sweep(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut sweeps = Vec::new();
+    let mut contact_sheet = None;
+    let mut render_args = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sweep" => {
+                let value = args.next().ok_or("--sweep requires a value")?;
+                sweeps.push(value.parse()?);
+            }
+            "--contact-sheet" => {
+                contact_sheet = Some(args.next().ok_or("--contact-sheet requires an image")?);
+            }
+            _ => render_args.push(arg),
+        }
+    }
+    if sweeps.is_empty() {
+        return Err("sweep requires --sweep".to_string());
+    }
+
+    Ok(Command::Sweep(SweepOptions {
+        render: parse_render(render_args.into_iter())?,
+        sweeps,
+        contact_sheet,
+    }))
+}
+
+fn parse_merge(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let inputs: Vec<String> = args.collect();
+    if let Some(option) = inputs.iter().find(|arg| arg.starts_with("--")) {
+        return Err(format!("unknown option '{}'", option));
+    }
+    if inputs.is_empty() {
+        return Err("merge requires at least one image".to_string());
+    }
+    Ok(Command::Merge(inputs))
+}
+
+fn parse_diff(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let inputs: Vec<String> = args.collect();
+    if let Some(option) = inputs.iter().find(|arg| arg.starts_with("--")) {
+        return Err(format!("unknown option '{}'", option));
+    }
+    match <[String; 2]>::try_from(inputs) {
+        Ok([a, b]) => Ok(Command::Diff(a, b)),
+        Err(_) => Err("diff requires exactly two images".to_string()),
+    }
+}
+
+fn parse_scene_diff(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let inputs: Vec<String> = args.collect();
+    if let Some(option) = inputs.iter().find(|arg| arg.starts_with("--")) {
+        return Err(format!("unknown option '{}'", option));
+    }
+    match <[String; 2]>::try_from(inputs) {
+        Ok([a, b]) => Ok(Command::SceneDiff(a, b)),
+        Err(_) => Err("scene-diff requires exactly two scene files".to_string()),
+    }
+}
+
+fn parse_analyze(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let inputs: Vec<String> = args.collect();
+    if let Some(option) = inputs.iter().find(|arg| arg.starts_with("--")) {
+        return Err(format!("unknown option '{}'", option));
+    }
+    match <[String; 1]>::try_from(inputs) {
+        Ok([image]) => Ok(Command::Analyze(image)),
+        Err(_) => Err("analyze requires exactly one image".to_string()),
+    }
+}
+
+fn parse_list(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let inputs: Vec<String> = args.collect();
+    match <[String; 1]>::try_from(inputs)
+        .as_ref()
+        .map(|[kind]| kind.as_str())
+    {
+        Ok("scenes") => Ok(Command::List(Listing::Scenes)),
+        Ok("materials") => Ok(Command::List(Listing::Materials)),
+        Ok("textures") => Ok(Command::List(Listing::Textures)),
+        Ok("media") => Ok(Command::List(Listing::Media)),
+        Ok(kind) => Err(format!("cannot list '{}'", kind)),
+        Err(_) => Err("list requires one of scenes, materials, textures or media".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        assert_eq!(
+            parse(args(&[])),
+            Ok(Command::Render(RenderOptions::default()))
+        );
+    }
+
+    #[test]
+    fn test_parse_scene_and_sampling() {
+        assert_eq!(
+            parse(args(&[
+                "bouncing_spheres",
+                "--sampling",
+                "independent",
+                "--keep-degenerate",
+                "--nan-guard",
+                "--path-guiding",
+                "--transparent-shadows",
+                "--self-hit",
+                "flat",
+                "--frame"
+            ])),
+            Ok(Command::Render(RenderOptions {
+                scene: "bouncing_spheres".to_string(),
+                pixel_sampling: PixelSampling::Independent,
+                keep_degenerate: true,
+                nan_guard: true,
+                path_guiding: true,
+                transparent_shadows: true,
+                self_hit_exclusion: SelfHitExclusion::Flat,
+                frame: true,
+                ..RenderOptions::default()
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_merge() {
+        assert_eq!(
+            parse(args(&["merge", "a.ppm", "b.ppm"])),
+            Ok(Command::Merge(args(&["a.ppm", "b.ppm"])))
+        );
+        assert!(parse(args(&["merge"])).is_err());
+        assert!(parse(args(&["merge", "a.ppm", "--bogus"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_diff() {
+        assert_eq!(
+            parse(args(&["diff", "a.ppm", "b.ppm"])),
+            Ok(Command::Diff("a.ppm".to_string(), "b.ppm".to_string()))
+        );
+        assert!(parse(args(&["diff", "a.ppm"])).is_err());
+        assert!(parse(args(&["diff", "a.ppm", "b.ppm", "c.ppm"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_scene_diff() {
+        assert_eq!(
+            parse(args(&["scene-diff", "a.json", "b.json"])),
+            Ok(Command::SceneDiff(
+                "a.json".to_string(),
+                "b.json".to_string()
+            ))
+        );
+        assert!(parse(args(&["scene-diff", "a.json"])).is_err());
+        assert!(parse(args(&["scene-diff", "a.json", "--bogus"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_analyze() {
+        assert_eq!(
+            parse(args(&["analyze", "a.ppm"])),
+            Ok(Command::Analyze("a.ppm".to_string()))
+        );
+        assert!(parse(args(&["analyze"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(
+            parse(args(&["list", "materials"])),
+            Ok(Command::List(Listing::Materials))
+        );
+        assert_eq!(
+            parse(args(&["list", "media"])),
+            Ok(Command::List(Listing::Media))
+        );
+        assert!(parse(args(&["list"])).is_err());
+        assert!(parse(args(&["list", "lights"])).is_err());
+        assert!(parse(args(&["list", "scenes", "textures"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_convergence() {
+        assert_eq!(
+            parse(args(&[
+                "convergence",
+                "lit_spheres",
+                "--max-samples",
+                "16",
+                "--sampling",
+                "independent",
+                "--reference",
+                "ref.ppm"
+            ])),
+            Ok(Command::Convergence(ConvergenceOptions {
+                render: RenderOptions {
+                    scene: "lit_spheres".to_string(),
+                    pixel_sampling: PixelSampling::Independent,
+                    ..RenderOptions::default()
+                },
+                max_samples: 16,
+                reference: Some("ref.ppm".to_string()),
+            }))
+        );
+        assert!(parse(args(&["convergence", "--max-samples", "0"])).is_err());
+        assert!(parse(args(&["convergence", "--reference"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_sweep() {
+        assert_eq!(
+            parse(args(&[
+                "sweep",
+                "softbox",
+                "--sweep",
+                "camera.vertical_fov=20..40:3",
+                "--set",
+                "camera.samples_per_pixel=4",
+                "--contact-sheet",
+                "sheet.ppm"
+            ])),
+            Ok(Command::Sweep(SweepOptions {
+                render: RenderOptions {
+                    scene: "softbox".to_string(),
+                    overrides: vec![Override::new("camera.samples_per_pixel", 4)],
+                    ..RenderOptions::default()
+                },
+                sweeps: vec!["camera.vertical_fov=20..40:3".parse().unwrap()],
+                contact_sheet: Some("sheet.ppm".to_string()),
+            }))
+        );
+        assert!(parse(args(&["sweep", "softbox"])).is_err());
+        assert!(parse(args(&["sweep", "--sweep", "camera.vertical_fov=20"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_turntable() {
+        assert_eq!(
+            parse(args(&[
+                "turntable",
+                "softbox",
+                "--frames",
+                "12",
+                "--apng",
+                "spin.png"
+            ])),
+            Ok(Command::Turntable(AnimationOptions {
+                render: RenderOptions {
+                    scene: "softbox".to_string(),
+                    ..RenderOptions::default()
+                },
+                frames: 12,
+                apng: Some("spin.png".to_string()),
+                fps: DEFAULT_ANIMATION_FPS,
+            }))
+        );
+        match parse(args(&["turntable", "--fps", "30"])) {
+            Ok(Command::Turntable(options)) => {
+                assert_eq!(options.frames, DEFAULT_ANIMATION_FRAMES);
+                assert_eq!(options.apng, None);
+                assert_eq!(options.fps, 30);
+            }
+            _ => panic!("not a turntable"),
+        }
+        assert!(parse(args(&["turntable", "--frames", "0"])).is_err());
+        assert!(parse(args(&["turntable", "--fps", "0"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_simulate() {
+        assert_eq!(
+            parse(args(&["simulate", "bouncing_spheres", "--frames", "48"])),
+            Ok(Command::Simulate(AnimationOptions {
+                render: RenderOptions {
+                    scene: "bouncing_spheres".to_string(),
+                    ..RenderOptions::default()
+                },
+                frames: 48,
+                apng: None,
+                fps: DEFAULT_ANIMATION_FPS,
+            }))
+        );
+        assert!(parse(args(&["simulate", "--frames", "none"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_bake() {
+        assert_eq!(
+            parse(args(&[
+                "bake",
+                "lit_spheres",
+                "--object",
+                "2",
+                "--bake",
+                "occlusion",
+                "--size",
+                "64"
+            ])),
+            Ok(Command::Bake(BakeOptions {
+                render: RenderOptions {
+                    scene: "lit_spheres".to_string(),
+                    ..RenderOptions::default()
+                },
+                object: 2,
+                mode: BakeMode::Occlusion,
+                size: 64,
+            }))
+        );
+        assert!(parse(args(&["bake"])).is_err());
+        assert!(parse(args(&["bake", "--object", "-1"])).is_err());
+        assert!(parse(args(&["bake", "--object", "0", "--size", "0"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_trace_pixel() {
+        assert_eq!(
+            parse(args(&[
+                "trace-pixel",
+                "furnace",
+                "--pixel",
+                "10,20",
+                "--sample",
+                "3"
+            ])),
+            Ok(Command::TracePixel(TracePixelOptions {
+                render: RenderOptions {
+                    scene: "furnace".to_string(),
+                    ..RenderOptions::default()
+                },
+                pixel: (10, 20),
+                sample: 3,
+            }))
+        );
+        assert!(parse(args(&["trace-pixel"])).is_err());
+        assert!(parse(args(&["trace-pixel", "--pixel", "10"])).is_err());
+        assert!(parse(args(&["trace-pixel", "--pixel", "a,b"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_placement() {
+        assert_eq!(
+            parse(args(&["bouncing_spheres", "--placement", "poisson"])),
+            Ok(Command::Render(RenderOptions {
+                scene: "bouncing_spheres".to_string(),
+                placement: Placement::PoissonDisk,
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--placement", "scattered"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_display() {
+        assert_eq!(
+            parse(args(&["--display", "agx"])),
+            Ok(Command::Render(RenderOptions {
+                display: DisplayTransform::AgX,
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--display", "aces"])).is_err());
+        assert_eq!(
+            parse(args(&["--display", "agx", "--lut", "film.cube"])),
+            Ok(Command::Render(RenderOptions {
+                display: DisplayTransform::AgX,
+                lut: Some("film.cube".to_string()),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--lut"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_with_defaults() {
+        let defaults = args(&["--threads", "2", "--display", "agx", "--output-dir", "out"]);
+        match parse_with_defaults(
+            args(&["sweep", "--sweep", "camera.vertical_fov=20..40:3"]),
+            &defaults,
+        ) {
+            Ok(Command::Sweep(options)) => {
+                assert_eq!(options.render.threads, Some(2));
+                assert_eq!(options.render.output_dir, Some("out".to_string()));
+            }
+            _ => panic!("not a sweep"),
+        }
+        // The command line overrides the defaults
+        match parse_with_defaults(args(&["--display", "srgb"]), &defaults) {
+            Ok(command) => {
+                let options = command.render_options().unwrap();
+                assert_eq!(options.display, DisplayTransform::Srgb);
+                assert_eq!(options.threads, Some(2));
+            }
+            Err(error) => panic!("{}", error),
+        }
+        // Commands that don't render ignore them
+        assert_eq!(
+            parse_with_defaults(args(&["analyze", "a.ppm"]), &defaults),
+            Ok(Command::Analyze("a.ppm".to_string()))
+        );
+        assert!(parse(args(&["--threads", "0"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(
+            parse(args(&["--format", "pfm"])),
+            Ok(Command::Render(RenderOptions {
+                format: ImageFormat::Pfm,
+                ..RenderOptions::default()
+            }))
+        );
+        assert_eq!(
+            parse(args(&["--format", "exr"])),
+            Ok(Command::Render(RenderOptions {
+                format: ImageFormat::Exr,
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--format", "jpg"])).is_err());
+    }
+
+    #[test]
+    fn test_apply_render_args() {
+        let base = RenderOptions {
+            scene: "softbox".to_string(),
+            overrides: vec![Override::new("camera.vertical_fov", 35)],
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            apply_render_args(
+                base,
+                args(&["--set", "camera.vertical_fov=50", "--clay"]).into_iter()
+            ),
+            Ok(RenderOptions {
+                scene: "softbox".to_string(),
+                overrides: vec![
+                    Override::new("camera.vertical_fov", 35),
+                    Override::new("camera.vertical_fov", 50)
+                ],
+                clay: true,
+                ..RenderOptions::default()
+            })
+        );
+        assert_eq!(
+            parse(args(&["--watch", "preview.toml"])),
+            Ok(Command::Render(RenderOptions {
+                watch: Some("preview.toml".to_string()),
+                ..RenderOptions::default()
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_report() {
+        assert_eq!(
+            parse(args(&["--report", "report.json"])),
+            Ok(Command::Render(RenderOptions {
+                report: Some("report.json".to_string()),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--report"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_progress_webhook() {
+        assert_eq!(
+            parse(args(&[
+                "--progress-webhook",
+                "http://phone.local:8000/render"
+            ])),
+            Ok(Command::Render(RenderOptions {
+                progress_webhook: Some("http://phone.local:8000/render".to_string()),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--progress-webhook"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_output() {
+        assert_eq!(
+            parse(args(&["--output", "render.png"])),
+            Ok(Command::Render(RenderOptions {
+                output: Some("render.png".to_string()),
+                ..RenderOptions::default()
+            }))
+        );
+        assert_eq!(
+            parse(args(&["--output", "render.jpg"])),
+            Err("unknown image format for 'render.jpg'".to_string())
+        );
+        assert!(parse(args(&["--output"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_mesh() {
+        assert_eq!(
+            parse(args(&["bouncing_spheres", "--mesh", "bunny.obj"])),
+            Ok(Command::Render(RenderOptions {
+                scene: "bouncing_spheres".to_string(),
+                mesh: Some("bunny.obj".to_string()),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--mesh"])).is_err());
+        assert_eq!(
+            parse(args(&[
+                "bouncing_spheres",
+                "--mesh",
+                "bunny.obj",
+                "--mesh-up",
+                "z",
+                "--mesh-left-handed"
+            ])),
+            Ok(Command::Render(RenderOptions {
+                scene: "bouncing_spheres".to_string(),
+                mesh: Some("bunny.obj".to_string()),
+                mesh_axes: AxisConvention {
+                    up: UpAxis::Z,
+                    handedness: Handedness::Left,
+                },
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--mesh-up", "x"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_supersample() {
+        assert_eq!(
+            parse(args(&["--supersample", "3"])),
+            Ok(Command::Render(RenderOptions {
+                supersample: 3,
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--supersample", "0"])).is_err());
+        assert!(parse(args(&["--supersample", "x"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_scene_width_and_samples() {
+        assert_eq!(
+            parse(args(&[
+                "--scene",
+                "bouncing_spheres",
+                "--width",
+                "1920",
+                "--samples",
+                "500",
+                "--output",
+                "out.png"
+            ])),
+            Ok(Command::Render(RenderOptions {
+                scene: "bouncing_spheres".to_string(),
+                width: Some(1920),
+                samples: Some(500),
+                output: Some("out.png".to_string()),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--scene"])).is_err());
+        assert!(parse(args(&["--width", "0"])).is_err());
+        assert!(parse(args(&["--samples", "many"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_adaptive() {
+        assert_eq!(
+            parse(args(&["--adaptive", "0.05", "--min-samples", "8"])),
+            Ok(Command::Render(RenderOptions {
+                adaptive: Some(0.05),
+                min_samples: Some(8),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--adaptive", "0"])).is_err());
+        assert!(parse(args(&["--adaptive", "inf"])).is_err());
+        assert!(parse(args(&["--min-samples", "0"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_tile_size() {
+        assert_eq!(
+            parse(args(&["--tile-size", "32"])),
+            Ok(Command::Render(RenderOptions {
+                tile_size: Some(32),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--tile-size", "0"])).is_err());
+        assert!(parse(args(&["--tile-size"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_tile_budget() {
+        assert_eq!(
+            parse(args(&["--tile-budget", "0.25"])),
+            Ok(Command::Render(RenderOptions {
+                tile_budget: Some(Duration::from_millis(250)),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--tile-budget", "0"])).is_err());
+        assert!(parse(args(&["--tile-budget", "-1"])).is_err());
+        assert!(parse(args(&["--tile-budget"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_progressive() {
+        assert_eq!(
+            parse(args(&["--progressive", "4", "--output", "out.png"])),
+            Ok(Command::Render(RenderOptions {
+                progressive: Some(4),
+                output: Some("out.png".to_string()),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--progressive", "0"])).is_err());
+        assert!(parse(args(&["--progressive"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_preview_every() {
+        assert_eq!(
+            parse(args(&["--preview-every", "2.5", "--output", "out.png"])),
+            Ok(Command::Render(RenderOptions {
+                preview_every: Some(Duration::from_millis(2500)),
+                output: Some("out.png".to_string()),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--preview-every", "0"])).is_err());
+        assert!(parse(args(&["--preview-every", "soon"])).is_err());
+        assert!(parse(args(&["--preview-every"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_regularize() {
+        assert_eq!(
+            parse(args(&["--regularize", "0.3"])),
+            Ok(Command::Render(RenderOptions {
+                regularization: Some(0.3),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--regularize", "1.5"])).is_err());
+        assert!(parse(args(&["--regularize", "rough"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_ods() {
+        assert_eq!(
+            parse(args(&["--ods", "0.064"])),
+            Ok(Command::Render(RenderOptions {
+                projection: Projection::OmniStereo { ipd: 0.064 },
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--ods", "-1"])).is_err());
+        assert!(parse(args(&["--ods"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_cameras() {
+        let views = |value: &str| match parse(args(&["--cameras", value])) {
+            Ok(Command::Render(options)) => Ok(options.views),
+            Ok(_) => panic!("not a render"),
+            Err(error) => Err(error),
+        };
+        assert_eq!(views("all"), Ok(Some(ViewSelection::All)));
+        assert_eq!(
+            views("front,top"),
+            Ok(Some(ViewSelection::Named(vec![
+                "front".to_string(),
+                "top".to_string()
+            ])))
+        );
+        assert!(views("front,").is_err());
+        assert!(parse(args(&["--cameras"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_set() {
+        assert_eq!(
+            parse(args(&[
+                "--set",
+                "camera.vertical_fov=35",
+                "--set",
+                "materials.glass.refraction_index=1.45"
+            ])),
+            Ok(Command::Render(RenderOptions {
+                overrides: vec![
+                    Override::new("camera.vertical_fov", 35),
+                    Override::new("materials.glass.refraction_index", 1.45)
+                ],
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--set", "camera.vertical_fov"])).is_err());
+        assert!(parse(args(&["--set"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_highlight_rolloff() {
+        let rolloff = |value: &str| match parse(args(&["--highlight-rolloff", value])) {
+            Ok(Command::Render(options)) => Ok(options.highlight_rolloff),
+            Ok(_) => panic!("not a render"),
+            Err(error) => Err(error),
+        };
+        assert_eq!(rolloff("0.8"), Ok(Some(RolloffStart::Luminance(0.8))));
+        assert_eq!(rolloff("95%"), Ok(Some(RolloffStart::Percentile(95.0))));
+        assert!(rolloff("101%").is_err());
+        assert!(rolloff("-1").is_err());
+        assert!(rolloff("bright").is_err());
+    }
+
+    #[test]
+    fn test_parse_tilt_shift() {
+        assert_eq!(
+            parse(args(&["--tilt", "10,-5", "--lens-shift", "0,0.25"])),
+            Ok(Command::Render(RenderOptions {
+                tilt: (10.0, -5.0),
+                lens_shift: (0.0, 0.25),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--tilt", "10"])).is_err());
+        assert!(parse(args(&["--lens-shift", "up,0"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_scene_seed() {
+        assert_eq!(
+            parse(args(&["--scene-seed", "42", "bouncing_spheres"])),
+            Ok(Command::Render(RenderOptions {
+                scene: "bouncing_spheres".to_string(),
+                scene_seed: Some(42),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--scene-seed", "-1"])).is_err());
+        assert!(parse(args(&["--scene-seed"])).is_err());
+
+        assert_eq!(
+            parse(args(&["--seed", "7", "--scene-seed", "42"])),
+            Ok(Command::Render(RenderOptions {
+                scene_seed: Some(42),
+                seed: Some(7),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--seed", "x"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_clay() {
+        assert_eq!(
+            parse(args(&["--clay", "lit_spheres"])),
+            Ok(Command::Render(RenderOptions {
+                scene: "lit_spheres".to_string(),
+                clay: true,
+                ..RenderOptions::default()
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_units() {
+        assert_eq!(
+            parse(args(&["--units", "cm"])),
+            Ok(Command::Render(RenderOptions {
+                units: Units::Centimeters,
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--units", "parsecs"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_pass() {
+        assert_eq!(
+            parse(args(&["--pass", "material-id"])),
+            Ok(Command::Render(RenderOptions {
+                pass: RenderPass::MaterialId,
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--pass", "normals"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_renderer() {
+        assert_eq!(
+            parse(args(&["--renderer", "wavefront"])),
+            Ok(Command::Render(RenderOptions {
+                renderer: Renderer::Wavefront,
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--renderer", "gpu"])).is_err());
+        assert!(parse(args(&["--renderer"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_lights() {
+        assert_eq!(
+            parse(args(&["many_lights", "--lights", "all"])),
+            Ok(Command::Render(RenderOptions {
+                scene: "many_lights".to_string(),
+                light_sampling: Some(LightSampling::All),
+                ..RenderOptions::default()
+            }))
+        );
+        assert!(parse(args(&["--lights", "some"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(args(&["--sampling"])).is_err());
+        assert!(parse(args(&["--sampling", "sobol"])).is_err());
+        assert!(parse(args(&["--bogus"])).is_err());
+    }
+}
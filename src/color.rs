@@ -1,30 +1,180 @@
+use crate::display::DisplayTransform;
 use crate::interval::Interval;
+use crate::utilities::hash_f64;
 use crate::vec3::Vec3;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::ops::{Add, AddAssign, Mul, MulAssign};
 
+/// Second radiation constant of Planck's law, hc/k, in meter kelvins.
+const PLANCK_C2: f64 = 1.4388e-2;
+/// Wavelengths, in nanometers, the blackbody spectrum is integrated over.
+const VISIBLE_NM: (u32, u32) = (380, 780);
+const SPECTRUM_STEP_NM: usize = 5;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Color(Vec3);
 
+/// How the components of an authored color are encoded.
+///
+/// The renderer works in linear light, but colors picked in an image editor or color
+/// picker are sRGB encoded and come out too bright if taken as linear.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColorSpace {
+    /// Components are proportional to light, as the renderer uses them
+    #[default]
+    Linear,
+    /// Components are encoded with the sRGB transfer function, as in most images
+    Srgb,
+}
+
+impl ColorSpace {
+    /// Converts `color`, encoded in this color space, to linear components.
+    pub fn to_linear(self, color: Color) -> Color {
+        match self {
+            ColorSpace::Linear => color,
+            ColorSpace::Srgb => Color::new(
+                srgb_to_linear(color.r()),
+                srgb_to_linear(color.g()),
+                srgb_to_linear(color.b()),
+            ),
+        }
+    }
+}
+
+/// Decodes one sRGB-encoded component in [0, 1] with the exact piecewise curve.
+fn srgb_to_linear(encoded: f64) -> f64 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
 impl Color {
     #[inline]
     pub const fn new(r: f64, g: f64, b: f64) -> Color {
         Color(Vec3::new(r, g, b))
     }
 
-    pub fn write_color(&self) -> String {
-        // Apply a linear to gamma transform for gamma 2
-        let r = Color::linear_to_gamma(self.0.x());
-        let g = Color::linear_to_gamma(self.0.y());
-        let b = Color::linear_to_gamma(self.0.z());
+    #[inline]
+    pub fn r(&self) -> f64 {
+        self.0.x()
+    }
+
+    #[inline]
+    pub fn g(&self) -> f64 {
+        self.0.y()
+    }
 
+    #[inline]
+    pub fn b(&self) -> f64 {
+        self.0.z()
+    }
+
+    /// Returns true if no component is NaN or infinite.
+    #[inline]
+    pub fn is_finite(&self) -> bool {
+        self.0.is_finite()
+    }
+
+    /// Relative luminance using the Rec. 709 primaries.
+    #[inline]
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r() + 0.7152 * self.g() + 0.0722 * self.b()
+    }
+
+    /// Returns a copy with every component clamped to [0, 1].
+    #[inline]
+    pub fn clamped(&self) -> Color {
+        Color::new(
+            self.r().clamp(0.0, 1.0),
+            self.g().clamp(0.0, 1.0),
+            self.b().clamp(0.0, 1.0),
+        )
+    }
+
+    /// Encodes the color with `display` and formats it as three bytes.
+    pub fn write_color(&self, display: DisplayTransform) -> String {
+        let [rbyte, gbyte, bbyte] = self.to_bytes(display);
+        format!("{} {} {}", rbyte, gbyte, bbyte)
+    }
+
+    /// Encodes the color with `display` as red, green and blue bytes.
+    pub fn to_bytes(self, display: DisplayTransform) -> [u8; 3] {
+        Self::display_bytes(display.encode(self))
+    }
+
+    /// Formats display values in [0, 1], already encoded, as red, green and blue bytes.
+    pub fn display_bytes(encoded: Color) -> [u8; 3] {
         // Translate the [0,1] component values to the byte range [0,255].
         let intensity = Interval::new(0.000, 0.999);
-        let rbyte = (256.0 * intensity.clamp(r)) as i32;
-        let gbyte = (256.0 * intensity.clamp(g)) as i32;
-        let bbyte = (256.0 * intensity.clamp(b)) as i32;
+        [encoded.r(), encoded.g(), encoded.b()].map(|c| (256.0 * intensity.clamp(c)) as u8)
+    }
 
-        format!("{} {} {}", rbyte, gbyte, bbyte)
+    /// Maps `t` in [0, 1] onto a black-red-yellow-white heatmap ramp.
+    ///
+    /// The ramp is evenly spaced once gamma encoded, so it displays as intended.
+    pub fn heat(t: f64) -> Color {
+        let t = t.clamp(0.0, 1.0) * 3.0;
+        let r = t.min(1.0);
+        let g = (t - 1.0).clamp(0.0, 1.0);
+        let b = (t - 2.0).clamp(0.0, 1.0);
+        Color::new(
+            Color::gamma_to_linear(r),
+            Color::gamma_to_linear(g),
+            Color::gamma_to_linear(b),
+        )
+    }
+
+    /// Returns the color of an ideal blackbody at `kelvin`, scaled to unit luminance.
+    ///
+    /// 2700K is a warm incandescent bulb and 6500K is roughly daylight. Multiply the
+    /// result by the brightness wanted. Planck's law is integrated against the
+    /// multi-lobe Gaussian fit of the CIE 1931 observer by Wyman, Sloan and Shirley
+    /// (JCGT 2013) and converted to linear Rec. 709 RGB; out-of-gamut components are
+    /// clamped to zero.
+    pub fn from_blackbody(kelvin: f64) -> Color {
+        let kelvin = kelvin.max(1.0);
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for nm in (VISIBLE_NM.0..=VISIBLE_NM.1).step_by(SPECTRUM_STEP_NM) {
+            let nm = nm as f64;
+            let meters = nm * 1e-9;
+            let radiance = 1.0 / (meters.powi(5) * ((PLANCK_C2 / (meters * kelvin)).exp() - 1.0));
+            let (cie_x, cie_y, cie_z) = cie_1931(nm);
+            x += radiance * cie_x;
+            y += radiance * cie_y;
+            z += radiance * cie_z;
+        }
+
+        let color = Color::new(
+            (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+            (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+            (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0),
+        );
+        color * (1.0 / color.luminance())
+    }
+
+    /// Decodes integer components written by `write_color` with `display`.
+    ///
+    /// Each component is taken from the middle of its quantization step. Returns
+    /// `None` if the display transform cannot be inverted.
+    pub fn from_display_bytes(
+        r: u32,
+        g: u32,
+        b: u32,
+        max_value: u32,
+        display: DisplayTransform,
+    ) -> Option<Color> {
+        let center =
+            |component: u32| (component.min(max_value) as f64 + 0.5) / (max_value as f64 + 1.0);
+        display.decode(Color::new(center(r), center(g), center(b)))
+    }
+
+    pub fn gamma_to_linear(gamma_component: f64) -> f64 {
+        gamma_component * gamma_component
     }
 
     pub fn linear_to_gamma(linear_component: f64) -> f64 {
@@ -36,6 +186,21 @@ impl Color {
     }
 }
 
+/// Approximates the CIE 1931 color matching functions at `nm` nanometers.
+fn cie_1931(nm: f64) -> (f64, f64, f64) {
+    // A Gaussian with different widths either side of its peak
+    let lobe = |mean: f64, below: f64, above: f64| {
+        let width = if nm < mean { below } else { above };
+        (-0.5 * ((nm - mean) / width).powi(2)).exp()
+    };
+    (
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
 impl Add for Color {
     type Output = Color;
 
@@ -84,6 +249,14 @@ impl MulAssign<f64> for Color {
     }
 }
 
+impl Hash for Color {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_f64(self.r(), state);
+        hash_f64(self.g(), state);
+        hash_f64(self.b(), state);
+    }
+}
+
 impl fmt::Display for Color {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{} {} {}", self.0.x(), self.0.y(), self.0.z())
@@ -93,14 +266,13 @@ impl fmt::Display for Color {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::f64::EPSILON;
 
     #[test]
     fn test_color_new() {
         let c = Color::new(0.1, 0.2, 0.3);
-        assert!((c.0.x() - 0.1).abs() < EPSILON);
-        assert!((c.0.y() - 0.2).abs() < EPSILON);
-        assert!((c.0.z() - 0.3).abs() < EPSILON);
+        assert!((c.0.x() - 0.1).abs() < f64::EPSILON);
+        assert!((c.0.y() - 0.2).abs() < f64::EPSILON);
+        assert!((c.0.z() - 0.3).abs() < f64::EPSILON);
     }
 
     #[test]
@@ -117,15 +289,83 @@ mod tests {
     fn test_write_color() {
         // Test normal values in range [0,1]
         let c1 = Color::new(0.0, 0.5, 1.0);
-        assert_eq!(c1.write_color(), "0 181 255");
+        assert_eq!(c1.write_color(DisplayTransform::Gamma2), "0 181 255");
 
         // Test clamping for values > 1.0
         let c2 = Color::new(1.5, 0.5, 2.0);
-        assert_eq!(c2.write_color(), "255 181 255");
+        assert_eq!(c2.write_color(DisplayTransform::Gamma2), "255 181 255");
 
         // Test clamping for values < 0.0
         let c3 = Color::new(-0.5, 0.5, -1.0);
-        assert_eq!(c3.write_color(), "0 181 0");
+        assert_eq!(c3.write_color(DisplayTransform::Gamma2), "0 181 0");
+    }
+
+    #[test]
+    fn test_srgb_to_linear() {
+        let encoded = Color::new(0.0, 0.5, 1.0);
+        assert_eq!(ColorSpace::Linear.to_linear(encoded), encoded);
+
+        let linear = ColorSpace::Srgb.to_linear(encoded);
+        assert_eq!(linear.r(), 0.0);
+        assert!((linear.g() - 0.214041).abs() < 1e-6);
+        assert!((linear.b() - 1.0).abs() < 1e-12);
+        // The linear toe and the power curve meet at the breakpoint
+        assert!((srgb_to_linear(0.04045) - srgb_to_linear(0.04045 + 1e-9)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_from_blackbody() {
+        let warm = Color::from_blackbody(2700.0);
+        let daylight = Color::from_blackbody(6500.0);
+        let sky = Color::from_blackbody(12000.0);
+
+        for color in [warm, daylight, sky] {
+            assert!((color.luminance() - 1.0).abs() < 1e-9);
+        }
+        assert!(warm.r() > warm.g() && warm.g() > warm.b());
+        assert!(sky.b() > sky.g() && sky.g() > sky.r());
+        // A 6500K blackbody is close to, but not exactly, the sRGB white point
+        for component in [daylight.r(), daylight.g(), daylight.b()] {
+            assert!((component - 1.0).abs() < 0.1, "{}", daylight);
+        }
+    }
+
+    #[test]
+    fn test_heat() {
+        assert_eq!(Color::heat(0.0), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(Color::heat(1.0 / 3.0), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(Color::heat(2.0 / 3.0), Color::new(1.0, 1.0, 0.0));
+        assert_eq!(Color::heat(1.0), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(Color::heat(2.0), Color::heat(1.0));
+    }
+
+    #[test]
+    fn test_is_finite() {
+        assert!(Color::new(0.0, 1.0, 100.0).is_finite());
+        assert!(!Color::new(f64::NAN, 0.0, 0.0).is_finite());
+        assert!(!Color::new(0.0, 0.0, f64::INFINITY).is_finite());
+    }
+
+    #[test]
+    fn test_luminance() {
+        assert!((Color::new(1.0, 1.0, 1.0).luminance() - 1.0).abs() < 1e-12);
+        assert_eq!(Color::new(0.0, 0.0, 0.0).luminance(), 0.0);
+        assert!(Color::new(0.0, 1.0, 0.0).luminance() > Color::new(1.0, 0.0, 1.0).luminance());
+    }
+
+    #[test]
+    fn test_from_display_bytes_round_trip() {
+        for display in DisplayTransform::ALL {
+            if display == DisplayTransform::AgX {
+                assert_eq!(Color::from_display_bytes(1, 2, 3, 255, display), None);
+                continue;
+            }
+            for byte in [0, 1, 64, 128, 181, 254, 255] {
+                let color = Color::from_display_bytes(byte, byte, byte, 255, display).unwrap();
+                let expected = format!("{} {} {}", byte, byte, byte);
+                assert_eq!(color.write_color(display), expected, "{:?}", display);
+            }
+        }
     }
 
     #[test]
@@ -135,9 +375,9 @@ mod tests {
         let result = c1 + c2;
 
         // Using approx_eq for floating point comparison
-        assert!((result.0.x() - 0.3).abs() < EPSILON);
-        assert!((result.0.y() - 0.5).abs() < EPSILON);
-        assert!((result.0.z() - 0.7).abs() < EPSILON);
+        assert!((result.0.x() - 0.3).abs() < f64::EPSILON);
+        assert!((result.0.y() - 0.5).abs() < f64::EPSILON);
+        assert!((result.0.z() - 0.7).abs() < f64::EPSILON);
     }
 
     #[test]
@@ -147,9 +387,9 @@ mod tests {
         c1 += c2;
 
         // Using approx_eq for floating point comparison
-        assert!((c1.0.x() - 0.3).abs() < EPSILON);
-        assert!((c1.0.y() - 0.5).abs() < EPSILON);
-        assert!((c1.0.z() - 0.7).abs() < EPSILON);
+        assert!((c1.0.x() - 0.3).abs() < f64::EPSILON);
+        assert!((c1.0.y() - 0.5).abs() < f64::EPSILON);
+        assert!((c1.0.z() - 0.7).abs() < f64::EPSILON);
     }
 
     #[test]
@@ -0,0 +1,773 @@
+//! The subcommands, each reading its options, rendering or reading images through
+//! the library, and reporting what it did on stderr.
+
+use crate::cli::{
+    self, AnimationOptions, BakeOptions, ConvergenceOptions, Listing, RenderOptions, SweepOptions,
+    TracePixelOptions, ViewSelection,
+};
+use crate::config;
+use crate::report::{ImageRecord, RenderReport};
+use raytrace::analysis::{self, LuminanceHistogram};
+use raytrace::bvh::Bvh;
+use raytrace::camera::CameraBuilder;
+use raytrace::framebuffer::{Framebuffer, ImageError, ImageFormat};
+use raytrace::hittable::Hittable;
+use raytrace::lut::Lut;
+use raytrace::material::{Lambertian, Material};
+use raytrace::medium::MediumPreset;
+use raytrace::overrides::Override;
+use raytrace::postprocess::HighlightRolloff;
+use raytrace::progress::WebhookReporter;
+use raytrace::sampler::AdaptiveSampling;
+use raytrace::scenes::{self, BouncingSimulation, Scene};
+use raytrace::texture::TextureEnum;
+use raytrace::utilities::seed_thread_rng;
+use raytrace::{apng, compare, overrides, preprocess};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Margin left around the scene by `--frame`, as a fraction of the scene's size.
+const FRAME_PADDING: f64 = 0.05;
+/// Samples per pixel a watched scene is previewed with, unless its options say otherwise.
+const PREVIEW_SAMPLES: u32 = 4;
+/// How often a watched file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+/// Least time between progress reports posted to a webhook.
+const WEBHOOK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Preprocesses a scene's objects, logging what was found, and builds the BVH over them.
+fn build_world(
+    mut objects: Vec<Box<dyn Hittable>>,
+    options: &RenderOptions,
+) -> Result<Bvh, String> {
+    eprintln!(
+        "{}",
+        preprocess::prepare(&mut objects, !options.keep_degenerate)
+    );
+    Bvh::new(objects).map_err(|error| format!("{}: {}", options.scene, error))
+}
+
+fn scene(options: &RenderOptions) -> Result<(Bvh, CameraBuilder), String> {
+    let (objects, camera) = scene_objects(options)?;
+    let world = build_world(objects, options)?;
+    let camera = framed(camera, &world, options);
+    Ok((world, camera))
+}
+
+/// Moves the camera back to fit the whole scene in view if `--frame` was given.
+fn framed(camera: CameraBuilder, world: &Bvh, options: &RenderOptions) -> CameraBuilder {
+    if options.frame {
+        camera.frame_scene(world, FRAME_PADDING)
+    } else {
+        camera
+    }
+}
+
+/// Creates the requested scene's objects and its camera with the options applied.
+///
+/// Generated scenes draw their arrangement from a seed, which is logged so the
+/// same arrangement can be rendered again with `--scene-seed`.
+fn scene_objects(
+    options: &RenderOptions,
+) -> Result<(Vec<Box<dyn Hittable>>, CameraBuilder), String> {
+    let seed = seed_scene(options);
+    if options.mesh.is_some() && options.scene != "bouncing_spheres" {
+        eprintln!("warning: --mesh only applies to bouncing_spheres");
+    }
+    let built = if options.scene.ends_with(".json") {
+        scene_file(&options.scene, &options.overrides)
+    } else {
+        let mesh = options.mesh.as_deref().map(Path::new);
+        scenes::build(&options.scene, options.placement, mesh, options.mesh_axes)
+            .ok_or_else(|| unknown_scene(&options.scene))?
+            .and_then(|(objects, camera)| {
+                Ok((objects, override_camera(camera, &options.overrides)?))
+            })
+    };
+    let (objects, camera) = built.map_err(|error| {
+        let name = options.mesh.as_deref().unwrap_or(&options.scene);
+        format!("failed to load {}: {}", name, error)
+    })?;
+    let camera = configure(camera, options, seed)?;
+    Ok((objects, camera))
+}
+
+/// Explains that there is no built-in scene called `name`, listing those there are.
+fn unknown_scene(name: &str) -> String {
+    let names: Vec<&str> = scenes::SCENES.iter().map(|(name, _)| *name).collect();
+    format!(
+        "unknown scene '{}'; expected a .json file or one of {}",
+        name,
+        names.join(", ")
+    )
+}
+
+/// Loads the scene described by the JSON file at `path`, with `--set` overrides.
+#[cfg(feature = "scene")]
+fn scene_file(path: &str, overrides: &[Override]) -> Result<Scene, raytrace::Error> {
+    Ok(raytrace::scene::load_with_overrides(path, overrides)?)
+}
+
+#[cfg(not(feature = "scene"))]
+fn scene_file(_path: &str, _overrides: &[Override]) -> Result<Scene, raytrace::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "scene files need the scene feature",
+    )
+    .into())
+}
+
+/// Applies `--set` overrides to the camera of a built-in scene.
+#[cfg(feature = "scene")]
+fn override_camera(
+    camera: CameraBuilder,
+    overrides: &[Override],
+) -> Result<CameraBuilder, raytrace::Error> {
+    Ok(raytrace::scene::override_camera(camera, overrides)?)
+}
+
+#[cfg(not(feature = "scene"))]
+fn override_camera(
+    camera: CameraBuilder,
+    overrides: &[Override],
+) -> Result<CameraBuilder, raytrace::Error> {
+    if overrides.is_empty() {
+        Ok(camera)
+    } else {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "--set needs the scene feature").into())
+    }
+}
+
+/// Seeds the random arrangement of a generated scene, logging the seed.
+fn seed_scene(options: &RenderOptions) -> u64 {
+    let seed = options.scene_seed.unwrap_or_else(rand::random);
+    eprintln!("Scene seed: {}", seed);
+    seed_thread_rng(seed);
+    seed
+}
+
+/// Applies the options to a scene's camera.
+fn configure(
+    camera: CameraBuilder,
+    options: &RenderOptions,
+    seed: u64,
+) -> Result<CameraBuilder, String> {
+    let camera = match options.width {
+        Some(width) => camera.image_width(width),
+        None => camera,
+    };
+    let camera = match options.samples {
+        Some(samples) => camera.samples_per_pixel(samples),
+        None => camera,
+    };
+    let camera =
+        match &options.lut {
+            Some(path) => camera
+                .lut(Some(Arc::new(Lut::load_cube(path).map_err(|error| {
+                    format!("failed to load {}: {}", path, error)
+                })?))),
+            None => camera,
+        };
+    let camera = match options.adaptive {
+        Some(tolerance) => camera.adaptive_sampling(Some(AdaptiveSampling::new(
+            tolerance,
+            options
+                .min_samples
+                .unwrap_or(AdaptiveSampling::DEFAULT_MIN_SAMPLES),
+        ))),
+        None => camera,
+    };
+    let camera = match options.tile_size {
+        Some(size) => camera.tile_size(size),
+        None => camera,
+    };
+    let camera = match &options.progress_webhook {
+        Some(url) => camera.progress_reporter(Arc::new(
+            WebhookReporter::new(url, WEBHOOK_INTERVAL).map_err(|error| error.to_string())?,
+        )),
+        None => camera,
+    };
+    let camera = match options.tile_budget {
+        Some(budget) => camera.tile_time_budget(Some(budget)),
+        None => camera,
+    };
+    let camera = match options.seed {
+        Some(seed) => camera.seed(seed),
+        None => camera,
+    };
+    let camera = match options.light_sampling {
+        Some(light_sampling) => camera.light_sampling(light_sampling),
+        None => camera,
+    };
+    let camera = if options.transparent_shadows {
+        camera.transparent_shadows(true)
+    } else {
+        camera
+    };
+    Ok(camera
+        .self_hit_exclusion(options.self_hit_exclusion)
+        .pixel_sampling(options.pixel_sampling)
+        .pass(options.pass)
+        .renderer(options.renderer)
+        .path_guiding(options.path_guiding)
+        .units(options.units)
+        .scene_seed(seed)
+        .display(options.display)
+        .tilt(options.tilt.0, options.tilt.1)
+        .lens_shift(options.lens_shift.0, options.lens_shift.1)
+        .highlight_rolloff(options.highlight_rolloff.map(HighlightRolloff::new))
+        .supersample(options.supersample)
+        .regularization(options.regularization)
+        .projection(options.projection)
+        .material_override(options.clay.then(Lambertian::clay))
+        .nan_guard(options.nan_guard))
+}
+
+/// Prints every step of the path one sample of one pixel follows through the scene.
+pub fn trace_pixel(options: &TracePixelOptions) -> Result<(), String> {
+    let (world, camera) = scene(&options.render)?;
+    let (x, y) = options.pixel;
+    println!(
+        "{}",
+        camera.build().trace_pixel(&world, x, y, options.sample)
+    );
+    Ok(())
+}
+
+/// Renders the selected named cameras of a scene, building its BVH only once, each
+/// to its own file named after the scene and the camera.
+pub fn render_views(options: &RenderOptions, selection: &ViewSelection) -> Result<(), String> {
+    let run_start = Instant::now();
+    let mut report = RenderReport::new("render", options);
+    let (objects, camera) = scene_objects(options)?;
+    let views = match selection {
+        ViewSelection::All => camera.views().iter().collect(),
+        ViewSelection::Named(names) => names
+            .iter()
+            .map(|name| {
+                camera
+                    .views()
+                    .iter()
+                    .find(|view| view.name == *name)
+                    .ok_or_else(|| format!("scene '{}' has no camera '{}'", options.scene, name))
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+    if views.is_empty() {
+        return Err(format!("scene '{}' has no named cameras", options.scene));
+    }
+
+    let world = build_world(objects, options)?;
+    for view in views {
+        let path = output_path(options, &format!("{}-{}.ppm", options.scene, view.name))?;
+        let start = Instant::now();
+        let frame = framed(camera.through(view), &world, options)
+            .build()
+            .render_frame(&world);
+        report.push(write_image(&frame, ImageFormat::Ppm, &path, start)?);
+        eprintln!("Wrote {} in {:.1?}", path, start.elapsed());
+    }
+    write_report(options, &report, run_start)
+}
+
+/// Renders every combination of the swept parameters, each to a file named after
+/// the scene and its settings, then tiles them into a contact sheet if asked.
+pub fn sweep(options: &SweepOptions) -> Result<(), String> {
+    // Every render draws a generated scene from the same seed so only the swept
+    // parameters differ between them
+    let scene_seed = Some(options.render.scene_seed.unwrap_or_else(rand::random));
+    let run_start = Instant::now();
+    let mut report = RenderReport::new(
+        "sweep",
+        &RenderOptions {
+            scene_seed,
+            ..options.render.clone()
+        },
+    );
+    let mut frames = Vec::new();
+    for combination in overrides::grid(&options.sweeps) {
+        let mut render = RenderOptions {
+            scene_seed,
+            ..options.render.clone()
+        };
+        let mut name = render.scene.clone();
+        for (setting, step) in combination {
+            name = format!("{}-{}", name, setting);
+            render.overrides.push(step);
+        }
+
+        let path = output_path(&render, &format!("{}.ppm", name))?;
+        let start = Instant::now();
+        let (world, camera) = scene(&render)?;
+        let frame = camera.build().render_frame(&world);
+        report.push(write_image(&frame, ImageFormat::Ppm, &path, start)?);
+        eprintln!("Wrote {} in {:.1?}", path, start.elapsed());
+        if options.contact_sheet.is_some() {
+            frames.push(frame);
+        }
+    }
+
+    if let Some(path) = &options.contact_sheet {
+        let columns = options.sweeps.last().map_or(1, |sweep| sweep.steps.len());
+        let start = Instant::now();
+        let sheet = Framebuffer::contact_sheet(&frames, columns as u32)
+            .map_err(|error| error.to_string())?;
+        report.push(write_image(&sheet, ImageFormat::Ppm, path, start)?);
+        eprintln!("Wrote {}", path);
+    }
+    write_report(&options.render, &report, run_start)
+}
+
+/// Renders frames evenly spaced around one revolution of the camera about the point
+/// it looks at, building the BVH once, then assembles them into an animated PNG if asked.
+pub fn turntable(options: &AnimationOptions) -> Result<(), String> {
+    let run_start = Instant::now();
+    let mut report = RenderReport::new("turntable", &options.render);
+    let (world, camera) = scene(&options.render)?;
+    let mut frames = Vec::new();
+    for index in 0..options.frames {
+        let name = format!("{}-turntable-{:03}.ppm", options.render.scene, index);
+        let path = output_path(&options.render, &name)?;
+        let start = Instant::now();
+        let degrees = 360.0 * index as f64 / options.frames as f64;
+        let frame = camera.orbit(degrees).build().render_frame(&world);
+        report.push(write_image(&frame, ImageFormat::Ppm, &path, start)?);
+        eprintln!("Wrote {} in {:.1?}", path, start.elapsed());
+        if options.apng.is_some() {
+            frames.push(frame);
+        }
+    }
+
+    write_animation(options, &frames)?;
+    write_report(&options.render, &report, run_start)
+}
+
+/// Drops the small spheres of `bouncing_spheres` and renders a frame every
+/// `1 / fps` seconds as they bounce, each blurred over the motion during its frame.
+pub fn simulate(options: &AnimationOptions) -> Result<(), String> {
+    if options.render.scene != "bouncing_spheres" {
+        return Err(format!("cannot simulate {}", options.render.scene));
+    }
+    let run_start = Instant::now();
+    let mut report = RenderReport::new("simulate", &options.render);
+    let seed = seed_scene(&options.render);
+    let mut simulation = BouncingSimulation::new(options.render.placement);
+
+    let mut frames = Vec::new();
+    for index in 0..options.frames {
+        let name = format!("{}-simulate-{:03}.ppm", options.render.scene, index);
+        let path = output_path(&options.render, &name)?;
+        let start = Instant::now();
+        let (objects, camera) = simulation
+            .advance(
+                1.0 / options.fps as f64,
+                options.render.mesh.as_deref().map(Path::new),
+                options.render.mesh_axes,
+            )
+            .map_err(|error| error.to_string())?;
+        let camera = override_camera(camera, &options.render.overrides)
+            .map_err(|error| error.to_string())?;
+        let camera = configure(camera, &options.render, seed)?;
+        let world = Bvh::new(objects).map_err(|error| error.to_string())?;
+
+        let frame = framed(camera, &world, &options.render)
+            .build()
+            .render_frame(&world);
+        report.push(write_image(&frame, ImageFormat::Ppm, &path, start)?);
+        eprintln!("Wrote {} in {:.1?}", path, start.elapsed());
+        if options.apng.is_some() {
+            frames.push(frame);
+        }
+    }
+
+    write_animation(options, &frames)?;
+    write_report(&options.render, &report, run_start)
+}
+
+/// Assembles `frames` into the animated PNG asked for with `--apng`, if any.
+fn write_animation(options: &AnimationOptions, frames: &[Framebuffer]) -> Result<(), String> {
+    if let Some(path) = &options.apng {
+        File::create(path)
+            .map_err(ImageError::from)
+            .and_then(|mut file| apng::write_apng(frames, options.fps, &mut file))
+            .map_err(|error| format!("failed to write {}: {}", path, error))?;
+        eprintln!("Wrote {}", path);
+    }
+    Ok(())
+}
+
+/// Re-renders a preview of the scene whenever the options file at `path`, or the
+/// JSON scene file being rendered, changes, until interrupted.
+///
+/// The file's options are applied over the command line's, and the preview is
+/// rendered with few samples unless they set `camera.samples_per_pixel`. Generated scenes keep
+/// one arrangement throughout, and the BVH is only rebuilt when something other
+/// than the camera changed. A mistake in either file is reported and waits for the
+/// next edit.
+pub fn watch(options: &RenderOptions, path: &str) -> Result<(), String> {
+    let mut overrides = vec![Override::new("camera.samples_per_pixel", PREVIEW_SAMPLES)];
+    overrides.extend(options.overrides.iter().cloned());
+    let base = RenderOptions {
+        scene_seed: Some(options.scene_seed.unwrap_or_else(rand::random)),
+        overrides,
+        watch: None,
+        views: None,
+        ..options.clone()
+    };
+    let output = output_path(options, &format!("{}-preview.ppm", options.scene))?;
+    eprintln!("Watching {} for changes", path);
+
+    // The scene file is the one the options file named last time it was read
+    let mut scene = base.scene.clone();
+    let mut last_modified = None;
+    let mut built = None;
+    loop {
+        let modified = (modified(path), scene_file_modified(&scene));
+        if last_modified != Some(modified) {
+            last_modified = Some(modified);
+            let result = watched_options(&base, path).and_then(|options| {
+                scene.clone_from(&options.scene);
+                preview(&options, scene_file_modified(&scene), &output, &mut built)
+            });
+            if let Err(error) = result {
+                eprintln!("error: {}", error);
+            }
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// When the file at `path` was last changed, or `None` while it cannot be read,
+/// such as between an editor removing and rewriting it.
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// When the scene file `scene` was last changed, or `None` for a built-in scene.
+fn scene_file_modified(scene: &str) -> Option<SystemTime> {
+    scene.ends_with(".json").then(|| modified(scene)).flatten()
+}
+
+/// Reads the options file at `path` and applies it over `base`.
+fn watched_options(base: &RenderOptions, path: &str) -> Result<RenderOptions, String> {
+    let text =
+        fs::read_to_string(path).map_err(|error| format!("failed to read {}: {}", path, error))?;
+    let args = config::parse_config(&text).map_err(|error| format!("{}: {}", path, error))?;
+    cli::apply_render_args(base.clone(), args.into_iter())
+        .map_err(|error| format!("{}: {}", path, error))
+}
+
+/// The world `watch` last built, with what it was built from.
+struct WatchedWorld {
+    options: RenderOptions,
+    /// When the scene file had last changed as it was read
+    scene_modified: Option<SystemTime>,
+    world: Bvh,
+}
+
+/// Renders one preview for `watch`, reusing the previous preview's BVH if the
+/// scene's geometry and materials, and the scene file, are unchanged.
+fn preview(
+    options: &RenderOptions,
+    scene_modified: Option<SystemTime>,
+    output: &str,
+    built: &mut Option<WatchedWorld>,
+) -> Result<(), String> {
+    let start = Instant::now();
+    let (objects, camera) = scene_objects(options)?;
+    let reused = built.as_ref().is_some_and(|previous| {
+        same_world(&previous.options, options) && previous.scene_modified == scene_modified
+    });
+    if !reused {
+        *built = Some(WatchedWorld {
+            options: options.clone(),
+            scene_modified,
+            world: build_world(objects, options)?,
+        });
+    }
+    let world = &built.as_ref().expect("The world was just built").world;
+    let frame = framed(camera, world, options).build().render_frame(world);
+    write_image(&frame, ImageFormat::Ppm, output, start)?;
+    eprintln!(
+        "Wrote {} in {:.1?}{}",
+        output,
+        start.elapsed(),
+        if reused { ", reusing the BVH" } else { "" }
+    );
+    Ok(())
+}
+
+/// Returns true if two sets of options create the same objects with the same
+/// materials, so a BVH built for one can render the other.
+fn same_world(a: &RenderOptions, b: &RenderOptions) -> bool {
+    let world_overrides = |options: &RenderOptions| -> Vec<Override> {
+        options
+            .overrides
+            .iter()
+            .filter(|setting| !setting.changes_camera())
+            .cloned()
+            .collect()
+    };
+    a.scene == b.scene
+        && a.scene_seed == b.scene_seed
+        && a.placement == b.placement
+        && a.mesh == b.mesh
+        && a.keep_degenerate == b.keep_degenerate
+        && world_overrides(a) == world_overrides(b)
+}
+
+/// Renders the scene to the `--output` file, or to stdout.
+pub fn render(options: &RenderOptions) -> Result<(), String> {
+    let (path, format) = match &options.output {
+        Some(path) => (
+            path.as_str(),
+            ImageFormat::from_path(path).expect("output format checked when parsing"),
+        ),
+        None => ("-", options.format),
+    };
+    if path == "-" && options.report.as_deref() == Some("-") {
+        return Err("the image is written to stdout, so --report needs a file".to_string());
+    }
+    if path == "-" && options.progressive.is_some() {
+        return Err(
+            "--progressive rewrites the image after each pass, so it needs --output".to_string(),
+        );
+    }
+    if path == "-" && options.preview_every.is_some() {
+        return Err(
+            "--preview-every rewrites the image as it renders, so it needs --output".to_string(),
+        );
+    }
+    if options.progressive.is_some() && options.preview_every.is_some() {
+        return Err(
+            "--progressive and --preview-every both rewrite the image; pick one".to_string(),
+        );
+    }
+    let start = Instant::now();
+    let mut report = RenderReport::new("render", options);
+    let (world, camera) = scene(options)?;
+    let camera = camera.build();
+    let frame = match options.progressive {
+        Some(samples_per_pass) => {
+            let mut failure = None;
+            let frame = camera.render_progressive(&world, samples_per_pass, |frame| {
+                match frame.save_replacing(path) {
+                    Ok(()) => {
+                        eprintln!("{} samples per pixel", frame.samples_per_pixel());
+                        ControlFlow::Continue(())
+                    }
+                    Err(error) => {
+                        failure = Some(format!("failed to write {}: {}", path, error));
+                        ControlFlow::Break(())
+                    }
+                }
+            });
+            if let Some(failure) = failure {
+                return Err(failure);
+            }
+            frame
+        }
+        None => match options.preview_every {
+            Some(interval) => camera.render_streaming(&world, interval, |frame| {
+                // A missed preview is no reason to abandon the render
+                if let Err(error) = frame.save_replacing(path) {
+                    eprintln!("warning: failed to write preview {}: {}", path, error);
+                }
+            }),
+            None => camera.render_frame(&world as &dyn Hittable),
+        },
+    };
+    report.push(write_image(&frame, format, path, start)?);
+    write_report(options, &report, start)
+}
+
+/// Encodes `frame` in `format` and writes it to `path`, or stdout for `-`, returning
+/// its record for the run's report. What its render noticed is printed first.
+fn write_image(
+    frame: &Framebuffer,
+    format: ImageFormat,
+    path: &str,
+    start: Instant,
+) -> Result<ImageRecord, String> {
+    print_diagnostics(frame);
+    let mut encoded = Vec::new();
+    frame
+        .write(format, &mut encoded)
+        .expect("Failed to encode image");
+    if path == "-" {
+        io::stdout().lock().write_all(&encoded)
+    } else {
+        fs::write(path, &encoded)
+    }
+    .map_err(|error| format!("failed to write {}: {}", path, error))?;
+    Ok(ImageRecord::new(path, frame, &encoded, start.elapsed()))
+}
+
+/// Prints what the render of `frame` noticed, one line each.
+fn print_diagnostics(frame: &Framebuffer) {
+    for diagnostic in frame.diagnostics() {
+        if diagnostic.is_warning() {
+            eprintln!("warning: {}", diagnostic);
+        } else {
+            eprintln!("{}", diagnostic);
+        }
+    }
+}
+
+/// Writes the run's JSON report where `--report` asked, if it did.
+fn write_report(
+    options: &RenderOptions,
+    report: &RenderReport,
+    start: Instant,
+) -> Result<(), String> {
+    let Some(path) = &options.report else {
+        return Ok(());
+    };
+    let json = format!("{}\n", report.to_json(start.elapsed()));
+    if path == "-" {
+        io::stdout().lock().write_all(json.as_bytes())
+    } else {
+        fs::write(path, json)
+    }
+    .map_err(|error| format!("failed to write {}: {}", path, error))
+}
+
+/// Returns where to write a generated image called `name`, creating the output
+/// directory first if one was given.
+fn output_path(options: &RenderOptions, name: &str) -> Result<String, String> {
+    let Some(dir) = &options.output_dir else {
+        return Ok(name.to_string());
+    };
+    fs::create_dir_all(dir).map_err(|error| format!("failed to create {}: {}", dir, error))?;
+    Ok(Path::new(dir).join(name).display().to_string())
+}
+
+/// Prints each name with its description, aligned in two columns.
+pub fn list(listing: Listing) {
+    let media: Vec<(&str, &str)> = MediumPreset::ALL
+        .iter()
+        .map(|preset| (preset.name, preset.description))
+        .collect();
+    let entries: &[(&str, &str)] = match listing {
+        Listing::Scenes => &scenes::SCENES,
+        Listing::Materials => &Material::KINDS,
+        Listing::Textures => &TextureEnum::KINDS,
+        Listing::Media => &media,
+    };
+    let width = entries
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, description) in entries {
+        println!("{:width$}  {}", name, description, width = width);
+    }
+}
+
+fn read_image(path: &str) -> Result<Framebuffer, ImageError> {
+    Framebuffer::read(path)
+}
+
+pub fn merge(inputs: &[String]) -> Result<(), ImageError> {
+    let frames = inputs
+        .iter()
+        .map(|path| read_image(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let merged = Framebuffer::merge(&frames)?;
+    eprintln!(
+        "Merged {} images with {} samples per pixel",
+        frames.len(),
+        merged.samples_per_pixel()
+    );
+    if merged.scene_seed().is_none() && frames.iter().any(|f| f.scene_seed().is_some()) {
+        eprintln!("warning: the images were rendered from different scene seeds");
+    }
+    // The merge is written as the first image was, linear if it was
+    let format = ImageFormat::from_path(&inputs[0]).unwrap_or_default();
+    merged.write(format, &mut io::stdout().lock())?;
+    Ok(())
+}
+
+pub fn diff(a: &str, b: &str) -> Result<(), ImageError> {
+    let (report, heatmap) = compare::diff(&read_image(a)?, &read_image(b)?)?;
+    eprintln!("RMSE: {:.6}", report.rmse);
+    eprintln!("Max error: {:.6}", report.max_error);
+    eprintln!("SSIM: {:.6}", report.ssim);
+    heatmap.write_ppm(&mut io::stdout().lock())?;
+    Ok(())
+}
+
+/// Prints where two scene files differ, one difference per line.
+#[cfg(feature = "scene")]
+pub fn scene_diff(a: &str, b: &str) -> Result<(), raytrace::scene::SceneError> {
+    let differences = raytrace::scene::diff_files(a, b)?;
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    eprintln!("{} differences", differences.len());
+    Ok(())
+}
+
+#[cfg(not(feature = "scene"))]
+pub fn scene_diff(_a: &str, _b: &str) -> Result<(), String> {
+    Err("comparing scene files needs the scene feature".to_string())
+}
+
+pub fn analyze(image: &str) -> Result<(), ImageError> {
+    let image = read_image(image)?;
+    eprintln!("{}", LuminanceHistogram::new(&image));
+    analysis::false_color(&image).write_ppm(&mut io::stdout().lock())?;
+    Ok(())
+}
+
+pub fn convergence(options: &ConvergenceOptions) -> Result<(), String> {
+    let (world, camera) = scene(&options.render)?;
+
+    let reference = match &options.reference {
+        Some(path) => read_image(path).map_err(|error| format!("{}: {}", path, error))?,
+        None => camera
+            .clone()
+            .samples_per_pixel(options.max_samples * 4)
+            .build()
+            .render_frame(&world),
+    };
+
+    let mut out = io::stdout().lock();
+    writeln!(out, "samples,rmse,ssim,seconds").map_err(|error| error.to_string())?;
+    for point in compare::convergence(&camera, &world, options.max_samples, &reference) {
+        let point = point.map_err(|error| error.to_string())?;
+        writeln!(
+            out,
+            "{},{:.6},{:.6},{:.3}",
+            point.samples, point.rmse, point.ssim, point.seconds
+        )
+        .map_err(|error| error.to_string())?;
+    }
+    Ok(())
+}
+
+pub fn bake(options: &BakeOptions) -> Result<(), String> {
+    let (objects, camera) = scene_objects(&options.render)?;
+    let (baked, report) = camera
+        .build()
+        .bake_object(
+            objects,
+            options.object,
+            options.size,
+            options.mode,
+            !options.render.keep_degenerate,
+        )
+        .map_err(|error| error.to_string())?;
+    eprintln!("{}", report);
+    print_diagnostics(&baked);
+    baked
+        .write_ppm(&mut io::stdout().lock())
+        .map_err(|error| error.to_string())
+}
@@ -0,0 +1,252 @@
+//! Numeric and visual comparison of two renders of the same scene.
+
+use crate::camera::CameraBuilder;
+use crate::color::Color;
+use crate::framebuffer::{Framebuffer, ImageError};
+use crate::hittable::Hittable;
+use std::iter;
+use std::time::Instant;
+
+/// Side length of the square windows SSIM is averaged over.
+const SSIM_WINDOW: usize = 8;
+/// SSIM stabilizing constants for values in [0, 1].
+const SSIM_C1: f64 = 0.01 * 0.01;
+const SSIM_C2: f64 = 0.03 * 0.03;
+
+/// Error metrics between two images, computed on display (gamma encoded) values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiffReport {
+    /// Root mean square error over every color channel
+    pub rmse: f64,
+    /// Largest per-pixel error, which the heatmap is normalized to
+    pub max_error: f64,
+    /// Mean structural similarity of the luminance, 1.0 for identical images
+    pub ssim: f64,
+}
+
+/// Compares two images, returning error metrics and a heatmap of where they differ.
+///
+/// The heatmap runs from black (identical) through red and yellow to white at the
+/// largest error in the image.
+pub fn diff(a: &Framebuffer, b: &Framebuffer) -> Result<(DiffReport, Framebuffer), ImageError> {
+    if a.width() != b.width() || a.height() != b.height() {
+        return Err(ImageError::SizeMismatch);
+    }
+
+    let a_display: Vec<Color> = a.pixels().iter().map(display).collect();
+    let b_display: Vec<Color> = b.pixels().iter().map(display).collect();
+
+    let errors: Vec<f64> = a_display
+        .iter()
+        .zip(&b_display)
+        .map(|(a, b)| {
+            let (dr, dg, db) = (a.r() - b.r(), a.g() - b.g(), a.b() - b.b());
+            ((dr * dr + dg * dg + db * db) / 3.0).sqrt()
+        })
+        .collect();
+
+    let pixel_count = errors.len().max(1) as f64;
+    let rmse = (errors.iter().map(|e| e * e).sum::<f64>() / pixel_count).sqrt();
+    let max_error = errors.iter().copied().fold(0.0, f64::max);
+
+    let heatmap = errors
+        .iter()
+        .map(|&error| {
+            let t = if max_error > 0.0 {
+                error / max_error
+            } else {
+                0.0
+            };
+            Color::heat(t)
+        })
+        .collect();
+
+    let report = DiffReport {
+        rmse,
+        max_error,
+        ssim: ssim(&a_display, &b_display, a.width() as usize),
+    };
+    Ok((report, Framebuffer::new(a.width(), a.height(), heatmap)))
+}
+
+/// Converts a linear color to the clamped, gamma encoded values that end up on screen.
+fn display(color: &Color) -> Color {
+    let encode = |c: f64| Color::linear_to_gamma(c).min(1.0);
+    Color::new(encode(color.r()), encode(color.g()), encode(color.b()))
+}
+
+/// Mean SSIM of the luminance over non-overlapping windows.
+fn ssim(a: &[Color], b: &[Color], width: usize) -> f64 {
+    if a.is_empty() {
+        return 1.0;
+    }
+    let height = a.len() / width;
+    let mut total = 0.0;
+    let mut windows = 0;
+
+    for y0 in (0..height).step_by(SSIM_WINDOW) {
+        for x0 in (0..width).step_by(SSIM_WINDOW) {
+            let indices: Vec<usize> = (y0..(y0 + SSIM_WINDOW).min(height))
+                .flat_map(|y| (x0..(x0 + SSIM_WINDOW).min(width)).map(move |x| y * width + x))
+                .collect();
+            let n = indices.len() as f64;
+
+            let mean_a = indices.iter().map(|&i| a[i].luminance()).sum::<f64>() / n;
+            let mean_b = indices.iter().map(|&i| b[i].luminance()).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+            for &i in &indices {
+                let da = a[i].luminance() - mean_a;
+                let db = b[i].luminance() - mean_b;
+                var_a += da * da;
+                var_b += db * db;
+                covariance += da * db;
+            }
+            var_a /= n;
+            var_b /= n;
+            covariance /= n;
+
+            total += ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (var_a + var_b + SSIM_C2));
+            windows += 1;
+        }
+    }
+
+    total / windows as f64
+}
+
+/// How close a render with some number of samples per pixel came to a reference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConvergencePoint {
+    pub samples: u32,
+    pub rmse: f64,
+    pub ssim: f64,
+    /// Time the render took, not counting the comparison
+    pub seconds: f64,
+}
+
+/// Renders `world` through `camera` at 1, 2, 4, ... samples per pixel up to
+/// `max_samples`, comparing each render to `reference` as it finishes.
+///
+/// Renders happen as the iterator is advanced, so a caller can report each point
+/// before the next, slower render starts.
+pub fn convergence<'a>(
+    camera: &'a CameraBuilder,
+    world: &'a dyn Hittable,
+    max_samples: u32,
+    reference: &'a Framebuffer,
+) -> impl Iterator<Item = Result<ConvergencePoint, ImageError>> + 'a {
+    iter::successors(Some(1), |&n: &u32| n.checked_mul(2))
+        .take_while(move |&n| n <= max_samples)
+        .map(move |samples| {
+            let start = Instant::now();
+            let frame = camera
+                .clone()
+                .samples_per_pixel(samples)
+                .build()
+                .render_frame(world);
+            let seconds = start.elapsed().as_secs_f64();
+
+            let (report, _) = diff(&frame, reference)?;
+            Ok(ConvergencePoint {
+                samples,
+                rmse: report.rmse,
+                ssim: report.ssim,
+                seconds,
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> Framebuffer {
+        let pixels = (0..width * height)
+            .map(|i| {
+                let v = i as f64 / (width * height) as f64;
+                Color::new(v, v, v)
+            })
+            .collect();
+        Framebuffer::new(width, height, pixels)
+    }
+
+    #[test]
+    fn test_identical_images() {
+        let image = gradient(16, 16);
+        let (report, heatmap) = diff(&image, &image).unwrap();
+        assert_eq!(report.rmse, 0.0);
+        assert_eq!(report.max_error, 0.0);
+        assert!((report.ssim - 1.0).abs() < 1e-12);
+        assert!(
+            heatmap
+                .pixels()
+                .iter()
+                .all(|&p| p == Color::new(0.0, 0.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn test_different_images() {
+        let white = Framebuffer::new(2, 1, vec![Color::new(1.0, 1.0, 1.0); 2]);
+        let half = Framebuffer::new(
+            2,
+            1,
+            vec![Color::new(1.0, 1.0, 1.0), Color::new(0.0, 0.0, 0.0)],
+        );
+        let (report, heatmap) = diff(&white, &half).unwrap();
+        assert!((report.rmse - 0.5f64.sqrt()).abs() < 1e-12);
+        assert_eq!(report.max_error, 1.0);
+        assert!(report.ssim < 1.0);
+        // The unchanged pixel is black and the largest error is white
+        assert_eq!(heatmap.pixels()[0], Color::new(0.0, 0.0, 0.0));
+        assert_eq!(heatmap.pixels()[1], Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_noise_lowers_ssim() {
+        let image = gradient(16, 16);
+        let mut noisy = image.clone();
+        for (i, pixel) in noisy.pixels_mut().iter_mut().enumerate() {
+            if i % 2 == 0 {
+                *pixel = Color::new(1.0, 1.0, 1.0);
+            }
+        }
+        let (report, _) = diff(&image, &noisy).unwrap();
+        assert!(report.ssim < 0.5, "ssim was {}", report.ssim);
+    }
+
+    #[test]
+    fn test_convergence_doubles_samples_up_to_the_limit() {
+        use crate::material::Lambertian;
+        use crate::point3::Point3;
+        use crate::sphere::SphereBuilder;
+
+        let world = SphereBuilder::new()
+            .center(Point3::new(0.0, 0.0, -1.0))
+            .radius(0.5)
+            .material(Lambertian::clay())
+            .build()
+            .unwrap();
+        let camera = CameraBuilder::new().image_width(8).aspect_ratio(1.0);
+        let reference = camera
+            .clone()
+            .samples_per_pixel(64)
+            .build()
+            .render_frame(&world);
+
+        let points = convergence(&camera, &world, 12, &reference)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let samples: Vec<_> = points.iter().map(|point| point.samples).collect();
+        assert_eq!(samples, vec![1, 2, 4, 8]);
+        assert!(points.iter().all(|point| point.rmse.is_finite()));
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        assert!(matches!(
+            diff(&gradient(2, 2), &gradient(2, 1)),
+            Err(ImageError::SizeMismatch)
+        ));
+    }
+}
@@ -0,0 +1,273 @@
+//! Default render options from a `raytracing.toml` file and the environment.
+//!
+//! Each key names a render option by its long flag, so `display = "agx"` in the
+//! file or `RAYTRACE_DISPLAY=agx` in the environment acts like `--display agx`.
+//! Switches take `true` or `false`, and an array repeats a flag such as `set`.
+//! Strings are quoted as in TOML: `"..."` with backslash escapes, or `'...'` taken
+//! literally, which suits Windows paths. Tables are not supported.
+//! The file is read from the working directory, or from `RAYTRACE_CONFIG` when
+//! that is set. The environment overrides the file, and the command line both.
+
+use std::env;
+use std::fs;
+use std::io;
+
+const CONFIG_FILE: &str = "raytracing.toml";
+/// Environment variable naming a config file to read instead.
+const CONFIG_VAR: &str = "RAYTRACE_CONFIG";
+const ENV_PREFIX: &str = "RAYTRACE_";
+
+/// Reads the default flags from the config file and the environment, in the order
+/// they should be applied.
+pub fn defaults() -> Result<Vec<String>, String> {
+    let explicit = env::var(CONFIG_VAR).ok();
+    let path = explicit.clone().unwrap_or_else(|| CONFIG_FILE.to_string());
+    let mut args = match fs::read_to_string(&path) {
+        Ok(text) => parse_config(&text).map_err(|error| format!("{}: {}", path, error))?,
+        // Only a config file that was asked for has to exist
+        Err(error) if error.kind() == io::ErrorKind::NotFound && explicit.is_none() => Vec::new(),
+        Err(error) => return Err(format!("failed to read {}: {}", path, error)),
+    };
+    args.extend(env_args(env::vars()));
+    Ok(args)
+}
+
+/// Turns `key = value` lines into flags, ignoring blank lines and `#` comments.
+pub fn parse_config(text: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |reason: &str| format!("line {}: {}", number + 1, reason);
+        if line.starts_with('[') {
+            return Err(error(&format!(
+                "tables are not supported, so {} cannot be read; put its keys at the top of the file",
+                line.split('#').next().unwrap_or(line).trim_end()
+            )));
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected KEY = VALUE"))?;
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(error(&format!("invalid key '{}'", key)));
+        }
+
+        let values = parse_value(value.trim()).map_err(|reason| error(&reason))?;
+        for value in values {
+            args.extend(flag(key, &value));
+        }
+    }
+    Ok(args)
+}
+
+/// Splits a value into its items: one for a scalar, any number for an array.
+fn parse_value(value: &str) -> Result<Vec<String>, String> {
+    let (items, rest) = match value.strip_prefix('[') {
+        Some(array) => {
+            let mut items = Vec::new();
+            let mut rest = array.trim_start();
+            loop {
+                if let Some(after) = rest.strip_prefix(']') {
+                    break (items, after);
+                }
+                let (item, after) = parse_scalar(rest)?;
+                items.push(item);
+                let after = after.trim_start();
+                rest = match after.strip_prefix(',') {
+                    Some(after) => after.trim_start(),
+                    None if after.starts_with(']') => after,
+                    None => return Err("expected ',' or ']' in array".to_string()),
+                };
+            }
+        }
+        None => {
+            let (item, rest) = parse_scalar(value)?;
+            (vec![item], rest)
+        }
+    };
+
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(items)
+    } else {
+        Err(format!("unexpected '{}' after value", rest))
+    }
+}
+
+/// Reads a quoted string or a bare number or boolean from the start of `text`,
+/// returning it and what follows.
+fn parse_scalar(text: &str) -> Result<(String, &str), String> {
+    if let Some(quoted) = text.strip_prefix('"') {
+        return parse_basic_string(quoted);
+    }
+    if let Some(quoted) = text.strip_prefix('\'') {
+        let end = quoted.find('\'').ok_or("unterminated string")?;
+        return Ok((quoted[..end].to_string(), &quoted[end + 1..]));
+    }
+    let end = text
+        .find(|c: char| c == ',' || c == ']' || c == '#' || c.is_whitespace())
+        .unwrap_or(text.len());
+    if end == 0 {
+        return Err("missing value".to_string());
+    }
+    Ok((text[..end].to_string(), &text[end..]))
+}
+
+/// Reads a double-quoted string up to its closing quote, replacing its escapes,
+/// and returns it and what follows.
+fn parse_basic_string(quoted: &str) -> Result<(String, &str), String> {
+    let mut string = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((string, &quoted[index + 1..])),
+            '\\' => {
+                let (_, escape) = chars.next().ok_or("unterminated string")?;
+                string.push(match escape {
+                    '\\' => '\\',
+                    '"' => '"',
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'u' | 'U' => {
+                        let digits = if escape == 'u' { 4 } else { 8 };
+                        let hex: String = chars.by_ref().take(digits).map(|(_, c)| c).collect();
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .filter(|_| hex.len() == digits)
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| format!("invalid escape '\\{}{}'", escape, hex))?
+                    }
+                    _ => {
+                        return Err(format!(
+                            "invalid escape '\\{}'; write '\\\\' for a backslash or use single quotes",
+                            escape
+                        ));
+                    }
+                });
+            }
+            _ => string.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+/// Collects flags from `RAYTRACE_*` variables, sorted so they apply in a fixed order.
+fn env_args(vars: impl Iterator<Item = (String, String)>) -> Vec<String> {
+    let mut vars: Vec<_> = vars
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX) && name != CONFIG_VAR)
+        .collect();
+    vars.sort();
+    vars.iter()
+        .flat_map(|(name, value)| flag(&name[ENV_PREFIX.len()..].to_lowercase(), value))
+        .collect()
+}
+
+/// Returns the flag for `key` set to `value`: alone for `true`, nothing for `false`.
+fn flag(key: &str, value: &str) -> Vec<String> {
+    let flag = format!("--{}", key.replace('_', "-"));
+    match value {
+        "true" => vec![flag],
+        "false" => Vec::new(),
+        _ => vec![flag, value.to_string()],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = "\
+# Studio defaults
+display = \"agx\"
+supersample = 2   # antialias edges
+nan_guard = true
+clay = false
+set = [\"camera.look_from=0,2,8\", \"camera.samples_per_pixel=64\"]
+";
+        assert_eq!(
+            parse_config(config),
+            Ok([
+                "--display",
+                "agx",
+                "--supersample",
+                "2",
+                "--nan-guard",
+                "--set",
+                "camera.look_from=0,2,8",
+                "--set",
+                "camera.samples_per_pixel=64",
+            ]
+            .map(String::from)
+            .to_vec())
+        );
+
+        assert_eq!(
+            parse_config("[render] # studio"),
+            Err(
+                "line 1: tables are not supported, so [render] cannot be read; \
+                 put its keys at the top of the file"
+                    .to_string()
+            )
+        );
+        assert!(parse_config("\ndisplay").unwrap_err().starts_with("line 2"));
+        assert!(parse_config("display = \"agx").is_err());
+        assert!(parse_config("set = [\"a\" \"b\"]").is_err());
+        assert!(parse_config("threads = 4 4").is_err());
+    }
+
+    #[test]
+    fn test_strings() {
+        let value = |text: &str| parse_value(text).map(|items| items.join("|"));
+        assert_eq!(
+            value(r#""C:\\scenes\\a.json""#),
+            Ok(r"C:\scenes\a.json".to_string())
+        );
+        assert_eq!(
+            value(r"'C:\scenes\a.json'"),
+            Ok(r"C:\scenes\a.json".to_string())
+        );
+        assert_eq!(
+            value(r#"["say \"hi\"\t\u00e9\U0001F600", 'a"b']"#),
+            Ok("say \"hi\"\té😀|a\"b".to_string())
+        );
+        assert_eq!(value(r#""a\\" # done"#), Ok(r"a\".to_string()));
+
+        assert!(
+            value(r#""C:\scenes""#)
+                .unwrap_err()
+                .starts_with(r"invalid escape '\s'")
+        );
+        assert!(value(r#""\u00""#).is_err());
+        assert!(value(r#""\uD800""#).is_err());
+        assert!(value(r#""a\""#).is_err());
+        assert!(value("'a").is_err());
+    }
+
+    #[test]
+    fn test_env_args() {
+        let vars = [
+            ("RAYTRACE_THREADS", "4"),
+            ("RAYTRACE_CONFIG", "studio.toml"),
+            ("HOME", "/root"),
+            ("RAYTRACE_OUTPUT_DIR", "renders"),
+            ("RAYTRACE_CLAY", "true"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        assert_eq!(
+            env_args(vars.into_iter()),
+            ["--clay", "--output-dir", "renders", "--threads", "4"]
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Display transforms turning linear scene colors into values for a screen.
+//!
+//! The transform is the last step of the color pipeline, applied as the image is
+//! written. Images record the transform they were written with, so the ones that
+//! can be inverted are decoded back to linear when read for merging or comparison.
+//!
+//! The AgX view follows the minimal fit by Benjamin Wrensch of Troy Sobotka's AgX:
+//! colors are inset towards neutral, log encoded over a fixed range of stops, passed
+//! through a sigmoid contrast curve and outset again, so bright saturated lights
+//! bleach towards white instead of clipping to a flat primary.
+
+use crate::color::{Color, ColorSpace};
+
+/// Stops below middle gray mapped to black by AgX.
+const AGX_MIN_EV: f64 = -12.47393;
+/// Stops above middle gray mapped to white by AgX.
+const AGX_MAX_EV: f64 = 4.026069;
+
+/// How linear colors are encoded for display.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DisplayTransform {
+    /// A plain 2.0 gamma, the renderer's historical output
+    #[default]
+    Gamma2,
+    /// The piecewise sRGB curve, as expected by most monitors and web browsers
+    Srgb,
+    /// Rec. 709 primaries with the BT.1886 2.4 gamma of broadcast displays
+    Rec709,
+    /// Wide-gamut Display P3 primaries with the sRGB curve, as on recent Apple screens
+    DisplayP3,
+    /// A filmic view that rolls highlights off smoothly; cannot be decoded
+    AgX,
+}
+
+impl DisplayTransform {
+    /// Every transform, for parsing and listing.
+    pub const ALL: [DisplayTransform; 5] = [
+        DisplayTransform::Gamma2,
+        DisplayTransform::Srgb,
+        DisplayTransform::Rec709,
+        DisplayTransform::DisplayP3,
+        DisplayTransform::AgX,
+    ];
+
+    /// Returns the name used on the command line and in image headers.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisplayTransform::Gamma2 => "gamma2",
+            DisplayTransform::Srgb => "srgb",
+            DisplayTransform::Rec709 => "rec709",
+            DisplayTransform::DisplayP3 => "p3",
+            DisplayTransform::AgX => "agx",
+        }
+    }
+
+    /// Looks a transform up by its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|transform| transform.name() == name)
+    }
+
+    /// Encodes a linear Rec. 709 color as display values in [0, 1].
+    pub fn encode(&self, color: Color) -> Color {
+        match self {
+            DisplayTransform::Gamma2 => map(color, Color::linear_to_gamma),
+            DisplayTransform::Srgb => map(color, srgb_encode),
+            DisplayTransform::Rec709 => map(color, |c| c.max(0.0).powf(1.0 / 2.4)),
+            DisplayTransform::DisplayP3 => map(
+                transform(
+                    color,
+                    [
+                        [0.8224621, 0.1775380, 0.0],
+                        [0.0331941, 0.9668058, 0.0],
+                        [0.0170827, 0.0723974, 0.9105199],
+                    ],
+                ),
+                srgb_encode,
+            ),
+            DisplayTransform::AgX => agx(color),
+        }
+        .clamped()
+    }
+
+    /// Decodes display values back to a linear Rec. 709 color.
+    ///
+    /// Returns `None` for AgX, whose curve discards the highlight detail needed to
+    /// recover the original radiance.
+    pub fn decode(&self, encoded: Color) -> Option<Color> {
+        match self {
+            DisplayTransform::Gamma2 => Some(map(encoded, Color::gamma_to_linear)),
+            DisplayTransform::Srgb => Some(ColorSpace::Srgb.to_linear(encoded)),
+            DisplayTransform::Rec709 => Some(map(encoded, |c| c.powf(2.4))),
+            DisplayTransform::DisplayP3 => Some(transform(
+                ColorSpace::Srgb.to_linear(encoded),
+                [
+                    [1.2249401, -0.2249404, 0.0],
+                    [-0.0420569, 1.0420571, 0.0],
+                    [-0.0196376, -0.0786361, 1.0982735],
+                ],
+            )),
+            DisplayTransform::AgX => None,
+        }
+    }
+}
+
+fn map(color: Color, f: impl Fn(f64) -> f64) -> Color {
+    Color::new(f(color.r()), f(color.g()), f(color.b()))
+}
+
+/// Multiplies `color` by a row-major 3×3 matrix.
+fn transform(color: Color, matrix: [[f64; 3]; 3]) -> Color {
+    let row = |r: [f64; 3]| r[0] * color.r() + r[1] * color.g() + r[2] * color.b();
+    Color::new(row(matrix[0]), row(matrix[1]), row(matrix[2]))
+}
+
+fn srgb_encode(linear: f64) -> f64 {
+    let linear = linear.max(0.0);
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Applies the AgX base view, returning display values.
+fn agx(color: Color) -> Color {
+    let inset = transform(
+        color,
+        [
+            [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+            [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+            [0.0423756549057051, 0.0784336, 0.879142973793104],
+        ],
+    );
+    let curved = map(inset, |c| {
+        let log = c
+            .max(f64::MIN_POSITIVE)
+            .log2()
+            .clamp(AGX_MIN_EV, AGX_MAX_EV);
+        let x = (log - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+        // Polynomial fit of the AgX sigmoid
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+    transform(
+        curved,
+        [
+            [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+            [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+            [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_round_trip() {
+        for transform in DisplayTransform::ALL {
+            assert_eq!(
+                DisplayTransform::from_name(transform.name()),
+                Some(transform)
+            );
+        }
+        assert_eq!(DisplayTransform::from_name("aces"), None);
+    }
+
+    #[test]
+    fn test_decode_inverts_encode() {
+        let color = Color::new(0.02, 0.18, 0.7);
+        for transform in DisplayTransform::ALL {
+            let Some(decoded) = transform.decode(transform.encode(color)) else {
+                assert_eq!(transform, DisplayTransform::AgX);
+                continue;
+            };
+            for (a, b) in [
+                (decoded.r(), color.r()),
+                (decoded.g(), color.g()),
+                (decoded.b(), color.b()),
+            ] {
+                assert!((a - b).abs() < 1e-6, "{:?} gave {}", transform, decoded);
+            }
+        }
+    }
+
+    #[test]
+    fn test_white_stays_white() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        for transform in [
+            DisplayTransform::Gamma2,
+            DisplayTransform::Srgb,
+            DisplayTransform::Rec709,
+            DisplayTransform::DisplayP3,
+        ] {
+            let encoded = transform.encode(white);
+            for c in [encoded.r(), encoded.g(), encoded.b()] {
+                assert!((c - 1.0).abs() < 1e-6, "{:?} gave {}", transform, encoded);
+            }
+        }
+    }
+
+    #[test]
+    fn test_agx_rolls_off_highlights() {
+        let agx = |v: f64| DisplayTransform::AgX.encode(Color::new(v, v, v));
+        assert!(agx(0.0).r() < 0.01);
+        // Brightness keeps increasing well past where the other transforms clip
+        assert!(agx(1.0).g() < agx(4.0).g() && agx(4.0).g() < agx(16.0).g());
+        assert!(agx(16.0).g() <= 1.0);
+        // A saturated red light bleaches rather than staying pure red
+        let red = DisplayTransform::AgX.encode(Color::new(50.0, 0.0, 0.0));
+        assert!(red.g() > 0.3, "{}", red);
+    }
+}
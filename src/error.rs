@@ -0,0 +1,153 @@
+//! The crate's error type, so code embedding the renderer can handle every
+//! failure through one type.
+//!
+//! Each module keeps its own error type for callers that want to match on it in
+//! detail; all of them convert into [`Error`] with `?`.
+
+use crate::bvh::BvhError;
+use crate::camera::BakeError;
+use crate::framebuffer::ImageError;
+use crate::lut::CubeError;
+use crate::mesh::ObjError;
+#[cfg(feature = "scene")]
+use crate::scene::SceneError;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The scene's objects could not be organized into a BVH
+    Bvh(BvhError),
+    /// An image could not be read, written or combined
+    Image(ImageError),
+    /// A mesh file could not be parsed
+    Obj(ObjError),
+    /// A lookup table file could not be parsed
+    Cube(CubeError),
+    Io(io::Error),
+    /// A builder was missing a required setting
+    Build(&'static str),
+    /// An object could not be baked into its texture space
+    Bake(BakeError),
+    /// A scene file could not be read or built, boxed because it can hold an
+    /// [`Error`] of its own
+    #[cfg(feature = "scene")]
+    Scene(Box<SceneError>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Bvh(e) => write!(f, "Failed to build BVH: {}", e),
+            Error::Image(e) => write!(f, "{}", e),
+            Error::Obj(e) => write!(f, "Failed to parse OBJ: {}", e),
+            Error::Cube(e) => write!(f, "Failed to parse LUT: {}", e),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Build(reason) => write!(f, "Invalid object: {}", reason),
+            Error::Bake(e) => write!(f, "{}", e),
+            #[cfg(feature = "scene")]
+            Error::Scene(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Bvh(e) => Some(e),
+            Error::Image(e) => Some(e),
+            Error::Obj(e) => Some(e),
+            Error::Cube(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Build(_) => None,
+            Error::Bake(e) => Some(e),
+            #[cfg(feature = "scene")]
+            Error::Scene(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+impl From<BvhError> for Error {
+    fn from(error: BvhError) -> Self {
+        Error::Bvh(error)
+    }
+}
+
+impl From<BakeError> for Error {
+    fn from(error: BakeError) -> Self {
+        Error::Bake(error)
+    }
+}
+
+#[cfg(feature = "scene")]
+impl From<SceneError> for Error {
+    fn from(error: SceneError) -> Self {
+        Error::Scene(Box::new(error))
+    }
+}
+
+impl From<ImageError> for Error {
+    fn from(error: ImageError) -> Self {
+        Error::Image(error)
+    }
+}
+
+impl From<ObjError> for Error {
+    fn from(error: ObjError) -> Self {
+        Error::Obj(error)
+    }
+}
+
+impl From<CubeError> for Error {
+    fn from(error: CubeError) -> Self {
+        Error::Cube(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::Bvh;
+    use std::error::Error as _;
+
+    #[test]
+    fn test_errors_convert_with_question_mark() {
+        fn empty_scene() -> Result<Bvh, Error> {
+            Ok(Bvh::new(Vec::new())?)
+        }
+        let Err(error) = empty_scene() else {
+            panic!("built a BVH over nothing");
+        };
+        assert!(matches!(error, Error::Bvh(BvhError::EmptyObjectList)));
+        assert_eq!(
+            error.to_string(),
+            "Failed to build BVH: Cannot create BVH from empty object list"
+        );
+        assert!(error.source().is_some());
+
+        let error = Error::from(ImageError::SizeMismatch);
+        assert_eq!(error.to_string(), "Images have different dimensions");
+        assert!(Error::Build("sphere has no material").source().is_none());
+    }
+
+    #[cfg(feature = "scene")]
+    #[test]
+    fn test_scene_errors_convert_with_question_mark() {
+        fn load_missing() -> Result<(), Error> {
+            crate::scene::load("no/such/scene.json")?;
+            Ok(())
+        }
+        let Err(error) = load_missing() else {
+            panic!("loaded a scene that does not exist");
+        };
+        assert!(matches!(&error, Error::Scene(e) if matches!(**e, SceneError::Io(_))));
+        assert!(error.to_string().starts_with("I/O error"));
+        assert!(error.source().is_some());
+    }
+}
@@ -0,0 +1,25 @@
+//! Float functions that `core` lacks, from libm when the standard library is off.
+
+/// Brings the standard library's float methods back into scope under `no_std`.
+pub(crate) trait Float {
+    fn sqrt(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+}
+
+impl Float for f64 {
+    #[inline]
+    fn sqrt(self) -> f64 {
+        libm::sqrt(self)
+    }
+
+    #[inline]
+    fn sin(self) -> f64 {
+        libm::sin(self)
+    }
+
+    #[inline]
+    fn cos(self) -> f64 {
+        libm::cos(self)
+    }
+}
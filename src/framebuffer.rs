@@ -0,0 +1,1303 @@
+use crate::color::Color;
+use crate::display::DisplayTransform;
+use crate::lut::Lut;
+use crate::point3::Point3;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// PPM header comment recording how many samples each pixel received.
+const SAMPLES_COMMENT: &str = "# samples";
+/// PPM header comment recording the seed the scene was generated from.
+const SCENE_SEED_COMMENT: &str = "# scene seed";
+/// PPM header comment naming the display transform the pixels were encoded with.
+const DISPLAY_COMMENT: &str = "# display";
+/// PPM header comment naming the lookup table applied after the display transform.
+const LUT_COMMENT: &str = "# lut";
+/// PPM header comment recording the tile size and average samples per pixel of each
+/// tile, when tiles were given a time budget.
+const TILE_SAMPLES_COMMENT: &str = "# tile samples";
+/// Appended to the name of an image being written before it replaces the old one.
+const PARTIAL_SUFFIX: &str = ".partial";
+/// Radius of the downsampling filter, in output pixels.
+const DOWNSAMPLE_RADIUS: f64 = 2.0;
+/// Black border between the images of a contact sheet, in pixels.
+const CONTACT_SHEET_GAP: u32 = 4;
+
+/// A rendered image together with its auxiliary output variables (AOVs).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    samples_per_pixel: u32,
+    /// Seed the scene's random arrangement was generated from, if known
+    scene_seed: Option<u64>,
+    /// How the pixels are encoded when the image is written
+    display: DisplayTransform,
+    /// Look applied to the display values as the image is written
+    lut: Option<Arc<Lut>>,
+    pixels: Vec<Color>,
+    /// Average distance from the camera to the first surface hit in each pixel,
+    /// `f64::INFINITY` where every sample escaped to the background
+    depth: Option<Vec<f64>>,
+    /// Fraction of each pixel's samples that hit a surface rather than the background
+    alpha: Option<Vec<f64>>,
+    /// Samples each tile actually took, when they stopped at a time budget
+    tile_samples: Option<TileSamples>,
+    /// Set when the image was read from a display-encoded file with channels at
+    /// their maximum value, which may stand for anything brighter
+    clipped: bool,
+    /// What the render noticed along the way, for the caller to report
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// How many samples the tiles of a render took when each had a time budget, so
+/// tiles that ran out of time can be told apart from those that finished.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileSamples {
+    /// Width and height of the tiles, in the pixels the image was rendered at before
+    /// any downsampling
+    pub tile_size: u32,
+    /// Average samples per pixel of each tile, in rows from the top left
+    pub samples: Vec<f64>,
+}
+
+/// Something a render noticed that whoever started it may want to know.
+///
+/// The renderer never prints; it attaches these to the image it returns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diagnostic {
+    /// A sample's radiance or throughput stopped being finite at a surface, so the
+    /// NaN guard dropped the rest of its path
+    NonFinite {
+        pixel: (u32, u32),
+        sample: u32,
+        bounce: u32,
+        /// The first non-finite value: the radiance if that was not finite, else
+        /// the throughput
+        value: Color,
+        material: &'static str,
+        position: Point3,
+    },
+    /// Pixels took fewer samples than the camera allows, stopped by adaptive
+    /// sampling or a time budget
+    SamplesTaken { average: f64, max: u32 },
+    /// A path guide was trained before the render
+    PathGuide { samples: u32, cells: usize },
+    /// The totals behind a BVH cost heatmap, per camera sample
+    BvhCost {
+        average_visits: f64,
+        max_visits: f64,
+        average_tests: f64,
+    },
+    /// The totals behind a time heatmap, in milliseconds
+    PixelTime {
+        average: f64,
+        max: f64,
+        slowest: (u32, u32),
+    },
+    /// The camera's progress reporter could not pass on the render's progress
+    ProgressNotReported(String),
+}
+
+impl Diagnostic {
+    /// Returns true for diagnostics that point at a problem with the scene or the
+    /// render's setup rather than describe the render.
+    pub fn is_warning(&self) -> bool {
+        matches!(
+            self,
+            Diagnostic::NonFinite { .. } | Diagnostic::ProgressNotReported(_)
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::NonFinite {
+                pixel,
+                sample,
+                bounce,
+                value,
+                material,
+                position,
+            } => write!(
+                f,
+                "non-finite radiance {} at pixel ({}, {}) sample {}, bounce {}: {} at {}",
+                value, pixel.0, pixel.1, sample, bounce, material, **position
+            ),
+            Diagnostic::SamplesTaken { average, max } => write!(
+                f,
+                "Took {:.1} samples per pixel on average, of at most {}",
+                average, max
+            ),
+            Diagnostic::PathGuide { samples, cells } => write!(
+                f,
+                "Trained path guide on {} samples per pixel, covering {} cells",
+                samples, cells
+            ),
+            Diagnostic::BvhCost {
+                average_visits,
+                max_visits,
+                average_tests,
+            } => write!(
+                f,
+                "BVH cost per sample: {:.1} node visits on average, {:.1} at most, {:.1} intersection tests on average",
+                average_visits, max_visits, average_tests
+            ),
+            Diagnostic::PixelTime {
+                average,
+                max,
+                slowest,
+            } => write!(
+                f,
+                "Time per pixel: {:.3} ms on average, {:.3} ms at most in pixel ({}, {})",
+                average, max, slowest.0, slowest.1
+            ),
+            Diagnostic::ProgressNotReported(error) => {
+                write!(f, "could not report progress: {}", error)
+            }
+        }
+    }
+}
+
+/// The file formats an image can be written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Plain-text PPM, display encoded, which can be merged and compared later
+    #[default]
+    Ppm,
+    /// Binary PAM with an alpha channel from the surface coverage
+    Pam,
+    /// Binary portable float map of the linear colors, for HDR work
+    Pfm,
+    /// Compressed PNG, display encoded, which any image viewer can open
+    Png,
+    /// OpenEXR of the linear colors in 32-bit floats, with alpha, for compositing
+    Exr,
+    /// Radiance HDR of the linear colors, with a shared exponent per pixel
+    Hdr,
+}
+
+impl ImageFormat {
+    /// Looks a format up by its file extension.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ppm" => Some(ImageFormat::Ppm),
+            "pam" => Some(ImageFormat::Pam),
+            "pfm" => Some(ImageFormat::Pfm),
+            "png" => Some(ImageFormat::Png),
+            "exr" => Some(ImageFormat::Exr),
+            "hdr" => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
+
+    /// Picks the format for a file from its extension, ignoring case.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        Self::from_name(&extension.to_ascii_lowercase())
+    }
+}
+
+/// Picks the format for writing to `path`, failing if its extension names none.
+pub(crate) fn format_for(path: &Path) -> Result<ImageFormat, ImageError> {
+    ImageFormat::from_path(path).ok_or_else(|| {
+        ImageError::InvalidFormat(format!("unknown image format for {}", path.display()))
+    })
+}
+
+/// Returns the display transform a PPM comment `line` names, if it names one.
+///
+/// Fails for a display that is unknown, or for a LUT, which cannot be undone to
+/// decode the image back to linear.
+pub(crate) fn display_comment(line: &str) -> Result<Option<DisplayTransform>, ImageError> {
+    if let Some(title) = line.strip_prefix(LUT_COMMENT) {
+        return Err(ImageError::InvalidFormat(format!(
+            "images with the LUT '{}' cannot be decoded to linear",
+            title.trim()
+        )));
+    }
+    line.strip_prefix(DISPLAY_COMMENT)
+        .map(|name| {
+            DisplayTransform::from_name(name.trim()).ok_or_else(|| {
+                ImageError::InvalidFormat(format!("unknown display '{}'", name.trim()))
+            })
+        })
+        .transpose()
+}
+
+/// Parses the next header or pixel value of a text image.
+fn next_number(tokens: &mut impl Iterator<Item = String>, what: &str) -> Result<u32, ImageError> {
+    let token = tokens
+        .next()
+        .ok_or_else(|| ImageError::InvalidFormat(format!("missing {}", what)))?;
+    token
+        .parse()
+        .map_err(|_| ImageError::InvalidFormat(format!("bad {} '{}'", what, token)))
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    InvalidFormat(String),
+    SizeMismatch,
+    NoImages,
+    /// Images to merge were display encoded with values clipped at white
+    Clipped,
+    /// Images to merge hold no samples, or more than can be counted
+    SampleCount,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io(e) => write!(f, "I/O error: {}", e),
+            ImageError::InvalidFormat(reason) => write!(f, "Invalid image: {}", reason),
+            ImageError::SizeMismatch => write!(f, "Images have different dimensions"),
+            ImageError::NoImages => write!(f, "No images given"),
+            ImageError::Clipped => write!(
+                f,
+                "Images have highlights clipped at white; merge PFM, EXR or HDR renders"
+            ),
+            ImageError::SampleCount => write!(f, "Images have no samples or too many to count"),
+        }
+    }
+}
+
+impl Error for ImageError {}
+
+impl From<io::Error> for ImageError {
+    fn from(error: io::Error) -> Self {
+        ImageError::Io(error)
+    }
+}
+
+impl Framebuffer {
+    /// Creates a framebuffer from row-major pixel data rendered with one sample per pixel.
+    ///
+    /// # Panics
+    /// Panics if `pixels` does not hold exactly `width * height` values.
+    pub fn new(width: u32, height: u32, pixels: Vec<Color>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            width as usize * height as usize,
+            "Pixel buffer does not match dimensions"
+        );
+        Self {
+            width,
+            height,
+            samples_per_pixel: 1,
+            scene_seed: None,
+            display: DisplayTransform::default(),
+            lut: None,
+            pixels,
+            depth: None,
+            alpha: None,
+            tile_samples: None,
+            clipped: false,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Attaches a row-major depth AOV.
+    ///
+    /// Depth is not premultiplied: each pixel holds the average distance of just the
+    /// samples that hit a surface, or infinity if none did, and the alpha AOV the
+    /// fraction that hit. A pixel on a silhouette so has the depth of the surface
+    /// covering part of it, to be weighted by its alpha when composited.
+    ///
+    /// # Panics
+    /// Panics if `depth` does not hold exactly `width * height` values.
+    pub fn with_depth(mut self, depth: Vec<f64>) -> Self {
+        assert_eq!(
+            depth.len(),
+            self.pixels.len(),
+            "Depth buffer does not match dimensions"
+        );
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Attaches a row-major alpha AOV of surface coverage in [0, 1].
+    ///
+    /// # Panics
+    /// Panics if `alpha` does not hold exactly `width * height` values.
+    pub fn with_alpha(mut self, alpha: Vec<f64>) -> Self {
+        assert_eq!(
+            alpha.len(),
+            self.pixels.len(),
+            "Alpha buffer does not match dimensions"
+        );
+        self.alpha = Some(alpha);
+        self
+    }
+
+    /// Records how many samples each pixel received.
+    pub fn with_samples_per_pixel(mut self, samples_per_pixel: u32) -> Self {
+        self.samples_per_pixel = samples_per_pixel;
+        self
+    }
+
+    /// Records the seed the scene was generated from, so it can be recreated.
+    pub fn with_scene_seed(mut self, scene_seed: Option<u64>) -> Self {
+        self.scene_seed = scene_seed;
+        self
+    }
+
+    /// Selects how the pixels are encoded when the image is written.
+    pub fn with_display(mut self, display: DisplayTransform) -> Self {
+        self.display = display;
+        self
+    }
+
+    /// Applies `lut` to the display values as the image is written, for a look such
+    /// as a film stock. Images written with a LUT cannot be decoded back to linear.
+    pub fn with_lut(mut self, lut: Option<Arc<Lut>>) -> Self {
+        self.lut = lut;
+        self
+    }
+
+    /// Records how many samples each tile took when tiles were given a time budget.
+    pub fn with_tile_samples(mut self, tile_samples: Option<TileSamples>) -> Self {
+        self.tile_samples = tile_samples;
+        self
+    }
+
+    /// Attaches what the render noticed, for the caller to report.
+    pub fn with_diagnostics(mut self, diagnostics: Vec<Diagnostic>) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    #[inline]
+    pub fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    #[inline]
+    pub fn scene_seed(&self) -> Option<u64> {
+        self.scene_seed
+    }
+
+    /// The display transform the pixels are encoded with when written.
+    #[inline]
+    pub fn display(&self) -> DisplayTransform {
+        self.display
+    }
+
+    /// The lookup table applied after the display transform, if any.
+    #[inline]
+    pub fn lut(&self) -> Option<&Lut> {
+        self.lut.as_deref()
+    }
+
+    /// How many samples each tile took, if tiles were given a time budget.
+    #[inline]
+    pub fn tile_samples(&self) -> Option<&TileSamples> {
+        self.tile_samples.as_ref()
+    }
+
+    /// What the render noticed along the way, such as non-finite samples or the
+    /// totals behind a heatmap, in the order it noticed them.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Encodes a linear pixel with the display transform and any LUT, as red, green
+    /// and blue bytes.
+    pub fn encode(&self, pixel: Color) -> [u8; 3] {
+        match &self.lut {
+            Some(lut) => Color::display_bytes(lut.apply(self.display.encode(pixel))),
+            None => pixel.to_bytes(self.display),
+        }
+    }
+
+    /// Row-major pixel colors in linear space.
+    #[inline]
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    #[inline]
+    pub fn pixels_mut(&mut self) -> &mut [Color] {
+        &mut self.pixels
+    }
+
+    /// Row-major depth AOV, if it was rendered, not premultiplied by the alpha.
+    #[inline]
+    pub fn depth(&self) -> Option<&[f64]> {
+        self.depth.as_deref()
+    }
+
+    /// Row-major alpha AOV, if it was rendered.
+    #[inline]
+    pub fn alpha(&self) -> Option<&[f64]> {
+        self.alpha.as_deref()
+    }
+
+    /// Writes the image in `format`.
+    pub fn write(&self, format: ImageFormat, out: &mut impl Write) -> io::Result<()> {
+        match format {
+            ImageFormat::Ppm => self.write_ppm(out),
+            ImageFormat::Pam => self.write_pam(out),
+            ImageFormat::Pfm => self.write_pfm(out),
+            ImageFormat::Png => crate::apng::write_png(self, out),
+            ImageFormat::Exr => crate::hdr::write_exr(self, out),
+            ImageFormat::Hdr => crate::hdr::write_hdr(self, out),
+        }
+    }
+
+    /// Writes the image to a file in the format its extension names.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ImageError> {
+        let path = path.as_ref();
+        let format = format_for(path)?;
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(format, &mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Writes the image to a temporary file beside `path` and renames it over `path`,
+    /// so a viewer or web server reading the file never sees a partly written image.
+    pub fn save_replacing(&self, path: impl AsRef<Path>) -> Result<(), ImageError> {
+        let path = path.as_ref();
+        let format = format_for(path)?;
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(PARTIAL_SUFFIX);
+        let partial = PathBuf::from(partial);
+        let mut out = BufWriter::new(File::create(&partial)?);
+        self.write(format, &mut out)?;
+        out.flush()?;
+        drop(out);
+        fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    /// Writes the pixels as a plain-text (P3) PPM image.
+    ///
+    /// The sample count is stored in a header comment so renders can be merged later,
+    /// followed by the scene seed when there is one, the display transform when
+    /// it isn't the default, the title of any LUT, and the samples each tile took
+    /// when tiles had a time budget.
+    pub fn write_ppm(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "P3")?;
+        writeln!(out, "{} {}", SAMPLES_COMMENT, self.samples_per_pixel)?;
+        if let Some(seed) = self.scene_seed {
+            writeln!(out, "{} {}", SCENE_SEED_COMMENT, seed)?;
+        }
+        if self.display != DisplayTransform::default() {
+            writeln!(out, "{} {}", DISPLAY_COMMENT, self.display.name())?;
+        }
+        if let Some(lut) = &self.lut {
+            writeln!(out, "{} {}", LUT_COMMENT, lut.title().unwrap_or("untitled"))?;
+        }
+        if let Some(tiles) = &self.tile_samples {
+            let samples: Vec<String> = tiles.samples.iter().map(f64::to_string).collect();
+            writeln!(
+                out,
+                "{} {} {}",
+                TILE_SAMPLES_COMMENT,
+                tiles.tile_size,
+                samples.join(",")
+            )?;
+        }
+        writeln!(out, "{} {}", self.width, self.height)?;
+        writeln!(out, "255")?;
+        for pixel in &self.pixels {
+            let [r, g, b] = self.encode(*pixel);
+            writeln!(out, "{} {} {}", r, g, b)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the pixels display encoded as a binary PAM image with an alpha channel.
+    ///
+    /// Alpha is the fraction of samples that hit a surface, or opaque everywhere when
+    /// there is no alpha AOV. The colors are not premultiplied and still include
+    /// whatever background the scene was rendered against.
+    pub fn write_pam(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "P7")?;
+        writeln!(out, "WIDTH {}", self.width)?;
+        writeln!(out, "HEIGHT {}", self.height)?;
+        writeln!(out, "DEPTH 4")?;
+        writeln!(out, "MAXVAL 255")?;
+        writeln!(out, "TUPLTYPE RGB_ALPHA")?;
+        writeln!(out, "ENDHDR")?;
+        let mut data = Vec::with_capacity(self.pixels.len() * 4);
+        for (index, pixel) in self.pixels.iter().enumerate() {
+            let alpha = self.alpha.as_ref().map_or(1.0, |alpha| alpha[index]);
+            data.extend(self.encode(*pixel));
+            data.push((alpha.clamp(0.0, 1.0) * 255.0).round() as u8);
+        }
+        out.write_all(&data)
+    }
+
+    /// Writes the linear pixels, before any display transform, as a binary PFM image.
+    ///
+    /// PFM stores rows bottom to top, and a negative scale marks the floats as
+    /// little-endian.
+    pub fn write_pfm(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "PF")?;
+        writeln!(out, "{} {}", self.width, self.height)?;
+        writeln!(out, "-1.0")?;
+        let mut data = Vec::with_capacity(self.pixels.len() * 12);
+        for row in self.pixels.chunks(self.width as usize).rev() {
+            for pixel in row {
+                for c in [pixel.r(), pixel.g(), pixel.b()] {
+                    data.extend((c as f32).to_le_bytes());
+                }
+            }
+        }
+        out.write_all(&data)
+    }
+
+    /// Reads a plain-text (P3) PPM image, converting it back to linear space.
+    ///
+    /// Images without a sample count comment are treated as having one sample per pixel,
+    /// and images without a display comment as gamma 2 encoded. Images encoded with a
+    /// display transform that cannot be inverted, or with a LUT, are rejected.
+    pub fn read_ppm(input: impl BufRead) -> Result<Self, ImageError> {
+        let mut samples_per_pixel = 1;
+        let mut scene_seed = None;
+        let mut display = DisplayTransform::default();
+        let mut tokens = Vec::new();
+        for line in input.lines() {
+            let line = line?;
+            if let Some(count) = line.strip_prefix(SAMPLES_COMMENT) {
+                samples_per_pixel = count.trim().parse().map_err(|_| {
+                    ImageError::InvalidFormat(format!("bad sample count '{}'", count.trim()))
+                })?;
+            }
+            if let Some(seed) = line.strip_prefix(SCENE_SEED_COMMENT) {
+                scene_seed = Some(seed.trim().parse().map_err(|_| {
+                    ImageError::InvalidFormat(format!("bad scene seed '{}'", seed.trim()))
+                })?);
+            }
+            if let Some(named) = display_comment(&line)? {
+                display = named;
+            }
+            let content = line.split('#').next().unwrap_or_default();
+            tokens.extend(content.split_whitespace().map(str::to_string));
+        }
+
+        let mut tokens = tokens.into_iter();
+        if tokens.next().as_deref() != Some("P3") {
+            return Err(ImageError::InvalidFormat("not a P3 PPM image".to_string()));
+        }
+        let width = next_number(&mut tokens, "width")?;
+        let height = next_number(&mut tokens, "height")?;
+        let max_value = next_number(&mut tokens, "maximum value")?;
+        if max_value == 0 {
+            return Err(ImageError::InvalidFormat(
+                "maximum value is zero".to_string(),
+            ));
+        }
+        // Trust the size only as far as the file holds the values for it
+        let pixel_count = (width as usize)
+            .checked_mul(height as usize)
+            .filter(|count| count.checked_mul(3) == Some(tokens.len()))
+            .ok_or_else(|| {
+                ImageError::InvalidFormat(format!(
+                    "{} values for a {}x{} image",
+                    tokens.len(),
+                    width,
+                    height
+                ))
+            })?;
+
+        let mut clipped = false;
+        let mut pixels = Vec::with_capacity(pixel_count);
+        for _ in 0..pixel_count {
+            let r = next_number(&mut tokens, "red")?;
+            let g = next_number(&mut tokens, "green")?;
+            let b = next_number(&mut tokens, "blue")?;
+            clipped |= [r, g, b].iter().any(|&c| c >= max_value);
+            let pixel =
+                Color::from_display_bytes(r, g, b, max_value, display).ok_or_else(|| {
+                    ImageError::InvalidFormat(format!(
+                        "{} images cannot be decoded to linear",
+                        display.name()
+                    ))
+                })?;
+            pixels.push(pixel);
+        }
+
+        let mut framebuffer = Framebuffer::new(width, height, pixels)
+            .with_samples_per_pixel(samples_per_pixel)
+            .with_scene_seed(scene_seed)
+            .with_display(display);
+        framebuffer.clipped = clipped;
+        Ok(framebuffer)
+    }
+
+    /// Reads a binary PFM image of linear colors, as written by
+    /// [`write_pfm`](Self::write_pfm).
+    ///
+    /// Only three-channel images are read. PFM has nowhere to record a sample count,
+    /// so the image is taken to have one sample per pixel.
+    pub fn read_pfm(mut input: impl BufRead) -> Result<Self, ImageError> {
+        let mut header = Vec::new();
+        while header.len() < 4 {
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Err(ImageError::InvalidFormat(
+                    "truncated PFM header".to_string(),
+                ));
+            }
+            header.extend(line.split_whitespace().map(str::to_string));
+        }
+        let mut tokens = header.into_iter();
+        if tokens.next().as_deref() != Some("PF") {
+            return Err(ImageError::InvalidFormat(
+                "not a color PFM image".to_string(),
+            ));
+        }
+        let width = next_number(&mut tokens, "width")?;
+        let height = next_number(&mut tokens, "height")?;
+        let scale: f64 = tokens
+            .next()
+            .and_then(|scale| scale.parse().ok())
+            .ok_or_else(|| ImageError::InvalidFormat("bad PFM scale".to_string()))?;
+
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let row_bytes = (width as usize).checked_mul(12);
+        if row_bytes.and_then(|row| row.checked_mul(height as usize)) != Some(data.len()) {
+            return Err(ImageError::InvalidFormat(format!(
+                "{} bytes of pixels for a {}x{} image",
+                data.len(),
+                width,
+                height
+            )));
+        }
+
+        // A negative scale marks little-endian floats, and rows run bottom to top
+        let float = |bytes: &[u8]| {
+            let bytes = bytes.try_into().expect("four bytes");
+            if scale < 0.0 {
+                f32::from_le_bytes(bytes)
+            } else {
+                f32::from_be_bytes(bytes)
+            }
+        };
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+        if width > 0 {
+            for row in data.chunks(width as usize * 12).rev() {
+                pixels.extend(row.chunks(12).map(|pixel| {
+                    Color::new(
+                        float(&pixel[..4]) as f64,
+                        float(&pixel[4..8]) as f64,
+                        float(&pixel[8..]) as f64,
+                    )
+                }));
+            }
+        }
+        Ok(Framebuffer::new(width, height, pixels))
+    }
+
+    /// Reads an image in the format named by the extension of `path`.
+    ///
+    /// PPM, PFM, OpenEXR and Radiance HDR images can be read, the float formats only
+    /// as this crate writes them.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let path = path.as_ref();
+        let input = BufReader::new(File::open(path)?);
+        match format_for(path)? {
+            ImageFormat::Ppm => Self::read_ppm(input),
+            ImageFormat::Pfm => Self::read_pfm(input),
+            ImageFormat::Exr => crate::hdr::read_exr(input),
+            ImageFormat::Hdr => crate::hdr::read_hdr(input),
+            format @ (ImageFormat::Pam | ImageFormat::Png) => Err(ImageError::InvalidFormat(
+                format!("{:?} images cannot be read", format),
+            )),
+        }
+    }
+
+    /// Merges independent renders of the same scene into one lower-noise image.
+    ///
+    /// Each render is weighted by its sample count, so the result is what a single
+    /// render with the combined number of samples would have converged to. The scene
+    /// seed is kept only if every render agrees on it, and the alpha only if every
+    /// render has one.
+    ///
+    /// Averaging is only right for linear radiance, so renders read from display
+    /// encoded images with highlights clipped at white are refused.
+    pub fn merge(frames: &[Framebuffer]) -> Result<Framebuffer, ImageError> {
+        let first = frames.first().ok_or(ImageError::NoImages)?;
+        if frames
+            .iter()
+            .any(|f| f.width != first.width || f.height != first.height)
+        {
+            return Err(ImageError::SizeMismatch);
+        }
+        if frames.iter().any(|f| f.clipped) {
+            return Err(ImageError::Clipped);
+        }
+
+        let total_samples = frames
+            .iter()
+            .try_fold(0u32, |total, f| total.checked_add(f.samples_per_pixel))
+            .filter(|&total| total > 0)
+            .ok_or(ImageError::SampleCount)?;
+        let weights: Vec<f64> = frames
+            .iter()
+            .map(|f| f.samples_per_pixel as f64 / total_samples as f64)
+            .collect();
+        let mut pixels = vec![Color::new(0.0, 0.0, 0.0); first.pixels.len()];
+        for (frame, &weight) in frames.iter().zip(&weights) {
+            for (merged, pixel) in pixels.iter_mut().zip(&frame.pixels) {
+                *merged += *pixel * weight;
+            }
+        }
+        let alpha = frames.iter().all(|f| f.alpha.is_some()).then(|| {
+            let mut alpha = vec![0.0; first.pixels.len()];
+            for (frame, &weight) in frames.iter().zip(&weights) {
+                let coverage = frame.alpha.as_deref().unwrap_or_default();
+                for (merged, coverage) in alpha.iter_mut().zip(coverage) {
+                    *merged += coverage * weight;
+                }
+            }
+            alpha
+        });
+
+        let scene_seed = first
+            .scene_seed
+            .filter(|&seed| frames.iter().all(|f| f.scene_seed == Some(seed)));
+        let mut merged = Framebuffer::new(first.width, first.height, pixels)
+            .with_samples_per_pixel(total_samples)
+            .with_scene_seed(scene_seed)
+            .with_display(first.display)
+            .with_lut(first.lut.clone());
+        merged.alpha = alpha;
+        Ok(merged)
+    }
+
+    /// Tiles equally sized images left to right and top to bottom, `columns` to a
+    /// row, separated by thin black borders.
+    ///
+    /// The sheet is credited with the samples of the first image and encoded the
+    /// same way, and keeps no depth or alpha.
+    pub fn contact_sheet(frames: &[Framebuffer], columns: u32) -> Result<Framebuffer, ImageError> {
+        let first = frames.first().ok_or(ImageError::NoImages)?;
+        if frames
+            .iter()
+            .any(|f| f.width != first.width || f.height != first.height)
+        {
+            return Err(ImageError::SizeMismatch);
+        }
+
+        let columns = columns.clamp(1, frames.len() as u32);
+        let rows = (frames.len() as u32).div_ceil(columns);
+        let width = columns * first.width + (columns - 1) * CONTACT_SHEET_GAP;
+        let height = rows * first.height + (rows - 1) * CONTACT_SHEET_GAP;
+        let mut pixels = vec![Color::new(0.0, 0.0, 0.0); (width * height) as usize];
+        for (index, frame) in frames.iter().enumerate() {
+            let left = (index as u32 % columns) * (first.width + CONTACT_SHEET_GAP);
+            let top = (index as u32 / columns) * (first.height + CONTACT_SHEET_GAP);
+            for (y, row) in frame.pixels.chunks(first.width as usize).enumerate() {
+                let start = ((top + y as u32) * width + left) as usize;
+                pixels[start..start + row.len()].copy_from_slice(row);
+            }
+        }
+
+        Ok(Framebuffer::new(width, height, pixels)
+            .with_samples_per_pixel(first.samples_per_pixel)
+            .with_scene_seed(first.scene_seed)
+            .with_display(first.display)
+            .with_lut(first.lut.clone()))
+    }
+
+    /// Shrinks an image rendered at `factor` times the resolution to its final size.
+    ///
+    /// Colors are resampled with a separable Mitchell-Netravali filter, which stays
+    /// sharper than averaging each block of pixels without ringing like a sinc. Each
+    /// output pixel keeps the nearest depth and the average alpha of the pixels it
+    /// covers, and is credited with all of their samples.
+    ///
+    /// # Panics
+    /// Panics if `factor` is zero or does not divide both dimensions.
+    pub fn downsample(&self, factor: u32) -> Framebuffer {
+        assert!(
+            factor > 0 && self.width.is_multiple_of(factor) && self.height.is_multiple_of(factor),
+            "Downsample factor must divide the image dimensions"
+        );
+        let (width, height) = (self.width / factor, self.height / factor);
+
+        let rows = resample(&self.pixels, self.width, self.height, factor, true);
+        let mut pixels = resample(&rows, width, self.height, factor, false);
+        for pixel in &mut pixels {
+            *pixel = Color::new(pixel.r().max(0.0), pixel.g().max(0.0), pixel.b().max(0.0));
+        }
+
+        let factor = factor as usize;
+        let depth = self.depth.as_ref().map(|depth| {
+            (0..height as usize * width as usize)
+                .map(|index| {
+                    let (x, y) = (index % width as usize, index / width as usize);
+                    (0..factor * factor)
+                        .map(|i| {
+                            let (sx, sy) = (x * factor + i % factor, y * factor + i / factor);
+                            depth[sy * self.width as usize + sx]
+                        })
+                        .fold(f64::INFINITY, f64::min)
+                })
+                .collect()
+        });
+        let alpha = self.alpha.as_ref().map(|alpha| {
+            (0..height as usize * width as usize)
+                .map(|index| {
+                    let (x, y) = (index % width as usize, index / width as usize);
+                    (0..factor * factor)
+                        .map(|i| {
+                            let (sx, sy) = (x * factor + i % factor, y * factor + i / factor);
+                            alpha[sy * self.width as usize + sx]
+                        })
+                        .sum::<f64>()
+                        / (factor * factor) as f64
+                })
+                .collect()
+        });
+
+        Framebuffer {
+            width,
+            height,
+            samples_per_pixel: self.samples_per_pixel * (factor * factor) as u32,
+            scene_seed: self.scene_seed,
+            display: self.display,
+            lut: self.lut.clone(),
+            pixels,
+            depth,
+            alpha,
+            tile_samples: self.tile_samples.clone(),
+            clipped: self.clipped,
+            diagnostics: self.diagnostics.clone(),
+        }
+    }
+}
+
+/// Filters `pixels` along one axis, shrinking that axis by `factor`.
+///
+/// `horizontal` picks the axis; `width` and `height` are the input's dimensions.
+fn resample(
+    pixels: &[Color],
+    width: u32,
+    height: u32,
+    factor: u32,
+    horizontal: bool,
+) -> Vec<Color> {
+    let (width, height, factor) = (width as usize, height as usize, factor as usize);
+    let (out_width, out_height) = if horizontal {
+        (width / factor, height)
+    } else {
+        (width, height / factor)
+    };
+    let length = if horizontal { width } else { height };
+    let reach = (DOWNSAMPLE_RADIUS * factor as f64).ceil() as isize;
+
+    (0..out_width * out_height)
+        .map(|index| {
+            let (x, y) = (index % out_width, index / out_width);
+            let out = if horizontal { x } else { y };
+            // Center of the output pixel in input pixel coordinates
+            let center = (out as f64 + 0.5) * factor as f64;
+            let first = (center as isize - reach).max(0);
+            let last = (center as isize + reach).min(length as isize);
+
+            let mut sum = Color::new(0.0, 0.0, 0.0);
+            let mut total = 0.0;
+            for i in first..last {
+                let weight = mitchell((i as f64 + 0.5 - center) / factor as f64);
+                let source = if horizontal {
+                    y * width + i as usize
+                } else {
+                    i as usize * width + x
+                };
+                sum += pixels[source] * weight;
+                total += weight;
+            }
+            sum * (1.0 / total)
+        })
+        .collect()
+}
+
+/// The Mitchell-Netravali cubic with B = C = 1/3, spanning [-2, 2].
+fn mitchell(x: f64) -> f64 {
+    let x = x.abs();
+    if x < 1.0 {
+        (7.0 * x * x * x - 12.0 * x * x + 16.0 / 3.0) / 6.0
+    } else if x < 2.0 {
+        (-7.0 / 3.0 * x * x * x + 12.0 * x * x - 20.0 * x + 32.0 / 3.0) / 6.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_ppm() {
+        let framebuffer = Framebuffer::new(
+            2,
+            1,
+            vec![Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)],
+        )
+        .with_samples_per_pixel(16);
+        let mut out = Vec::new();
+        framebuffer.write_ppm(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "P3\n# samples 16\n2 1\n255\n0 0 0\n255 255 255\n"
+        );
+
+        let budgeted = framebuffer.with_tile_samples(Some(TileSamples {
+            tile_size: 1,
+            samples: vec![16.0, 3.5],
+        }));
+        let mut out = Vec::new();
+        budgeted.write_ppm(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("P3\n# samples 16\n# tile samples 1 16,3.5\n2 1\n"));
+        assert_eq!(
+            Framebuffer::read_ppm(text.as_bytes())
+                .unwrap()
+                .samples_per_pixel(),
+            16
+        );
+    }
+
+    #[test]
+    fn test_write_pam() {
+        let framebuffer = Framebuffer::new(
+            2,
+            1,
+            vec![Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)],
+        );
+        let header = "P7\nWIDTH 2\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n";
+
+        let mut out = Vec::new();
+        framebuffer.write_pam(&mut out).unwrap();
+        assert_eq!(&out[..header.len()], header.as_bytes());
+        assert_eq!(&out[header.len()..], [0, 0, 0, 255, 255, 255, 255, 255]);
+
+        let mut out = Vec::new();
+        let covered = framebuffer.with_alpha(vec![0.0, 0.5]);
+        covered.write(ImageFormat::Pam, &mut out).unwrap();
+        assert_eq!(&out[header.len()..], [0, 0, 0, 0, 255, 255, 255, 128]);
+    }
+
+    #[test]
+    fn test_image_format_from_path() {
+        assert_eq!(
+            ImageFormat::from_path("out/render.png"),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(ImageFormat::from_path("render.PFM"), Some(ImageFormat::Pfm));
+        assert_eq!(ImageFormat::from_path("render.exr"), Some(ImageFormat::Exr));
+        assert_eq!(ImageFormat::from_path("render.hdr"), Some(ImageFormat::Hdr));
+        assert_eq!(ImageFormat::from_path("render.jpg"), None);
+        assert_eq!(ImageFormat::from_path("render"), None);
+        assert!(matches!(
+            Framebuffer::new(1, 1, vec![Color::new(0.0, 0.0, 0.0)]).save("render.jpg"),
+            Err(ImageError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_write_pfm() {
+        let framebuffer = Framebuffer::new(
+            1,
+            2,
+            vec![Color::new(4.0, 0.5, 0.0), Color::new(0.25, 1.0, 2.0)],
+        );
+        let mut out = Vec::new();
+        framebuffer.write_pfm(&mut out).unwrap();
+
+        let header = "PF\n1 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header.as_bytes());
+        let floats: Vec<f32> = out[header.len()..]
+            .chunks(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        // Bottom row first, and values above one kept
+        assert_eq!(floats, [0.25, 1.0, 2.0, 4.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn test_read_ppm_round_trip() {
+        let framebuffer = Framebuffer::new(
+            2,
+            1,
+            vec![Color::new(0.25, 0.5, 0.0), Color::new(1.0, 1.0, 1.0)],
+        )
+        .with_samples_per_pixel(8);
+        let mut out = Vec::new();
+        framebuffer.write_ppm(&mut out).unwrap();
+
+        let read = Framebuffer::read_ppm(out.as_slice()).unwrap();
+        assert_eq!(read.width, 2);
+        assert_eq!(read.height, 1);
+        assert_eq!(read.samples_per_pixel(), 8);
+        // Quantization to 8 bits loses a little precision
+        let mut again = Vec::new();
+        read.write_ppm(&mut again).unwrap();
+        assert_eq!(out, again);
+    }
+
+    #[test]
+    fn test_scene_seed_round_trip() {
+        let framebuffer =
+            Framebuffer::new(1, 1, vec![Color::new(0.0, 0.0, 0.0)]).with_scene_seed(Some(u64::MAX));
+        let mut out = Vec::new();
+        framebuffer.write_ppm(&mut out).unwrap();
+
+        let read = Framebuffer::read_ppm(out.as_slice()).unwrap();
+        assert_eq!(read.scene_seed(), Some(u64::MAX));
+        assert!(Framebuffer::read_ppm("P3\n# scene seed x\n1 1\n255\n0 0 0\n".as_bytes()).is_err());
+
+        // Renders of different arrangements have no single seed
+        let other = framebuffer.clone().with_scene_seed(Some(1));
+        let merged = Framebuffer::merge(&[framebuffer.clone(), framebuffer.clone()]).unwrap();
+        assert_eq!(merged.scene_seed(), Some(u64::MAX));
+        assert_eq!(
+            Framebuffer::merge(&[framebuffer, other])
+                .unwrap()
+                .scene_seed(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let pixels = vec![Color::new(0.25, 0.5, 0.0), Color::new(0.01, 0.9, 1.0)];
+        let framebuffer = Framebuffer::new(2, 1, pixels).with_display(DisplayTransform::Srgb);
+        let mut out = Vec::new();
+        framebuffer.write_ppm(&mut out).unwrap();
+        assert!(String::from_utf8_lossy(&out).contains("# display srgb\n"));
+
+        let read = Framebuffer::read_ppm(out.as_slice()).unwrap();
+        assert_eq!(read.display, DisplayTransform::Srgb);
+        for (a, b) in read.pixels().iter().zip(framebuffer.pixels()) {
+            assert!((a.g() - b.g()).abs() < 0.01, "{} vs {}", a, b);
+        }
+
+        let agx = framebuffer.with_display(DisplayTransform::AgX);
+        let mut out = Vec::new();
+        agx.write_ppm(&mut out).unwrap();
+        assert!(Framebuffer::read_ppm(out.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_lut_applies_after_display() {
+        // Swaps red and blue
+        let lut = Lut::parse_cube(
+            "TITLE \"Swap\"\nLUT_3D_SIZE 2\n\
+             0 0 0\n0 0 1\n0 1 0\n0 1 1\n1 0 0\n1 0 1\n1 1 0\n1 1 1\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let pixel = Color::new(0.25, 0.0, 1.0);
+        let framebuffer = Framebuffer::new(1, 1, vec![pixel]).with_lut(Some(Arc::new(lut)));
+        let [r, g, b] = pixel.to_bytes(DisplayTransform::default());
+        assert_eq!(framebuffer.encode(pixel), [b, g, r]);
+
+        let mut out = Vec::new();
+        framebuffer.write_ppm(&mut out).unwrap();
+        let text = String::from_utf8_lossy(&out);
+        assert!(text.contains("# lut Swap\n"));
+        assert!(text.ends_with(&format!("{} {} {}\n", b, g, r)));
+        assert!(Framebuffer::read_ppm(out.as_slice()).is_err());
+        assert_eq!(
+            framebuffer.downsample(1).lut().unwrap().title(),
+            Some("Swap")
+        );
+    }
+
+    #[test]
+    fn test_read_ppm_errors() {
+        assert!(Framebuffer::read_ppm("P6\n1 1\n255\n".as_bytes()).is_err());
+        assert!(Framebuffer::read_ppm("P3\n2 1\n255\n0 0 0\n".as_bytes()).is_err());
+        assert!(Framebuffer::read_ppm("P3\n1 1\n0\n0 0 0\n".as_bytes()).is_err());
+        // A size the file has no values for is refused before anything is allocated
+        assert!(
+            Framebuffer::read_ppm("P3\n4294967295 4294967295\n255\n0 0 0\n".as_bytes()).is_err()
+        );
+        assert!(Framebuffer::read_ppm("P3\n1 1\n255\n0 0 0 0\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_read_pfm_round_trip() {
+        let framebuffer = Framebuffer::new(
+            1,
+            2,
+            vec![Color::new(4.0, 0.5, 0.0), Color::new(0.25, 1.0, 2.0)],
+        );
+        let mut out = Vec::new();
+        framebuffer.write_pfm(&mut out).unwrap();
+        assert_eq!(Framebuffer::read_pfm(out.as_slice()).unwrap(), framebuffer);
+
+        // Big-endian floats are marked by a positive scale
+        let mut big_endian = b"PF\n1 1\n1.0\n".to_vec();
+        for c in [2.0f32, 0.5, 0.0] {
+            big_endian.extend(c.to_be_bytes());
+        }
+        let read = Framebuffer::read_pfm(big_endian.as_slice()).unwrap();
+        assert_eq!(read.pixels(), [Color::new(2.0, 0.5, 0.0)]);
+
+        assert!(Framebuffer::read_pfm("Pf\n1 1\n-1.0\n".as_bytes()).is_err());
+        assert!(Framebuffer::read_pfm(&out[..out.len() - 1]).is_err());
+        assert!(Framebuffer::read_pfm("PF\n4294967295 4294967295\n-1.0\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_merge_weights_by_samples() {
+        let a = Framebuffer::new(1, 1, vec![Color::new(1.0, 1.0, 1.0)]).with_samples_per_pixel(3);
+        let b = Framebuffer::new(1, 1, vec![Color::new(0.0, 0.0, 0.0)]).with_samples_per_pixel(1);
+
+        let merged = Framebuffer::merge(&[a, b]).unwrap();
+        assert_eq!(merged.samples_per_pixel(), 4);
+        assert_eq!(merged.pixels[0], Color::new(0.75, 0.75, 0.75));
+    }
+
+    #[test]
+    fn test_merge_errors() {
+        assert!(matches!(Framebuffer::merge(&[]), Err(ImageError::NoImages)));
+
+        let a = Framebuffer::new(1, 1, vec![Color::new(1.0, 1.0, 1.0)]);
+        let b = Framebuffer::new(2, 1, vec![Color::new(1.0, 1.0, 1.0); 2]);
+        assert!(matches!(
+            Framebuffer::merge(&[a, b]),
+            Err(ImageError::SizeMismatch)
+        ));
+
+        let none =
+            Framebuffer::new(1, 1, vec![Color::new(1.0, 1.0, 1.0)]).with_samples_per_pixel(0);
+        assert!(matches!(
+            Framebuffer::merge(&[none.clone(), none]),
+            Err(ImageError::SampleCount)
+        ));
+        let many = Framebuffer::new(1, 1, vec![Color::new(1.0, 1.0, 1.0)])
+            .with_samples_per_pixel(u32::MAX);
+        assert!(matches!(
+            Framebuffer::merge(&[many.clone(), many]),
+            Err(ImageError::SampleCount)
+        ));
+    }
+
+    #[test]
+    fn test_merge_refuses_clipped_images() {
+        // Highlights brighter than white are all written as 255
+        let mut out = Vec::new();
+        Framebuffer::new(1, 1, vec![Color::new(4.0, 0.0, 0.0)])
+            .write_ppm(&mut out)
+            .unwrap();
+        let clipped = Framebuffer::read_ppm(out.as_slice()).unwrap();
+        assert!(matches!(
+            Framebuffer::merge(&[clipped.clone(), clipped]),
+            Err(ImageError::Clipped)
+        ));
+
+        // Images with nothing at white lost only precision
+        let mut out = Vec::new();
+        Framebuffer::new(1, 1, vec![Color::new(0.5, 0.0, 0.0)])
+            .write_ppm(&mut out)
+            .unwrap();
+        let dim = Framebuffer::read_ppm(out.as_slice()).unwrap();
+        assert!(Framebuffer::merge(&[dim.clone(), dim]).is_ok());
+    }
+
+    #[test]
+    fn test_merge_alpha() {
+        let frame = |alpha: f64, samples: u32| {
+            Framebuffer::new(1, 1, vec![Color::new(1.0, 1.0, 1.0)])
+                .with_samples_per_pixel(samples)
+                .with_alpha(vec![alpha])
+        };
+        let merged = Framebuffer::merge(&[frame(1.0, 3), frame(0.0, 1)]).unwrap();
+        assert_eq!(merged.alpha(), Some(&[0.75][..]));
+
+        // Without alpha in every image there is none to average
+        let opaque = Framebuffer::new(1, 1, vec![Color::new(1.0, 1.0, 1.0)]);
+        let merged = Framebuffer::merge(&[frame(1.0, 1), opaque]).unwrap();
+        assert_eq!(merged.alpha(), None);
+    }
+
+    #[test]
+    fn test_contact_sheet() {
+        let frames: Vec<Framebuffer> = (1..=3)
+            .map(|i| Framebuffer::new(2, 1, vec![Color::new(i as f64, 0.0, 0.0); 2]))
+            .collect();
+        let sheet = Framebuffer::contact_sheet(&frames, 2).unwrap();
+        let gap = CONTACT_SHEET_GAP;
+        assert_eq!((sheet.width(), sheet.height()), (4 + gap, 2 + gap));
+
+        let red = |x: u32, y: u32| sheet.pixels()[(y * sheet.width() + x) as usize].r();
+        assert_eq!((red(0, 0), red(1, 0)), (1.0, 1.0));
+        assert_eq!(red(2, 0), 0.0);
+        assert_eq!(red(2 + gap, 0), 2.0);
+        assert_eq!(red(0, 1 + gap), 3.0);
+        assert_eq!(red(2 + gap, 1 + gap), 0.0);
+
+        assert!(matches!(
+            Framebuffer::contact_sheet(&[], 2),
+            Err(ImageError::NoImages)
+        ));
+        let odd = Framebuffer::new(1, 1, vec![Color::new(1.0, 1.0, 1.0)]);
+        assert!(matches!(
+            Framebuffer::contact_sheet(&[frames[0].clone(), odd], 2),
+            Err(ImageError::SizeMismatch)
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Depth buffer does not match dimensions")]
+    fn test_mismatched_depth_buffer() {
+        Framebuffer::new(1, 1, vec![Color::new(0.0, 0.0, 0.0)]).with_depth(vec![]);
+    }
+
+    #[test]
+    fn test_downsample() {
+        // A flat image stays flat, and the output is credited with every sample
+        let flat = Framebuffer::new(4, 4, vec![Color::new(0.5, 0.25, 1.0); 16])
+            .with_samples_per_pixel(2)
+            .with_depth((0..16).map(|i| i as f64).collect())
+            .with_alpha((0..16).map(|i| (i % 2) as f64).collect());
+        let small = flat.downsample(2);
+        assert_eq!((small.width(), small.height()), (2, 2));
+        assert_eq!(small.samples_per_pixel(), 8);
+        for pixel in small.pixels() {
+            assert!((pixel.g() - 0.25).abs() < 1e-12, "{}", pixel);
+        }
+        assert_eq!(small.depth().unwrap(), &[0.0, 2.0, 8.0, 10.0]);
+        assert_eq!(small.alpha, Some(vec![0.5; 4]));
+
+        // A hard edge between output pixels stays mostly on its own side
+        let edge: Vec<Color> = (0..16)
+            .map(|i| Color::new(if i % 4 < 2 { 1.0 } else { 0.0 }, 0.0, 0.0))
+            .collect();
+        let small = Framebuffer::new(4, 4, edge).downsample(2);
+        assert!(small.pixels()[0].r() > 0.8 && small.pixels()[1].r() < 0.2);
+        assert!(small.pixels().iter().all(|p| p.r() >= 0.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Downsample factor must divide the image dimensions")]
+    fn test_downsample_uneven() {
+        Framebuffer::new(3, 1, vec![Color::new(0.0, 0.0, 0.0); 3]).downsample(2);
+    }
+}
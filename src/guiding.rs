@@ -0,0 +1,226 @@
+//! Path guiding: a learned distribution of incident radiance for steering bounces.
+//!
+//! Space is divided into a sparse grid of cubic cells, each holding a histogram
+//! over the sphere of directions that records how much light arrived from each
+//! direction during a short training render. Bins use an equal-area cylindrical
+//! mapping (uniform in `z` and in azimuth), so every bin covers the same solid angle
+//! and sampling within a bin is uniform.
+
+use crate::point3::Point3;
+use crate::vec3::Vec3;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// Histogram bins along `z`, from straight down to straight up.
+const Z_BINS: usize = 8;
+/// Histogram bins around the azimuth.
+const PHI_BINS: usize = 16;
+const BINS: usize = Z_BINS * PHI_BINS;
+/// Solid angle covered by each bin.
+const BIN_SOLID_ANGLE: f64 = 4.0 * PI / BINS as f64;
+
+/// Incident radiance recorded in one cell, by direction.
+#[derive(Clone, Debug, PartialEq)]
+struct DirectionalHistogram {
+    weights: [f64; BINS],
+    /// Running totals of the normalized weights, built once training has finished
+    cdf: Vec<f64>,
+}
+
+impl Default for DirectionalHistogram {
+    fn default() -> Self {
+        Self {
+            weights: [0.0; BINS],
+            cdf: Vec::new(),
+        }
+    }
+}
+
+impl DirectionalHistogram {
+    fn total(&self) -> f64 {
+        self.weights.iter().sum()
+    }
+}
+
+/// Learned incident radiance over the scene, used to importance sample bounce directions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathGuide {
+    cell_size: f64,
+    cells: HashMap<(i64, i64, i64), DirectionalHistogram>,
+}
+
+impl PathGuide {
+    /// Creates an empty guide whose grid cells are `cell_size` across.
+    pub fn new(cell_size: f64) -> Self {
+        Self {
+            cell_size: cell_size.max(f64::EPSILON),
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell(&self, position: &Point3) -> (i64, i64, i64) {
+        let index = |c: f64| (c / self.cell_size).floor() as i64;
+        (
+            index(position.x()),
+            index(position.y()),
+            index(position.z()),
+        )
+    }
+
+    /// Records `radiance` (a luminance) arriving at `position` from `direction`.
+    pub fn record(&mut self, position: &Point3, direction: &Vec3, radiance: f64) {
+        if !(radiance.is_finite() && radiance > 0.0) {
+            return;
+        }
+        let cell = self.cell(position);
+        self.cells.entry(cell).or_default().weights[bin(direction)] += radiance;
+    }
+
+    /// Adds the radiance recorded by `other`, which must use the same cell size.
+    pub fn merge(mut self, other: PathGuide) -> Self {
+        for (cell, histogram) in other.cells {
+            let merged = self.cells.entry(cell).or_default();
+            for (weight, other) in merged.weights.iter_mut().zip(histogram.weights) {
+                *weight += other;
+            }
+        }
+        self
+    }
+
+    /// Normalizes every histogram for sampling. Call once recording has finished.
+    pub fn finish(&mut self) {
+        self.cells.retain(|_, histogram| histogram.total() > 0.0);
+        for histogram in self.cells.values_mut() {
+            let total = histogram.total();
+            histogram.cdf = histogram
+                .weights
+                .iter()
+                .scan(0.0, |sum, weight| {
+                    *sum += weight / total;
+                    Some(*sum)
+                })
+                .collect();
+        }
+    }
+
+    /// Number of cells that recorded any light.
+    pub fn cell_count(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Whether any light was recorded around `position`, so `sample` has something
+    /// to offer there.
+    pub fn covers(&self, position: &Point3) -> bool {
+        self.cells.contains_key(&self.cell(position))
+    }
+
+    /// Samples a unit direction from the distribution learned at `position`.
+    ///
+    /// `u` picks the bin and `v` and `w` the point within it, all uniform in [0, 1).
+    /// Returns `None` where no light was recorded, as the guide has nothing to offer.
+    pub fn sample(&self, position: &Point3, u: f64, v: f64, w: f64) -> Option<Vec3> {
+        let histogram = self.cells.get(&self.cell(position))?;
+        let index = histogram.cdf.partition_point(|&sum| sum <= u).min(BINS - 1);
+
+        let z = -1.0 + 2.0 * ((index / PHI_BINS) as f64 + v) / Z_BINS as f64;
+        let phi = -PI + 2.0 * PI * ((index % PHI_BINS) as f64 + w) / PHI_BINS as f64;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        Some(Vec3::new(r * phi.cos(), r * phi.sin(), z))
+    }
+
+    /// Returns the solid angle density of sampling `direction` at `position`.
+    pub fn pdf(&self, position: &Point3, direction: &Vec3) -> f64 {
+        let Some(histogram) = self.cells.get(&self.cell(position)) else {
+            return 0.0;
+        };
+        let index = bin(direction);
+        let below = if index == 0 {
+            0.0
+        } else {
+            histogram.cdf[index - 1]
+        };
+        (histogram.cdf[index] - below) / BIN_SOLID_ANGLE
+    }
+}
+
+/// Returns the histogram bin a direction falls in.
+fn bin(direction: &Vec3) -> usize {
+    let direction = direction.unit();
+    let z = ((direction.z() + 1.0) / 2.0 * Z_BINS as f64) as usize;
+    let phi = (direction.y().atan2(direction.x()) + PI) / (2.0 * PI);
+    let phi = (phi * PHI_BINS as f64) as usize;
+    z.min(Z_BINS - 1) * PHI_BINS + phi.min(PHI_BINS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::random_double;
+
+    #[test]
+    fn test_sampled_directions_fall_in_their_bin() {
+        let mut guide = PathGuide::new(1.0);
+        let position = Point3::new(0.5, 0.5, 0.5);
+        let up = Vec3::new(0.1, 0.2, 1.0);
+        guide.record(&position, &up, 1.0);
+        guide.finish();
+
+        for _ in 0..100 {
+            let direction = guide
+                .sample(&position, random_double(), random_double(), random_double())
+                .unwrap();
+            assert!((direction.length() - 1.0).abs() < 1e-9);
+            assert_eq!(bin(&direction), bin(&up));
+        }
+        assert!((guide.pdf(&position, &up) - 1.0 / BIN_SOLID_ANGLE).abs() < 1e-9);
+        assert_eq!(guide.pdf(&position, &-up), 0.0);
+    }
+
+    #[test]
+    fn test_pdf_integrates_to_one() {
+        let mut guide = PathGuide::new(1.0);
+        let position = Point3::default();
+        for i in 0..50 {
+            let direction = Vec3::new((i as f64).sin(), (i as f64).cos(), (i as f64 * 0.3).sin());
+            guide.record(&position, &direction, i as f64);
+        }
+        guide.finish();
+
+        // Uniform directions estimate the integral of the pdf over the sphere
+        let samples = 10_000;
+        let integral = (0..samples)
+            .map(|_| guide.pdf(&position, &Vec3::random_unit()))
+            .sum::<f64>()
+            * 4.0
+            * PI
+            / samples as f64;
+        assert!((integral - 1.0).abs() < 0.05, "integral was {}", integral);
+    }
+
+    #[test]
+    fn test_unvisited_cells_offer_nothing() {
+        let mut guide = PathGuide::new(1.0);
+        guide.record(&Point3::default(), &Vec3::new(0.0, 0.0, 1.0), 1.0);
+        guide.record(&Point3::new(5.0, 0.0, 0.0), &Vec3::new(0.0, 0.0, 1.0), 0.0);
+        guide.finish();
+
+        let elsewhere = Point3::new(5.0, 0.0, 0.0);
+        assert_eq!(guide.cell_count(), 1);
+        assert_eq!(guide.sample(&elsewhere, 0.5, 0.5, 0.5), None);
+        assert_eq!(guide.pdf(&elsewhere, &Vec3::new(0.0, 0.0, 1.0)), 0.0);
+    }
+
+    #[test]
+    fn test_merge_adds_recorded_radiance() {
+        let direction = Vec3::new(1.0, 0.0, 0.0);
+        let mut a = PathGuide::new(1.0);
+        a.record(&Point3::default(), &direction, 1.0);
+        let mut b = PathGuide::new(1.0);
+        b.record(&Point3::default(), &direction, 2.0);
+        b.record(&Point3::new(3.0, 0.0, 0.0), &direction, 1.0);
+
+        let merged = a.merge(b);
+        assert_eq!(merged.cell_count(), 2);
+        assert_eq!(merged.cells[&(0, 0, 0)].total(), 3.0);
+    }
+}
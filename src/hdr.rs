@@ -0,0 +1,723 @@
+//! Float images in OpenEXR and Radiance HDR, which keep the linear colors of a
+//! render, values above one included, for tone mapping, compositing and merging
+//! later.
+//!
+//! EXR files are single-part scanline images with no compression, holding 32-bit
+//! float R, G and B channels and, when the render has one, an alpha channel of
+//! surface coverage. HDR files hold shared-exponent RGBE pixels in scanlines
+//! written as the run-length format's literal runs, which every reader accepts.
+//! Both record the sample count and scene seed, as PPM comments do, so renders
+//! read back can be merged.
+//!
+//! Reading supports what is written here, and run-length HDR scanlines with runs:
+//! uncompressed scanline EXR with half, float or integer channels, and HDR images
+//! stored top to bottom.
+
+use crate::color::Color;
+use crate::framebuffer::{Framebuffer, ImageError};
+use std::io::{self, BufRead, Read, Write};
+
+const EXR_MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+/// Version 2, single-part scanline
+const EXR_VERSION: [u8; 4] = [2, 0, 0, 0];
+/// The pixel type of 32-bit float channels.
+const EXR_FLOAT: i32 = 2;
+/// The pixel type of 16-bit float channels.
+const EXR_HALF: i32 = 1;
+/// The pixel type of 32-bit unsigned integer channels.
+const EXR_UINT: i32 = 0;
+/// Version flags of tiled, deep and multi-part files, none of which are read.
+const EXR_UNSUPPORTED_FLAGS: u8 = 0x02 | 0x08 | 0x10;
+/// EXR attribute holding the samples each pixel received.
+const EXR_SAMPLES: &str = "samplesPerPixel";
+/// EXR attribute holding the seed the scene was generated from.
+const EXR_SCENE_SEED: &str = "sceneSeed";
+/// Longest literal run in an HDR scanline.
+const HDR_MAX_RUN: usize = 128;
+/// HDR header variable holding the samples each pixel received.
+const HDR_SAMPLES: &str = "SAMPLES=";
+/// HDR header variable holding the seed the scene was generated from.
+const HDR_SCENE_SEED: &str = "SCENE_SEED=";
+
+/// Writes the frame's linear pixels as an uncompressed OpenEXR image.
+///
+/// Fails with [`io::ErrorKind::InvalidInput`] if the size, a row or the sample count
+/// is too large for the format's 32-bit signed fields.
+pub fn write_exr(frame: &Framebuffer, out: &mut impl Write) -> io::Result<()> {
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    let samples = exr_int("sample count", frame.samples_per_pixel() as usize)?;
+    let last = [
+        exr_int("width", width)?.saturating_sub(1),
+        exr_int("height", height)?.saturating_sub(1),
+    ];
+    // Channels are stored in alphabetical order
+    let pixels = frame.pixels();
+    let channel = |value: fn(&Color) -> f64| -> Vec<f32> {
+        pixels.iter().map(|pixel| value(pixel) as f32).collect()
+    };
+    let mut channels = Vec::new();
+    if let Some(alpha) = frame.alpha() {
+        channels.push(("A", alpha.iter().map(|&a| a as f32).collect()));
+    }
+    channels.push(("B", channel(Color::b)));
+    channels.push(("G", channel(Color::g)));
+    channels.push(("R", channel(Color::r)));
+
+    let mut header = Vec::new();
+    header.extend(EXR_MAGIC);
+    header.extend(EXR_VERSION);
+    let mut channel_list = Vec::new();
+    for (name, _) in &channels {
+        channel_list.extend(name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend(EXR_FLOAT.to_le_bytes());
+        // Not perceptually linear, three reserved bytes, and no subsampling
+        channel_list.extend([0, 0, 0, 0]);
+        channel_list.extend(1i32.to_le_bytes());
+        channel_list.extend(1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    attribute(&mut header, "channels", "chlist", &channel_list);
+    attribute(&mut header, "compression", "compression", &[0]);
+    let window: Vec<u8> = [0, 0, last[0], last[1]]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    // Rows from the top down
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute(&mut header, EXR_SAMPLES, "int", &samples.to_le_bytes());
+    if let Some(seed) = frame.scene_seed() {
+        attribute(
+            &mut header,
+            EXR_SCENE_SEED,
+            "string",
+            seed.to_string().as_bytes(),
+        );
+    }
+    header.push(0);
+    out.write_all(&header)?;
+
+    // Each row is a block of its own, found through a table of offsets
+    let row_bytes = width * channels.len() * 4;
+    let row_size = exr_int("row size", row_bytes)?;
+    let block_bytes = 8 + row_bytes;
+    let first_block = header.len() + height * 8;
+    let offsets: Vec<u8> = (0..height)
+        .flat_map(|y| ((first_block + y * block_bytes) as u64).to_le_bytes())
+        .collect();
+    out.write_all(&offsets)?;
+
+    let mut block = Vec::with_capacity(block_bytes);
+    for y in 0..height {
+        block.clear();
+        block.extend((y as i32).to_le_bytes());
+        block.extend(row_size.to_le_bytes());
+        for (_, values) in &channels {
+            for value in &values[y * width..(y + 1) * width] {
+                block.extend(value.to_le_bytes());
+            }
+        }
+        out.write_all(&block)?;
+    }
+    Ok(())
+}
+
+/// Converts `value` for a 32-bit signed EXR field, naming it in the error if it
+/// does not fit.
+fn exr_int(name: &str, value: usize) -> io::Result<i32> {
+    i32::try_from(value).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} {} is too large for an EXR file", name, value),
+        )
+    })
+}
+
+/// Appends an EXR header attribute.
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    for text in [name, kind] {
+        header.extend(text.as_bytes());
+        header.push(0);
+    }
+    header.extend((value.len() as i32).to_le_bytes());
+    header.extend(value);
+}
+
+/// Writes the frame's linear pixels as a Radiance HDR image.
+pub fn write_hdr(frame: &Framebuffer, out: &mut impl Write) -> io::Result<()> {
+    let width = frame.width() as usize;
+    writeln!(out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe")?;
+    writeln!(out, "{}{}", HDR_SAMPLES, frame.samples_per_pixel())?;
+    if let Some(seed) = frame.scene_seed() {
+        writeln!(out, "{}{}", HDR_SCENE_SEED, seed)?;
+    }
+    write!(out, "\n-Y {} +X {}\n", frame.height(), width)?;
+    // Readers only expect run-length scanlines of these widths
+    let run_length = (8..0x8000).contains(&width);
+    let mut scanline = Vec::new();
+    for row in frame.pixels().chunks(width) {
+        scanline.clear();
+        let rgbe: Vec<[u8; 4]> = row.iter().map(|&pixel| rgbe(pixel)).collect();
+        if run_length {
+            scanline.extend([2, 2, (width >> 8) as u8, (width & 0xff) as u8]);
+            for channel in 0..4 {
+                for run in rgbe.chunks(HDR_MAX_RUN) {
+                    scanline.push(run.len() as u8);
+                    scanline.extend(run.iter().map(|pixel| pixel[channel]));
+                }
+            }
+        } else {
+            scanline.extend(rgbe.iter().flatten());
+        }
+        out.write_all(&scanline)?;
+    }
+    Ok(())
+}
+
+/// Encodes `color` as three 8-bit mantissas sharing the exponent of the brightest
+/// channel. Negative channels are written as zero.
+fn rgbe(color: Color) -> [u8; 4] {
+    let [r, g, b] = [color.r(), color.g(), color.b()].map(|c| c.max(0.0));
+    let brightest = r.max(g).max(b);
+    if brightest < 1e-32 || !brightest.is_finite() {
+        return [0; 4];
+    }
+    // The smallest power of two above the brightest channel
+    let mut exponent = brightest.log2().floor() as i32 + 1;
+    if brightest >= 2f64.powi(exponent) {
+        exponent += 1;
+    }
+    let exponent = exponent.clamp(-128, 127);
+    let scale = 256.0 / 2f64.powi(exponent);
+    let mantissa = |c: f64| (c * scale).min(255.0) as u8;
+    [
+        mantissa(r),
+        mantissa(g),
+        mantissa(b),
+        (exponent + 128) as u8,
+    ]
+}
+
+/// Reads an OpenEXR image of linear colors.
+///
+/// Only single-part, uncompressed scanline images are read, which must have R, G and
+/// B channels and may have an alpha channel. Images without a sample count are taken
+/// to have one sample per pixel.
+pub fn read_exr(mut input: impl Read) -> Result<Framebuffer, ImageError> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    let mut reader = Reader::new(&data);
+    if reader.take(4)? != EXR_MAGIC {
+        return Err(invalid("not an OpenEXR image"));
+    }
+    let version = reader.take(4)?;
+    if version[0] != 2 || version[1] & EXR_UNSUPPORTED_FLAGS != 0 {
+        return Err(invalid(
+            "only single-part scanline OpenEXR images can be read",
+        ));
+    }
+
+    let mut channels = Vec::new();
+    let mut window = None;
+    let mut samples_per_pixel = 1;
+    let mut scene_seed = None;
+    loop {
+        let name = reader.text()?;
+        if name.is_empty() {
+            break;
+        }
+        let _kind = reader.text()?;
+        let size = usize::try_from(reader.int()?).unwrap_or(usize::MAX);
+        let bytes = reader.take(size)?;
+        let mut value = Reader::new(bytes);
+        match name {
+            "channels" => loop {
+                let channel = value.text()?;
+                if channel.is_empty() {
+                    break;
+                }
+                let pixel_type = value.int()?;
+                value.take(4)?;
+                if (value.int()?, value.int()?) != (1, 1) {
+                    return Err(invalid("subsampled OpenEXR channels cannot be read"));
+                }
+                channels.push((channel.to_string(), pixel_type));
+            },
+            "compression" if bytes != [0] => {
+                return Err(invalid("compressed OpenEXR images cannot be read"));
+            }
+            "dataWindow" => {
+                window = Some([value.int()?, value.int()?, value.int()?, value.int()?]);
+            }
+            EXR_SAMPLES => {
+                samples_per_pixel =
+                    u32::try_from(value.int()?).map_err(|_| invalid("bad OpenEXR sample count"))?;
+            }
+            EXR_SCENE_SEED => {
+                let seed = std::str::from_utf8(bytes).ok();
+                scene_seed = Some(
+                    seed.and_then(|seed| seed.parse().ok())
+                        .ok_or_else(|| invalid("bad OpenEXR scene seed"))?,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    let [left, top, right, bottom] = window.ok_or_else(|| invalid("no OpenEXR data window"))?;
+    let size = |min: i32, max: i32| {
+        u32::try_from(i64::from(max) - i64::from(min) + 1)
+            .ok()
+            .filter(|&size| size > 0)
+    };
+    let (Some(width), Some(height)) = (size(left, right), size(top, bottom)) else {
+        return Err(invalid("bad OpenEXR data window"));
+    };
+    let (width, height) = (width as usize, height as usize);
+    let mut channel_bytes = Vec::with_capacity(channels.len());
+    for (_, pixel_type) in &channels {
+        channel_bytes.push(match *pixel_type {
+            EXR_HALF => 2,
+            EXR_FLOAT | EXR_UINT => 4,
+            _ => return Err(invalid("unknown OpenEXR pixel type")),
+        });
+    }
+    // Trust the size only as far as the file holds the rows for it
+    let row_bytes = width
+        .checked_mul(channel_bytes.iter().sum())
+        .ok_or_else(|| invalid("OpenEXR image too large"))?;
+    let needed = row_bytes
+        .checked_add(16)
+        .and_then(|block| block.checked_mul(height));
+    if needed.is_none_or(|needed| needed > reader.remaining()) {
+        return Err(invalid("truncated OpenEXR image"));
+    }
+
+    let mut values = vec![vec![0.0; width * height]; channels.len()];
+    for row in 0..height {
+        let offset = u64::from_le_bytes(reader.take(8)?.try_into().expect("eight bytes"));
+        let mut block = Reader::new(&data);
+        block.take(usize::try_from(offset).unwrap_or(usize::MAX))?;
+        let y = i64::from(block.int()?) - i64::from(top);
+        if !(0..height as i64).contains(&y) || block.int()? as usize != row_bytes {
+            return Err(invalid(&format!("bad OpenEXR block for row {}", row)));
+        }
+        let y = y as usize;
+        for (index, channel) in values.iter_mut().enumerate() {
+            let (pixel_type, bytes) = (channels[index].1, channel_bytes[index]);
+            let row_values = &mut channel[y * width..(y + 1) * width];
+            let raw_values = block.take(width * bytes)?.chunks(bytes);
+            for (value, raw) in row_values.iter_mut().zip(raw_values) {
+                *value = match pixel_type {
+                    EXR_HALF => half_to_f32(u16::from_le_bytes([raw[0], raw[1]])) as f64,
+                    EXR_FLOAT => f32::from_le_bytes(raw.try_into().expect("four bytes")) as f64,
+                    _ => u32::from_le_bytes(raw.try_into().expect("four bytes")) as f64,
+                };
+            }
+        }
+    }
+    let mut channel = |name: &str| {
+        let index = channels.iter().position(|(channel, _)| channel == name)?;
+        Some(std::mem::take(&mut values[index]))
+    };
+    let alpha = channel("A");
+    let (Some(r), Some(g), Some(b)) = (channel("R"), channel("G"), channel("B")) else {
+        return Err(invalid("OpenEXR image has no R, G and B channels"));
+    };
+    let pixels = (0..width * height)
+        .map(|index| Color::new(r[index], g[index], b[index]))
+        .collect();
+    let frame = Framebuffer::new(width as u32, height as u32, pixels)
+        .with_samples_per_pixel(samples_per_pixel)
+        .with_scene_seed(scene_seed);
+    Ok(match alpha {
+        Some(alpha) => frame.with_alpha(alpha),
+        None => frame,
+    })
+}
+
+/// Reads a Radiance HDR image of linear colors.
+///
+/// Images must be stored top to bottom in RGBE, with flat or run-length scanlines.
+/// Exposure recorded in the header is divided out, and images without a sample
+/// count are taken to have one sample per pixel.
+pub fn read_hdr(mut input: impl BufRead) -> Result<Framebuffer, ImageError> {
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid("not a Radiance HDR image"));
+    }
+    let mut samples_per_pixel = 1;
+    let mut scene_seed = None;
+    let mut exposure = 1.0;
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Err(invalid("truncated Radiance HDR header"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid(&format!(
+                    "Radiance HDR format '{}' cannot be read",
+                    format
+                )));
+            }
+        } else if let Some(value) = line.strip_prefix("EXPOSURE=") {
+            exposure *= value
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| invalid("bad Radiance HDR exposure"))?;
+        } else if let Some(value) = line.strip_prefix(HDR_SAMPLES) {
+            samples_per_pixel = value
+                .trim()
+                .parse()
+                .map_err(|_| invalid("bad Radiance HDR sample count"))?;
+        } else if let Some(value) = line.strip_prefix(HDR_SCENE_SEED) {
+            scene_seed = Some(
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("bad Radiance HDR scene seed"))?,
+            );
+        }
+    }
+    line.clear();
+    input.read_line(&mut line)?;
+    let size: Vec<&str> = line.split_whitespace().collect();
+    let (width, height) = match size[..] {
+        ["-Y", height, "+X", width] => (width.parse::<u32>(), height.parse::<u32>()),
+        _ => {
+            return Err(invalid(
+                "only top to bottom Radiance HDR images can be read",
+            ));
+        }
+    };
+    let (Ok(width), Ok(height)) = (width, height) else {
+        return Err(invalid("bad Radiance HDR size"));
+    };
+    (width as usize)
+        .checked_mul(height as usize)
+        .ok_or_else(|| invalid("Radiance HDR image too large"))?;
+
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    let mut reader = Reader::new(&data);
+    let width = width as usize;
+    let scale = 1.0 / exposure;
+    // Pixels are only allocated a scanline at a time, as each is found in the file
+    let mut pixels = Vec::new();
+    let mut rgbe = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        let run_length = (8..0x8000).contains(&width)
+            && reader.peek(4) == Some(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8][..]);
+        if run_length {
+            reader.take(4)?;
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = reader.take(1)?[0] as usize;
+                    let (count, repeated) = if count > 128 {
+                        (count - 128, true)
+                    } else {
+                        (count, false)
+                    };
+                    if count == 0 || x + count > width {
+                        return Err(invalid("bad Radiance HDR run"));
+                    }
+                    if repeated {
+                        let value = reader.take(1)?[0];
+                        rgbe[x..x + count]
+                            .iter_mut()
+                            .for_each(|pixel| pixel[channel] = value);
+                    } else {
+                        for (pixel, &value) in
+                            rgbe[x..x + count].iter_mut().zip(reader.take(count)?)
+                        {
+                            pixel[channel] = value;
+                        }
+                    }
+                    x += count;
+                }
+            }
+        } else {
+            for (pixel, bytes) in rgbe.iter_mut().zip(reader.take(width * 4)?.chunks(4)) {
+                pixel.copy_from_slice(bytes);
+            }
+        }
+        pixels.extend(rgbe.iter().map(|&pixel| from_rgbe(pixel) * scale));
+    }
+    Ok(Framebuffer::new(width as u32, height, pixels)
+        .with_samples_per_pixel(samples_per_pixel)
+        .with_scene_seed(scene_seed))
+}
+
+/// Decodes a shared-exponent pixel, taking each mantissa from the middle of the
+/// range it stands for.
+fn from_rgbe([r, g, b, e]: [u8; 4]) -> Color {
+    if e == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let scale = 2f64.powi(e as i32 - 136);
+    let channel = |c: u8| (c as f64 + 0.5) * scale;
+    Color::new(channel(r), channel(g), channel(b))
+}
+
+/// Widens a 16-bit float.
+fn half_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10) & 0x1f;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15),
+    }
+}
+
+fn invalid(reason: &str) -> ImageError {
+    ImageError::InvalidFormat(reason.to_string())
+}
+
+/// Reads little-endian values from the bytes of an image, failing at their end.
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    fn peek(&self, count: usize) -> Option<&'a [u8]> {
+        self.data
+            .get(self.position..self.position.checked_add(count)?)
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], ImageError> {
+        let bytes = self.peek(count).ok_or_else(|| invalid("truncated image"))?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn int(&mut self) -> Result<i32, ImageError> {
+        Ok(i32::from_le_bytes(
+            self.take(4)?.try_into().expect("four bytes"),
+        ))
+    }
+
+    /// Takes a null-terminated string.
+    fn text(&mut self) -> Result<&'a str, ImageError> {
+        let rest = &self.data[self.position..];
+        let end = rest
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or_else(|| invalid("truncated image"))?;
+        let text = std::str::from_utf8(&rest[..end]).map_err(|_| invalid("bad text in image"))?;
+        self.position += end + 1;
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_f32(bytes: &[u8]) -> f32 {
+        f32::from_le_bytes(bytes[..4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_write_exr() {
+        let frame = Framebuffer::new(
+            2,
+            1,
+            vec![Color::new(4.0, 0.5, 0.0), Color::new(0.25, 1.0, 2.0)],
+        );
+        let mut out = Vec::new();
+        write_exr(&frame, &mut out).unwrap();
+
+        assert_eq!(out[..4], EXR_MAGIC);
+        let channels = b"channels\0chlist\0";
+        assert_eq!(&out[8..8 + channels.len()], channels);
+        // The header is followed by the one row's offset, then the row
+        let table = out.len() - (8 + 2 * 3 * 4) - 8;
+        let offset = u64::from_le_bytes(out[table..table + 8].try_into().unwrap()) as usize;
+        assert_eq!(offset, table + 8);
+
+        let block = &out[offset..];
+        assert_eq!(block[..8], [0, 0, 0, 0, 24, 0, 0, 0]);
+        let floats: Vec<f32> = block[8..].chunks(4).map(read_f32).collect();
+        // B, G, R, each across the row, and values above one kept
+        assert_eq!(floats, [0.0, 2.0, 0.5, 1.0, 4.0, 0.25]);
+
+        let frame = frame.with_samples_per_pixel(u32::MAX);
+        let error = write_exr(&frame, &mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            error.to_string(),
+            "sample count 4294967295 is too large for an EXR file"
+        );
+    }
+
+    #[test]
+    fn test_write_exr_alpha() {
+        let frame = Framebuffer::new(1, 1, vec![Color::new(1.0, 1.0, 1.0)]).with_alpha(vec![0.5]);
+        let mut out = Vec::new();
+        write_exr(&frame, &mut out).unwrap();
+        let list = b"channels\0chlist\0";
+        assert_eq!(out[8 + list.len() + 4], b'A');
+        assert_eq!(read_f32(&out[out.len() - 16..]), 0.5);
+    }
+
+    #[test]
+    fn test_rgbe() {
+        assert_eq!(rgbe(Color::new(0.0, 0.0, 0.0)), [0; 4]);
+        assert_eq!(rgbe(Color::new(1.0, 0.5, 0.0)), [128, 64, 0, 129]);
+        assert_eq!(rgbe(Color::new(0.75, 3.0, -1.0)), [48, 192, 0, 130]);
+    }
+
+    #[test]
+    fn test_write_hdr() {
+        let header = "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\nSAMPLES=1\n\n-Y 1 +X ";
+
+        let narrow = Framebuffer::new(2, 1, vec![Color::new(1.0, 0.5, 0.0); 2]);
+        let mut out = Vec::new();
+        write_hdr(&narrow, &mut out).unwrap();
+        let pixels = &out[header.len() + 2..];
+        assert_eq!(pixels, [128, 64, 0, 129, 128, 64, 0, 129]);
+
+        let wide = Framebuffer::new(8, 1, vec![Color::new(1.0, 0.5, 0.0); 8]);
+        let mut out = Vec::new();
+        write_hdr(&wide, &mut out).unwrap();
+        let scanline = &out[header.len() + 2..];
+        assert_eq!(scanline[..4], [2, 2, 0, 8]);
+        // One literal run of eight per channel
+        assert_eq!(scanline[4..13], [8, 128, 128, 128, 128, 128, 128, 128, 128]);
+        assert_eq!(scanline.len(), 4 + 4 * 9);
+    }
+
+    #[test]
+    fn test_read_exr_round_trip() {
+        let frame = Framebuffer::new(
+            2,
+            2,
+            vec![
+                Color::new(4.0, 0.5, 0.0),
+                Color::new(0.25, 1.0, 2.0),
+                Color::new(0.0, 0.0, 0.0),
+                Color::new(100.0, 0.125, 3.5),
+            ],
+        )
+        .with_samples_per_pixel(32)
+        .with_scene_seed(Some(u64::MAX))
+        .with_alpha(vec![1.0, 0.5, 0.0, 0.25]);
+        let mut out = Vec::new();
+        write_exr(&frame, &mut out).unwrap();
+        assert_eq!(read_exr(out.as_slice()).unwrap(), frame);
+
+        assert!(read_exr(&out[..out.len() - 1]).is_err());
+        assert!(read_exr(&b"not an image"[..]).is_err());
+    }
+
+    #[test]
+    fn test_read_exr_half() {
+        let mut out = Vec::new();
+        write_exr(
+            &Framebuffer::new(1, 1, vec![Color::new(0.0, 0.0, 0.0)]),
+            &mut out,
+        )
+        .unwrap();
+        // Rewrite the channels as halves of 1.0, 2.0 and 0.5 for B, G and R
+        let list = b"channels\0chlist\0";
+        for channel in 0..3 {
+            let at = 8 + list.len() + 4 + channel * 18 + 2;
+            out[at..at + 4].copy_from_slice(&EXR_HALF.to_le_bytes());
+        }
+        let block = out.len() - 12 - 8;
+        out.truncate(block);
+        out.extend(0i32.to_le_bytes());
+        out.extend(6i32.to_le_bytes());
+        out.extend([0x00, 0x3c, 0x00, 0x40, 0x00, 0x38]);
+
+        let frame = read_exr(out.as_slice()).unwrap();
+        assert_eq!(frame.pixels(), [Color::new(0.5, 2.0, 1.0)]);
+        assert_eq!(half_to_f32(0x7c00), f32::INFINITY);
+        assert_eq!(half_to_f32(0x8001), -(2f32.powi(-24)));
+    }
+
+    #[test]
+    fn test_read_hdr_round_trip() {
+        let colors = [
+            Color::new(4.0, 0.5, 0.0),
+            Color::new(0.25, 1.0, 2.0),
+            Color::new(0.0, 0.0, 0.0),
+        ];
+        for width in [3, 200] {
+            let pixels = (0..width).map(|x| colors[x % 3]).collect();
+            let frame = Framebuffer::new(width as u32, 1, pixels)
+                .with_samples_per_pixel(8)
+                .with_scene_seed(Some(7));
+            let mut out = Vec::new();
+            write_hdr(&frame, &mut out).unwrap();
+
+            let read = read_hdr(out.as_slice()).unwrap();
+            assert_eq!((read.width(), read.samples_per_pixel()), (width as u32, 8));
+            assert_eq!(read.scene_seed(), Some(7));
+            for (a, b) in read.pixels().iter().zip(frame.pixels()) {
+                // Each mantissa keeps eight bits below the brightest channel's exponent
+                let tolerance = a.r().max(a.g()).max(a.b()) / 128.0;
+                for (x, y) in [(a.r(), b.r()), (a.g(), b.g()), (a.b(), b.b())] {
+                    assert!((x - y).abs() <= tolerance, "{} vs {}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_hdr_runs() {
+        // A run of eight mid-gray pixels for each channel, at exposure two
+        let mut out = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\nEXPOSURE=2\n\n-Y 1 +X 8\n".to_vec();
+        out.extend([2, 2, 0, 8]);
+        for value in [128, 128, 128, 129] {
+            out.extend([128 + 8, value]);
+        }
+        let frame = read_hdr(out.as_slice()).unwrap();
+        let expected = (128.5 / 128.0) / 2.0;
+        assert_eq!(
+            frame.pixels(),
+            [Color::new(expected, expected, expected); 8]
+        );
+
+        // Truncated scanlines and other orientations are refused
+        assert!(read_hdr(&out[..out.len() - 1]).is_err());
+        let flipped = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n+Y 1 +X 1\n\0\0\0\0";
+        assert!(read_hdr(&flipped[..]).is_err());
+        let xyz = b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n\0\0\0\0";
+        assert!(read_hdr(&xyz[..]).is_err());
+    }
+}
@@ -2,35 +2,301 @@ use crate::aabb::Aabb;
 use crate::interval::Interval;
 use crate::material::Material;
 use crate::point3::Point3;
-use crate::ray::Ray;
+use crate::ray::{PrimitiveId, Ray, RayKind};
 use crate::vec3::Vec3;
+use rand::RngCore;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// Screen-space derivatives of a hit point and its normal.
+///
+/// Computed from the ray differentials of the incoming ray and used to filter
+/// textures and to propagate differentials through specular bounces.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfaceDifferentials {
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    pub dndx: Vec3,
+    pub dndy: Vec3,
+}
+
+/// Relative distance a spawned ray's origin is pushed off the surface.
+const RAY_OFFSET_SCALE: f64 = 1e-9;
+
+/// Density of a direction drawn uniformly from the whole sphere of directions.
+pub const UNIFORM_SPHERE_PDF: f64 = 1.0 / (4.0 * PI);
+
+/// Which primitives a ray spawned from a hit skips, as an alternative to pushing
+/// its origin off the surface.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SelfHitExclusion {
+    /// Offset every spawned ray along the geometric normal
+    #[default]
+    Off,
+    /// Skip the originating primitive when it is flat, such as a quad or triangle
+    Flat,
+    /// Skip the originating primitive, ignoring only the intersection at the
+    /// ray's origin for curved primitives such as spheres
+    All,
+}
+
+impl SelfHitExclusion {
+    /// Returns whether rays leaving `primitive` should skip it.
+    pub fn excludes(&self, primitive: PrimitiveId) -> bool {
+        match self {
+            SelfHitExclusion::Off => false,
+            SelfHitExclusion::Flat => primitive.is_flat(),
+            SelfHitExclusion::All => true,
+        }
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct HitRecord<'a> {
     pub position: Point3,
+    /// The shading normal, used for BSDF evaluation.
+    ///
+    /// Primitives with interpolated normals overwrite this after `set_face_normal`,
+    /// keeping it on the same side of the surface as `geometric_normal`.
     pub normal: Vec3,
+    /// The true surface normal, facing against the incoming ray.
+    ///
+    /// Used for sidedness tests and for offsetting spawned rays.
+    pub geometric_normal: Vec3,
     pub t: f64,
     pub front_face: bool,
     pub material: Option<&'a Material>,
     pub texture_coords: (f64, f64),
+    pub differentials: Option<SurfaceDifferentials>,
+    /// Light-linking group of the object hit, if it was put in one
+    pub light_group: Option<u32>,
+    /// The primitive hit, if it has an ID
+    pub primitive: Option<PrimitiveId>,
+    /// Whether rays spawned from this hit skip `primitive` rather than being
+    /// offset from the surface
+    pub exclude_primitive: bool,
 }
 
 pub trait Hittable: Send + Sync {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord>;
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>>;
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb>;
+
+    /// Calls `visit` with every material this object owns, so scene preprocessing
+    /// can replace them. Objects without materials need not implement this.
+    fn visit_materials(&mut self, _visit: &mut dyn FnMut(&mut Arc<Material>)) {}
+
+    /// Returns why this object cannot be intersected reliably, if it cannot.
+    ///
+    /// Degenerate objects produce NaN hit records, so scene preprocessing reports
+    /// and drops them before rendering starts.
+    fn degenerate_reason(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Returns how many of this object's primitives were left out as it was built
+    /// because they are degenerate, so scene preprocessing can report them.
+    fn degenerate_primitives(&self) -> usize {
+        0
+    }
+
+    /// Returns the point on the surface with texture coordinates `uv`, as a hit
+    /// record seen from outside, for baking into texture space.
+    ///
+    /// Objects without a UV parameterization need not implement this.
+    fn surface_at(&self, _uv: (f64, f64)) -> Option<HitRecord<'_>> {
+        None
+    }
+
+    /// Returns whether `surface_at` finds any of this object's surface to bake into.
+    ///
+    /// Shapes covering all of texture space are answered by looking at its middle;
+    /// objects that may leave the middle uncovered override this.
+    fn has_texture_space(&self) -> bool {
+        self.surface_at((0.5, 0.5)).is_some()
+    }
+
+    /// Returns the center and radius of the ball this object fills, if it is one,
+    /// so overlaps with it can be measured exactly rather than by bounding box.
+    fn solid_sphere(&self) -> Option<(Point3, f64)> {
+        None
+    }
+
+    /// Returns whether this object is a volume, such as fog, that other objects
+    /// may stand inside, so overlaps with it are expected.
+    fn is_volume(&self) -> bool {
+        false
+    }
+
+    /// Returns whether `pdf_value` and `random` aim at this object, so it can be
+    /// sampled as a light. Objects that leave them to their defaults return false.
+    fn is_sampleable(&self) -> bool {
+        false
+    }
+
+    /// Returns the density, per unit solid angle, with which `random` picks
+    /// `direction` from `origin`.
+    ///
+    /// Together with `random` this lets an integrator aim rays at an object, such
+    /// as a light, and weight them correctly. Objects that cannot be sampled
+    /// return zero.
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// Returns a unit direction from `origin` towards a random point on this object.
+    fn random(&self, _origin: &Point3, _rng: &mut dyn RngCore) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+}
+
+/// Maps a point `u` in [0, 1)² to a unit direction, uniformly over the whole sphere
+/// of directions.
+pub fn uniform_sphere_direction(u: (f64, f64)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// A shared object can be placed in the world while also being used on its own.
+///
+/// Its materials cannot be replaced through the shared reference, so scene
+/// preprocessing leaves them alone.
+impl<T: Hittable + ?Sized> Hittable for Arc<T> {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        self.as_ref().hit(r, ray_t)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.as_ref().bounding_box(time0, time1)
+    }
+
+    fn degenerate_reason(&self) -> Option<&'static str> {
+        self.as_ref().degenerate_reason()
+    }
+
+    fn degenerate_primitives(&self) -> usize {
+        self.as_ref().degenerate_primitives()
+    }
+
+    fn surface_at(&self, uv: (f64, f64)) -> Option<HitRecord<'_>> {
+        self.as_ref().surface_at(uv)
+    }
+
+    fn has_texture_space(&self) -> bool {
+        self.as_ref().has_texture_space()
+    }
+
+    fn solid_sphere(&self) -> Option<(Point3, f64)> {
+        self.as_ref().solid_sphere()
+    }
+
+    fn is_volume(&self) -> bool {
+        self.as_ref().is_volume()
+    }
+
+    fn is_sampleable(&self) -> bool {
+        self.as_ref().is_sampleable()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.as_ref().pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        self.as_ref().random(origin, rng)
+    }
 }
 
 impl HitRecord<'_> {
-    /// Sets the HitRecord's normal vector
+    /// Sets the HitRecord's geometric and shading normal vectors
     ///
     /// The parameter `outward_normal` is assumed to have unit length
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vec3) {
         self.front_face = r.direction().dot(outward_normal) < 0.0;
-        self.normal = if self.front_face {
+        self.geometric_normal = if self.front_face {
             *outward_normal
         } else {
             -outward_normal
         };
+        self.normal = self.geometric_normal;
+    }
+
+    /// Creates a ray leaving the hit point in `direction`.
+    ///
+    /// The origin is offset along the geometric normal towards the side the ray
+    /// travels to, so the ray cannot re-hit the surface it starts on. With
+    /// `exclude_primitive` set the ray starts on the surface and skips the
+    /// primitive instead.
+    pub fn spawn_ray(&self, direction: Vec3, time: f64) -> Ray {
+        if self.exclude_primitive && self.primitive.is_some() {
+            return Ray::new(self.position, direction, time)
+                .with_kind(RayKind::Secondary)
+                .with_excluded_primitive(self.primitive);
+        }
+        let magnitude = self
+            .position
+            .x()
+            .abs()
+            .max(self.position.y().abs())
+            .max(self.position.z().abs())
+            .max(1.0);
+        let offset = self.geometric_normal * (RAY_OFFSET_SCALE * magnitude);
+        let origin = if direction.dot(&self.geometric_normal) < 0.0 {
+            self.position + -offset
+        } else {
+            self.position + offset
+        };
+        Ray::new(origin, direction, time).with_kind(RayKind::Secondary)
+    }
+
+    /// Computes the surface differentials from the ray's differentials, if it has any.
+    ///
+    /// The offset rays are intersected with the tangent plane at the hit point.
+    /// `curvature` is the rate of change of the outward normal per unit of surface
+    /// distance (`1 / radius` for a sphere, `0` for flat surfaces). Must be called
+    /// after `set_face_normal`.
+    pub fn set_differentials(&mut self, r: &Ray, curvature: f64) {
+        let Some(rd) = r.differentials() else {
+            return;
+        };
+
+        let normal = self.geometric_normal;
+        let plane_distance = normal.dot(&self.position);
+        let intersect = |origin: &Point3, direction: &Vec3| -> Option<Vec3> {
+            let t = (plane_distance - normal.dot(origin)) / normal.dot(direction);
+            t.is_finite()
+                .then(|| (*origin + *direction * t) - self.position)
+        };
+
+        let (Some(dpdx), Some(dpdy)) = (
+            intersect(&rd.rx_origin, &rd.rx_direction),
+            intersect(&rd.ry_origin, &rd.ry_direction),
+        ) else {
+            return;
+        };
+
+        // The normal was flipped for back faces, so its derivative flips too
+        let curvature = if self.front_face {
+            curvature
+        } else {
+            -curvature
+        };
+
+        self.differentials = Some(SurfaceDifferentials {
+            dpdx,
+            dpdy,
+            dndx: dpdx * curvature,
+            dndy: dpdy * curvature,
+        });
+    }
+
+    /// Width of the hit point's footprint in world space, or zero if unknown.
+    #[inline]
+    pub fn footprint(&self) -> f64 {
+        self.differentials
+            .map_or(0.0, |d| d.dpdx.length().max(d.dpdy.length()))
     }
 }
 
@@ -39,10 +305,114 @@ impl Default for HitRecord<'_> {
         Self {
             position: Point3::default(),
             normal: Vec3::default(),
+            geometric_normal: Vec3::default(),
             t: 0.0,
             front_face: false,
             material: None,
             texture_coords: (0.0, 0.0),
+            differentials: None,
+            light_group: None,
+            primitive: None,
+            exclude_primitive: false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::RayDifferentials;
+
+    #[test]
+    fn test_set_differentials_on_plane() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let ray = Ray::new(origin, Vec3::new(0.0, 0.0, -1.0), 0.0).with_differentials(Some(
+            RayDifferentials {
+                rx_origin: origin,
+                rx_direction: Vec3::new(0.1, 0.0, -1.0),
+                ry_origin: origin,
+                ry_direction: Vec3::new(0.0, 0.1, -1.0),
+            },
+        ));
+        let mut hit_record = HitRecord {
+            position: Point3::new(0.0, 0.0, -2.0),
+            t: 2.0,
+            ..Default::default()
+        };
+        hit_record.set_face_normal(&ray, &Vec3::new(0.0, 0.0, 1.0));
+        hit_record.set_differentials(&ray, 0.0);
+
+        let differentials = hit_record.differentials.unwrap();
+        assert!((differentials.dpdx - Vec3::new(0.2, 0.0, 0.0)).near_zero());
+        assert!((differentials.dpdy - Vec3::new(0.0, 0.2, 0.0)).near_zero());
+        assert_eq!(differentials.dndx, Vec3::default());
+        assert!((hit_record.footprint() - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_set_face_normal_sets_both_normals() {
+        let ray = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let mut hit_record = HitRecord::default();
+        hit_record.set_face_normal(&ray, &Vec3::new(0.0, 0.0, 1.0));
+        assert!(!hit_record.front_face);
+        assert_eq!(hit_record.geometric_normal, Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(hit_record.normal, hit_record.geometric_normal);
+    }
+
+    #[test]
+    fn test_spawn_ray_offsets_along_geometric_normal() {
+        let hit_record = HitRecord {
+            position: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.6, 0.8, 0.0),
+            geometric_normal: Vec3::new(0.0, 1.0, 0.0),
+            ..Default::default()
+        };
+
+        let reflected = hit_record.spawn_ray(Vec3::new(1.0, 1.0, 0.0), 0.5);
+        assert!(reflected.origin().y() > 0.0);
+        assert_eq!(reflected.origin().x(), 0.0);
+        assert_eq!(reflected.time(), 0.5);
+
+        let transmitted = hit_record.spawn_ray(Vec3::new(1.0, -1.0, 0.0), 0.5);
+        assert!(transmitted.origin().y() < 0.0);
+        assert!((*transmitted.origin() - hit_record.position).near_zero());
+        assert_eq!(transmitted.kind(), RayKind::Secondary);
+    }
+
+    #[test]
+    fn test_spawn_ray_excludes_primitive() {
+        let flat = PrimitiveId::next(true);
+        let curved = PrimitiveId::next(false);
+        assert_ne!(flat, PrimitiveId::next(true));
+        assert!(SelfHitExclusion::Flat.excludes(flat));
+        assert!(!SelfHitExclusion::Flat.excludes(curved));
+        assert!(SelfHitExclusion::All.excludes(curved));
+        assert!(!SelfHitExclusion::Off.excludes(flat));
+
+        let mut hit_record = HitRecord {
+            position: Point3::new(1.0, 2.0, 3.0),
+            geometric_normal: Vec3::new(0.0, 1.0, 0.0),
+            primitive: Some(flat),
+            ..Default::default()
+        };
+        let offset = hit_record.spawn_ray(Vec3::new(0.0, 1.0, 0.0), 0.0);
+        assert_ne!(*offset.origin(), hit_record.position);
+        assert_eq!(offset.excluded_primitive(), None);
+
+        hit_record.exclude_primitive = true;
+        let excluding = hit_record.spawn_ray(Vec3::new(0.0, 1.0, 0.0), 0.0);
+        assert_eq!(*excluding.origin(), hit_record.position);
+        assert_eq!(excluding.excluded_primitive(), Some(flat));
+        assert_eq!(excluding.kind(), RayKind::Secondary);
+    }
+
+    #[test]
+    fn test_set_differentials_without_ray_differentials() {
+        let ray = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let mut hit_record = HitRecord::default();
+        hit_record.set_face_normal(&ray, &Vec3::new(0.0, 0.0, 1.0));
+        hit_record.set_differentials(&ray, 1.0);
+        assert!(hit_record.differentials.is_none());
+        assert_eq!(hit_record.footprint(), 0.0);
+    }
+}
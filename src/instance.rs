@@ -0,0 +1,263 @@
+//! Instancing: one shared object placed many times.
+//!
+//! An [`Instance`] places a shared object with a [`Transform`], built up from
+//! moves, turns and stretches, and can give it a material of its own, so thousands
+//! of copies of a mesh cost one copy of its triangles and BVH. Rays are carried into
+//! the object's space rather than the object into the world, which leaves the ray
+//! parameter `t` unchanged.
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::{InstanceId, Ray};
+use crate::transform::Transform;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+/// A shared object placed in the world by an affine transform.
+///
+/// The builder methods act in the object's own space, about its origin, before
+/// the placements given earlier: `Instance::new(object, offset).rotate_y(30.0)`
+/// turns the object where it stands, then moves it by `offset`.
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    to_world: Transform,
+    /// The inverse of `to_world`, or `None` if it flattens the object
+    to_local: Option<Transform>,
+    material: Option<Arc<Material>>,
+    /// Tells this placement's primitives apart from other placements' of the object
+    id: InstanceId,
+}
+
+impl Instance {
+    /// Places `object` moved by `offset`, at its own size and with its own materials.
+    pub fn new(object: Arc<dyn Hittable>, offset: Vec3) -> Self {
+        let to_world = Transform::translation(offset);
+        Self {
+            object,
+            to_world,
+            to_local: to_world.inverse(),
+            material: None,
+            id: InstanceId::next(),
+        }
+    }
+
+    /// Stretches the object by each component of `scale` about its origin.
+    pub fn scale(self, scale: Vec3) -> Self {
+        self.transform(Transform::scaling(scale))
+    }
+
+    /// Turns the object `degrees` about its x axis.
+    pub fn rotate_x(self, degrees: f64) -> Self {
+        self.transform(Transform::rotation_x(degrees))
+    }
+
+    /// Turns the object `degrees` about its y axis.
+    pub fn rotate_y(self, degrees: f64) -> Self {
+        self.transform(Transform::rotation_y(degrees))
+    }
+
+    /// Turns the object `degrees` about its z axis.
+    pub fn rotate_z(self, degrees: f64) -> Self {
+        self.transform(Transform::rotation_z(degrees))
+    }
+
+    /// Applies `transform` to the object before the placements so far.
+    pub fn transform(mut self, transform: Transform) -> Self {
+        self.to_world = self.to_world * transform;
+        self.to_local = self.to_world.inverse();
+        self
+    }
+
+    /// Shades this instance with `material` instead of the object's own.
+    pub fn material(mut self, material: impl Into<Arc<Material>>) -> Self {
+        self.material = Some(material.into());
+        self
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let to_local = self.to_local.as_ref()?;
+        let local = Ray::new(
+            to_local.point(r.origin()),
+            to_local.vector(r.direction()),
+            r.time(),
+        )
+        .with_kind(r.kind())
+        .with_viewpoint(to_local.point(r.viewpoint()))
+        .with_excluded_primitive(r.excluded_primitive().map(|p| p.outside(self.id)));
+
+        let mut hit_record = self.object.hit(&local, ray_t)?;
+        hit_record.position = self.to_world.point(&hit_record.position);
+        hit_record.primitive = hit_record.primitive.map(|p| p.within(self.id));
+        // Normals go through the inverse transpose so they stay perpendicular to
+        // stretched surfaces
+        hit_record.normal = to_local.transposed_vector(&hit_record.normal).unit();
+        hit_record.geometric_normal = to_local
+            .transposed_vector(&hit_record.geometric_normal)
+            .unit();
+        // Stretching bends curved surfaces unevenly, so their curvature is lost
+        hit_record.differentials = None;
+        hit_record.set_differentials(r, 0.0);
+        if let Some(material) = &self.material {
+            hit_record.material = Some(material.as_ref());
+        }
+        Some(hit_record)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        let bbox = self.object.bounding_box(time0, time1)?;
+        Some(self.to_world.bounds(&bbox))
+    }
+
+    /// Only the instance's own material can be replaced; the object's are shared.
+    fn visit_materials(&mut self, visit: &mut dyn FnMut(&mut Arc<Material>)) {
+        if let Some(material) = &mut self.material {
+            visit(material);
+        }
+    }
+
+    fn is_volume(&self) -> bool {
+        self.object.is_volume()
+    }
+
+    fn degenerate_reason(&self) -> Option<&'static str> {
+        if self.to_local.is_none() || !self.to_world.is_finite() {
+            Some("transform is singular")
+        } else {
+            self.object.degenerate_reason()
+        }
+    }
+
+    fn degenerate_primitives(&self) -> usize {
+        self.object.degenerate_primitives()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Dielectric, TestMaterial};
+    use crate::point3::Point3;
+    use crate::sphere::{Sphere, SphereType};
+
+    #[test]
+    fn test_instance_moves_and_stretches() {
+        let unit: Arc<dyn Hittable> = Arc::new(SphereType::Static(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            TestMaterial::new(),
+        )));
+        let instance = Instance::new(Arc::clone(&unit), Vec3::new(10.0, 0.0, 0.0))
+            .scale(Vec3::new(2.0, 1.0, 1.0))
+            .material(Dielectric::new(1.5));
+
+        let bbox = instance.bounding_box(0.0, 1.0).unwrap();
+        assert_eq!(bbox.center(), Point3::new(10.0, 0.0, 0.0));
+        assert_eq!(bbox.diagonal(), Vec3::new(4.0, 2.0, 2.0));
+
+        // The stretched sphere reaches out to x = 8, two units from its center
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let hit = instance
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!((hit.t - 8.0).abs() < 1e-9);
+        assert!((hit.position.x() - 8.0).abs() < 1e-9);
+        assert!((hit.normal - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-9);
+        assert_eq!(hit.material, Some(&Dielectric::new(1.5)));
+
+        // Off the axis the normal leans towards the short axes of the ellipsoid
+        let ray = Ray::new(Point3::new(10.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let hit = instance
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-9);
+        assert!((hit.normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+
+        // The object is shared, not copied
+        assert_eq!(Arc::strong_count(&unit), 2);
+        let flat = Instance::new(unit, Vec3::new(0.0, 0.0, 0.0)).scale(Vec3::new(1.0, 0.0, 1.0));
+        assert_eq!(flat.degenerate_reason(), Some("transform is singular"));
+        assert!(
+            flat.hit(&ray, Interval::new(0.001, f64::INFINITY))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_instance_turns() {
+        let unit: Arc<dyn Hittable> = Arc::new(SphereType::Static(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            TestMaterial::new(),
+        )));
+        // Stretched along z, then turned a quarter so the long axis lies along x
+        let instance = Instance::new(unit, Vec3::new(0.0, 0.0, -10.0))
+            .rotate_y(90.0)
+            .scale(Vec3::new(1.0, 1.0, 3.0));
+
+        let bbox = instance.bounding_box(0.0, 1.0).unwrap();
+        assert!((bbox.diagonal() - Vec3::new(6.0, 2.0, 2.0)).length() < 1e-9);
+        assert!((bbox.center() - Point3::new(0.0, 0.0, -10.0)).length() < 1e-9);
+
+        let ray = Ray::new(
+            Point3::new(-10.0, 0.0, -10.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            0.0,
+        );
+        let hit = instance
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!((hit.t - 7.0).abs() < 1e-9);
+        assert!((hit.normal - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-9);
+        assert!(hit.front_face);
+
+        // Looking down the turned short axis, the sphere is one unit deep
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let hit = instance
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!((hit.t - 9.0).abs() < 1e-9);
+        assert!((hit.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_rays_leaving_one_instance_hit_another() {
+        use crate::quad::Quad;
+
+        let unit: Arc<dyn Hittable> = Arc::new(Quad::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            TestMaterial::new(),
+        ));
+        let near = Instance::new(Arc::clone(&unit), Vec3::new(0.0, 0.0, 0.0));
+        let far = Instance::new(unit, Vec3::new(0.0, 0.0, -2.0));
+
+        let ray = Ray::new(Point3::new(0.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let mut hit = near.hit(&ray, Interval::new(0.001, f64::INFINITY)).unwrap();
+        hit.exclude_primitive = true;
+        assert_ne!(
+            hit.primitive,
+            far.hit(&ray, Interval::new(0.001, f64::INFINITY))
+                .unwrap()
+                .primitive
+        );
+
+        // The same quad placed further on is still there for the spawned ray
+        let through = hit.spawn_ray(Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let behind = far
+            .hit(&through, Interval::new(0.0, f64::INFINITY))
+            .unwrap();
+        assert!((behind.t - 2.0).abs() < 1e-9);
+
+        // While the copy it left is skipped
+        let grazing = hit.spawn_ray(Vec3::new(1.0, 0.0, -1e-12), 0.0);
+        assert!(
+            near.hit(&grazing, Interval::new(0.0, f64::INFINITY))
+                .is_none()
+        );
+    }
+}
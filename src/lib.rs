@@ -0,0 +1,169 @@
+//! A physically based path tracer, grown from *Ray Tracing in One Weekend*.
+//!
+//! Scenes are built from hittable objects such as spheres, each with a material,
+//! and rendered by a camera configured with [`camera::CameraBuilder`]. The most
+//! common types are re-exported from [`prelude`]:
+//!
+//! ```no_run
+//! use raytrace::prelude::*;
+//!
+//! let sphere = SphereBuilder::new()
+//!     .center(Point3::new(0.0, 0.0, -1.0))
+//!     .radius(0.5)
+//!     .material(Lambertian::clay())
+//!     .build()
+//!     .unwrap();
+//! let world = Bvh::new(vec![Box::new(sphere)]).unwrap();
+//! let image = CameraBuilder::new().image_width(100).build().render_frame(&world);
+//! image.write_ppm(&mut std::io::stdout()).unwrap();
+//! ```
+//!
+//! Materials and textures beyond the built-in ones are added by implementing
+//! [`material::Scatter`] or [`texture::Texture`] and wrapping the implementation
+//! with [`material::CustomMaterial::new`] or [`texture::CustomTexture::new`].
+//!
+//! Rendering needs the default `std` feature. Without it the crate is `no_std` and
+//! only the math core is built: [`vec3`], [`point3`], [`ray`], [`interval`],
+//! [`aabb`] and [`transform`], for reuse in kernels and on embedded targets.
+//!
+//! The `serde` feature derives `Serialize` and `Deserialize` for the math types,
+//! colors, materials, textures, lights, spheres, meshes and the camera's settings.
+//! Custom materials and textures are skipped, and fail to serialize. Image textures
+//! are saved as their paths, but cannot be read back without a texture cache.
+//!
+//! The default `scene` feature adds [`scene`], which loads whole scenes from JSON
+//! files.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+// The math core, which needs neither the standard library nor an allocator
+pub mod aabb;
+// Tests link the standard library, whose float methods shadow these
+#[cfg(not(any(feature = "std", test)))]
+mod float;
+pub mod interval;
+pub mod point3;
+pub mod ray;
+pub mod transform;
+pub mod vec3;
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod apng;
+#[cfg(feature = "std")]
+pub mod background;
+#[cfg(feature = "std")]
+pub mod bvh;
+#[cfg(feature = "std")]
+pub mod camera;
+#[cfg(feature = "std")]
+pub mod color;
+#[cfg(feature = "std")]
+pub mod compare;
+#[cfg(feature = "std")]
+pub mod display;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
+pub mod framebuffer;
+#[cfg(feature = "std")]
+pub mod guiding;
+#[cfg(feature = "std")]
+pub mod hdr;
+#[cfg(feature = "std")]
+pub mod hittable;
+#[cfg(feature = "std")]
+pub mod instance;
+#[cfg(feature = "std")]
+pub mod light;
+#[cfg(feature = "std")]
+pub mod light_linking;
+#[cfg(feature = "std")]
+pub mod light_tree;
+#[cfg(feature = "std")]
+pub mod lod;
+#[cfg(feature = "std")]
+pub mod lut;
+#[cfg(feature = "std")]
+pub mod material;
+#[cfg(feature = "std")]
+pub mod medium;
+#[cfg(feature = "std")]
+pub mod mesh;
+#[cfg(feature = "std")]
+pub mod microfacet;
+#[cfg(feature = "std")]
+pub mod onb;
+#[cfg(feature = "std")]
+pub mod overrides;
+#[cfg(feature = "std")]
+mod parallel;
+#[cfg(feature = "std")]
+pub mod path_trace;
+#[cfg(feature = "std")]
+pub mod pdf;
+#[cfg(feature = "std")]
+pub mod perlin;
+#[cfg(feature = "std")]
+pub mod physics;
+#[cfg(feature = "std")]
+pub mod placement;
+#[cfg(feature = "std")]
+pub mod postprocess;
+#[cfg(feature = "std")]
+pub mod preprocess;
+#[cfg(feature = "std")]
+pub mod progress;
+#[cfg(feature = "std")]
+pub mod quad;
+#[cfg(feature = "std")]
+pub mod sampler;
+#[cfg(feature = "scene")]
+pub mod scene;
+#[cfg(feature = "std")]
+pub mod scenes;
+#[cfg(feature = "std")]
+pub mod sphere;
+#[cfg(feature = "std")]
+pub mod texture;
+#[cfg(feature = "std")]
+pub mod texture_cache;
+#[cfg(feature = "std")]
+pub mod units;
+#[cfg(feature = "std")]
+pub mod utilities;
+#[cfg(feature = "std")]
+pub mod visibility;
+
+#[cfg(feature = "std")]
+pub use error::Error;
+
+/// The types needed to build and render a scene.
+#[cfg(feature = "std")]
+pub mod prelude {
+    pub use crate::bvh::{Bvh, BvhBuildStrategy};
+    pub use crate::camera::{Camera, CameraBuilder};
+    pub use crate::color::Color;
+    pub use crate::framebuffer::Framebuffer;
+    pub use crate::hittable::Hittable;
+    pub use crate::instance::Instance;
+    pub use crate::light::{Light, PointLight, QuadLight, SpotLight, SunLight};
+    pub use crate::lod::LodGroup;
+    pub use crate::material::{
+        ComplexIor, CustomMaterial, Dielectric, DiffuseLight, Isotropic, Lambertian, Material,
+        Metal, Scatter,
+    };
+    pub use crate::medium::ConstantMedium;
+    pub use crate::mesh::{Mesh, TriangleMesh};
+    pub use crate::point3::Point3;
+    pub use crate::quad::{BoxObject, Quad};
+    pub use crate::sphere::{SphereBuilder, SphereType};
+    pub use crate::texture::{
+        CheckerTexture, CustomTexture, ImageTexture, NoisePattern, NoiseTexture, SolidColor,
+        Texture, TextureEnum, WindowTexture,
+    };
+    pub use crate::texture_cache::TextureCache;
+    pub use crate::transform::Transform;
+    pub use crate::vec3::Vec3;
+}
@@ -0,0 +1,670 @@
+//! Analytic light sources.
+//!
+//! These lights are not part of the scene's geometry, so the camera never sees them
+//! and scattered rays do not stop at them. Instead the camera samples them directly
+//! from diffuse surfaces and casts a shadow ray to test visibility. Area lights can
+//! also be found by scattered rays, so their reflections show in glossy surfaces.
+//!
+//! Lights with an extent map a point in the unit square onto it. The camera passes
+//! stratified points, so the samples of a pixel spread evenly over a light instead
+//! of clumping, which shows up as blotchy soft shadows.
+
+use crate::color::Color;
+use crate::hittable::uniform_sphere_direction;
+use crate::interval::Interval;
+use crate::onb::Onb;
+use crate::point3::Point3;
+use crate::ray::Ray;
+use crate::sphere::sphere_cap_direction;
+use crate::utilities::degrees_to_radians;
+use crate::vec3::Vec3;
+
+/// How a light's intensity decreases with distance.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Falloff {
+    /// Physically correct `1 / d²` falloff
+    #[default]
+    InverseSquare,
+    /// No falloff; the light is equally bright at any distance
+    Constant,
+}
+
+impl Falloff {
+    /// Returns the attenuation at distance `distance` from a light of the given radius.
+    ///
+    /// Inside the radius the distance is clamped, so the light does not blow up to
+    /// infinity when a surface touches it.
+    #[inline]
+    pub fn attenuation(&self, distance: f64, radius: f64) -> f64 {
+        match self {
+            Falloff::InverseSquare => {
+                let d = distance.max(radius).max(f64::EPSILON);
+                1.0 / (d * d)
+            }
+            Falloff::Constant => 1.0,
+        }
+    }
+}
+
+/// A light that can be sampled from a point in the scene.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Light {
+    /// A light emitting in all directions from a point or small sphere
+    Point(PointLight),
+    /// A point light restricted to a cone
+    Spot(SpotLight),
+    /// A distant light such as the sun, arriving from a small cone of directions
+    Sun(SunLight),
+    /// A one-sided rectangular area light
+    Quad(QuadLight),
+}
+
+/// The result of sampling a light from a shading point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightSample {
+    /// Unit direction from the shading point towards the sampled point on the light
+    pub direction: Vec3,
+    /// Distance to the sampled point on the light; infinite for distant lights
+    pub distance: f64,
+    /// Irradiance arriving at the shading point from this sample, before the cosine term
+    pub irradiance: Color,
+}
+
+impl Light {
+    /// Samples the light as seen from `point`, using `u` in [0, 1)² to pick where
+    /// on the light the sample lands.
+    ///
+    /// Returns `None` if the light cannot illuminate the point at all.
+    #[inline]
+    pub fn sample(&self, point: &Point3, u: (f64, f64)) -> Option<LightSample> {
+        match self {
+            Light::Point(l) => l.sample(point, u),
+            Light::Spot(l) => l.sample(point, u),
+            Light::Sun(l) => Some(l.sample(u)),
+            Light::Quad(l) => l.sample(point, u),
+        }
+    }
+
+    /// Returns how far along `ray` it meets the emitting side of the light, within
+    /// `ray_t`, and the radiance seen there.
+    ///
+    /// Only area lights can be reached by a ray; the others always return `None`.
+    #[inline]
+    pub fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<(f64, Color)> {
+        match self {
+            Light::Quad(l) => l.hit(ray, ray_t).map(|t| (t, l.radiance)),
+            _ => None,
+        }
+    }
+
+    /// Whether scattered rays can find the light, so `hit` may return a hit.
+    pub fn is_reachable(&self) -> bool {
+        matches!(self, Light::Quad(_))
+    }
+
+    /// Returns the density over solid angle with which `sample` picks `direction`
+    /// from `point`, for the lights `hit` can find.
+    ///
+    /// Returns `None` for lights that can only be reached by sampling them.
+    pub fn pdf(&self, point: &Point3, direction: &Vec3) -> Option<f64> {
+        match self {
+            Light::Quad(l) => Some(l.pdf(point, direction)),
+            _ => None,
+        }
+    }
+
+    /// Returns where the light is, or `None` for distant lights.
+    pub fn position(&self) -> Option<Point3> {
+        match self {
+            Light::Point(l) => Some(l.position),
+            Light::Spot(l) => Some(l.position),
+            Light::Sun(_) => None,
+            Light::Quad(l) => Some(l.corner + (l.u + l.v) * 0.5),
+        }
+    }
+
+    /// Returns the luminance of the light's intensity, a rough measure of its brightness.
+    pub fn power(&self) -> f64 {
+        match self {
+            Light::Point(l) => l.intensity.luminance(),
+            Light::Spot(l) => l.intensity.luminance(),
+            Light::Sun(l) => l.irradiance.luminance(),
+            Light::Quad(l) => l.radiance.luminance() * l.area,
+        }
+    }
+}
+
+/// An omnidirectional light.
+///
+/// A non-zero `radius` turns the light into a small sphere, which softens shadows
+/// with a penumbra proportional to its size.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PointLight {
+    position: Point3,
+    intensity: Color,
+    radius: f64,
+    falloff: Falloff,
+}
+
+impl PointLight {
+    /// Creates a new point light at `position` with the given radiant intensity.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(position: Point3, intensity: Color) -> Light {
+        Light::Point(PointLight {
+            position,
+            intensity,
+            radius: 0.0,
+            falloff: Falloff::default(),
+        })
+    }
+
+    #[inline]
+    fn sample(&self, point: &Point3, u: (f64, f64)) -> Option<LightSample> {
+        sample_emitter(
+            &self.position,
+            self.radius,
+            self.intensity,
+            self.falloff,
+            point,
+            u,
+        )
+    }
+}
+
+/// A point light that only emits inside a cone.
+///
+/// The intensity fades smoothly to zero between `cone_angle - edge_angle` and
+/// `cone_angle`, both measured in degrees from the spot's axis.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpotLight {
+    position: Point3,
+    direction: Vec3,
+    intensity: Color,
+    radius: f64,
+    falloff: Falloff,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl SpotLight {
+    /// Creates a new spot light at `position` pointing along `direction`.
+    ///
+    /// # Arguments
+    /// * `cone_angle` - Half-angle of the cone in degrees
+    /// * `edge_angle` - Width in degrees of the soft edge inside the cone
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        position: Point3,
+        direction: Vec3,
+        intensity: Color,
+        cone_angle: f64,
+        edge_angle: f64,
+    ) -> Light {
+        let outer = degrees_to_radians(cone_angle.clamp(0.0, 180.0));
+        let inner = (outer - degrees_to_radians(edge_angle.max(0.0))).max(0.0);
+        Light::Spot(SpotLight {
+            position,
+            direction: direction.unit(),
+            intensity,
+            radius: 0.0,
+            falloff: Falloff::default(),
+            cos_inner: inner.cos(),
+            cos_outer: outer.cos(),
+        })
+    }
+
+    #[inline]
+    fn sample(&self, point: &Point3, u: (f64, f64)) -> Option<LightSample> {
+        // Spot attenuation is measured from the light's center
+        let to_point = (*point - self.position).unit();
+        let cos_angle = to_point.dot(&self.direction);
+        if cos_angle <= self.cos_outer {
+            return None;
+        }
+        let cone = if cos_angle >= self.cos_inner {
+            1.0
+        } else {
+            let t = (cos_angle - self.cos_outer) / (self.cos_inner - self.cos_outer);
+            t * t * (3.0 - 2.0 * t)
+        };
+
+        sample_emitter(
+            &self.position,
+            self.radius,
+            self.intensity * cone,
+            self.falloff,
+            point,
+            u,
+        )
+    }
+}
+
+impl Light {
+    /// Returns this light with the given radius, turning it into a small sphere.
+    ///
+    /// Distant and area lights already have their shape, so they are returned unchanged.
+    pub fn radius(mut self, radius: f64) -> Light {
+        let radius = radius.max(0.0);
+        match &mut self {
+            Light::Point(l) => l.radius = radius,
+            Light::Spot(l) => l.radius = radius,
+            Light::Sun(_) | Light::Quad(_) => {}
+        }
+        self
+    }
+
+    /// Returns this light with the given distance falloff.
+    ///
+    /// Distant and area lights have no falloff to choose, so they are returned unchanged.
+    pub fn falloff(mut self, falloff: Falloff) -> Light {
+        match &mut self {
+            Light::Point(l) => l.falloff = falloff,
+            Light::Spot(l) => l.falloff = falloff,
+            Light::Sun(_) | Light::Quad(_) => {}
+        }
+        self
+    }
+}
+
+/// A directional light infinitely far away.
+///
+/// The sun covers a disk of `angular_diameter` degrees in the sky (about 0.53 for
+/// the real sun). Sampling directions across that disk gives shadows soft edges
+/// whose width grows with the distance from the occluder.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SunLight {
+    to_sun: Onb,
+    irradiance: Color,
+    cos_half_angle: f64,
+}
+
+impl SunLight {
+    /// Creates a new sun shining along `direction`.
+    ///
+    /// # Arguments
+    /// * `direction` - The direction the light travels, i.e. from the sun to the scene
+    /// * `irradiance` - Irradiance on a surface facing the sun
+    /// * `angular_diameter` - Apparent diameter of the sun's disk in degrees
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(direction: Vec3, irradiance: Color, angular_diameter: f64) -> Light {
+        let half_angle = degrees_to_radians(angular_diameter.clamp(0.0, 180.0)) / 2.0;
+        Light::Sun(SunLight {
+            to_sun: Onb::new(&-direction),
+            irradiance,
+            cos_half_angle: half_angle.cos(),
+        })
+    }
+
+    /// Maps `u` to a direction uniformly over the solid angle of the sun's disk.
+    #[inline]
+    fn sample(&self, u: (f64, f64)) -> LightSample {
+        let cos_theta = 1.0 - u.0 * (1.0 - self.cos_half_angle);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * u.1;
+        let local = Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta);
+
+        LightSample {
+            direction: self.to_sun.transform(&local),
+            distance: f64::INFINITY,
+            irradiance: self.irradiance,
+        }
+    }
+}
+
+/// A rectangle emitting `radiance` from the side its normal `u × v` points to.
+///
+/// Points are picked uniformly over the area and converted to a density over solid
+/// angle, so a sample near the edge of a large light, seen at a grazing angle or
+/// from far away, carries correspondingly less light.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QuadLight {
+    corner: Point3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    area: f64,
+    radiance: Color,
+}
+
+impl QuadLight {
+    /// Creates a quad light spanning `corner` to `corner + u + v`.
+    ///
+    /// # Arguments
+    /// * `corner` - One corner of the rectangle
+    /// * `u`, `v` - The two edges leaving `corner`
+    /// * `radiance` - Radiance leaving the emitting side in every direction
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(corner: Point3, u: Vec3, v: Vec3, radiance: Color) -> Light {
+        let cross = u.cross(&v);
+        let area = cross.length();
+        Light::Quad(QuadLight {
+            corner,
+            u,
+            v,
+            normal: if area > 0.0 { cross / area } else { cross },
+            area,
+            radiance,
+        })
+    }
+
+    #[inline]
+    fn sample(&self, point: &Point3, u: (f64, f64)) -> Option<LightSample> {
+        let target = self.corner + self.u * u.0 + self.v * u.1;
+        let to_light = target - *point;
+        let distance_squared = to_light.length_squared();
+        if distance_squared == 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        let direction = to_light / distance;
+
+        // The emitting side must face the point
+        let cos_light = -direction.dot(&self.normal);
+        if cos_light <= 0.0 {
+            return None;
+        }
+
+        // Dividing by the density over solid angle, distance² / (cos · area)
+        Some(LightSample {
+            direction,
+            distance,
+            irradiance: self.radiance * (self.area * cos_light / distance_squared),
+        })
+    }
+
+    /// Returns the ray parameter where `ray` crosses the quad from its emitting side.
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<f64> {
+        let denominator = self.normal.dot(ray.direction());
+        if denominator >= 0.0 {
+            return None;
+        }
+        let t = (self.corner - *ray.origin()).dot(&self.normal) / denominator;
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+
+        // Coordinates of the crossing along the two edges
+        let cross = self.u.cross(&self.v);
+        let w = cross / cross.length_squared();
+        let planar = ray.at_time(t) - self.corner;
+        let alpha = w.dot(&planar.cross(&self.v));
+        let beta = w.dot(&self.u.cross(&planar));
+        ((0.0..=1.0).contains(&alpha) && (0.0..=1.0).contains(&beta)).then_some(t)
+    }
+
+    fn pdf(&self, point: &Point3, direction: &Vec3) -> f64 {
+        let ray = Ray::new(*point, direction.unit(), 0.0);
+        let Some(distance) = self.hit(&ray, Interval::new(0.0, f64::INFINITY)) else {
+            return 0.0;
+        };
+        let cos_light = -ray.direction().dot(&self.normal);
+        distance * distance / (cos_light * self.area)
+    }
+}
+
+/// Samples a point or spherical emitter centered at `center`.
+///
+/// Outside a spherical emitter, directions are drawn uniformly over the cap facing
+/// the point, as points on the far side would be hidden behind it.
+#[inline]
+fn sample_emitter(
+    center: &Point3,
+    radius: f64,
+    intensity: Color,
+    falloff: Falloff,
+    point: &Point3,
+    u: (f64, f64),
+) -> Option<LightSample> {
+    let center_distance = (*center - *point).length();
+    if center_distance == 0.0 {
+        return None;
+    }
+
+    let (direction, distance) = if radius > 0.0 && center_distance > radius {
+        let direction = sphere_cap_direction(*center, radius, point, u);
+        // Nearest intersection of the sampled direction with the sphere
+        let along = direction.dot(&(*center - *point));
+        let half_chord = (along * along - (center_distance * center_distance - radius * radius))
+            .max(0.0)
+            .sqrt();
+        (direction, along - half_chord)
+    } else {
+        let target = if radius > 0.0 {
+            *center + uniform_sphere_direction(u) * radius
+        } else {
+            *center
+        };
+        let to_light = target - *point;
+        let distance = to_light.length();
+        if distance == 0.0 {
+            return None;
+        }
+        (to_light / distance, distance)
+    };
+
+    Some(LightSample {
+        direction,
+        distance,
+        irradiance: intensity * falloff.attenuation(center_distance, radius),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::random_double;
+
+    fn random_u() -> (f64, f64) {
+        (random_double(), random_double())
+    }
+
+    #[test]
+    fn test_inverse_square_falloff() {
+        let light = PointLight::new(Point3::new(0.0, 2.0, 0.0), Color::new(4.0, 4.0, 4.0));
+        let sample = light
+            .sample(&Point3::new(0.0, 0.0, 0.0), random_u())
+            .unwrap();
+        assert_eq!(sample.direction, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(sample.distance, 2.0);
+        assert_eq!(sample.irradiance, Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_constant_falloff() {
+        let light = PointLight::new(Point3::new(0.0, 10.0, 0.0), Color::new(1.0, 1.0, 1.0))
+            .falloff(Falloff::Constant);
+        let sample = light
+            .sample(&Point3::new(0.0, 0.0, 0.0), random_u())
+            .unwrap();
+        assert_eq!(sample.irradiance, Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_radius_clamps_falloff_and_jitters_samples() {
+        let light =
+            PointLight::new(Point3::new(0.0, 0.5, 0.0), Color::new(1.0, 1.0, 1.0)).radius(1.0);
+        let point = Point3::new(0.0, 0.0, 0.0);
+
+        // Inside the radius the falloff is clamped to 1 / r²
+        let sample = light.sample(&point, random_u()).unwrap();
+        assert_eq!(sample.irradiance, Color::new(1.0, 1.0, 1.0));
+
+        // Samples land on the light's surface, so directions vary
+        let directions: Vec<Vec3> = (0..10)
+            .map(|_| light.sample(&point, random_u()).unwrap().direction)
+            .collect();
+        assert!(directions.iter().any(|d| *d != directions[0]));
+    }
+
+    #[test]
+    fn test_sphere_light_samples_visible_cap() {
+        let center = Point3::new(0.0, 4.0, 0.0);
+        let light = PointLight::new(center, Color::new(16.0, 16.0, 16.0)).radius(1.0);
+        let point = Point3::new(0.0, 0.0, 0.0);
+        let cos_half_angle = (15.0_f64 / 16.0).sqrt();
+
+        let samples: Vec<LightSample> = (0..100)
+            .map(|_| light.sample(&point, random_u()).unwrap())
+            .collect();
+        for sample in &samples {
+            assert!(sample.direction.y() >= cos_half_angle - 1e-9);
+            // Each sample lands on the near side of the sphere
+            let target = point + sample.direction * sample.distance;
+            assert!(((target - center).length() - 1.0).abs() < 1e-9);
+            assert!(target.y() <= center.y());
+            assert_eq!(sample.irradiance, Color::new(1.0, 1.0, 1.0));
+        }
+        assert!(samples.iter().any(|s| s.direction != samples[0].direction));
+    }
+
+    #[test]
+    fn test_sun_without_diameter_is_directional() {
+        let light = SunLight::new(Vec3::new(0.0, -1.0, 0.0), Color::new(1.0, 1.0, 1.0), 0.0);
+        let sample = light
+            .sample(&Point3::new(5.0, 0.0, 5.0), random_u())
+            .unwrap();
+        assert!((sample.direction - Vec3::new(0.0, 1.0, 0.0)).near_zero());
+        assert_eq!(sample.distance, f64::INFINITY);
+        assert_eq!(sample.irradiance, Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_sun_samples_stay_within_angular_diameter() {
+        let light = SunLight::new(Vec3::new(1.0, -1.0, 0.0), Color::new(1.0, 1.0, 1.0), 10.0);
+        let to_sun = Vec3::new(-1.0, 1.0, 0.0).unit();
+        let cos_half_angle = (5.0_f64).to_radians().cos();
+
+        let samples: Vec<Vec3> = (0..100)
+            .map(|_| {
+                light
+                    .sample(&Point3::default(), random_u())
+                    .unwrap()
+                    .direction
+            })
+            .collect();
+        for direction in &samples {
+            assert!((direction.length() - 1.0).abs() < 1e-9);
+            assert!(direction.dot(&to_sun) >= cos_half_angle - 1e-9);
+        }
+        assert!(samples.iter().any(|d| *d != samples[0]));
+    }
+
+    #[test]
+    fn test_position_and_power() {
+        let point = PointLight::new(Point3::new(1.0, 2.0, 3.0), Color::new(2.0, 2.0, 2.0));
+        assert_eq!(point.position(), Some(Point3::new(1.0, 2.0, 3.0)));
+        assert!((point.power() - 2.0).abs() < 1e-12);
+
+        let sun = SunLight::new(Vec3::new(0.0, -1.0, 0.0), Color::new(1.0, 1.0, 1.0), 0.5);
+        assert_eq!(sun.position(), None);
+    }
+
+    #[test]
+    fn test_spot_light_cone() {
+        let light = SpotLight::new(
+            Point3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            30.0,
+            10.0,
+        );
+
+        // Directly below is inside the cone at full intensity
+        let inside = light
+            .sample(&Point3::new(0.0, 0.0, 0.0), random_u())
+            .unwrap();
+        assert_eq!(inside.irradiance, Color::new(1.0, 1.0, 1.0));
+
+        // 45 degrees off-axis is outside the cone
+        assert!(
+            light
+                .sample(&Point3::new(1.0, 0.0, 0.0), random_u())
+                .is_none()
+        );
+
+        // 25 degrees off-axis is in the soft edge
+        let edge = light
+            .sample(
+                &Point3::new((25.0_f64).to_radians().tan(), 0.0, 0.0),
+                random_u(),
+            )
+            .unwrap();
+        let unattenuated = light
+            .clone()
+            .falloff(Falloff::Constant)
+            .sample(
+                &Point3::new((25.0_f64).to_radians().tan(), 0.0, 0.0),
+                random_u(),
+            )
+            .unwrap();
+        assert!(unattenuated.irradiance != Color::new(1.0, 1.0, 1.0));
+        assert!(unattenuated.irradiance != Color::new(0.0, 0.0, 0.0));
+        assert!(edge.irradiance != unattenuated.irradiance);
+    }
+
+    #[test]
+    fn test_quad_light_faces_one_way() {
+        let light = QuadLight::new(
+            Point3::new(-0.5, 2.0, -0.5),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Color::new(1.0, 1.0, 1.0),
+        );
+        // u × v points down, so only points below are lit
+        let below = light
+            .sample(&Point3::new(0.0, 0.0, 0.0), (0.5, 0.5))
+            .unwrap();
+        assert_eq!(below.direction, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(below.distance, 2.0);
+        // Radiance times area over distance squared, straight on
+        assert_eq!(below.irradiance, Color::new(0.25, 0.25, 0.25));
+        assert!(
+            light
+                .sample(&Point3::new(0.0, 4.0, 0.0), (0.5, 0.5))
+                .is_none()
+        );
+        assert_eq!(light.position(), Some(Point3::new(0.0, 2.0, 0.0)));
+    }
+
+    #[test]
+    fn test_quad_light_irradiance_matches_form_factor() {
+        let (width, height) = (2.0, 1.0);
+        let light = QuadLight::new(
+            Point3::new(-width / 2.0, height, -width / 2.0),
+            Vec3::new(width, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, width),
+            Color::new(1.0, 1.0, 1.0),
+        );
+        let point = Point3::new(0.0, 0.0, 0.0);
+
+        // Midpoints of a grid of strata, as stratified samples converge to
+        let n = 64;
+        let mut irradiance = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let u = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                let sample = light.sample(&point, u).unwrap();
+                irradiance += sample.irradiance.g() * sample.direction.y();
+            }
+        }
+        irradiance /= (n * n) as f64;
+
+        // A point below the shared corner of four rectangles, each half the light
+        let x = width / 2.0 / height;
+        let corner = (x / (1.0 + x * x).sqrt() * (x / (1.0 + x * x).sqrt()).atan()) * 2.0
+            / (2.0 * std::f64::consts::PI);
+        let expected = std::f64::consts::PI * 4.0 * corner;
+        assert!(
+            (irradiance - expected).abs() < 1e-3,
+            "{} vs {}",
+            irradiance,
+            expected
+        );
+    }
+}
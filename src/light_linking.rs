@@ -0,0 +1,146 @@
+//! Light linking: choosing which lights illuminate which objects.
+//!
+//! Objects are put in a numbered group by wrapping them in `InLightGroup`, and each
+//! light can be limited to, or kept off, a set of groups. Links are applied where
+//! the camera lights a surface directly, so light that bounces off other objects
+//! still reaches everything.
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::point3::Point3;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use rand::RngCore;
+use std::sync::Arc;
+
+/// Which light groups a light illuminates.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LightLink {
+    /// Every object, grouped or not
+    #[default]
+    All,
+    /// Only objects in one of these groups
+    Include(Vec<u32>),
+    /// Every object except those in one of these groups
+    Exclude(Vec<u32>),
+}
+
+impl LightLink {
+    /// Returns true if the light illuminates an object in `group`, or an
+    /// ungrouped object when `group` is `None`.
+    #[inline]
+    pub fn illuminates(&self, group: Option<u32>) -> bool {
+        match self {
+            LightLink::All => true,
+            LightLink::Include(groups) => group.is_some_and(|group| groups.contains(&group)),
+            LightLink::Exclude(groups) => !group.is_some_and(|group| groups.contains(&group)),
+        }
+    }
+}
+
+/// Wraps a hittable so its hits report that it belongs to a light group.
+pub struct InLightGroup {
+    object: Box<dyn Hittable>,
+    group: u32,
+}
+
+impl InLightGroup {
+    /// Puts `object` in light group `group`.
+    pub fn new(object: Box<dyn Hittable>, group: u32) -> Self {
+        Self { object, group }
+    }
+}
+
+impl Hittable for InLightGroup {
+    #[inline]
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let mut hit_record = self.object.hit(ray, ray_t)?;
+        hit_record.light_group = Some(self.group);
+        Some(hit_record)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.object.bounding_box(time0, time1)
+    }
+
+    fn visit_materials(&mut self, visit: &mut dyn FnMut(&mut Arc<Material>)) {
+        self.object.visit_materials(visit);
+    }
+
+    fn degenerate_reason(&self) -> Option<&'static str> {
+        self.object.degenerate_reason()
+    }
+
+    fn degenerate_primitives(&self) -> usize {
+        self.object.degenerate_primitives()
+    }
+
+    fn surface_at(&self, uv: (f64, f64)) -> Option<HitRecord<'_>> {
+        let mut hit_record = self.object.surface_at(uv)?;
+        hit_record.light_group = Some(self.group);
+        Some(hit_record)
+    }
+
+    fn has_texture_space(&self) -> bool {
+        self.object.has_texture_space()
+    }
+
+    fn solid_sphere(&self) -> Option<(Point3, f64)> {
+        self.object.solid_sphere()
+    }
+
+    fn is_sampleable(&self) -> bool {
+        self.object.is_sampleable()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.object.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        self.object.random(origin, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::TestMaterial;
+    use crate::sphere::SphereBuilder;
+
+    #[test]
+    fn test_light_link_illuminates() {
+        assert!(LightLink::All.illuminates(None));
+        assert!(LightLink::All.illuminates(Some(3)));
+
+        let include = LightLink::Include(vec![1, 2]);
+        assert!(include.illuminates(Some(2)));
+        assert!(!include.illuminates(Some(3)));
+        assert!(!include.illuminates(None));
+
+        let exclude = LightLink::Exclude(vec![1, 2]);
+        assert!(!exclude.illuminates(Some(1)));
+        assert!(exclude.illuminates(Some(3)));
+        assert!(exclude.illuminates(None));
+    }
+
+    #[test]
+    fn test_in_light_group_tags_hits() {
+        let sphere = SphereBuilder::new()
+            .center(Point3::new(0.0, 0.0, -2.0))
+            .radius(0.5)
+            .material(TestMaterial::new())
+            .build()
+            .unwrap();
+        let ray = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        assert_eq!(sphere.hit(&ray, ray_t).unwrap().light_group, None);
+
+        let grouped = InLightGroup::new(Box::new(sphere), 4);
+        assert_eq!(grouped.hit(&ray, ray_t).unwrap().light_group, Some(4));
+        assert_eq!(grouped.surface_at((0.5, 0.5)).unwrap().light_group, Some(4));
+    }
+}
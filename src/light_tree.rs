@@ -0,0 +1,296 @@
+//! Importance sampling of scenes with many lights.
+//!
+//! Positioned lights are arranged in a binary tree whose nodes store the bounds and
+//! total power of the lights below them. Sampling walks down from the root, picking
+//! a child with probability proportional to how much light it could deliver to the
+//! shading point, so a single shadow ray is spent on a light that probably matters.
+//! Distant lights have no position to cluster by and are left out of the tree.
+
+use crate::light::Light;
+use crate::point3::Point3;
+use crate::utilities::random_double;
+use crate::vec3::Vec3;
+
+/// A node of the light tree.
+#[derive(Clone, Debug, PartialEq)]
+enum LightNode {
+    Leaf {
+        /// Index of the light in the camera's light list
+        light: usize,
+        bounds: Bounds,
+        power: f64,
+    },
+    Branch {
+        left: Box<LightNode>,
+        right: Box<LightNode>,
+        bounds: Bounds,
+        power: f64,
+    },
+}
+
+/// Axis-aligned bounds of the light positions below a node.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Bounds {
+    min: Vec3,
+    max: Vec3,
+}
+
+impl Bounds {
+    fn point(p: Vec3) -> Self {
+        Self { min: p, max: p }
+    }
+
+    fn union(&self, other: &Bounds) -> Self {
+        let component = |f: fn(f64, f64) -> f64, a: &Vec3, b: &Vec3| {
+            Vec3::new(f(a.x(), b.x()), f(a.y(), b.y()), f(a.z(), b.z()))
+        };
+        Self {
+            min: component(f64::min, &self.min, &other.min),
+            max: component(f64::max, &self.max, &other.max),
+        }
+    }
+
+    fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
+    fn longest_axis(&self) -> usize {
+        let extent = self.max - self.min;
+        (0..3)
+            .max_by(|&a, &b| extent[a].total_cmp(&extent[b]))
+            .unwrap_or(0)
+    }
+}
+
+impl LightNode {
+    fn bounds(&self) -> &Bounds {
+        match self {
+            LightNode::Leaf { bounds, .. } | LightNode::Branch { bounds, .. } => bounds,
+        }
+    }
+
+    fn power(&self) -> f64 {
+        match self {
+            LightNode::Leaf { power, .. } | LightNode::Branch { power, .. } => *power,
+        }
+    }
+
+    /// Returns the probability of descending from this node to light `light`, or
+    /// `None` if the light is not below it.
+    fn pdf(&self, point: &Point3, light: usize) -> Option<f64> {
+        match self {
+            LightNode::Leaf { light: index, .. } => (*index == light).then_some(1.0),
+            LightNode::Branch { left, right, .. } => {
+                let p_left = p_left(left, right, point);
+                left.pdf(point, light)
+                    .map(|pdf| pdf * p_left)
+                    .or_else(|| right.pdf(point, light).map(|pdf| pdf * (1.0 - p_left)))
+            }
+        }
+    }
+
+    /// Estimates how much light the node's lights could deliver to `point`.
+    ///
+    /// The distance is clamped to the size of the node, as any point inside the
+    /// bounds could be right next to one of its lights.
+    fn importance(&self, point: &Point3) -> f64 {
+        let bounds = self.bounds();
+        let distance_squared = (bounds.center() - point.as_vec3()).length_squared();
+        let radius_squared = ((bounds.max - bounds.min) / 2.0).length_squared();
+        self.power() / distance_squared.max(radius_squared).max(f64::EPSILON)
+    }
+}
+
+/// A hierarchy over the positioned lights of a scene for choosing one to sample.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LightTree {
+    root: Option<LightNode>,
+    /// Indices of the lights with no position, which must be sampled separately
+    distant: Vec<usize>,
+}
+
+impl LightTree {
+    /// Builds a tree over `lights`, splitting clusters at the median of their longest axis.
+    pub fn new(lights: &[Light]) -> Self {
+        let mut leaves = Vec::new();
+        let mut distant = Vec::new();
+        for (index, light) in lights.iter().enumerate() {
+            match light.position() {
+                Some(position) => leaves.push(LightNode::Leaf {
+                    light: index,
+                    bounds: Bounds::point(position.as_vec3()),
+                    power: light.power(),
+                }),
+                None => distant.push(index),
+            }
+        }
+
+        Self {
+            root: Self::build(leaves),
+            distant,
+        }
+    }
+
+    fn build(mut nodes: Vec<LightNode>) -> Option<LightNode> {
+        if nodes.len() <= 1 {
+            return nodes.pop();
+        }
+
+        let bounds = nodes
+            .iter()
+            .skip(1)
+            .fold(*nodes[0].bounds(), |bounds, node| {
+                bounds.union(node.bounds())
+            });
+        let axis = bounds.longest_axis();
+        nodes.sort_by(|a, b| a.bounds().center()[axis].total_cmp(&b.bounds().center()[axis]));
+
+        let right = nodes.split_off(nodes.len() / 2);
+        let left = Self::build(nodes)?;
+        let right = Self::build(right)?;
+        Some(LightNode::Branch {
+            power: left.power() + right.power(),
+            left: Box::new(left),
+            right: Box::new(right),
+            bounds,
+        })
+    }
+
+    /// Indices of the lights without a position, such as the sun.
+    pub fn distant(&self) -> &[usize] {
+        &self.distant
+    }
+
+    /// Returns the probability that `sample` picks light `light` from `point`.
+    pub fn pdf(&self, point: &Point3, light: usize) -> f64 {
+        self.root
+            .as_ref()
+            .and_then(|root| root.pdf(point, light))
+            .unwrap_or(0.0)
+    }
+
+    /// Picks one positioned light to sample from `point`.
+    ///
+    /// Returns the light's index and the probability it was picked, or `None` if the
+    /// scene has no positioned lights.
+    pub fn sample(&self, point: &Point3) -> Option<(usize, f64)> {
+        let mut node = self.root.as_ref()?;
+        let mut pdf = 1.0;
+
+        loop {
+            match node {
+                LightNode::Leaf { light, .. } => return Some((*light, pdf)),
+                LightNode::Branch { left, right, .. } => {
+                    let p_left = p_left(left, right, point);
+                    if random_double() < p_left {
+                        pdf *= p_left;
+                        node = left;
+                    } else {
+                        pdf *= 1.0 - p_left;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns the probability of descending into `left` rather than `right` from `point`.
+fn p_left(left: &LightNode, right: &LightNode, point: &Point3) -> f64 {
+    let left_importance = left.importance(point);
+    let total = left_importance + right.importance(point);
+    if total > 0.0 {
+        left_importance / total
+    } else {
+        0.5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::light::{PointLight, SunLight};
+    use crate::utilities::seed_thread_rng;
+
+    fn lights() -> Vec<Light> {
+        (0..8)
+            .map(|i| {
+                PointLight::new(
+                    Point3::new(i as f64 * 10.0, 5.0, 0.0),
+                    Color::new(1.0, 1.0, 1.0),
+                )
+            })
+            .chain([SunLight::new(
+                Vec3::new(0.0, -1.0, 0.0),
+                Color::new(1.0, 1.0, 1.0),
+                0.5,
+            )])
+            .collect()
+    }
+
+    #[test]
+    fn test_distant_lights_are_kept_out_of_the_tree() {
+        let tree = LightTree::new(&lights());
+        assert_eq!(tree.distant(), &[8]);
+
+        let only_sun = LightTree::new(&lights()[8..]);
+        assert_eq!(only_sun.sample(&Point3::default()), None);
+    }
+
+    #[test]
+    fn test_single_light_is_always_picked() {
+        let tree = LightTree::new(&lights()[..1]);
+        assert_eq!(tree.sample(&Point3::default()), Some((0, 1.0)));
+    }
+
+    #[test]
+    fn test_pdf_matches_sampling() {
+        seed_thread_rng(3);
+        let tree = LightTree::new(&lights());
+        let point = Point3::new(12.0, 0.0, 0.0);
+        for _ in 0..20 {
+            let (light, pdf) = tree.sample(&point).unwrap();
+            assert!((tree.pdf(&point, light) - pdf).abs() < 1e-12);
+        }
+        let total: f64 = (0..8).map(|light| tree.pdf(&point, light)).sum();
+        assert!((total - 1.0).abs() < 1e-12);
+        // The sun is never picked from the tree
+        assert_eq!(tree.pdf(&point, 8), 0.0);
+    }
+
+    #[test]
+    fn test_sampling_is_unbiased() {
+        seed_thread_rng(7);
+        let tree = LightTree::new(&lights());
+        let point = Point3::new(12.0, 0.0, 0.0);
+
+        // Weighting each pick by its inverse probability counts every light once
+        let samples = 20_000;
+        let mut estimates = [0.0; 8];
+        for _ in 0..samples {
+            let (light, pdf) = tree.sample(&point).unwrap();
+            estimates[light] += 1.0 / pdf / samples as f64;
+        }
+        for (light, estimate) in estimates.iter().enumerate() {
+            assert!(
+                (estimate - 1.0).abs() < 0.1,
+                "light {} estimated {}",
+                light,
+                estimate
+            );
+        }
+    }
+
+    #[test]
+    fn test_nearby_lights_are_preferred() {
+        seed_thread_rng(11);
+        let tree = LightTree::new(&lights());
+        let point = Point3::new(70.0, 0.0, 0.0);
+
+        let near = (0..1000)
+            .filter(|_| tree.sample(&point).unwrap().0 >= 6)
+            .count();
+        assert!(near > 800, "picked the two nearest lights {} times", near);
+    }
+}
@@ -0,0 +1,195 @@
+//! Level of detail: simpler stand-ins for objects far from the viewer.
+//!
+//! A [`LodGroup`] holds several versions of one object, from the most detailed to
+//! the simplest, each in a BVH of its own, and intersects only the one suited to
+//! how far away the ray's viewpoint is. The camera picks that once per sample,
+//! where the sample's ray starts, and every ray bouncing on along the path carries
+//! it, so distant objects cost a few primitives instead of their full detail while
+//! shadow and bounce rays see the same surface the camera did.
+
+use crate::aabb::Aabb;
+use crate::bvh::{Bvh, BvhError};
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::point3::Point3;
+use crate::ray::Ray;
+use std::sync::Arc;
+
+/// One version of the object, used from `distance` outwards.
+struct Level {
+    distance: f64,
+    /// The version's objects, or `None` if it shows nothing at all
+    objects: Option<Bvh>,
+}
+
+/// Versions of an object at decreasing detail, picked by distance from the ray's
+/// viewpoint.
+pub struct LodGroup {
+    levels: Vec<Level>,
+    /// Center of the bounds of every version, which distances are measured from
+    center: Point3,
+}
+
+impl LodGroup {
+    /// Creates a group whose most detailed version is made of `objects`.
+    ///
+    /// Fails if one of the objects has no bounding box.
+    pub fn new(objects: Vec<Box<dyn Hittable>>) -> Result<Self, BvhError> {
+        let mut group = Self {
+            levels: Vec::new(),
+            center: Point3::default(),
+        };
+        group.push(0.0, objects)?;
+        Ok(group)
+    }
+
+    /// Uses `objects` instead for rays whose viewpoint is at least `distance` away
+    /// from the center of the group's bounds.
+    ///
+    /// Fails if one of the objects has no bounding box.
+    pub fn level(
+        mut self,
+        distance: f64,
+        objects: Vec<Box<dyn Hittable>>,
+    ) -> Result<Self, BvhError> {
+        self.push(distance, objects)?;
+        Ok(self)
+    }
+
+    fn push(&mut self, distance: f64, objects: Vec<Box<dyn Hittable>>) -> Result<(), BvhError> {
+        let objects = if objects.is_empty() {
+            None
+        } else {
+            Some(Bvh::new(objects)?)
+        };
+        let index = self
+            .levels
+            .partition_point(|level| level.distance <= distance);
+        self.levels.insert(index, Level { distance, objects });
+        if let Some(bbox) = self.bounding_box(0.0, 1.0) {
+            self.center = bbox.center();
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the version seen from `viewpoint`, counting from the
+    /// most detailed.
+    pub fn select(&self, viewpoint: &Point3) -> usize {
+        let distance = (self.center - *viewpoint).length();
+        self.levels
+            .partition_point(|level| level.distance <= distance)
+            .saturating_sub(1)
+    }
+
+    fn versions(&self) -> impl Iterator<Item = &Bvh> {
+        self.levels
+            .iter()
+            .filter_map(|level| level.objects.as_ref())
+    }
+}
+
+impl Hittable for LodGroup {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        self.levels[self.select(r.viewpoint())]
+            .objects
+            .as_ref()?
+            .hit(r, ray_t)
+    }
+
+    /// Bounds every version, so the group's place in a BVH does not depend on
+    /// where the rays come from.
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.versions()
+            .filter_map(|version| version.bounding_box(time0, time1))
+            .reduce(|a, b| Aabb::surrounding(&a, &b))
+    }
+
+    fn visit_materials(&mut self, visit: &mut dyn FnMut(&mut Arc<Material>)) {
+        for level in &mut self.levels {
+            if let Some(objects) = &mut level.objects {
+                objects.visit_materials(visit);
+            }
+        }
+    }
+
+    fn degenerate_reason(&self) -> Option<&'static str> {
+        self.versions()
+            .find_map(|version| version.degenerate_reason())
+    }
+
+    fn degenerate_primitives(&self) -> usize {
+        self.versions()
+            .map(|version| version.degenerate_primitives())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::Instance;
+    use crate::material::{Scatter, TestMaterial};
+    use crate::sphere::{Sphere, SphereType};
+    use crate::vec3::Vec3;
+
+    fn sphere(x: f64, radius: f64) -> Box<dyn Hittable> {
+        Box::new(SphereType::Static(Sphere::new(
+            Point3::new(x, 0.0, 0.0),
+            radius,
+            TestMaterial::new(),
+        )))
+    }
+
+    #[test]
+    fn test_levels_are_picked_by_distance() {
+        // A detailed pair of spheres, a single large one further out, and nothing
+        // at all beyond that
+        let group = LodGroup::new(vec![sphere(-0.6, 0.4), sphere(0.6, 0.4)])
+            .and_then(|group| group.level(100.0, Vec::new()))
+            .and_then(|group| group.level(10.0, vec![sphere(0.0, 1.0)]))
+            .unwrap();
+        assert_eq!(group.select(&Point3::new(0.0, 0.0, 5.0)), 0);
+        assert_eq!(group.select(&Point3::new(0.0, 0.0, 50.0)), 1);
+        assert_eq!(group.select(&Point3::new(0.0, 0.0, 500.0)), 2);
+
+        let bbox = group.bounding_box(0.0, 1.0).unwrap();
+        assert_eq!(bbox.diagonal(), Vec3::new(2.0, 2.0, 2.0));
+
+        // Up close the gap between the detailed spheres shows
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        let near = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(group.hit(&near, ray_t).is_none());
+        let near = Ray::new(Point3::new(0.6, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let hit = group.hit(&near, ray_t).unwrap();
+        assert!((hit.t - 4.6).abs() < 1e-9);
+        assert_eq!(hit.material.map(Material::name), Some("test"));
+
+        let far = Ray::new(Point3::new(0.0, 0.0, 50.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!((group.hit(&far, ray_t).unwrap().t - 49.0).abs() < 1e-9);
+        let distant = Ray::new(Point3::new(0.0, 0.0, 500.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(group.hit(&distant, ray_t).is_none());
+    }
+
+    #[test]
+    fn test_levels_follow_the_viewpoint_not_the_origin() {
+        let group = LodGroup::new(vec![sphere(-0.6, 0.4), sphere(0.6, 0.4)])
+            .and_then(|group| group.level(10.0, vec![sphere(0.0, 1.0)]))
+            .unwrap();
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+
+        // A ray starting close by, bounced from a camera sample taken far away,
+        // sees the simple version the camera saw
+        let bounced = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(group.hit(&bounced, ray_t).is_none());
+        let bounced = bounced.with_viewpoint(Point3::new(0.0, 0.0, 50.0));
+        assert!((group.hit(&bounced, ray_t).unwrap().t - 4.0).abs() < 1e-9);
+
+        // Instances measure the viewpoint in the object's own space
+        let instance = Instance::new(Arc::new(group), Vec3::new(0.0, 0.0, -100.0));
+        let camera_ray = Ray::new(Point3::new(0.0, 0.0, -95.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(instance.hit(&camera_ray, ray_t).is_none());
+        let shadow_ray = camera_ray.with_viewpoint(Point3::new(0.0, 0.0, -50.0));
+        assert!((instance.hit(&shadow_ray, ray_t).unwrap().t - 4.0).abs() < 1e-9);
+    }
+}
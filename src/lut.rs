@@ -0,0 +1,319 @@
+//! Lookup tables in the `.cube` format, for film-stock and other looks.
+//!
+//! A LUT maps display values to display values, so it is applied as the image is
+//! written, after the display transform has tone mapped it. Both the 1D tables,
+//! which map each channel on its own, and the 3D tables, which map whole colors
+//! and can shift hues, of the Resolve `.cube` format are read.
+
+use crate::color::Color;
+use crate::error::Error;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Largest table size accepted, far above the 65 points of the finest common LUTs.
+const MAX_SIZE: usize = 256;
+
+/// Why a `.cube` file could not be read, and where.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CubeError {
+    /// Line number, counting from 1, or 0 for a problem with the file as a whole
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for CubeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.reason)
+        } else {
+            write!(f, "line {}: {}", self.line, self.reason)
+        }
+    }
+}
+
+impl error::Error for CubeError {}
+
+/// Whether a table maps channels separately or colors as a whole.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LutKind {
+    /// One curve per channel, `size` points long
+    OneD,
+    /// A `size`³ lattice of colors, interpolated trilinearly
+    ThreeD,
+}
+
+/// A lookup table read from a `.cube` file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut {
+    title: Option<String>,
+    kind: LutKind,
+    size: usize,
+    /// Input values mapped to the first and last entries of the table
+    domain_min: Color,
+    domain_max: Color,
+    /// Entries in file order: for 3D tables red changes fastest, then green, then blue
+    table: Vec<Color>,
+}
+
+impl Lut {
+    /// Reads the `.cube` file at `path`.
+    pub fn load_cube(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::parse_cube(BufReader::new(File::open(path)?))
+    }
+
+    /// Reads a `.cube` file's contents.
+    pub fn parse_cube(input: impl BufRead) -> Result<Self, Error> {
+        let mut title = None;
+        let mut kind = None;
+        let mut domain_min = Color::new(0.0, 0.0, 0.0);
+        let mut domain_max = Color::new(1.0, 1.0, 1.0);
+        let mut table = Vec::new();
+        for (number, line) in input.lines().enumerate() {
+            let line = line?;
+            let error = |reason: String| CubeError {
+                line: number + 1,
+                reason,
+            };
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some(keyword) = line.split_whitespace().next() else {
+                continue;
+            };
+            let rest = line[keyword.len()..].trim();
+            match keyword {
+                "TITLE" => title = Some(rest.trim_matches('"').to_string()),
+                "LUT_1D_SIZE" | "LUT_3D_SIZE" => {
+                    if kind.is_some() {
+                        return Err(error("the table size is given twice".to_string()).into());
+                    }
+                    let size = match rest.parse() {
+                        Ok(size) if (2..=MAX_SIZE).contains(&size) => size,
+                        _ => return Err(error(format!("invalid table size '{}'", rest)).into()),
+                    };
+                    let one_d = keyword == "LUT_1D_SIZE";
+                    kind = Some((
+                        if one_d {
+                            LutKind::OneD
+                        } else {
+                            LutKind::ThreeD
+                        },
+                        size,
+                    ));
+                }
+                "DOMAIN_MIN" => domain_min = color(rest).map_err(error)?,
+                "DOMAIN_MAX" => domain_max = color(rest).map_err(error)?,
+                // Adobe's form of the domain, the same for every channel
+                "LUT_1D_INPUT_RANGE" | "LUT_3D_INPUT_RANGE" => {
+                    let [min, max] = numbers(rest).map_err(error)?;
+                    domain_min = Color::new(min, min, min);
+                    domain_max = Color::new(max, max, max);
+                }
+                _ if keyword.parse::<f64>().is_ok() => table.push(color(line).map_err(error)?),
+                _ => return Err(error(format!("unknown keyword '{}'", keyword)).into()),
+            }
+        }
+
+        let whole_file = |reason: String| CubeError { line: 0, reason };
+        let (kind, size) =
+            kind.ok_or_else(|| whole_file("no LUT_1D_SIZE or LUT_3D_SIZE".to_string()))?;
+        let expected = match kind {
+            LutKind::OneD => size,
+            LutKind::ThreeD => size * size * size,
+        };
+        if table.len() != expected {
+            return Err(whole_file(format!(
+                "expected {} entries, found {}",
+                expected,
+                table.len()
+            ))
+            .into());
+        }
+        let (min, max) = (domain_min, domain_max);
+        if max.r() <= min.r() || max.g() <= min.g() || max.b() <= min.b() {
+            return Err(whole_file("the domain is empty".to_string()).into());
+        }
+        Ok(Self {
+            title,
+            kind,
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    /// The table's title, if the file gave one.
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    #[inline]
+    pub fn kind(&self) -> LutKind {
+        self.kind
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Looks up display values `color`, interpolating between the table's entries.
+    /// Values outside the domain are clamped to it.
+    pub fn apply(&self, color: Color) -> Color {
+        let last = (self.size - 1) as f64;
+        let position =
+            |value: f64, min: f64, max: f64| ((value - min) / (max - min)).clamp(0.0, 1.0) * last;
+        let (min, max) = (self.domain_min, self.domain_max);
+        let p = [
+            position(color.r(), min.r(), max.r()),
+            position(color.g(), min.g(), max.g()),
+            position(color.b(), min.b(), max.b()),
+        ];
+        // The entry below each coordinate, and how far past it the coordinate is
+        let split = |x: f64| {
+            let i = (x.floor() as usize).min(self.size - 2);
+            (i, x - i as f64)
+        };
+
+        match self.kind {
+            LutKind::OneD => {
+                let channel = |x: f64, pick: fn(&Color) -> f64| {
+                    let (i, t) = split(x);
+                    pick(&self.table[i]) * (1.0 - t) + pick(&self.table[i + 1]) * t
+                };
+                Color::new(
+                    channel(p[0], Color::r),
+                    channel(p[1], Color::g),
+                    channel(p[2], Color::b),
+                )
+            }
+            LutKind::ThreeD => {
+                let ((r, tr), (g, tg), (b, tb)) = (split(p[0]), split(p[1]), split(p[2]));
+                let entry = |dr: usize, dg: usize, db: usize| {
+                    self.table[(r + dr) + self.size * ((g + dg) + self.size * (b + db))]
+                };
+                let lerp = |a: Color, b: Color, t: f64| a * (1.0 - t) + b * t;
+                let along_r = |dg: usize, db: usize| lerp(entry(0, dg, db), entry(1, dg, db), tr);
+                let along_g = |db: usize| lerp(along_r(0, db), along_r(1, db), tg);
+                lerp(along_g(0), along_g(1), tb)
+            }
+        }
+    }
+}
+
+fn numbers<const N: usize>(text: &str) -> Result<[f64; N], String> {
+    let values: Vec<f64> = text
+        .split_whitespace()
+        .map(|field| {
+            field
+                .parse()
+                .map_err(|_| format!("invalid number '{}'", field))
+        })
+        .collect::<Result<_, _>>()?;
+    <[f64; N]>::try_from(values)
+        .map_err(|values| format!("expected {} numbers, found {}", N, values.len()))
+}
+
+fn color(text: &str) -> Result<Color, String> {
+    let [r, g, b] = numbers(text)?;
+    Ok(Color::new(r, g, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Lut, Error> {
+        Lut::parse_cube(text.as_bytes())
+    }
+
+    fn assert_near(a: Color, b: Color) {
+        assert!(
+            (a.r() - b.r()).abs() < 1e-9
+                && (a.g() - b.g()).abs() < 1e-9
+                && (a.b() - b.b()).abs() < 1e-9,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn test_identity_3d_lut_changes_nothing() {
+        let mut text = "TITLE \"Identity\"\n# a comment\nLUT_3D_SIZE 3\n\n".to_string();
+        for b in 0..3 {
+            for g in 0..3 {
+                for r in 0..3 {
+                    text += &format!("{} {} {}\n", r as f64 / 2.0, g as f64 / 2.0, b as f64 / 2.0);
+                }
+            }
+        }
+        let lut = parse(&text).unwrap();
+        assert_eq!(lut.title(), Some("Identity"));
+        assert_eq!((lut.kind(), lut.size()), (LutKind::ThreeD, 3));
+        for color in [
+            Color::new(0.1, 0.7, 0.3),
+            Color::new(1.0, 0.0, 0.5),
+            Color::new(0.25, 0.25, 0.9),
+        ] {
+            assert_near(lut.apply(color), color);
+        }
+        // Outside the domain clamps to its edge
+        assert_near(
+            lut.apply(Color::new(2.0, -1.0, 0.5)),
+            Color::new(1.0, 0.0, 0.5),
+        );
+    }
+
+    #[test]
+    fn test_1d_lut_maps_channels_separately() {
+        // Inverts red, squares green through a coarse curve, keeps blue
+        let lut = parse(
+            "LUT_1D_SIZE 3\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 1 1 1\n1 0 0\n0.5 0.25 0.5\n0 1 1\n",
+        )
+        .unwrap();
+        assert_eq!(lut.kind(), LutKind::OneD);
+        assert_near(
+            lut.apply(Color::new(0.25, 0.5, 0.75)),
+            Color::new(0.75, 0.25, 0.75),
+        );
+
+        // A wider domain stretches the table over it
+        let lut = parse("LUT_1D_INPUT_RANGE 0 2\nLUT_1D_SIZE 2\n0 0 0\n1 1 1\n").unwrap();
+        assert_near(
+            lut.apply(Color::new(1.0, 0.5, 2.0)),
+            Color::new(0.5, 0.25, 1.0),
+        );
+    }
+
+    #[test]
+    fn test_errors_name_the_line() {
+        let error = |text: &str| parse(text).unwrap_err().to_string();
+        assert_eq!(
+            error("LUT_3D_SIZE 2\n0 0 0\n0 0\n"),
+            "Failed to parse LUT: line 3: expected 3 numbers, found 2"
+        );
+        assert_eq!(
+            error("LUT_3D_SIZE 2\n0 0 0\n"),
+            "Failed to parse LUT: expected 8 entries, found 1"
+        );
+        assert_eq!(
+            error("LUT_3D_SIZE 1\n"),
+            "Failed to parse LUT: line 1: invalid table size '1'"
+        );
+        assert_eq!(
+            error("LUT_SIZE 2\n"),
+            "Failed to parse LUT: line 1: unknown keyword 'LUT_SIZE'"
+        );
+        assert_eq!(
+            error("0 0 0\n"),
+            "Failed to parse LUT: no LUT_1D_SIZE or LUT_3D_SIZE"
+        );
+        assert!(matches!(
+            Lut::load_cube("/nonexistent/look.cube"),
+            Err(Error::Io(_))
+        ));
+    }
+}
@@ -1,196 +1,57 @@
-use crate::bvh::Bvh;
-use crate::color::Color;
-use crate::hittable::Hittable;
-use crate::material::{Dielectric, Lambertian, Metal};
-use crate::point3::Point3;
-use crate::sphere::{SphereBuilder, SphereType};
-use crate::texture::{CheckerTexture, TextureEnum};
-use crate::utilities::random_double;
-use crate::vec3::Vec3;
+use crate::cli::Command;
+use std::fmt;
 
-mod aabb;
-mod bvh;
-mod camera;
-mod color;
-mod hittable;
-mod interval;
-mod material;
-mod point3;
-mod ray;
-mod sphere;
-mod texture;
-mod utilities;
-mod vec3;
+mod cli;
+mod commands;
+mod config;
+mod report;
 
-fn bouncing_spheres() {
-    // World
-    let mut objects: Vec<Box<dyn Hittable>> = Vec::new();
-
-    objects.push(Box::new(
-        SphereBuilder::new()
-            .center(Point3::new(0.0, -1000.0, 0.0))
-            .radius(1000.0)
-            .material(Lambertian::new(Box::new(TextureEnum::CheckerTexture(
-                CheckerTexture::new(
-                    3.0,
-                    Box::new(TextureEnum::SolidColor(Color::new(1.0, 1.0, 1.0).into())),
-                    Box::new(TextureEnum::SolidColor(Color::new(0.0, 0.0, 0.0).into())),
-                ),
-            ))))
-            .build()
-            .expect("Failed to build ground sphere"),
-    ));
-
-    for i in -8..8 {
-        for j in -8..8 {
-            let choose_mat = random_double();
-            let center = Point3::new(
-                i as f64 + 0.9 * random_double(),
-                0.2,
-                j as f64 + 0.9 * random_double(),
-            );
-            if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                if choose_mat < 0.8 {
-                    let center2 = center + Vec3::new(0.0, random_double() * 0.5, 0.0);
-                    if let Some(SphereType::Moving(moving_sphere)) = SphereBuilder::new()
-                        .center(center)
-                        .center_end(center2)
-                        .radius(0.2)
-                        .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
-                            Color::new(random_double(), random_double(), random_double()).into(),
-                        ))))
-                        .time_range(0.0, 1.0)
-                        .build()
-                    {
-                        objects.push(Box::new(moving_sphere));
-                    } else {
-                        panic!("Failed to build moving sphere");
-                    }
-                } else if choose_mat < 0.95 {
-                    objects.push(Box::new(
-                        SphereBuilder::new()
-                            .center(center)
-                            .radius(0.2)
-                            .material(Metal::new(
-                                Color::new(random_double(), random_double(), random_double()),
-                                0.5,
-                            ))
-                            .build()
-                            .expect("Failed to build metal sphere"),
-                    ));
-                } else {
-                    objects.push(Box::new(
-                        SphereBuilder::new()
-                            .center(center)
-                            .radius(0.2)
-                            .material(Dielectric::new(1.5))
-                            .build()
-                            .expect("Failed to build dielectric sphere"),
-                    ));
-                }
-            }
-        }
+fn main() {
+    let defaults = config::defaults().unwrap_or_else(|error| {
+        eprintln!("error: {}", error);
+        std::process::exit(2);
+    });
+    let command =
+        cli::parse_with_defaults(std::env::args().skip(1), &defaults).unwrap_or_else(|error| {
+            eprintln!("error: {}\n\n{}", error, cli::USAGE);
+            std::process::exit(2);
+        });
+    if let Some(threads) = command.render_options().and_then(|options| options.threads) {
+        #[cfg(feature = "parallel")]
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("Failed to start render threads");
+        #[cfg(not(feature = "parallel"))]
+        eprintln!(
+            "warning: built without the parallel feature, so --threads {} is ignored",
+            threads
+        );
     }
 
-    objects.push(Box::new(
-        SphereBuilder::new()
-            .center(Point3::new(0.0, 1.0, 0.0))
-            .radius(1.0)
-            .material(Dielectric::new(1.5))
-            .build()
-            .expect("Failed to build large dielectric sphere"),
-    ));
-
-    objects.push(Box::new(
-        SphereBuilder::new()
-            .center(Point3::new(-4.0, 1.0, 0.0))
-            .radius(1.0)
-            .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
-                Color::new(0.4, 0.2, 0.1).into(),
-            ))))
-            .build()
-            .expect("Failed to build brown lambertian sphere"),
-    ));
-
-    objects.push(Box::new(
-        SphereBuilder::new()
-            .center(Point3::new(4.0, 1.0, 0.0))
-            .radius(1.0)
-            .material(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0))
-            .build()
-            .expect("Failed to build metal sphere"),
-    ));
-
-    // Build BVH from objects
-    let world = Bvh::new(objects).expect("Failed to create BVH");
-
-    // Camera
-    let camera = camera::CameraBuilder::new()
-        .aspect_ratio(16.0 / 9.0)
-        .image_width(800)
-        .samples_per_pixel(100)
-        .max_depth(50)
-        .vertical_fov(20.0)
-        .look_from(Point3::new(13.0, 2.0, 3.0))
-        .look_at(Point3::new(0.0, 0.0, 0.0))
-        .vup(Vec3::new(0.0, 1.0, 0.0))
-        .defocus_angle(1.0)
-        .focus_dist(10.0)
-        .build();
-
-    camera.render(&world as &dyn Hittable);
-}
-
-fn checkered_spheres() {
-    let mut objects: Vec<Box<dyn Hittable>> = Vec::new();
-
-    let checker = CheckerTexture::new(
-        3.0,
-        Box::new(TextureEnum::SolidColor(Color::new(0.2, 0.3, 0.1).into())),
-        Box::new(TextureEnum::SolidColor(Color::new(0.9, 0.9, 0.9).into())),
-    );
-
-    objects.push(Box::new(
-        SphereBuilder::new()
-            .center(Point3::new(0.0, -10.0, 0.0))
-            .radius(10.0)
-            .material(Lambertian::new(Box::new(TextureEnum::CheckerTexture(
-                checker.clone(),
-            ))))
-            .build()
-            .expect("Failed to build ground sphere"),
-    ));
-
-    objects.push(Box::new(
-        SphereBuilder::new()
-            .center(Point3::new(0.0, 10.0, 0.0))
-            .radius(10.0)
-            .material(Lambertian::new(Box::new(TextureEnum::CheckerTexture(
-                checker.clone(),
-            ))))
-            .build()
-            .expect("Failed to build ground sphere"),
-    ));
-
-    let world = Bvh::new(objects).expect("Failed to create BVH");
-
-    let camera = camera::CameraBuilder::new()
-        .aspect_ratio(16.0 / 9.0)
-        .image_width(800)
-        .samples_per_pixel(100)
-        .max_depth(50)
-        .vertical_fov(20.0)
-        .look_from(Point3::new(13.0, 2.0, 3.0))
-        .look_at(Point3::new(0.0, 0.0, 0.0))
-        .vup(Vec3::new(0.0, 1.0, 0.0))
-        .defocus_angle(0.0)
-        .focus_dist(10.0)
-        .build();
-
-    camera.render(&world as &dyn Hittable);
+    match command {
+        Command::Render(options) => match (&options.watch, &options.views) {
+            (Some(path), _) => exit_on_error(commands::watch(&options, path)),
+            (None, Some(selection)) => exit_on_error(commands::render_views(&options, selection)),
+            (None, None) => exit_on_error(commands::render(&options)),
+        },
+        Command::Merge(inputs) => exit_on_error(commands::merge(&inputs)),
+        Command::Diff(a, b) => exit_on_error(commands::diff(&a, &b)),
+        Command::SceneDiff(a, b) => exit_on_error(commands::scene_diff(&a, &b)),
+        Command::Analyze(image) => exit_on_error(commands::analyze(&image)),
+        Command::List(listing) => commands::list(listing),
+        Command::Convergence(options) => exit_on_error(commands::convergence(&options)),
+        Command::Bake(options) => exit_on_error(commands::bake(&options)),
+        Command::Sweep(options) => exit_on_error(commands::sweep(&options)),
+        Command::Turntable(options) => exit_on_error(commands::turntable(&options)),
+        Command::Simulate(options) => exit_on_error(commands::simulate(&options)),
+        Command::TracePixel(options) => exit_on_error(commands::trace_pixel(&options)),
+    }
 }
 
-fn main() {
-    // bouncing_spheres();
-    checkered_spheres();
+fn exit_on_error(result: Result<(), impl fmt::Display>) {
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
 }
@@ -1,42 +1,255 @@
 use crate::color::Color;
 use crate::hittable::HitRecord;
-use crate::ray::Ray;
-use crate::texture::{Texture, TextureEnum};
-use crate::utilities::random_double;
+use crate::medium::Medium;
+use crate::microfacet;
+use crate::onb::Onb;
+use crate::pdf::{CosinePdf, ScatterPdf, SpherePdf};
+use crate::point3::Point3;
+use crate::ray::{Ray, RayDifferentials, RayKind};
+use crate::texture::{SolidColor, Texture, TextureEnum};
+use crate::utilities::{hash_f64, random_double};
 use crate::vec3::Vec3;
+use rand::Rng;
+use std::collections::hash_map::DefaultHasher;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::sync::Arc;
+
+/// Reflectance of the clay material, a neutral mid-grey.
+const CLAY_ALBEDO: Color = Color::new(0.5, 0.5, 0.5);
+/// Fuzz given to randomly generated metals.
+const RANDOM_METAL_FUZZ: f64 = 0.5;
+/// Facets a rough metal tries before giving up on finding a reflection that is not
+/// hidden by other facets.
+const MAX_FACET_SAMPLES: u32 = 64;
 
 /// Represents different types of materials that can be applied to surfaces.
 /// Each material type has its own scattering behavior and properties.
-#[derive(Clone, Debug, PartialEq)]
+///
+/// Equal materials hash equally, so identical materials can be found and shared.
+#[derive(Clone, Debug, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Material {
     /// A diffuse material that scatters light in all directions
     Lambertian(Lambertian),
-    /// A reflective material with optional fuzziness
+    /// A reflective material, mirror-like or rough
     Metal(Metal),
     /// A transparent material with refraction
     Dielectric(Dielectric),
+    /// A surface that glows and absorbs everything arriving at it
+    DiffuseLight(DiffuseLight),
+    /// The inside of a participating medium, scattering evenly in every direction
+    Isotropic(Isotropic),
+    /// A material defined outside this crate, which cannot be serialized
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(CustomMaterial),
     /// A simple material for testing purposes
+    #[cfg(test)]
+    #[cfg_attr(feature = "serde", serde(skip))]
     Test(TestMaterial),
 }
 
 impl Material {
-    /// Calculates how a ray is scattered when it hits a surface with this material.
-    /// Returns the attenuation color and the scattered ray.
+    /// Every kind of material by name, with a summary of its parameters.
+    pub const KINDS: [(&'static str, &'static str); 5] = [
+        (
+            "lambertian",
+            "Diffuse, scattering light in all directions. texture: its color",
+        ),
+        (
+            "metal",
+            "Reflective. albedo: its color or texture; roughness (or fuzz): 0 for a mirror up \
+             to 1 for brushed (--set materials.NAME.roughness); roughness_texture: a texture \
+             whose brightness scales the roughness; conductor: gold, copper, aluminum or silver, \
+             whose Fresnel reflectance shifts its color towards grazing angles",
+        ),
+        (
+            "dielectric",
+            "Transparent, reflecting and refracting like glass. refraction_index: 1.5 for \
+             glass, 1.33 for water (--set materials.NAME.refraction_index); tint: the color it filters \
+             light through, white for clear glass; roughness: 0 for clear up to 1 for frosted; \
+             thin: true for single sheets such as bubbles and panes; interior: the medium \
+             filling it, with absorption per channel, scattering and albedo",
+        ),
+        (
+            "diffuse_light",
+            "Emissive, lighting the scene by being hit rather than sampled. texture: its \
+             radiance, above 1 to light its surroundings",
+        ),
+        (
+            "isotropic",
+            "Scatters evenly in every direction, for fog and smoke inside a constant \
+             medium. texture: the color of the light it scatters",
+        ),
+    ];
+
+    /// A flat color that identifies this material, for checking material assignments.
+    ///
+    /// Equal materials get the same color; different ones get hues spread around
+    /// the color wheel by their structural hash.
+    pub fn id_color(&self) -> Color {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        // The top 53 bits of the hash give a uniform hue in [0, 6)
+        let hue = (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64 * 6.0;
+        let x = 1.0 - (hue % 2.0 - 1.0).abs();
+        let (r, g, b) = match hue as u32 {
+            0 => (1.0, x, 0.0),
+            1 => (x, 1.0, 0.0),
+            2 => (0.0, 1.0, x),
+            3 => (0.0, x, 1.0),
+            4 => (x, 0.0, 1.0),
+            _ => (1.0, 0.0, x),
+        };
+        // Keep the colors saturated but not blinding
+        Color::new(0.1 + 0.8 * r, 0.1 + 0.8 * g, 0.1 + 0.8 * b)
+    }
+
+    /// Creates a diffuse material with a random solid color.
+    ///
+    /// Pass a seeded generator to get the same material every time; the scene
+    /// generators use the thread's generator, which follows the scene seed.
+    pub fn random_lambertian<R: Rng + ?Sized>(rng: &mut R) -> Material {
+        Lambertian::new(Box::new(TextureEnum::SolidColor(random_color(rng).into())))
+    }
+
+    /// Creates a moderately fuzzy metal with a random color.
+    pub fn random_metal<R: Rng + ?Sized>(rng: &mut R) -> Material {
+        Metal::new(random_color(rng), RANDOM_METAL_FUZZ)
+    }
+
+    /// Approximate number of bytes this material occupies, including its textures.
+    pub fn memory_size(&self) -> usize {
+        mem::size_of::<Material>()
+            + match self {
+                Material::Lambertian(l) => l.texture.memory_size(),
+                Material::Metal(m) => {
+                    m.albedo.memory_size()
+                        + m.roughness_map.as_ref().map_or(0, |map| map.memory_size())
+                }
+                Material::DiffuseLight(d) => d.texture.memory_size(),
+                Material::Dielectric(d) => d.interior.as_ref().map_or(0, |m| m.memory_size()),
+                Material::Isotropic(i) => i.texture.memory_size(),
+                Material::Custom(c) => mem::size_of_val(&*c.0),
+                #[cfg(test)]
+                Material::Test(_) => 0,
+            }
+    }
+
+    /// Calls `visit` with every texture the material uses, nested ones included.
+    pub fn visit_textures(&self, visit: &mut dyn FnMut(&TextureEnum)) {
+        match self {
+            Material::Lambertian(l) => l.texture.visit(visit),
+            Material::Metal(m) => {
+                m.albedo.visit(visit);
+                if let Some(map) = &m.roughness_map {
+                    map.visit(visit);
+                }
+            }
+            Material::DiffuseLight(d) => d.texture.visit(visit),
+            Material::Isotropic(i) => i.texture.visit(visit),
+            Material::Dielectric(_) | Material::Custom(_) => {}
+            #[cfg(test)]
+            Material::Test(_) => {}
+        }
+    }
+
+    /// Returns the medium filling the solid this material bounds, if it declares one.
+    pub fn interior_medium(&self) -> Option<&Medium> {
+        match self {
+            Material::Dielectric(d) if !d.thin => d.interior.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// Built-in materials are dispatched by matching, so they are inlined and never
+/// pay for a virtual call; only custom materials go through the trait object.
+impl Scatter for Material {
+    fn name(&self) -> &'static str {
+        match self {
+            Material::Lambertian(_) => "lambertian",
+            Material::Metal(_) => "metal",
+            Material::Dielectric(_) => "dielectric",
+            Material::DiffuseLight(_) => "diffuse_light",
+            Material::Isotropic(_) => "isotropic",
+            Material::Custom(c) => c.0.name(),
+            #[cfg(test)]
+            Material::Test(_) => "test",
+        }
+    }
+
     #[inline]
-    pub fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> (Color, Ray) {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)> {
         match self {
             Material::Lambertian(l) => l.scatter(ray, hit_record),
             Material::Metal(m) => m.scatter(ray, hit_record),
             Material::Dielectric(d) => d.scatter(ray, hit_record),
-            Material::Test(t) => t.scatter(ray, hit_record),
+            Material::DiffuseLight(d) => d.scatter(ray, hit_record),
+            Material::Isotropic(i) => i.scatter(ray, hit_record),
+            Material::Custom(c) => c.0.scatter(ray, hit_record),
+            #[cfg(test)]
+            Material::Test(t) => Some(t.scatter(ray, hit_record)),
+        }
+    }
+
+    #[inline]
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        match self {
+            Material::DiffuseLight(d) => d.emitted(u, v, p),
+            Material::Custom(c) => c.0.emitted(u, v, p),
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    #[inline]
+    fn diffuse_reflectance(&self, hit_record: &HitRecord) -> Option<Color> {
+        match self {
+            Material::Lambertian(l) => Some(l.albedo(hit_record)),
+            Material::Custom(c) => c.0.diffuse_reflectance(hit_record),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn phase_albedo(&self, hit_record: &HitRecord) -> Option<Color> {
+        match self {
+            Material::Isotropic(i) => i.phase_albedo(hit_record),
+            Material::Custom(c) => c.0.phase_albedo(hit_record),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn scatter_pdf(&self, hit_record: &HitRecord) -> Option<ScatterPdf> {
+        match self {
+            Material::Lambertian(l) => l.scatter_pdf(hit_record),
+            Material::Isotropic(i) => i.scatter_pdf(hit_record),
+            Material::Custom(c) => c.0.scatter_pdf(hit_record),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn shadow_transmittance(&self, ray: &Ray, hit_record: &HitRecord) -> Option<Color> {
+        match self {
+            Material::Dielectric(d) => d.shadow_transmittance(ray, hit_record),
+            Material::Custom(c) => c.0.shadow_transmittance(ray, hit_record),
+            _ => None,
         }
     }
 }
 
+/// A color with each channel drawn uniformly from [0, 1).
+fn random_color<R: Rng + ?Sized>(rng: &mut R) -> Color {
+    Color::new(rng.random(), rng.random(), rng.random())
+}
+
 /// A diffuse material that scatters light in all directions.
 /// The color of the material is determined by its texture.
-#[derive(Clone)]
+#[derive(Clone, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Lambertian {
     texture: Box<TextureEnum>,
 }
@@ -47,125 +260,878 @@ impl fmt::Debug for Lambertian {
     }
 }
 
-impl PartialEq for Lambertian {
-    fn eq(&self, _other: &Self) -> bool {
-        // Since TextureEnum doesn't implement PartialEq, we can't compare textures
-        // We'll just return false to be safe
-        false
-    }
-}
-
 impl Lambertian {
     /// Creates a new Lambertian material with the given texture.
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(texture: Box<TextureEnum>) -> Material {
         Material::Lambertian(Lambertian { texture })
     }
 
+    /// Creates the neutral mid-grey "clay" used to judge lighting and shape
+    /// without material distractions.
+    pub fn clay() -> Material {
+        Self::new(Box::new(TextureEnum::SolidColor(SolidColor::new(
+            CLAY_ALBEDO,
+        ))))
+    }
+
+    /// Returns the texture color at the hit point.
+    #[inline]
+    fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.texture.filtered_value(
+            hit_record.texture_coords.0,
+            hit_record.texture_coords.1,
+            &hit_record.position,
+            hit_record.footprint(),
+        )
+    }
+}
+
+impl Scatter for Lambertian {
+    fn name(&self) -> &'static str {
+        "lambertian"
+    }
+
     /// Calculates how a ray is scattered when it hits a Lambertian surface.
     /// The scattered ray is randomly distributed in the hemisphere around the normal.
     #[inline]
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> (Color, Ray) {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)> {
         let mut scatter_direction = hit_record.normal + Vec3::random_unit();
         if scatter_direction.near_zero() {
             scatter_direction = hit_record.normal;
         }
+        // A shading normal tilted away from the geometric one can send the ray into
+        // the surface; mirror it back out instead of darkening the silhouette
+        if scatter_direction.dot(&hit_record.geometric_normal) <= 0.0 {
+            scatter_direction = scatter_direction.reflect(&hit_record.geometric_normal);
+        }
         let time = ray.time();
-        let scatter = Ray::new(hit_record.position, scatter_direction, time);
-        let attenuation = self.texture.value(
-            hit_record.texture_coords.0,
-            hit_record.texture_coords.1,
-            &hit_record.position,
-        );
-        (attenuation, scatter)
+        let scatter = hit_record.spawn_ray(scatter_direction, time);
+        Some((self.albedo(hit_record), scatter))
+    }
+
+    #[inline]
+    fn diffuse_reflectance(&self, hit_record: &HitRecord) -> Option<Color> {
+        Some(self.albedo(hit_record))
+    }
+
+    #[inline]
+    fn scatter_pdf(&self, hit_record: &HitRecord) -> Option<ScatterPdf> {
+        Some(ScatterPdf::Cosine(CosinePdf::new(&hit_record.normal)))
     }
 }
 
-/// A reflective material that can have a fuzzy reflection.
-/// The fuzz parameter controls how much the reflection is blurred.
-#[derive(Clone, Debug, PartialEq)]
+/// The complex refractive index of a conductor, per color channel.
+///
+/// Light entering a metal dies out within a fraction of a wavelength, at a rate set
+/// by the extinction coefficient `k`. Together with the real index `eta` this fixes
+/// how much is reflected at each angle, which is what colors gold and copper and
+/// whitens every metal towards grazing angles.
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ComplexIor {
+    pub eta: Color,
+    pub k: Color,
+}
+
+impl ComplexIor {
+    /// Measured indices at the red, green and blue wavelengths 650, 550 and 450 nm.
+    pub const GOLD: ComplexIor = ComplexIor {
+        eta: Color::new(0.143, 0.374, 1.442),
+        k: Color::new(3.983, 2.385, 1.603),
+    };
+    pub const COPPER: ComplexIor = ComplexIor {
+        eta: Color::new(0.200, 0.924, 1.102),
+        k: Color::new(3.912, 2.452, 2.142),
+    };
+    pub const ALUMINUM: ComplexIor = ComplexIor {
+        eta: Color::new(1.657, 0.880, 0.521),
+        k: Color::new(9.224, 6.270, 4.837),
+    };
+    pub const SILVER: ComplexIor = ComplexIor {
+        eta: Color::new(0.155, 0.117, 0.138),
+        k: Color::new(4.828, 3.122, 2.147),
+    };
+
+    /// The named metals, for scene files and listings.
+    pub const PRESETS: [(&'static str, ComplexIor); 4] = [
+        ("gold", Self::GOLD),
+        ("copper", Self::COPPER),
+        ("aluminum", Self::ALUMINUM),
+        ("silver", Self::SILVER),
+    ];
+
+    /// Returns the indices of the metal called `name`, if it is one of [`Self::PRESETS`].
+    pub fn named(name: &str) -> Option<ComplexIor> {
+        Self::PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == name)
+            .map(|(_, ior)| *ior)
+    }
+
+    /// Fraction of unpolarized light reflected by each channel, arriving at `cosine`
+    /// to the normal from air.
+    pub fn reflectance(&self, cosine: f64) -> Color {
+        let channel = |eta: f64, k: f64| conductor_reflectance(cosine, eta, k);
+        Color::new(
+            channel(self.eta.r(), self.k.r()),
+            channel(self.eta.g(), self.k.g()),
+            channel(self.eta.b(), self.k.b()),
+        )
+    }
+}
+
+/// The exact Fresnel reflectance of a conductor with index `eta + ik`, averaged
+/// over both polarizations.
+fn conductor_reflectance(cosine: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cosine.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cos2.sqrt() * a;
+    let perpendicular = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+    0.5 * (perpendicular + parallel)
+}
+
+/// A reflective material, from a perfect mirror to brushed metal.
+///
+/// Rough metals reflect off GGX microfacets. Reflections hidden by other facets
+/// are drawn again rather than lost, so a white metal reflects everything at any
+/// roughness. Metals given a [`ComplexIor`] also tint their reflections by its
+/// Fresnel reflectance at each facet.
+///
+/// The color can be any texture, and the roughness can vary across the surface
+/// with a second one, for checkered, brushed or worn metals.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Metal {
-    /// The base color of the metal
-    albedo: Color,
-    /// How fuzzy the reflection is (0.0 = perfect reflection, 1.0 = maximum fuzz)
-    fuzz: f64,
+    /// The base color of the metal, multiplying the conductor's reflectance if any
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "albedo_texture"))]
+    albedo: Box<TextureEnum>,
+    /// How blurred the reflection is (0.0 = perfect reflection, 1.0 = fully rough),
+    /// once called fuzz
+    #[cfg_attr(feature = "serde", serde(alias = "fuzz"))]
+    roughness: f64,
+    /// Texture whose brightness, clamped to [0, 1], scales the roughness at each point
+    #[cfg_attr(feature = "serde", serde(default))]
+    roughness_map: Option<Box<TextureEnum>>,
+    /// Refractive index whose Fresnel reflectance colors the metal, or `None` to
+    /// reflect the albedo at every angle
+    #[cfg_attr(feature = "serde", serde(default))]
+    conductor: Option<ComplexIor>,
+}
+
+impl fmt::Debug for Metal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metal")
+            .field("roughness", &self.roughness)
+            .field("roughness_map", &self.roughness_map.is_some())
+            .field("conductor", &self.conductor)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Hash for Metal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.albedo.hash(state);
+        hash_f64(self.roughness, state);
+        self.roughness_map.hash(state);
+        self.conductor.hash(state);
+    }
+}
+
+/// Reads a metal's albedo as a texture, or as the plain color metals once took.
+#[cfg(feature = "serde")]
+fn albedo_texture<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Box<TextureEnum>, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Albedo {
+        Color(Color),
+        Texture(TextureEnum),
+    }
+    let albedo = <Albedo as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(Box::new(match albedo {
+        Albedo::Color(color) => color.into(),
+        Albedo::Texture(texture) => texture,
+    }))
 }
 
 impl Metal {
-    /// Creates a new metal material with the given color and fuzziness.
-    /// The fuzz parameter is clamped between 0.0 and 1.0.
-    pub fn new(albedo: Color, fuzz: f64) -> Material {
-        let fuzz = fuzz.clamp(0.0, 1.0);
-        Material::Metal(Metal { albedo, fuzz })
+    /// Creates a new metal material with the given color or texture and roughness,
+    /// also known as fuzz. The roughness is clamped between 0.0 and 1.0.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(albedo: impl Into<TextureEnum>, roughness: f64) -> Material {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Material::Metal(Metal {
+            albedo: Box::new(albedo.into()),
+            roughness,
+            roughness_map: None,
+            conductor: None,
+        })
+    }
+
+    /// Creates a metal whose roughness is `roughness` scaled by the brightness of
+    /// `roughness_map` at each point, such as stripes for brushed steel or noise for
+    /// wear.
+    pub fn textured(
+        albedo: impl Into<TextureEnum>,
+        roughness: f64,
+        roughness_map: TextureEnum,
+    ) -> Material {
+        let mut material = Self::new(albedo, roughness);
+        if let Material::Metal(metal) = &mut material {
+            metal.set_roughness_map(Some(roughness_map));
+        }
+        material
+    }
+
+    /// Creates a metal colored by the Fresnel reflectance of `ior`, such as
+    /// [`ComplexIor::GOLD`], with the given roughness.
+    pub fn conductor(ior: ComplexIor, roughness: f64) -> Material {
+        Material::Metal(Metal {
+            albedo: Box::new(Color::new(1.0, 1.0, 1.0).into()),
+            roughness: roughness.clamp(0.0, 1.0),
+            roughness_map: None,
+            conductor: Some(ior),
+        })
+    }
+
+    /// Creates gold with the given roughness.
+    pub fn gold(roughness: f64) -> Material {
+        Self::conductor(ComplexIor::GOLD, roughness)
+    }
+
+    /// Creates copper with the given roughness.
+    pub fn copper(roughness: f64) -> Material {
+        Self::conductor(ComplexIor::COPPER, roughness)
+    }
+
+    /// Creates aluminum with the given roughness.
+    pub fn aluminum(roughness: f64) -> Material {
+        Self::conductor(ComplexIor::ALUMINUM, roughness)
+    }
+
+    /// Creates silver with the given roughness.
+    pub fn silver(roughness: f64) -> Material {
+        Self::conductor(ComplexIor::SILVER, roughness)
+    }
+
+    /// Color reflected off a facet seen at `cosine` to its normal, where the
+    /// surface's own color is `albedo`.
+    #[inline]
+    fn reflectance(&self, albedo: Color, cosine: f64) -> Color {
+        match &self.conductor {
+            Some(ior) => albedo * ior.reflectance(cosine),
+            None => albedo,
+        }
+    }
+
+    /// Returns the base color and roughness at the hit point.
+    #[inline]
+    fn surface(&self, hit_record: &HitRecord) -> (Color, f64) {
+        let (u, v) = hit_record.texture_coords;
+        let position = &hit_record.position;
+        let albedo = self
+            .albedo
+            .filtered_value(u, v, position, hit_record.footprint());
+        let roughness = match &self.roughness_map {
+            Some(map) => {
+                let scale = map.filtered_value(u, v, position, hit_record.footprint());
+                self.roughness * scale.luminance().clamp(0.0, 1.0)
+            }
+            None => self.roughness,
+        };
+        (albedo, roughness)
+    }
+
+    /// Changes the base color or texture, which tints a conductor's reflectance.
+    pub fn set_albedo(&mut self, albedo: impl Into<TextureEnum>) {
+        *self.albedo = albedo.into();
+    }
+
+    /// Varies the roughness across the surface by the brightness of `roughness_map`,
+    /// or stops varying it when `None`.
+    pub fn set_roughness_map(&mut self, roughness_map: Option<TextureEnum>) {
+        self.roughness_map = roughness_map.map(Box::new);
+    }
+
+    /// Changes how rough the surface is, clamped between 0.0 and 1.0.
+    pub fn set_roughness(&mut self, roughness: f64) {
+        self.roughness = roughness.clamp(0.0, 1.0);
+    }
+
+    /// Changes the roughness under its older name.
+    pub fn set_fuzz(&mut self, fuzz: f64) {
+        self.set_roughness(fuzz);
+    }
+}
+
+impl Scatter for Metal {
+    fn name(&self) -> &'static str {
+        "metal"
     }
 
     /// Calculates how a ray is scattered when it hits a metal surface.
-    /// The scattered ray is reflected with optional fuzziness.
+    /// Smooth metals reflect it as a mirror; rough ones off a facet of the surface
+    /// picked by the GGX distribution of normals visible to the ray.
     #[inline]
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> (Color, Ray) {
-        let mut reflected = ray.direction().reflect(&hit_record.normal);
-        reflected = reflected.unit() + (Vec3::random_unit() * self.fuzz);
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)> {
+        let mirror = ray.direction().reflect(&hit_record.normal).unit();
         let time = ray.time();
-        let scatter = Ray::new(hit_record.position, reflected, time);
-        (self.albedo, scatter)
+        let differentials = reflect_differentials(ray, hit_record, &mirror);
+        let (albedo, roughness) = self.surface(hit_record);
+        if roughness == 0.0 {
+            let scatter = hit_record
+                .spawn_ray(mirror, time)
+                .with_differentials(differentials);
+            let cosine = -ray.direction().unit().dot(&hit_record.normal);
+            return Some((self.reflectance(albedo, cosine), scatter));
+        }
+
+        let basis = Onb::new(&hit_record.normal);
+        let wo = basis.to_local(&-ray.direction().unit());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let alpha = microfacet::alpha(roughness);
+        // Keeping only the reflections no other facet hides, in proportion to how
+        // visible they are, spreads the light they would lose over the rest of the
+        // lobe instead of darkening the surface as it roughens
+        let (wi, facet) = (0..MAX_FACET_SAMPLES).find_map(|_| {
+            let u = (random_double(), random_double());
+            let facet = microfacet::sample_visible_normal(&wo, alpha, u);
+            let wi = (-wo).reflect(&facet);
+            (wi.z() > 0.0 && random_double() < microfacet::smith_g1(&wi, alpha))
+                .then_some((wi, facet))
+        })?;
+        let scatter = hit_record
+            .spawn_ray(basis.transform(&wi), time)
+            .with_differentials(differentials);
+        Some((self.reflectance(albedo, wo.dot(&facet)), scatter))
     }
 }
 
 /// A transparent material that can refract light.
 /// The refraction index determines how much the light is bent when passing through.
+///
+/// Rough dielectrics, such as frosted or sandblasted glass, reflect and refract
+/// off GGX microfacets as rough metals do, blurring what is seen through them.
+///
+/// Thin dielectrics model a single sheet with no inside, such as a soap bubble or
+/// a window pane built from one quad: light passing through leaves in the same
+/// direction it arrived instead of being bent into a volume that is not there.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Dielectric {
     /// The index of refraction of the material
     refraction_index: f64,
+    /// Color filtering light each time it passes through the surface
+    tint: Color,
+    /// How blurred reflections and refractions are (0.0 = clear, 1.0 = fully frosted)
+    #[cfg_attr(feature = "serde", serde(default))]
+    roughness: f64,
+    /// Whether each surface is a whole sheet of glass rather than the boundary of
+    /// a solid
+    #[cfg_attr(feature = "serde", serde(default))]
+    thin: bool,
+    /// The medium filling the solid, which paths refracted into it travel through
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    interior: Option<Box<Medium>>,
+}
+
+impl Hash for Dielectric {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_f64(self.refraction_index, state);
+        self.tint.hash(state);
+        hash_f64(self.roughness, state);
+        self.thin.hash(state);
+        self.interior.hash(state);
+    }
 }
 
 impl Dielectric {
     /// Creates a new dielectric material with the given refraction index.
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(refraction_index: f64) -> Material {
-        Material::Dielectric(Dielectric { refraction_index })
+        Self::tinted(refraction_index, Color::new(1.0, 1.0, 1.0))
     }
 
-    /// Calculates how a ray is scattered when it hits a dielectric surface.
-    /// The ray can either be reflected or refracted based on the material properties.
+    /// Creates colored glass, which filters light passing through each of its
+    /// surfaces by `tint`, as stained glass does.
+    pub fn tinted(refraction_index: f64, tint: Color) -> Material {
+        Material::Dielectric(Dielectric {
+            refraction_index,
+            tint,
+            roughness: 0.0,
+            thin: false,
+            interior: None,
+        })
+    }
+
+    /// Creates frosted glass, whose roughness is clamped between 0.0 and 1.0.
+    pub fn frosted(refraction_index: f64, roughness: f64) -> Material {
+        Material::Dielectric(Dielectric {
+            refraction_index,
+            tint: Color::new(1.0, 1.0, 1.0),
+            roughness: roughness.clamp(0.0, 1.0),
+            thin: false,
+            interior: None,
+        })
+    }
+
+    /// Creates a thin sheet of glass, such as a window pane or soap bubble modeled
+    /// as a single surface.
+    pub fn thin(refraction_index: f64) -> Material {
+        Material::Dielectric(Dielectric {
+            refraction_index,
+            tint: Color::new(1.0, 1.0, 1.0),
+            roughness: 0.0,
+            thin: true,
+            interior: None,
+        })
+    }
+
+    /// Changes the index of refraction.
+    pub fn set_refraction_index(&mut self, refraction_index: f64) {
+        self.refraction_index = refraction_index;
+    }
+
+    /// Changes the color filtering light passing through.
+    pub fn set_tint(&mut self, tint: Color) {
+        self.tint = tint;
+    }
+
+    /// Changes how rough the surface is, clamped between 0.0 and 1.0.
+    pub fn set_roughness(&mut self, roughness: f64) {
+        self.roughness = roughness.clamp(0.0, 1.0);
+    }
+
+    /// Changes whether each surface is a thin sheet rather than the boundary of a solid.
+    pub fn set_thin(&mut self, thin: bool) {
+        self.thin = thin;
+    }
+
+    /// Fills the solid with `medium`, or leaves it clear. Thin sheets have no
+    /// inside, so ignore it.
+    pub fn set_interior(&mut self, medium: Option<Medium>) {
+        self.interior = medium.map(Box::new);
+    }
+
+    /// Ratio of the refraction indices on either side of the surface, for light
+    /// crossing it from the side the ray arrived on.
     #[inline]
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> (Color, Ray) {
-        let attenuation = Color::new(1.0, 1.0, 1.0);
-        let ri = if hit_record.front_face {
+    fn relative_index(&self, hit_record: &HitRecord) -> f64 {
+        if hit_record.front_face {
             1.0 / self.refraction_index
         } else {
             self.refraction_index
+        }
+    }
+
+    /// Calculates the reflectance coefficient using Schlick's approximation.
+    #[inline]
+    fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
+        let mut r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
+        r0 = r0 * r0;
+        r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+    }
+
+    /// Fraction of the light arriving at `cosine` to the normal that is reflected,
+    /// with `ri` the ratio of indices across the surface.
+    ///
+    /// All of it is reflected beyond the critical angle of a solid. A thin sheet has
+    /// no critical angle, but reflects more, as light transmitted by its front
+    /// surface bounces back and forth between its two surfaces.
+    #[inline]
+    fn reflected_fraction(&self, cosine: f64, ri: f64) -> f64 {
+        let reflectance = Self::reflectance(cosine, ri);
+        if self.thin {
+            2.0 * reflectance / (1.0 + reflectance)
+        } else if ri * (1.0 - cosine * cosine).sqrt() > 1.0 {
+            1.0
+        } else {
+            reflectance
+        }
+    }
+
+    /// Reflects or refracts off a facet of a rough surface, which splits the light
+    /// by its own Fresnel reflectance.
+    fn scatter_rough(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)> {
+        let ri = self.relative_index(hit_record);
+        let basis = Onb::new(&hit_record.normal);
+        let wo = basis.to_local(&-ray.direction().unit());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let alpha = microfacet::alpha(self.roughness);
+        // As for rough metals, directions hidden by other facets or leaving on the
+        // wrong side of the surface are drawn again rather than lost
+        let (wi, refracted) = (0..MAX_FACET_SAMPLES).find_map(|_| {
+            let u = (random_double(), random_double());
+            let facet = microfacet::sample_visible_normal(&wo, alpha, u);
+            let cos_theta = wo.dot(&facet).min(1.0);
+            let refracted = self.reflected_fraction(cos_theta, ri) <= random_double();
+            let wi = match (refracted, self.thin) {
+                (false, _) => (-wo).reflect(&facet),
+                (true, false) => (-wo).refract(&facet, ri),
+                // Light through a rough sheet leaves as blurred as its reflection,
+                // mirrored through the sheet
+                (true, true) => {
+                    let reflected = (-wo).reflect(&facet);
+                    Vec3::new(reflected.x(), reflected.y(), -reflected.z())
+                }
+            };
+            let on_its_side = (wi.z() < 0.0) == refracted && wi.z() != 0.0;
+            (on_its_side && random_double() < microfacet::smith_g1(&wi, alpha))
+                .then_some((wi, refracted))
+        })?;
+
+        let direction = basis.transform(&wi);
+        let (attenuation, differentials) = if refracted {
+            let ri = if self.thin { 1.0 } else { ri };
+            (
+                self.tint,
+                refract_differentials(ray, hit_record, &direction, ri),
+            )
+        } else {
+            (
+                Color::new(1.0, 1.0, 1.0),
+                reflect_differentials(ray, hit_record, &direction),
+            )
         };
+        let scatter = hit_record
+            .spawn_ray(direction, ray.time())
+            .with_differentials(differentials);
+        Some((attenuation, scatter))
+    }
+}
+
+impl Scatter for Dielectric {
+    fn name(&self) -> &'static str {
+        "dielectric"
+    }
 
+    /// Calculates how a ray is scattered when it hits a dielectric surface.
+    /// The ray can either be reflected or refracted based on the material properties.
+    /// Rough surfaces do the same at a facet picked from the GGX distribution of
+    /// normals visible to the ray.
+    #[inline]
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)> {
+        if self.roughness > 0.0 {
+            return self.scatter_rough(ray, hit_record);
+        }
+        let ri = self.relative_index(hit_record);
         let unit_direction = ray.direction().unit();
         let cos_theta = (-unit_direction.dot(&hit_record.normal)).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
-        let cannot_refract = ri * sin_theta > 1.0;
-        let direction = if cannot_refract || Self::reflectance(cos_theta, ri) > random_double() {
-            unit_direction.reflect(&hit_record.normal)
-        } else {
-            unit_direction.refract(&hit_record.normal, ri)
-        };
+        let (attenuation, direction, differentials) =
+            if self.reflected_fraction(cos_theta, ri) > random_double() {
+                let direction = unit_direction.reflect(&hit_record.normal);
+                (
+                    Color::new(1.0, 1.0, 1.0),
+                    direction,
+                    reflect_differentials(ray, hit_record, &direction),
+                )
+            } else if self.thin {
+                // The sheet is too thin to offset the ray, so it passes straight on
+                (
+                    self.tint,
+                    unit_direction,
+                    refract_differentials(ray, hit_record, &unit_direction, 1.0),
+                )
+            } else {
+                let direction = unit_direction.refract(&hit_record.normal, ri);
+                (
+                    self.tint,
+                    direction,
+                    refract_differentials(ray, hit_record, &direction, ri),
+                )
+            };
 
         let time = ray.time();
-        (attenuation, Ray::new(hit_record.position, direction, time))
+        let scatter = hit_record
+            .spawn_ray(direction, time)
+            .with_differentials(differentials);
+        Some((attenuation, scatter))
     }
 
-    /// Calculates the reflectance coefficient using Schlick's approximation.
+    /// Lets shadow rays through, filtered by the tint and dimmed by the light the
+    /// surface reflects instead, ignoring how refraction bends or frosting blurs them.
     #[inline]
-    fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
-        let mut r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
-        r0 = r0 * r0;
-        r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+    fn shadow_transmittance(&self, ray: &Ray, hit_record: &HitRecord) -> Option<Color> {
+        let ri = self.relative_index(hit_record);
+        let cos_theta = (-ray.direction().unit().dot(&hit_record.normal)).min(1.0);
+        let reflected = self.reflected_fraction(cos_theta, ri);
+        (reflected < 1.0).then(|| self.tint * (1.0 - reflected))
+    }
+}
+
+/// An emissive material for area lights such as a Cornell box's ceiling lamp.
+///
+/// Unlike the analytic lights it is never sampled directly, so paths only find it
+/// by scattering into it; small bright emitters render noisily.
+#[derive(Clone, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiffuseLight {
+    texture: Box<TextureEnum>,
+}
+
+impl fmt::Debug for DiffuseLight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DiffuseLight {{ texture: Box<TextureEnum> }}")
+    }
+}
+
+impl DiffuseLight {
+    /// Creates a light emitting the radiance of the given texture.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(texture: Box<TextureEnum>) -> Material {
+        Material::DiffuseLight(DiffuseLight { texture })
+    }
+}
+
+impl Scatter for DiffuseLight {
+    fn name(&self) -> &'static str {
+        "diffuse_light"
+    }
+
+    /// Absorbs every ray, so the light shows only its own emission.
+    fn scatter(&self, _ray: &Ray, _hit_record: &HitRecord) -> Option<(Color, Ray)> {
+        None
+    }
+
+    /// Emits the texture's radiance from both sides of the surface.
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.texture.value(u, v, p)
+    }
+}
+
+/// The phase function of a fog or smoke, scattering light equally in every direction.
+///
+/// Meant for the inside of a [`ConstantMedium`](crate::medium::ConstantMedium),
+/// whose hits are points within its volume rather than on a surface.
+#[derive(Clone, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Isotropic {
+    texture: Box<TextureEnum>,
+}
+
+impl fmt::Debug for Isotropic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Isotropic {{ texture: Box<TextureEnum> }}")
+    }
+}
+
+impl Isotropic {
+    /// Creates a medium that scatters the color of the given texture.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(texture: Box<TextureEnum>) -> Material {
+        Material::Isotropic(Isotropic { texture })
+    }
+
+    #[inline]
+    fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.texture.value(
+            hit_record.texture_coords.0,
+            hit_record.texture_coords.1,
+            &hit_record.position,
+        )
+    }
+}
+
+impl Scatter for Isotropic {
+    fn name(&self) -> &'static str {
+        "isotropic"
+    }
+
+    /// Sends the ray on in a uniformly random direction from the point inside the
+    /// medium, which needs no offset as there is no surface to escape.
+    #[inline]
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)> {
+        let scatter = Ray::new(hit_record.position, Vec3::random_unit(), ray.time())
+            .with_kind(RayKind::Secondary);
+        Some((self.albedo(hit_record), scatter))
+    }
+
+    #[inline]
+    fn phase_albedo(&self, hit_record: &HitRecord) -> Option<Color> {
+        Some(self.albedo(hit_record))
+    }
+
+    #[inline]
+    fn scatter_pdf(&self, _hit_record: &HitRecord) -> Option<ScatterPdf> {
+        Some(ScatterPdf::Sphere(SpherePdf))
+    }
+}
+
+/// Propagates ray differentials through a perfect mirror reflection.
+///
+/// `reflected` is the mirror direction. Returns `None` if either the ray or the
+/// hit record lacks differentials.
+fn reflect_differentials(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    reflected: &Vec3,
+) -> Option<RayDifferentials> {
+    let rd = ray.differentials()?;
+    let sd = hit_record.differentials?;
+    let n = hit_record.normal;
+    let wo = -ray.direction().unit();
+    let wi = reflected.unit();
+
+    let dwodx = -rd.rx_direction.unit() - wo;
+    let dwody = -rd.ry_direction.unit() - wo;
+    let ddndx = dwodx.dot(&n) + wo.dot(&sd.dndx);
+    let ddndy = dwody.dot(&n) + wo.dot(&sd.dndy);
+    let wo_dot_n = wo.dot(&n);
+
+    Some(RayDifferentials {
+        rx_origin: hit_record.position + sd.dpdx,
+        rx_direction: wi - dwodx + 2.0 * (wo_dot_n * sd.dndx + ddndx * n),
+        ry_origin: hit_record.position + sd.dpdy,
+        ry_direction: wi - dwody + 2.0 * (wo_dot_n * sd.dndy + ddndy * n),
+    })
+}
+
+/// Propagates ray differentials through a refraction with the given ratio of
+/// refraction indices.
+///
+/// `refracted` is the refracted direction. Returns `None` if either the ray or the
+/// hit record lacks differentials.
+fn refract_differentials(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    refracted: &Vec3,
+    eta: f64,
+) -> Option<RayDifferentials> {
+    let rd = ray.differentials()?;
+    let sd = hit_record.differentials?;
+    let n = hit_record.normal;
+    let wo = -ray.direction().unit();
+    let wi = refracted.unit();
+
+    let dwodx = -rd.rx_direction.unit() - wo;
+    let dwody = -rd.ry_direction.unit() - wo;
+    let ddndx = dwodx.dot(&n) + wo.dot(&sd.dndx);
+    let ddndy = dwody.dot(&n) + wo.dot(&sd.dndy);
+
+    let wo_dot_n = wo.dot(&n);
+    let wi_dot_n = wi.dot(&n).abs();
+    let mu = eta * wo_dot_n - wi_dot_n;
+    let dmu = eta - (eta * eta * wo_dot_n) / wi_dot_n;
+
+    Some(RayDifferentials {
+        rx_origin: hit_record.position + sd.dpdx,
+        rx_direction: wi - eta * dwodx + (mu * sd.dndx + dmu * ddndx * n),
+        ry_origin: hit_record.position + sd.dpdy,
+        ry_direction: wi - eta * dwody + (mu * sd.dndy + dmu * ddndy * n),
+    })
+}
+
+/// How a material scatters light, implemented by every material.
+///
+/// The renderer only shades through this trait. Implement it for a material
+/// outside this crate and wrap it with [`CustomMaterial::new`] to use it wherever
+/// a built-in material can go.
+pub trait Scatter: Send + Sync {
+    /// A short lowercase name for the kind of material, for diagnostics.
+    fn name(&self) -> &'static str;
+
+    /// Calculates how a ray is scattered when it hits a surface with this material.
+    /// Returns the attenuation color and the scattered ray, or `None` if the
+    /// surface absorbs the ray.
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)>;
+
+    /// Returns the radiance the surface emits at texture coordinates `u`, `v` and
+    /// point `p`. Only lights emit; other materials keep the default of black.
+    fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// Returns the reflectance at the hit point if the surface is diffuse, so
+    /// lights are sampled directly from it. Specular materials keep the default.
+    fn diffuse_reflectance(&self, _hit_record: &HitRecord) -> Option<Color> {
+        None
+    }
+
+    /// Returns the albedo at the hit point if it lies inside a medium that scatters
+    /// evenly in every direction, so lights are sampled directly from it. Surfaces
+    /// keep the default.
+    fn phase_albedo(&self, _hit_record: &HitRecord) -> Option<Color> {
+        None
+    }
+
+    /// Returns the distribution of directions `scatter` draws from at the hit point,
+    /// if the material scatters diffusely or as a medium, so the camera can mix it
+    /// with aiming at light objects. Specular materials keep the default.
+    fn scatter_pdf(&self, _hit_record: &HitRecord) -> Option<ScatterPdf> {
+        None
+    }
+
+    /// Returns the fraction of light a shadow ray keeps passing straight through
+    /// the surface, if the renderer lets shadows through transparent surfaces.
+    /// Opaque materials keep the default, blocking the ray.
+    fn shadow_transmittance(&self, _ray: &Ray, _hit_record: &HitRecord) -> Option<Color> {
+        None
+    }
+}
+
+/// A shared [`Scatter`] implementation.
+///
+/// Custom materials are compared by identity: clones of one are equal and
+/// hash equally, but two separately created ones never are, even with the same
+/// parameters.
+#[derive(Clone)]
+pub struct CustomMaterial(Arc<dyn Scatter>);
+
+impl CustomMaterial {
+    /// Creates a material that scatters with `scatter`.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(scatter: impl Scatter + 'static) -> Material {
+        Material::Custom(CustomMaterial(Arc::new(scatter)))
+    }
+}
+
+impl fmt::Debug for CustomMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CustomMaterial {{ name: {:?} }}", self.0.name())
+    }
+}
+
+impl PartialEq for CustomMaterial {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Hash for CustomMaterial {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const () as usize).hash(state);
     }
 }
 
 /// A simple material for testing purposes.
 /// Always scatters rays in the normal direction with white color.
-#[derive(Clone, Debug, PartialEq)]
+#[cfg(test)]
+#[derive(Clone, Debug, Hash, PartialEq)]
 pub struct TestMaterial;
 
+#[cfg(test)]
 impl TestMaterial {
     /// Creates a new test material.
+    #[allow(clippy::new_ret_no_self)]
     pub fn new() -> Material {
         Material::Test(TestMaterial)
     }
@@ -175,7 +1141,7 @@ impl TestMaterial {
     fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> (Color, Ray) {
         let scatter_direction = hit_record.normal;
         let time = ray.time();
-        let scatter = Ray::new(hit_record.position, scatter_direction, time);
+        let scatter = hit_record.spawn_ray(scatter_direction, time);
         (Color::new(1.0, 1.0, 1.0), scatter)
     }
 }
@@ -185,18 +1151,358 @@ mod tests {
     use super::*;
     use crate::point3::Point3;
     use crate::texture::SolidColor;
+    use crate::utilities::seed_thread_rng;
 
     // Helper function to create a HitRecord for testing
-    fn create_hit_record(position: Point3, normal: Vec3, material: Option<&Material>) -> HitRecord {
-        let hit_record = HitRecord {
+    fn create_hit_record(
+        position: Point3,
+        normal: Vec3,
+        material: Option<&Material>,
+    ) -> HitRecord<'_> {
+        HitRecord {
             position,
             normal,
             t: 1.0,
             front_face: true,
             material,
+            geometric_normal: normal,
             ..Default::default()
+        }
+    }
+
+    /// Fractions of incident energy a material reflects and transmits, from
+    /// `samples` scatters of light arriving along `incoming` at a surface facing +z.
+    fn estimate_energy(
+        material: &Material,
+        incoming: Vec3,
+        front_face: bool,
+        samples: u32,
+    ) -> (f64, f64) {
+        let position = Point3::new(0.0, 0.0, 0.0);
+        let mut hit_record = create_hit_record(position, Vec3::new(0.0, 0.0, 1.0), Some(material));
+        hit_record.front_face = front_face;
+        let ray = Ray::new(Point3::from(-incoming), incoming, 0.0);
+
+        let (mut reflected, mut transmitted) = (0.0, 0.0);
+        for _ in 0..samples {
+            let Some((attenuation, scattered)) = material.scatter(&ray, &hit_record) else {
+                continue;
+            };
+            if scattered.direction().z() > 0.0 {
+                reflected += attenuation.luminance();
+            } else {
+                transmitted += attenuation.luminance();
+            }
+        }
+        (reflected / samples as f64, transmitted / samples as f64)
+    }
+
+    fn incoming_at(degrees: f64) -> Vec3 {
+        let theta = degrees.to_radians();
+        Vec3::new(theta.sin(), 0.0, -theta.cos())
+    }
+
+    fn white_lambertian() -> Material {
+        Lambertian::new(Box::new(TextureEnum::SolidColor(SolidColor::new(
+            Color::new(1.0, 1.0, 1.0),
+        ))))
+    }
+
+    #[test]
+    fn test_kinds_name_every_material() {
+        let names: Vec<&str> = Material::KINDS.iter().map(|(name, _)| *name).collect();
+        for material in [
+            Lambertian::clay(),
+            Metal::new(Color::new(0.8, 0.8, 0.8), 0.1),
+            Dielectric::new(1.5),
+            DiffuseLight::new(Box::new(TextureEnum::SolidColor(SolidColor::new(
+                Color::new(4.0, 4.0, 4.0),
+            )))),
+            Isotropic::new(Box::new(TextureEnum::SolidColor(SolidColor::new(
+                Color::new(0.8, 0.8, 0.8),
+            )))),
+        ] {
+            assert!(names.contains(&material.name()), "{}", material.name());
+        }
+        assert_eq!(names.len(), 5);
+    }
+
+    #[test]
+    fn test_isotropic() {
+        let albedo = Color::new(0.9, 0.8, 0.7);
+        let fog = Isotropic::new(Box::new(TextureEnum::SolidColor(albedo.into())));
+        let hit_record = create_hit_record(
+            Point3::new(1.0, 2.0, 3.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Some(&fog),
+        );
+        let ray = Ray::new(Point3::new(1.0, 2.0, 4.0), Vec3::new(0.0, 0.0, -1.0), 0.5);
+
+        // Scattered rays start exactly at the point and go every way, not just
+        // towards the normal's side
+        let mut backwards = 0;
+        for _ in 0..200 {
+            let (attenuation, scattered) = fog.scatter(&ray, &hit_record).unwrap();
+            assert_eq!(attenuation, albedo);
+            assert_eq!(*scattered.origin(), hit_record.position);
+            assert_eq!(scattered.time(), 0.5);
+            assert!((scattered.direction().length() - 1.0).abs() < 1e-9);
+            if scattered.direction().y() < 0.0 {
+                backwards += 1;
+            }
+        }
+        assert!((50..150).contains(&backwards), "{}", backwards);
+
+        assert_eq!(fog.phase_albedo(&hit_record), Some(albedo));
+        assert_eq!(fog.diffuse_reflectance(&hit_record), None);
+        assert_eq!(
+            fog.emitted(0.0, 0.0, &hit_record.position),
+            Color::new(0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_diffuse_light() {
+        let radiance = Color::new(4.0, 3.0, 2.0);
+        let light = DiffuseLight::new(Box::new(TextureEnum::SolidColor(radiance.into())));
+        let hit_record = create_hit_record(
+            Point3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Some(&light),
+        );
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+
+        assert_eq!(light.emitted(0.5, 0.5, &hit_record.position), radiance);
+        assert!(light.scatter(&ray, &hit_record).is_none());
+        assert_eq!(light.diffuse_reflectance(&hit_record), None);
+        // Other materials emit nothing
+        assert_eq!(
+            white_lambertian().emitted(0.5, 0.5, &hit_record.position),
+            Color::new(0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_materials_conserve_energy() {
+        seed_thread_rng(42);
+        let materials = [
+            white_lambertian(),
+            Lambertian::new(Box::new(TextureEnum::SolidColor(SolidColor::new(
+                Color::new(0.8, 0.3, 0.1),
+            )))),
+            Metal::new(Color::new(1.0, 1.0, 1.0), 0.0),
+            Metal::new(Color::new(1.0, 1.0, 1.0), 0.5),
+            Metal::new(Color::new(0.9, 0.6, 0.2), 1.0),
+            Dielectric::new(1.33),
+            Dielectric::new(1.5),
+            Dielectric::new(2.4),
+        ];
+
+        for material in &materials {
+            for degrees in [0.0, 30.0, 60.0, 85.0] {
+                for front_face in [true, false] {
+                    let (reflected, transmitted) =
+                        estimate_energy(material, incoming_at(degrees), front_face, 2000);
+                    assert!(
+                        reflected + transmitted <= 1.0 + 1e-9,
+                        "{:?} at {} degrees created energy: {} + {}",
+                        material,
+                        degrees,
+                        reflected,
+                        transmitted
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_lossless_materials_keep_all_energy() {
+        seed_thread_rng(7);
+        for degrees in [0.0, 45.0, 80.0] {
+            let incoming = incoming_at(degrees);
+
+            // A white diffuse surface reflects everything and transmits nothing
+            let (reflected, transmitted) =
+                estimate_energy(&white_lambertian(), incoming, true, 2000);
+            assert!((reflected - 1.0).abs() < 1e-9);
+            assert_eq!(transmitted, 0.0);
+
+            // So does a white perfect mirror
+            let mirror = Metal::new(Color::new(1.0, 1.0, 1.0), 0.0);
+            let (reflected, _) = estimate_energy(&mirror, incoming, true, 100);
+            assert!((reflected - 1.0).abs() < 1e-9);
+
+            // as does frosted glass between what it reflects and transmits
+            for roughness in [0.3, 1.0] {
+                let frosted = Dielectric::frosted(1.5, roughness);
+                for front_face in [true, false] {
+                    let (reflected, transmitted) =
+                        estimate_energy(&frosted, incoming, front_face, 2000);
+                    assert!(
+                        (reflected + transmitted - 1.0).abs() < 1e-3,
+                        "{} {} {}",
+                        roughness,
+                        reflected,
+                        transmitted
+                    );
+                }
+            }
+
+            // and a white rough metal, however rough
+            for roughness in [0.3, 1.0] {
+                let metal = Metal::new(Color::new(1.0, 1.0, 1.0), roughness);
+                let (reflected, transmitted) = estimate_energy(&metal, incoming, true, 2000);
+                assert!(
+                    (reflected - 1.0).abs() < 1e-3,
+                    "{} {}",
+                    roughness,
+                    reflected
+                );
+                assert_eq!(transmitted, 0.0);
+            }
+
+            // Glass splits energy between reflection and refraction without loss
+            let (reflected, transmitted) =
+                estimate_energy(&Dielectric::new(1.5), incoming, true, 2000);
+            assert!((reflected + transmitted - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_dielectric_fresnel_split() {
+        seed_thread_rng(1234);
+        let glass = Dielectric::new(1.5);
+
+        // About 4% of light is reflected at normal incidence on glass
+        let (reflected, transmitted) = estimate_energy(&glass, incoming_at(0.0), true, 20000);
+        assert!((reflected - 0.04).abs() < 0.01, "reflected {}", reflected);
+        assert!(
+            (transmitted - 0.96).abs() < 0.01,
+            "transmitted {}",
+            transmitted
+        );
+
+        // Beyond the critical angle inside the glass everything is reflected
+        let (reflected, transmitted) = estimate_energy(&glass, incoming_at(60.0), false, 1000);
+        assert_eq!(reflected, 1.0);
+        assert_eq!(transmitted, 0.0);
+    }
+
+    #[test]
+    fn test_frosted_glass_blurs_what_it_transmits() {
+        seed_thread_rng(47);
+        let incoming = incoming_at(30.0);
+        let spread = |roughness: f64| {
+            let glass = Dielectric::frosted(1.5, roughness);
+            let hit_record = create_hit_record(
+                Point3::new(0.0, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+                Some(&glass),
+            );
+            let ray = Ray::new(Point3::from(-incoming), incoming, 0.0);
+            let straight = incoming.refract(&Vec3::new(0.0, 0.0, 1.0), 1.0 / 1.5);
+            let cosines: Vec<f64> = (0..2000)
+                .filter_map(|_| glass.scatter(&ray, &hit_record))
+                .map(|(_, scattered)| scattered.direction().unit())
+                .filter(|direction| direction.z() < 0.0)
+                .map(|direction| direction.dot(&straight))
+                .collect();
+            cosines.iter().sum::<f64>() / cosines.len() as f64
         };
-        hit_record
+        // Light through the glass strays further from the clear refraction as it roughens
+        let (clear, light, heavy) = (spread(0.0), spread(0.2), spread(0.8));
+        assert!((clear - 1.0).abs() < 1e-9, "{}", clear);
+        assert!(light > heavy && light < clear, "{} {}", light, heavy);
+
+        let mut glass = Dielectric::new(1.5);
+        if let Material::Dielectric(dielectric) = &mut glass {
+            dielectric.set_roughness(2.0);
+        }
+        assert_eq!(glass, Dielectric::frosted(1.5, 1.0));
+    }
+
+    #[test]
+    fn test_thin_glass_passes_light_straight_through() {
+        seed_thread_rng(53);
+        let sheet = Dielectric::thin(1.5);
+        for front_face in [true, false] {
+            // Light leaves a sheet in the direction it arrived, even at angles a
+            // solid would reflect it all from the inside
+            let incoming = incoming_at(60.0);
+            let mut hit_record =
+                create_hit_record(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), None);
+            hit_record.front_face = front_face;
+            let ray = Ray::new(Point3::from(-incoming), incoming, 0.0);
+            let through = (0..200)
+                .filter_map(|_| sheet.scatter(&ray, &hit_record))
+                .map(|(_, scattered)| scattered.direction().unit())
+                .filter(|direction| direction.z() < 0.0)
+                .inspect(|direction| assert!((*direction - incoming).length() < 1e-12))
+                .count();
+            assert!(through > 100, "{}", through);
+
+            let (reflected, transmitted) = estimate_energy(&sheet, incoming, front_face, 2000);
+            assert!((reflected + transmitted - 1.0).abs() < 1e-9);
+        }
+
+        // Both of its surfaces reflect, so a sheet reflects more than a solid's surface
+        let (sheet_reflected, _) = estimate_energy(&sheet, incoming_at(0.0), true, 20000);
+        assert!(
+            (sheet_reflected - 0.077).abs() < 0.01,
+            "{}",
+            sheet_reflected
+        );
+        let hit_record =
+            create_hit_record(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), None);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let kept = sheet.shadow_transmittance(&ray, &hit_record).unwrap();
+        assert!((kept.g() - (1.0 - 0.08 / 1.04)).abs() < 1e-9, "{:?}", kept);
+
+        // A rough sheet blurs what it transmits but keeps it on the far side
+        let frosted = Dielectric::frosted(1.5, 0.5);
+        let mut rough_sheet = frosted.clone();
+        if let Material::Dielectric(dielectric) = &mut rough_sheet {
+            dielectric.set_thin(true);
+        }
+        assert_ne!(rough_sheet, frosted);
+        let (reflected, transmitted) = estimate_energy(&rough_sheet, incoming_at(30.0), true, 2000);
+        assert!((reflected + transmitted - 1.0).abs() < 1e-3);
+        assert!(transmitted > 0.8, "{}", transmitted);
+    }
+
+    #[test]
+    fn test_tinted_glass_filters_light() {
+        seed_thread_rng(99);
+        let red = Color::new(0.9, 0.2, 0.1);
+        let glass = Dielectric::tinted(1.5, red);
+        let position = Point3::new(0.0, 0.0, 0.0);
+        let mut hit_record = create_hit_record(position, Vec3::new(0.0, 0.0, 1.0), Some(&glass));
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+
+        // Refracted light takes the tint, reflected light stays white
+        for _ in 0..100 {
+            let (attenuation, scattered) = glass.scatter(&ray, &hit_record).unwrap();
+            if scattered.direction().z() < 0.0 {
+                assert_eq!(attenuation, red);
+            } else {
+                assert_eq!(attenuation, Color::new(1.0, 1.0, 1.0));
+            }
+        }
+
+        // Shadow rays keep what is not reflected, tinted
+        let kept = glass.shadow_transmittance(&ray, &hit_record).unwrap();
+        let expected = red * 0.96;
+        assert!((kept.r() - expected.r()).abs() < 1e-9, "{:?}", kept);
+        assert!((kept.b() - expected.b()).abs() < 1e-9, "{:?}", kept);
+        // but none beyond the critical angle inside the glass
+        hit_record.front_face = false;
+        let grazing = Ray::new(Point3::from(-incoming_at(60.0)), incoming_at(60.0), 0.0);
+        assert_eq!(glass.shadow_transmittance(&grazing, &hit_record), None);
+        assert_eq!(
+            white_lambertian().shadow_transmittance(&ray, &hit_record),
+            None
+        );
     }
 
     #[test]
@@ -216,6 +1522,65 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_lambertian_equality_compares_textures() {
+        let gray = || TextureEnum::SolidColor(SolidColor::new(Color::new(0.5, 0.5, 0.5)));
+        let red = TextureEnum::SolidColor(SolidColor::new(Color::new(0.9, 0.1, 0.1)));
+
+        assert_eq!(
+            Lambertian::new(Box::new(gray())),
+            Lambertian::new(Box::new(gray()))
+        );
+        assert_ne!(
+            Lambertian::new(Box::new(gray())),
+            Lambertian::new(Box::new(red))
+        );
+    }
+
+    #[test]
+    fn test_random_materials_follow_the_seed() {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+
+        let generate = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (
+                Material::random_lambertian(&mut rng),
+                Material::random_metal(&mut rng),
+            )
+        };
+        assert_eq!(generate(7), generate(7));
+        assert_ne!(generate(7), generate(8));
+
+        let (diffuse, metal) = generate(7);
+        assert_eq!(diffuse.name(), "lambertian");
+        let Material::Metal(metal) = metal else {
+            panic!("expected a metal");
+        };
+        assert_eq!(metal.roughness, RANDOM_METAL_FUZZ);
+        let albedo = metal.albedo.value(0.0, 0.0, &Point3::default());
+        for c in [albedo.r(), albedo.g(), albedo.b()] {
+            assert!((0.0..1.0).contains(&c));
+        }
+    }
+
+    #[test]
+    fn test_id_color() {
+        let glass = Dielectric::new(1.5);
+        let water = Dielectric::new(1.33);
+        let mirror = Metal::new(Color::new(0.8, 0.8, 0.8), 0.0);
+
+        assert_eq!(glass.id_color(), Dielectric::new(1.5).id_color());
+        assert_ne!(glass.id_color(), water.id_color());
+        assert_ne!(glass.id_color(), mirror.id_color());
+        for material in [glass, water, mirror] {
+            let color = material.id_color();
+            assert!(color.r() > 0.0 && color.r() < 1.0);
+            assert!(color.g() > 0.0 && color.g() < 1.0);
+            assert!(color.b() > 0.0 && color.b() < 1.0);
+        }
+    }
+
     #[test]
     fn test_lambertian_scatter() {
         let texture = TextureEnum::SolidColor(SolidColor::new(Color::new(0.5, 0.5, 0.5)));
@@ -229,7 +1594,7 @@ mod tests {
         let hit_record = create_hit_record(hit_point, normal, Some(&binding));
 
         let (scattered_color, scattered_ray) = match material {
-            Material::Lambertian(l) => l.scatter(&ray, &hit_record),
+            Material::Lambertian(l) => l.scatter(&ray, &hit_record).unwrap(),
             _ => panic!("Expected Lambertian material"),
         };
 
@@ -240,7 +1605,7 @@ mod tests {
         );
 
         // Check that the scattered ray originates from the hit point
-        assert_eq!(*scattered_ray.origin(), hit_point);
+        assert!((*scattered_ray.origin() - hit_point).near_zero());
 
         // In the Lambertian scatter implementation, the scatter direction is:
         // hit_record.normal + Vec3::random_unit()
@@ -257,6 +1622,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_lambertian_scatter_stays_above_geometric_surface() {
+        let texture = TextureEnum::SolidColor(SolidColor::new(Color::new(0.5, 0.5, 0.5)));
+        let material = Lambertian::new(Box::new(texture));
+
+        let ray = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let binding = material.clone();
+        let hit_record = HitRecord {
+            position: Point3::new(0.0, 0.0, 0.0),
+            // Shading normal nearly tangent to the surface, as at a smooth silhouette
+            normal: Vec3::new(0.995, 0.0998, 0.0).unit(),
+            geometric_normal: Vec3::new(0.0, 1.0, 0.0),
+            front_face: true,
+            material: Some(&binding),
+            ..Default::default()
+        };
+
+        for _ in 0..100 {
+            let (_, scattered_ray) = material.scatter(&ray, &hit_record).unwrap();
+            assert!(scattered_ray.direction().dot(&hit_record.geometric_normal) >= 0.0);
+            assert!(scattered_ray.origin().y() > 0.0);
+        }
+    }
+
     #[test]
     fn test_metal_creation() {
         let albedo = Color::new(0.8, 0.8, 0.8);
@@ -265,8 +1654,8 @@ mod tests {
         let material1 = Metal::new(albedo, 0.5);
         match material1 {
             Material::Metal(m) => {
-                assert_eq!(m.albedo, albedo);
-                assert_eq!(m.fuzz, 0.5);
+                assert!(*m.albedo == albedo.into());
+                assert_eq!(m.roughness, 0.5);
             }
             _ => panic!("Expected Metal material"),
         }
@@ -275,8 +1664,8 @@ mod tests {
         let material2 = Metal::new(albedo, 1.5);
         match material2 {
             Material::Metal(m) => {
-                assert_eq!(m.albedo, albedo);
-                assert_eq!(m.fuzz, 1.0); // Should be clamped to 1.0
+                assert!(*m.albedo == albedo.into());
+                assert_eq!(m.roughness, 1.0); // Should be clamped to 1.0
             }
             _ => panic!("Expected Metal material"),
         }
@@ -285,8 +1674,8 @@ mod tests {
         let material3 = Metal::new(albedo, -0.5);
         match material3 {
             Material::Metal(m) => {
-                assert_eq!(m.albedo, albedo);
-                assert_eq!(m.fuzz, 0.0); // Should be clamped to 0.0
+                assert!(*m.albedo == albedo.into());
+                assert_eq!(m.roughness, 0.0); // Should be clamped to 0.0
             }
             _ => panic!("Expected Metal material"),
         }
@@ -309,7 +1698,7 @@ mod tests {
         let hit_record = create_hit_record(hit_point, normal, Some(&binding));
 
         let (scattered_color, scattered_ray) = match material {
-            Material::Metal(m) => m.scatter(&ray, &hit_record),
+            Material::Metal(m) => m.scatter(&ray, &hit_record).unwrap(),
             _ => panic!("Expected Metal material"),
         };
 
@@ -317,7 +1706,7 @@ mod tests {
         assert_eq!(scattered_color, albedo);
 
         // Check that the scattered ray originates from the hit point
-        assert_eq!(*scattered_ray.origin(), hit_point);
+        assert!((*scattered_ray.origin() - hit_point).near_zero());
 
         // In the Metal implementation, reflection is calculated using ray.direction().reflect(&hit_record.normal)
         // and then normalized before adding fuzz
@@ -333,6 +1722,53 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_textured_metal() {
+        use crate::texture::CheckerTexture;
+
+        let checker = |odd: Color, even: Color| {
+            TextureEnum::CheckerTexture(CheckerTexture::new(
+                std::f64::consts::PI,
+                Box::new(odd.into()),
+                Box::new(even.into()),
+            ))
+        };
+        let (red, blue) = (Color::new(0.9, 0.1, 0.1), Color::new(0.1, 0.1, 0.9));
+        let (black, white) = (Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0));
+        // Smooth where the metal is red, fully rough where it is blue
+        let material = Metal::textured(checker(red, blue), 1.0, checker(black, white));
+        let Material::Metal(metal) = &material else {
+            panic!("Expected Metal material");
+        };
+
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        // Whether each of a few scatters off the cell around `x` is a mirror
+        // reflection, and its color
+        let scatters_at = |x: f64| -> Vec<(bool, Color)> {
+            let hit_point = Point3::new(x, 0.5, 0.5);
+            let ray = Ray::new(
+                hit_point + Vec3::new(-1.0, 1.0, 0.0),
+                Vec3::new(1.0, -1.0, 0.0),
+                0.0,
+            );
+            let mirror = ray.direction().reflect(&normal).unit();
+            let hit_record = create_hit_record(hit_point, normal, Some(&material));
+            (0..32)
+                .filter_map(|_| metal.scatter(&ray, &hit_record))
+                .map(|(color, ray)| ((*ray.direction() - mirror).length() < 1e-10, color))
+                .collect()
+        };
+
+        // The checkers flip between x = 0.5 and x = 1.5
+        let (first, second) = (scatters_at(0.5), scatters_at(1.5));
+        let smooth = |scatters: &[(bool, Color)]| scatters.iter().all(|&(mirror, _)| mirror);
+        assert_ne!(smooth(&first), smooth(&second));
+        assert!(first.iter().all(|&(_, color)| color == first[0].1));
+        assert!(second.iter().all(|&(_, color)| color == second[0].1));
+        let colors = [first[0].1, second[0].1];
+        assert!(colors == [red, blue] || colors == [blue, red]);
+    }
+
     #[test]
     fn test_metal_scatter_with_fuzz() {
         let albedo = Color::new(0.8, 0.8, 0.8);
@@ -349,34 +1785,182 @@ mod tests {
         let binding = material.clone();
         let hit_record = create_hit_record(hit_point, normal, Some(&binding));
 
-        let (scattered_color, scattered_ray) = match material {
-            Material::Metal(m) => m.scatter(&ray, &hit_record),
-            _ => panic!("Expected Metal material"),
+        let Material::Metal(metal) = material else {
+            panic!("Expected Metal material");
         };
+        for _ in 0..100 {
+            // A rough surface reflects the ray above itself, keeping the albedo
+            let Some((scattered_color, scattered_ray)) = metal.scatter(&ray, &hit_record) else {
+                continue;
+            };
+            assert!((*scattered_ray.origin() - hit_point).near_zero());
+            assert!(scattered_ray.direction().dot(&normal) > 0.0);
+            assert_eq!(scattered_color, albedo);
+        }
+    }
 
-        // Check that the scattered color is the albedo
-        assert_eq!(scattered_color, albedo);
-
-        // Check that the scattered ray originates from the hit point
-        assert_eq!(*scattered_ray.origin(), hit_point);
-
-        // With maximum fuzz (1.0), the implementation does:
-        // reflected = ray.direction().reflect(&hit_record.normal).unit() + (Vec3::random_unit() * 1.0)
-        // This means the direction will be the normalized reflection plus a random unit vector
-        // Since there's randomness involved, we can't predict the exact direction
-        // Instead, we'll just verify that the direction is not zero and has a reasonable length
-        let direction_length = scattered_ray.direction().length();
+    #[test]
+    fn test_conductor_fresnel() {
+        // Gold reflects red more than blue head on, and everything at grazing angles
+        let gold = ComplexIor::GOLD.reflectance(1.0);
         assert!(
-            direction_length > 0.0 && direction_length < 3.0,
-            "Expected direction length between 0.0 and 3.0, got: {}",
-            direction_length
+            gold.r() > 0.9 && gold.g() > 0.6 && gold.b() < 0.4,
+            "{:?}",
+            gold
+        );
+        let grazing = ComplexIor::GOLD.reflectance(0.0);
+        for c in [grazing.r(), grazing.g(), grazing.b()] {
+            assert!((c - 1.0).abs() < 1e-9);
+        }
+        let oblique = ComplexIor::GOLD.reflectance(0.1);
+        assert!(oblique.b() > gold.b());
+
+        // A perfect conductor reflects everything, and an index of 1 nothing head on
+        assert!((conductor_reflectance(0.5, 0.0, 1e6) - 1.0).abs() < 1e-6);
+        assert!(conductor_reflectance(1.0, 1.0, 0.0).abs() < 1e-12);
+        for (_, ior) in ComplexIor::PRESETS {
+            for cosine in [0.0, 0.3, 0.7, 1.0] {
+                let r = ior.reflectance(cosine);
+                for c in [r.r(), r.g(), r.b()] {
+                    assert!((0.0..=1.0).contains(&c));
+                }
+            }
+        }
+        assert_eq!(ComplexIor::named("copper"), Some(ComplexIor::COPPER));
+        assert_eq!(ComplexIor::named("brass"), None);
+
+        // Mirrors of a conductor reflect its Fresnel color
+        let mirror = Metal::gold(0.0);
+        let hit_record = create_hit_record(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Some(&mirror),
         );
+        let incoming = incoming_at(60.0);
+        let ray = Ray::new(Point3::from(-incoming), incoming, 0.0);
+        let (attenuation, _) = mirror.scatter(&ray, &hit_record).unwrap();
+        let expected = ComplexIor::GOLD.reflectance(0.5);
+        assert!((attenuation.g() - expected.g()).abs() < 1e-9);
+        assert!((attenuation.b() - expected.b()).abs() < 1e-9);
+        assert_ne!(Metal::gold(0.0), Metal::copper(0.0));
+    }
 
-        // Also verify that the direction is not zero
+    #[test]
+    fn test_rough_metal_blurs_around_the_mirror_direction() {
+        seed_thread_rng(31);
+        let incoming = incoming_at(45.0);
+        let mirror = Vec3::new(incoming.x(), 0.0, -incoming.z());
+        let spread = |roughness: f64| {
+            let metal = Metal::new(Color::new(1.0, 1.0, 1.0), roughness);
+            let hit_record = create_hit_record(
+                Point3::new(0.0, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+                Some(&metal),
+            );
+            let ray = Ray::new(Point3::from(-incoming), incoming, 0.0);
+            let cosines: Vec<f64> = (0..2000)
+                .filter_map(|_| metal.scatter(&ray, &hit_record))
+                .map(|(_, scattered)| scattered.direction().unit().dot(&mirror))
+                .collect();
+            cosines.iter().sum::<f64>() / cosines.len() as f64
+        };
+        // Reflections stray further from the mirror direction as roughness grows
+        let (glossy, brushed, rough) = (spread(0.1), spread(0.5), spread(1.0));
+        assert!(glossy > 0.99, "{}", glossy);
         assert!(
-            !scattered_ray.direction().near_zero(),
-            "Scattered ray direction should not be near zero"
+            glossy > brushed && brushed > rough,
+            "{} {} {}",
+            glossy,
+            brushed,
+            rough
+        );
+    }
+
+    #[test]
+    fn test_metal_scatter_propagates_differentials() {
+        use crate::hittable::SurfaceDifferentials;
+
+        let material = Metal::new(Color::new(0.8, 0.8, 0.8), 0.0);
+        let origin = Point3::new(0.0, 1.0, 0.0);
+        let ray = Ray::new(origin, Vec3::new(0.0, -1.0, 0.0), 0.0).with_differentials(Some(
+            RayDifferentials {
+                rx_origin: origin,
+                rx_direction: Vec3::new(0.01, -1.0, 0.0),
+                ry_origin: origin,
+                ry_direction: Vec3::new(0.0, -1.0, 0.01),
+            },
+        ));
+
+        let binding = material.clone();
+        let mut hit_record = create_hit_record(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Some(&binding),
         );
+        hit_record.differentials = Some(SurfaceDifferentials {
+            dpdx: Vec3::new(0.01, 0.0, 0.0),
+            dpdy: Vec3::new(0.0, 0.0, 0.01),
+            dndx: Vec3::default(),
+            dndy: Vec3::default(),
+        });
+
+        let (_, scattered_ray) = material.scatter(&ray, &hit_record).unwrap();
+        let differentials = scattered_ray
+            .differentials()
+            .expect("Mirror reflection should keep differentials");
+
+        // A flat mirror keeps the footprint diverging at the same rate
+        assert_eq!(differentials.rx_origin, Point3::new(0.01, 0.0, 0.0));
+        assert!(differentials.rx_direction.x() > 0.0);
+        assert!(differentials.rx_direction.y() > 0.0);
+    }
+
+    #[test]
+    fn test_refract_differentials() {
+        use crate::hittable::SurfaceDifferentials;
+
+        // Light entering glass at 30 degrees, its neighbours a little off either way
+        let eta = 1.0 / 1.5;
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let direction = Vec3::new(0.5, -(0.75f64.sqrt()), 0.0);
+        let rx_direction = direction + Vec3::new(0.001, 0.0, 0.0);
+        let ry_direction = direction + Vec3::new(0.0, 0.0, 0.001);
+        let origin = Point3::new(-0.5, 0.75f64.sqrt(), 0.0);
+        let ray = Ray::new(origin, direction, 0.0).with_differentials(Some(RayDifferentials {
+            rx_origin: origin,
+            rx_direction,
+            ry_origin: origin,
+            ry_direction,
+        }));
+
+        let mut hit_record = create_hit_record(Point3::new(0.0, 0.0, 0.0), normal, None);
+        hit_record.differentials = Some(SurfaceDifferentials {
+            dpdx: Vec3::new(0.002, 0.0, 0.0),
+            dpdy: Vec3::new(0.0, 0.0, 0.001),
+            dndx: Vec3::default(),
+            dndy: Vec3::default(),
+        });
+
+        let refracted = direction.unit().refract(&normal, eta);
+        let differentials = refract_differentials(&ray, &hit_record, &refracted, eta)
+            .expect("Refraction should keep differentials");
+
+        // The offset rays leave from where they landed on the surface
+        assert_eq!(differentials.rx_origin, Point3::new(0.002, 0.0, 0.0));
+        assert_eq!(differentials.ry_origin, Point3::new(0.0, 0.0, 0.001));
+
+        // And head where Snell's law bends them, to first order
+        for (transferred, incoming) in [
+            (differentials.rx_direction, rx_direction),
+            (differentials.ry_direction, ry_direction),
+        ] {
+            let exact = incoming.unit().refract(&normal, eta);
+            let error = (transferred.unit() - exact).length();
+            assert!(error < 1e-5, "Transferred direction off by {error}");
+        }
+        // Entering a denser medium narrows the spread of directions
+        let spread = (differentials.rx_direction.unit() - refracted).length();
+        assert!(spread < (rx_direction.unit() - direction.unit()).length());
     }
 
     #[test]
@@ -408,7 +1992,7 @@ mod tests {
         assert_eq!(scattered_color, Color::new(1.0, 1.0, 1.0));
 
         // Check that the scattered ray originates from the hit point
-        assert_eq!(*scattered_ray.origin(), hit_point);
+        assert!((*scattered_ray.origin() - hit_point).near_zero());
 
         // Check that the scattered ray direction is the normal
         assert_eq!(*scattered_ray.direction(), normal);
@@ -429,9 +2013,136 @@ mod tests {
         let hit_record = create_hit_record(hit_point, normal, Some(&binding));
 
         // Call scatter through the Material enum
-        let (color, _) = lambertian.scatter(&ray, &hit_record);
+        let (color, _) = lambertian.scatter(&ray, &hit_record).unwrap();
 
         // Verify we got the right color back
         assert_eq!(color, texture.value(0.0, 0.0, &Point3::new(0.0, 0.0, 0.0)));
     }
+
+    #[test]
+    fn test_materials_scatter_through_trait_objects() {
+        let clay = Lambertian::clay();
+        let hit_record = create_hit_record(
+            Point3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Some(&clay),
+        );
+        let Material::Lambertian(lambertian) = &clay else {
+            panic!("not a lambertian");
+        };
+        let materials: [&dyn Scatter; 3] = [
+            &clay,
+            lambertian,
+            &Dielectric {
+                refraction_index: 1.5,
+                tint: Color::new(1.0, 1.0, 1.0),
+                roughness: 0.0,
+                thin: false,
+                interior: None,
+            },
+        ];
+
+        assert_eq!(
+            materials.map(|material| material.name()),
+            ["lambertian", "lambertian", "dielectric"]
+        );
+        assert_eq!(
+            materials.map(|material| material.diffuse_reflectance(&hit_record)),
+            [Some(CLAY_ALBEDO), Some(CLAY_ALBEDO), None]
+        );
+    }
+
+    /// Tints rays and sends them straight back, as a stand-in for a material from
+    /// another crate.
+    struct Retroreflector(Color);
+
+    impl Scatter for Retroreflector {
+        fn name(&self) -> &'static str {
+            "retroreflector"
+        }
+
+        fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)> {
+            Some((self.0, hit_record.spawn_ray(-*ray.direction(), ray.time())))
+        }
+    }
+
+    #[test]
+    fn test_custom_material() {
+        let tint = Color::new(0.9, 0.8, 0.7);
+        let material = CustomMaterial::new(Retroreflector(tint));
+        let hit_record = create_hit_record(
+            Point3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Some(&material),
+        );
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+
+        let (color, scattered) = material.scatter(&ray, &hit_record).unwrap();
+        assert_eq!(color, tint);
+        assert_eq!(*scattered.direction(), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(material.name(), "retroreflector");
+        assert_eq!(material.diffuse_reflectance(&hit_record), None);
+
+        // Clones are the same material, a second instance is not
+        assert_eq!(material.clone(), material);
+        assert_eq!(material.id_color(), material.clone().id_color());
+        assert_ne!(CustomMaterial::new(Retroreflector(tint)), material);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        use crate::texture::CheckerTexture;
+
+        let checker = TextureEnum::CheckerTexture(CheckerTexture::new(
+            3.0,
+            Box::new(TextureEnum::SolidColor(Color::new(1.0, 1.0, 1.0).into())),
+            Box::new(TextureEnum::SolidColor(Color::new(0.0, 0.0, 0.0).into())),
+        ));
+        let materials = [
+            Lambertian::new(Box::new(checker.clone())),
+            Metal::new(Color::new(0.7, 0.6, 0.5), 0.25),
+            Metal::textured(checker.clone(), 0.5, checker),
+            Dielectric::new(1.5),
+            DiffuseLight::new(Box::new(TextureEnum::SolidColor(
+                Color::new(4.0, 4.0, 4.0).into(),
+            ))),
+        ];
+        let mut water = Dielectric::new(1.33);
+        if let Material::Dielectric(d) = &mut water {
+            d.set_interior(Some(Medium::new(
+                Color::new(0.3, 0.05, 0.01),
+                0.2,
+                Color::new(0.8, 0.9, 1.0),
+            )));
+        }
+        for material in materials.into_iter().chain([water]) {
+            let json = serde_json::to_string(&material).unwrap();
+            assert_eq!(serde_json::from_str::<Material>(&json).unwrap(), material);
+        }
+
+        // Metals written when roughness was called fuzz still read
+        let metal = Metal::new(Color::new(0.7, 0.6, 0.5), 0.25);
+        let json = serde_json::to_string(&metal)
+            .unwrap()
+            .replace("roughness", "fuzz");
+        assert_eq!(
+            serde_json::from_str::<Material>(&json).unwrap(),
+            Metal::new(Color::new(0.7, 0.6, 0.5), 0.25)
+        );
+
+        // Metals written when their albedo could only be a color still read
+        let json = format!(
+            r#"{{"Metal":{{"albedo":{},"roughness":0.25}}}}"#,
+            serde_json::to_string(&Color::new(0.7, 0.6, 0.5)).unwrap()
+        );
+        assert_eq!(
+            serde_json::from_str::<Material>(&json).unwrap(),
+            Metal::new(Color::new(0.7, 0.6, 0.5), 0.25)
+        );
+
+        // Materials defined outside the crate have nothing to write
+        let custom = CustomMaterial::new(Retroreflector(Color::new(1.0, 1.0, 1.0)));
+        assert!(serde_json::to_string(&custom).is_err());
+    }
 }
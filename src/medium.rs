@@ -0,0 +1,641 @@
+//! Participating media such as fog and smoke.
+//!
+//! A [`ConstantMedium`] fills the inside of another object with particles of
+//! uniform density. Rather than stopping at the boundary, a ray travelling through
+//! it scatters at a random distance drawn from the exponential distribution the
+//! density implies, or passes straight through if that distance lies beyond the
+//! far side. Shadow rays are tested the same way, so the medium casts soft shadows
+//! and beams of light through it show up as God rays.
+//!
+//! [`MediumPreset`] holds the coefficients of common media, such as milk, skin and
+//! fog, for filling an object with one by name.
+//!
+//! A [`Medium`] is instead declared by a dielectric's material as what fills its
+//! inside, such as the water in a glass. Paths keep a [`MediumStack`] of the media
+//! they have refracted into, so the medium a ray travels through follows it in and
+//! out of nested solids. The camera can also fill the space around every solid
+//! with one, at the bottom of each stack, to haze the scene with distance.
+
+use crate::aabb::Aabb;
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::{Isotropic, Material};
+use crate::ray::Ray;
+use crate::texture::TextureEnum;
+use crate::units::Units;
+use crate::utilities::{hash_f64, random_double};
+use std::hash::{Hash, Hasher};
+use std::ptr;
+use std::sync::Arc;
+
+/// Gap left after the near side of the boundary when looking for the far side.
+const BOUNDARY_EPSILON: f64 = 1e-4;
+/// Millimeters in a meter, as preset coefficients are per millimeter.
+const MILLIMETERS_PER_METER: f64 = 1000.0;
+
+/// Extinction coefficient, per millimeter, of air in which a dark object can just
+/// be seen `visibility` meters away (Koschmieder's law).
+const fn fog_extinction(visibility: f64) -> f64 {
+    3.912 / (visibility * MILLIMETERS_PER_METER)
+}
+
+/// How much light a common medium absorbs and scatters, per millimeter of it.
+///
+/// Milk, skin, marble and the foods are the measurements of Jensen et al., "A
+/// Practical Model for Subsurface Light Transport" (2001); wax and orange juice are
+/// approximations chosen to look right. Fog is given by how far one can see through
+/// it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MediumPreset {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Fraction of the light absorbed per millimeter, for each color channel
+    pub absorption: Color,
+    /// Fraction of the light scattered per millimeter, for each color channel
+    pub scattering: Color,
+}
+
+impl MediumPreset {
+    /// Every preset, for listing and finding by name.
+    pub const ALL: [MediumPreset; 14] = [
+        Self::measured(
+            "whole_milk",
+            "Whole milk",
+            (0.0011, 0.0024, 0.014),
+            (2.55, 3.21, 3.77),
+        ),
+        Self::measured(
+            "skimmed_milk",
+            "Skimmed milk, bluish and more translucent",
+            (0.0014, 0.0025, 0.0142),
+            (0.70, 1.22, 1.90),
+        ),
+        Self::measured(
+            "skin",
+            "Fair human skin",
+            (0.032, 0.17, 0.48),
+            (0.74, 0.88, 1.01),
+        ),
+        Self::measured(
+            "marble",
+            "White marble",
+            (0.0021, 0.0041, 0.0071),
+            (2.19, 2.62, 3.00),
+        ),
+        Self::measured(
+            "apple",
+            "The flesh of an apple",
+            (0.0030, 0.0034, 0.046),
+            (2.29, 2.39, 1.97),
+        ),
+        Self::measured(
+            "potato",
+            "The flesh of a potato",
+            (0.0024, 0.0090, 0.12),
+            (0.68, 0.70, 0.55),
+        ),
+        Self::measured(
+            "chicken",
+            "Raw chicken breast",
+            (0.015, 0.077, 0.19),
+            (0.15, 0.21, 0.38),
+        ),
+        Self::measured(
+            "ketchup",
+            "Tomato ketchup",
+            (0.061, 0.97, 1.45),
+            (0.18, 0.07, 0.03),
+        ),
+        Self::measured(
+            "wax",
+            "Candle wax, approximate",
+            (0.003, 0.006, 0.02),
+            (1.2, 1.3, 1.4),
+        ),
+        Self::measured(
+            "orange_juice",
+            "Orange juice, approximate",
+            (0.02, 0.08, 0.45),
+            (0.40, 0.34, 0.25),
+        ),
+        Self::fog("haze", "Haze, seeing 5 km", 5000.0),
+        Self::fog("light_fog", "Light fog, seeing 1 km", 1000.0),
+        Self::fog("fog", "Fog, seeing 200 m", 200.0),
+        Self::fog("dense_fog", "Dense fog, seeing 50 m", 50.0),
+    ];
+
+    const fn measured(
+        name: &'static str,
+        description: &'static str,
+        absorption: (f64, f64, f64),
+        scattering: (f64, f64, f64),
+    ) -> Self {
+        Self {
+            name,
+            description,
+            absorption: Color::new(absorption.0, absorption.1, absorption.2),
+            scattering: Color::new(scattering.0, scattering.1, scattering.2),
+        }
+    }
+
+    /// Fog droplets scatter every color alike and absorb almost nothing.
+    const fn fog(name: &'static str, description: &'static str, visibility: f64) -> Self {
+        let extinction = fog_extinction(visibility);
+        Self {
+            name,
+            description,
+            absorption: Color::new(0.0, 0.0, 0.0),
+            scattering: Color::new(extinction, extinction, extinction),
+        }
+    }
+
+    /// Returns the preset called `name`, if there is one.
+    pub fn named(name: &str) -> Option<MediumPreset> {
+        Self::ALL.iter().find(|preset| preset.name == name).copied()
+    }
+
+    /// Fraction of the light removed per millimeter by absorption and scattering.
+    pub fn extinction(&self) -> Color {
+        self.absorption + self.scattering
+    }
+
+    /// Fraction of the light removed at each interaction that is scattered rather
+    /// than absorbed, which gives the medium its color.
+    pub fn albedo(&self) -> Color {
+        let extinction = self.extinction();
+        let ratio = |scattering: f64, extinction: f64| {
+            if extinction > 0.0 {
+                scattering / extinction
+            } else {
+                0.0
+            }
+        };
+        Color::new(
+            ratio(self.scattering.r(), extinction.r()),
+            ratio(self.scattering.g(), extinction.g()),
+            ratio(self.scattering.b(), extinction.b()),
+        )
+    }
+
+    /// The density of a [`ConstantMedium`] of this preset, per unit of a scene in `units`.
+    ///
+    /// A constant medium has one density for every color, so this averages the
+    /// channels' extinction. The preset keeps its color through its albedo, though
+    /// not how that color deepens with distance.
+    pub fn density(&self, units: Units) -> f64 {
+        let extinction = self.extinction();
+        let per_millimeter = (extinction.r() + extinction.g() + extinction.b()) / 3.0;
+        per_millimeter * MILLIMETERS_PER_METER * units.meters_per_unit()
+    }
+
+    /// The phase function scattering light evenly with this preset's albedo.
+    pub fn phase_function(&self) -> Material {
+        Isotropic::new(Box::new(TextureEnum::SolidColor(self.albedo().into())))
+    }
+
+    /// The interior [`Medium`] of this preset, per unit of a scene in `units`.
+    ///
+    /// Absorption keeps its color per channel. Scattering happens at the average
+    /// of the channels' rates, colored by the preset's albedo.
+    pub fn medium(&self, units: Units) -> Medium {
+        let per_unit = MILLIMETERS_PER_METER * units.meters_per_unit();
+        let scattering = (self.scattering.r() + self.scattering.g() + self.scattering.b()) / 3.0;
+        Medium::new(
+            self.absorption * per_unit,
+            scattering * per_unit,
+            self.albedo(),
+        )
+    }
+}
+
+/// A uniform medium filling the inside of a solid, declared by its material.
+///
+/// Light travelling through it is absorbed following Beer's law, separately for
+/// each color channel, and scatters evenly in every direction off its particles.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Medium {
+    /// Fraction of the light absorbed per unit of distance, for each color channel
+    absorption: Color,
+    /// Chance per unit of distance that light scatters off a particle
+    scattering: f64,
+    phase_function: Material,
+}
+
+impl Hash for Medium {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.absorption.hash(state);
+        hash_f64(self.scattering, state);
+        self.phase_function.hash(state);
+    }
+}
+
+impl Medium {
+    /// Creates a medium absorbing `absorption` and scattering with chance
+    /// `scattering` per unit of distance, the scattered light colored by `albedo`.
+    pub fn new(absorption: Color, scattering: f64, albedo: Color) -> Self {
+        Self {
+            absorption,
+            scattering: scattering.max(0.0),
+            phase_function: Isotropic::new(Box::new(TextureEnum::SolidColor(albedo.into()))),
+        }
+    }
+
+    /// Creates a clear medium that only absorbs, such as colored water.
+    pub fn absorbing(absorption: Color) -> Self {
+        Self::new(absorption, 0.0, Color::new(1.0, 1.0, 1.0))
+    }
+
+    /// Approximate number of bytes this medium occupies.
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Medium>() + self.phase_function.memory_size()
+    }
+
+    /// Fraction of the light that passes `distance` through the medium unabsorbed.
+    pub fn transmittance(&self, distance: f64) -> Color {
+        let channel = |absorption: f64| {
+            if absorption > 0.0 {
+                (-absorption * distance).exp()
+            } else {
+                1.0
+            }
+        };
+        Color::new(
+            channel(self.absorption.r()),
+            channel(self.absorption.g()),
+            channel(self.absorption.b()),
+        )
+    }
+
+    /// Follows `ray` through the medium up to `t_max`, returning the fraction of
+    /// its light left on arrival and, if it scatters off a particle first, the
+    /// point where it does.
+    pub fn sample(&self, ray: &Ray, t_max: f64) -> (Color, Option<HitRecord<'_>>) {
+        let ray_length = ray.direction().length();
+        let distance = t_max * ray_length;
+        let hit_distance = if self.scattering > 0.0 {
+            -(1.0 - random_double()).ln() / self.scattering
+        } else {
+            f64::INFINITY
+        };
+        if hit_distance >= distance {
+            return (self.transmittance(distance), None);
+        }
+
+        let t = hit_distance / ray_length;
+        let normal = -ray.direction().unit();
+        let hit_record = HitRecord {
+            position: ray.at_time(t),
+            normal,
+            geometric_normal: normal,
+            t,
+            front_face: true,
+            material: Some(&self.phase_function),
+            ..Default::default()
+        };
+        (self.transmittance(hit_distance), Some(hit_record))
+    }
+}
+
+/// The media a path is inside, innermost last.
+///
+/// Leaving a solid nested in another returns the path to the outer one's medium,
+/// so overlapping media need no special handling.
+#[derive(Clone, Debug, Default)]
+pub struct MediumStack<'a> {
+    media: Vec<&'a Medium>,
+}
+
+impl<'a> MediumStack<'a> {
+    /// Creates a stack for a path starting out in `outside`, the medium filling
+    /// the space around every solid.
+    pub fn new(outside: Option<&'a Medium>) -> Self {
+        Self {
+            media: outside.into_iter().collect(),
+        }
+    }
+
+    /// Returns the medium the path is travelling through, if any.
+    pub fn current(&self) -> Option<&'a Medium> {
+        self.media.last().copied()
+    }
+
+    /// Updates the stack for a path scattered from `hit_record` as `scattered`.
+    ///
+    /// Passing through a surface whose material has an interior medium enters it
+    /// from the front and leaves it from the back; reflecting changes nothing.
+    pub fn cross(&mut self, hit_record: &HitRecord<'a>, scattered: &Ray) {
+        let Some(medium) = hit_record.material.and_then(Material::interior_medium) else {
+            return;
+        };
+        if scattered.direction().dot(&hit_record.geometric_normal) >= 0.0 {
+            return;
+        }
+        if hit_record.front_face {
+            self.media.push(medium);
+        } else if let Some(index) = self
+            .media
+            .iter()
+            .rposition(|&entered| ptr::eq(entered, medium))
+        {
+            self.media.remove(index);
+        }
+    }
+}
+
+/// A volume of constant density filling a convex boundary object.
+///
+/// The boundary is only used for its shape; its materials are ignored, and
+/// the medium scatters with its own phase function, usually an
+/// [`Isotropic`](crate::material::Isotropic) material.
+pub struct ConstantMedium {
+    boundary: Box<dyn Hittable>,
+    density: f64,
+    phase_function: Arc<Material>,
+}
+
+impl ConstantMedium {
+    /// Fills `boundary` with a medium of `density`, the chance per unit of distance
+    /// that a ray scatters off a particle.
+    pub fn new(
+        boundary: Box<dyn Hittable>,
+        density: f64,
+        phase_function: impl Into<Arc<Material>>,
+    ) -> Self {
+        Self {
+            boundary,
+            density,
+            phase_function: phase_function.into(),
+        }
+    }
+
+    /// Fills `boundary` with a common medium, such as [`MediumPreset::named`]`("whole_milk")`,
+    /// for a scene modeled in `units`.
+    pub fn preset(boundary: Box<dyn Hittable>, preset: &MediumPreset, units: Units) -> Self {
+        Self::new(boundary, preset.density(units), preset.phase_function())
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        // Find where the whole line enters and leaves the boundary, so rays that
+        // start inside the medium scatter too
+        let everywhere = Interval::new(f64::NEG_INFINITY, f64::INFINITY);
+        let entry = self.boundary.hit(r, everywhere)?.t;
+        let exit = self
+            .boundary
+            .hit(r, Interval::new(entry + BOUNDARY_EPSILON, f64::INFINITY))?
+            .t;
+
+        let enter = entry.max(ray_t.min()).max(0.0);
+        let leave = exit.min(ray_t.max());
+        if enter >= leave {
+            return None;
+        }
+
+        let ray_length = r.direction().length();
+        let distance_inside = (leave - enter) * ray_length;
+        let hit_distance = -(1.0 - random_double()).ln() / self.density;
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        let t = enter + hit_distance / ray_length;
+        // Inside a volume there is no surface, so the normal just faces the ray
+        let normal = -r.direction().unit();
+        Some(HitRecord {
+            position: r.at_time(t),
+            normal,
+            geometric_normal: normal,
+            t,
+            front_face: true,
+            material: Some(self.phase_function.as_ref()),
+            texture_coords: (0.0, 0.0),
+            differentials: None,
+            light_group: None,
+            primitive: None,
+            exclude_primitive: false,
+        })
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.boundary.bounding_box(time0, time1)
+    }
+
+    fn visit_materials(&mut self, visit: &mut dyn FnMut(&mut Arc<Material>)) {
+        visit(&mut self.phase_function);
+    }
+
+    fn is_volume(&self) -> bool {
+        true
+    }
+
+    fn degenerate_reason(&self) -> Option<&'static str> {
+        if !(self.density.is_finite() && self.density > 0.0) {
+            Some("density is not positive")
+        } else {
+            self.boundary.degenerate_reason()
+        }
+    }
+
+    fn degenerate_primitives(&self) -> usize {
+        self.boundary.degenerate_primitives()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::{Dielectric, Isotropic, Scatter, TestMaterial};
+    use crate::point3::Point3;
+    use crate::sphere::{Sphere, SphereType};
+    use crate::texture::TextureEnum;
+    use crate::utilities::seed_thread_rng;
+    use crate::vec3::Vec3;
+
+    fn fog(density: f64) -> ConstantMedium {
+        let boundary = SphereType::Static(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            TestMaterial::new(),
+        ));
+        ConstantMedium::new(
+            Box::new(boundary),
+            density,
+            Isotropic::new(Box::new(TextureEnum::SolidColor(
+                Color::new(1.0, 1.0, 1.0).into(),
+            ))),
+        )
+    }
+
+    #[test]
+    fn test_rays_scatter_with_beer_lambert_transmittance() {
+        seed_thread_rng(9);
+        let medium = fog(0.5);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -2.0), 0.0);
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+
+        let trials = 20_000;
+        let mut passed = 0;
+        for _ in 0..trials {
+            match medium.hit(&ray, ray_t) {
+                Some(hit) => {
+                    assert!(hit.position.z().abs() <= 1.0 + 1e-9, "{:?}", hit.position);
+                    assert_eq!(hit.material.map(Material::name), Some("isotropic"));
+                }
+                None => passed += 1,
+            }
+        }
+        // Two units of medium at density 0.5 let through e^-1 of the rays
+        let transmittance = passed as f64 / trials as f64;
+        assert!(
+            (transmittance - (-1.0f64).exp()).abs() < 0.01,
+            "{}",
+            transmittance
+        );
+
+        // Rays starting inside scatter before reaching the far side
+        let inside = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        assert!((0..100).any(|_| medium.hit(&inside, ray_t).is_some()));
+        // and rays ending before the medium never reach it
+        let short = Interval::new(0.001, 1.5);
+        assert!((0..100).all(|_| medium.hit(&ray, short).is_none()));
+        let miss = Ray::new(Point3::new(0.0, 2.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(medium.hit(&miss, ray_t).is_none());
+
+        assert_eq!(
+            fog(0.0).degenerate_reason(),
+            Some("density is not positive")
+        );
+        assert_eq!(medium.degenerate_reason(), None);
+        assert!(medium.is_volume());
+    }
+
+    #[test]
+    fn test_presets() {
+        for preset in MediumPreset::ALL {
+            assert_eq!(MediumPreset::named(preset.name), Some(preset));
+            let albedo = preset.albedo();
+            for c in [albedo.r(), albedo.g(), albedo.b()] {
+                assert!((0.0..=1.0).contains(&c), "{} {:?}", preset.name, albedo);
+            }
+            assert!(preset.density(Units::Meters) > 0.0);
+        }
+        assert_eq!(MediumPreset::named("mayonnaise"), None);
+
+        // Milk scatters nearly all it stops, more so in blue, and a millimeter of it
+        // stops most light
+        let milk = MediumPreset::named("whole_milk").unwrap();
+        assert!(milk.albedo().r() > 0.99 && milk.albedo().b() > milk.albedo().r() - 0.01);
+        assert!((milk.density(Units::Millimeters) - 3.18).abs() < 0.01);
+        assert!((milk.density(Units::Meters) - 3180.0).abs() < 10.0);
+        // Ketchup absorbs green and blue
+        let ketchup = MediumPreset::named("ketchup").unwrap().albedo();
+        assert!(ketchup.r() > 0.7 && ketchup.g() < 0.1);
+
+        // Through fog a dark object fades to 2% of its contrast at the visibility
+        let fog = MediumPreset::named("fog").unwrap();
+        let transmittance = (-fog.density(Units::Meters) * 200.0).exp();
+        assert!((transmittance - 0.02).abs() < 1e-3, "{}", transmittance);
+        assert_eq!(fog.albedo(), Color::new(1.0, 1.0, 1.0));
+
+        let boundary = SphereType::Static(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            TestMaterial::new(),
+        ));
+        let medium = ConstantMedium::preset(Box::new(boundary), &fog, Units::Kilometers);
+        assert!((medium.density - 19.56).abs() < 1e-9);
+        assert_eq!(medium.phase_function.name(), "isotropic");
+    }
+
+    #[test]
+    fn test_medium_absorbs_and_scatters() {
+        seed_thread_rng(4);
+        let ray = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, -2.0), 0.0);
+
+        // A clear medium only absorbs, each channel at its own rate
+        let water = Medium::absorbing(Color::new(0.5, 0.1, 0.0));
+        let (transmittance, scattered) = water.sample(&ray, 1.0);
+        assert!(scattered.is_none());
+        assert!((transmittance.r() - (-1.0f64).exp()).abs() < 1e-12);
+        assert!((transmittance.g() - (-0.2f64).exp()).abs() < 1e-12);
+        assert_eq!(transmittance.b(), 1.0);
+        assert_eq!(water.sample(&ray, f64::INFINITY).0.b(), 1.0);
+
+        // Scattering stops rays at the rate the scattering coefficient gives
+        let milk = Medium::new(Color::new(0.0, 0.0, 0.0), 0.5, Color::new(0.9, 0.9, 0.8));
+        let trials = 20_000;
+        let passed = (0..trials)
+            .filter(|_| match milk.sample(&ray, 1.0) {
+                (_, Some(hit)) => {
+                    assert!(hit.t > 0.0 && hit.t < 1.0);
+                    assert_eq!(hit.material.map(Material::name), Some("isotropic"));
+                    false
+                }
+                (_, None) => true,
+            })
+            .count();
+        let transmittance = passed as f64 / trials as f64;
+        assert!(
+            (transmittance - (-1.0f64).exp()).abs() < 0.01,
+            "{}",
+            transmittance
+        );
+
+        let preset = MediumPreset::named("whole_milk")
+            .unwrap()
+            .medium(Units::Meters);
+        assert!(preset.scattering > 0.0);
+        assert!(preset.transmittance(0.001).b() < preset.transmittance(0.001).r());
+    }
+
+    #[test]
+    fn test_medium_stack_follows_nested_solids() {
+        let mut water = Dielectric::new(1.33);
+        if let Material::Dielectric(d) = &mut water {
+            d.set_interior(Some(Medium::absorbing(Color::new(0.1, 0.0, 0.0))));
+        }
+        let mut wax = Dielectric::new(1.45);
+        if let Material::Dielectric(d) = &mut wax {
+            d.set_interior(Some(Medium::new(
+                Color::new(0.0, 0.0, 0.0),
+                1.0,
+                Color::new(1.0, 1.0, 1.0),
+            )));
+        }
+        let boundary = |material, front_face| HitRecord {
+            geometric_normal: Vec3::new(0.0, 0.0, 1.0),
+            front_face,
+            material: Some(material),
+            ..Default::default()
+        };
+        let inwards = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let outwards = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, 1.0), 0.0);
+
+        let mut media = MediumStack::default();
+        // Reflecting off the outside leaves the path where it was
+        media.cross(&boundary(&water, true), &outwards);
+        assert!(media.current().is_none());
+        media.cross(&boundary(&water, true), &inwards);
+        assert_eq!(media.current(), water.interior_medium());
+        media.cross(&boundary(&wax, true), &inwards);
+        assert_eq!(media.current(), wax.interior_medium());
+        // Leaving the inner solid returns to the outer one's medium
+        media.cross(&boundary(&wax, false), &inwards);
+        assert_eq!(media.current(), water.interior_medium());
+        media.cross(&boundary(&water, false), &inwards);
+        assert!(media.current().is_none());
+
+        // Clear glass and thin sheets have no inside to enter
+        let glass = Dielectric::new(1.5);
+        media.cross(&boundary(&glass, true), &inwards);
+        assert!(media.current().is_none());
+        if let Material::Dielectric(d) = &mut water {
+            d.set_thin(true);
+        }
+        assert!(water.interior_medium().is_none());
+    }
+}
@@ -0,0 +1,803 @@
+//! Triangle meshes loaded from Wavefront OBJ files.
+//!
+//! A [`Mesh`] holds the vertex data read from a file and can be moved and scaled
+//! before it is turned into a [`TriangleMesh`], which organizes its triangles in a
+//! BVH of their own so a mesh of any size is a single object in the scene.
+//!
+//! Only geometry is read: `v`, `vt`, `vn` and `f` lines. Polygons are split into
+//! triangle fans, and other statements such as groups and materials are ignored.
+//!
+//! Tools disagree on which way is up and which way the axes turn. An
+//! [`AxisConvention`] names the one a file was written in, and
+//! [`Mesh::convert_axes`] carries it into the renderer's own: y up, right-handed.
+
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::error::Error;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::point3::Point3;
+use crate::ray::{PrimitiveId, Ray};
+use crate::transform::Transform;
+use crate::vec3::Vec3;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Determinants smaller than this mean the ray runs parallel to the triangle.
+const PARALLEL_EPSILON: f64 = 1e-12;
+/// Least thickness of a triangle's bounding box, so boxes around triangles lying
+/// in an axis plane are not empty.
+const MIN_BOX_THICKNESS: f64 = 1e-6;
+/// Most cells along each side of the grid that finds triangles in texture space.
+const MAX_UV_GRID_SIZE: usize = 256;
+
+/// A line of an OBJ file that could not be read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjError {
+    /// Line number, counting from 1
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl error::Error for ObjError {}
+
+/// One corner of a face: indices into the mesh's positions, texture coordinates
+/// and normals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Corner {
+    pub position: usize,
+    pub texture_coords: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+/// The axis pointing up in a file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum UpAxis {
+    #[default]
+    Y,
+    Z,
+}
+
+/// Whether a file's axes turn like the fingers of the right hand or the left.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Handedness {
+    #[default]
+    Right,
+    Left,
+}
+
+/// The axis convention a file was written in.
+///
+/// The default, y up and right-handed, is the renderer's own and that of most
+/// OBJ exporters. Blender and 3ds Max write z up, Unity y up left-handed and
+/// Unreal z up left-handed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AxisConvention {
+    pub up: UpAxis,
+    pub handedness: Handedness,
+}
+
+impl AxisConvention {
+    /// Returns the root transform carrying coordinates written in this convention
+    /// into the renderer's.
+    ///
+    /// Left-handed files are first mirrored along their forward axis, which is the
+    /// one neither up nor x, then z-up ones are turned so +z points along +y.
+    pub fn transform(&self) -> Transform {
+        let mirror = match (self.handedness, self.up) {
+            (Handedness::Right, _) => Transform::IDENTITY,
+            (Handedness::Left, UpAxis::Y) => Transform::scaling(Vec3::new(1.0, 1.0, -1.0)),
+            (Handedness::Left, UpAxis::Z) => Transform::scaling(Vec3::new(1.0, -1.0, 1.0)),
+        };
+        let turn = match self.up {
+            UpAxis::Y => Transform::IDENTITY,
+            UpAxis::Z => Transform::rotation_x(-90.0),
+        };
+        turn * mirror
+    }
+}
+
+/// Vertex data and triangles read from an OBJ file.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mesh {
+    pub positions: Vec<Point3>,
+    pub texture_coords: Vec<(f64, f64)>,
+    pub normals: Vec<Vec3>,
+    pub triangles: Vec<[Corner; 3]>,
+}
+
+impl Mesh {
+    /// Reads the OBJ file at `path`.
+    pub fn load_obj(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::parse_obj(BufReader::new(File::open(path)?))
+    }
+
+    /// Reads a mesh in OBJ format from `reader`.
+    pub fn parse_obj(reader: impl BufRead) -> Result<Self, Error> {
+        let mut mesh = Mesh::default();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            mesh.parse_line(&line).map_err(|reason| ObjError {
+                line: number + 1,
+                reason,
+            })?;
+        }
+        Ok(mesh)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("v") => {
+                let [x, y, z] = numbers(fields)?;
+                self.positions.push(Point3::new(x, y, z));
+            }
+            Some("vt") => {
+                // The second coordinate is optional, and any third is for 3D textures
+                let u = number(fields.next().ok_or("vt needs a coordinate")?)?;
+                let v = fields.next().map(number).transpose()?.unwrap_or(0.0);
+                self.texture_coords.push((u, v));
+            }
+            Some("vn") => {
+                let [x, y, z] = numbers(fields)?;
+                self.normals.push(Vec3::new(x, y, z).unit());
+            }
+            Some("f") => {
+                let corners = fields
+                    .map(|field| self.corner(field))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if corners.len() < 3 {
+                    return Err("a face needs at least three corners".to_string());
+                }
+                for i in 1..corners.len() - 1 {
+                    self.triangles
+                        .push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Parses a face corner written `v`, `v/vt`, `v//vn` or `v/vt/vn`.
+    fn corner(&self, field: &str) -> Result<Corner, String> {
+        let mut indices = field.split('/');
+        let position = indices.next().unwrap_or_default();
+        let optional = |index: Option<&str>, count: usize| match index {
+            None | Some("") => Ok(None),
+            Some(index) => resolve(index, count).map(Some),
+        };
+        Ok(Corner {
+            position: resolve(position, self.positions.len())?,
+            texture_coords: optional(indices.next(), self.texture_coords.len())?,
+            normal: optional(indices.next(), self.normals.len())?,
+        })
+    }
+
+    /// Creates an axis-aligned box from corner `min` to corner `max`, with faces
+    /// wound to point outwards and each face textured from (0, 0) to (1, 1).
+    pub fn cuboid(min: Point3, max: Point3) -> Self {
+        // Corner i takes max along x, y and z where bits 0, 1 and 2 of i are set
+        let positions = (0..8)
+            .map(|i| {
+                Point3::new(
+                    if i & 1 == 0 { min.x() } else { max.x() },
+                    if i & 2 == 0 { min.y() } else { max.y() },
+                    if i & 4 == 0 { min.z() } else { max.z() },
+                )
+            })
+            .collect();
+        let faces = [
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+        ];
+        let triangles = faces
+            .iter()
+            .flat_map(|face| {
+                let corner = |k: usize| Corner {
+                    position: face[k],
+                    texture_coords: Some(k),
+                    normal: None,
+                };
+                [
+                    [corner(0), corner(1), corner(2)],
+                    [corner(0), corner(2), corner(3)],
+                ]
+            })
+            .collect();
+        Self {
+            positions,
+            texture_coords: vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+            normals: Vec::new(),
+            triangles,
+        }
+    }
+
+    fn triangle_positions(&self, index: usize) -> [Point3; 3] {
+        self.triangles[index].map(|corner| self.positions[corner.position])
+    }
+
+    /// Returns the texture coordinates of a triangle's corners, if all have them.
+    fn triangle_uvs(&self, index: usize) -> Option<[(f64, f64); 3]> {
+        match self.triangles[index].map(|corner| corner.texture_coords) {
+            [Some(a), Some(b), Some(c)] => Some([a, b, c].map(|i| self.texture_coords[i])),
+            _ => None,
+        }
+    }
+
+    /// Interpolates a triangle's vertex normals at the barycentric `weights`, turned
+    /// to the same side as `geometric_normal`. Returns `None` if the corners have no
+    /// normals or they cancel out.
+    fn shading_normal(
+        &self,
+        index: usize,
+        weights: [f64; 3],
+        geometric_normal: &Vec3,
+    ) -> Option<Vec3> {
+        let [Some(a), Some(b), Some(c)] = self.triangles[index].map(|corner| corner.normal) else {
+            return None;
+        };
+        let n = [a, b, c].map(|i| self.normals[i]);
+        let shading = (n[0] * weights[0] + n[1] * weights[1] + n[2] * weights[2]).unit();
+        if !shading.is_finite() {
+            None
+        } else if shading.dot(geometric_normal) < 0.0 {
+            Some(-shading)
+        } else {
+            Some(shading)
+        }
+    }
+
+    /// Returns the box enclosing every vertex, or `None` if there are none.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.positions
+            .iter()
+            .map(|&p| point_box(p))
+            .reduce(|a, b| Aabb::surrounding(&a, &b))
+    }
+
+    /// Carries the mesh through `transform`.
+    ///
+    /// Normals are carried by the inverse transpose so they stay perpendicular to
+    /// stretched surfaces, and a mirroring transform reverses the winding of every
+    /// triangle so faces keep pointing outwards.
+    pub fn apply(&mut self, transform: &Transform) -> Result<(), Error> {
+        let inverse = transform
+            .inverse()
+            .ok_or(Error::Build("mesh transform flattens space"))?;
+        for position in &mut self.positions {
+            *position = transform.point(position);
+        }
+        for normal in &mut self.normals {
+            *normal = inverse.transposed_vector(normal).unit();
+        }
+        if transform.determinant() < 0.0 {
+            for triangle in &mut self.triangles {
+                triangle.swap(1, 2);
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts a mesh written in `convention` into the renderer's axes.
+    pub fn convert_axes(&mut self, convention: AxisConvention) {
+        self.apply(&convention.transform())
+            .expect("axis conventions only turn and mirror");
+    }
+
+    /// Scales the mesh about the origin by `scale`, then moves it by `offset`.
+    pub fn transform(&mut self, scale: f64, offset: Vec3) {
+        for position in &mut self.positions {
+            *position = Point3::from(position.as_vec3() * scale) + offset;
+        }
+        if scale < 0.0 {
+            for normal in &mut self.normals {
+                *normal = -*normal;
+            }
+        }
+    }
+}
+
+/// Parses three numbers, ignoring any that follow.
+fn numbers<'a>(mut fields: impl Iterator<Item = &'a str>) -> Result<[f64; 3], String> {
+    let mut next = || number(fields.next().ok_or("expected three coordinates")?);
+    Ok([next()?, next()?, next()?])
+}
+
+fn number(field: &str) -> Result<f64, String> {
+    field
+        .parse()
+        .map_err(|_| format!("invalid number '{}'", field))
+}
+
+/// Turns a 1-based OBJ index, or a negative one counting back from the last
+/// element read, into an index into a list of `count` elements.
+fn resolve(index: &str, count: usize) -> Result<usize, String> {
+    let value: i64 = index
+        .parse()
+        .map_err(|_| format!("invalid index '{}'", index))?;
+    let resolved = if value < 0 {
+        count as i64 + value
+    } else {
+        value - 1
+    };
+    if value == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(format!("index {} out of range", value));
+    }
+    Ok(resolved as usize)
+}
+
+fn point_box(p: Point3) -> Aabb {
+    Aabb::new(
+        Interval::new(p.x(), p.x()),
+        Interval::new(p.y(), p.y()),
+        Interval::new(p.z(), p.z()),
+    )
+}
+
+/// One triangle of a shared mesh.
+///
+/// Triangles carry no material; the [`TriangleMesh`] holding them fills it in.
+struct Triangle {
+    mesh: Arc<Mesh>,
+    index: usize,
+    id: PrimitiveId,
+}
+
+impl Triangle {
+    /// Returns the unnormalized face normal, whose length is twice the area.
+    fn face_normal(&self) -> Vec3 {
+        let [p0, p1, p2] = self.mesh.triangle_positions(self.index);
+        (p1 - p0).cross(&(p2 - p0))
+    }
+}
+
+impl Hittable for Triangle {
+    /// Möller–Trumbore intersection, giving the hit's barycentric coordinates.
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        if r.excluded_primitive() == Some(self.id) {
+            return None;
+        }
+        let [p0, p1, p2] = self.mesh.triangle_positions(self.index);
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+        let p = r.direction().cross(&edge2);
+        let determinant = edge1.dot(&p);
+        if determinant.abs() < PARALLEL_EPSILON {
+            return None;
+        }
+
+        let inverse = 1.0 / determinant;
+        let s = *r.origin() - p0;
+        let u = s.dot(&p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(&edge1);
+        let v = r.direction().dot(&q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(&q) * inverse;
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+
+        let weights = [1.0 - u - v, u, v];
+        let texture_coords = match self.mesh.triangle_uvs(self.index) {
+            Some(uv) => (
+                weights[0] * uv[0].0 + weights[1] * uv[1].0 + weights[2] * uv[2].0,
+                weights[0] * uv[0].1 + weights[1] * uv[1].1 + weights[2] * uv[2].1,
+            ),
+            None => (u, v),
+        };
+
+        let outward_normal = edge1.cross(&edge2).unit();
+        let mut hit_record = HitRecord {
+            t,
+            position: r.at_time(t),
+            front_face: true,
+            material: None,
+            texture_coords,
+            normal: outward_normal,
+            geometric_normal: outward_normal,
+            differentials: None,
+            light_group: None,
+            primitive: Some(self.id),
+            exclude_primitive: false,
+        };
+        hit_record.set_face_normal(r, &outward_normal);
+        hit_record.set_differentials(r, 0.0);
+
+        if let Some(shading) =
+            self.mesh
+                .shading_normal(self.index, weights, &hit_record.geometric_normal)
+        {
+            hit_record.normal = shading;
+        }
+
+        Some(hit_record)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        let [p0, p1, p2] = self.mesh.triangle_positions(self.index);
+        let bbox = Aabb::surrounding(
+            &Aabb::surrounding(&point_box(p0), &point_box(p1)),
+            &point_box(p2),
+        );
+        let axis = |axis: usize| {
+            let interval = bbox.axis_interval(axis);
+            let pad = (MIN_BOX_THICKNESS - (interval.max() - interval.min())).max(0.0) / 2.0;
+            Interval::new(interval.min() - pad, interval.max() + pad)
+        };
+        Some(Aabb::new(axis(0), axis(1), axis(2)))
+    }
+}
+
+/// The triangles of a mesh sorted into a grid over texture space, so the one
+/// covering a texture coordinate is found without testing them all.
+struct UvGrid {
+    /// Cells along each side of the unit square
+    size: usize,
+    /// Index and ID of each triangle whose texture coordinates overlap a cell, in
+    /// rows of increasing `v`
+    cells: Vec<Vec<(usize, PrimitiveId)>>,
+}
+
+impl UvGrid {
+    /// Sorts the `triangles` that have texture coordinates, returning `None` if
+    /// none do.
+    fn new(mesh: &Mesh, triangles: &[Triangle]) -> Option<Self> {
+        let mapped: Vec<_> = triangles
+            .iter()
+            .filter_map(|triangle| Some((triangle, mesh.triangle_uvs(triangle.index)?)))
+            .collect();
+        if mapped.is_empty() {
+            return None;
+        }
+        let size = ((mapped.len() as f64).sqrt().ceil() as usize).clamp(1, MAX_UV_GRID_SIZE);
+        let cell = |x: f64| ((x.clamp(0.0, 1.0) * size as f64) as usize).min(size - 1);
+        let mut cells = vec![Vec::new(); size * size];
+        for (triangle, uv) in mapped {
+            let low = uv.iter().fold((f64::INFINITY, f64::INFINITY), |low, uv| {
+                (low.0.min(uv.0), low.1.min(uv.1))
+            });
+            let high = uv
+                .iter()
+                .fold((f64::NEG_INFINITY, f64::NEG_INFINITY), |high, uv| {
+                    (high.0.max(uv.0), high.1.max(uv.1))
+                });
+            for row in cell(low.1)..=cell(high.1) {
+                for column in cell(low.0)..=cell(high.0) {
+                    cells[row * size + column].push((triangle.index, triangle.id));
+                }
+            }
+        }
+        Some(Self { size, cells })
+    }
+
+    /// Returns the triangle whose texture coordinates cover `uv`, its ID, and the
+    /// barycentric weights of `uv` within it.
+    fn find(&self, mesh: &Mesh, uv: (f64, f64)) -> Option<(usize, PrimitiveId, [f64; 3])> {
+        if !(0.0..=1.0).contains(&uv.0) || !(0.0..=1.0).contains(&uv.1) {
+            return None;
+        }
+        let cell = |x: f64| ((x * self.size as f64) as usize).min(self.size - 1);
+        self.cells[cell(uv.1) * self.size + cell(uv.0)]
+            .iter()
+            .find_map(|&(index, id)| {
+                let weights = barycentric(mesh.triangle_uvs(index)?, uv)?;
+                Some((index, id, weights))
+            })
+    }
+}
+
+/// Returns the weights of the corners of the 2D triangle `corners` that average
+/// to `p`, if it lies inside.
+fn barycentric(corners: [(f64, f64); 3], p: (f64, f64)) -> Option<[f64; 3]> {
+    let [a, b, c] = corners;
+    let area = (b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1);
+    if area == 0.0 {
+        return None;
+    }
+    let w1 = ((p.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (p.1 - a.1)) / area;
+    let w2 = ((b.0 - a.0) * (p.1 - a.1) - (p.0 - a.0) * (b.1 - a.1)) / area;
+    let w0 = 1.0 - w1 - w2;
+    (w0 >= 0.0 && w1 >= 0.0 && w2 >= 0.0).then_some([w0, w1, w2])
+}
+
+/// A triangle mesh with one material, intersected through its own BVH.
+pub struct TriangleMesh {
+    triangles: Bvh,
+    mesh: Arc<Mesh>,
+    /// Where the triangles lie in texture space, if they have texture coordinates
+    uv_grid: Option<UvGrid>,
+    /// Triangles with no area, left out when the mesh was built
+    degenerate: usize,
+    material: Arc<Material>,
+}
+
+impl TriangleMesh {
+    /// Creates a mesh object from `mesh`, leaving out triangles with no area.
+    ///
+    /// How many were left out is reported by [`Hittable::degenerate_primitives`].
+    pub fn new(mesh: Mesh, material: impl Into<Arc<Material>>) -> Result<Self, Error> {
+        let mesh = Arc::new(mesh);
+        let triangles: Vec<Triangle> = (0..mesh.triangles.len())
+            .map(|index| Triangle {
+                mesh: Arc::clone(&mesh),
+                index,
+                id: PrimitiveId::next(true),
+            })
+            .filter(|triangle| {
+                let normal = triangle.face_normal();
+                normal.is_finite() && normal.length_squared() > 0.0
+            })
+            .collect();
+        if triangles.is_empty() {
+            return Err(Error::Build("mesh has no triangles"));
+        }
+        Ok(Self {
+            uv_grid: UvGrid::new(&mesh, &triangles),
+            degenerate: mesh.triangles.len() - triangles.len(),
+            triangles: Bvh::new(
+                triangles
+                    .into_iter()
+                    .map(|triangle| Box::new(triangle) as Box<dyn Hittable>)
+                    .collect(),
+            )?,
+            mesh,
+            material: material.into(),
+        })
+    }
+
+    /// Reads the OBJ file at `path` into a mesh object.
+    pub fn load_obj(
+        path: impl AsRef<Path>,
+        material: impl Into<Arc<Material>>,
+    ) -> Result<Self, Error> {
+        Self::new(Mesh::load_obj(path)?, material)
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let mut hit_record = self.triangles.hit(r, ray_t)?;
+        hit_record.material = Some(self.material.as_ref());
+        Some(hit_record)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.triangles.bounding_box(time0, time1)
+    }
+
+    fn visit_materials(&mut self, visit: &mut dyn FnMut(&mut Arc<Material>)) {
+        visit(&mut self.material);
+    }
+
+    fn degenerate_primitives(&self) -> usize {
+        self.degenerate
+    }
+
+    /// Finds the triangle whose texture coordinates cover `uv` and interpolates
+    /// its surface there.
+    fn surface_at(&self, uv: (f64, f64)) -> Option<HitRecord<'_>> {
+        let (index, id, weights) = self.uv_grid.as_ref()?.find(&self.mesh, uv)?;
+        let [p0, p1, p2] = self.mesh.triangle_positions(index);
+        let outward_normal = (p1 - p0).cross(&(p2 - p0)).unit();
+        Some(HitRecord {
+            position: p0 + (p1 - p0) * weights[1] + (p2 - p0) * weights[2],
+            front_face: true,
+            material: Some(self.material.as_ref()),
+            texture_coords: uv,
+            normal: self
+                .mesh
+                .shading_normal(index, weights, &outward_normal)
+                .unwrap_or(outward_normal),
+            geometric_normal: outward_normal,
+            primitive: Some(id),
+            ..Default::default()
+        })
+    }
+
+    fn has_texture_space(&self) -> bool {
+        self.uv_grid.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::TestMaterial;
+
+    /// A unit square in the z = 0 plane, facing +z, split into two triangles with
+    /// texture coordinates and normals.
+    const SQUARE: &str = "\
+# unit square
+o square
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 -1/-1/-1
+";
+
+    fn square() -> Mesh {
+        Mesh::parse_obj(SQUARE.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_obj() {
+        let mesh = square();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.texture_coords[2], (1.0, 1.0));
+        assert_eq!(mesh.normals, vec![Vec3::new(0.0, 0.0, 1.0)]);
+        // The quad is split into a fan around its first corner
+        let positions: Vec<_> = mesh
+            .triangles
+            .iter()
+            .map(|triangle| triangle.map(|corner| corner.position))
+            .collect();
+        assert_eq!(positions, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.triangles[1][2].texture_coords, Some(3));
+
+        let mesh = Mesh::parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1//1 2 3\n".as_bytes());
+        assert!(mesh.is_err());
+        let Err(Error::Obj(error)) = Mesh::parse_obj("v 0 0 0\n\nf 1 2 3\n".as_bytes()) else {
+            panic!("parsed a face with missing vertices");
+        };
+        assert_eq!(error.to_string(), "line 3: index 2 out of range");
+        assert!(Mesh::parse_obj("v 0 zero 0\n".as_bytes()).is_err());
+        assert!(Mesh::parse_obj("v 0 0 0\nf 1 1\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_triangle_mesh_hit() {
+        let mesh = TriangleMesh::new(square(), TestMaterial::new()).unwrap();
+        let ray = Ray::new(Point3::new(0.75, 0.25, 2.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let hit = mesh.hit(&ray, Interval::new(0.001, f64::INFINITY)).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-9);
+        assert!(hit.front_face);
+        assert!(hit.material.is_some());
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+        let (u, v) = hit.texture_coords;
+        assert!((u - 0.75).abs() < 1e-9 && (v - 0.25).abs() < 1e-9);
+
+        // From behind, the normals face the ray
+        let ray = Ray::new(Point3::new(0.25, 0.75, -1.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let hit = mesh.hit(&ray, Interval::new(0.001, f64::INFINITY)).unwrap();
+        assert!(!hit.front_face);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, -1.0));
+
+        let ray = Ray::new(Point3::new(1.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(
+            mesh.hit(&ray, Interval::new(0.001, f64::INFINITY))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_transform_and_degenerate_triangles() {
+        let mut mesh = square();
+        mesh.transform(2.0, Vec3::new(0.0, 0.0, -1.0));
+        let bounds = mesh.bounds().unwrap();
+        assert_eq!(bounds.center(), Point3::new(1.0, 1.0, -1.0));
+        assert_eq!(bounds.diagonal(), Vec3::new(2.0, 2.0, 0.0));
+
+        let cuboid = Mesh::cuboid(Point3::new(-1.0, 0.0, 2.0), Point3::new(1.0, 3.0, 4.0));
+        assert_eq!(cuboid.triangles.len(), 12);
+        let bounds = cuboid.bounds().unwrap();
+        assert_eq!(bounds.center(), Point3::new(0.0, 1.5, 3.0));
+        assert_eq!(bounds.diagonal(), Vec3::new(2.0, 3.0, 2.0));
+        // Every face points away from the middle of the box
+        let cuboid = TriangleMesh::new(cuboid, TestMaterial::new()).unwrap();
+        for (origin, direction) in [
+            (Point3::new(0.0, 1.5, 10.0), Vec3::new(0.0, 0.0, -1.0)),
+            (Point3::new(-5.0, 1.5, 3.0), Vec3::new(1.0, 0.0, 0.0)),
+            (Point3::new(0.5, 9.0, 2.5), Vec3::new(0.0, -1.0, 0.0)),
+        ] {
+            let ray = Ray::new(origin, direction, 0.0);
+            let hit = cuboid
+                .hit(&ray, Interval::new(0.001, f64::INFINITY))
+                .unwrap();
+            assert!(hit.front_face, "{:?}", direction);
+            assert_eq!(hit.normal, -direction);
+        }
+
+        let line = Mesh::parse_obj("v 0 0 0\nv 1 0 0\nv 2 0 0\nf 1 2 3\n".as_bytes()).unwrap();
+        assert!(matches!(
+            TriangleMesh::new(line, TestMaterial::new()),
+            Err(Error::Build(_))
+        ));
+
+        let mut mesh = square();
+        mesh.parse_line("f 1 2 2").unwrap();
+        let mesh = TriangleMesh::new(mesh, TestMaterial::new()).unwrap();
+        assert_eq!(mesh.degenerate_primitives(), 1);
+    }
+
+    #[test]
+    fn test_surface_at() {
+        let mut mesh = square();
+        mesh.transform(2.0, Vec3::new(0.0, 0.0, -1.0));
+        let mesh = TriangleMesh::new(mesh, TestMaterial::new()).unwrap();
+        assert!(mesh.has_texture_space());
+        for uv in [(0.75, 0.25), (0.25, 0.75), (0.5, 0.5), (0.0, 1.0)] {
+            let surface = mesh.surface_at(uv).unwrap();
+            let expected = Point3::new(2.0 * uv.0, 2.0 * uv.1, -1.0);
+            assert!((surface.position - expected).length() < 1e-9, "{:?}", uv);
+            assert_eq!(surface.normal, Vec3::new(0.0, 0.0, 1.0));
+            assert_eq!(surface.texture_coords, uv);
+            assert!(surface.front_face && surface.material.is_some());
+            assert!(surface.primitive.is_some());
+        }
+        assert!(mesh.surface_at((1.5, 0.5)).is_none());
+
+        // Without texture coordinates there is nothing to bake into
+        let bare = Mesh::parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n".as_bytes()).unwrap();
+        let bare = TriangleMesh::new(bare, TestMaterial::new()).unwrap();
+        assert!(!bare.has_texture_space());
+        assert!(bare.surface_at((0.1, 0.1)).is_none());
+    }
+
+    #[test]
+    fn test_convert_axes() {
+        let face = |mesh: &Mesh| {
+            let [a, b, c] = mesh.triangles[0].map(|corner| mesh.positions[corner.position]);
+            ((b - a).cross(&(c - a)).unit(), mesh.normals[0])
+        };
+        let convert = |up, handedness| {
+            let mut mesh = square();
+            mesh.convert_axes(AxisConvention { up, handedness });
+            mesh
+        };
+
+        assert_eq!(convert(UpAxis::Y, Handedness::Right), square());
+
+        // A floor facing up in a z-up file faces up here too
+        let mesh = convert(UpAxis::Z, Handedness::Right);
+        let (winding, normal) = face(&mesh);
+        assert!((winding - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+        assert!((normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+        let bounds = mesh.bounds().unwrap();
+        assert!((bounds.center() - Point3::new(0.5, 0.0, -0.5)).length() < 1e-12);
+
+        // Mirroring flips the face, and its winding follows its normal
+        for up in [UpAxis::Y, UpAxis::Z] {
+            let mirrored = convert(up, Handedness::Left);
+            let (winding, normal) = face(&mirrored);
+            assert!((winding - normal).length() < 1e-12, "{:?}", up);
+        }
+        let (winding, _) = face(&convert(UpAxis::Y, Handedness::Left));
+        assert_eq!(winding, Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(AxisConvention::default().transform(), Transform::IDENTITY);
+    }
+}
@@ -0,0 +1,97 @@
+//! The GGX microfacet distribution, for rough reflections and refractions.
+//!
+//! A rough surface is modeled as tiny mirror facets whose normals follow the GGX
+//! distribution. Reflections are sampled by picking a facet visible from the
+//! incoming direction and mirroring off it, which never wastes samples on facets
+//! facing away. Directions are in a local frame whose z axis is the surface normal.
+//!
+//! Only single reflections off the facets are modeled, so light that would bounce
+//! between facets is lost unless the material gives it back.
+
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+
+/// Converts a perceptual roughness from 0 to 1 into the width of the GGX distribution.
+#[inline]
+pub fn alpha(roughness: f64) -> f64 {
+    roughness * roughness
+}
+
+/// Picks a facet normal visible from `wo` with GGX width `alpha`, placed by the
+/// uniform numbers `u`.
+///
+/// Facets are picked in proportion to how much of them `wo` sees, following
+/// Heitz, "Sampling the GGX Distribution of Visible Normals" (2018).
+pub fn sample_visible_normal(wo: &Vec3, alpha: f64, u: (f64, f64)) -> Vec3 {
+    // Stretch the view so the distribution becomes a hemisphere
+    let view = Vec3::new(alpha * wo.x(), alpha * wo.y(), wo.z()).unit();
+    let length_squared = view.x() * view.x() + view.y() * view.y();
+    let t1 = if length_squared > 0.0 {
+        Vec3::new(-view.y(), view.x(), 0.0) / length_squared.sqrt()
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let t2 = view.cross(&t1);
+
+    // A point on the disk, squeezed towards the part of it the view sees
+    let r = u.0.sqrt();
+    let phi = 2.0 * PI * u.1;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + view.z());
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let normal = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * view;
+
+    // Unstretch back to the surface
+    Vec3::new(alpha * normal.x(), alpha * normal.y(), normal.z().max(1e-6)).unit()
+}
+
+/// Fraction of the facets seen from `w` that are not hidden by other facets.
+#[inline]
+pub fn smith_g1(w: &Vec3, alpha: f64) -> f64 {
+    let cos2 = w.z() * w.z();
+    if cos2 <= 0.0 {
+        return 0.0;
+    }
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    2.0 / (1.0 + (1.0 + alpha * alpha * tan2).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::{random_double, seed_thread_rng};
+
+    #[test]
+    fn test_visible_normals_face_the_view() {
+        seed_thread_rng(5);
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        for roughness in [0.1, 0.5, 1.0] {
+            for _ in 0..1000 {
+                let u = (random_double(), random_double());
+                let normal = sample_visible_normal(&wo, alpha(roughness), u);
+                assert!((normal.length() - 1.0).abs() < 1e-9);
+                assert!(normal.z() > 0.0 && normal.dot(&wo) > 0.0, "{:?}", normal);
+            }
+        }
+
+        // Smooth surfaces have their facets lined up with the surface
+        let normal = sample_visible_normal(&wo, alpha(0.01), (0.3, 0.7));
+        assert!(normal.z() > 0.999);
+    }
+
+    #[test]
+    fn test_smith_g1() {
+        // Nothing hides facets seen head on, and more of them hide towards grazing
+        let head_on = Vec3::new(0.0, 0.0, 1.0);
+        let oblique = Vec3::new(0.8, 0.0, 0.6);
+        let grazing = Vec3::new(0.999, 0.0, 0.045).unit();
+        for roughness in [0.1, 0.5, 1.0] {
+            let a = alpha(roughness);
+            assert!((smith_g1(&head_on, a) - 1.0).abs() < 1e-12);
+            assert!(smith_g1(&oblique, a) < 1.0);
+            assert!(smith_g1(&grazing, a) < smith_g1(&oblique, a));
+        }
+        assert!(smith_g1(&grazing, alpha(0.1)) > smith_g1(&grazing, alpha(1.0)));
+        assert_eq!(smith_g1(&Vec3::new(1.0, 0.0, 0.0), 0.5), 0.0);
+    }
+}
@@ -0,0 +1,69 @@
+use crate::vec3::Vec3;
+
+/// An orthonormal basis built around a single axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Onb {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Onb {
+    /// Creates a basis whose `w` axis points along `n`.
+    pub fn new(n: &Vec3) -> Self {
+        let w = n.unit();
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(&a).unit();
+        let u = w.cross(&v);
+        Self { u, v, w }
+    }
+
+    /// Transforms a vector from basis coordinates to world coordinates.
+    #[inline]
+    pub fn transform(&self, v: &Vec3) -> Vec3 {
+        v.x() * self.u + v.y() * self.v + v.z() * self.w
+    }
+
+    /// Transforms a vector from world coordinates to basis coordinates.
+    #[inline]
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(v.dot(&self.u), v.dot(&self.v), v.dot(&self.w))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_onb_is_orthonormal() {
+        for n in [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 2.0, -3.0),
+        ] {
+            let onb = Onb::new(&n);
+            assert!((onb.w - n.unit()).near_zero());
+            assert!(onb.u.dot(&onb.v).abs() < 1e-12);
+            assert!(onb.u.dot(&onb.w).abs() < 1e-12);
+            assert!(onb.v.dot(&onb.w).abs() < 1e-12);
+            assert!((onb.u.length() - 1.0).abs() < 1e-12);
+            assert!((onb.v.length() - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_onb_transform() {
+        let onb = Onb::new(&Vec3::new(0.0, 2.0, 0.0));
+        let world = onb.transform(&Vec3::new(0.0, 0.0, 1.0));
+        assert!((world - Vec3::new(0.0, 1.0, 0.0)).near_zero());
+
+        let v = Vec3::new(0.3, -1.2, 2.0);
+        assert!((onb.transform(&onb.to_local(&v)) - v).near_zero());
+    }
+}
@@ -0,0 +1,362 @@
+//! Overrides of a scene's parameters from the command line.
+//!
+//! `--set camera.vertical_fov=35 --set materials.glass.refraction_index=1.45`
+//! changes a scene without editing it, for parameter sweeps and scripts. A key is
+//! the path to a setting in the scene file format, its parts naming fields, or
+//! indexing arrays as in `objects.2.radius`, so anything a scene file sets can be
+//! overridden, and materials are found by their names. The value is JSON, such as
+//! `[0, 2, 8]`, though `0,2,8` is read as an array and other text as a string.
+//!
+//! Scenes built in code have no file, so only their `camera` settings can be
+//! overridden. A sweep steps one key evenly over a range, so a grid of renders can
+//! compare its values side by side.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// One scene parameter set from the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Override {
+    /// Path to the setting, such as `materials.glass.refraction_index`
+    pub key: String,
+    /// The value as it was written
+    pub value: String,
+}
+
+impl Override {
+    pub fn new(key: &str, value: impl fmt::Display) -> Self {
+        Override {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    /// Returns true if this override sets a camera parameter rather than changing
+    /// the objects or their materials.
+    pub fn changes_camera(&self) -> bool {
+        self.key.starts_with("camera.")
+    }
+
+    /// The value read as JSON, or as an array or string it is shorthand for.
+    #[cfg(feature = "scene")]
+    pub fn json(&self) -> serde_json::Value {
+        if let Ok(value) = serde_json::from_str(&self.value) {
+            return value;
+        }
+        if self.value.contains(',')
+            && let Ok(value) = serde_json::from_str(&format!("[{}]", self.value))
+        {
+            return value;
+        }
+        serde_json::Value::String(self.value.clone())
+    }
+
+    /// Sets this override's value in the JSON `document` of a scene file.
+    ///
+    /// The setting itself may be one the file leaves out, as may a top level
+    /// section such as `camera`, but everything else the key passes through must
+    /// be in the file, so a misspelt material name is reported rather than
+    /// defining a new material.
+    #[cfg(feature = "scene")]
+    pub fn patch(&self, document: &mut serde_json::Value) -> Result<(), String> {
+        use serde_json::Value;
+
+        let parts: Vec<&str> = self.key.split('.').collect();
+        let missing = |depth: usize| {
+            format!(
+                "cannot set {}: the scene has no {}",
+                self.key,
+                parts[..=depth].join(".")
+            )
+        };
+        let index = |part: &str, items: &Vec<Value>| {
+            part.parse::<usize>()
+                .ok()
+                .filter(|&index| index < items.len())
+        };
+
+        let (last, parents) = parts.split_last().expect("keys are checked when parsed");
+        let mut target = document;
+        for (depth, part) in parents.iter().enumerate() {
+            target = match target {
+                Value::Object(fields) => {
+                    if depth == 0 {
+                        fields
+                            .entry(*part)
+                            .or_insert_with(|| Value::Object(Default::default()))
+                    } else {
+                        fields.get_mut(*part).ok_or_else(|| missing(depth))?
+                    }
+                }
+                Value::Array(items) => {
+                    let index = index(part, items).ok_or_else(|| missing(depth))?;
+                    &mut items[index]
+                }
+                _ => return Err(missing(depth)),
+            };
+        }
+        match target {
+            Value::Object(fields) => {
+                fields.insert(last.to_string(), self.json());
+            }
+            Value::Array(items) => {
+                let index = index(last, items).ok_or_else(|| missing(parents.len()))?;
+                items[index] = self.json();
+            }
+            _ => return Err(missing(parents.len())),
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Override {
+    type Err = String;
+
+    /// Parses `KEY=VALUE`, such as `camera.look_from=0,2,8`.
+    fn from_str(setting: &str) -> Result<Self, String> {
+        let (key, value) = setting
+            .split_once('=')
+            .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", setting))?;
+        if key.split('.').any(str::is_empty) {
+            return Err(format!("invalid scene parameter '{}'", key));
+        }
+        if value.is_empty() {
+            return Err(format!("missing value for {}", key));
+        }
+        Ok(Override::new(key, value))
+    }
+}
+
+impl fmt::Display for Override {
+    /// Formats the override as the `KEY=VALUE` it is parsed from.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+/// Sets each of `overrides` in turn in the JSON `document` of a scene file.
+#[cfg(feature = "scene")]
+pub fn patch(document: &mut serde_json::Value, overrides: &[Override]) -> Result<(), String> {
+    overrides
+        .iter()
+        .try_for_each(|setting| setting.patch(document))
+}
+
+/// A scene parameter stepped evenly over a range, parsed from `KEY=START..END:STEPS`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sweep {
+    /// Each step's setting as `KEY=VALUE`, for naming its render, with its override
+    pub steps: Vec<(String, Override)>,
+}
+
+impl FromStr for Sweep {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid sweep '{}'; expected KEY=START..END:STEPS", spec);
+        let (key, range) = spec.split_once('=').ok_or_else(invalid)?;
+        let (range, steps) = range.split_once(':').ok_or_else(invalid)?;
+        let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+        let (Ok(start), Ok(end), Ok(steps)) = (
+            start.parse::<f64>(),
+            end.parse::<f64>(),
+            steps.parse::<u32>(),
+        ) else {
+            return Err(invalid());
+        };
+        if steps == 0 {
+            return Err(invalid());
+        }
+
+        let steps = (0..steps)
+            .map(|i| {
+                let t = if steps > 1 {
+                    i as f64 / (steps - 1) as f64
+                } else {
+                    0.0
+                };
+                // Rounding keeps names like 0.3 rather than 0.30000000000000004
+                let value = ((start + (end - start) * t) * 1e9).round() / 1e9;
+                let setting = format!("{}={}", key, value);
+                setting.parse().map(|step| (setting, step))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Sweep { steps })
+    }
+}
+
+/// Every combination of one step from each sweep, the first sweep varying slowest.
+pub fn grid(sweeps: &[Sweep]) -> Vec<Vec<(String, Override)>> {
+    sweeps.iter().fold(vec![Vec::new()], |combinations, sweep| {
+        combinations
+            .iter()
+            .flat_map(|combination| {
+                sweep.steps.iter().map(move |step| {
+                    let mut combination = combination.clone();
+                    combination.push(step.clone());
+                    combination
+                })
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_overrides() {
+        assert_eq!(
+            "camera.vertical_fov=35".parse(),
+            Ok(Override::new("camera.vertical_fov", 35))
+        );
+        assert_eq!(
+            "camera.look_from=0,2,-8".parse(),
+            Ok(Override::new("camera.look_from", "0,2,-8"))
+        );
+        assert!("camera.vertical_fov".parse::<Override>().is_err());
+        assert!("camera.vertical_fov=".parse::<Override>().is_err());
+        assert!("camera..fov=35".parse::<Override>().is_err());
+
+        for setting in [
+            "camera.look_at=0,1.5,-2",
+            "objects.2.radius=0.5",
+            "materials.glass.refraction_index=1.45",
+        ] {
+            assert_eq!(setting.parse::<Override>().unwrap().to_string(), setting);
+        }
+        assert!(Override::new("camera.max_depth", 8).changes_camera());
+        assert!(!Override::new("materials.glass.thin", true).changes_camera());
+    }
+
+    #[cfg(feature = "scene")]
+    #[test]
+    fn test_values() {
+        use serde_json::json;
+
+        let value = |text: &str| Override::new("camera.look_at", text).json();
+        assert_eq!(value("35"), json!(35));
+        assert_eq!(value("[0, 2, 8]"), json!([0, 2, 8]));
+        assert_eq!(value("0,2,-8"), json!([0, 2, -8]));
+        assert_eq!(value("true"), json!(true));
+        assert_eq!(value("tree"), json!("tree"));
+        assert_eq!(value("gold,silver"), json!("gold,silver"));
+    }
+
+    #[cfg(feature = "scene")]
+    #[test]
+    fn test_patch() {
+        use serde_json::json;
+
+        let mut document = json!({
+            "materials": {
+                "glass": { "type": "dielectric", "refraction_index": 1.5 },
+                "water": { "type": "dielectric", "refraction_index": 1.33 }
+            },
+            "objects": [{ "type": "sphere", "radius": 1, "material": "glass" }]
+        });
+        let overrides: Vec<Override> = [
+            "materials.glass.refraction_index=1.45",
+            "materials.water.tint=0.8,0.9,1",
+            "objects.0.radius=2",
+            "camera.vertical_fov=35",
+        ]
+        .iter()
+        .map(|setting| setting.parse().unwrap())
+        .collect();
+        patch(&mut document, &overrides).unwrap();
+        assert_eq!(
+            document,
+            json!({
+                "materials": {
+                    "glass": { "type": "dielectric", "refraction_index": 1.45 },
+                    "water": { "type": "dielectric", "refraction_index": 1.33, "tint": [0.8, 0.9, 1] }
+                },
+                "objects": [{ "type": "sphere", "radius": 2, "material": "glass" }],
+                "camera": { "vertical_fov": 35 }
+            })
+        );
+
+        for (setting, error) in [
+            (
+                "materials.glas.refraction_index=1.4",
+                "cannot set materials.glas.refraction_index: the scene has no materials.glas",
+            ),
+            (
+                "objects.1.radius=2",
+                "cannot set objects.1.radius: the scene has no objects.1",
+            ),
+            (
+                "objects.0.radius.x=2",
+                "cannot set objects.0.radius.x: the scene has no objects.0.radius.x",
+            ),
+        ] {
+            let setting: Override = setting.parse().unwrap();
+            assert_eq!(setting.patch(&mut document), Err(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_parse_sweep() {
+        let sweep: Sweep = "materials.metal.roughness=0..1:11".parse().unwrap();
+        assert_eq!(sweep.steps.len(), 11);
+        assert_eq!(
+            sweep.steps[0],
+            (
+                "materials.metal.roughness=0".to_string(),
+                Override::new("materials.metal.roughness", 0)
+            )
+        );
+        assert_eq!(
+            sweep.steps[3],
+            (
+                "materials.metal.roughness=0.3".to_string(),
+                Override::new("materials.metal.roughness", 0.3)
+            )
+        );
+        assert_eq!(
+            sweep.steps[10].1,
+            Override::new("materials.metal.roughness", 1)
+        );
+
+        let samples: Sweep = "camera.samples_per_pixel=4..16:3".parse().unwrap();
+        assert_eq!(
+            samples.steps[1].1,
+            Override::new("camera.samples_per_pixel", 10)
+        );
+
+        assert!("camera.vertical_fov=10..20".parse::<Sweep>().is_err());
+        assert!("camera.vertical_fov=10..20:0".parse::<Sweep>().is_err());
+        assert!("camera..fov=10..20:3".parse::<Sweep>().is_err());
+    }
+
+    #[test]
+    fn test_grid() {
+        let sweeps: Vec<Sweep> = [
+            "camera.vertical_fov=20..40:3",
+            "materials.glass.refraction_index=1..2:2",
+        ]
+        .iter()
+        .map(|spec| spec.parse().unwrap())
+        .collect();
+        let combinations = grid(&sweeps);
+        assert_eq!(combinations.len(), 6);
+        let names: Vec<&str> = combinations[1]
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "camera.vertical_fov=20",
+                "materials.glass.refraction_index=2"
+            ]
+        );
+        assert_eq!(
+            combinations[5][0].1,
+            Override::new("camera.vertical_fov", 40)
+        );
+        assert_eq!(grid(&[]), vec![Vec::new()]);
+    }
+}
@@ -0,0 +1,52 @@
+//! Data parallelism through rayon, or plain iterators on the calling thread when
+//! the `parallel` feature is off, so the renderer builds without a thread pool.
+//!
+//! Without the feature, `into_par_iter`, `par_iter` and `par_iter_mut` return the
+//! standard sequential iterators, which share the adapters the renderer uses, and
+//! `join` runs its two closures one after the other.
+
+#[cfg(all(feature = "parallel", feature = "scene"))]
+pub(crate) use rayon::join;
+#[cfg(feature = "parallel")]
+pub(crate) use rayon::prelude::*;
+
+#[cfg(not(feature = "parallel"))]
+pub(crate) use sequential::*;
+
+#[cfg(not(feature = "parallel"))]
+mod sequential {
+    /// Stands in for rayon's function of the same name.
+    #[cfg(feature = "scene")]
+    pub(crate) fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA,
+        B: FnOnce() -> RB,
+    {
+        (a(), b())
+    }
+
+    /// Stands in for rayon's trait of the same name.
+    pub(crate) trait IntoParallelIterator: IntoIterator + Sized {
+        fn into_par_iter(self) -> Self::IntoIter {
+            self.into_iter()
+        }
+    }
+
+    impl<I: IntoIterator> IntoParallelIterator for I {}
+
+    /// Stands in for rayon's borrowing iterators over slices.
+    pub(crate) trait ParallelSlice<T> {
+        fn par_iter(&self) -> std::slice::Iter<'_, T>;
+        fn par_iter_mut(&mut self) -> std::slice::IterMut<'_, T>;
+    }
+
+    impl<T> ParallelSlice<T> for [T] {
+        fn par_iter(&self) -> std::slice::Iter<'_, T> {
+            self.iter()
+        }
+
+        fn par_iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+            self.iter_mut()
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! A record of every bounce of a single camera path, for debugging the integrator.
+
+use crate::color::Color;
+use crate::material::{Material, Scatter};
+use crate::point3::Point3;
+use crate::vec3::Vec3;
+use std::fmt;
+
+/// What happened to a path at a surface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathEvent {
+    /// Scattered diffusely, after sampling the lights
+    Diffuse,
+    /// Reflected back to the side the path arrived from
+    Reflect,
+    /// Transmitted through the surface
+    Transmit,
+    /// Scattered inside a medium, after sampling the lights
+    Scatter,
+    /// Absorbed, ending the path
+    Absorb,
+}
+
+/// Why a path stopped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathEnd {
+    /// Left the scene and picked up the background
+    Escaped,
+    /// Absorbed by a surface, or dropped by the NaN guard
+    Absorbed,
+    /// Reached the camera's maximum depth
+    DepthLimit,
+}
+
+/// One surface interaction along a path.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathVertex {
+    pub position: Point3,
+    /// The shading normal, facing the side the path arrived from
+    pub normal: Vec3,
+    pub material: Option<Material>,
+    pub event: PathEvent,
+    /// Product of the attenuations of the earlier bounces
+    pub throughput: Color,
+    /// Light added to the pixel at this vertex, already weighted by the throughput
+    pub contribution: Color,
+}
+
+/// The full history of one camera sample.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathTrace {
+    pub pixel: (u32, u32),
+    pub sample: u32,
+    pub vertices: Vec<PathVertex>,
+    pub end: PathEnd,
+    /// Light gathered from the background when the path escaped
+    pub background: Color,
+    /// The sample's final color before it is averaged into the pixel
+    pub radiance: Color,
+}
+
+impl fmt::Display for PathTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Pixel ({}, {}) sample {}: radiance {}",
+            self.pixel.0, self.pixel.1, self.sample, self.radiance
+        )?;
+        for (bounce, vertex) in self.vertices.iter().enumerate() {
+            writeln!(
+                f,
+                "  {}: {:?} {} at {} normal {}, throughput {}, gathered {}",
+                bounce,
+                vertex.event,
+                vertex
+                    .material
+                    .as_ref()
+                    .map_or("no material", Material::name),
+                *vertex.position,
+                vertex.normal,
+                vertex.throughput,
+                vertex.contribution
+            )?;
+        }
+        match self.end {
+            PathEnd::Escaped => write!(f, "  Escaped, gathering {}", self.background),
+            PathEnd::Absorbed => write!(f, "  Absorbed"),
+            PathEnd::DepthLimit => write!(f, "  Reached the depth limit"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Dielectric;
+
+    #[test]
+    fn test_display() {
+        let trace = PathTrace {
+            pixel: (3, 4),
+            sample: 1,
+            vertices: vec![PathVertex {
+                position: Point3::new(0.0, 0.0, -1.0),
+                normal: Vec3::new(0.0, 0.0, 1.0),
+                material: Some(Dielectric::new(1.5)),
+                event: PathEvent::Transmit,
+                throughput: Color::new(1.0, 1.0, 1.0),
+                contribution: Color::new(0.0, 0.0, 0.0),
+            }],
+            end: PathEnd::Escaped,
+            background: Color::new(0.5, 0.5, 0.5),
+            radiance: Color::new(0.5, 0.5, 0.5),
+        };
+
+        let text = trace.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("Pixel (3, 4) sample 1"));
+        assert!(lines[1].starts_with("  0: Transmit dielectric at"));
+        assert!(lines[2].starts_with("  Escaped"));
+    }
+}
@@ -0,0 +1,318 @@
+//! Probability densities over directions, for importance sampling scattered rays.
+//!
+//! A [`Pdf`] both draws directions and reports the density with which it draws a
+//! given one, per unit solid angle. Dividing a sample's contribution by that density
+//! keeps the estimate unbiased whatever the distribution, so the camera mixes a
+//! material's own distribution with one aimed at the emissive objects in the scene
+//! and spends fewer paths missing small lights.
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable, UNIFORM_SPHERE_PDF, uniform_sphere_direction};
+use crate::interval::Interval;
+use crate::onb::Onb;
+use crate::point3::Point3;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use rand::{Rng, RngCore};
+use std::f64::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+/// A distribution of directions.
+pub trait Pdf {
+    /// Returns the density, per unit solid angle, with which `generate` picks `direction`.
+    fn value(&self, direction: &Vec3) -> f64;
+
+    /// Returns a random unit direction drawn from the distribution.
+    fn generate(&self, rng: &mut dyn RngCore) -> Vec3;
+}
+
+/// Directions weighted by their cosine to an axis, as a diffuse surface scatters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CosinePdf {
+    basis: Onb,
+}
+
+impl CosinePdf {
+    /// Creates a distribution around the normal `w`.
+    pub fn new(w: &Vec3) -> Self {
+        Self { basis: Onb::new(w) }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let cosine = self.basis.to_local(&direction.unit()).z();
+        cosine.max(0.0) / PI
+    }
+
+    fn generate(&self, rng: &mut dyn RngCore) -> Vec3 {
+        let (r1, r2): (f64, f64) = (rng.random(), rng.random());
+        let phi = 2.0 * PI * r1;
+        let r = r2.sqrt();
+        let local = Vec3::new(phi.cos() * r, phi.sin() * r, (1.0 - r2).sqrt());
+        self.basis.transform(&local)
+    }
+}
+
+/// Directions spread evenly over the whole sphere, as a fog scatters.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SpherePdf;
+
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: &Vec3) -> f64 {
+        UNIFORM_SPHERE_PDF
+    }
+
+    fn generate(&self, rng: &mut dyn RngCore) -> Vec3 {
+        uniform_sphere_direction((rng.random(), rng.random()))
+    }
+}
+
+/// The distribution a material's `scatter` draws directions from, for materials
+/// that scatter diffusely.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScatterPdf {
+    Cosine(CosinePdf),
+    Sphere(SpherePdf),
+}
+
+impl Pdf for ScatterPdf {
+    #[inline]
+    fn value(&self, direction: &Vec3) -> f64 {
+        match self {
+            ScatterPdf::Cosine(pdf) => pdf.value(direction),
+            ScatterPdf::Sphere(pdf) => pdf.value(direction),
+        }
+    }
+
+    #[inline]
+    fn generate(&self, rng: &mut dyn RngCore) -> Vec3 {
+        match self {
+            ScatterPdf::Cosine(pdf) => pdf.generate(rng),
+            ScatterPdf::Sphere(pdf) => pdf.generate(rng),
+        }
+    }
+}
+
+/// Directions from `origin` towards random points on an object, such as a light.
+pub struct HittablePdf<'a> {
+    object: &'a dyn Hittable,
+    origin: Point3,
+}
+
+impl<'a> HittablePdf<'a> {
+    /// Creates a distribution aimed at `object` as seen from `origin`.
+    pub fn new(object: &'a dyn Hittable, origin: Point3) -> Self {
+        Self { object, origin }
+    }
+}
+
+impl Pdf for HittablePdf<'_> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.object.pdf_value(&self.origin, direction)
+    }
+
+    fn generate(&self, rng: &mut dyn RngCore) -> Vec3 {
+        self.object.random(&self.origin, rng)
+    }
+}
+
+/// An even mix of two distributions, drawing from either with equal probability.
+pub struct MixturePdf<'a> {
+    pdfs: [&'a dyn Pdf; 2],
+}
+
+impl<'a> MixturePdf<'a> {
+    pub fn new(first: &'a dyn Pdf, second: &'a dyn Pdf) -> Self {
+        Self {
+            pdfs: [first, second],
+        }
+    }
+}
+
+impl Pdf for MixturePdf<'_> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        0.5 * (self.pdfs[0].value(direction) + self.pdfs[1].value(direction))
+    }
+
+    fn generate(&self, rng: &mut dyn RngCore) -> Vec3 {
+        if rng.random::<bool>() {
+            self.pdfs[0].generate(rng)
+        } else {
+            self.pdfs[1].generate(rng)
+        }
+    }
+}
+
+/// The emissive objects the camera aims scattered rays at.
+///
+/// The objects are shared with the world, which they must also be placed in to be
+/// seen. Sampling picks one of them with equal probability.
+#[derive(Clone, Default)]
+pub struct LightObjects(Vec<Arc<dyn Hittable>>);
+
+impl fmt::Debug for LightObjects {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LightObjects {{ len: {} }}", self.0.len())
+    }
+}
+
+impl LightObjects {
+    pub fn push(&mut self, object: Arc<dyn Hittable>) {
+        self.0.push(object);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Hittable for LightObjects {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let mut closest = ray_t;
+        let mut hit = None;
+        for object in &self.0 {
+            if let Some(hit_record) = object.hit(r, closest) {
+                closest = Interval::new(ray_t.min(), hit_record.t);
+                hit = Some(hit_record);
+            }
+        }
+        hit
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.0.iter().try_fold(None, |bbox: Option<Aabb>, object| {
+            let object_box = object.bounding_box(time0, time1)?;
+            Some(Some(bbox.map_or(object_box, |bbox| {
+                Aabb::surrounding(&bbox, &object_box)
+            })))
+        })?
+    }
+
+    fn is_sampleable(&self) -> bool {
+        !self.0.is_empty() && self.0.iter().all(|object| object.is_sampleable())
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.0.is_empty() {
+            return 0.0;
+        }
+        let total: f64 = self
+            .0
+            .iter()
+            .map(|object| object.pdf_value(origin, direction))
+            .sum();
+        total / self.0.len() as f64
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        if self.0.is_empty() {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+        let index = rng.random_range(0..self.0.len());
+        self.0[index].random(origin, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::SphereBuilder;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    /// Averages `value / pdf.value` over directions drawn from `pdf`, estimating
+    /// the integral of `value` over the sphere.
+    fn estimate(pdf: &dyn Pdf, value: impl Fn(&Vec3) -> f64, samples: u32) -> f64 {
+        let mut rng = StdRng::seed_from_u64(3);
+        (0..samples)
+            .map(|_| {
+                let direction = pdf.generate(&mut rng);
+                value(&direction) / pdf.value(&direction)
+            })
+            .sum::<f64>()
+            / samples as f64
+    }
+
+    fn sphere(center: Point3, radius: f64) -> Arc<dyn Hittable> {
+        Arc::new(
+            SphereBuilder::new()
+                .center(center)
+                .radius(radius)
+                .material(Lambertian::clay())
+                .build()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_cosine_pdf() {
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let pdf = CosinePdf::new(&normal);
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let direction = pdf.generate(&mut rng);
+            assert!((direction.length() - 1.0).abs() < 1e-9);
+            assert!(direction.dot(&normal) >= 0.0);
+        }
+        assert!((pdf.value(&normal) - 1.0 / PI).abs() < 1e-12);
+        assert_eq!(pdf.value(&-normal), 0.0);
+
+        // The cosine over the hemisphere integrates to pi
+        let integral = estimate(&SpherePdf, |d| d.dot(&normal).max(0.0), 100_000);
+        assert!((integral - PI).abs() < 0.02, "integrated {}", integral);
+    }
+
+    #[test]
+    fn test_mixture_pdf_integrates_to_one() {
+        let lights = {
+            let mut lights = LightObjects::default();
+            lights.push(sphere(Point3::new(0.0, 3.0, 0.0), 0.5));
+            lights.push(sphere(Point3::new(2.0, 1.0, -1.0), 0.25));
+            lights
+        };
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let cosine = CosinePdf::new(&Vec3::new(0.0, 1.0, 0.0));
+        let towards_lights = HittablePdf::new(&lights, origin);
+        let mixture = MixturePdf::new(&towards_lights, &cosine);
+
+        let integral = estimate(&SpherePdf, |d| mixture.value(d), 200_000);
+        assert!((integral - 1.0).abs() < 0.02, "integrated {}", integral);
+
+        // Half the directions head for a light
+        let mut rng = StdRng::seed_from_u64(2);
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        let hits = (0..10_000)
+            .filter(|_| {
+                let direction = mixture.generate(&mut rng);
+                lights
+                    .hit(&Ray::new(origin, direction, 0.0), ray_t)
+                    .is_some()
+            })
+            .count();
+        assert!(hits > 4_800, "{} of 10000 directions hit a light", hits);
+    }
+
+    #[test]
+    fn test_light_objects() {
+        let mut lights = LightObjects::default();
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        assert!(lights.is_empty());
+        assert_eq!(lights.pdf_value(&origin, &Vec3::new(0.0, 1.0, 0.0)), 0.0);
+        assert!(lights.bounding_box(0.0, 1.0).is_none());
+
+        lights.push(sphere(Point3::new(0.0, 2.0, 0.0), 0.5));
+        lights.push(sphere(Point3::new(0.0, 4.0, 0.0), 0.5));
+        let ray = Ray::new(origin, Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let hit = lights
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!((hit.t - 1.5).abs() < 1e-9);
+
+        let bbox = lights.bounding_box(0.0, 1.0).unwrap();
+        assert!((bbox.axis_interval(1).max() - 4.5).abs() < 1e-9);
+        assert_eq!(format!("{:?}", lights), "LightObjects { len: 2 }");
+    }
+}
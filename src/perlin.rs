@@ -0,0 +1,146 @@
+//! Perlin gradient noise, as used by procedural textures.
+//!
+//! Space is divided into unit cells whose corners are given pseudo-random unit
+//! gradient vectors, picked by hashing the corner's coordinates through three
+//! permutation tables. The noise at a point blends the dot products of the eight
+//! surrounding gradients with the offsets to the point, trilinearly with Hermite
+//! smoothing, so it is smooth everywhere and zero on the lattice.
+
+use crate::point3::Point3;
+use crate::vec3::Vec3;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::mem;
+
+/// Number of gradients and entries in each permutation table.
+const POINT_COUNT: usize = 256;
+
+/// A Perlin noise generator, fully determined by the seed it was created from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Perlin {
+    gradients: [Vec3; POINT_COUNT],
+    perm_x: [u8; POINT_COUNT],
+    perm_y: [u8; POINT_COUNT],
+    perm_z: [u8; POINT_COUNT],
+}
+
+impl Perlin {
+    /// Creates the noise generator for `seed`.
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = std::array::from_fn(|_| {
+            Vec3::new(
+                rng.random_range(-1.0..1.0),
+                rng.random_range(-1.0..1.0),
+                rng.random_range(-1.0..1.0),
+            )
+            .unit()
+        });
+        Self {
+            gradients,
+            perm_x: permutation(&mut rng),
+            perm_y: permutation(&mut rng),
+            perm_z: permutation(&mut rng),
+        }
+    }
+
+    /// Returns the noise at `p`, between -1 and 1.
+    pub fn noise(&self, p: &Point3) -> f64 {
+        let cell = [p.x().floor(), p.y().floor(), p.z().floor()];
+        let offset = Vec3::new(p.x() - cell[0], p.y() - cell[1], p.z() - cell[2]);
+        // Wrapping to the table size makes the lattice repeat every 256 units
+        let corner = cell.map(|c| (c as i64).rem_euclid(POINT_COUNT as i64) as usize);
+
+        let mut sum = 0.0;
+        for i in 0..2 {
+            for j in 0..2 {
+                for k in 0..2 {
+                    let hash = self.perm_x[(corner[0] + i) % POINT_COUNT]
+                        ^ self.perm_y[(corner[1] + j) % POINT_COUNT]
+                        ^ self.perm_z[(corner[2] + k) % POINT_COUNT];
+                    let gradient = self.gradients[hash as usize];
+                    let (i, j, k) = (i as f64, j as f64, k as f64);
+                    let weight = offset - Vec3::new(i, j, k);
+                    sum += blend(i, offset.x())
+                        * blend(j, offset.y())
+                        * blend(k, offset.z())
+                        * gradient.dot(&weight);
+                }
+            }
+        }
+        sum
+    }
+
+    /// Returns the sum of `depth` octaves of the absolute noise at `p`, each at
+    /// twice the frequency and half the amplitude of the last.
+    ///
+    /// The result is at least zero and rarely above one.
+    pub fn turbulence(&self, p: &Point3, depth: u32) -> f64 {
+        let mut sum = 0.0;
+        let mut point = *p;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            sum += weight * self.noise(&point).abs();
+            weight *= 0.5;
+            point = Point3::from(point.as_vec3() * 2.0);
+        }
+        sum
+    }
+
+    /// Number of bytes the generator's tables occupy.
+    pub fn memory_size(&self) -> usize {
+        mem::size_of::<Perlin>()
+    }
+}
+
+/// Returns a random ordering of `0..POINT_COUNT`.
+fn permutation(rng: &mut StdRng) -> [u8; POINT_COUNT] {
+    let mut table = std::array::from_fn(|i| i as u8);
+    table.shuffle(rng);
+    table
+}
+
+/// Weight of the lattice corner at `corner` (0 or 1) along an axis for a point
+/// `t` of the way across the cell, smoothed so the noise has no creases.
+#[inline]
+fn blend(corner: f64, t: f64) -> f64 {
+    let smooth = t * t * (3.0 - 2.0 * t);
+    corner * smooth + (1.0 - corner) * (1.0 - smooth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise_is_smooth_and_zero_on_the_lattice() {
+        let perlin = Perlin::new(7);
+        assert_eq!(perlin, Perlin::new(7));
+        assert_ne!(perlin, Perlin::new(8));
+
+        assert_eq!(perlin.noise(&Point3::new(3.0, -2.0, 5.0)), 0.0);
+        let mut previous = perlin.noise(&Point3::new(0.5, 0.5, 0.5));
+        for step in 1..100 {
+            let p = Point3::new(0.5 + step as f64 * 0.01, 0.5, 0.5);
+            let value = perlin.noise(&p);
+            assert!((-1.0..=1.0).contains(&value));
+            assert!((value - previous).abs() < 0.05, "jump at {:?}", p);
+            previous = value;
+        }
+
+        // The lattice repeats every 256 units, including at negative coordinates
+        let p = Point3::new(-0.3, 1.7, 2.2);
+        let shifted = Point3::new(p.x() + 256.0, p.y(), p.z() - 256.0);
+        assert!((perlin.noise(&p) - perlin.noise(&shifted)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_turbulence() {
+        let perlin = Perlin::new(1);
+        let p = Point3::new(1.3, 2.7, -0.4);
+        assert_eq!(perlin.turbulence(&p, 1), perlin.noise(&p).abs());
+        assert!(perlin.turbulence(&p, 7) >= perlin.turbulence(&p, 1));
+        assert_eq!(perlin.turbulence(&p, 0), 0.0);
+    }
+}
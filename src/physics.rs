@@ -0,0 +1,211 @@
+//! A small rigid-body simulation of spheres bouncing on a ground plane.
+//!
+//! Spheres fall under gravity and bounce off the ground and off each other with
+//! a fixed restitution. Collisions are resolved with impulses along the line
+//! between centers, so spheres neither spin nor slide; that is enough to animate
+//! generated scenes with believable bounces. Motion recorded by another physics
+//! engine can drive the same renders: all a renderer needs is where each sphere
+//! is at the start and end of every frame.
+
+use crate::point3::Point3;
+use crate::vec3::Vec3;
+
+/// Acceleration due to gravity at the Earth's surface, in meters per second squared.
+pub const STANDARD_GRAVITY: f64 = 9.81;
+/// Fraction of the approaching speed two bodies separate with unless told otherwise.
+const DEFAULT_RESTITUTION: f64 = 0.7;
+
+/// A sphere taking part in the simulation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Body {
+    pub position: Point3,
+    pub velocity: Vec3,
+    pub radius: f64,
+    /// Fixed bodies are obstacles: they never move, as if infinitely heavy
+    pub fixed: bool,
+}
+
+impl Body {
+    /// Creates a sphere that moves, starting at `position` with `velocity`.
+    pub fn dynamic(position: Point3, velocity: Vec3, radius: f64) -> Self {
+        Self {
+            position,
+            velocity,
+            radius,
+            fixed: false,
+        }
+    }
+
+    /// Creates a sphere that stays at `position` for others to bounce off.
+    pub fn fixed(position: Point3, radius: f64) -> Self {
+        Self {
+            position,
+            velocity: Vec3::new(0.0, 0.0, 0.0),
+            radius,
+            fixed: true,
+        }
+    }
+
+    /// Returns one over the mass of a sphere of unit density, or zero if fixed.
+    fn inverse_mass(&self) -> f64 {
+        if self.fixed {
+            0.0
+        } else {
+            1.0 / self.radius.powi(3)
+        }
+    }
+}
+
+/// Spheres falling onto the horizontal plane `y = ground`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Simulation {
+    bodies: Vec<Body>,
+    gravity: Vec3,
+    ground: f64,
+    restitution: f64,
+}
+
+impl Simulation {
+    /// Creates a simulation of `bodies` under standard gravity above the plane `y = 0`.
+    pub fn new(bodies: Vec<Body>) -> Self {
+        Self {
+            bodies,
+            gravity: Vec3::new(0.0, -STANDARD_GRAVITY, 0.0),
+            ground: 0.0,
+            restitution: DEFAULT_RESTITUTION,
+        }
+    }
+
+    /// Sets the fraction of their approaching speed bodies bounce back with, clamped
+    /// to [0, 1] from completely inelastic to perfectly elastic.
+    pub fn restitution(mut self, restitution: f64) -> Self {
+        self.restitution = restitution.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn bodies(&self) -> &[Body] {
+        &self.bodies
+    }
+
+    /// Returns where every body is, in the order they were given.
+    pub fn positions(&self) -> Vec<Point3> {
+        self.bodies.iter().map(|body| body.position).collect()
+    }
+
+    /// Advances the simulation by `duration` seconds in `steps` equal steps.
+    pub fn advance(&mut self, duration: f64, steps: u32) {
+        let dt = duration / steps.max(1) as f64;
+        for _ in 0..steps.max(1) {
+            self.step(dt);
+        }
+    }
+
+    /// Advances the simulation by one step of `dt` seconds.
+    ///
+    /// Velocities are updated before positions (semi-implicit Euler), which keeps
+    /// bounces from gaining energy, and then contacts are resolved.
+    pub fn step(&mut self, dt: f64) {
+        for body in self.bodies.iter_mut().filter(|body| !body.fixed) {
+            body.velocity = body.velocity + self.gravity * dt;
+            body.position = body.position + body.velocity * dt;
+        }
+        for i in 0..self.bodies.len() {
+            for j in i + 1..self.bodies.len() {
+                self.collide(i, j);
+            }
+        }
+        for body in self.bodies.iter_mut().filter(|body| !body.fixed) {
+            let floor = self.ground + body.radius;
+            if body.position.y() < floor {
+                let p = body.position;
+                body.position = Point3::new(p.x(), floor, p.z());
+                if body.velocity.y() < 0.0 {
+                    body.velocity[1] = -body.velocity.y() * self.restitution;
+                }
+            }
+        }
+    }
+
+    /// Separates bodies `i` and `j` if they overlap and bounces them apart if they
+    /// are approaching.
+    fn collide(&mut self, i: usize, j: usize) {
+        let (a, b) = (self.bodies[i], self.bodies[j]);
+        let inverse_mass = a.inverse_mass() + b.inverse_mass();
+        if inverse_mass == 0.0 {
+            return;
+        }
+        let offset = b.position - a.position;
+        let distance = offset.length();
+        let overlap = a.radius + b.radius - distance;
+        if overlap <= 0.0 || distance == 0.0 {
+            return;
+        }
+
+        let normal = offset / distance;
+        // Each body gives way in proportion to its share of the inverse mass
+        let push = normal * (overlap / inverse_mass);
+        self.bodies[i].position = a.position + push * -a.inverse_mass();
+        self.bodies[j].position = b.position + push * b.inverse_mass();
+
+        let approach = (b.velocity - a.velocity).dot(&normal);
+        if approach < 0.0 {
+            let impulse = normal * (-(1.0 + self.restitution) * approach / inverse_mass);
+            self.bodies[i].velocity = a.velocity - impulse * a.inverse_mass();
+            self.bodies[j].velocity = b.velocity + impulse * b.inverse_mass();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ball_bounces_lower_each_time() {
+        let ball = Body::dynamic(Point3::new(0.0, 2.0, 0.0), Vec3::new(0.0, 0.0, 0.0), 0.2);
+        let mut simulation = Simulation::new(vec![ball]).restitution(0.5);
+
+        // Record the highest point reached after each bounce
+        let mut peaks = Vec::new();
+        let mut previous = simulation.bodies()[0];
+        for _ in 0..3000 {
+            simulation.step(0.001);
+            let body = simulation.bodies()[0];
+            assert!(body.position.y() >= 0.2);
+            if previous.velocity.y() > 0.0 && body.velocity.y() <= 0.0 {
+                peaks.push(body.position.y());
+            }
+            previous = body;
+        }
+
+        assert!(peaks.len() >= 2, "{:?}", peaks);
+        // Bouncing back at half speed reaches a quarter of the height fallen
+        assert!((peaks[0] - 0.65).abs() < 0.02, "{:?}", peaks);
+        assert!(
+            peaks.windows(2).all(|pair| pair[1] < pair[0]),
+            "{:?}",
+            peaks
+        );
+    }
+
+    #[test]
+    fn test_collisions_exchange_momentum() {
+        let a = Body::dynamic(Point3::new(-1.0, 5.0, 0.0), Vec3::new(2.0, 0.0, 0.0), 0.5);
+        let b = Body::dynamic(Point3::new(1.0, 5.0, 0.0), Vec3::new(-2.0, 0.0, 0.0), 0.5);
+        let mut simulation = Simulation::new(vec![a, b]).restitution(1.0);
+        simulation.advance(0.5, 500);
+        let [a, b] = [simulation.bodies()[0], simulation.bodies()[1]];
+        // Equal spheres meeting head on swap their velocities
+        assert!((a.velocity.x() + 2.0).abs() < 1e-9, "{:?}", a);
+        assert!((b.velocity.x() - 2.0).abs() < 1e-9, "{:?}", b);
+        assert!(a.position.x() < b.position.x() - 1.0);
+
+        // A fixed sphere does not move when something lands on it
+        let obstacle = Body::fixed(Point3::new(0.0, 1.0, 0.0), 1.0);
+        let ball = Body::dynamic(Point3::new(0.0, 3.0, 0.0), Vec3::new(0.0, 0.0, 0.0), 0.2);
+        let mut simulation = Simulation::new(vec![obstacle, ball]);
+        simulation.advance(1.0, 1000);
+        assert_eq!(simulation.bodies()[0], obstacle);
+        assert!(simulation.bodies()[1].position.y() > 2.0);
+    }
+}
@@ -0,0 +1,206 @@
+//! Strategies for scattering objects over the ground in generated scenes.
+//!
+//! Poisson-disk placement follows Bridson, "Fast Poisson Disk Sampling in Arbitrary
+//! Dimensions" (SIGGRAPH 2007 sketch): new points are tried in an annulus around
+//! accepted ones, and a background grid with one point per cell makes the distance
+//! check constant time. A density map thins the accepted points where it is low.
+
+use crate::utilities::random_double;
+use std::f64::consts::PI;
+
+/// Candidates tried around an accepted point before it is retired.
+const POISSON_ATTEMPTS: u32 = 30;
+
+/// How a generated scene spreads its objects over the ground.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Placement {
+    /// One point at a random offset within each cell of a grid; neighbors can overlap
+    #[default]
+    JitteredGrid,
+    /// Random points no closer together than a minimum distance
+    PoissonDisk,
+    /// A sunflower spiral stepping by the golden angle, evenly spread without randomness
+    GoldenSpiral,
+}
+
+/// Returns one point per unit cell of `min`..`max`, offset by up to `jitter` of the cell.
+pub fn jittered_grid(min: (f64, f64), max: (f64, f64), jitter: f64) -> Vec<(f64, f64)> {
+    let mut points = Vec::new();
+    let mut x = min.0;
+    while x < max.0 {
+        let mut z = min.1;
+        while z < max.1 {
+            points.push((x + jitter * random_double(), z + jitter * random_double()));
+            z += 1.0;
+        }
+        x += 1.0;
+    }
+    points
+}
+
+/// Returns random points in `min`..`max` that are at least `min_distance` apart.
+///
+/// `density` maps a position to the probability in [0, 1] of keeping a point there,
+/// so sparse regions can be painted into the layout. Pass `|_, _| 1.0` to fill the
+/// whole area.
+pub fn poisson_disk(
+    min: (f64, f64),
+    max: (f64, f64),
+    min_distance: f64,
+    density: impl Fn(f64, f64) -> f64,
+) -> Vec<(f64, f64)> {
+    let cell_size = min_distance / 2.0_f64.sqrt();
+    let columns = ((max.0 - min.0) / cell_size).ceil().max(1.0) as usize;
+    let rows = ((max.1 - min.1) / cell_size).ceil().max(1.0) as usize;
+    let cell = |(x, z): (f64, f64)| {
+        let column = (((x - min.0) / cell_size) as usize).min(columns - 1);
+        let row = (((z - min.1) / cell_size) as usize).min(rows - 1);
+        (column, row)
+    };
+
+    // Thinned points stay on the grid too, so the spacing holds everywhere
+    let mut grid: Vec<Option<(f64, f64)>> = vec![None; columns * rows];
+    let mut active = vec![(
+        min.0 + (max.0 - min.0) * random_double(),
+        min.1 + (max.1 - min.1) * random_double(),
+    )];
+    let mut points = Vec::new();
+    let mut accept = |point: (f64, f64), grid: &mut [Option<(f64, f64)>]| {
+        let (column, row) = cell(point);
+        grid[row * columns + column] = Some(point);
+        if random_double() < density(point.0, point.1) {
+            points.push(point);
+        }
+    };
+    accept(active[0], &mut grid);
+
+    while !active.is_empty() {
+        let slot = ((random_double() * active.len() as f64) as usize).min(active.len() - 1);
+        let center = active[slot];
+        let candidate = (0..POISSON_ATTEMPTS).find_map(|_| {
+            let radius = min_distance * (1.0 + random_double());
+            let angle = 2.0 * PI * random_double();
+            let point = (
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+            );
+            let inside = point.0 >= min.0 && point.0 < max.0 && point.1 >= min.1 && point.1 < max.1;
+            (inside && is_clear(&grid, columns, rows, cell(point), point, min_distance))
+                .then_some(point)
+        });
+        match candidate {
+            Some(point) => {
+                accept(point, &mut grid);
+                active.push(point);
+            }
+            None => {
+                active.swap_remove(slot);
+            }
+        }
+    }
+
+    points
+}
+
+/// Whether no point already on the grid lies within `min_distance` of `point`.
+fn is_clear(
+    grid: &[Option<(f64, f64)>],
+    columns: usize,
+    rows: usize,
+    (column, row): (usize, usize),
+    point: (f64, f64),
+    min_distance: f64,
+) -> bool {
+    let neighbors = |index: usize, count: usize| index.saturating_sub(2)..(index + 3).min(count);
+    neighbors(row, rows).all(|r| {
+        neighbors(column, columns).all(|c| match grid[r * columns + c] {
+            Some(other) => {
+                let (dx, dz) = (other.0 - point.0, other.1 - point.1);
+                dx * dx + dz * dz >= min_distance * min_distance
+            }
+            None => true,
+        })
+    })
+}
+
+/// Returns `count` points on a sunflower spiral centered on `center`.
+///
+/// Point `i` sits at radius `spacing * sqrt(i + 0.5)`, turned by the golden angle from
+/// the previous one, so the points cover a disk at an even density with about
+/// `spacing` between neighbors.
+pub fn golden_spiral(center: (f64, f64), count: usize, spacing: f64) -> Vec<(f64, f64)> {
+    let golden_angle = PI * (3.0 - 5.0_f64.sqrt());
+    (0..count)
+        .map(|i| {
+            let radius = spacing * (i as f64 + 0.5).sqrt();
+            let angle = i as f64 * golden_angle;
+            (
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::seed_thread_rng;
+
+    fn closest_pair(points: &[(f64, f64)]) -> f64 {
+        let mut closest = f64::INFINITY;
+        for (i, a) in points.iter().enumerate() {
+            for b in &points[i + 1..] {
+                closest = closest.min(((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt());
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn test_jittered_grid_covers_every_cell() {
+        let points = jittered_grid((-2.0, -2.0), (2.0, 2.0), 0.9);
+        assert_eq!(points.len(), 16);
+        assert!(
+            points
+                .iter()
+                .all(|&(x, z)| (-2.0..2.0).contains(&x) && (-2.0..2.0).contains(&z))
+        );
+    }
+
+    #[test]
+    fn test_poisson_disk_keeps_its_distance() {
+        seed_thread_rng(3);
+        let points = poisson_disk((-8.0, -8.0), (8.0, 8.0), 0.5, |_, _| 1.0);
+        assert!(closest_pair(&points) >= 0.5);
+        assert!(
+            points
+                .iter()
+                .all(|&(x, z)| (-8.0..8.0).contains(&x) && (-8.0..8.0).contains(&z))
+        );
+        // A maximal packing fills the area far better than one point per unit cell
+        assert!(points.len() > 400, "placed {} points", points.len());
+    }
+
+    #[test]
+    fn test_poisson_disk_follows_density() {
+        seed_thread_rng(5);
+        let points = poisson_disk((0.0, 0.0), (10.0, 10.0), 0.5, |x, _| {
+            if x < 5.0 { 1.0 } else { 0.0 }
+        });
+        assert!(!points.is_empty());
+        assert!(points.iter().all(|&(x, _)| x < 5.0));
+    }
+
+    #[test]
+    fn test_golden_spiral_is_evenly_spread() {
+        let points = golden_spiral((1.0, 2.0), 200, 0.5);
+        assert_eq!(points.len(), 200);
+        assert!(closest_pair(&points) > 0.4);
+        let farthest = points
+            .iter()
+            .map(|&(x, z)| ((x - 1.0).powi(2) + (z - 2.0).powi(2)).sqrt())
+            .fold(0.0, f64::max);
+        assert!((farthest - 0.5 * 199.5_f64.sqrt()).abs() < 1e-9);
+    }
+}
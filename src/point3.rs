@@ -1,8 +1,9 @@
 use crate::vec3::Vec3;
-use std::ops::Deref;
-use std::ops::{Add, Sub};
+use core::ops::Deref;
+use core::ops::{Add, Sub};
 
 #[derive(Copy, Clone, Debug, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Point3(Vec3);
 
 impl Point3 {
@@ -0,0 +1,212 @@
+//! Image-space effects applied to a finished framebuffer.
+
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+
+/// Exponential distance fog driven by the depth AOV.
+///
+/// A cheap depth cue: each pixel is blended towards `color` by
+/// `1 - exp(-density * depth)`, so the background, which is infinitely far
+/// away, takes on the fog color entirely. Where the framebuffer has an alpha AOV,
+/// pixels only partly covered by a surface are fogged as that surface over the
+/// coverage and as background over the rest, so silhouettes blend into the fog.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fog {
+    color: Color,
+    density: f64,
+}
+
+impl Fog {
+    /// Creates a fog of the given color. `density` is the extinction per unit distance.
+    pub fn new(color: Color, density: f64) -> Self {
+        Self {
+            color,
+            density: density.max(0.0),
+        }
+    }
+
+    /// Fraction of the fog color seen through `depth` units of fog.
+    #[inline]
+    fn amount(&self, depth: f64) -> f64 {
+        if self.density == 0.0 {
+            return 0.0;
+        }
+        1.0 - (-self.density * depth).exp()
+    }
+
+    /// Blends every pixel towards the fog color according to its depth and coverage.
+    ///
+    /// Framebuffers without a depth AOV are left untouched.
+    pub fn apply(&self, framebuffer: &mut Framebuffer) {
+        let Some(depth) = framebuffer.depth() else {
+            return;
+        };
+        let alpha = framebuffer.alpha();
+        let amounts: Vec<f64> = depth
+            .iter()
+            .enumerate()
+            .map(|(index, &depth)| {
+                // Depth is that of the covered part; the rest sees the background
+                let coverage = alpha.map_or(1.0, |alpha| alpha[index].clamp(0.0, 1.0));
+                self.amount(depth) * coverage + self.amount(f64::INFINITY) * (1.0 - coverage)
+            })
+            .collect();
+        for (pixel, amount) in framebuffer.pixels_mut().iter_mut().zip(amounts) {
+            *pixel = *pixel * (1.0 - amount) + self.color * amount;
+        }
+    }
+}
+
+/// Where a highlight rolloff's shoulder begins.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RolloffStart {
+    /// At a fixed linear luminance, where 1.0 is display white
+    Luminance(f64),
+    /// At the luminance exceeded by this percentage of pixels' brightness, in [0, 100]
+    Percentile(f64),
+}
+
+/// A soft shoulder that compresses highlights instead of clipping them to white.
+///
+/// Pixels darker than the start are left alone. Brighter ones have their luminance
+/// mapped onto the rest of the range below white by an exponential curve that
+/// leaves the start with unit slope, and keep their hue. A start at or above white
+/// leaves the image unchanged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HighlightRolloff {
+    start: RolloffStart,
+}
+
+impl HighlightRolloff {
+    pub fn new(start: RolloffStart) -> Self {
+        Self { start }
+    }
+
+    /// Returns the luminance the shoulder starts at for `framebuffer`.
+    fn threshold(&self, framebuffer: &Framebuffer) -> f64 {
+        match self.start {
+            RolloffStart::Luminance(luminance) => luminance,
+            RolloffStart::Percentile(percentile) => {
+                let mut luminances: Vec<f64> =
+                    framebuffer.pixels().iter().map(Color::luminance).collect();
+                if luminances.is_empty() {
+                    return f64::INFINITY;
+                }
+                luminances.sort_by(f64::total_cmp);
+                let rank = percentile.clamp(0.0, 100.0) / 100.0 * (luminances.len() - 1) as f64;
+                luminances[rank.round() as usize]
+            }
+        }
+    }
+
+    /// Compresses the highlights of every pixel.
+    pub fn apply(&self, framebuffer: &mut Framebuffer) {
+        let start = self.threshold(framebuffer).max(0.0);
+        if start >= 1.0 {
+            return;
+        }
+        let headroom = 1.0 - start;
+        for pixel in framebuffer.pixels_mut() {
+            let luminance = pixel.luminance();
+            if luminance > start {
+                let compressed = start + headroom * (1.0 - (-(luminance - start) / headroom).exp());
+                *pixel *= compressed / luminance;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fog_by_depth() {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let mut framebuffer = Framebuffer::new(3, 1, vec![black; 3]).with_depth(vec![
+            0.0,
+            2.0f64.ln(),
+            f64::INFINITY,
+        ]);
+        Fog::new(Color::new(1.0, 1.0, 1.0), 1.0).apply(&mut framebuffer);
+
+        let pixels = framebuffer.pixels_mut();
+        // No fog in front of the camera
+        assert_eq!(pixels[0], black);
+        // Half way to the fog color at depth ln(2) with unit density
+        assert_eq!(pixels[1], Color::new(0.5, 0.5, 0.5));
+        // The background is entirely fogged
+        assert_eq!(pixels[2], Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_fog_by_coverage() {
+        let black = Color::new(0.0, 0.0, 0.0);
+        // A silhouette half covered by a surface right at the camera, and one whose
+        // samples all missed
+        let mut framebuffer = Framebuffer::new(2, 1, vec![black; 2])
+            .with_depth(vec![0.0, f64::INFINITY])
+            .with_alpha(vec![0.5, 0.0]);
+        Fog::new(Color::new(1.0, 1.0, 1.0), 1.0).apply(&mut framebuffer);
+
+        let pixels = framebuffer.pixels();
+        assert_eq!(pixels[0], Color::new(0.5, 0.5, 0.5));
+        assert_eq!(pixels[1], Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_zero_density_fog_does_nothing() {
+        let color = Color::new(0.2, 0.4, 0.6);
+        let mut framebuffer = Framebuffer::new(1, 1, vec![color]).with_depth(vec![f64::INFINITY]);
+        Fog::new(Color::new(1.0, 1.0, 1.0), 0.0).apply(&mut framebuffer);
+        assert_eq!(framebuffer.pixels_mut()[0], color);
+
+        // Without depth there is nothing to fog
+        let mut framebuffer = Framebuffer::new(1, 1, vec![color]);
+        Fog::new(Color::new(1.0, 1.0, 1.0), 1.0).apply(&mut framebuffer);
+        assert_eq!(framebuffer.pixels_mut()[0], color);
+    }
+
+    #[test]
+    fn test_highlight_rolloff() {
+        let shadow = Color::new(0.1, 0.2, 0.05);
+        let highlight = Color::new(8.0, 4.0, 2.0);
+        let mut framebuffer = Framebuffer::new(2, 1, vec![shadow, highlight]);
+        HighlightRolloff::new(RolloffStart::Luminance(0.5)).apply(&mut framebuffer);
+
+        let pixels = framebuffer.pixels();
+        assert_eq!(pixels[0], shadow);
+        // The highlight lands just under white with its hue intact
+        let luminance = pixels[1].luminance();
+        assert!(luminance > 0.99 && luminance < 1.0, "{}", luminance);
+        assert!((pixels[1].r() / pixels[1].g() - 2.0).abs() < 1e-9);
+
+        // The curve is continuous at the start of the shoulder
+        let mut framebuffer = Framebuffer::new(1, 1, vec![Color::new(0.5001, 0.5001, 0.5001)]);
+        HighlightRolloff::new(RolloffStart::Luminance(0.5)).apply(&mut framebuffer);
+        assert!((framebuffer.pixels()[0].r() - 0.5001).abs() < 1e-7);
+    }
+
+    #[test]
+    fn test_highlight_rolloff_percentile() {
+        let pixels = (1..=10)
+            .map(|i| Color::new(1.0, 1.0, 1.0) * (i as f64 / 10.0))
+            .collect();
+        let framebuffer = Framebuffer::new(10, 1, pixels);
+        let rolloff = HighlightRolloff::new(RolloffStart::Percentile(50.0));
+        assert!((rolloff.threshold(&framebuffer) - 0.6).abs() < 1e-9);
+
+        let mut rolled = framebuffer.clone();
+        rolloff.apply(&mut rolled);
+        assert_eq!(rolled.pixels()[..6], framebuffer.pixels()[..6]);
+        assert!(rolled.pixels()[9].r() < 1.0);
+
+        // A shoulder starting above white has nothing to compress into
+        let mut unchanged = framebuffer.clone();
+        HighlightRolloff::new(RolloffStart::Luminance(1.5)).apply(&mut unchanged);
+        assert_eq!(unchanged, framebuffer);
+    }
+}
@@ -0,0 +1,601 @@
+//! Scene preprocessing run once the objects are created, before the BVH is built.
+
+use crate::aabb::Aabb;
+use crate::hittable::Hittable;
+use crate::material::Material;
+use crate::point3::Point3;
+use crate::texture::TextureEnum;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Overlaps shallower than this fraction of the larger object's size count as touching.
+const OVERLAP_TOLERANCE: f64 = 1e-6;
+
+/// Outcome of merging identical materials into shared instances.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DedupeReport {
+    /// Material references found on the scene's objects
+    pub materials: usize,
+    /// Distinct materials left after merging
+    pub unique: usize,
+    /// Approximate memory released by dropping the duplicates
+    pub bytes_saved: usize,
+}
+
+impl fmt::Display for DedupeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Deduplicated {} materials to {} unique, saving {:.1} KiB",
+            self.materials,
+            self.unique,
+            self.bytes_saved as f64 / 1024.0
+        )
+    }
+}
+
+/// Degenerate objects found in a scene.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DegenerateReport {
+    /// Index of each degenerate object in the scene's object list and why it is degenerate
+    pub objects: Vec<(usize, &'static str)>,
+    /// Index of each object that left out degenerate primitives as it was built,
+    /// such as triangles with no area in a mesh, and how many it left out
+    pub primitives: Vec<(usize, usize)>,
+    /// Whether the degenerate objects were removed from the scene
+    pub dropped: bool,
+}
+
+impl fmt::Display for DegenerateReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} degenerate objects",
+            if self.dropped { "Dropped" } else { "Kept" },
+            self.objects.len()
+        )?;
+        for (index, reason) in &self.objects {
+            write!(f, "\n    object {}: {}", index, reason)?;
+        }
+        for (index, count) in &self.primitives {
+            write!(
+                f,
+                "\n    object {}: left out {} degenerate primitives",
+                index, count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Two objects whose solids overlap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Intersection {
+    /// Indices of the objects in the scene's object list
+    pub objects: (usize, usize),
+    /// How far the objects would have to move apart to stop overlapping
+    pub depth: f64,
+    /// Whether the depth was measured between the true shapes rather than estimated
+    /// from bounding boxes
+    pub exact: bool,
+}
+
+/// Pairs of objects found interpenetrating each other.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IntersectionReport {
+    pub intersections: Vec<Intersection>,
+}
+
+impl fmt::Display for IntersectionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Found {} intersecting object pairs",
+            self.intersections.len()
+        )?;
+        for intersection in &self.intersections {
+            write!(
+                f,
+                "\n    objects {} and {}: overlap by {:.6}{}",
+                intersection.objects.0,
+                intersection.objects.1,
+                intersection.depth,
+                if intersection.exact {
+                    ""
+                } else {
+                    " (bounding boxes)"
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Image textures that could not be read, so render magenta.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextureReport {
+    /// Each unreadable image's path and why it could not be read
+    pub unreadable: Vec<(PathBuf, String)>,
+}
+
+impl fmt::Display for TextureReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not read {} textures", self.unreadable.len())?;
+        for (path, reason) in &self.unreadable {
+            write!(f, "\n    {}: {}", path.display(), reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Everything [`prepare`] found in a scene.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PreprocessReport {
+    pub degenerate: DegenerateReport,
+    pub intersections: IntersectionReport,
+    pub textures: TextureReport,
+    pub dedupe: DedupeReport,
+}
+
+impl fmt::Display for PreprocessReport {
+    /// Lists the degenerate and intersecting objects and unreadable textures, if
+    /// there were any, then the materials merged.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.degenerate.objects.is_empty() || !self.degenerate.primitives.is_empty() {
+            writeln!(f, "{}", self.degenerate)?;
+        }
+        if !self.intersections.intersections.is_empty() {
+            writeln!(f, "{}", self.intersections)?;
+        }
+        if !self.textures.unreadable.is_empty() {
+            writeln!(f, "{}", self.textures)?;
+        }
+        write!(f, "{}", self.dedupe)
+    }
+}
+
+/// Runs every preprocessing pass over a scene's objects, in the order the BVH
+/// needs them: degenerate objects are found first, dropped when `drop_degenerate`
+/// is set, so later passes never see them.
+pub fn prepare(objects: &mut Vec<Box<dyn Hittable>>, drop_degenerate: bool) -> PreprocessReport {
+    let degenerate = filter_degenerate(objects, drop_degenerate);
+    let intersections = find_intersections(objects);
+    let textures = find_unreadable_textures(objects);
+    let dedupe = dedupe_materials(objects);
+    PreprocessReport {
+        degenerate,
+        intersections,
+        textures,
+        dedupe,
+    }
+}
+
+/// Finds objects that would produce NaN hit records, removing them when `drop` is set.
+pub fn filter_degenerate(objects: &mut Vec<Box<dyn Hittable>>, drop: bool) -> DegenerateReport {
+    let report = DegenerateReport {
+        objects: objects
+            .iter()
+            .enumerate()
+            .filter_map(|(index, object)| Some((index, object.degenerate_reason()?)))
+            .collect(),
+        primitives: objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| (index, object.degenerate_primitives()))
+            .filter(|&(_, count)| count > 0)
+            .collect(),
+        dropped: drop,
+    };
+    if drop {
+        objects.retain(|object| object.degenerate_reason().is_none());
+    }
+    report
+}
+
+/// Finds pairs of objects whose solids overlap, which is rarely intended.
+///
+/// Spheres are compared exactly. Any other pair is compared by bounding boxes, which
+/// can report objects that only come close. Objects without bounds are skipped, as
+/// are volumes such as fog, which are meant to surround other objects.
+pub fn find_intersections(objects: &[Box<dyn Hittable>]) -> IntersectionReport {
+    let mut bounded: Vec<_> = objects
+        .iter()
+        .enumerate()
+        .filter(|(_, object)| !object.is_volume())
+        .filter_map(|(index, object)| {
+            Some((index, object.bounding_box(0.0, 1.0)?, object.solid_sphere()))
+        })
+        .collect();
+
+    // Sweep along x so only objects whose extents meet there are compared
+    bounded.sort_by(|a, b| {
+        a.1.axis_interval(0)
+            .min()
+            .total_cmp(&b.1.axis_interval(0).min())
+    });
+    let mut intersections = Vec::new();
+    for (i, (a, a_box, a_sphere)) in bounded.iter().enumerate() {
+        let end = a_box.axis_interval(0).max();
+        for (b, b_box, b_sphere) in bounded[i + 1..]
+            .iter()
+            .take_while(|(_, b_box, _)| b_box.axis_interval(0).min() < end)
+        {
+            let (depth, exact) = match (a_sphere, b_sphere) {
+                (Some((a_center, a_radius)), Some((b_center, b_radius))) => {
+                    (a_radius + b_radius - (*a_center - *b_center).length(), true)
+                }
+                (Some((center, radius)), None) => (radius - box_distance(b_box, center), false),
+                (None, Some((center, radius))) => (radius - box_distance(a_box, center), false),
+                (None, None) => (box_overlap(a_box, b_box), false),
+            };
+            let size = a_box.diagonal().length().max(b_box.diagonal().length());
+            if depth > OVERLAP_TOLERANCE * size {
+                intersections.push(Intersection {
+                    objects: (*a.min(b), *a.max(b)),
+                    depth,
+                    exact,
+                });
+            }
+        }
+    }
+
+    intersections.sort_by_key(|intersection| intersection.objects);
+    IntersectionReport { intersections }
+}
+
+/// Returns the distance from `point` to the closest point of `aabb`, zero inside it.
+fn box_distance(aabb: &Aabb, point: &Point3) -> f64 {
+    (0..3)
+        .map(|axis| {
+            let interval = aabb.axis_interval(axis);
+            let c = point.as_vec3()[axis];
+            (interval.min() - c)
+                .max(c - interval.max())
+                .max(0.0)
+                .powi(2)
+        })
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Returns how far two boxes overlap along the axis where they overlap least.
+fn box_overlap(a: &Aabb, b: &Aabb) -> f64 {
+    (0..3)
+        .map(|axis| {
+            let (a, b) = (a.axis_interval(axis), b.axis_interval(axis));
+            a.max().min(b.max()) - a.min().max(b.min())
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// Finds the image textures on `objects` that their cache has already failed to
+/// read, each image once.
+///
+/// Images not yet looked up are not read here; scene files decode theirs as they
+/// load, so every failure is known by then.
+pub fn find_unreadable_textures(objects: &mut [Box<dyn Hittable>]) -> TextureReport {
+    let mut report = TextureReport::default();
+    for object in objects.iter_mut() {
+        object.visit_materials(&mut |material| {
+            material.visit_textures(&mut |texture| {
+                if let TextureEnum::Image(image) = texture
+                    && let Some(reason) = image.failure()
+                {
+                    let path = image.path();
+                    if !report.unreadable.iter().any(|(seen, _)| *seen == path) {
+                        report.unreadable.push((path, reason));
+                    }
+                }
+            });
+        });
+    }
+    report
+}
+
+/// Replaces structurally identical materials on `objects` with one shared instance.
+///
+/// Materials are bucketed by their structural hash and compared for equality, so
+/// generated scenes that create the same material for many objects keep only one.
+pub fn dedupe_materials(objects: &mut [Box<dyn Hittable>]) -> DedupeReport {
+    let mut buckets: HashMap<u64, Vec<Arc<Material>>> = HashMap::new();
+    let mut report = DedupeReport::default();
+
+    for object in objects.iter_mut() {
+        object.visit_materials(&mut |material| {
+            report.materials += 1;
+
+            let mut hasher = DefaultHasher::new();
+            material.hash(&mut hasher);
+            let bucket = buckets.entry(hasher.finish()).or_default();
+
+            match bucket.iter().find(|shared| **shared == *material) {
+                Some(shared) if Arc::ptr_eq(shared, material) => {}
+                Some(shared) => {
+                    // Only the last reference frees the material itself
+                    if Arc::strong_count(material) == 1 {
+                        report.bytes_saved += material.memory_size();
+                    }
+                    *material = Arc::clone(shared);
+                }
+                None => {
+                    report.unique += 1;
+                    bucket.push(Arc::clone(material));
+                }
+            }
+        });
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::{Dielectric, Lambertian, Metal};
+    use crate::point3::Point3;
+    use crate::sphere::SphereBuilder;
+    use crate::texture::TextureEnum;
+
+    fn sphere(material: impl Into<Arc<Material>>) -> Box<dyn Hittable> {
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, 0.0))
+                .material(material)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    fn gray() -> Material {
+        Lambertian::new(Box::new(TextureEnum::SolidColor(
+            Color::new(0.5, 0.5, 0.5).into(),
+        )))
+    }
+
+    fn material_of(object: &mut Box<dyn Hittable>) -> Arc<Material> {
+        let mut found = None;
+        object.visit_materials(&mut |material| found = Some(Arc::clone(material)));
+        found.expect("object has a material")
+    }
+
+    fn sphere_at(center: Point3, radius: f64) -> Box<dyn Hittable> {
+        Box::new(
+            SphereBuilder::new()
+                .center(center)
+                .radius(radius)
+                .material(gray())
+                .build()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_degenerate_spheres_are_dropped() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let mut objects = vec![
+            sphere_at(origin, 1.0),
+            sphere_at(origin, 0.0),
+            sphere_at(origin, -1.0),
+            sphere_at(Point3::new(f64::NAN, 0.0, 0.0), 1.0),
+            Box::new(
+                SphereBuilder::new()
+                    .center(origin)
+                    .center_end(Point3::new(0.0, 1.0, 0.0))
+                    .time_range(0.5, 0.5)
+                    .material(gray())
+                    .build()
+                    .unwrap(),
+            ),
+        ];
+
+        let report = filter_degenerate(&mut objects, true);
+        assert_eq!(
+            report.objects,
+            vec![
+                (1, "radius is not a positive number"),
+                (2, "radius is not a positive number"),
+                (3, "non-finite center"),
+                (4, "empty time range"),
+            ]
+        );
+        assert_eq!(objects.len(), 1);
+        assert!(
+            report
+                .to_string()
+                .starts_with("Dropped 4 degenerate objects")
+        );
+    }
+
+    #[test]
+    fn test_degenerate_triangles_are_reported() {
+        use crate::mesh::{Mesh, TriangleMesh};
+
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 2 0 0\nf 1 2 3\nf 1 2 4\nf 1 1 3\n";
+        let mesh = TriangleMesh::new(Mesh::parse_obj(obj.as_bytes()).unwrap(), gray()).unwrap();
+        let mut objects = vec![
+            sphere_at(Point3::new(5.0, 0.0, 0.0), 1.0),
+            Box::new(mesh) as Box<dyn Hittable>,
+        ];
+
+        let report = filter_degenerate(&mut objects, true);
+        assert!(report.objects.is_empty());
+        assert_eq!(report.primitives, vec![(1, 2)]);
+        // The mesh keeps its good triangle, so it stays in the scene
+        assert_eq!(objects.len(), 2);
+        assert!(
+            report
+                .to_string()
+                .ends_with("object 1: left out 2 degenerate primitives")
+        );
+    }
+
+    #[test]
+    fn test_degenerate_spheres_can_be_kept() {
+        let mut objects = vec![sphere_at(Point3::new(0.0, 0.0, 0.0), 0.0)];
+        let report = filter_degenerate(&mut objects, false);
+        assert_eq!(report.objects.len(), 1);
+        assert_eq!(objects.len(), 1);
+    }
+
+    #[test]
+    fn test_prepare_drops_degenerate_objects_before_later_passes() {
+        let mut objects = vec![
+            sphere_at(Point3::new(0.0, 0.0, 0.0), 1.0),
+            sphere_at(Point3::new(0.0, 0.0, 0.0), 0.0),
+            sphere_at(Point3::new(1.5, 0.0, 0.0), 1.0),
+        ];
+
+        let report = prepare(&mut objects, true);
+        assert_eq!(objects.len(), 2);
+        assert_eq!(report.degenerate.objects.len(), 1);
+        // Indices after the drop, so the pair is the first and last sphere
+        let pairs: Vec<_> = report
+            .intersections
+            .intersections
+            .iter()
+            .map(|i| i.objects)
+            .collect();
+        assert_eq!(pairs, vec![(0, 1)]);
+        assert_eq!(report.dedupe.materials, 2);
+        assert_eq!(report.dedupe.unique, 1);
+        assert_eq!(report.to_string().lines().count(), 5);
+    }
+
+    #[test]
+    fn test_prepare_report_omits_empty_findings() {
+        let mut objects = vec![sphere_at(Point3::new(0.0, 0.0, 0.0), 1.0)];
+        let report = prepare(&mut objects, true);
+        assert_eq!(report.to_string(), report.dedupe.to_string());
+    }
+
+    #[test]
+    fn test_unreadable_textures_are_reported_once() {
+        use crate::texture::ImageTexture;
+        use crate::texture_cache::TextureCache;
+
+        let cache = Arc::new(TextureCache::default());
+        let missing = || {
+            Lambertian::new(Box::new(TextureEnum::Image(ImageTexture::new(
+                &cache,
+                "no/such/preprocess.ppm",
+            ))))
+        };
+        let mut objects = vec![sphere(missing()), sphere(missing()), sphere(gray())];
+        // Nothing has looked the image up yet
+        assert!(find_unreadable_textures(&mut objects).unreadable.is_empty());
+
+        cache.preload();
+        let report = find_unreadable_textures(&mut objects);
+        assert_eq!(report.unreadable.len(), 1);
+        assert_eq!(
+            report.unreadable[0].0,
+            PathBuf::from("no/such/preprocess.ppm")
+        );
+        assert!(report.to_string().starts_with("Could not read 1 textures"));
+    }
+
+    #[test]
+    fn test_identical_materials_are_shared() {
+        let mut objects = vec![
+            sphere(gray()),
+            sphere(Metal::new(Color::new(0.7, 0.6, 0.5), 0.1)),
+            sphere(gray()),
+            sphere(Metal::new(Color::new(0.7, 0.6, 0.5), 0.1)),
+            sphere(Dielectric::new(1.5)),
+        ];
+
+        let report = dedupe_materials(&mut objects);
+        assert_eq!(report.materials, 5);
+        assert_eq!(report.unique, 3);
+        assert_eq!(
+            report.bytes_saved,
+            gray().memory_size() + Metal::new(Color::new(0.7, 0.6, 0.5), 0.1).memory_size()
+        );
+
+        let first = material_of(&mut objects[0]);
+        let third = material_of(&mut objects[2]);
+        assert!(Arc::ptr_eq(&first, &third));
+    }
+
+    #[test]
+    fn test_different_materials_are_kept() {
+        let mut objects = vec![
+            sphere(Metal::new(Color::new(0.7, 0.6, 0.5), 0.1)),
+            sphere(Metal::new(Color::new(0.7, 0.6, 0.5), 0.2)),
+            sphere(Dielectric::new(1.5)),
+            sphere(Dielectric::new(1.33)),
+        ];
+
+        let report = dedupe_materials(&mut objects);
+        assert_eq!(report.unique, 4);
+        assert_eq!(report.bytes_saved, 0);
+    }
+
+    #[test]
+    fn test_already_shared_materials_save_nothing() {
+        let shared = Arc::new(gray());
+        let mut objects = vec![sphere(Arc::clone(&shared)), sphere(Arc::clone(&shared))];
+        drop(shared);
+
+        let report = dedupe_materials(&mut objects);
+        assert_eq!(report.materials, 2);
+        assert_eq!(report.unique, 1);
+        assert_eq!(report.bytes_saved, 0);
+    }
+
+    #[test]
+    fn test_overlapping_spheres_are_reported() {
+        let objects = vec![
+            // A ground sphere with one sphere resting on it and one sunk into it
+            sphere_at(Point3::new(0.0, -1000.0, 0.0), 1000.0),
+            sphere_at(Point3::new(0.0, 1.0, 0.0), 1.0),
+            sphere_at(Point3::new(5.0, 0.5, 0.0), 1.0),
+            sphere_at(Point3::new(1.5, 1.0, 0.0), 1.0),
+        ];
+
+        let report = find_intersections(&objects);
+        let pairs: Vec<_> = report.intersections.iter().map(|i| i.objects).collect();
+        assert_eq!(pairs, vec![(0, 2), (1, 3)]);
+        let overlap = report.intersections[1];
+        assert!(overlap.exact);
+        assert!((overlap.depth - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_moving_spheres_are_compared_by_bounds() {
+        let moving: Box<dyn Hittable> = Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, 0.0))
+                .center_end(Point3::new(2.0, 0.0, 0.0))
+                .time_range(0.0, 1.0)
+                .radius(0.5)
+                .material(gray())
+                .build()
+                .unwrap(),
+        );
+        let objects = vec![moving, sphere_at(Point3::new(2.0, 0.0, 0.8), 0.5)];
+
+        let report = find_intersections(&objects);
+        assert_eq!(report.intersections.len(), 1);
+        assert!(!report.intersections[0].exact);
+        assert!((report.intersections[0].depth - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_volumes_may_surround_objects() {
+        use crate::medium::ConstantMedium;
+
+        let fog: Box<dyn Hittable> = Box::new(ConstantMedium::new(
+            sphere_at(Point3::new(0.0, 0.0, 0.0), 5.0),
+            0.1,
+            gray(),
+        ));
+        let objects = vec![fog, sphere_at(Point3::new(1.0, 0.0, 0.0), 1.0)];
+        assert!(find_intersections(&objects).intersections.is_empty());
+    }
+}
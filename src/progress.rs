@@ -0,0 +1,507 @@
+//! Progress of long renders: bars on stderr when the `progress` feature is on, and
+//! reports to any [`ProgressReporter`] the camera is given, such as a
+//! [`WebhookReporter`] for watching a headless machine from elsewhere.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long a webhook may take to accept a connection or answer a request.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How far a render has got.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProgressUpdate {
+    /// Units of work finished
+    pub done: u64,
+    /// Units of work in the whole render
+    pub total: u64,
+    /// What the work is counted in, such as tiles
+    pub units: &'static str,
+    /// Time since the render started
+    pub elapsed: Duration,
+}
+
+impl ProgressUpdate {
+    /// The fraction of the work finished, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.done as f64 / self.total as f64
+        }
+    }
+
+    /// Estimates the time left from the pace so far, once any work is finished.
+    pub fn eta(&self) -> Option<Duration> {
+        (self.done > 0).then(|| {
+            let remaining = self.total.saturating_sub(self.done) as f64;
+            self.elapsed.mul_f64(remaining / self.done as f64)
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.done >= self.total
+    }
+
+    /// Writes the update as a JSON object, with times in seconds.
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"done":{},"total":{},"units":"{}","fraction":{},"elapsed_seconds":{},"eta_seconds":{}}}"#,
+            self.done,
+            self.total,
+            self.units,
+            self.fraction(),
+            self.elapsed.as_secs_f64(),
+            self.eta()
+                .map_or("null".to_string(), |eta| eta.as_secs_f64().to_string())
+        )
+    }
+}
+
+/// Receives a render's progress as it goes.
+pub trait ProgressReporter: Send + Sync {
+    /// Called as each unit of work finishes, on whichever thread finished it, so it
+    /// should return quickly.
+    fn report(&self, update: &ProgressUpdate);
+
+    /// Called once a render has reported its last update, returning what went
+    /// wrong passing its progress on, which the render attaches to its image.
+    fn finish(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// A shared reporter, which settings holding one can print.
+#[derive(Clone)]
+pub(crate) struct SharedReporter(pub(crate) Arc<dyn ProgressReporter>);
+
+impl fmt::Debug for SharedReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SharedReporter")
+    }
+}
+
+/// A bar counting completed units of work, which also tells any reporter.
+pub(crate) struct Progress {
+    #[cfg(feature = "progress")]
+    bar: indicatif::ProgressBar,
+    reporter: Option<Arc<dyn ProgressReporter>>,
+    done: AtomicU64,
+    total: u64,
+    units: &'static str,
+    start: Instant,
+}
+
+impl Progress {
+    /// Starts a bar that counts up to `len` of `units`, such as scanlines.
+    pub(crate) fn new(len: u64, units: &'static str, reporter: Option<&SharedReporter>) -> Self {
+        #[cfg(feature = "progress")]
+        let bar = {
+            let bar = indicatif::ProgressBar::new(len);
+            bar.set_style(
+                indicatif::ProgressStyle::default_bar()
+                    .template(&format!(
+                        "[{{elapsed_precise}}] [{{bar:80.cyan/blue}}] {{pos}}/{{len}} {} ({{eta}})",
+                        units
+                    ))
+                    .expect("Invalid progress bar template")
+                    .progress_chars("#>-"),
+            );
+            bar
+        };
+        let progress = Self {
+            #[cfg(feature = "progress")]
+            bar,
+            reporter: reporter.map(|reporter| reporter.0.clone()),
+            done: AtomicU64::new(0),
+            total: len,
+            units,
+            start: Instant::now(),
+        };
+        progress.report(0);
+        progress
+    }
+
+    /// Counts one more unit as done.
+    pub(crate) fn inc(&self) {
+        #[cfg(feature = "progress")]
+        self.bar.inc(1);
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        self.report(done);
+    }
+
+    pub(crate) fn finish(&self) {
+        #[cfg(feature = "progress")]
+        self.bar.finish_with_message("Rendering complete");
+    }
+
+    fn report(&self, done: u64) {
+        if let Some(reporter) = &self.reporter {
+            reporter.report(&ProgressUpdate {
+                done,
+                total: self.total,
+                units: self.units,
+                elapsed: self.start.elapsed(),
+            });
+        }
+    }
+}
+
+/// Posts progress as JSON to an `http://` URL, such as a chat or phone
+/// notification webhook, at most once per interval and whenever a render finishes.
+///
+/// Requests are sent from a thread of their own, so a slow or unreachable server
+/// never holds up rendering until it finishes, when the render waits for the last
+/// of them and notes any failures in its image's diagnostics. Dropping the
+/// reporter also waits for requests already queued. HTTPS is not supported, so
+/// forward through a local proxy to reach one.
+pub struct WebhookReporter {
+    sender: Mutex<Option<Sender<ProgressUpdate>>>,
+    worker: Option<JoinHandle<()>>,
+    interval: Duration,
+    /// When the last update was queued, if any has been
+    last_sent: Mutex<Option<Instant>>,
+    /// Updates queued for the worker so far
+    queued: AtomicU64,
+    /// What the worker has done with them, signalled as each is posted
+    delivery: Arc<(Mutex<Delivery>, Condvar)>,
+}
+
+/// The updates a webhook's worker has posted, and the failures not yet collected.
+#[derive(Default)]
+struct Delivery {
+    posted: u64,
+    failures: Vec<String>,
+}
+
+impl WebhookReporter {
+    /// Creates a reporter posting to `url` at most every `interval`.
+    ///
+    /// Fails if `url` is not an `http://` URL with a host.
+    pub fn new(url: &str, interval: Duration) -> io::Result<Self> {
+        let endpoint = Endpoint::parse(url)?;
+        let (sender, updates) = mpsc::channel();
+        let delivery = Arc::new((Mutex::new(Delivery::default()), Condvar::new()));
+        let worker = {
+            let delivery = delivery.clone();
+            thread::spawn(move || post_updates(&endpoint, updates, &delivery))
+        };
+        Ok(Self {
+            sender: Mutex::new(Some(sender)),
+            worker: Some(worker),
+            interval,
+            last_sent: Mutex::new(None),
+            queued: AtomicU64::new(0),
+            delivery,
+        })
+    }
+}
+
+impl ProgressReporter for WebhookReporter {
+    fn report(&self, update: &ProgressUpdate) {
+        // The locks only guard a timestamp, the queue's sender and counts, which a
+        // panic elsewhere cannot leave half updated, so a poisoned lock is used as is
+        {
+            let mut last_sent = self.last_sent.lock().unwrap_or_else(|p| p.into_inner());
+            let due = last_sent.is_none_or(|last| last.elapsed() >= self.interval);
+            if !due && !update.is_finished() {
+                return;
+            }
+            *last_sent = Some(Instant::now());
+        }
+        if let Some(sender) = &*self.sender.lock().unwrap_or_else(|p| p.into_inner()) {
+            // The worker only stops once the sender is dropped
+            if sender.send(*update).is_ok() {
+                self.queued.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Waits until every queued update has been posted, then returns the failures.
+    fn finish(&self) -> Vec<String> {
+        let (delivery, posted) = &*self.delivery;
+        let mut delivery = delivery.lock().unwrap_or_else(|p| p.into_inner());
+        while delivery.posted < self.queued.load(Ordering::SeqCst) {
+            delivery = posted.wait(delivery).unwrap_or_else(|p| p.into_inner());
+        }
+        std::mem::take(&mut delivery.failures)
+    }
+}
+
+impl Drop for WebhookReporter {
+    fn drop(&mut self) {
+        // Closing the queue lets the worker finish what is queued and stop
+        self.sender.lock().unwrap_or_else(|p| p.into_inner()).take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Where a webhook is served.
+#[derive(Clone, Debug, PartialEq)]
+struct Endpoint {
+    /// Host and port, as written in the URL
+    authority: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> io::Result<Self> {
+        let invalid = |reason: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid webhook URL '{}': {}", url, reason),
+            )
+        };
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| invalid("only http:// URLs are supported"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.strip_prefix('[') {
+            // An IPv6 address is bracketed, as its colons would read as a port
+            Some(bracketed) => {
+                let (host, rest) = bracketed
+                    .split_once(']')
+                    .ok_or_else(|| invalid("unclosed '['"))?;
+                let port = match rest.strip_prefix(':') {
+                    Some(port) => port.parse().map_err(|_| invalid("bad port"))?,
+                    None if rest.is_empty() => 80,
+                    None => return Err(invalid("bad port")),
+                };
+                (host, port)
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, port.parse().map_err(|_| invalid("bad port"))?),
+                None => (authority, 80),
+            },
+        };
+        if host.is_empty() {
+            return Err(invalid("no host"));
+        }
+        Ok(Self {
+            authority: authority.to_string(),
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// Posts `body` as JSON, failing unless the server answers with success.
+    fn post(&self, body: &str) -> io::Result<()> {
+        let address = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no address"))?;
+        let mut stream = TcpStream::connect_timeout(&address, WEBHOOK_TIMEOUT)?;
+        stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+        stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.authority,
+            body.len(),
+            body
+        )?;
+        stream.flush()?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "webhook answered '{}'",
+                status.trim()
+            ))),
+        }
+    }
+}
+
+/// Posts each update received until the reporter is dropped, recording only the
+/// first failure in a row so an unreachable server doesn't flood the diagnostics.
+fn post_updates(
+    endpoint: &Endpoint,
+    updates: Receiver<ProgressUpdate>,
+    delivery: &(Mutex<Delivery>, Condvar),
+) {
+    let mut failing = false;
+    for update in updates {
+        let result = endpoint.post(&update.to_json());
+        let (delivery, posted) = delivery;
+        let mut delivery = delivery.lock().unwrap_or_else(|p| p.into_inner());
+        match result {
+            Ok(()) => failing = false,
+            Err(e) if !failing => {
+                delivery.failures.push(e.to_string());
+                failing = true;
+            }
+            Err(_) => {}
+        }
+        delivery.posted += 1;
+        posted.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_progress_update() {
+        let update = ProgressUpdate {
+            done: 1,
+            total: 4,
+            units: "tiles",
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(update.fraction(), 0.25);
+        assert_eq!(update.eta(), Some(Duration::from_secs(6)));
+        assert!(!update.is_finished());
+        assert_eq!(
+            update.to_json(),
+            r#"{"done":1,"total":4,"units":"tiles","fraction":0.25,"elapsed_seconds":2,"eta_seconds":6}"#
+        );
+
+        let started = ProgressUpdate { done: 0, ..update };
+        assert_eq!(started.eta(), None);
+        assert!(started.to_json().ends_with(r#""eta_seconds":null}"#));
+    }
+
+    #[test]
+    fn test_endpoint_parse() {
+        assert_eq!(
+            Endpoint::parse("http://example.com:8080/hooks/render").unwrap(),
+            Endpoint {
+                authority: "example.com:8080".to_string(),
+                host: "example.com".to_string(),
+                port: 8080,
+                path: "/hooks/render".to_string(),
+            }
+        );
+        let bare = Endpoint::parse("http://localhost").unwrap();
+        assert_eq!((bare.port, bare.path.as_str()), (80, "/"));
+        assert!(Endpoint::parse("https://example.com").is_err());
+        assert!(Endpoint::parse("http://:80/").is_err());
+        assert!(Endpoint::parse("http://host:port/").is_err());
+    }
+
+    #[test]
+    fn test_endpoint_parse_ipv6() {
+        assert_eq!(
+            Endpoint::parse("http://[::1]:8000/").unwrap(),
+            Endpoint {
+                authority: "[::1]:8000".to_string(),
+                host: "::1".to_string(),
+                port: 8000,
+                path: "/".to_string(),
+            }
+        );
+        let bare = Endpoint::parse("http://[fe80::1]/hook").unwrap();
+        assert_eq!((bare.host.as_str(), bare.port), ("fe80::1", 80));
+        assert!(Endpoint::parse("http://[::1/").is_err());
+        assert!(Endpoint::parse("http://[::1]8000/").is_err());
+        assert!(Endpoint::parse("http://[]:80/").is_err());
+    }
+
+    #[test]
+    fn test_webhook_survives_poisoned_locks() {
+        // Nothing listens on the port, so queued updates fail quietly
+        let reporter = WebhookReporter::new("http://127.0.0.1:9/", Duration::ZERO).unwrap();
+        thread::scope(|scope| {
+            let poisoner = scope.spawn(|| {
+                let _clock = reporter.last_sent.lock().unwrap();
+                let _queue = reporter.sender.lock().unwrap();
+                panic!("poison the webhook's locks");
+            });
+            assert!(poisoner.join().is_err());
+        });
+        assert!(reporter.last_sent.is_poisoned() && reporter.sender.is_poisoned());
+
+        reporter.report(&ProgressUpdate {
+            done: 1,
+            total: 1,
+            units: "tiles",
+            elapsed: Duration::ZERO,
+        });
+        assert!(
+            reporter
+                .last_sent
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .is_some()
+        );
+        // Dropping still closes the queue, so the worker stops and is joined
+        drop(reporter);
+    }
+
+    #[test]
+    fn test_webhook_records_failures() {
+        let reporter = WebhookReporter::new("http://127.0.0.1:9/", Duration::ZERO).unwrap();
+        for done in 0..3 {
+            reporter.report(&ProgressUpdate {
+                done,
+                total: 2,
+                units: "tiles",
+                elapsed: Duration::ZERO,
+            });
+        }
+        // Only the first of a run of failures is kept, and only until collected
+        let failures = reporter.finish();
+        assert_eq!(failures.len(), 1, "{:?}", failures);
+        assert!(reporter.finish().is_empty());
+    }
+
+    #[test]
+    fn test_webhook_posts_progress() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/progress", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                // The body is the last thing sent, and ends in a brace
+                while !request.ends_with(b"}") {
+                    let read = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                stream
+                    .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                    .unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+
+        // Updates in between the first and the last come too soon to be sent
+        let reporter = WebhookReporter::new(&url, Duration::from_secs(3600)).unwrap();
+        let reporter = SharedReporter(Arc::new(reporter));
+        let progress = Progress::new(3, "tiles", Some(&reporter));
+        for _ in 0..3 {
+            progress.inc();
+        }
+        drop((progress, reporter));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /progress HTTP/1.1\r\n"));
+        assert!(requests[0].contains("Content-Type: application/json\r\n"));
+        assert!(requests[0].contains(r#"{"done":0,"total":3,"units":"tiles""#));
+        assert!(requests[1].contains(r#""done":3,"total":3"#));
+    }
+}
@@ -0,0 +1,433 @@
+//! Flat parallelograms, and axis-aligned boxes built from six of them.
+//!
+//! A [`Quad`] is textured from (0, 0) at its corner to (1, 1) at the opposite one,
+//! and a [`BoxObject`] gives each of its faces the same mapping, so walls, floors
+//! and blocks such as those of a Cornell box need no mesh.
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::point3::Point3;
+use crate::ray::{PrimitiveId, Ray};
+use crate::vec3::Vec3;
+use rand::{Rng, RngCore};
+use std::sync::Arc;
+
+/// Denominators smaller than this mean the ray runs parallel to the quad.
+const PARALLEL_EPSILON: f64 = 1e-12;
+/// Least thickness of a quad's bounding box, so boxes around quads lying in an
+/// axis plane are not empty.
+const MIN_BOX_THICKNESS: f64 = 1e-6;
+
+/// A parallelogram spanning `corner` to `corner + u + v`, facing along `u × v`.
+#[derive(Debug)]
+pub struct Quad {
+    corner: Point3,
+    u: Vec3,
+    v: Vec3,
+    /// `u × v` scaled so a point's coordinates along the edges are dot products with it
+    w: Vec3,
+    normal: Vec3,
+    /// Distance of the quad's plane from the origin along `normal`
+    d: f64,
+    area: f64,
+    material: Arc<Material>,
+    id: PrimitiveId,
+}
+
+/// A copy is a separate primitive, so it gets its own ID.
+impl Clone for Quad {
+    fn clone(&self) -> Self {
+        Quad::new(self.corner, self.u, self.v, Arc::clone(&self.material))
+    }
+}
+
+impl Quad {
+    /// Creates a quad with corner `corner` and edges `u` and `v` leaving it.
+    ///
+    /// The side `u × v` points to is the front, whose texture reads the right way
+    /// round; both sides can be hit.
+    pub fn new(corner: Point3, u: Vec3, v: Vec3, material: impl Into<Arc<Material>>) -> Self {
+        let cross = u.cross(&v);
+        let area = cross.length();
+        let normal = if area > 0.0 { cross / area } else { cross };
+        Self {
+            corner,
+            u,
+            v,
+            w: cross / cross.length_squared(),
+            normal,
+            d: normal.dot(&corner.as_vec3()),
+            area,
+            material: material.into(),
+            id: PrimitiveId::next(true),
+        }
+    }
+
+    /// Returns the ray parameter where `ray` crosses the quad within `ray_t`, and
+    /// the crossing's coordinates along the two edges.
+    fn intersect(&self, ray: &Ray, ray_t: Interval) -> Option<(f64, (f64, f64))> {
+        let denominator = self.normal.dot(ray.direction());
+        if denominator.abs() < PARALLEL_EPSILON {
+            return None;
+        }
+        let t = (self.d - self.normal.dot(&ray.origin().as_vec3())) / denominator;
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+
+        let planar = ray.at_time(t) - self.corner;
+        let alpha = self.w.dot(&planar.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar));
+        ((0.0..=1.0).contains(&alpha) && (0.0..=1.0).contains(&beta)).then_some((t, (alpha, beta)))
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        if r.excluded_primitive() == Some(self.id) {
+            return None;
+        }
+        let (t, texture_coords) = self.intersect(r, ray_t)?;
+        let mut hit_record = HitRecord {
+            t,
+            position: r.at_time(t),
+            material: Some(self.material.as_ref()),
+            texture_coords,
+            primitive: Some(self.id),
+            ..Default::default()
+        };
+        hit_record.set_face_normal(r, &self.normal);
+        hit_record.set_differentials(r, 0.0);
+        Some(hit_record)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        let bbox = [self.u, self.v, self.u + self.v]
+            .iter()
+            .fold(point_box(self.corner), |bbox, &edge| {
+                Aabb::surrounding(&bbox, &point_box(self.corner + edge))
+            });
+        let axis = |axis: usize| {
+            let interval = bbox.axis_interval(axis);
+            let pad = (MIN_BOX_THICKNESS - (interval.max() - interval.min())).max(0.0) / 2.0;
+            Interval::new(interval.min() - pad, interval.max() + pad)
+        };
+        Some(Aabb::new(axis(0), axis(1), axis(2)))
+    }
+
+    fn visit_materials(&mut self, visit: &mut dyn FnMut(&mut Arc<Material>)) {
+        visit(&mut self.material);
+    }
+
+    fn degenerate_reason(&self) -> Option<&'static str> {
+        (!(self.area > 0.0 && self.area.is_finite() && self.corner.is_finite()))
+            .then_some("quad has no area")
+    }
+
+    fn surface_at(&self, uv: (f64, f64)) -> Option<HitRecord<'_>> {
+        Some(HitRecord {
+            position: self.corner + self.u * uv.0 + self.v * uv.1,
+            normal: self.normal,
+            geometric_normal: self.normal,
+            front_face: true,
+            material: Some(self.material.as_ref()),
+            texture_coords: uv,
+            primitive: Some(self.id),
+            ..Default::default()
+        })
+    }
+
+    fn is_sampleable(&self) -> bool {
+        true
+    }
+
+    /// Points are picked uniformly over the area and converted to a density over
+    /// solid angle.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let ray = Ray::new(*origin, direction.unit(), 0.0);
+        let Some((distance, _)) = self.intersect(&ray, Interval::new(0.0, f64::INFINITY)) else {
+            return 0.0;
+        };
+        let cosine = ray.direction().dot(&self.normal).abs();
+        distance * distance / (cosine * self.area)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        let point = self.corner + self.u * rng.random::<f64>() + self.v * rng.random::<f64>();
+        (point - *origin).unit()
+    }
+}
+
+fn point_box(p: Point3) -> Aabb {
+    Aabb::new(
+        Interval::new(p.x(), p.x()),
+        Interval::new(p.y(), p.y()),
+        Interval::new(p.z(), p.z()),
+    )
+}
+
+/// An axis-aligned box made of six quads facing outwards.
+#[derive(Debug, Clone)]
+pub struct BoxObject {
+    sides: [Quad; 6],
+    bbox: Aabb,
+}
+
+impl BoxObject {
+    /// Creates a box from corner `p_min` to the opposite corner `p_max`, taking the
+    /// least and greatest of their coordinates along each axis.
+    pub fn new(p_min: Point3, p_max: Point3, material: impl Into<Arc<Material>>) -> Self {
+        let material = material.into();
+        let min = Point3::new(
+            p_min.x().min(p_max.x()),
+            p_min.y().min(p_max.y()),
+            p_min.z().min(p_max.z()),
+        );
+        let max = Point3::new(
+            p_min.x().max(p_max.x()),
+            p_min.y().max(p_max.y()),
+            p_min.z().max(p_max.z()),
+        );
+        let dx = Vec3::new(max.x() - min.x(), 0.0, 0.0);
+        let dy = Vec3::new(0.0, max.y() - min.y(), 0.0);
+        let dz = Vec3::new(0.0, 0.0, max.z() - min.z());
+        let side = |corner: Point3, u: Vec3, v: Vec3| Quad::new(corner, u, v, material.clone());
+
+        Self {
+            sides: [
+                side(min, dz, dy),
+                side(Point3::new(max.x(), min.y(), min.z()), dy, dz),
+                side(min, dx, dz),
+                side(Point3::new(min.x(), max.y(), min.z()), dz, dx),
+                side(min, dy, dx),
+                side(Point3::new(min.x(), min.y(), max.z()), dx, dy),
+            ],
+            bbox: Aabb::surrounding(&point_box(min), &point_box(max)),
+        }
+    }
+}
+
+impl Hittable for BoxObject {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        self.bbox.intersect(r, ray_t)?;
+        let mut closest = ray_t;
+        let mut hit = None;
+        for side in &self.sides {
+            if let Some(hit_record) = side.hit(r, closest) {
+                closest = Interval::new(ray_t.min(), hit_record.t);
+                hit = Some(hit_record);
+            }
+        }
+        hit
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.sides
+            .iter()
+            .filter_map(|side| side.bounding_box(time0, time1))
+            .reduce(|a, b| Aabb::surrounding(&a, &b))
+    }
+
+    fn visit_materials(&mut self, visit: &mut dyn FnMut(&mut Arc<Material>)) {
+        for side in &mut self.sides {
+            side.visit_materials(visit);
+        }
+    }
+
+    fn degenerate_reason(&self) -> Option<&'static str> {
+        let diagonal = self.bbox.diagonal();
+        (!(0..3).all(|axis| diagonal[axis] > 0.0 && diagonal[axis].is_finite()))
+            .then_some("box has no volume")
+    }
+
+    fn is_sampleable(&self) -> bool {
+        true
+    }
+
+    /// Each side is picked with equal probability, so the density is the average
+    /// of the sides'.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.sides
+            .iter()
+            .map(|side| side.pdf_value(origin, direction))
+            .sum::<f64>()
+            / self.sides.len() as f64
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        let side = rng.random_range(0..self.sides.len());
+        self.sides[side].random(origin, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{UNIFORM_SPHERE_PDF, uniform_sphere_direction};
+    use crate::material::Lambertian;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn unit_box() -> BoxObject {
+        BoxObject::new(
+            Point3::new(1.0, 1.0, 1.0),
+            Point3::new(-1.0, -1.0, -1.0),
+            Lambertian::clay(),
+        )
+    }
+
+    #[test]
+    fn test_quad_hit() {
+        let quad = Quad::new(
+            Point3::new(-1.0, -1.0, -2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 4.0, 0.0),
+            Lambertian::clay(),
+        );
+        let ray = Ray::new(Point3::default(), Vec3::new(0.5, 1.0, -2.0), 0.0);
+        let hit = quad.hit(&ray, Interval::new(0.001, f64::INFINITY)).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-12);
+        assert!(hit.front_face);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!((hit.texture_coords.0 - 0.75).abs() < 1e-12);
+        assert!((hit.texture_coords.1 - 0.5).abs() < 1e-12);
+
+        // From behind, the normal faces the ray
+        let behind = Ray::new(Point3::new(0.0, 0.0, -4.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let hit = quad
+            .hit(&behind, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!(!hit.front_face);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, -1.0));
+
+        let outside = Ray::new(Point3::default(), Vec3::new(2.0, 0.0, -2.0), 0.0);
+        assert!(
+            quad.hit(&outside, Interval::new(0.001, f64::INFINITY))
+                .is_none()
+        );
+        let parallel = Ray::new(Point3::default(), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        assert!(
+            quad.hit(&parallel, Interval::new(0.001, f64::INFINITY))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_quad_hit_excluding_itself() {
+        let quad = Quad::new(
+            Point3::new(-1.0, -1.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            Lambertian::clay(),
+        );
+        let ray = Ray::new(Point3::new(0.1, 0.2, 1.0), Vec3::new(0.3, 0.1, -1.0), 0.0);
+        let mut hit = quad.hit(&ray, Interval::new(0.001, f64::INFINITY)).unwrap();
+        hit.exclude_primitive = true;
+
+        // A ray grazing back along the surface cannot find the quad it left
+        let grazing = hit.spawn_ray(Vec3::new(1.0, 0.0, 1e-12), 0.0);
+        assert_eq!(grazing.excluded_primitive(), hit.primitive);
+        assert!(
+            quad.hit(&grazing, Interval::new(0.0, f64::INFINITY))
+                .is_none()
+        );
+        assert!(
+            quad.clone()
+                .hit(&hit.spawn_ray(-hit.normal, 0.0), Interval::new(-1.0, 1.0))
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_quad_bounding_box_is_not_flat() {
+        let quad = Quad::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Lambertian::clay(),
+        );
+        let bbox = quad.bounding_box(0.0, 1.0).unwrap();
+        assert!(bbox.axis_interval(1).max() > bbox.axis_interval(1).min());
+        assert_eq!(bbox.axis_interval(0).max(), 1.0);
+    }
+
+    #[test]
+    fn test_box_faces_point_outwards() {
+        let cube = unit_box();
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        for axis in 0..3 {
+            for sign in [-1.0, 1.0] {
+                let mut outward = [0.0; 3];
+                outward[axis] = sign;
+                let outward = Vec3::new(outward[0], outward[1], outward[2]);
+                // Aim slightly off the middle of the face so the texture is not symmetric
+                let target = Point3::from(outward + Vec3::new(0.1, 0.2, 0.3) * 0.5);
+                let origin = Point3::from(outward * 5.0);
+                let ray = Ray::new(origin, target - origin, 0.0);
+                let hit = cube.hit(&ray, ray_t).unwrap();
+                assert!(hit.front_face);
+                assert!((hit.normal - outward).near_zero());
+                assert!((hit.position.as_vec3().dot(&outward) - 1.0).abs() < 1e-9);
+                let (u, v) = hit.texture_coords;
+                assert!((0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v));
+                assert!(u != 0.5 && v != 0.5);
+            }
+        }
+
+        let inside = Ray::new(Point3::default(), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let hit = cube.hit(&inside, ray_t).unwrap();
+        assert!(!hit.front_face);
+        assert!((hit.t - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_box_bounds_and_materials() {
+        let mut cube = unit_box();
+        let bbox = cube.bounding_box(0.0, 1.0).unwrap();
+        for axis in 0..3 {
+            // Faces lying in the box's planes are padded slightly
+            assert!((bbox.axis_interval(axis).min() + 1.0).abs() < 1e-6);
+            assert!((bbox.axis_interval(axis).max() - 1.0).abs() < 1e-6);
+        }
+        assert_eq!(cube.degenerate_reason(), None);
+
+        let mut visited = 0;
+        cube.visit_materials(&mut |_| visited += 1);
+        assert_eq!(visited, 6);
+
+        let flat = BoxObject::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 1.0),
+            Lambertian::clay(),
+        );
+        assert_eq!(flat.degenerate_reason(), Some("box has no volume"));
+    }
+
+    #[test]
+    fn test_box_pdf_matches_sampling() {
+        let cube = unit_box();
+        let origin = Point3::new(0.5, 3.0, -4.0);
+        let mut rng = StdRng::seed_from_u64(5);
+
+        // Directions drawn towards the box hit it
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        for _ in 0..100 {
+            let direction = cube.random(&origin, &mut rng);
+            assert!(cube.hit(&Ray::new(origin, direction, 0.0), ray_t).is_some());
+            assert!(cube.pdf_value(&origin, &direction) > 0.0);
+        }
+
+        // Uniform directions estimate the integral of the pdf over the sphere
+        let samples = 200_000;
+        let integral = (0..samples)
+            .map(|_| {
+                let direction = uniform_sphere_direction((rng.random(), rng.random()));
+                cube.pdf_value(&origin, &direction) / UNIFORM_SPHERE_PDF
+            })
+            .sum::<f64>()
+            / samples as f64;
+        assert!((integral - 1.0).abs() < 0.05, "integrated {}", integral);
+    }
+}
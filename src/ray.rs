@@ -1,11 +1,144 @@
 use crate::point3::Point3;
 use crate::vec3::Vec3;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Auxiliary rays offset by one pixel in x and y from a main ray.
+///
+/// Differentials let a hit point estimate the size of its screen-space footprint,
+/// which textures use to filter out detail that would otherwise shimmer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RayDifferentials {
+    pub rx_origin: Point3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Point3,
+    pub ry_direction: Vec3,
+}
+
+impl RayDifferentials {
+    /// Scales the offset rays towards the main ray defined by `origin` and `direction`.
+    ///
+    /// Used to shrink the footprint when a pixel is covered by many samples.
+    #[inline]
+    pub fn scaled(&self, origin: &Point3, direction: &Vec3, scale: f64) -> RayDifferentials {
+        RayDifferentials {
+            rx_origin: *origin + (self.rx_origin - *origin) * scale,
+            rx_direction: *direction + (self.rx_direction - *direction) * scale,
+            ry_origin: *origin + (self.ry_origin - *origin) * scale,
+            ry_direction: *direction + (self.ry_direction - *direction) * scale,
+        }
+    }
+}
+
+/// The role a ray plays in the light transport, used for per-object visibility.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RayKind {
+    /// A primary ray leaving the camera
+    #[default]
+    Camera,
+    /// A ray scattered from a surface
+    Secondary,
+    /// A ray testing occlusion towards a light
+    Shadow,
+}
+
+/// Identifies a single primitive, such as a sphere or triangle, so a ray spawned
+/// from it can be told to skip it.
+///
+/// A primitive inside a shared object is reached through every [`InstanceId`] the
+/// object is placed with, and is told apart by the instances it was reached
+/// through, so a ray leaving one copy still hits the others.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PrimitiveId {
+    index: u32,
+    flat: bool,
+    /// The instances the primitive was reached through, folded together by
+    /// [`PrimitiveId::within`], or 0 when it sits directly in the world
+    instances: u64,
+}
+
+/// Odd, so folding an instance into a path with it can be undone
+const PATH_MULTIPLIER: u64 = 0x9E37_79B9_7F4A_7C15;
+const PATH_INVERSE: u64 = multiplicative_inverse(PATH_MULTIPLIER);
+
+/// Inverse of the odd `n` modulo 2⁶⁴, by Newton's iteration, each step of which
+/// doubles the number of correct low bits.
+const fn multiplicative_inverse(n: u64) -> u64 {
+    let mut inverse = n;
+    let mut step = 0;
+    while step < 6 {
+        inverse = inverse.wrapping_mul(2u64.wrapping_sub(n.wrapping_mul(inverse)));
+        step += 1;
+    }
+    inverse
+}
+
+impl PrimitiveId {
+    /// Returns an ID no other primitive has been given.
+    ///
+    /// `flat` records whether the primitive is planar, in which case a ray leaving
+    /// it can never meet it again.
+    pub fn next(flat: bool) -> Self {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        Self {
+            index: NEXT.fetch_add(1, Ordering::Relaxed),
+            flat,
+            instances: 0,
+        }
+    }
+
+    #[inline]
+    pub const fn is_flat(&self) -> bool {
+        self.flat
+    }
+
+    /// Returns this primitive as reached through `instance`, for hits leaving it.
+    #[inline]
+    pub const fn within(mut self, instance: InstanceId) -> Self {
+        self.instances = self
+            .instances
+            .wrapping_add(instance.0 as u64 + 1)
+            .wrapping_mul(PATH_MULTIPLIER);
+        self
+    }
+
+    /// Undoes [`PrimitiveId::within`], for rays entering `instance`.
+    ///
+    /// A primitive reached through another instance comes out with a path no
+    /// primitive inside `instance` has, so rays from it skip nothing there.
+    #[inline]
+    pub const fn outside(mut self, instance: InstanceId) -> Self {
+        self.instances = self
+            .instances
+            .wrapping_mul(PATH_INVERSE)
+            .wrapping_sub(instance.0 as u64 + 1);
+        self
+    }
+}
+
+/// Identifies one placement of a shared object, so the same primitive in two
+/// placements has two [`PrimitiveId`]s.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InstanceId(u32);
+
+impl InstanceId {
+    /// Returns an ID no other instance has been given.
+    pub fn next() -> Self {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Ray {
     origin: Point3,
     direction: Vec3,
     time: f64,
+    kind: RayKind,
+    differentials: Option<RayDifferentials>,
+    excluded: Option<PrimitiveId>,
+    /// Where the camera sample this ray's path began was taken, if not at the
+    /// ray's own origin
+    viewpoint: Option<Point3>,
 }
 
 impl Ray {
@@ -15,9 +148,42 @@ impl Ray {
             origin,
             direction,
             time,
+            kind: RayKind::Camera,
+            differentials: None,
+            excluded: None,
+            viewpoint: None,
         }
     }
 
+    /// Returns a copy of this ray with the given kind.
+    #[inline]
+    pub const fn with_kind(mut self, kind: RayKind) -> Ray {
+        self.kind = kind;
+        self
+    }
+
+    /// Returns a copy of this ray carrying the given differentials.
+    #[inline]
+    pub const fn with_differentials(mut self, differentials: Option<RayDifferentials>) -> Ray {
+        self.differentials = differentials;
+        self
+    }
+
+    /// Returns a copy of this ray that ignores its own start on `primitive`.
+    #[inline]
+    pub const fn with_excluded_primitive(mut self, primitive: Option<PrimitiveId>) -> Ray {
+        self.excluded = primitive;
+        self
+    }
+
+    /// Returns a copy of this ray that picks levels of detail as seen from
+    /// `viewpoint`, so every ray of a path sees the version its camera sample did.
+    #[inline]
+    pub const fn with_viewpoint(mut self, viewpoint: Point3) -> Ray {
+        self.viewpoint = Some(viewpoint);
+        self
+    }
+
     #[inline]
     pub const fn origin(&self) -> &Point3 {
         &self.origin
@@ -33,6 +199,33 @@ impl Ray {
         self.time
     }
 
+    #[inline]
+    pub const fn kind(&self) -> RayKind {
+        self.kind
+    }
+
+    #[inline]
+    pub const fn differentials(&self) -> Option<&RayDifferentials> {
+        self.differentials.as_ref()
+    }
+
+    /// Returns the primitive this ray left, whose hit at the ray's origin must be
+    /// ignored, if it was spawned with self-hit exclusion.
+    #[inline]
+    pub const fn excluded_primitive(&self) -> Option<PrimitiveId> {
+        self.excluded
+    }
+
+    /// Returns where levels of detail are picked from: where the camera sample
+    /// this ray's path began was taken, or for a ray given none, its own origin.
+    #[inline]
+    pub const fn viewpoint(&self) -> &Point3 {
+        match &self.viewpoint {
+            Some(viewpoint) => viewpoint,
+            None => &self.origin,
+        }
+    }
+
     #[inline]
     pub fn at_time(&self, t: f64) -> Point3 {
         self.origin + self.direction * t
@@ -82,4 +275,37 @@ mod tests {
         assert_eq!(point_at_two.y(), 12.0); // 2 + 5*2
         assert_eq!(point_at_two.z(), 15.0); // 3 + 6*2
     }
+
+    #[test]
+    fn test_ray_differentials_scaled() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let direction = Vec3::new(0.0, 0.0, -1.0);
+        let differentials = RayDifferentials {
+            rx_origin: origin,
+            rx_direction: Vec3::new(0.2, 0.0, -1.0),
+            ry_origin: origin,
+            ry_direction: Vec3::new(0.0, 0.4, -1.0),
+        };
+        let ray = Ray::new(origin, direction, 0.0)
+            .with_differentials(Some(differentials.scaled(&origin, &direction, 0.5)));
+
+        let scaled = ray.differentials().unwrap();
+        assert_eq!(scaled.rx_direction, Vec3::new(0.1, 0.0, -1.0));
+        assert_eq!(scaled.ry_direction, Vec3::new(0.0, 0.2, -1.0));
+        assert_eq!(scaled.rx_origin, origin);
+        assert!(Ray::new(origin, direction, 0.0).differentials().is_none());
+    }
+
+    #[test]
+    fn test_primitive_ids_through_instances() {
+        assert_eq!(PATH_MULTIPLIER.wrapping_mul(PATH_INVERSE), 1);
+
+        let primitive = PrimitiveId::next(true);
+        let (outer, inner, other) = (InstanceId::next(), InstanceId::next(), InstanceId::next());
+        let nested = primitive.within(inner).within(outer);
+        assert_ne!(nested, primitive.within(outer).within(inner));
+        assert_ne!(nested, primitive.within(other).within(outer));
+        assert_eq!(nested.outside(outer).outside(inner), primitive);
+        assert_ne!(nested.outside(other).outside(inner), primitive);
+    }
 }
@@ -0,0 +1,319 @@
+//! Machine-readable JSON reports of a render run for pipelines and CI.
+//!
+//! A report records the settings a run was given, how long it took, and for each
+//! image written its size, timing, a few statistics and a hash of the bytes written,
+//! so image tests can spot a changed render without diffing pixels.
+
+use crate::cli::RenderOptions;
+use raytrace::framebuffer::{Framebuffer, TileSamples};
+use std::fmt;
+use std::time::Duration;
+
+/// A JSON value, written compactly by its `Display` implementation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Keys and values in the order they are written
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            // JSON has no infinities or NaN
+            Json::Number(n) if !n.is_finite() => write!(f, "null"),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<f64> for Json {
+    fn from(n: f64) -> Self {
+        Json::Number(n)
+    }
+}
+
+impl From<u32> for Json {
+    fn from(n: u32) -> Self {
+        Json::Number(n as f64)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+/// One image written by a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageRecord {
+    /// File the image was written to, or `-` for stdout
+    pub output: String,
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u32,
+    /// Seed the scene was generated from, if known
+    pub scene_seed: Option<u64>,
+    /// Time spent building the scene and rendering this image
+    pub duration: Duration,
+    /// Average linear luminance, NaN if any pixel is not finite
+    pub mean_luminance: f64,
+    /// Pixels with a NaN or infinite channel
+    pub non_finite_pixels: u32,
+    /// Length of the encoded image
+    pub bytes: usize,
+    /// FNV-1a hash of the encoded image
+    pub hash: u64,
+    /// Samples each tile took, when tiles had a time budget
+    pub tile_samples: Option<TileSamples>,
+}
+
+impl ImageRecord {
+    /// Records `frame`, encoded as `encoded`, written to `output`.
+    pub fn new(output: &str, frame: &Framebuffer, encoded: &[u8], duration: Duration) -> Self {
+        let pixel_count = frame.pixels().len().max(1) as f64;
+        Self {
+            output: output.to_string(),
+            width: frame.width(),
+            height: frame.height(),
+            samples_per_pixel: frame.samples_per_pixel(),
+            scene_seed: frame.scene_seed(),
+            duration,
+            mean_luminance: frame.pixels().iter().map(|p| p.luminance()).sum::<f64>() / pixel_count,
+            non_finite_pixels: frame.pixels().iter().filter(|p| !p.is_finite()).count() as u32,
+            bytes: encoded.len(),
+            hash: fnv1a(encoded),
+            tile_samples: frame.tile_samples().cloned(),
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("output", self.output.as_str().into()),
+            ("width", self.width.into()),
+            ("height", self.height.into()),
+            ("samples_per_pixel", self.samples_per_pixel.into()),
+            // A string, as JSON numbers lose precision past 2^53
+            (
+                "scene_seed",
+                self.scene_seed
+                    .map(|seed| seed.to_string())
+                    .as_deref()
+                    .into(),
+            ),
+            ("seconds", self.duration.as_secs_f64().into()),
+            ("mean_luminance", self.mean_luminance.into()),
+            ("non_finite_pixels", self.non_finite_pixels.into()),
+            ("bytes", (self.bytes as f64).into()),
+            ("fnv1a64", format!("{:016x}", self.hash).as_str().into()),
+            (
+                "tile_samples",
+                self.tile_samples.as_ref().map_or(Json::Null, |tiles| {
+                    Json::Object(vec![
+                        ("tile_size", tiles.tile_size.into()),
+                        (
+                            "samples",
+                            Json::Array(tiles.samples.iter().map(|&n| n.into()).collect()),
+                        ),
+                    ])
+                }),
+            ),
+        ])
+    }
+}
+
+/// Everything a run did, built up as it goes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderReport {
+    command: &'static str,
+    settings: Json,
+    images: Vec<ImageRecord>,
+}
+
+impl RenderReport {
+    /// Starts a report of `command` run with `options`.
+    pub fn new(command: &'static str, options: &RenderOptions) -> Self {
+        let settings = Json::Object(vec![
+            ("scene", options.scene.as_str().into()),
+            ("display", options.display.name().into()),
+            ("supersample", options.supersample.into()),
+            (
+                "threads",
+                options.threads.map(|threads| threads as u32).into(),
+            ),
+            (
+                "overrides",
+                Json::Array(
+                    options
+                        .overrides
+                        .iter()
+                        .map(|setting| setting.to_string().as_str().into())
+                        .collect(),
+                ),
+            ),
+        ]);
+        Self {
+            command,
+            settings,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, image: ImageRecord) {
+        self.images.push(image);
+    }
+
+    /// Returns the report as JSON, crediting it with `duration` in total.
+    pub fn to_json(&self, duration: Duration) -> Json {
+        Json::Object(vec![
+            ("command", self.command.into()),
+            ("settings", self.settings.clone()),
+            ("seconds", duration.as_secs_f64().into()),
+            (
+                "images",
+                Json::Array(self.images.iter().map(ImageRecord::to_json).collect()),
+            ),
+        ])
+    }
+}
+
+/// 64-bit FNV-1a hash, stable across platforms and releases unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raytrace::color::Color;
+    use raytrace::overrides::Override;
+
+    #[test]
+    fn test_json_display() {
+        let json = Json::Object(vec![
+            ("name", "a \"quoted\"\nline".into()),
+            (
+                "values",
+                Json::Array(vec![1.5.into(), Json::Null, f64::NAN.into()]),
+            ),
+            ("seed", None::<u32>.into()),
+        ]);
+        assert_eq!(
+            json.to_string(),
+            r#"{"name":"a \"quoted\"\nline","values":[1.5,null,null],"seed":null}"#
+        );
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_render_report() {
+        let options = RenderOptions {
+            scene: "softbox".to_string(),
+            overrides: vec![Override::new("camera.vertical_fov", 35)],
+            ..RenderOptions::default()
+        };
+        let mut report = RenderReport::new("render", &options);
+        let frame = Framebuffer::new(
+            2,
+            1,
+            vec![Color::new(1.0, 1.0, 1.0), Color::new(0.0, 0.0, 0.0)],
+        )
+        .with_scene_seed(Some(u64::MAX));
+        report.push(ImageRecord::new(
+            "-",
+            &frame,
+            b"a",
+            Duration::from_millis(250),
+        ));
+
+        let json = report.to_json(Duration::from_secs(1)).to_string();
+        assert!(
+            json.starts_with(r#"{"command":"render","settings":{"scene":"softbox""#),
+            "{}",
+            json
+        );
+        assert!(
+            json.contains(r#""overrides":["camera.vertical_fov=35"]"#),
+            "{}",
+            json
+        );
+        assert!(json.contains(r#""seconds":1,"#), "{}", json);
+        assert!(
+            json.contains(concat!(
+                r#""scene_seed":"18446744073709551615","seconds":0.25,"mean_luminance":0.5,"#,
+                r#""non_finite_pixels":0,"bytes":1,"fnv1a64":"af63dc4c8601ec8c","#,
+                r#""tile_samples":null"#
+            )),
+            "{}",
+            json
+        );
+
+        let frame = frame.with_tile_samples(Some(TileSamples {
+            tile_size: 16,
+            samples: vec![4.0, 2.5],
+        }));
+        let image = ImageRecord::new("-", &frame, b"a", Duration::ZERO);
+        assert!(
+            image
+                .to_json()
+                .to_string()
+                .ends_with(r#""tile_samples":{"tile_size":16,"samples":[4,2.5]}}"#),
+            "{}",
+            image.to_json()
+        );
+    }
+}
@@ -0,0 +1,405 @@
+//! Per-pixel sample generation.
+//!
+//! Correlated multi-jittered sampling follows Kensler, "Correlated Multi-Jittered
+//! Sampling" (Pixar Technical Memo 13-01). Pixel and lens positions are drawn from
+//! two independently scrambled patterns, so each is stratified on its own while
+//! their pairing stays uncorrelated. Light samples get further patterns, one for
+//! each light at each bounce.
+//!
+//! [`AdaptiveSampling`] stops sampling a pixel once its samples agree closely
+//! enough, tracked by [`SampleStats`]. The samples it takes are a random subset of
+//! the pattern's strata, so stopping early adds noise but no bias.
+
+use crate::utilities::{random_double, random_u32};
+use crate::vec3::Vec3;
+
+/// Strategy used to place samples within a pixel and on the lens.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PixelSampling {
+    /// Independent uniform random samples
+    Independent,
+    /// Stratified correlated multi-jittered samples
+    #[default]
+    CorrelatedMultiJittered,
+}
+
+/// Where one camera sample lands in the pixel and on the lens.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraSample {
+    /// Offset from the pixel center in [-0.5, 0.5)
+    pub pixel_offset: Vec3,
+    /// Point in the unit disk
+    pub lens: Vec3,
+}
+
+/// Generates the samples for one pixel.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PixelSampler {
+    sampling: PixelSampling,
+    count: u32,
+    pixel_pattern: u32,
+    lens_pattern: u32,
+    light_pattern: u32,
+}
+
+impl PixelSampler {
+    /// Creates a sampler for `count` samples with freshly scrambled patterns.
+    pub fn new(sampling: PixelSampling, count: u32) -> Self {
+        Self {
+            sampling,
+            count: count.max(1),
+            pixel_pattern: random_u32(),
+            lens_pattern: random_u32(),
+            light_pattern: random_u32(),
+        }
+    }
+
+    /// Returns sample number `index` of the pixel.
+    #[inline]
+    pub fn sample(&self, index: u32) -> CameraSample {
+        match self.sampling {
+            PixelSampling::Independent => CameraSample {
+                pixel_offset: Vec3::sample_square(),
+                lens: Vec3::random_in_unit_disk(),
+            },
+            PixelSampling::CorrelatedMultiJittered => {
+                let index = index % self.count;
+                let (px, py) = cmj(index, self.count, self.pixel_pattern);
+                let (lx, ly) = cmj(index, self.count, self.lens_pattern);
+                CameraSample {
+                    pixel_offset: Vec3::new(px - 0.5, py - 0.5, 0.0),
+                    lens: concentric_disk(lx, ly),
+                }
+            }
+        }
+    }
+
+    /// Returns the point in [0, 1)² that sample number `index` uses to pick a spot
+    /// on a light.
+    ///
+    /// Each `dimension`, such as a light at a particular bounce, gets its own
+    /// pattern, so the pixel's samples are stratified over every light separately.
+    #[inline]
+    pub fn light(&self, index: u32, dimension: u32) -> (f64, f64) {
+        match self.sampling {
+            PixelSampling::Independent => (random_double(), random_double()),
+            PixelSampling::CorrelatedMultiJittered => {
+                let count = self.count.max(1);
+                let pattern = self
+                    .light_pattern
+                    .wrapping_add(dimension.wrapping_mul(0x9e3779b9));
+                cmj(index % count, count, pattern)
+            }
+        }
+    }
+}
+
+/// Brightness below which a pixel's error is judged against this instead, so nearly
+/// black pixels don't take every sample to settle noise too dark to see.
+const DARK_LUMINANCE: f64 = 0.01;
+/// Half-width of a 95% confidence interval in standard errors.
+const CONFIDENCE_Z: f64 = 1.96;
+
+/// When to stop sampling a pixel before it takes every sample.
+///
+/// A pixel stops once it has taken at least `min_samples` and the 95% confidence
+/// interval of its mean brightness is within `tolerance` of that mean, such as 0.05
+/// for 5%. The camera's samples per pixel is the most any pixel takes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdaptiveSampling {
+    tolerance: f64,
+    min_samples: u32,
+}
+
+impl AdaptiveSampling {
+    /// Samples every pixel takes by default before its error is trusted.
+    pub const DEFAULT_MIN_SAMPLES: u32 = 16;
+
+    pub fn new(tolerance: f64, min_samples: u32) -> Self {
+        Self {
+            tolerance,
+            // The variance needs two samples
+            min_samples: min_samples.max(2),
+        }
+    }
+
+    #[inline]
+    pub fn tolerance(&self) -> f64 {
+        self.tolerance
+    }
+
+    #[inline]
+    pub fn min_samples(&self) -> u32 {
+        self.min_samples
+    }
+
+    /// Returns whether the pixel whose samples are summed up in `stats` has enough.
+    #[inline]
+    pub fn converged(&self, stats: &SampleStats) -> bool {
+        stats.count() >= self.min_samples
+            && stats.error() <= self.tolerance * stats.mean().max(DARK_LUMINANCE)
+    }
+}
+
+/// Running mean and variance of a pixel's sample brightness, by Welford's method.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SampleStats {
+    count: u32,
+    mean: f64,
+    /// Sum of squared differences from the mean
+    m2: f64,
+}
+
+impl SampleStats {
+    /// Adds a sample of brightness `value`.
+    #[inline]
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    #[inline]
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    #[inline]
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// The samples' unbiased variance, or infinity before there are two.
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            f64::INFINITY
+        } else {
+            self.m2 / (self.count - 1) as f64
+        }
+    }
+
+    /// Half-width of the 95% confidence interval of the mean.
+    pub fn error(&self) -> f64 {
+        CONFIDENCE_Z * (self.variance() / self.count as f64).sqrt()
+    }
+}
+
+/// Returns sample `s` of an `n`-sample correlated multi-jittered pattern in [0, 1)².
+fn cmj(s: u32, n: u32, pattern: u32) -> (f64, f64) {
+    let m = ((n as f64).sqrt() as u32).max(1);
+    let rows = n.div_ceil(m);
+    let s = permute(s, n, pattern.wrapping_mul(0x51633e2d));
+    let sx = permute(s % m, m, pattern.wrapping_mul(0x68bc21eb));
+    let sy = permute(s / m, rows, pattern.wrapping_mul(0x02e5be93));
+    let jx = rand_unit(s, pattern.wrapping_mul(0x967a889b));
+    let jy = rand_unit(s, pattern.wrapping_mul(0x368cc8b7));
+
+    let x = ((s % m) as f64 + (sy as f64 + jx) / rows as f64) / m as f64;
+    let y = ((s / m) as f64 + (sx as f64 + jy) / m as f64) / rows as f64;
+    (x, y)
+}
+
+/// A hashed permutation of `i` within `[0, len)`, selected by `pattern`.
+fn permute(mut i: u32, len: u32, pattern: u32) -> u32 {
+    if len <= 1 {
+        return 0;
+    }
+
+    let p = pattern;
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    // Cycle-walk until the hash lands back inside the range
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % len
+}
+
+/// A hashed value in [0, 1) for index `i`, selected by `pattern`.
+fn rand_unit(mut i: u32, pattern: u32) -> f64 {
+    let p = pattern;
+    i ^= p;
+    i ^= i >> 17;
+    i ^= i >> 10;
+    i = i.wrapping_mul(0xb36534e5);
+    i ^= i >> 12;
+    i ^= i >> 21;
+    i = i.wrapping_mul(0x93fc4795);
+    i ^= 0xdf6e307f;
+    i ^= i >> 17;
+    i = i.wrapping_mul(1 | p >> 18);
+    i as f64 / 4294967296.0
+}
+
+/// Maps the unit square onto the unit disk, preserving stratification.
+///
+/// Shirley and Chiu's concentric mapping keeps neighbouring strata adjacent and
+/// roughly equal in shape, unlike the polar mapping.
+fn concentric_disk(u: f64, v: f64) -> Vec3 {
+    let a = 2.0 * u - 1.0;
+    let b = 2.0 * v - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return Vec3::default();
+    }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, std::f64::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (
+            b,
+            std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (a / b),
+        )
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permute_is_a_permutation() {
+        for len in [1, 2, 7, 16, 100] {
+            let mut seen: Vec<u32> = (0..len).map(|i| permute(i, len, 12345)).collect();
+            seen.sort();
+            assert_eq!(seen, (0..len).collect::<Vec<u32>>());
+        }
+    }
+
+    #[test]
+    fn test_cmj_is_stratified() {
+        // 16 samples form a 4x4 grid with one sample per cell and one per row
+        // and column of the 16x16 fine grid
+        let n = 16;
+        let samples: Vec<(f64, f64)> = (0..n).map(|s| cmj(s, n, 42)).collect();
+
+        let mut coarse: Vec<(u32, u32)> = samples
+            .iter()
+            .map(|(x, y)| ((x * 4.0) as u32, (y * 4.0) as u32))
+            .collect();
+        coarse.sort();
+        coarse.dedup();
+        assert_eq!(coarse.len(), 16);
+
+        let mut fine_x: Vec<u32> = samples.iter().map(|(x, _)| (x * 16.0) as u32).collect();
+        fine_x.sort();
+        assert_eq!(fine_x, (0..16).collect::<Vec<u32>>());
+
+        let mut fine_y: Vec<u32> = samples.iter().map(|(_, y)| (y * 16.0) as u32).collect();
+        fine_y.sort();
+        assert_eq!(fine_y, (0..16).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_cmj_handles_non_square_counts() {
+        for n in [1, 3, 10, 100] {
+            for s in 0..n {
+                let (x, y) = cmj(s, n, 7);
+                assert!((0.0..1.0).contains(&x), "x out of range: {}", x);
+                assert!((0.0..1.0).contains(&y), "y out of range: {}", y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_concentric_disk() {
+        assert_eq!(concentric_disk(0.5, 0.5), Vec3::default());
+        for (u, v) in [(0.0, 0.0), (1.0, 0.5), (0.3, 0.9), (0.99, 0.01)] {
+            let p = concentric_disk(u, v);
+            assert!(p.length_squared() <= 1.0 + 1e-12);
+            assert_eq!(p.z(), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_sampler_ranges() {
+        for sampling in [
+            PixelSampling::Independent,
+            PixelSampling::CorrelatedMultiJittered,
+        ] {
+            let sampler = PixelSampler::new(sampling, 10);
+            for s in 0..10 {
+                let sample = sampler.sample(s);
+                assert!((-0.5..0.5).contains(&sample.pixel_offset.x()));
+                assert!((-0.5..0.5).contains(&sample.pixel_offset.y()));
+                assert!(sample.lens.length_squared() <= 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_light_samples_are_stratified_per_dimension() {
+        let sampler = PixelSampler::new(PixelSampling::CorrelatedMultiJittered, 16);
+        for dimension in [0, 1, 7] {
+            let mut cells: Vec<(u32, u32)> = (0..16)
+                .map(|s| {
+                    let (x, y) = sampler.light(s, dimension);
+                    ((x * 4.0) as u32, (y * 4.0) as u32)
+                })
+                .collect();
+            cells.sort();
+            cells.dedup();
+            assert_eq!(cells.len(), 16);
+        }
+        assert_ne!(sampler.light(0, 0), sampler.light(0, 1));
+    }
+
+    #[test]
+    fn test_adaptive_sampling_stops_when_samples_agree() {
+        let mut stats = SampleStats::default();
+        for value in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            stats.add(value);
+        }
+        assert_eq!(stats.count(), 8);
+        assert!((stats.mean() - 5.0).abs() < 1e-12);
+        assert!((stats.variance() - 32.0 / 7.0).abs() < 1e-12);
+
+        let adaptive = AdaptiveSampling::new(0.05, 4);
+        assert!(!adaptive.converged(&stats));
+        let mut flat = SampleStats::default();
+        for _ in 0..3 {
+            flat.add(0.5);
+        }
+        // Not before the minimum, however flat
+        assert!(!adaptive.converged(&flat));
+        flat.add(0.5);
+        assert!(adaptive.converged(&flat));
+
+        // Black pixels stop, and dim noise is judged against a floor
+        let mut dark = SampleStats::default();
+        for value in [0.0, 0.0005, 0.0, 0.0005] {
+            dark.add(value);
+        }
+        assert!(adaptive.converged(&dark));
+        assert_eq!(SampleStats::default().variance(), f64::INFINITY);
+        assert_eq!(AdaptiveSampling::new(0.1, 0).min_samples(), 2);
+    }
+}
@@ -0,0 +1,1307 @@
+//! Scenes described in JSON files rather than in code.
+//!
+//! A scene file names its textures and materials, then places objects and lights
+//! that refer to them by name. Every section but `objects` may be left out, as may
+//! any camera setting, which then keeps the [`CameraBuilder`] default. The `type` of
+//! each texture and material is one of the kinds `raytrace list textures` and
+//! `raytrace list materials` describe, with the same parameters.
+//!
+//! ```json
+//! {
+//!     "camera": { "look_from": [0, 2, 8], "look_at": [0, 1, 0], "vertical_fov": 35 },
+//!     "textures": {
+//!         "tiles": { "type": "checker", "scale": 3, "odd": [0.1, 0.1, 0.1], "even": [0.9, 0.9, 0.9] }
+//!     },
+//!     "materials": {
+//!         "floor": { "type": "lambertian", "texture": "tiles" },
+//!         "gold": { "type": "metal", "conductor": "gold", "roughness": 0.2 }
+//!     },
+//!     "objects": [
+//!         { "type": "sphere", "center": [0, -1000, 0], "radius": 1000, "material": "floor" },
+//!         { "type": "sphere", "center": [0, 1, 0], "radius": 1, "material": "gold" }
+//!     ],
+//!     "lights": [{ "type": "point", "position": [3, 5, 3], "intensity": [40, 40, 40] }]
+//! }
+//! ```
+//!
+//! Wherever a texture is expected, a color may be given instead. Mesh and image
+//! paths are relative to the scene file. Images are checked as the scene loads, and
+//! as much of them decoded as fits in `texture_memory_mb`, the most held at once;
+//! the rest is decoded as the render reaches it. One that cannot be read renders
+//! magenta. Mistakes are reported with what they refer to, such
+//! as the index of an object whose material is not defined, and parse errors with
+//! the line and column.
+
+use crate::background::Background;
+use crate::camera::{CameraBuilder, LightSampling};
+use crate::color::Color;
+use crate::error::Error;
+use crate::hittable::Hittable;
+use crate::instance::Instance;
+use crate::light::{Light, PointLight, QuadLight, SpotLight, SunLight};
+use crate::material::{
+    ComplexIor, Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal,
+};
+use crate::medium::Medium;
+use crate::mesh::{AxisConvention, Handedness, Mesh, TriangleMesh, UpAxis};
+use crate::overrides::{self, Override};
+use crate::parallel::*;
+use crate::point3::Point3;
+use crate::quad::BoxObject;
+use crate::sphere::SphereBuilder;
+use crate::texture::{
+    CheckerTexture, ImageTexture, NoisePattern, NoiseTexture, TextureEnum, WindowTexture,
+};
+use crate::texture_cache::{DEFAULT_BUDGET, TextureCache};
+use crate::vec3::Vec3;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Deepest chain of textures made of other textures, which catches cycles.
+const MAX_TEXTURE_DEPTH: usize = 16;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    /// The file is not JSON, or does not match the scene format
+    Parse(serde_json::Error),
+    /// A material or texture refers to a texture that is not defined
+    UnknownTexture {
+        user: String,
+        name: String,
+    },
+    /// An object refers to a material that is not defined
+    UnknownMaterial {
+        object: usize,
+        name: String,
+    },
+    /// A metal names a conductor that is not one of the presets
+    UnknownConductor {
+        material: String,
+        name: String,
+    },
+    /// Textures refer to each other in a loop
+    TextureCycle(String),
+    /// The object at this index could not be built
+    Object {
+        object: usize,
+        error: Error,
+    },
+    /// A parameter set from the command line is not in the scene
+    Override(String),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "I/O error: {}", e),
+            SceneError::Parse(e) => write!(f, "{}", e),
+            SceneError::UnknownTexture { user, name } => {
+                write!(f, "{} uses unknown texture '{}'", user, name)
+            }
+            SceneError::UnknownMaterial { object, name } => {
+                write!(f, "object {} uses unknown material '{}'", object, name)
+            }
+            SceneError::UnknownConductor { material, name } => {
+                write!(
+                    f,
+                    "material '{}' is made of unknown metal '{}'",
+                    material, name
+                )
+            }
+            SceneError::TextureCycle(name) => {
+                write!(f, "texture '{}' is made of itself", name)
+            }
+            SceneError::Object { object, error } => write!(f, "object {}: {}", object, error),
+            SceneError::Override(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io(e) => Some(e),
+            SceneError::Parse(e) => Some(e),
+            SceneError::Object { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SceneError {
+    fn from(error: io::Error) -> Self {
+        SceneError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for SceneError {
+    fn from(error: serde_json::Error) -> Self {
+        SceneError::Parse(error)
+    }
+}
+
+/// Reads the scene file at `path` into its objects and a camera.
+pub fn load(path: impl AsRef<Path>) -> Result<(Vec<Box<dyn Hittable>>, CameraBuilder), SceneError> {
+    load_with_overrides(path, &[])
+}
+
+/// Reads the scene file at `path` as if `overrides` had been set in it.
+pub fn load_with_overrides(
+    path: impl AsRef<Path>,
+    overrides: &[Override],
+) -> Result<(Vec<Box<dyn Hittable>>, CameraBuilder), SceneError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    if overrides.is_empty() {
+        // Parsing the text itself keeps the line and column in parse errors
+        return parse(&text, dir);
+    }
+    let mut document = serde_json::from_str(&text)?;
+    overrides::patch(&mut document, overrides).map_err(SceneError::Override)?;
+    build(serde_json::from_value(document)?, dir)
+}
+
+/// Sets `overrides` on the camera of a scene built in code, which has no file for
+/// anything but camera settings to be set in. Keys name the settings as a scene
+/// file's `camera` does.
+pub fn override_camera(
+    camera: CameraBuilder,
+    overrides: &[Override],
+) -> Result<CameraBuilder, SceneError> {
+    if let Some(setting) = overrides.iter().find(|setting| !setting.changes_camera()) {
+        return Err(SceneError::Override(format!(
+            "cannot set {}: only camera settings can be set on a built-in scene",
+            setting.key
+        )));
+    }
+    let mut document = serde_json::json!({ "camera": {} });
+    overrides::patch(&mut document, overrides).map_err(SceneError::Override)?;
+    let spec: CameraSpec = serde_json::from_value(document["camera"].take())?;
+    Ok(spec.apply(camera))
+}
+
+/// Builds the scene described by the JSON `text`, finding meshes relative to `dir`.
+///
+/// The scene is built in stages: materials are resolved first, then every mesh file
+/// is read once, however many objects use it, and finally the objects are built,
+/// each mesh indexing its triangles in a BVH of its own. Image textures are read
+/// while the meshes are, and decoded as far as the texture budget allows. With the
+/// `parallel` feature the files are read and decoded, and the objects built, on
+/// every core.
+pub fn parse(
+    text: &str,
+    dir: &Path,
+) -> Result<(Vec<Box<dyn Hittable>>, CameraBuilder), SceneError> {
+    build(serde_json::from_str(text)?, dir)
+}
+
+/// Builds the scene `file` describes, finding meshes relative to `dir`.
+fn build(
+    file: SceneFile,
+    dir: &Path,
+) -> Result<(Vec<Box<dyn Hittable>>, CameraBuilder), SceneError> {
+    let cache = file.texture_cache();
+    let materials = build_materials(&file, dir, &cache)?;
+
+    let specs = file
+        .objects
+        .iter()
+        .enumerate()
+        .map(|(index, spec)| {
+            let name = spec.material();
+            let material =
+                materials
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| SceneError::UnknownMaterial {
+                        object: index,
+                        name: name.to_string(),
+                    })?;
+            Ok((index, spec, material))
+        })
+        .collect::<Result<Vec<_>, SceneError>>()?;
+
+    let (meshes, ()) = join(|| load_meshes(&file.objects, dir), || cache.preload());
+    let meshes = meshes?;
+    let objects = specs
+        .into_par_iter()
+        .map(|(index, spec, material)| {
+            build_object(spec, material, &meshes).map_err(|error| SceneError::Object {
+                object: index,
+                error,
+            })
+        })
+        .collect::<Result<Vec<_>, SceneError>>()?;
+    Ok((objects, file.camera()))
+}
+
+/// A place where two scenes differ, such as `materials.gold.Metal.roughness`.
+///
+/// A side is `None` where that scene has nothing at the path.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneDifference {
+    pub path: String,
+    pub first: Option<serde_json::Value>,
+    pub second: Option<serde_json::Value>,
+}
+
+impl fmt::Display for SceneDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.first, &self.second) {
+            (Some(first), Some(second)) => write!(f, "{}: {} -> {}", self.path, first, second),
+            (Some(first), None) => write!(f, "{}: only in the first scene ({})", self.path, first),
+            (None, Some(second)) => {
+                write!(f, "{}: only in the second scene ({})", self.path, second)
+            }
+            (None, None) => write!(f, "{}", self.path),
+        }
+    }
+}
+
+/// Compares the scene files at `first` and `second`, as [`diff`] does.
+pub fn diff_files(
+    first: impl AsRef<Path>,
+    second: impl AsRef<Path>,
+) -> Result<Vec<SceneDifference>, SceneError> {
+    diff(&fs::read_to_string(first)?, &fs::read_to_string(second)?)
+}
+
+/// Lists where the scenes described by the JSON `first` and `second` differ, in
+/// their objects, materials and camera, for finding why two scenes that should be
+/// the same render differently.
+///
+/// The camera and materials are compared as they are built, with defaults filled
+/// in, textures resolved and lights added to the camera, so spelling the same
+/// scene differently, such as `fuzz` for `roughness`, makes no difference. Objects
+/// are compared as written. Both scenes must be valid.
+pub fn diff(first: &str, second: &str) -> Result<Vec<SceneDifference>, SceneError> {
+    let mut differences = Vec::new();
+    diff_values(
+        "",
+        &comparable(first)?,
+        &comparable(second)?,
+        &mut differences,
+    );
+    Ok(differences)
+}
+
+/// The parts of the scene in `text` that [`diff`] compares, as JSON.
+fn comparable(text: &str) -> Result<serde_json::Value, SceneError> {
+    let file: SceneFile = serde_json::from_str(text)?;
+    let materials = build_materials(&file, Path::new(""), &file.texture_cache())?
+        .into_iter()
+        .map(|(name, material)| Ok((name.to_string(), serde_json::to_value(&*material)?)))
+        .collect::<Result<serde_json::Map<_, _>, serde_json::Error>>()?;
+    let written: serde_json::Value = serde_json::from_str(text)?;
+    Ok(serde_json::json!({
+        "camera": serde_json::to_value(file.camera())?,
+        "materials": materials,
+        "objects": written["objects"],
+    }))
+}
+
+/// Adds every difference between `first` and `second`, found under `path`, to
+/// `differences`.
+fn diff_values(
+    path: &str,
+    first: &serde_json::Value,
+    second: &serde_json::Value,
+    differences: &mut Vec<SceneDifference>,
+) {
+    use serde_json::Value;
+
+    let mut child =
+        |child: String, first: Option<&Value>, second: Option<&Value>| match (first, second) {
+            (Some(first), Some(second)) => diff_values(&child, first, second, differences),
+            _ => differences.push(SceneDifference {
+                path: child,
+                first: first.cloned(),
+                second: second.cloned(),
+            }),
+        };
+    match (first, second) {
+        (Value::Object(first), Value::Object(second)) => {
+            let keys: std::collections::BTreeSet<&String> =
+                first.keys().chain(second.keys()).collect();
+            for key in keys {
+                let key_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                child(key_path, first.get(key), second.get(key));
+            }
+        }
+        (Value::Array(first), Value::Array(second)) => {
+            for index in 0..first.len().max(second.len()) {
+                child(
+                    format!("{}[{}]", path, index),
+                    first.get(index),
+                    second.get(index),
+                );
+            }
+        }
+        _ if first != second => differences.push(SceneDifference {
+            path: path.to_string(),
+            first: Some(first.clone()),
+            second: Some(second.clone()),
+        }),
+        _ => {}
+    }
+}
+
+/// Builds every material of `file` by name, finding images relative to `dir`.
+///
+/// The images are registered with `cache`, which they are all read through, but
+/// not read.
+fn build_materials<'a>(
+    file: &'a SceneFile,
+    dir: &Path,
+    cache: &Arc<TextureCache>,
+) -> Result<BTreeMap<&'a str, Arc<Material>>, SceneError> {
+    let images = Images {
+        dir,
+        cache: Arc::clone(cache),
+    };
+    let mut materials = BTreeMap::new();
+    for (name, spec) in &file.materials {
+        let user = format!("material '{}'", name);
+        let texture =
+            |texture: &TextureRef| resolve_texture(texture, &file.textures, &images, &user, 0);
+        let material = match spec {
+            MaterialSpec::Lambertian { texture: t } => Lambertian::new(Box::new(texture(t)?)),
+            MaterialSpec::Metal {
+                albedo,
+                roughness,
+                roughness_texture,
+                conductor,
+            } => {
+                let mut material = match conductor {
+                    Some(conductor) => {
+                        let ior = ComplexIor::named(conductor).ok_or_else(|| {
+                            SceneError::UnknownConductor {
+                                material: name.clone(),
+                                name: conductor.clone(),
+                            }
+                        })?;
+                        Metal::conductor(ior, *roughness)
+                    }
+                    None => Metal::new(Color::new(1.0, 1.0, 1.0), *roughness),
+                };
+                if let Material::Metal(metal) = &mut material {
+                    metal.set_albedo(texture(albedo)?);
+                    metal.set_roughness_map(roughness_texture.as_ref().map(texture).transpose()?);
+                }
+                material
+            }
+            MaterialSpec::Dielectric {
+                refraction_index,
+                tint,
+                roughness,
+                thin,
+                interior,
+            } => {
+                let mut material = Dielectric::frosted(*refraction_index, *roughness);
+                if let Material::Dielectric(dielectric) = &mut material {
+                    dielectric.set_tint(color(tint));
+                    dielectric.set_thin(*thin);
+                    dielectric.set_interior(interior.as_ref().map(MediumSpec::medium));
+                }
+                material
+            }
+            MaterialSpec::DiffuseLight { texture: t } => DiffuseLight::new(Box::new(texture(t)?)),
+            MaterialSpec::Isotropic { texture: t } => Isotropic::new(Box::new(texture(t)?)),
+        };
+        materials.insert(name.as_str(), Arc::new(material));
+    }
+    Ok(materials)
+}
+
+/// Where a scene's image textures are found, and the cache they are read through.
+struct Images<'a> {
+    dir: &'a Path,
+    cache: Arc<TextureCache>,
+}
+
+/// Builds the texture `texture` refers to, from inside `user`.
+fn resolve_texture(
+    texture: &TextureRef,
+    textures: &BTreeMap<String, TextureSpec>,
+    images: &Images,
+    user: &str,
+    depth: usize,
+) -> Result<TextureEnum, SceneError> {
+    let name = match texture {
+        TextureRef::Color(rgb) => return Ok(TextureEnum::SolidColor(color(rgb).into())),
+        TextureRef::Name(name) => name,
+    };
+    if depth >= MAX_TEXTURE_DEPTH {
+        return Err(SceneError::TextureCycle(name.clone()));
+    }
+    let spec = textures
+        .get(name)
+        .ok_or_else(|| SceneError::UnknownTexture {
+            user: user.to_string(),
+            name: name.clone(),
+        })?;
+    let user = format!("texture '{}'", name);
+    let inner = |texture: &TextureRef| {
+        resolve_texture(texture, textures, images, &user, depth + 1).map(Box::new)
+    };
+    Ok(match spec {
+        TextureSpec::SolidColor { color: rgb } => TextureEnum::SolidColor(color(rgb).into()),
+        TextureSpec::Checker { scale, odd, even } => {
+            TextureEnum::CheckerTexture(CheckerTexture::new(*scale, inner(odd)?, inner(even)?))
+        }
+        TextureSpec::Noise {
+            scale,
+            pattern,
+            seed,
+        } => {
+            let pattern = match pattern {
+                PatternSpec::Smooth => NoisePattern::Smooth,
+                PatternSpec::Turbulence => NoisePattern::Turbulence,
+                PatternSpec::Marble => NoisePattern::Marble,
+            };
+            TextureEnum::Noise(match seed {
+                Some(seed) => NoiseTexture::seeded(*scale, pattern, *seed),
+                None => NoiseTexture::new(*scale, pattern),
+            })
+        }
+        TextureSpec::Windows {
+            color: rgb,
+            floor_height,
+            window_spacing,
+            lit_fraction,
+        } => {
+            let windows = WindowTexture::new(color(rgb), *floor_height, *window_spacing);
+            TextureEnum::Windows(match lit_fraction {
+                Some(lit_fraction) => windows.lit_fraction(*lit_fraction),
+                None => windows,
+            })
+        }
+        TextureSpec::Image { path } => {
+            TextureEnum::Image(ImageTexture::new(&images.cache, images.dir.join(path)))
+        }
+    })
+}
+
+/// Reads every mesh file `objects` use, each once, by path.
+///
+/// A file that cannot be read is reported against the first object using it.
+fn load_meshes<'a>(
+    objects: &'a [ObjectSpec],
+    dir: &Path,
+) -> Result<BTreeMap<&'a str, Mesh>, SceneError> {
+    let mut paths: Vec<&str> = objects.iter().filter_map(ObjectSpec::mesh_path).collect();
+    paths.sort_unstable();
+    paths.dedup();
+    let loaded: Vec<_> = paths
+        .into_par_iter()
+        .map(|path| (path, Mesh::load_obj(dir.join(path))))
+        .collect();
+
+    let mut meshes = BTreeMap::new();
+    let mut failure: Option<(usize, Error)> = None;
+    for (path, mesh) in loaded {
+        match mesh {
+            Ok(mesh) => {
+                meshes.insert(path, mesh);
+            }
+            Err(error) => {
+                let object = objects
+                    .iter()
+                    .position(|spec| spec.mesh_path() == Some(path))
+                    .unwrap_or_default();
+                if failure.as_ref().is_none_or(|(first, _)| object < *first) {
+                    failure = Some((object, error));
+                }
+            }
+        }
+    }
+    match failure {
+        Some((object, error)) => Err(SceneError::Object { object, error }),
+        None => Ok(meshes),
+    }
+}
+
+/// Builds the object `spec` describes, taking mesh files from `meshes`.
+fn build_object(
+    spec: &ObjectSpec,
+    material: Arc<Material>,
+    meshes: &BTreeMap<&str, Mesh>,
+) -> Result<Box<dyn Hittable>, Error> {
+    Ok(match spec {
+        ObjectSpec::Sphere {
+            center,
+            radius,
+            center_end,
+            ..
+        } => {
+            let sphere = SphereBuilder::new()
+                .center(point(center))
+                .radius(*radius)
+                .material(material);
+            let sphere = match center_end {
+                Some(end) => sphere.center_end(point(end)),
+                None => sphere,
+            };
+            Box::new(sphere.build()?)
+        }
+        ObjectSpec::Box { min, max, .. } => {
+            Box::new(BoxObject::new(point(min), point(max), material))
+        }
+        ObjectSpec::Mesh {
+            path,
+            translate,
+            rotate_y,
+            scale,
+            up,
+            handedness,
+            ..
+        } => {
+            let mut mesh = meshes
+                .get(path.as_str())
+                .cloned()
+                .ok_or(Error::Build("mesh file was not loaded"))?;
+            mesh.convert_axes(AxisConvention {
+                up: match up {
+                    UpAxisSpec::Y => UpAxis::Y,
+                    UpAxisSpec::Z => UpAxis::Z,
+                },
+                handedness: match handedness {
+                    HandednessSpec::Right => Handedness::Right,
+                    HandednessSpec::Left => Handedness::Left,
+                },
+            });
+            let mesh = TriangleMesh::new(mesh, material)?;
+            Box::new(
+                Instance::new(Arc::new(mesh), vector(translate))
+                    .rotate_y(*rotate_y)
+                    .scale(Vec3::new(*scale, *scale, *scale)),
+            )
+        }
+    })
+}
+
+fn color(rgb: &[f64; 3]) -> Color {
+    Color::new(rgb[0], rgb[1], rgb[2])
+}
+
+fn point(p: &[f64; 3]) -> Point3 {
+    Point3::new(p[0], p[1], p[2])
+}
+
+fn vector(v: &[f64; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+fn white() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn white_texture() -> TextureRef {
+    TextureRef::Color(white())
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    camera: CameraSpec,
+    #[serde(default)]
+    textures: BTreeMap<String, TextureSpec>,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialSpec>,
+    objects: Vec<ObjectSpec>,
+    #[serde(default)]
+    lights: Vec<LightSpec>,
+    /// Most megabytes of decoded image texture held in memory at once
+    texture_memory_mb: Option<usize>,
+}
+
+impl SceneFile {
+    /// The camera, lit by the file's lights.
+    fn camera(&self) -> CameraBuilder {
+        self.lights
+            .iter()
+            .fold(self.camera.apply(CameraBuilder::new()), |camera, light| {
+                camera.light(light.build())
+            })
+    }
+
+    /// An empty cache for the file's images, within its texture memory budget.
+    fn texture_cache(&self) -> Arc<TextureCache> {
+        Arc::new(TextureCache::new(
+            self.texture_memory_mb
+                .map_or(DEFAULT_BUDGET, |megabytes| megabytes << 20),
+        ))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CameraSpec {
+    look_from: Option<[f64; 3]>,
+    look_at: Option<[f64; 3]>,
+    vup: Option<[f64; 3]>,
+    vertical_fov: Option<f64>,
+    aspect_ratio: Option<f64>,
+    image_width: Option<u32>,
+    samples_per_pixel: Option<u32>,
+    max_depth: Option<u32>,
+    defocus_angle: Option<f64>,
+    focus_dist: Option<f64>,
+    background: Option<[f64; 3]>,
+    light_sampling: Option<LightSamplingSpec>,
+    transparent_shadows: Option<bool>,
+    /// Haze filling the space around every object
+    medium: Option<MediumSpec>,
+    /// How far rays that hit nothing travel through the medium
+    medium_extent: Option<f64>,
+}
+
+impl CameraSpec {
+    /// Returns `camera` with the settings this spec gives.
+    fn apply(&self, mut camera: CameraBuilder) -> CameraBuilder {
+        if let Some(p) = &self.look_from {
+            camera = camera.look_from(point(p));
+        }
+        if let Some(p) = &self.look_at {
+            camera = camera.look_at(point(p));
+        }
+        if let Some(v) = &self.vup {
+            camera = camera.vup(vector(v));
+        }
+        if let Some(fov) = self.vertical_fov {
+            camera = camera.vertical_fov(fov);
+        }
+        if let Some(aspect_ratio) = self.aspect_ratio {
+            camera = camera.aspect_ratio(aspect_ratio);
+        }
+        if let Some(width) = self.image_width {
+            camera = camera.image_width(width);
+        }
+        if let Some(samples) = self.samples_per_pixel {
+            camera = camera.samples_per_pixel(samples);
+        }
+        if let Some(depth) = self.max_depth {
+            camera = camera.max_depth(depth);
+        }
+        if let Some(angle) = self.defocus_angle {
+            camera = camera.defocus_angle(angle);
+        }
+        if let Some(distance) = self.focus_dist {
+            camera = camera.focus_dist(distance);
+        }
+        if let Some(rgb) = &self.background {
+            camera = camera.background(Background::Uniform(color(rgb)));
+        }
+        if let Some(light_sampling) = self.light_sampling {
+            camera = camera.light_sampling(match light_sampling {
+                LightSamplingSpec::All => LightSampling::All,
+                LightSamplingSpec::Tree => LightSampling::Tree,
+            });
+        }
+        if let Some(transparent_shadows) = self.transparent_shadows {
+            camera = camera.transparent_shadows(transparent_shadows);
+        }
+        if let Some(medium) = &self.medium {
+            camera = camera.medium(Some(medium.medium()));
+        }
+        if let Some(extent) = self.medium_extent {
+            camera = camera.medium_extent(Some(extent));
+        }
+        camera
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum LightSamplingSpec {
+    All,
+    Tree,
+}
+
+/// A texture given by name, or a plain color.
+#[derive(Debug, Deserialize)]
+#[serde(untagged, expecting = "a texture name or an [r, g, b] color")]
+enum TextureRef {
+    Name(String),
+    Color([f64; 3]),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureSpec {
+    SolidColor {
+        color: [f64; 3],
+    },
+    Checker {
+        scale: f64,
+        odd: TextureRef,
+        even: TextureRef,
+    },
+    Noise {
+        scale: f64,
+        #[serde(default)]
+        pattern: PatternSpec,
+        seed: Option<u64>,
+    },
+    Windows {
+        color: [f64; 3],
+        floor_height: f64,
+        window_spacing: f64,
+        lit_fraction: Option<f64>,
+    },
+    Image {
+        /// PPM file, relative to the scene file
+        path: String,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PatternSpec {
+    #[default]
+    Smooth,
+    Turbulence,
+    Marble,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialSpec {
+    Lambertian {
+        texture: TextureRef,
+    },
+    Metal {
+        #[serde(default = "white_texture")]
+        albedo: TextureRef,
+        #[serde(default, alias = "fuzz")]
+        roughness: f64,
+        /// Texture whose brightness scales the roughness across the surface
+        roughness_texture: Option<TextureRef>,
+        /// One of the named metals, whose Fresnel reflectance colors it
+        conductor: Option<String>,
+    },
+    Dielectric {
+        refraction_index: f64,
+        #[serde(default = "white")]
+        tint: [f64; 3],
+        #[serde(default)]
+        roughness: f64,
+        /// Whether each surface is a single sheet, such as a pane modeled as a quad
+        #[serde(default)]
+        thin: bool,
+        /// The medium filling the solid, such as the water in a glass
+        interior: Option<MediumSpec>,
+    },
+    DiffuseLight {
+        texture: TextureRef,
+    },
+    Isotropic {
+        texture: TextureRef,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MediumSpec {
+    /// Fraction of the light absorbed per unit of distance, for each channel
+    #[serde(default)]
+    absorption: [f64; 3],
+    /// Chance per unit of distance of scattering off a particle
+    #[serde(default)]
+    scattering: f64,
+    #[serde(default = "white")]
+    albedo: [f64; 3],
+}
+
+impl MediumSpec {
+    fn medium(&self) -> Medium {
+        Medium::new(
+            color(&self.absorption),
+            self.scattering,
+            color(&self.albedo),
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectSpec {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: String,
+        /// Where the sphere has moved to by the end of the shutter
+        center_end: Option<[f64; 3]>,
+    },
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: String,
+    },
+    /// An OBJ file, scaled, then turned about y, then moved
+    Mesh {
+        path: String,
+        material: String,
+        #[serde(default)]
+        translate: [f64; 3],
+        #[serde(default)]
+        rotate_y: f64,
+        #[serde(default = "one")]
+        scale: f64,
+        /// The axis pointing up in the file
+        #[serde(default)]
+        up: UpAxisSpec,
+        /// Whether the file's axes are right- or left-handed
+        #[serde(default)]
+        handedness: HandednessSpec,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum UpAxisSpec {
+    #[default]
+    Y,
+    Z,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum HandednessSpec {
+    #[default]
+    Right,
+    Left,
+}
+
+impl ObjectSpec {
+    fn material(&self) -> &str {
+        match self {
+            ObjectSpec::Sphere { material, .. }
+            | ObjectSpec::Box { material, .. }
+            | ObjectSpec::Mesh { material, .. } => material,
+        }
+    }
+
+    /// The mesh file the object is read from, if it is a mesh.
+    fn mesh_path(&self) -> Option<&str> {
+        match self {
+            ObjectSpec::Mesh { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightSpec {
+    Point {
+        position: [f64; 3],
+        intensity: [f64; 3],
+    },
+    Spot {
+        position: [f64; 3],
+        direction: [f64; 3],
+        intensity: [f64; 3],
+        cone_angle: f64,
+        #[serde(default)]
+        edge_angle: f64,
+    },
+    Sun {
+        direction: [f64; 3],
+        irradiance: [f64; 3],
+        #[serde(default)]
+        angular_diameter: f64,
+    },
+    Quad {
+        corner: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+        radiance: [f64; 3],
+    },
+}
+
+impl LightSpec {
+    fn build(&self) -> Light {
+        match self {
+            LightSpec::Point {
+                position,
+                intensity,
+            } => PointLight::new(point(position), color(intensity)),
+            LightSpec::Spot {
+                position,
+                direction,
+                intensity,
+                cone_angle,
+                edge_angle,
+            } => SpotLight::new(
+                point(position),
+                vector(direction),
+                color(intensity),
+                *cone_angle,
+                *edge_angle,
+            ),
+            LightSpec::Sun {
+                direction,
+                irradiance,
+                angular_diameter,
+            } => SunLight::new(vector(direction), color(irradiance), *angular_diameter),
+            LightSpec::Quad {
+                corner,
+                u,
+                v,
+                radiance,
+            } => QuadLight::new(point(corner), vector(u), vector(v), color(radiance)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interval::Interval;
+    use crate::ray::Ray;
+
+    const EXAMPLE: &str = r#"{
+        "camera": { "look_from": [0, 2, 8], "look_at": [0, 1, 0], "vertical_fov": 35 },
+        "textures": {
+            "tiles": { "type": "checker", "scale": 3, "odd": "dark", "even": [0.9, 0.9, 0.9] },
+            "dark": { "type": "solid_color", "color": [0.1, 0.1, 0.1] }
+        },
+        "materials": {
+            "floor": { "type": "lambertian", "texture": "tiles" },
+            "gold": { "type": "metal", "albedo": [0.9, 0.7, 0.3], "fuzz": 0.2 },
+            "glass": { "type": "dielectric", "refraction_index": 1.5, "roughness": 0.4 },
+            "copper": { "type": "metal", "conductor": "copper", "roughness": 0.3 }
+        },
+        "objects": [
+            { "type": "sphere", "center": [0, -1000, 0], "radius": 1000, "material": "floor" },
+            { "type": "sphere", "center": [0, 1, 0], "radius": 1, "material": "gold" },
+            { "type": "box", "min": [2, 0, -1], "max": [3, 1, 0], "material": "glass" }
+        ],
+        "lights": [{ "type": "point", "position": [3, 5, 3], "intensity": [40, 40, 40] }]
+    }"#;
+
+    fn parse_str(text: &str) -> Result<(Vec<Box<dyn Hittable>>, CameraBuilder), SceneError> {
+        parse(text, Path::new(""))
+    }
+
+    #[test]
+    fn test_parse_scene() {
+        let (objects, camera) = parse_str(EXAMPLE).unwrap();
+        assert_eq!(objects.len(), 3);
+
+        let ray = Ray::new(Point3::new(0.0, 1.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let hit = objects[1]
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-9);
+        assert_eq!(
+            hit.material,
+            Some(&Metal::new(Color::new(0.9, 0.7, 0.3), 0.2))
+        );
+        let bbox = objects[2].bounding_box(0.0, 1.0).unwrap();
+        assert_eq!(bbox.center(), Point3::new(2.5, 0.5, -0.5));
+        camera.build();
+    }
+
+    #[test]
+    fn test_image_texture() {
+        let scene = |path: &str| {
+            format!(
+                r#"{{
+                    "textures": {{ "wood": {{ "type": "image", "path": "{}" }} }},
+                    "materials": {{ "m": {{ "type": "lambertian", "texture": "wood" }} }},
+                    "objects": [{{ "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "m" }}],
+                    "texture_memory_mb": 64
+                }}"#,
+                path
+            )
+        };
+        // The image is read while loading, but a missing one only renders magenta
+        let (mut objects, _) = parse(&scene("wood.ppm"), Path::new("scenes")).unwrap();
+        let unreadable = crate::preprocess::find_unreadable_textures(&mut objects).unreadable;
+        assert_eq!(unreadable.len(), 1);
+        assert_eq!(unreadable[0].0, Path::new("scenes/wood.ppm"));
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let hit = objects[0]
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        let saved = serde_json::to_string(hit.material.unwrap()).unwrap();
+        assert!(saved.contains(r#""path":"scenes/wood.ppm""#), "{}", saved);
+
+        let differences = diff(&scene("wood.ppm"), &scene("oak.ppm")).unwrap();
+        assert_eq!(differences.len(), 1, "{:?}", differences);
+    }
+
+    #[test]
+    fn test_textured_metal() {
+        let (objects, _) = parse_str(
+            r#"{
+                "textures": {
+                    "stripes": { "type": "checker", "scale": 3, "odd": [0, 0, 0], "even": [1, 1, 1] }
+                },
+                "materials": {
+                    "steel": {
+                        "type": "metal",
+                        "albedo": [0.8, 0.8, 0.8],
+                        "roughness": 0.4,
+                        "roughness_texture": "stripes"
+                    }
+                },
+                "objects": [{ "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "steel" }]
+            }"#,
+        )
+        .unwrap();
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let hit = objects[0]
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        let stripes = TextureEnum::CheckerTexture(CheckerTexture::new(
+            3.0,
+            Box::new(Color::new(0.0, 0.0, 0.0).into()),
+            Box::new(Color::new(1.0, 1.0, 1.0).into()),
+        ));
+        assert_eq!(
+            hit.material,
+            Some(&Metal::textured(Color::new(0.8, 0.8, 0.8), 0.4, stripes))
+        );
+    }
+
+    #[test]
+    fn test_dielectric_interior() {
+        let (objects, _) = parse_str(
+            r#"{
+                "materials": {
+                    "water": {
+                        "type": "dielectric",
+                        "refraction_index": 1.33,
+                        "interior": { "absorption": [0.4, 0.1, 0.05], "scattering": 0.2 }
+                    }
+                },
+                "objects": [{ "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "water" }]
+            }"#,
+        )
+        .unwrap();
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let hit = objects[0]
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert_eq!(
+            hit.material.and_then(Material::interior_medium),
+            Some(&Medium::new(
+                Color::new(0.4, 0.1, 0.05),
+                0.2,
+                Color::new(1.0, 1.0, 1.0)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_camera_medium() {
+        let (_, camera) = parse_str(
+            r#"{
+                "camera": {
+                    "medium": { "absorption": [0.02, 0.02, 0.01], "scattering": 0.01 },
+                    "medium_extent": 50
+                },
+                "objects": []
+            }"#,
+        )
+        .unwrap();
+        let settings = serde_json::to_value(&camera).unwrap();
+        assert_eq!(
+            settings["medium"],
+            serde_json::to_value(Medium::new(
+                Color::new(0.02, 0.02, 0.01),
+                0.01,
+                Color::new(1.0, 1.0, 1.0)
+            ))
+            .unwrap()
+        );
+        assert_eq!(settings["medium_extent"], 50.0);
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff(EXAMPLE, EXAMPLE).unwrap(), Vec::new());
+        // Spelling a setting differently builds the same scene
+        let respelled = EXAMPLE.replace(r#""fuzz": 0.2"#, r#""roughness": 0.2"#);
+        assert_eq!(diff(EXAMPLE, &respelled).unwrap(), Vec::new());
+
+        let changed = EXAMPLE
+            .replace(r#""vertical_fov": 35"#, r#""vertical_fov": 40"#)
+            .replace(r#""fuzz": 0.2"#, r#""fuzz": 0.3"#)
+            .replace(
+                r#",
+            { "type": "box", "min": [2, 0, -1], "max": [3, 1, 0], "material": "glass" }"#,
+                "",
+            );
+        let differences: Vec<String> = diff(EXAMPLE, &changed)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(differences.len(), 3, "{:#?}", differences);
+        assert_eq!(differences[0], "camera.vertical_fov: 35.0 -> 40.0");
+        assert_eq!(differences[1], "materials.gold.Metal.roughness: 0.2 -> 0.3");
+        assert!(
+            differences[2].starts_with("objects[2]: only in the first scene ({"),
+            "{}",
+            differences[2]
+        );
+
+        assert!(matches!(diff(EXAMPLE, "{}"), Err(SceneError::Parse(_))));
+    }
+
+    #[test]
+    fn test_errors_say_what_is_wrong() {
+        let error = |text: &str| parse_str(text).err().unwrap().to_string();
+
+        assert_eq!(
+            error(
+                r#"{ "objects": [{ "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "chalk" }] }"#
+            ),
+            "object 0 uses unknown material 'chalk'"
+        );
+        let missing = error(
+            r#"{ "materials": { "m": { "type": "lambertian", "texture": [1, 1, 1] } },
+                "objects": [{ "type": "sphere", "center": [0, 0, 0], "material": "m" }] }"#,
+        );
+        assert!(missing.contains("missing field `radius`"), "{}", missing);
+        assert!(missing.contains("line 2"), "{}", missing);
+        let unknown = error(r#"{ "materials": { "m": { "type": "velvet" } }, "objects": [] }"#);
+        assert!(unknown.contains("unknown variant `velvet`"), "{}", unknown);
+        assert!(unknown.contains("lambertian"), "{}", unknown);
+        assert_eq!(
+            error(
+                r#"{ "materials": { "m": { "type": "lambertian", "texture": "fur" } }, "objects": [] }"#
+            ),
+            "material 'm' uses unknown texture 'fur'"
+        );
+        assert_eq!(
+            error(
+                r#"{ "materials": { "m": { "type": "metal", "conductor": "brass" } }, "objects": [] }"#
+            ),
+            "material 'm' is made of unknown metal 'brass'"
+        );
+        assert_eq!(
+            error(
+                r#"{ "textures": { "a": { "type": "checker", "scale": 1, "odd": "a", "even": "a" } },
+                    "materials": { "m": { "type": "lambertian", "texture": "a" } }, "objects": [] }"#
+            ),
+            "texture 'a' is made of itself"
+        );
+        let missing_file = error(
+            r#"{ "materials": { "m": { "type": "lambertian", "texture": [1, 1, 1] } },
+                "objects": [{ "type": "mesh", "path": "no/such/file.obj", "material": "m" }] }"#,
+        );
+        assert!(missing_file.starts_with("object 0: "), "{}", missing_file);
+        // A file shared by several objects is reported against the first of them
+        let missing_shared = error(
+            r#"{ "materials": { "m": { "type": "lambertian", "texture": [1, 1, 1] } },
+                "objects": [
+                    { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "m" },
+                    { "type": "mesh", "path": "no/such/file.obj", "material": "m" },
+                    { "type": "mesh", "path": "no/such/file.obj", "material": "m" }
+                ] }"#,
+        );
+        assert!(
+            missing_shared.starts_with("object 1: "),
+            "{}",
+            missing_shared
+        );
+        let typo = error(r#"{ "camera": { "fov": 30 }, "objects": [] }"#);
+        assert!(typo.contains("unknown field `fov`"), "{}", typo);
+        assert!(parse_str(r#"{ "objects": [] }"#).unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn test_load_with_overrides() {
+        let path = std::env::temp_dir().join(format!(
+            "raytrace-scene-overrides-{}.json",
+            std::process::id()
+        ));
+        fs::write(&path, EXAMPLE).unwrap();
+        let overrides: Vec<Override> = [
+            "materials.gold.fuzz=0.5",
+            "objects.1.radius=0.5",
+            "camera.vertical_fov=50",
+        ]
+        .iter()
+        .map(|setting| setting.parse().unwrap())
+        .collect();
+        let loaded = load_with_overrides(&path, &overrides);
+        let misspelt =
+            load_with_overrides(&path, &["materials.gild.roughness=0.5".parse().unwrap()]);
+        let invalid = load_with_overrides(&path, &["camera.image_width=wide".parse().unwrap()]);
+        fs::remove_file(&path).unwrap();
+
+        let (objects, camera) = loaded.unwrap();
+        let ray = Ray::new(Point3::new(0.0, 1.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let hit = objects[1]
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-9);
+        // Only the material named gold changes, not every metal
+        assert_eq!(
+            hit.material,
+            Some(&Metal::new(Color::new(0.9, 0.7, 0.3), 0.5))
+        );
+        let (_, expected) =
+            parse_str(&EXAMPLE.replace(r#""vertical_fov": 35"#, r#""vertical_fov": 50"#)).unwrap();
+        assert_eq!(format!("{:?}", camera), format!("{:?}", expected));
+
+        assert_eq!(
+            misspelt.err().unwrap().to_string(),
+            "cannot set materials.gild.roughness: the scene has no materials.gild"
+        );
+        let invalid = invalid.err().unwrap().to_string();
+        assert!(invalid.contains("invalid type"), "{}", invalid);
+    }
+
+    #[test]
+    fn test_override_camera() {
+        let camera = CameraBuilder::new().vertical_fov(90.0).max_depth(10);
+        let overrides = [
+            Override::new("camera.vertical_fov", 35),
+            Override::new("camera.look_from", "0,2,8"),
+        ];
+        let expected = CameraBuilder::new()
+            .vertical_fov(35.0)
+            .max_depth(10)
+            .look_from(Point3::new(0.0, 2.0, 8.0));
+        assert_eq!(
+            format!("{:?}", override_camera(camera.clone(), &overrides).unwrap()),
+            format!("{:?}", expected)
+        );
+
+        let material = override_camera(
+            camera.clone(),
+            &[Override::new("materials.glass.refraction_index", 1.45)],
+        );
+        assert_eq!(
+            material.err().unwrap().to_string(),
+            "cannot set materials.glass.refraction_index: \
+only camera settings can be set on a built-in scene"
+        );
+        let typo = override_camera(camera, &[Override::new("camera.fov", 35)]);
+        let typo = typo.err().unwrap().to_string();
+        assert!(typo.contains("unknown field `fov`"), "{}", typo);
+    }
+}
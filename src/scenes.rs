@@ -0,0 +1,1109 @@
+//! Built-in demo scenes, each made of its objects and a camera to view them with,
+//! from a pair of checkered spheres to a city lit by hundreds of lamps. [`SCENES`]
+//! lists them and [`build`] builds one by name.
+//!
+//! Scenes with a random arrangement draw it from the thread's generator, materials
+//! included through [`Material::random_lambertian`] and [`Material::random_metal`],
+//! so seeding it with [`seed_thread_rng`](crate::utilities::seed_thread_rng) first
+//! builds the same scene every time.
+
+use crate::background::{Background, NightSky};
+use crate::camera::{CameraBuilder, LightSampling};
+use crate::color::{Color, ColorSpace};
+use crate::error::Error;
+use crate::hittable::Hittable;
+use crate::instance::Instance;
+use crate::light::{Falloff, PointLight, QuadLight, SpotLight, SunLight};
+use crate::light_linking::{InLightGroup, LightLink};
+use crate::lod::LodGroup;
+use crate::material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal};
+use crate::medium::ConstantMedium;
+use crate::mesh::{AxisConvention, Mesh, TriangleMesh};
+use crate::physics::{Body, Simulation};
+use crate::placement::{self, Placement};
+use crate::point3::Point3;
+use crate::postprocess::Fog;
+use crate::quad::BoxObject;
+use crate::sphere::{MovingSphere, SphereBuilder};
+use crate::texture::{CheckerTexture, SolidColor, TextureEnum, WindowTexture};
+use crate::utilities::{random_double, random_double_range, random_u32, with_thread_rng};
+use crate::vec3::Vec3;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A scene's objects and the camera to view them with.
+pub type Scene = (Vec<Box<dyn Hittable>>, CameraBuilder);
+
+/// The names of the built-in scenes, as [`build`] knows them, and what they show.
+pub const SCENES: [(&str, &str); 11] = [
+    (
+        "checkered_spheres",
+        "Two large checkered spheres, one above the other (the default)",
+    ),
+    (
+        "bouncing_spheres",
+        "Three large spheres among hundreds of small random ones, some bouncing with \
+         motion blur, laid out by --placement",
+    ),
+    (
+        "lit_spheres",
+        "Glass, metal and diffuse spheres lit by a point light and a spotlight",
+    ),
+    (
+        "furnace",
+        "White furnace test: lossless spheres under a uniform sky should vanish",
+    ),
+    (
+        "many_lights",
+        "A grid of spheres under hundreds of small colored lamps, sampled with a light tree",
+    ),
+    (
+        "night",
+        "Spheres under a starry sky with the moon up, lit by a lamp and moonlight",
+    ),
+    (
+        "softbox",
+        "Spheres under a large overhead softbox with a linked rim light and named cameras",
+    ),
+    (
+        "glowing_spheres",
+        "Spheres in the dark lit only by an emissive sphere hanging above them",
+    ),
+    (
+        "city",
+        "Blocks of instanced buildings with glowing windows along streets lit by hundreds \
+         of lamps, sampled with a light tree",
+    ),
+    (
+        "smoke",
+        "A box of white smoke and a ball of black smoke in thin fog, with a spotlight \
+         casting a beam through the fog",
+    ),
+    (
+        "stained_glass",
+        "Sunlight through a stained glass window casting colored light on the floor, with \
+         shadows through the glass",
+    ),
+];
+/// Spacing between the small spheres when they are placed evenly.
+const BOUNCING_SPACING: f64 = 0.8;
+/// Radius of the small spheres of [`bouncing_spheres`].
+pub const MARBLE_RADIUS: f64 = 0.2;
+/// Physics steps taken for each frame of a [`BouncingSimulation`].
+const SIMULATION_STEPS_PER_FRAME: u32 = 20;
+/// Centers of the large glass, brown and metal spheres of [`bouncing_spheres`],
+/// which all have unit radius.
+const CENTERPIECES: [Point3; 3] = [
+    Point3::new(0.0, 1.0, 0.0),
+    Point3::new(-4.0, 1.0, 0.0),
+    Point3::new(4.0, 1.0, 0.0),
+];
+
+/// Builds the built-in scene called `name`, or returns `None` if there is no such
+/// scene.
+///
+/// Only `bouncing_spheres` uses `placement`, `mesh` and `mesh_axes`, as
+/// [`bouncing_spheres`] does.
+pub fn build(
+    name: &str,
+    placement: Placement,
+    mesh: Option<&Path>,
+    mesh_axes: AxisConvention,
+) -> Option<Result<Scene, Error>> {
+    Some(Ok(match name {
+        "checkered_spheres" => checkered_spheres(),
+        "bouncing_spheres" => return Some(bouncing_spheres(placement, mesh, mesh_axes)),
+        "lit_spheres" => lit_spheres(),
+        "furnace" => furnace(),
+        "many_lights" => many_lights(),
+        "night" => night(),
+        "softbox" => softbox(),
+        "glowing_spheres" => glowing_spheres(),
+        "city" => city(),
+        "smoke" => smoke(),
+        "stained_glass" => stained_glass(),
+        _ => return None,
+    }))
+}
+
+/// A small sphere of [`bouncing_spheres`].
+#[derive(Clone)]
+pub struct Marble {
+    pub center: Point3,
+    /// Where the sphere has bounced up to by the end of the shutter, if it moves
+    pub center_end: Option<Point3>,
+    pub material: Material,
+}
+
+/// Scatters the small spheres of [`bouncing_spheres`] over the ground, leaving room
+/// around the large metal sphere.
+///
+/// Most are diffuse and bounce, some are metal and a few are glass, with their
+/// positions, colors and bounces drawn from the thread's generator.
+pub fn marbles(placement: Placement) -> Vec<Marble> {
+    let positions = match placement {
+        Placement::JitteredGrid => placement::jittered_grid((-8.0, -8.0), (8.0, 8.0), 0.9),
+        Placement::PoissonDisk => {
+            placement::poisson_disk((-8.0, -8.0), (8.0, 8.0), BOUNCING_SPACING, |_, _| 1.0)
+        }
+        Placement::GoldenSpiral => placement::golden_spiral((0.0, 0.0), 256, BOUNCING_SPACING),
+    };
+    let mut marbles = Vec::new();
+    for (x, z) in positions {
+        let choose_mat = random_double();
+        let center = Point3::new(x, MARBLE_RADIUS, z);
+        if (center - Point3::new(4.0, MARBLE_RADIUS, 0.0)).length() > 0.9 {
+            marbles.push(if choose_mat < 0.8 {
+                let center_end = center + Vec3::new(0.0, random_double() * 0.5, 0.0);
+                Marble {
+                    center,
+                    center_end: Some(center_end),
+                    material: with_thread_rng(Material::random_lambertian),
+                }
+            } else if choose_mat < 0.95 {
+                Marble {
+                    center,
+                    center_end: None,
+                    material: with_thread_rng(Material::random_metal),
+                }
+            } else {
+                Marble {
+                    center,
+                    center_end: None,
+                    material: Dielectric::new(1.5),
+                }
+            });
+        }
+    }
+    marbles
+}
+
+/// Three large spheres among hundreds of small random ones laid out by
+/// `placement`, some bouncing with motion blur.
+///
+/// The large glass sphere is replaced by the mesh at `mesh`, written in
+/// `mesh_axes`, if given.
+pub fn bouncing_spheres(
+    placement: Placement,
+    mesh: Option<&Path>,
+    mesh_axes: AxisConvention,
+) -> Result<Scene, Error> {
+    let mut objects = vec![bouncing_ground()];
+    for marble in marbles(placement) {
+        let builder = SphereBuilder::new()
+            .center(marble.center)
+            .radius(MARBLE_RADIUS)
+            .material(marble.material);
+        let builder = match marble.center_end {
+            Some(center_end) => builder.center_end(center_end).time_range(0.0, 1.0),
+            None => builder,
+        };
+        objects.push(Box::new(builder.build()?));
+    }
+    objects.extend(bouncing_centerpieces(mesh, mesh_axes)?);
+    Ok((objects, bouncing_camera()))
+}
+
+/// The small spheres of [`bouncing_spheres`] dropped from a little above the
+/// ground to bounce off it, the large spheres and each other.
+pub struct BouncingSimulation {
+    marbles: Vec<Marble>,
+    simulation: Simulation,
+}
+
+impl BouncingSimulation {
+    /// Scatters the small spheres as [`marbles`] does and lifts each to a random
+    /// height with a random drift, drawn from the thread's generator.
+    pub fn new(placement: Placement) -> Self {
+        let marbles = marbles(placement);
+        let bodies = marbles
+            .iter()
+            .map(|marble| {
+                let drop = Vec3::new(0.0, random_double_range(0.5, 3.0), 0.0);
+                let drift = Vec3::new(
+                    random_double_range(-0.5, 0.5),
+                    0.0,
+                    random_double_range(-0.5, 0.5),
+                );
+                Body::dynamic(marble.center + drop, drift, MARBLE_RADIUS)
+            })
+            .chain(CENTERPIECES.map(|center| Body::fixed(center, 1.0)))
+            .collect();
+        Self {
+            marbles,
+            simulation: Simulation::new(bodies),
+        }
+    }
+
+    /// Advances the simulation by `seconds`, returning the scene with each small
+    /// sphere blurred along its motion over them.
+    ///
+    /// The large spheres are made as for [`bouncing_spheres`].
+    pub fn advance(
+        &mut self,
+        seconds: f64,
+        mesh: Option<&Path>,
+        mesh_axes: AxisConvention,
+    ) -> Result<Scene, Error> {
+        let from = self.simulation.positions();
+        self.simulation.advance(seconds, SIMULATION_STEPS_PER_FRAME);
+        let to = self.simulation.positions();
+
+        let mut objects = vec![bouncing_ground()];
+        for (marble, (&from, &to)) in self.marbles.iter().zip(from.iter().zip(&to)) {
+            objects.push(Box::new(MovingSphere::new(
+                (from, to),
+                (0.0, 1.0),
+                MARBLE_RADIUS,
+                marble.material.clone(),
+            )));
+        }
+        objects.extend(bouncing_centerpieces(mesh, mesh_axes)?);
+        Ok((objects, bouncing_camera()))
+    }
+}
+
+fn bouncing_ground() -> Box<dyn Hittable> {
+    Box::new(
+        SphereBuilder::new()
+            .center(Point3::new(0.0, -1000.0, 0.0))
+            .radius(1000.0)
+            .material(Lambertian::new(Box::new(TextureEnum::CheckerTexture(
+                CheckerTexture::new(
+                    3.0,
+                    Box::new(TextureEnum::SolidColor(Color::new(1.0, 1.0, 1.0).into())),
+                    Box::new(TextureEnum::SolidColor(Color::new(0.0, 0.0, 0.0).into())),
+                ),
+            ))))
+            .build()
+            .expect("Failed to build ground sphere"),
+    )
+}
+
+/// Returns the large spheres of [`bouncing_spheres`], with the glass one replaced
+/// by the mesh at `mesh`, written in `mesh_axes`, if given.
+fn bouncing_centerpieces(
+    mesh: Option<&Path>,
+    mesh_axes: AxisConvention,
+) -> Result<Vec<Box<dyn Hittable>>, Error> {
+    let mut objects: Vec<Box<dyn Hittable>> = Vec::new();
+    match mesh {
+        Some(path) => objects.push(Box::new(centerpiece(path, mesh_axes)?)),
+        None => objects.push(Box::new(
+            SphereBuilder::new()
+                .center(CENTERPIECES[0])
+                .radius(1.0)
+                .material(Dielectric::new(1.5))
+                .build()
+                .expect("Failed to build large dielectric sphere"),
+        )),
+    }
+
+    objects.push(Box::new(
+        SphereBuilder::new()
+            .center(CENTERPIECES[1])
+            .radius(1.0)
+            .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
+                Color::new(0.4, 0.2, 0.1).into(),
+            ))))
+            .build()
+            .expect("Failed to build brown lambertian sphere"),
+    ));
+
+    objects.push(Box::new(
+        SphereBuilder::new()
+            .center(CENTERPIECES[2])
+            .radius(1.0)
+            .material(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0))
+            .build()
+            .expect("Failed to build metal sphere"),
+    ));
+    Ok(objects)
+}
+
+fn bouncing_camera() -> CameraBuilder {
+    CameraBuilder::new()
+        .aspect_ratio(16.0 / 9.0)
+        .image_width(800)
+        .samples_per_pixel(100)
+        .max_depth(50)
+        .vertical_fov(20.0)
+        .look_from(Point3::new(13.0, 2.0, 3.0))
+        .look_at(Point3::new(0.0, 0.0, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .defocus_angle(1.0)
+        .focus_dist(10.0)
+}
+
+/// Loads the mesh at `path`, written in `axes`, in glass, scaled to fit the 2-unit
+/// cube the large sphere of [`bouncing_spheres`] fills and standing on the ground
+/// at its center.
+fn centerpiece(path: &Path, axes: AxisConvention) -> Result<TriangleMesh, Error> {
+    let mut mesh = Mesh::load_obj(path)?;
+    mesh.convert_axes(axes);
+    let bounds = mesh.bounds().ok_or(Error::Build("mesh has no vertices"))?;
+    let size = bounds.diagonal();
+    let scale = 2.0 / size.x().max(size.y()).max(size.z());
+    let center = bounds.center();
+    mesh.transform(
+        scale,
+        Vec3::new(
+            -center.x() * scale,
+            -bounds.axis_interval(1).min() * scale,
+            -center.z() * scale,
+        ),
+    );
+    TriangleMesh::new(mesh, Dielectric::new(1.5))
+}
+
+/// Two large checkered spheres, one above the other.
+pub fn checkered_spheres() -> Scene {
+    let mut objects: Vec<Box<dyn Hittable>> = Vec::new();
+
+    let checker = CheckerTexture::new(
+        3.0,
+        Box::new(TextureEnum::SolidColor(Color::new(0.2, 0.3, 0.1).into())),
+        Box::new(TextureEnum::SolidColor(Color::new(0.9, 0.9, 0.9).into())),
+    );
+
+    objects.push(Box::new(
+        SphereBuilder::new()
+            .center(Point3::new(0.0, -10.0, 0.0))
+            .radius(10.0)
+            .material(Lambertian::new(Box::new(TextureEnum::CheckerTexture(
+                checker.clone(),
+            ))))
+            .build()
+            .expect("Failed to build ground sphere"),
+    ));
+
+    objects.push(Box::new(
+        SphereBuilder::new()
+            .center(Point3::new(0.0, 10.0, 0.0))
+            .radius(10.0)
+            .material(Lambertian::new(Box::new(TextureEnum::CheckerTexture(
+                checker.clone(),
+            ))))
+            .build()
+            .expect("Failed to build ground sphere"),
+    ));
+
+    let camera = CameraBuilder::new()
+        .aspect_ratio(16.0 / 9.0)
+        .image_width(800)
+        .samples_per_pixel(100)
+        .max_depth(50)
+        .vertical_fov(20.0)
+        .look_from(Point3::new(13.0, 2.0, 3.0))
+        .look_at(Point3::new(0.0, 0.0, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .defocus_angle(0.0)
+        .focus_dist(10.0);
+
+    (objects, camera)
+}
+
+/// Spheres lit by a large rectangular softbox overhead, for judging the noise in
+/// broad soft shadows.
+///
+/// A rim light is linked to the small sphere alone, so it adds a highlight there
+/// without casting a second set of shadows on the ground.
+pub fn softbox() -> Scene {
+    const RIM_LIT: u32 = 1;
+    let diffuse = |color: Color| Lambertian::new(Box::new(TextureEnum::SolidColor(color.into())));
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, -1000.0, 0.0))
+                .radius(1000.0)
+                .material(diffuse(Color::new(0.6, 0.6, 0.6)))
+                .build()
+                .expect("Failed to build ground sphere"),
+        ),
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(-1.2, 1.0, 0.0))
+                .radius(1.0)
+                .material(diffuse(Color::new(0.7, 0.3, 0.2)))
+                .build()
+                .expect("Failed to build lambertian sphere"),
+        ),
+        Box::new(InLightGroup::new(
+            Box::new(
+                SphereBuilder::new()
+                    .center(Point3::new(1.2, 0.5, 0.8))
+                    .radius(0.5)
+                    .material(diffuse(Color::new(0.2, 0.4, 0.7)))
+                    .build()
+                    .expect("Failed to build lambertian sphere"),
+            ),
+            RIM_LIT,
+        )),
+    ];
+
+    // A 3 × 2 panel facing down, 4 units up, and a rim light on the small sphere only
+    let camera = CameraBuilder::new()
+        .aspect_ratio(16.0 / 9.0)
+        .image_width(800)
+        .samples_per_pixel(16)
+        .max_depth(8)
+        .vertical_fov(35.0)
+        .look_from(Point3::new(0.0, 3.0, 8.0))
+        .look_at(Point3::new(0.0, 0.8, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .background(Background::Uniform(Color::new(0.0, 0.0, 0.0)))
+        .light(QuadLight::new(
+            Point3::new(-1.5, 4.0, -1.0),
+            Vec3::new(3.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Color::new(4.0, 4.0, 4.0),
+        ))
+        .linked_light(
+            PointLight::new(Point3::new(3.0, 1.5, -1.5), Color::new(6.0, 5.0, 4.0)),
+            LightLink::Include(vec![RIM_LIT]),
+        );
+
+    // Product shot angles around the spheres, for `--cameras`
+    let target = Point3::new(0.0, 0.8, 0.0);
+    let camera = [
+        ("front", Point3::new(0.0, 3.0, 8.0)),
+        ("three_quarter", Point3::new(5.5, 3.5, 5.5)),
+        ("left", Point3::new(-8.0, 3.0, 0.0)),
+        ("right", Point3::new(8.0, 3.0, 0.0)),
+        ("back", Point3::new(0.0, 3.0, -8.0)),
+        ("top", Point3::new(0.0, 9.0, 1.0)),
+    ]
+    .into_iter()
+    .fold(camera, |camera, (name, look_from)| {
+        camera.view(name, look_from, target)
+    });
+
+    (objects, camera)
+}
+
+/// White diffuse, mirror and glass spheres under a uniform white sky.
+///
+/// None of them absorb or emit light, so a correct integrator renders a uniformly
+/// white image; any visible sphere points to lost or created energy.
+pub fn furnace() -> Scene {
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(-2.2, 0.0, 0.0))
+                .radius(1.0)
+                .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
+                    Color::new(1.0, 1.0, 1.0).into(),
+                ))))
+                .build()
+                .expect("Failed to build diffuse sphere"),
+        ),
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 0.0, 0.0))
+                .radius(1.0)
+                .material(Metal::new(Color::new(1.0, 1.0, 1.0), 0.0))
+                .build()
+                .expect("Failed to build mirror sphere"),
+        ),
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(2.2, 0.0, 0.0))
+                .radius(1.0)
+                .material(Dielectric::new(1.5))
+                .build()
+                .expect("Failed to build glass sphere"),
+        ),
+    ];
+
+    let camera = CameraBuilder::new()
+        .aspect_ratio(16.0 / 9.0)
+        .image_width(400)
+        .samples_per_pixel(16)
+        .max_depth(50)
+        .vertical_fov(30.0)
+        .look_from(Point3::new(0.0, 0.0, 12.0))
+        .look_at(Point3::new(0.0, 0.0, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .background(Background::Uniform(Color::new(1.0, 1.0, 1.0)));
+
+    (objects, camera)
+}
+
+/// Glass, metal and diffuse spheres lit by a point light and a spotlight.
+pub fn lit_spheres() -> Scene {
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, -1000.0, 0.0))
+                .radius(1000.0)
+                .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
+                    Color::new(0.5, 0.5, 0.5).into(),
+                ))))
+                .build()
+                .expect("Failed to build ground sphere"),
+        ),
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(-2.2, 1.0, 0.0))
+                .radius(1.0)
+                .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
+                    Color::new(0.7, 0.2, 0.1).into(),
+                ))))
+                .build()
+                .expect("Failed to build lambertian sphere"),
+        ),
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, 1.0, 0.0))
+                .radius(1.0)
+                .material(Dielectric::new(1.5))
+                .build()
+                .expect("Failed to build dielectric sphere"),
+        ),
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(2.2, 1.0, 0.0))
+                .radius(1.0)
+                .material(Metal::new(Color::new(0.8, 0.8, 0.8), 0.1))
+                .build()
+                .expect("Failed to build metal sphere"),
+        ),
+    ];
+
+    let camera = CameraBuilder::new()
+        .aspect_ratio(16.0 / 9.0)
+        .image_width(800)
+        .samples_per_pixel(100)
+        .max_depth(50)
+        .vertical_fov(30.0)
+        .look_from(Point3::new(0.0, 3.0, 10.0))
+        .look_at(Point3::new(0.0, 1.0, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .light(
+            PointLight::new(Point3::new(-3.0, 5.0, 3.0), Color::new(40.0, 32.0, 24.0)).radius(0.5),
+        )
+        .light(
+            SpotLight::new(
+                Point3::new(4.0, 6.0, 0.0),
+                Vec3::new(-4.0, -6.0, 0.0),
+                Color::new(60.0, 60.0, 80.0),
+                25.0,
+                5.0,
+            )
+            .radius(0.2),
+        )
+        .fog(Fog::new(Color::new(0.7, 0.75, 0.8), 0.02))
+        .light(
+            PointLight::new(Point3::new(0.0, 8.0, 8.0), Color::new(0.2, 0.2, 0.2))
+                .falloff(Falloff::Constant),
+        )
+        .light(SunLight::new(
+            Vec3::new(-1.0, -2.0, -1.0),
+            Color::new(0.6, 0.55, 0.5),
+            5.0,
+        ));
+
+    (objects, camera)
+}
+
+/// Spheres under a starry sky with the moon up, lit by a lamp and faint moonlight.
+pub fn night() -> Scene {
+    let sky = NightSky::default();
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, -1000.0, 0.0))
+                .radius(1000.0)
+                .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
+                    // Mossy grass, as picked in an image editor
+                    SolidColor::tagged(Color::new(0.45, 0.5, 0.4), ColorSpace::Srgb),
+                ))))
+                .build()
+                .expect("Failed to build ground sphere"),
+        ),
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(-1.2, 1.0, 0.0))
+                .radius(1.0)
+                .material(Metal::new(Color::new(0.9, 0.9, 0.9), 0.0))
+                .build()
+                .expect("Failed to build mirror sphere"),
+        ),
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(1.2, 1.0, 0.0))
+                .radius(1.0)
+                .material(Dielectric::new(1.5))
+                .build()
+                .expect("Failed to build glass sphere"),
+        ),
+    ];
+
+    let camera = CameraBuilder::new()
+        .aspect_ratio(16.0 / 9.0)
+        .image_width(800)
+        .samples_per_pixel(64)
+        .max_depth(20)
+        .vertical_fov(50.0)
+        .look_from(Point3::new(0.0, 1.0, 8.0))
+        .look_at(Point3::new(0.0, 2.5, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .light(
+            PointLight::new(
+                Point3::new(0.0, 2.5, 2.0),
+                Color::from_blackbody(2700.0) * 4.0,
+            )
+            .radius(0.1),
+        )
+        .light(SunLight::new(
+            -sky.moon_direction,
+            Color::new(0.05, 0.06, 0.08),
+            (2.0 * sky.moon_radius).to_degrees(),
+        ))
+        .background(Background::Night(sky));
+
+    (objects, camera)
+}
+
+/// A grid of spheres lit by hundreds of small colored lamps, like a city at night.
+///
+/// Sampling every lamp at each bounce would cost hundreds of shadow rays, so the
+/// camera picks one from a light tree instead.
+pub fn many_lights() -> Scene {
+    let mut objects: Vec<Box<dyn Hittable>> = vec![Box::new(
+        SphereBuilder::new()
+            .center(Point3::new(0.0, -1000.0, 0.0))
+            .radius(1000.0)
+            .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
+                Color::new(0.4, 0.4, 0.4).into(),
+            ))))
+            .build()
+            .expect("Failed to build ground sphere"),
+    )];
+    let mut camera = CameraBuilder::new()
+        .aspect_ratio(16.0 / 9.0)
+        .image_width(800)
+        .samples_per_pixel(64)
+        .max_depth(8)
+        .vertical_fov(40.0)
+        .look_from(Point3::new(0.0, 12.0, 24.0))
+        .look_at(Point3::new(0.0, 0.0, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .background(Background::Uniform(Color::new(0.01, 0.01, 0.02)))
+        .light_sampling(LightSampling::Tree);
+
+    for a in -10..10 {
+        for b in -10..10 {
+            let (x, z) = (a as f64 * 2.0, b as f64 * 2.0);
+            if a % 2 == 0 && b % 2 == 0 {
+                let radius = 0.4 + 0.4 * random_double();
+                objects.push(Box::new(
+                    SphereBuilder::new()
+                        .center(Point3::new(x + 1.0, radius, z + 1.0))
+                        .radius(radius)
+                        .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
+                            Color::new(0.8, 0.8, 0.8).into(),
+                        ))))
+                        .build()
+                        .expect("Failed to build building sphere"),
+                ));
+            }
+            let lamp = Color::new(random_double(), random_double(), random_double()) * 2.0;
+            camera = camera.light(PointLight::new(Point3::new(x, 0.3, z), lamp).radius(0.05));
+        }
+    }
+
+    (objects, camera)
+}
+
+/// Spheres in the dark lit only by an emissive sphere hanging above them.
+pub fn glowing_spheres() -> Scene {
+    let diffuse = |color: Color| Lambertian::new(Box::new(TextureEnum::SolidColor(color.into())));
+    let mut objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, -1000.0, 0.0))
+                .radius(1000.0)
+                .material(diffuse(Color::new(0.5, 0.5, 0.5)))
+                .build()
+                .expect("Failed to build ground sphere"),
+        ),
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(-1.2, 1.0, 0.0))
+                .radius(1.0)
+                .material(diffuse(Color::new(0.7, 0.2, 0.2)))
+                .build()
+                .expect("Failed to build red sphere"),
+        ),
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(1.2, 1.0, 0.0))
+                .radius(1.0)
+                .material(Metal::new(Color::new(0.8, 0.8, 0.8), 0.05))
+                .build()
+                .expect("Failed to build metal sphere"),
+        ),
+    ];
+    let lamp: Arc<dyn Hittable> = Arc::new(
+        SphereBuilder::new()
+            .center(Point3::new(0.0, 4.0, 0.0))
+            .radius(1.0)
+            .material(DiffuseLight::new(Box::new(TextureEnum::SolidColor(
+                Color::new(8.0, 7.0, 6.0).into(),
+            ))))
+            .build()
+            .expect("Failed to build glowing sphere"),
+    );
+    objects.push(Box::new(lamp.clone()));
+
+    let camera = CameraBuilder::new()
+        .aspect_ratio(16.0 / 9.0)
+        .image_width(400)
+        .samples_per_pixel(100)
+        .max_depth(50)
+        .vertical_fov(30.0)
+        .look_from(Point3::new(0.0, 2.0, 12.0))
+        .look_at(Point3::new(0.0, 1.5, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .background(Background::Uniform(Color::new(0.0, 0.0, 0.0)))
+        // Diffuse bounces aim at the lamp rather than waiting to bounce into it
+        .light_object(lamp)
+        .expect("A sphere can be sampled as a light");
+
+    (objects, camera)
+}
+
+/// Street centerlines are this far apart in the city, each way.
+const CITY_BLOCK: f64 = 5.0;
+/// Streets either side of the middle of the city, each way.
+const CITY_STREETS: i32 = 5;
+/// Height of a storey; buildings are whole storeys tall so their roofs stay dark.
+const CITY_FLOOR_HEIGHT: f64 = 0.25;
+/// Buildings further than this from the camera are drawn without their rooftop tanks.
+const CITY_DETAIL_DISTANCE: f64 = 40.0;
+
+/// A grid of city blocks at night, their buildings lit from within and the streets
+/// lit by hundreds of lamps.
+///
+/// Every building is an [`Instance`] of one unit cube stretched to its size, and
+/// the window materials are shared, so the city costs little more memory than a
+/// single box. Rooftop water tanks are left off buildings far from the camera by
+/// level of detail groups. The lamps are sampled through a light tree, as in
+/// [`many_lights`].
+pub fn city() -> Scene {
+    let mut objects: Vec<Box<dyn Hittable>> = vec![Box::new(
+        SphereBuilder::new()
+            .center(Point3::new(0.0, -1000.0, 0.0))
+            .radius(1000.0)
+            .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
+                Color::new(0.08, 0.08, 0.08).into(),
+            ))))
+            .build()
+            .expect("Failed to build ground sphere"),
+    )];
+    let windows: Vec<Arc<Material>> = [
+        Color::new(3.0, 2.2, 1.2),
+        Color::new(1.8, 2.2, 3.0),
+        Color::new(2.4, 2.6, 2.2),
+    ]
+    .into_iter()
+    .map(|color| {
+        let texture =
+            WindowTexture::new(color, CITY_FLOOR_HEIGHT, 0.3).seed(u64::from(random_u32()));
+        Arc::new(DiffuseLight::new(Box::new(TextureEnum::Windows(texture))))
+    })
+    .collect();
+    let unit_cube: Arc<dyn Hittable> = Arc::new(
+        TriangleMesh::new(
+            Mesh::cuboid(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0)),
+            Arc::clone(&windows[0]),
+        )
+        .expect("Failed to build building mesh"),
+    );
+    let tank = Arc::new(Lambertian::new(Box::new(TextureEnum::SolidColor(
+        Color::new(0.3, 0.25, 0.2).into(),
+    ))));
+
+    // Each block between the streets is split into four lots, a few left empty
+    let half_width = CITY_STREETS as f64 * CITY_BLOCK;
+    for a in -CITY_STREETS..CITY_STREETS {
+        for b in -CITY_STREETS..CITY_STREETS {
+            for lot in 0..4 {
+                if random_double() < 0.1 {
+                    continue;
+                }
+                let x = a as f64 * CITY_BLOCK + 0.6 + (lot % 2) as f64 * 1.9;
+                let z = b as f64 * CITY_BLOCK + 0.6 + (lot / 2) as f64 * 1.9;
+                // Downtown rises towards the middle of the city
+                let distance = (x * x + z * z).sqrt() / half_width;
+                let tallest = 6.0 + 30.0 * (-4.0 * distance * distance).exp();
+                let floors = random_double_range(2.0, tallest).floor();
+                let material = &windows[(random_double() * windows.len() as f64) as usize];
+                let height = floors * CITY_FLOOR_HEIGHT;
+                let building = || -> Box<dyn Hittable> {
+                    Box::new(
+                        Instance::new(Arc::clone(&unit_cube), Vec3::new(x, 0.0, z))
+                            .scale(Vec3::new(1.7, height, 1.7))
+                            .material(Arc::clone(material)),
+                    )
+                };
+                let mut detailed = vec![building()];
+                if random_double() < 0.5 {
+                    detailed.push(Box::new(
+                        Instance::new(Arc::clone(&unit_cube), Vec3::new(x + 0.4, height, z + 0.6))
+                            .scale(Vec3::new(0.5, 0.4, 0.5))
+                            .material(Arc::clone(&tank)),
+                    ));
+                }
+                objects.push(Box::new(
+                    LodGroup::new(detailed)
+                        .and_then(|group| group.level(CITY_DETAIL_DISTANCE, vec![building()]))
+                        .expect("Buildings have bounding boxes"),
+                ));
+            }
+        }
+    }
+
+    let mut camera = CameraBuilder::new()
+        .aspect_ratio(16.0 / 9.0)
+        .image_width(800)
+        .samples_per_pixel(64)
+        .max_depth(8)
+        .vertical_fov(35.0)
+        .look_from(Point3::new(32.0, 20.0, 40.0))
+        .look_at(Point3::new(0.0, 2.0, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .background(Background::Uniform(Color::new(0.005, 0.006, 0.012)))
+        .light_sampling(LightSampling::Tree);
+
+    // Lamps every half block along the streets, once at each crossing
+    let sodium = Color::new(1.5, 0.9, 0.35);
+    for street in -CITY_STREETS..=CITY_STREETS {
+        let across = street as f64 * CITY_BLOCK;
+        for step in -2 * CITY_STREETS..=2 * CITY_STREETS {
+            let along = step as f64 * CITY_BLOCK / 2.0;
+            camera =
+                camera.light(PointLight::new(Point3::new(across, 0.3, along), sodium).radius(0.05));
+            if step % 2 != 0 {
+                camera = camera
+                    .light(PointLight::new(Point3::new(along, 0.3, across), sodium).radius(0.05));
+            }
+        }
+    }
+
+    (objects, camera)
+}
+
+/// Smoke and fog: constant media lit by a spotlight whose beam shows in the fog.
+pub fn smoke() -> Scene {
+    let isotropic = |color: Color| Isotropic::new(Box::new(TextureEnum::SolidColor(color.into())));
+    let boundary = |min: Point3, max: Point3| -> Box<dyn Hittable> {
+        Box::new(
+            TriangleMesh::new(Mesh::cuboid(min, max), isotropic(Color::new(1.0, 1.0, 1.0)))
+                .expect("Failed to build smoke boundary"),
+        )
+    };
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, -1000.0, 0.0))
+                .radius(1000.0)
+                .material(Lambertian::new(Box::new(TextureEnum::SolidColor(
+                    Color::new(0.5, 0.5, 0.5).into(),
+                ))))
+                .build()
+                .expect("Failed to build ground sphere"),
+        ),
+        Box::new(ConstantMedium::new(
+            boundary(Point3::new(-2.6, 0.0, -1.0), Point3::new(-0.6, 2.0, 1.0)),
+            2.0,
+            isotropic(Color::new(0.9, 0.9, 0.9)),
+        )),
+        Box::new(ConstantMedium::new(
+            Box::new(
+                SphereBuilder::new()
+                    .center(Point3::new(1.6, 1.0, 0.0))
+                    .radius(1.0)
+                    .material(isotropic(Color::new(1.0, 1.0, 1.0)))
+                    .build()
+                    .expect("Failed to build smoke sphere"),
+            ),
+            3.0,
+            isotropic(Color::new(0.05, 0.05, 0.05)),
+        )),
+        // Thin fog over the whole scene, for the beam to show in
+        Box::new(ConstantMedium::new(
+            boundary(Point3::new(-12.0, 0.0, -12.0), Point3::new(12.0, 8.0, 12.0)),
+            0.04,
+            isotropic(Color::new(0.9, 0.9, 0.9)),
+        )),
+    ];
+
+    let camera = CameraBuilder::new()
+        .aspect_ratio(16.0 / 9.0)
+        .image_width(800)
+        .samples_per_pixel(128)
+        .max_depth(16)
+        .vertical_fov(40.0)
+        .look_from(Point3::new(0.0, 2.0, 10.0))
+        .look_at(Point3::new(0.0, 1.5, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .background(Background::Uniform(Color::new(0.0, 0.0, 0.0)))
+        .light(
+            SpotLight::new(
+                Point3::new(-1.5, 7.0, -2.0),
+                Vec3::new(1.5, -7.0, 2.0),
+                Color::new(300.0, 270.0, 220.0),
+                20.0,
+                4.0,
+            )
+            .radius(0.5),
+        );
+
+    (objects, camera)
+}
+
+/// Colors of the panes of the stained glass window.
+const STAINED_GLASS: [Color; 5] = [
+    Color::new(0.9, 0.15, 0.1),
+    Color::new(0.95, 0.6, 0.1),
+    Color::new(0.1, 0.8, 0.3),
+    Color::new(0.15, 0.3, 0.95),
+    Color::new(0.6, 0.2, 0.9),
+];
+
+/// A stained glass window in a wall, with the sun shining through it onto the floor.
+///
+/// The camera lets shadow rays through the glass, so the sunlight reaches the floor
+/// colored by the panes it passed through.
+pub fn stained_glass() -> Scene {
+    let diffuse = |color: Color| Lambertian::new(Box::new(TextureEnum::SolidColor(color.into())));
+    let block = |min: Point3, max: Point3, material: Material| -> Box<dyn Hittable> {
+        Box::new(BoxObject::new(min, max, material))
+    };
+    let stone = || diffuse(Color::new(0.6, 0.55, 0.5));
+
+    let mut objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(
+            SphereBuilder::new()
+                .center(Point3::new(0.0, -1000.0, 0.0))
+                .radius(1000.0)
+                .material(diffuse(Color::new(0.7, 0.7, 0.7)))
+                .build()
+                .expect("Failed to build ground sphere"),
+        ),
+        // The wall around a 3 × 2.4 window
+        block(
+            Point3::new(-6.0, 0.0, -0.1),
+            Point3::new(-1.5, 5.0, 0.1),
+            stone(),
+        ),
+        block(
+            Point3::new(1.5, 0.0, -0.1),
+            Point3::new(6.0, 5.0, 0.1),
+            stone(),
+        ),
+        block(
+            Point3::new(-1.5, 0.0, -0.1),
+            Point3::new(1.5, 1.0, 0.1),
+            stone(),
+        ),
+        block(
+            Point3::new(-1.5, 3.4, -0.1),
+            Point3::new(1.5, 5.0, 0.1),
+            stone(),
+        ),
+    ];
+    for column in 0..4 {
+        for row in 0..3 {
+            let x = -1.5 + 0.75 * column as f64;
+            let y = 1.0 + 0.8 * row as f64;
+            let tint = STAINED_GLASS[(column + 2 * row) % STAINED_GLASS.len()];
+            objects.push(block(
+                Point3::new(x, y, -0.02),
+                Point3::new(x + 0.75, y + 0.8, 0.02),
+                Dielectric::tinted(1.5, tint),
+            ));
+        }
+    }
+
+    let camera = CameraBuilder::new()
+        .aspect_ratio(16.0 / 9.0)
+        .image_width(800)
+        .samples_per_pixel(64)
+        .max_depth(12)
+        .vertical_fov(45.0)
+        .look_from(Point3::new(5.0, 3.5, 9.0))
+        .look_at(Point3::new(0.0, 1.2, 1.5))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .background(Background::Uniform(Color::new(0.15, 0.18, 0.25)))
+        .light(SunLight::new(
+            Vec3::new(0.3, -0.6, 1.0),
+            Color::new(4.0, 3.8, 3.5),
+            0.5,
+        ))
+        .transparent_shadows(true);
+
+    (objects, camera)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::seed_thread_rng;
+
+    #[test]
+    fn test_bouncing_spheres_follow_the_seed() {
+        let centers = |seed| {
+            seed_thread_rng(seed);
+            marbles(Placement::JitteredGrid)
+                .into_iter()
+                .map(|marble| (marble.center, marble.material))
+                .collect::<Vec<_>>()
+        };
+        let first = centers(7);
+        assert!(!first.is_empty());
+        assert!(first == centers(7));
+        assert!(first != centers(8));
+
+        seed_thread_rng(7);
+        let (objects, _) =
+            bouncing_spheres(Placement::JitteredGrid, None, AxisConvention::default()).unwrap();
+        // The ground and the three large spheres besides the small ones
+        assert_eq!(objects.len(), first.len() + 4);
+    }
+
+    #[test]
+    fn test_bouncing_simulation_falls() {
+        seed_thread_rng(3);
+        let mut simulation = BouncingSimulation::new(Placement::GoldenSpiral);
+        let count = simulation.marbles.len();
+        let start = simulation.simulation.positions();
+        let (objects, _) = simulation
+            .advance(0.1, None, AxisConvention::default())
+            .unwrap();
+        assert_eq!(objects.len(), count + 4);
+        let end = simulation.simulation.positions();
+        assert!(end[0].y() < start[0].y());
+    }
+
+    #[test]
+    fn test_build_knows_every_listed_scene() {
+        for (name, _) in SCENES {
+            let (objects, _) = build(name, Placement::default(), None, AxisConvention::default())
+                .unwrap_or_else(|| panic!("no scene called {}", name))
+                .unwrap();
+            assert!(!objects.is_empty(), "{} is empty", name);
+        }
+        assert!(
+            build(
+                "checkered",
+                Placement::default(),
+                None,
+                AxisConvention::default()
+            )
+            .is_none()
+        );
+    }
+}
@@ -4,20 +4,37 @@
 //! allowing rays to intersect with spheres in the scene.
 
 use crate::aabb::Aabb;
-use crate::hittable::{HitRecord, Hittable};
+use crate::error::Error;
+use crate::hittable::{HitRecord, Hittable, UNIFORM_SPHERE_PDF, uniform_sphere_direction};
 use crate::interval::Interval;
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::point3::Point3;
-use crate::ray::Ray;
+use crate::ray::{PrimitiveId, Ray};
 use crate::vec3::Vec3;
+use rand::{Rng, RngCore};
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 /// A sphere defined by its center point, radius, and material.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "SphereFields"))]
 pub struct Sphere {
     center: Point3,
     radius: f64,
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
     radius_squared: f64, // Pre-computed for efficiency
-    material: Material,
+    material: Arc<Material>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    id: PrimitiveId,
+}
+
+/// A copy is a separate primitive, so it gets its own ID.
+impl Clone for Sphere {
+    fn clone(&self) -> Self {
+        Sphere::new(self.center, self.radius, Arc::clone(&self.material))
+    }
 }
 
 impl Sphere {
@@ -33,22 +50,40 @@ impl Sphere {
     ///
     /// A new `Sphere` instance
     #[inline]
-    pub fn new(center: Point3, radius: f64, material: Material) -> Self {
+    pub fn new(center: Point3, radius: f64, material: impl Into<Arc<Material>>) -> Self {
         Self {
             center,
             radius: radius.max(0.0),
             radius_squared: radius * radius,
-            material,
+            material: material.into(),
+            id: PrimitiveId::next(false),
         }
     }
 }
 
+/// The stored fields of a sphere, which deserialize into one with the
+/// precomputed fields filled in.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct SphereFields {
+    center: Point3,
+    radius: f64,
+    material: Arc<Material>,
+}
+
+#[cfg(feature = "serde")]
+impl From<SphereFields> for Sphere {
+    fn from(fields: SphereFields) -> Self {
+        Sphere::new(fields.center, fields.radius, fields.material)
+    }
+}
+
 /// A builder for creating `Sphere` instances with a fluent interface.
 #[derive(Debug, Default)]
 pub struct SphereBuilder {
     center: Point3,
     radius: f64,
-    material: Option<Material>,
+    material: Option<Arc<Material>>,
     // New fields for moving sphere
     center_end: Option<Point3>,
     time_start: Option<f64>,
@@ -83,10 +118,10 @@ impl SphereBuilder {
         self
     }
 
-    /// Sets the material of the sphere.
+    /// Sets the material of the sphere, which may be shared with other objects.
     #[inline]
-    pub fn material(mut self, material: Material) -> Self {
-        self.material = Some(material);
+    pub fn material(mut self, material: impl Into<Arc<Material>>) -> Self {
+        self.material = Some(material.into());
         self
     }
 
@@ -109,18 +144,20 @@ impl SphereBuilder {
     ///
     /// # Returns
     ///
-    /// Returns `Some(SphereType)` if all required fields are set, `None` otherwise.
-    /// The returned object will be either a `Sphere` or `MovingSphere` depending on whether
-    /// moving properties were set.
+    /// Returns the sphere if all required fields are set, or [`Error::Build`] if the
+    /// material is missing. The returned object will be either a `Sphere` or
+    /// `MovingSphere` depending on whether moving properties were set.
     #[inline]
-    pub fn build(self) -> Option<SphereType> {
-        let material = self.material?;
+    pub fn build(self) -> Result<SphereType, Error> {
+        let material = self
+            .material
+            .ok_or(Error::Build("sphere has no material"))?;
 
         // If we have all the moving sphere properties, create a MovingSphere
         if let (Some(center_end), Some(time_start), Some(time_end)) =
             (self.center_end, self.time_start, self.time_end)
         {
-            Some(SphereType::Moving(MovingSphere::new(
+            Ok(SphereType::Moving(MovingSphere::new(
                 (self.center, center_end),
                 (time_start, time_end),
                 self.radius,
@@ -128,7 +165,7 @@ impl SphereBuilder {
             )))
         } else {
             // Otherwise create a regular Sphere
-            Some(SphereType::Static(Sphere::new(
+            Ok(SphereType::Static(Sphere::new(
                 self.center,
                 self.radius,
                 material,
@@ -139,6 +176,7 @@ impl SphereBuilder {
 
 /// An enum that can hold either a regular Sphere or a MovingSphere
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SphereType {
     Static(Sphere),
     Moving(MovingSphere),
@@ -146,7 +184,7 @@ pub enum SphereType {
 
 impl Hittable for SphereType {
     #[inline]
-    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
         match self {
             SphereType::Static(sphere) => sphere.hit(ray, ray_t),
             SphereType::Moving(sphere) => sphere.hit(ray, ray_t),
@@ -160,11 +198,195 @@ impl Hittable for SphereType {
             SphereType::Moving(sphere) => sphere.bounding_box(time0, time1),
         }
     }
+
+    fn visit_materials(&mut self, visit: &mut dyn FnMut(&mut Arc<Material>)) {
+        match self {
+            SphereType::Static(sphere) => visit(&mut sphere.material),
+            SphereType::Moving(sphere) => visit(&mut sphere.material),
+        }
+    }
+
+    fn degenerate_reason(&self) -> Option<&'static str> {
+        match self {
+            SphereType::Static(sphere) => sphere.degenerate_reason(),
+            SphereType::Moving(sphere) => sphere.degenerate_reason(),
+        }
+    }
+
+    fn surface_at(&self, uv: (f64, f64)) -> Option<HitRecord<'_>> {
+        match self {
+            SphereType::Static(sphere) => Some(sphere_surface_at(
+                sphere.center,
+                sphere.radius,
+                &sphere.material,
+                sphere.id,
+                uv,
+            )),
+            SphereType::Moving(sphere) => Some(sphere_surface_at(
+                sphere.center_at(sphere.time.0),
+                sphere.radius,
+                &sphere.material,
+                sphere.id,
+                uv,
+            )),
+        }
+    }
+
+    /// Moving spheres sweep out more than a ball, so only their bounds are known.
+    fn solid_sphere(&self) -> Option<(Point3, f64)> {
+        match self {
+            SphereType::Static(sphere) => Some((sphere.center, sphere.radius)),
+            SphereType::Moving(_) => None,
+        }
+    }
+
+    fn is_sampleable(&self) -> bool {
+        true
+    }
+
+    /// Moving spheres are sampled where they start.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        match self {
+            SphereType::Static(sphere) => {
+                cone_pdf_value(sphere.center, sphere.radius, origin, direction)
+            }
+            SphereType::Moving(sphere) => cone_pdf_value(
+                sphere.center_at(sphere.time.0),
+                sphere.radius,
+                origin,
+                direction,
+            ),
+        }
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        match self {
+            SphereType::Static(sphere) => {
+                sphere_cap_direction(sphere.center, sphere.radius, origin, random_point(rng))
+            }
+            SphereType::Moving(sphere) => sphere_cap_direction(
+                sphere.center_at(sphere.time.0),
+                sphere.radius,
+                origin,
+                random_point(rng),
+            ),
+        }
+    }
+}
+
+fn random_point(rng: &mut dyn RngCore) -> (f64, f64) {
+    (rng.random(), rng.random())
+}
+
+/// Returns the cosine of the half-angle of the cone a sphere subtends from a point
+/// `distance_squared` from its center, and one minus it, which is computed
+/// separately to stay accurate for distant spheres.
+fn cone_angle(radius: f64, distance_squared: f64) -> (f64, f64) {
+    let sin_squared = radius * radius / distance_squared;
+    let cos_theta_max = (1.0 - sin_squared).max(0.0).sqrt();
+    (cos_theta_max, sin_squared / (1.0 + cos_theta_max))
+}
+
+/// Density of `sphere_cap_direction` picking `direction` from `origin`: uniform over the
+/// cone the sphere subtends, or over all directions from inside it.
+fn cone_pdf_value(center: Point3, radius: f64, origin: &Point3, direction: &Vec3) -> f64 {
+    let to_center = center - *origin;
+    let distance_squared = to_center.length_squared();
+    if distance_squared <= radius * radius {
+        return UNIFORM_SPHERE_PDF;
+    }
+
+    let (cos_theta_max, one_minus_cos) = cone_angle(radius, distance_squared);
+    let cosine = direction.unit().dot(&to_center) / distance_squared.sqrt();
+    if cosine < cos_theta_max {
+        return 0.0;
+    }
+    1.0 / (2.0 * PI * one_minus_cos)
+}
+
+/// Maps a point `u` in [0, 1)² to a unit direction from `origin`, uniformly over the
+/// cone a sphere subtends, i.e. towards the cap of the sphere visible from `origin`.
+///
+/// Unlike picking points over the whole surface, no samples are wasted on the far
+/// side. From inside the sphere every direction is equally likely.
+pub fn sphere_cap_direction(center: Point3, radius: f64, origin: &Point3, u: (f64, f64)) -> Vec3 {
+    let to_center = center - *origin;
+    let distance_squared = to_center.length_squared();
+    if distance_squared <= radius * radius {
+        return uniform_sphere_direction(u);
+    }
+
+    let (_, one_minus_cos) = cone_angle(radius, distance_squared);
+    let z = 1.0 - u.0 * one_minus_cos;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Onb::new(&to_center).transform(&Vec3::new(r * phi.cos(), r * phi.sin(), z))
+}
+
+/// Returns the point on a sphere with texture coordinates `uv`, facing outwards.
+fn sphere_surface_at(
+    center: Point3,
+    radius: f64,
+    material: &Material,
+    id: PrimitiveId,
+    uv: (f64, f64),
+) -> HitRecord<'_> {
+    // Inverts get_sphere_uv
+    let theta = uv.1 * std::f64::consts::PI;
+    let phi = uv.0 * 2.0 * std::f64::consts::PI - std::f64::consts::PI;
+    let normal = Vec3::new(
+        theta.sin() * phi.cos(),
+        -theta.cos(),
+        -theta.sin() * phi.sin(),
+    );
+
+    HitRecord {
+        t: 0.0,
+        position: center + normal * radius,
+        front_face: true,
+        material: Some(material),
+        texture_coords: uv,
+        normal,
+        geometric_normal: normal,
+        differentials: None,
+        light_group: None,
+        primitive: Some(id),
+        exclude_primitive: false,
+    }
+}
+
+/// Returns the nearer of a sphere's two intersections `near` and `far` that lies
+/// within `ray_t`.
+///
+/// A ray that left this sphere starts on it, so when `started_on_sphere` the
+/// intersection closest to its origin is that start point and is skipped.
+fn nearest_root(near: f64, far: f64, ray_t: Interval, started_on_sphere: bool) -> Option<f64> {
+    let roots = if !started_on_sphere {
+        [Some(near), Some(far)]
+    } else if near.abs() <= far.abs() {
+        [None, Some(far)]
+    } else {
+        [Some(near), None]
+    };
+    roots
+        .into_iter()
+        .flatten()
+        .find(|&root| ray_t.surrounds(root))
 }
 
 impl Sphere {
+    fn degenerate_reason(&self) -> Option<&'static str> {
+        if !self.center.is_finite() {
+            Some("non-finite center")
+        } else if !(self.radius.is_finite() && self.radius > 0.0) {
+            Some("radius is not a positive number")
+        } else {
+            None
+        }
+    }
+
     #[inline]
-    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
         // Get the current center based on time (for moving spheres)
         let current_center = self.center;
 
@@ -189,16 +411,12 @@ impl Sphere {
         let sqrt_discriminant = discriminant.sqrt();
 
         // Find the nearest root in the acceptable range
-        // First try the closer intersection
-        let mut root = (-half_b - sqrt_discriminant) / a;
-
-        // If closer intersection is not in range, try the farther one
-        if !ray_t.surrounds(root) {
-            root = (-half_b + sqrt_discriminant) / a;
-            if !ray_t.surrounds(root) {
-                return None;
-            }
-        }
+        let root = nearest_root(
+            (-half_b - sqrt_discriminant) / a,
+            (-half_b + sqrt_discriminant) / a,
+            ray_t,
+            ray.excluded_primitive() == Some(self.id),
+        )?;
 
         // Calculate hit position
         let position = ray.at_time(root);
@@ -212,12 +430,18 @@ impl Sphere {
             t: root,
             position,
             front_face: true,
-            material: Some(&self.material),
+            material: Some(self.material.as_ref()),
             texture_coords,
             normal: outward_normal,
+            geometric_normal: outward_normal,
+            differentials: None,
+            light_group: None,
+            primitive: Some(self.id),
+            exclude_primitive: false,
         };
 
         hit_record.set_face_normal(ray, &outward_normal);
+        hit_record.set_differentials(ray, 1.0 / self.radius);
 
         Some(hit_record)
     }
@@ -233,12 +457,17 @@ impl Sphere {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "MovingSphereFields"))]
 pub struct MovingSphere {
     center: (Point3, Point3),
     time: (f64, f64),
     radius: f64,
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
     radius_squared: f64, // Pre-computed for efficiency
-    material: Material,
+    material: Arc<Material>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    id: PrimitiveId,
 }
 
 impl MovingSphere {
@@ -246,14 +475,15 @@ impl MovingSphere {
         center: (Point3, Point3),
         time: (f64, f64),
         radius: f64,
-        material: Material,
+        material: impl Into<Arc<Material>>,
     ) -> Self {
         Self {
             center,
             time,
             radius: radius.max(0.0),
             radius_squared: radius * radius,
-            material,
+            material: material.into(),
+            id: PrimitiveId::next(false),
         }
     }
 
@@ -262,6 +492,22 @@ impl MovingSphere {
             + (self.center.1 - self.center.0) * (time - self.time.0) / (self.time.1 - self.time.0)
     }
 }
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct MovingSphereFields {
+    center: (Point3, Point3),
+    time: (f64, f64),
+    radius: f64,
+    material: Arc<Material>,
+}
+
+#[cfg(feature = "serde")]
+impl From<MovingSphereFields> for MovingSphere {
+    fn from(fields: MovingSphereFields) -> Self {
+        MovingSphere::new(fields.center, fields.time, fields.radius, fields.material)
+    }
+}
 fn get_sphere_uv(point: Vec3) -> (f64, f64) {
     // p: a given point on the sphere of radius one, centered at the origin.
     // u: returned value [0,1] of angle around the Y axis from X=-1.
@@ -279,7 +525,7 @@ fn get_sphere_uv(point: Vec3) -> (f64, f64) {
 }
 
 impl Hittable for MovingSphere {
-    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
         // Get the current center based on time (for moving spheres)
         let current_center = self.center_at(ray.time());
 
@@ -304,16 +550,12 @@ impl Hittable for MovingSphere {
         let sqrt_discriminant = discriminant.sqrt();
 
         // Find the nearest root in the acceptable range
-        // First try the closer intersection
-        let mut root = (-half_b - sqrt_discriminant) / a;
-
-        // If closer intersection is not in range, try the farther one
-        if !ray_t.surrounds(root) {
-            root = (-half_b + sqrt_discriminant) / a;
-            if !ray_t.surrounds(root) {
-                return None;
-            }
-        }
+        let root = nearest_root(
+            (-half_b - sqrt_discriminant) / a,
+            (-half_b + sqrt_discriminant) / a,
+            ray_t,
+            ray.excluded_primitive() == Some(self.id),
+        )?;
 
         // Calculate hit position
         let position = ray.at_time(root);
@@ -327,12 +569,18 @@ impl Hittable for MovingSphere {
             t: root,
             position,
             normal: outward_normal,
+            geometric_normal: outward_normal,
             front_face: true,
-            material: Some(&self.material),
+            material: Some(self.material.as_ref()),
             texture_coords,
+            differentials: None,
+            light_group: None,
+            primitive: Some(self.id),
+            exclude_primitive: false,
         };
 
         hit_record.set_face_normal(ray, &outward_normal);
+        hit_record.set_differentials(ray, 1.0 / self.radius);
 
         Some(hit_record)
     }
@@ -368,6 +616,24 @@ impl Hittable for MovingSphere {
         );
         Some(Aabb::surrounding(&bbox0, &bbox1))
     }
+
+    fn visit_materials(&mut self, visit: &mut dyn FnMut(&mut Arc<Material>)) {
+        visit(&mut self.material);
+    }
+
+    fn degenerate_reason(&self) -> Option<&'static str> {
+        if !(self.center.0.is_finite() && self.center.1.is_finite()) {
+            Some("non-finite center")
+        } else if !(self.radius.is_finite() && self.radius > 0.0) {
+            Some("radius is not a positive number")
+        } else if !(self.time.0.is_finite() && self.time.1.is_finite())
+            || self.time.0 == self.time.1
+        {
+            Some("empty time range")
+        } else {
+            None
+        }
+    }
 }
 
 #[cfg(test)]
@@ -461,6 +727,32 @@ mod tests {
         assert!((hit.t - 1.0).abs() < 1e-6);
     }
 
+    #[test]
+    fn test_sphere_hit_excluding_itself() {
+        let sphere = Sphere::new(Point3::new(0.3, -0.2, 0.1), 1.0, TestMaterial::new());
+        let ray = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.1, 1.0), 0.0);
+        let mut hit = sphere
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert_eq!(hit.primitive, Some(sphere.id));
+        hit.exclude_primitive = true;
+        let ray_t = Interval::new(0.0, f64::INFINITY);
+
+        // Leaving the sphere, only the start point could be hit
+        let outward = hit.spawn_ray(hit.normal, 0.0);
+        assert_eq!(*outward.origin(), hit.position);
+        assert!(sphere.hit(&outward, ray_t).is_none());
+
+        // Entering it, the far side is still found
+        let inward = hit.spawn_ray(-hit.normal, 0.0);
+        let far = sphere.hit(&inward, ray_t).unwrap();
+        assert!((far.t - 2.0).abs() < 1e-9);
+
+        // Other spheres are not skipped
+        let other = sphere.clone();
+        assert!(other.hit(&inward, ray_t).is_some());
+    }
+
     #[test]
     fn test_sphere_hit_behind_ray() {
         // Create a sphere at the origin with radius 1
@@ -538,6 +830,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_surface_at_inverts_uv() {
+        let sphere = SphereBuilder::new()
+            .center(Point3::new(1.0, 2.0, 3.0))
+            .radius(2.0)
+            .material(TestMaterial::new())
+            .build()
+            .unwrap();
+
+        for uv in [(0.1, 0.2), (0.5, 0.5), (0.75, 0.9)] {
+            let record = sphere.surface_at(uv).unwrap();
+            let (u, v) = get_sphere_uv(record.normal);
+            assert!((u - uv.0).abs() < 1e-9 && (v - uv.1).abs() < 1e-9);
+            let offset = record.position - Point3::new(1.0, 2.0, 3.0);
+            assert!((offset - record.normal * 2.0).near_zero());
+        }
+    }
+
     #[test]
     fn test_get_sphere_uv_normalized() {
         // Test that the function works with non-unit vectors
@@ -564,15 +874,71 @@ mod tests {
         for point in test_points {
             let (u, v) = get_sphere_uv(point);
             assert!(
-                u >= 0.0 && u <= 1.0,
+                (0.0..=1.0).contains(&u),
                 "U coordinate out of range [0,1]: {}",
                 u
             );
             assert!(
-                v >= 0.0 && v <= 1.0,
+                (0.0..=1.0).contains(&v),
                 "V coordinate out of range [0,1]: {}",
                 v
             );
         }
     }
+
+    #[test]
+    fn test_sampling_directions_towards_sphere() {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+
+        let sphere = SphereBuilder::new()
+            .center(Point3::new(0.0, 0.0, -4.0))
+            .radius(1.0)
+            .material(TestMaterial::new())
+            .build()
+            .unwrap();
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let mut rng = StdRng::seed_from_u64(9);
+
+        // Every sampled direction hits the sphere, with the density of the cone
+        let expected = 1.0 / (2.0 * PI * (1.0 - (15.0_f64 / 16.0).sqrt()));
+        for _ in 0..100 {
+            let direction = sphere.random(&origin, &mut rng);
+            assert!((direction.length() - 1.0).abs() < 1e-9);
+            let ray = Ray::new(origin, direction, 0.0);
+            assert!(
+                sphere
+                    .hit(&ray, Interval::new(0.001, f64::INFINITY))
+                    .is_some()
+            );
+            assert!((sphere.pdf_value(&origin, &direction) - expected).abs() < 1e-6);
+        }
+        assert_eq!(sphere.pdf_value(&origin, &Vec3::new(0.0, 1.0, 0.0)), 0.0);
+
+        // From inside, every direction is equally likely
+        let inside = Point3::new(0.0, 0.0, -4.5);
+        assert_eq!(
+            sphere.pdf_value(&inside, &Vec3::new(1.0, 0.0, 0.0)),
+            UNIFORM_SPHERE_PDF
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        use crate::material::Dielectric;
+
+        let sphere = SphereType::Static(Sphere::new(
+            Point3::new(1.0, 2.0, 3.0),
+            0.5,
+            Dielectric::new(1.5),
+        ));
+        let json = serde_json::to_string(&sphere).unwrap();
+        assert!(!json.contains("radius_squared"), "{}", json);
+        let Ok(SphereType::Static(restored)) = serde_json::from_str::<SphereType>(&json) else {
+            panic!("{} did not restore a static sphere", json);
+        };
+        assert_eq!(restored.radius_squared, 0.25);
+        assert_eq!(*restored.material, Dielectric::new(1.5));
+    }
 }
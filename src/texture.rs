@@ -1,10 +1,78 @@
-use crate::color::Color;
+use crate::color::{Color, ColorSpace};
+use crate::perlin::Perlin;
 use crate::point3::Point3;
+use crate::texture_cache::{ImageId, TextureCache};
+use crate::utilities::{hash_f64, with_thread_rng};
+use rand::Rng;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-#[derive(Clone)]
+#[derive(Clone, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TextureEnum {
     SolidColor(SolidColor),
     CheckerTexture(CheckerTexture),
+    Noise(NoiseTexture),
+    Windows(WindowTexture),
+    /// Saved as its path, but needs a cache to read it back
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    Image(ImageTexture),
+    /// A texture defined outside this crate, which cannot be serialized
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(CustomTexture),
+}
+
+impl TextureEnum {
+    /// Every kind of texture by name, with a summary of its parameters.
+    pub const KINDS: [(&'static str, &'static str); 5] = [
+        ("solid_color", "One color everywhere. color: linear RGB"),
+        (
+            "checker",
+            "A 3D checkerboard of two textures. scale: checkers per unit times pi; odd, \
+             even: the textures of alternate checkers",
+        ),
+        (
+            "noise",
+            "Perlin noise in shades of gray. scale: noise features per unit; pattern: \
+             smooth, turbulence, or marble veins",
+        ),
+        (
+            "windows",
+            "Rows of building windows, some lit, for emissive facades. color: a lit \
+             window's brightest radiance; floor_height, window_spacing: window pitch in \
+             world units; lit_fraction: share of windows lit",
+        ),
+        (
+            "image",
+            "A PPM image mapped by texture coordinates, decoded a row of tiles at a time. \
+             path: the image file, relative to the scene",
+        ),
+    ];
+
+    /// Approximate number of bytes this texture occupies, including boxed children.
+    pub fn memory_size(&self) -> usize {
+        mem::size_of::<TextureEnum>()
+            + match self {
+                TextureEnum::SolidColor(_) => 0,
+                TextureEnum::CheckerTexture(t) => t.odd.memory_size() + t.even.memory_size(),
+                TextureEnum::Noise(t) => t.noise.memory_size(),
+                TextureEnum::Windows(_) => 0,
+                // Its pixels belong to the cache
+                TextureEnum::Image(_) => 0,
+                TextureEnum::Custom(t) => mem::size_of_val(&*t.0),
+            }
+    }
+
+    /// Calls `visit` with this texture and every texture nested inside it.
+    pub fn visit(&self, visit: &mut dyn FnMut(&TextureEnum)) {
+        visit(self);
+        if let TextureEnum::CheckerTexture(t) = self {
+            t.odd.visit(visit);
+            t.even.visit(visit);
+        }
+    }
 }
 
 impl Texture for TextureEnum {
@@ -12,6 +80,21 @@ impl Texture for TextureEnum {
         match self {
             TextureEnum::SolidColor(t) => t.value(u, v, p),
             TextureEnum::CheckerTexture(t) => t.value(u, v, p),
+            TextureEnum::Noise(t) => t.value(u, v, p),
+            TextureEnum::Windows(t) => t.value(u, v, p),
+            TextureEnum::Image(t) => t.value(u, v, p),
+            TextureEnum::Custom(t) => t.0.value(u, v, p),
+        }
+    }
+
+    fn filtered_value(&self, u: f64, v: f64, p: &Point3, footprint: f64) -> Color {
+        match self {
+            TextureEnum::SolidColor(t) => t.filtered_value(u, v, p, footprint),
+            TextureEnum::CheckerTexture(t) => t.filtered_value(u, v, p, footprint),
+            TextureEnum::Noise(t) => t.filtered_value(u, v, p, footprint),
+            TextureEnum::Windows(t) => t.filtered_value(u, v, p, footprint),
+            TextureEnum::Image(t) => t.filtered_value(u, v, p, footprint),
+            TextureEnum::Custom(t) => t.0.filtered_value(u, v, p, footprint),
         }
     }
 }
@@ -27,10 +110,46 @@ pub trait Texture: Send + Sync {
     /// * `v` - The V coordinate in texture space
     /// * `p` - The point in 3D space
     fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color;
+
+    /// Returns the color averaged over a footprint of the given world-space width.
+    ///
+    /// Textures with fine detail override this to fade that detail out once it is
+    /// smaller than the footprint. A `footprint` of zero means a point sample.
+    fn filtered_value(&self, u: f64, v: f64, p: &Point3, _footprint: f64) -> Color {
+        self.value(u, v, p)
+    }
+}
+
+/// A shared [`Texture`] implemented outside this crate.
+///
+/// Like custom materials, custom textures are compared by identity, so only
+/// clones of one are equal.
+#[derive(Clone)]
+pub struct CustomTexture(Arc<dyn Texture>);
+
+impl CustomTexture {
+    /// Creates a texture that looks up its colors in `texture`.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(texture: impl Texture + 'static) -> TextureEnum {
+        TextureEnum::Custom(CustomTexture(Arc::new(texture)))
+    }
+}
+
+impl PartialEq for CustomTexture {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Hash for CustomTexture {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const () as usize).hash(state);
+    }
 }
 
 /// A texture that returns a constant color regardless of position or UV coordinates.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SolidColor {
     /// The constant color to return
     pub color: Color,
@@ -44,6 +163,12 @@ impl SolidColor {
     pub fn new(color: Color) -> Self {
         Self { color }
     }
+
+    /// Creates a solid color texture from a color authored in `space`, converting
+    /// it to linear once here rather than at every lookup.
+    pub fn tagged(color: Color, space: ColorSpace) -> Self {
+        Self::new(space.to_linear(color))
+    }
 }
 
 impl From<Color> for SolidColor {
@@ -52,13 +177,20 @@ impl From<Color> for SolidColor {
     }
 }
 
+impl From<Color> for TextureEnum {
+    fn from(color: Color) -> Self {
+        TextureEnum::SolidColor(color.into())
+    }
+}
+
 impl Texture for SolidColor {
     fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
         self.color
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CheckerTexture {
     pub scale: f64,
     pub odd: Box<TextureEnum>,
@@ -81,6 +213,14 @@ impl CheckerTexture {
     }
 }
 
+impl Hash for CheckerTexture {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_f64(self.scale, state);
+        self.odd.hash(state);
+        self.even.hash(state);
+    }
+}
+
 impl Texture for CheckerTexture {
     fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
         let sines =
@@ -91,12 +231,371 @@ impl Texture for CheckerTexture {
             self.even.value(_u, _v, p)
         }
     }
+
+    fn filtered_value(&self, u: f64, v: f64, p: &Point3, footprint: f64) -> Color {
+        // Each checker is PI / scale wide; fade to the average of both colors as
+        // the footprint grows from half a checker to a whole one.
+        let checkers_per_footprint = footprint * self.scale / std::f64::consts::PI;
+        let blend = ((checkers_per_footprint - 0.5) * 2.0).clamp(0.0, 1.0);
+        if blend == 0.0 {
+            return self.value(u, v, p);
+        }
+
+        let average = (self.odd.filtered_value(u, v, p, footprint)
+            + self.even.filtered_value(u, v, p, footprint))
+            * 0.5;
+        if blend == 1.0 {
+            return average;
+        }
+        self.value(u, v, p) * (1.0 - blend) + average * blend
+    }
+}
+
+/// Octaves summed for turbulence and to perturb marble veins.
+const TURBULENCE_DEPTH: u32 = 7;
+/// How strongly turbulence displaces the veins of marble.
+const MARBLE_TURBULENCE: f64 = 10.0;
+
+/// How a [`NoiseTexture`] turns Perlin noise into a pattern.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NoisePattern {
+    /// The noise itself, soft blobs of gray
+    #[default]
+    Smooth,
+    /// Several octaves of noise summed, for a rough, cloudy look
+    Turbulence,
+    /// Bands along z whose phase is disturbed by turbulence, like the veins of marble
+    Marble,
+}
+
+/// A procedural gray pattern made from Perlin noise.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "NoiseFields"))]
+pub struct NoiseTexture {
+    /// Frequency of the noise: larger values give smaller features
+    pub scale: f64,
+    pub pattern: NoisePattern,
+    seed: u64,
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    noise: Box<Perlin>,
+}
+
+impl NoiseTexture {
+    /// Creates a noise texture whose noise is drawn from the thread's generator, so
+    /// scenes built from the same seed get the same pattern.
+    pub fn new(scale: f64, pattern: NoisePattern) -> Self {
+        Self::seeded(scale, pattern, with_thread_rng(|rng| rng.random()))
+    }
+
+    /// Creates a noise texture whose noise is generated from `seed`.
+    pub fn seeded(scale: f64, pattern: NoisePattern, seed: u64) -> Self {
+        Self {
+            scale,
+            pattern,
+            seed,
+            noise: Box::new(Perlin::new(seed)),
+        }
+    }
+}
+
+/// The stored fields of a noise texture, which deserialize into one with its
+/// noise regenerated from the seed.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct NoiseFields {
+    scale: f64,
+    pattern: NoisePattern,
+    seed: u64,
+}
+
+#[cfg(feature = "serde")]
+impl From<NoiseFields> for NoiseTexture {
+    fn from(fields: NoiseFields) -> Self {
+        NoiseTexture::seeded(fields.scale, fields.pattern, fields.seed)
+    }
+}
+
+/// The noise is generated from the seed, so it need not be hashed.
+impl Hash for NoiseTexture {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_f64(self.scale, state);
+        self.pattern.hash(state);
+        self.seed.hash(state);
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let scaled = Point3::from(p.as_vec3() * self.scale);
+        let gray = match self.pattern {
+            NoisePattern::Smooth => 0.5 * (1.0 + self.noise.noise(&scaled)),
+            NoisePattern::Turbulence => self.noise.turbulence(&scaled, TURBULENCE_DEPTH),
+            NoisePattern::Marble => {
+                let phase =
+                    scaled.z() + MARBLE_TURBULENCE * self.noise.turbulence(p, TURBULENCE_DEPTH);
+                0.5 * (1.0 + phase.sin())
+            }
+        };
+        Color::new(gray, gray, gray)
+    }
+}
+
+/// Share of the windows lit unless told otherwise.
+const DEFAULT_LIT_FRACTION: f64 = 0.35;
+/// Vertical extent of a window within its floor, as fractions of the floor height.
+const WINDOW_ROWS: (f64, f64) = (0.3, 0.8);
+/// Horizontal extent of a window within its bay, as fractions of the spacing.
+const WINDOW_COLUMNS: (f64, f64) = (0.2, 0.8);
+
+/// Lit and dark windows in rows, for the facades of buildings at night.
+///
+/// Windows are laid out by world position, floor by floor up from `y = 0` and
+/// along `x + z` across the facade, so every building sharing the texture lines up
+/// and buildings whose heights are whole floors get dark roofs. Which windows are
+/// lit, and how brightly, is picked by hashing each window's floor and bay.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WindowTexture {
+    /// Radiance of the brightest lit window; walls and dark windows are black
+    pub color: Color,
+    pub floor_height: f64,
+    /// Distance between the centers of neighboring windows on a floor
+    pub window_spacing: f64,
+    /// Share of the windows that are lit, from 0 to 1
+    pub lit_fraction: f64,
+    /// Picks which windows are lit
+    pub seed: u64,
+}
+
+impl WindowTexture {
+    /// Creates a facade with about a third of its windows lit.
+    pub fn new(color: Color, floor_height: f64, window_spacing: f64) -> Self {
+        Self {
+            color,
+            floor_height,
+            window_spacing,
+            lit_fraction: DEFAULT_LIT_FRACTION,
+            seed: 0,
+        }
+    }
+
+    pub fn lit_fraction(mut self, lit_fraction: f64) -> Self {
+        self.lit_fraction = lit_fraction;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+impl Hash for WindowTexture {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.color.hash(state);
+        hash_f64(self.floor_height, state);
+        hash_f64(self.window_spacing, state);
+        hash_f64(self.lit_fraction, state);
+        self.seed.hash(state);
+    }
+}
+
+impl Texture for WindowTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let floor = p.y() / self.floor_height;
+        let bay = (p.x() + p.z()) / self.window_spacing;
+        let (row, column) = (floor - floor.floor(), bay - bay.floor());
+        if !(WINDOW_ROWS.0..WINDOW_ROWS.1).contains(&row)
+            || !(WINDOW_COLUMNS.0..WINDOW_COLUMNS.1).contains(&column)
+        {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let hash = mix(mix(self.seed ^ floor.floor() as i64 as u64) ^ bay.floor() as i64 as u64);
+        // The top 32 bits decide whether the window is lit, the bottom 32 how brightly
+        let lit = (hash >> 32) as f64 / (1u64 << 32) as f64;
+        if lit >= self.lit_fraction {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let brightness = (hash & 0xffff_ffff) as f64 / (1u64 << 32) as f64;
+        self.color * (0.4 + 0.6 * brightness)
+    }
+}
+
+/// An image mapped onto surfaces by their texture coordinates, read through a
+/// shared [`TextureCache`], which decodes it once for every texture using it.
+///
+/// Lookups take the nearest pixel. Images are compared by cache and path.
+#[derive(Clone)]
+pub struct ImageTexture {
+    cache: Arc<TextureCache>,
+    image: ImageId,
+}
+
+impl ImageTexture {
+    /// Creates a texture reading the image at `path` through `cache`.
+    pub fn new(cache: &Arc<TextureCache>, path: impl AsRef<Path>) -> Self {
+        Self {
+            cache: Arc::clone(cache),
+            image: cache.register(path),
+        }
+    }
+
+    /// Returns the path the image is read from.
+    pub fn path(&self) -> PathBuf {
+        self.cache.path(self.image)
+    }
+
+    /// Returns why the image could not be read, if the cache has tried and failed.
+    pub fn failure(&self) -> Option<String> {
+        self.cache.failure(self.image)
+    }
+}
+
+impl PartialEq for ImageTexture {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cache, &other.cache) && self.image == other.image
+    }
+}
+
+impl Hash for ImageTexture {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.cache) as usize).hash(state);
+        self.image.hash(state);
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for ImageTexture {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut image = serializer.serialize_struct("ImageTexture", 1)?;
+        image.serialize_field("path", &self.path())?;
+        image.end()
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        self.cache.texel(self.image, u, v)
+    }
+}
+
+/// Scrambles the bits of `x` (the SplitMix64 finalizer), so neighboring windows
+/// get unrelated hashes.
+fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Stripes along x, as a stand-in for a texture from another crate.
+    struct Stripes;
+
+    impl Texture for Stripes {
+        fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+            if p.x().floor() as i64 % 2 == 0 {
+                Color::new(1.0, 1.0, 1.0)
+            } else {
+                Color::new(0.0, 0.0, 0.0)
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_texture() {
+        let texture = CustomTexture::new(Stripes);
+        let checker = TextureEnum::CheckerTexture(CheckerTexture::new(
+            1.0,
+            Box::new(texture.clone()),
+            Box::new(TextureEnum::SolidColor(Color::new(0.5, 0.5, 0.5).into())),
+        ));
+        assert_eq!(
+            texture.value(0.0, 0.0, &Point3::new(0.5, 0.0, 0.0)),
+            Color::new(1.0, 1.0, 1.0)
+        );
+        assert_eq!(
+            texture.filtered_value(0.0, 0.0, &Point3::new(1.5, 0.0, 0.0), 1.0),
+            Color::new(0.0, 0.0, 0.0)
+        );
+        assert!(checker.memory_size() > texture.memory_size());
+
+        assert!(texture == texture.clone());
+        assert!(texture != CustomTexture::new(Stripes));
+    }
+
+    #[test]
+    fn test_noise_texture() {
+        let p = Point3::new(0.3, 1.2, -2.5);
+        for pattern in [
+            NoisePattern::Smooth,
+            NoisePattern::Turbulence,
+            NoisePattern::Marble,
+        ] {
+            let texture = TextureEnum::Noise(NoiseTexture::seeded(4.0, pattern, 42));
+            let color = texture.value(0.0, 0.0, &p);
+            assert!(
+                (0.0..=1.0).contains(&color.r()),
+                "{:?}: {:?}",
+                pattern,
+                color
+            );
+            assert_eq!(color.r(), color.b());
+            // The same seed gives the same pattern
+            assert!(TextureEnum::Noise(NoiseTexture::seeded(4.0, pattern, 42)) == texture);
+        }
+
+        // Smooth noise is mid gray on the lattice
+        let smooth = NoiseTexture::seeded(1.0, NoisePattern::Smooth, 42);
+        assert_eq!(
+            smooth.value(0.0, 0.0, &Point3::new(1.0, 2.0, 3.0)),
+            Color::new(0.5, 0.5, 0.5)
+        );
+
+        crate::utilities::seed_thread_rng(3);
+        let a = NoiseTexture::new(1.0, NoisePattern::Marble);
+        crate::utilities::seed_thread_rng(3);
+        assert_eq!(NoiseTexture::new(1.0, NoisePattern::Marble), a);
+        assert!(TextureEnum::Noise(a).memory_size() > 6 * 1024);
+    }
+
+    #[test]
+    fn test_window_texture() {
+        let windows = WindowTexture::new(Color::new(4.0, 3.0, 2.0), 1.0, 1.0).lit_fraction(0.5);
+        let black = Color::new(0.0, 0.0, 0.0);
+        // Walls between windows and at floor level stay dark
+        assert_eq!(windows.value(0.0, 0.0, &Point3::new(0.1, 0.5, 0.0)), black);
+        assert_eq!(windows.value(0.0, 0.0, &Point3::new(0.5, 3.0, 0.0)), black);
+
+        let centers: Vec<_> = (0..20)
+            .flat_map(|floor| {
+                (0..20).map(move |bay| Point3::new(bay as f64 + 0.5, floor as f64 + 0.55, 0.0))
+            })
+            .collect();
+        let lit: Vec<_> = centers
+            .iter()
+            .map(|p| windows.value(0.0, 0.0, p))
+            .filter(|color| *color != black)
+            .collect();
+        assert!((150..250).contains(&lit.len()), "{} lit", lit.len());
+        assert!(lit.iter().all(|color| color.r() <= 4.0 && color.r() >= 1.6));
+        // The same window is lit anywhere across it, on every facade it spans
+        assert_eq!(
+            windows.value(0.0, 0.0, &Point3::new(2.3, 4.4, 0.0)),
+            windows.value(0.0, 0.0, &Point3::new(0.0, 4.7, 2.6))
+        );
+
+        let all_dark = windows.clone().lit_fraction(0.0);
+        assert!(centers.iter().all(|p| all_dark.value(0.0, 0.0, p) == black));
+        assert!(TextureEnum::Windows(windows.clone()) != TextureEnum::Windows(windows.seed(1)));
+    }
+
     #[test]
     fn test_solid_color_texture() {
         let color = Color::new(0.5, 0.3, 0.1);
@@ -109,6 +608,21 @@ mod tests {
         assert_eq!(texture.value(1.0, 1.0, &point), color);
     }
 
+    #[test]
+    fn test_tagged_solid_color() {
+        let authored = Color::new(0.5, 0.5, 0.5);
+        let point = Point3::new(0.0, 0.0, 0.0);
+
+        let linear = SolidColor::tagged(authored, ColorSpace::Linear);
+        assert_eq!(linear.value(0.0, 0.0, &point), authored);
+        let srgb = SolidColor::tagged(authored, ColorSpace::Srgb);
+        assert_eq!(
+            srgb.value(0.0, 0.0, &point),
+            ColorSpace::Srgb.to_linear(authored)
+        );
+        assert!(srgb.value(0.0, 0.0, &point).r() < 0.25);
+    }
+
     #[test]
     fn test_checker_texture() {
         let odd_color = Color::new(1.0, 1.0, 1.0); // White
@@ -186,4 +700,23 @@ mod tests {
         assert!(sines2 < 0.0);
         assert_eq!(texture.value(0.0, 0.0, &p2), even_color);
     }
+
+    #[test]
+    fn test_checker_texture_filtered_value() {
+        let odd_color = Color::new(1.0, 1.0, 1.0);
+        let even_color = Color::new(0.0, 0.0, 0.0);
+        let odd = Box::new(TextureEnum::SolidColor(SolidColor::new(odd_color)));
+        let even = Box::new(TextureEnum::SolidColor(SolidColor::new(even_color)));
+
+        let texture = CheckerTexture::new(std::f64::consts::PI, odd, even);
+        let p = Point3::new(0.5, 0.5, 0.5);
+
+        // A footprint much smaller than a checker leaves the pattern untouched
+        assert_eq!(texture.filtered_value(0.0, 0.0, &p, 0.01), odd_color);
+        // A footprint covering several checkers averages them out
+        assert_eq!(
+            texture.filtered_value(0.0, 0.0, &p, 4.0),
+            Color::new(0.5, 0.5, 0.5)
+        );
+    }
 }
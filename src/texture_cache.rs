@@ -0,0 +1,796 @@
+//! A cache of image textures that loads them lazily and keeps them within a
+//! memory budget.
+//!
+//! Images are registered by path without being read. The first lookup in an image
+//! reads it through once to find where each row of square tiles starts, keeping
+//! none of its pixels. Each lookup landing in a tile that is not held then decodes
+//! just that row of tiles, and the cache keeps tiles as long as they fit in its
+//! budget. When a tile needs room, the least recently used tiles of any image are
+//! evicted, to be decoded again if a later lookup lands in them. A scene can so
+//! reference more texture than fits in memory, holding only what the render is
+//! looking at, and never decoding more than a row of tiles at a time.
+//!
+//! Images are plain-text PPM files, decoded to linear color as
+//! [`Framebuffer::read_ppm`](crate::framebuffer::Framebuffer::read_ppm) decodes
+//! them. One that cannot be read looks up as [`MISSING_COLOR`], and
+//! [`TextureCache::failures`] tells which it was and why.
+
+use crate::color::Color;
+use crate::display::DisplayTransform;
+use crate::framebuffer::{ImageError, display_comment};
+use crate::parallel::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// Width and height of a tile, in pixels.
+pub const DEFAULT_TILE_SIZE: u32 = 64;
+/// Bytes of decoded pixels a cache holds by default.
+pub const DEFAULT_BUDGET: usize = 512 * 1024 * 1024;
+/// Returned for lookups in images that could not be read, to stand out in the render.
+pub const MISSING_COLOR: Color = Color::new(1.0, 0.0, 1.0);
+
+/// An image registered with a [`TextureCache`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageId(usize);
+
+/// How much a [`TextureCache`] holds and has done.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Bytes of decoded pixels held
+    pub resident_bytes: usize,
+    pub tiles: usize,
+    /// Times a row of tiles was decoded, including again after its tiles were
+    /// evicted
+    pub loads: usize,
+    pub evictions: usize,
+}
+
+struct Image {
+    path: PathBuf,
+    /// Where the image's rows of tiles start, known once a lookup has read it
+    layout: Option<Arc<Layout>>,
+    /// Why the image could not be read, once a lookup has tried
+    failure: Option<String>,
+    /// Whether a thread is decoding the image, which others wait for
+    loading: bool,
+}
+
+/// Which tile of which image, counting tiles along rows from the top left.
+type TileKey = (ImageId, u32);
+
+struct Tile {
+    width: u32,
+    pixels: Vec<Color>,
+    last_used: u64,
+}
+
+impl Tile {
+    fn bytes(&self) -> usize {
+        self.pixels.len() * mem::size_of::<Color>()
+    }
+}
+
+#[derive(Default)]
+struct CacheState {
+    images: Vec<Image>,
+    tiles: HashMap<TileKey, Tile>,
+    /// Every tile held, by when it was last used
+    recency: BTreeMap<u64, TileKey>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl CacheState {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Looks up pixel (`x`, `y`) of tile `key`, marking the tile used.
+    fn lookup(&mut self, key: TileKey, x: u32, y: u32) -> Option<Color> {
+        let now = self.tick();
+        let tile = self.tiles.get_mut(&key)?;
+        self.recency.remove(&tile.last_used);
+        self.recency.insert(now, key);
+        tile.last_used = now;
+        Some(tile.pixels[(y * tile.width + x) as usize])
+    }
+
+    fn insert(&mut self, key: TileKey, mut tile: Tile) {
+        tile.last_used = self.tick();
+        self.recency.insert(tile.last_used, key);
+        self.stats.resident_bytes += tile.bytes();
+        self.stats.tiles += 1;
+        if let Some(old) = self.tiles.insert(key, tile) {
+            self.recency.remove(&old.last_used);
+            self.stats.resident_bytes -= old.bytes();
+            self.stats.tiles -= 1;
+        }
+    }
+
+    /// Evicts the least recently used tiles until `bytes` more fit in `budget`.
+    fn make_room(&mut self, bytes: usize, budget: usize) {
+        while self.stats.resident_bytes + bytes > budget {
+            let Some((_, key)) = self.recency.pop_first() else {
+                return;
+            };
+            if let Some(tile) = self.tiles.remove(&key) {
+                self.stats.resident_bytes -= tile.bytes();
+                self.stats.tiles -= 1;
+                self.stats.evictions += 1;
+            }
+        }
+    }
+}
+
+/// Image textures shared by a scene, loaded on demand within a memory budget.
+///
+/// Lookups from many threads at once are safe; the cache is locked while each
+/// one is served, but not while an image is decoded, so different images decode
+/// side by side. Threads missing an image another is decoding wait for it rather
+/// than decoding it again.
+pub struct TextureCache {
+    budget: usize,
+    tile_size: u32,
+    state: Mutex<CacheState>,
+    /// Signalled whenever an image finishes decoding
+    decoded: Condvar,
+}
+
+impl Default for TextureCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUDGET)
+    }
+}
+
+impl TextureCache {
+    /// Creates an empty cache holding at most `budget` bytes of decoded pixels.
+    ///
+    /// The tile being looked up is always kept, so a budget smaller than one tile
+    /// still works, if slowly.
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            tile_size: DEFAULT_TILE_SIZE,
+            state: Mutex::new(CacheState::default()),
+            decoded: Condvar::new(),
+        }
+    }
+
+    /// Sets the width and height of the tiles images are split into.
+    pub fn tile_size(mut self, tile_size: u32) -> Self {
+        self.tile_size = tile_size.max(1);
+        self
+    }
+
+    /// Adds the image at `path` to the cache without reading it, returning the
+    /// image already added from that path if there is one.
+    pub fn register(&self, path: impl AsRef<Path>) -> ImageId {
+        let path = path.as_ref();
+        let mut state = self.state();
+        if let Some(index) = state.images.iter().position(|image| image.path == path) {
+            return ImageId(index);
+        }
+        state.images.push(Image {
+            path: path.to_path_buf(),
+            layout: None,
+            failure: None,
+            loading: false,
+        });
+        ImageId(state.images.len() - 1)
+    }
+
+    /// Returns the path `image` is read from.
+    pub fn path(&self, image: ImageId) -> PathBuf {
+        self.state().images[image.0].path.clone()
+    }
+
+    /// Returns why `image` could not be read, if a lookup has tried and failed.
+    pub fn failure(&self, image: ImageId) -> Option<String> {
+        self.state().images[image.0].failure.clone()
+    }
+
+    /// Returns the path of every image that could not be read so far, with why,
+    /// in the order they were registered. Lookups in them return [`MISSING_COLOR`].
+    pub fn failures(&self) -> Vec<(PathBuf, String)> {
+        self.state()
+            .images
+            .iter()
+            .filter_map(|image| Some((image.path.clone(), image.failure.clone()?)))
+            .collect()
+    }
+
+    /// Returns how much the cache holds and has done so far.
+    pub fn stats(&self) -> CacheStats {
+        self.state().stats
+    }
+
+    /// Reads every registered image through, several at once with the `parallel`
+    /// feature, then decodes rows of tiles from the top of each while they fit in
+    /// the budget left.
+    ///
+    /// Loading a scene this way finds the images that cannot be read up front,
+    /// recording them in [`TextureCache::failures`], and warms the cache without
+    /// holding more than the budget. Rows of tiles that do not fit are decoded as
+    /// lookups reach them.
+    pub fn preload(&self) {
+        let count = self.state().images.len();
+        (0..count).into_par_iter().for_each(|index| {
+            let image = ImageId(index);
+            let Some(layout) = self.layout(image) else {
+                return;
+            };
+            for band in 0..layout.bands.len() as u32 {
+                let top = band * self.tile_size;
+                let rows = self.tile_size.min(layout.height - top);
+                let bytes = layout.width as usize * rows as usize * mem::size_of::<Color>();
+                if self.stats().resident_bytes + bytes > self.budget {
+                    return;
+                }
+                // A lookup in the row's first tile decodes the row
+                self.texel(image, 0.0, 1.0 - (top as f64 + 0.5) / layout.height as f64);
+            }
+        });
+    }
+
+    /// Returns where the rows of tiles of `image` start, reading it through to
+    /// find out the first time, or `None` if it cannot be read.
+    fn layout(&self, image: ImageId) -> Option<Arc<Layout>> {
+        let path = {
+            let state = self.state();
+            let entry = &state.images[image.0];
+            if entry.failure.is_some() || entry.layout.is_some() {
+                return entry.layout.clone();
+            }
+            entry.path.clone()
+        };
+        // Another thread scanning the image at the same time finds the same layout
+        let scanned = File::open(&path)
+            .map_err(ImageError::from)
+            .and_then(|file| Layout::scan(BufReader::new(file), self.tile_size));
+        let mut state = self.state();
+        match scanned {
+            Ok(layout) => {
+                let layout = Arc::new(layout);
+                state.images[image.0].layout = Some(Arc::clone(&layout));
+                Some(layout)
+            }
+            Err(error) => {
+                state.images[image.0].failure = Some(error.to_string());
+                None
+            }
+        }
+    }
+
+    /// Looks up the pixel of `image` at texture coordinates (`u`, `v`), which
+    /// run from the bottom left corner at (0, 0) to the top right at (1, 1).
+    ///
+    /// Decodes the row of tiles the pixel lies in if its tile is not held.
+    pub fn texel(&self, image: ImageId, u: f64, v: f64) -> Color {
+        let mut state = self.state();
+        loop {
+            let entry = &state.images[image.0];
+            if entry.failure.is_some() {
+                return MISSING_COLOR;
+            }
+            if let Some(layout) = &entry.layout {
+                let (key, x, y) = self.locate(image, layout.size(), u, v);
+                if let Some(color) = state.lookup(key, x, y) {
+                    return color;
+                }
+            }
+            if !state.images[image.0].loading {
+                break;
+            }
+            state = self
+                .decoded
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        state.images[image.0].loading = true;
+        let path = state.images[image.0].path.clone();
+        let layout = state.images[image.0].layout.clone();
+        drop(state);
+
+        let _loading = Loading { cache: self, image };
+        match self.decode(image, &path, layout, u, v) {
+            Ok(color) => color,
+            Err(error) => {
+                self.state().images[image.0].failure = Some(error.to_string());
+                MISSING_COLOR
+            }
+        }
+    }
+
+    /// Decodes the row of tiles of `image` holding (`u`, `v`), first finding
+    /// where its rows start if `layout` is not yet known, and returns the pixel.
+    fn decode(
+        &self,
+        image: ImageId,
+        path: &Path,
+        layout: Option<Arc<Layout>>,
+        u: f64,
+        v: f64,
+    ) -> Result<Color, ImageError> {
+        let mut input = BufReader::new(File::open(path)?);
+        let layout = match layout {
+            Some(layout) => layout,
+            None => {
+                let layout = Arc::new(Layout::scan(&mut input, self.tile_size)?);
+                self.state().images[image.0].layout = Some(Arc::clone(&layout));
+                layout
+            }
+        };
+        let (wanted, x, y) = self.locate(image, layout.size(), u, v);
+        let columns = layout.width.div_ceil(self.tile_size);
+        let band = wanted.1 / columns;
+        let pixels = layout.read_band(&mut input, band)?;
+        Ok(self.fill(&layout, band, &pixels, wanted, x, y))
+    }
+
+    /// Stores tiles of the freshly decoded row of tiles `band`, whose `pixels`
+    /// run the full width of the image: `wanted` whatever it evicts, and the
+    /// others missing while they fit without evicting anything. Returns pixel
+    /// (`x`, `y`) of `wanted`.
+    fn fill(
+        &self,
+        layout: &Layout,
+        band: u32,
+        pixels: &[Color],
+        wanted: TileKey,
+        x: u32,
+        y: u32,
+    ) -> Color {
+        let mut state = self.state();
+        state.stats.loads += 1;
+
+        let tile = self.cut(layout.width, pixels, wanted.1);
+        let color = tile.pixels[(y * tile.width + x) as usize];
+        if !state.tiles.contains_key(&wanted) {
+            state.make_room(tile.bytes(), self.budget);
+            state.insert(wanted, tile);
+        }
+        let columns = layout.width.div_ceil(self.tile_size);
+        for index in band * columns..(band + 1) * columns {
+            let key = (wanted.0, index);
+            if state.tiles.contains_key(&key) {
+                continue;
+            }
+            let tile = self.cut(layout.width, pixels, index);
+            if state.stats.resident_bytes + tile.bytes() > self.budget {
+                break;
+            }
+            state.insert(key, tile);
+        }
+        color
+    }
+
+    /// Finds the tile of an image of `size` holding (`u`, `v`), and the pixel
+    /// within the tile.
+    fn locate(&self, image: ImageId, size: (u32, u32), u: f64, v: f64) -> (TileKey, u32, u32) {
+        let (width, height) = size;
+        let x = ((u.clamp(0.0, 1.0) * width as f64) as u32).min(width - 1);
+        let y = (((1.0 - v.clamp(0.0, 1.0)) * height as f64) as u32).min(height - 1);
+        let columns = width.div_ceil(self.tile_size);
+        let index = (y / self.tile_size) * columns + x / self.tile_size;
+        ((image, index), x % self.tile_size, y % self.tile_size)
+    }
+
+    /// Copies tile `index` out of `band`, the pixels of its row of tiles in an
+    /// image `image_width` wide.
+    fn cut(&self, image_width: u32, band: &[Color], index: u32) -> Tile {
+        let columns = image_width.div_ceil(self.tile_size);
+        let left = (index % columns) * self.tile_size;
+        let width = self.tile_size.min(image_width - left);
+        let pixels = band
+            .chunks_exact(image_width as usize)
+            .flat_map(|row| row[left as usize..(left + width) as usize].iter().copied())
+            .collect();
+        Tile {
+            width,
+            pixels,
+            last_used: 0,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, CacheState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Marks the end of an image's decoding when dropped, so the threads waiting for
+/// it wake even if decoding panics, rather than waiting forever.
+struct Loading<'a> {
+    cache: &'a TextureCache,
+    image: ImageId,
+}
+
+impl Drop for Loading<'_> {
+    fn drop(&mut self) {
+        self.cache.state().images[self.image.0].loading = false;
+        self.cache.decoded.notify_all();
+    }
+}
+
+/// Where each row of tiles of a plain-text PPM image starts in its file, found by
+/// reading it through once without keeping its pixels.
+struct Layout {
+    width: u32,
+    height: u32,
+    max_value: u32,
+    display: DisplayTransform,
+    /// Rows of pixels in each row of tiles
+    tile_size: u32,
+    /// For each row of tiles, the offset of the line holding its first value and
+    /// how many values come before that one on the line
+    bands: Vec<(u64, usize)>,
+}
+
+impl Layout {
+    /// Reads the header and every value of the image in `input`, checking they
+    /// parse and that there are as many as its size says.
+    fn scan(mut input: impl BufRead, tile_size: u32) -> Result<Self, ImageError> {
+        let mut header = Vec::new();
+        let mut layout = None;
+        let mut display = DisplayTransform::default();
+        let mut values = 0;
+        let mut offset = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = input.read_line(&mut line)?;
+            if read == 0 {
+                break;
+            }
+            if let Some(named) = display_comment(&line)? {
+                display = named;
+            }
+            let content = line.split('#').next().unwrap_or_default();
+            for (position, token) in content.split_whitespace().enumerate() {
+                let Some(layout) = &mut layout else {
+                    header.push(token.to_string());
+                    if header.len() == 4 {
+                        layout = Some(Layout::from_header(&header, tile_size)?);
+                    }
+                    continue;
+                };
+                token
+                    .parse::<u32>()
+                    .map_err(|_| ImageError::InvalidFormat(format!("bad value '{}'", token)))?;
+                if values % layout.band_values() == 0 {
+                    layout.bands.push((offset, position));
+                }
+                values += 1;
+            }
+            offset += read as u64;
+        }
+
+        let mut layout =
+            layout.ok_or_else(|| ImageError::InvalidFormat("missing header".to_string()))?;
+        // Trust the size only as far as the file holds the values for it
+        if values != layout.width as usize * layout.height as usize * 3 {
+            return Err(ImageError::InvalidFormat(format!(
+                "{} values for a {}x{} image",
+                values, layout.width, layout.height
+            )));
+        }
+        layout.display = display;
+        Ok(layout)
+    }
+
+    /// Reads the magic number, size and maximum value from the four tokens of a
+    /// PPM header.
+    fn from_header(header: &[String], tile_size: u32) -> Result<Self, ImageError> {
+        let number = |index: usize, what: &str| {
+            header[index]
+                .parse()
+                .map_err(|_| ImageError::InvalidFormat(format!("bad {} '{}'", what, header[index])))
+        };
+        if header[0] != "P3" {
+            return Err(ImageError::InvalidFormat("not a P3 PPM image".to_string()));
+        }
+        let layout = Layout {
+            width: number(1, "width")?,
+            height: number(2, "height")?,
+            max_value: number(3, "maximum value")?,
+            display: DisplayTransform::default(),
+            tile_size,
+            bands: Vec::new(),
+        };
+        if layout.width == 0 || layout.height == 0 {
+            return Err(ImageError::InvalidFormat("image has no pixels".to_string()));
+        }
+        if layout.max_value == 0 {
+            return Err(ImageError::InvalidFormat(
+                "maximum value is zero".to_string(),
+            ));
+        }
+        Ok(layout)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Values, three to a pixel, in a full row of tiles.
+    fn band_values(&self) -> usize {
+        self.width as usize * self.tile_size as usize * 3
+    }
+
+    /// Decodes the pixels of row of tiles `band` from `input`, the file the
+    /// layout was scanned from, a row of pixels after another.
+    fn read_band(
+        &self,
+        mut input: impl BufRead + Seek,
+        band: u32,
+    ) -> Result<Vec<Color>, ImageError> {
+        let (offset, mut skip) = self.bands[band as usize];
+        input.seek(SeekFrom::Start(offset))?;
+        let rows = self.tile_size.min(self.height - band * self.tile_size);
+        let count = self.width as usize * rows as usize * 3;
+        let mut values = Vec::with_capacity(count);
+        let mut line = String::new();
+        while values.len() < count {
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Err(ImageError::InvalidFormat(
+                    "image changed while it was read".to_string(),
+                ));
+            }
+            let content = line.split('#').next().unwrap_or_default();
+            for token in content
+                .split_whitespace()
+                .skip(skip)
+                .take(count - values.len())
+            {
+                values.push(
+                    token
+                        .parse::<u32>()
+                        .map_err(|_| ImageError::InvalidFormat(format!("bad value '{}'", token)))?,
+                );
+            }
+            skip = 0;
+        }
+        values
+            .chunks_exact(3)
+            .map(|rgb| {
+                Color::from_display_bytes(rgb[0], rgb[1], rgb[2], self.max_value, self.display)
+                    .ok_or_else(|| {
+                        ImageError::InvalidFormat(format!(
+                            "{} images cannot be decoded to linear",
+                            self.display.name()
+                        ))
+                    })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a `width` by `height` PPM whose pixel (x, y) has red x and green y,
+    /// out of 255, returning its path.
+    fn gradient(name: &str, width: u32, height: u32) -> PathBuf {
+        let mut text = format!("P3\n{} {}\n255\n", width, height);
+        for y in 0..height {
+            for x in 0..width {
+                text += &format!("{} {} 0\n", x, y);
+            }
+        }
+        let path = std::env::temp_dir().join(format!(
+            "raytrace-texture-cache-{}-{}.ppm",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn pixel(x: u32, y: u32) -> Color {
+        Color::from_display_bytes(x, y, 0, 255, Default::default()).unwrap()
+    }
+
+    #[test]
+    fn test_lazy_lookup() {
+        let path = gradient("lazy", 8, 4);
+        let cache = TextureCache::default().tile_size(4);
+        let image = cache.register(&path);
+        assert_eq!(cache.register(&path), image);
+        assert_eq!(cache.stats(), CacheStats::default());
+
+        // v runs up the image, whose first row is its top
+        assert_eq!(cache.texel(image, 0.0, 1.0), pixel(0, 0));
+        assert_eq!(cache.texel(image, 0.99, 0.0), pixel(7, 3));
+        assert_eq!(cache.texel(image, 0.5, 0.5), pixel(4, 2));
+        let stats = cache.stats();
+        assert_eq!((stats.loads, stats.tiles, stats.evictions), (1, 2, 0));
+        assert_eq!(stats.resident_bytes, 32 * mem::size_of::<Color>());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_misses_decode_only_their_row_of_tiles() {
+        // Values wrap across lines unevenly, with a comment in the way, so rows of
+        // tiles start partway along lines
+        let (width, height) = (6, 10);
+        let mut text = format!("P3 {} {} 255", width, height);
+        let mut count = 0;
+        for y in 0..height {
+            for x in 0..width {
+                for value in [x, y, 0] {
+                    text += if count % 7 == 0 { "\n" } else { " " };
+                    text += &value.to_string();
+                    count += 1;
+                }
+            }
+            if y == 4 {
+                text += " # halfway\n";
+            }
+        }
+        let path = std::env::temp_dir().join(format!(
+            "raytrace-texture-cache-{}-wrapped.ppm",
+            std::process::id()
+        ));
+        std::fs::write(&path, text).unwrap();
+
+        let cache = TextureCache::default().tile_size(4);
+        let image = cache.register(&path);
+        assert_eq!(cache.texel(image, 0.0, 1.0), pixel(0, 0));
+        let stats = cache.stats();
+        assert_eq!((stats.loads, stats.tiles), (1, 2));
+        assert_eq!(stats.resident_bytes, 24 * mem::size_of::<Color>());
+
+        // The short last row of tiles, then the middle one, each decoded alone
+        assert_eq!(cache.texel(image, 0.99, 0.0), pixel(5, 9));
+        assert_eq!(cache.texel(image, 0.5, 0.5), pixel(3, 5));
+        assert_eq!(cache.texel(image, 0.0, 0.45), pixel(0, 5));
+        let stats = cache.stats();
+        assert_eq!((stats.loads, stats.tiles), (3, 6));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_short_image_is_rejected() {
+        let path = std::env::temp_dir().join(format!(
+            "raytrace-texture-cache-{}-short.ppm",
+            std::process::id()
+        ));
+        std::fs::write(&path, "P3\n2 2\n255\n0 0 0\n1 1 1\n").unwrap();
+        let cache = TextureCache::default();
+        let image = cache.register(&path);
+        assert_eq!(cache.texel(image, 0.0, 1.0), MISSING_COLOR);
+        assert_eq!(
+            cache.failure(image).as_deref(),
+            Some("Invalid image: 6 values for a 2x2 image")
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_budget_evicts_least_recently_used() {
+        let path = gradient("budget", 8, 4);
+        let tile_bytes = 16 * mem::size_of::<Color>();
+        let cache = TextureCache::new(tile_bytes).tile_size(4);
+        let image = cache.register(&path);
+
+        assert_eq!(cache.texel(image, 0.0, 1.0), pixel(0, 0));
+        assert_eq!(cache.stats().tiles, 1);
+        // The right tile is missing, so the image is decoded again for it
+        assert_eq!(cache.texel(image, 0.99, 1.0), pixel(7, 0));
+        assert_eq!(cache.texel(image, 0.99, 0.0), pixel(7, 3));
+        let stats = cache.stats();
+        assert_eq!((stats.loads, stats.tiles, stats.evictions), (2, 1, 1));
+        assert_eq!(stats.resident_bytes, tile_bytes);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_misses_decode_once() {
+        let path = gradient("concurrent", 16, 16);
+        let cache = TextureCache::default().tile_size(4);
+        let image = cache.register(&path);
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let cache = &cache;
+                scope.spawn(move || {
+                    let u = i as f64 / 8.0;
+                    assert_eq!(cache.texel(image, u, 1.0), pixel(2 * i, 0));
+                });
+            }
+        });
+        assert_eq!(cache.stats().loads, 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_preload_decodes_every_image() {
+        let first = gradient("preload-first", 8, 4);
+        let second = gradient("preload-second", 4, 4);
+        let cache = TextureCache::default().tile_size(4);
+        let images = [
+            cache.register(&first),
+            cache.register(&second),
+            cache.register("no/such/preload.ppm"),
+        ];
+
+        cache.preload();
+        let stats = cache.stats();
+        assert_eq!((stats.loads, stats.tiles), (2, 3));
+        assert_eq!(cache.failures().len(), 1);
+        // Lookups find the tiles already held
+        assert_eq!(cache.texel(images[0], 0.99, 0.0), pixel(7, 3));
+        assert_eq!(cache.texel(images[1], 0.0, 1.0), pixel(0, 0));
+        assert_eq!(cache.texel(images[2], 0.0, 1.0), MISSING_COLOR);
+        assert_eq!(cache.stats().loads, 2);
+        std::fs::remove_file(first).unwrap();
+        std::fs::remove_file(second).unwrap();
+    }
+
+    #[test]
+    fn test_preload_stays_within_the_budget() {
+        let path = gradient("preload-budget", 4, 12);
+        let tile_bytes = 16 * mem::size_of::<Color>();
+        let cache = TextureCache::new(2 * tile_bytes).tile_size(4);
+        let image = cache.register(&path);
+        let missing = cache.register("no/such/preload-budget.ppm");
+
+        cache.preload();
+        let stats = cache.stats();
+        assert_eq!((stats.loads, stats.tiles, stats.evictions), (2, 2, 0));
+        assert_eq!(stats.resident_bytes, 2 * tile_bytes);
+        assert!(cache.failure(missing).is_some());
+        // The row of tiles left out is decoded once a lookup reaches it
+        assert_eq!(cache.texel(image, 0.0, 1.0), pixel(0, 0));
+        assert_eq!(cache.stats().loads, 2);
+        assert_eq!(cache.texel(image, 0.0, 0.0), pixel(0, 11));
+        assert_eq!(cache.stats().loads, 3);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_waiters_wake_when_decoding_panics() {
+        let path = gradient("panic", 4, 4);
+        let cache = TextureCache::default();
+        let image = cache.register(&path);
+        // Marked before either thread starts, so the waiter always waits
+        cache.state().images[image.0].loading = true;
+        std::thread::scope(|scope| {
+            let decoder = scope.spawn(|| {
+                let _loading = Loading {
+                    cache: &cache,
+                    image,
+                };
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                panic!("decoding failed");
+            });
+            let waiter = scope.spawn(|| cache.texel(image, 0.0, 1.0));
+            assert!(decoder.join().is_err());
+            assert_eq!(waiter.join().unwrap(), pixel(0, 0));
+        });
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_image() {
+        let cache = TextureCache::default();
+        let image = cache.register("no/such/texture.ppm");
+        assert_eq!(cache.failure(image), None);
+        assert!(cache.failures().is_empty());
+
+        assert_eq!(cache.texel(image, 0.5, 0.5), MISSING_COLOR);
+        assert_eq!(cache.texel(image, 0.5, 0.5), MISSING_COLOR);
+        assert_eq!(cache.stats().loads, 0);
+        let failures = cache.failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, Path::new("no/such/texture.ppm"));
+        assert_eq!(cache.failure(image).as_ref(), Some(&failures[0].1));
+    }
+}
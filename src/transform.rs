@@ -0,0 +1,294 @@
+//! Affine transforms as 4×4 matrices, for placing objects in the scene.
+
+use crate::aabb::Aabb;
+#[cfg(not(any(feature = "std", test)))]
+use crate::float::Float;
+use crate::interval::Interval;
+use crate::point3::Point3;
+use crate::vec3::Vec3;
+use core::ops::Mul;
+
+/// Determinants smaller than this mean the transform flattens space and cannot
+/// be undone.
+const SINGULAR_EPSILON: f64 = 1e-12;
+
+/// An affine transform: a linear map followed by a translation.
+///
+/// Stored as a row-major 4×4 matrix acting on column vectors, whose bottom row is
+/// always (0, 0, 0, 1). `a * b` is the transform that applies `b` first, then `a`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Transform {
+    m: [[f64; 4]; 4],
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    /// The transform that leaves everything where it is.
+    pub const IDENTITY: Transform = Transform {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    /// Creates the transform whose upper 3×3 is `linear`, followed by moving by `offset`.
+    fn affine(linear: [[f64; 3]; 3], offset: Vec3) -> Self {
+        let row = |i: usize| [linear[i][0], linear[i][1], linear[i][2], offset[i]];
+        Self {
+            m: [row(0), row(1), row(2), [0.0, 0.0, 0.0, 1.0]],
+        }
+    }
+
+    /// Moves everything by `offset`.
+    pub fn translation(offset: Vec3) -> Self {
+        Self::affine([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], offset)
+    }
+
+    /// Stretches everything about the origin by each component of `scale`.
+    pub fn scaling(scale: Vec3) -> Self {
+        Self::affine(
+            [
+                [scale.x(), 0.0, 0.0],
+                [0.0, scale.y(), 0.0],
+                [0.0, 0.0, scale.z()],
+            ],
+            Vec3::new(0.0, 0.0, 0.0),
+        )
+    }
+
+    /// Turns everything `degrees` about the x axis, from y towards z.
+    pub fn rotation_x(degrees: f64) -> Self {
+        let (sin, cos) = sin_cos(degrees);
+        Self::affine(
+            [[1.0, 0.0, 0.0], [0.0, cos, -sin], [0.0, sin, cos]],
+            Vec3::new(0.0, 0.0, 0.0),
+        )
+    }
+
+    /// Turns everything `degrees` about the y axis, from z towards x.
+    pub fn rotation_y(degrees: f64) -> Self {
+        let (sin, cos) = sin_cos(degrees);
+        Self::affine(
+            [[cos, 0.0, sin], [0.0, 1.0, 0.0], [-sin, 0.0, cos]],
+            Vec3::new(0.0, 0.0, 0.0),
+        )
+    }
+
+    /// Turns everything `degrees` about the z axis, from x towards y.
+    pub fn rotation_z(degrees: f64) -> Self {
+        let (sin, cos) = sin_cos(degrees);
+        Self::affine(
+            [[cos, -sin, 0.0], [sin, cos, 0.0], [0.0, 0.0, 1.0]],
+            Vec3::new(0.0, 0.0, 0.0),
+        )
+    }
+
+    /// Returns the determinant of the upper 3×3, which is negative for transforms
+    /// that mirror space and so turn the winding of faces inside out.
+    pub fn determinant(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Returns the transform that undoes this one, or `None` if it flattens space.
+    pub fn inverse(&self) -> Option<Transform> {
+        let m = &self.m;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        // The inverse of the upper 3×3 is its adjugate over its determinant
+        let adjugate = [
+            [
+                cofactor(1, 2, 1, 2),
+                -cofactor(0, 2, 1, 2),
+                cofactor(0, 1, 1, 2),
+            ],
+            [
+                -cofactor(1, 2, 0, 2),
+                cofactor(0, 2, 0, 2),
+                -cofactor(0, 1, 0, 2),
+            ],
+            [
+                cofactor(1, 2, 0, 1),
+                -cofactor(0, 2, 0, 1),
+                cofactor(0, 1, 0, 1),
+            ],
+        ];
+        let determinant =
+            m[0][0] * adjugate[0][0] + m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+        if determinant.abs() <= SINGULAR_EPSILON || !determinant.is_finite() {
+            return None;
+        }
+
+        let linear = adjugate.map(|row| row.map(|value| value / determinant));
+        let offset = Vec3::new(m[0][3], m[1][3], m[2][3]);
+        let apply =
+            |row: [f64; 3]| -(row[0] * offset.x() + row[1] * offset.y() + row[2] * offset.z());
+        let offset = Vec3::new(apply(linear[0]), apply(linear[1]), apply(linear[2]));
+        Some(Self::affine(linear, offset))
+    }
+
+    /// Moves the point `p`.
+    #[inline]
+    pub fn point(&self, p: &Point3) -> Point3 {
+        let v = self.vector(&p.as_vec3());
+        Point3::new(
+            v.x() + self.m[0][3],
+            v.y() + self.m[1][3],
+            v.z() + self.m[2][3],
+        )
+    }
+
+    /// Turns and stretches the direction `v`, which translation does not affect.
+    #[inline]
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        let row = |i: usize| self.m[i][0] * v.x() + self.m[i][1] * v.y() + self.m[i][2] * v.z();
+        Vec3::new(row(0), row(1), row(2))
+    }
+
+    /// Multiplies `v` by the transpose of the upper 3×3.
+    ///
+    /// Called on the inverse of a transform, this carries surface normals through
+    /// the transform so they stay perpendicular to stretched surfaces.
+    #[inline]
+    pub fn transposed_vector(&self, v: &Vec3) -> Vec3 {
+        let column = |j: usize| self.m[0][j] * v.x() + self.m[1][j] * v.y() + self.m[2][j] * v.z();
+        Vec3::new(column(0), column(1), column(2))
+    }
+
+    /// Returns the smallest axis-aligned box enclosing `bbox` once transformed.
+    pub fn bounds(&self, bbox: &Aabb) -> Aabb {
+        let corner = |i: usize| {
+            let pick = |axis: usize| {
+                let interval = bbox.axis_interval(axis);
+                if i & (1 << axis) == 0 {
+                    interval.min()
+                } else {
+                    interval.max()
+                }
+            };
+            self.point(&Point3::new(pick(0), pick(1), pick(2)))
+        };
+        let first = corner(0);
+        let (mut min, mut max) = (first.as_vec3(), first.as_vec3());
+        for i in 1..8 {
+            let p = corner(i).as_vec3();
+            for axis in 0..3 {
+                min[axis] = min[axis].min(p[axis]);
+                max[axis] = max[axis].max(p[axis]);
+            }
+        }
+        Aabb::new(
+            Interval::new(min.x(), max.x()),
+            Interval::new(min.y(), max.y()),
+            Interval::new(min.z(), max.z()),
+        )
+    }
+
+    /// Returns whether every entry is finite.
+    pub fn is_finite(&self) -> bool {
+        self.m.iter().flatten().all(|value| value.is_finite())
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    /// Composes two transforms, applying `rhs` first.
+    fn mul(self, rhs: Transform) -> Transform {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Transform { m }
+    }
+}
+
+/// Returns the sine and cosine of an angle in degrees.
+fn sin_cos(degrees: f64) -> (f64, f64) {
+    let radians = degrees * (core::f64::consts::PI / 180.0);
+    (radians.sin(), radians.cos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-12, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_rotations_and_composition() {
+        let quarter = Transform::rotation_y(90.0);
+        assert_near(
+            quarter.vector(&Vec3::new(0.0, 0.0, 1.0)),
+            Vec3::new(1.0, 0.0, 0.0),
+        );
+        assert_near(
+            Transform::rotation_x(90.0).vector(&Vec3::new(0.0, 1.0, 0.0)),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        assert_near(
+            Transform::rotation_z(90.0).vector(&Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+
+        // Scale, then turn, then move
+        let placed = Transform::translation(Vec3::new(5.0, 0.0, 0.0))
+            * quarter
+            * Transform::scaling(Vec3::new(1.0, 1.0, 2.0));
+        let p = placed.point(&Point3::new(0.0, 3.0, 1.0));
+        assert_near(p.as_vec3(), Vec3::new(7.0, 3.0, 0.0));
+        // Directions ignore the translation
+        assert_near(
+            placed.vector(&Vec3::new(0.0, 0.0, 1.0)),
+            Vec3::new(2.0, 0.0, 0.0),
+        );
+
+        let bounds = quarter.bounds(&Aabb::new(
+            Interval::new(0.0, 1.0),
+            Interval::new(0.0, 1.0),
+            Interval::new(0.0, 2.0),
+        ));
+        assert_near(bounds.diagonal(), Vec3::new(2.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_inverse() {
+        let placed = Transform::translation(Vec3::new(1.0, -2.0, 3.0))
+            * Transform::rotation_x(30.0)
+            * Transform::rotation_y(-50.0)
+            * Transform::scaling(Vec3::new(2.0, 0.5, -1.0));
+        let inverse = placed.inverse().unwrap();
+        let p = Point3::new(0.3, -4.0, 7.5);
+        assert_near(inverse.point(&placed.point(&p)).as_vec3(), p.as_vec3());
+        let product = placed * inverse;
+        for (i, j) in (0..4).flat_map(|i| (0..4).map(move |j| (i, j))) {
+            let expected = if i == j { 1.0 } else { 0.0 };
+            assert!((product.m[i][j] - expected).abs() < 1e-12);
+        }
+
+        // Normals carried by the inverse transpose stay perpendicular to the surface
+        let tangent = Vec3::new(1.0, 1.0, 0.0);
+        let normal = Vec3::new(1.0, -1.0, 0.0);
+        let moved = inverse.transposed_vector(&normal);
+        assert!(placed.vector(&tangent).dot(&moved).abs() < 1e-12);
+
+        assert!((placed.determinant() + 1.0).abs() < 1e-12);
+        assert_eq!(Transform::scaling(Vec3::new(1.0, 0.0, 1.0)).inverse(), None);
+        assert_eq!(Transform::IDENTITY.inverse(), Some(Transform::IDENTITY));
+    }
+}
@@ -0,0 +1,51 @@
+//! Units of length that scene coordinates can be given in.
+//!
+//! The renderer's distance tolerances are chosen for scenes modeled in meters. A
+//! scene in other units tells the camera its units so the tolerances keep the
+//! same physical size: otherwise centimeter scenes suffer self-intersection acne
+//! and kilometer scenes lose small details to the minimum hit distance.
+
+/// The length of one unit of scene coordinates.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Units {
+    Millimeters,
+    Centimeters,
+    #[default]
+    Meters,
+    Kilometers,
+    Inches,
+    Feet,
+}
+
+impl Units {
+    /// Returns how many meters one scene unit spans.
+    pub fn meters_per_unit(&self) -> f64 {
+        match self {
+            Units::Millimeters => 0.001,
+            Units::Centimeters => 0.01,
+            Units::Meters => 1.0,
+            Units::Kilometers => 1000.0,
+            Units::Inches => 0.0254,
+            Units::Feet => 0.3048,
+        }
+    }
+
+    /// Converts a length in meters to the same length in scene units.
+    pub fn scene_length(&self, meters: f64) -> f64 {
+        meters / self.meters_per_unit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scene_length_converts_meters() {
+        assert_eq!(Units::Meters.scene_length(2.0), 2.0);
+        assert!((Units::Centimeters.scene_length(0.001) - 0.1).abs() < 1e-12);
+        assert!((Units::Kilometers.scene_length(0.001) - 1e-6).abs() < 1e-18);
+        assert!((Units::Feet.scene_length(0.3048) - 1.0).abs() < 1e-12);
+    }
+}
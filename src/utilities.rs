@@ -1,4 +1,24 @@
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::hash::Hasher;
+
+thread_local! {
+    /// Per-thread generator behind every random helper, seeded from entropy
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_rng(&mut rand::rng()));
+}
+
+/// Reseed the calling thread's generator so the random helpers repeat exactly
+pub fn seed_thread_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// Run `f` with the calling thread's generator, for APIs that take an explicit `Rng`
+///
+/// Scene generators use this so their draws follow the seed set by `seed_thread_rng`.
+pub fn with_thread_rng<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
 
 /// Generate a random f64 in the range [0.0, 1.0)
 #[inline]
@@ -9,7 +29,35 @@ pub fn random_double() -> f64 {
 /// Generate a random f64 in the range [min, max)
 #[inline]
 pub fn random_double_range(min: f64, max: f64) -> f64 {
-    rand::rng().random_range(min..max)
+    RNG.with(|rng| rng.borrow_mut().random_range(min..max))
+}
+
+/// Generate a random u32
+#[inline]
+pub fn random_u32() -> u32 {
+    RNG.with(|rng| rng.borrow_mut().random())
+}
+
+/// Feed an f64 into a hasher so that values comparing equal hash equally
+///
+/// Negative zero is folded into positive zero; NaN never compares equal so its
+/// hash does not matter.
+#[inline]
+pub fn hash_f64<H: Hasher>(value: f64, state: &mut H) {
+    state.write_u64((value + 0.0).to_bits());
+}
+
+/// Derive a seed from `seed` and `keys`, such as a pixel's coordinates
+///
+/// Each key is mixed in with the SplitMix64 finalizer, so nearby keys give
+/// unrelated seeds.
+pub fn mix_seed(seed: u64, keys: &[u64]) -> u64 {
+    keys.iter().fold(seed, |state, &key| {
+        let mut z = (state ^ key).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    })
 }
 
 /// Convert degrees to radians
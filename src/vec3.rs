@@ -1,10 +1,13 @@
+#[cfg(not(any(feature = "std", test)))]
+use crate::float::Float;
+#[cfg(feature = "std")]
 use crate::utilities::{random_double, random_double_range};
-use rand::Rng;
-use std::fmt;
-use std::ops::{Add, Div, Index, IndexMut, Mul, Neg, Sub};
+use core::fmt;
+use core::ops::{Add, Div, Index, IndexMut, Mul, Neg, Sub};
 
 /// 3D vector for geometric calculations.
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vec3 {
     e: [f64; 3],
 }
@@ -17,19 +20,20 @@ impl Vec3 {
     }
 
     /// Generate a random point in the unit square [-0.5, 0.5)
+    #[cfg(feature = "std")]
     #[inline]
     pub fn sample_square() -> Vec3 {
         Vec3::new(random_double() - 0.5, random_double() - 0.5, 0.0)
     }
 
     /// Generate a random point in the unit disk
+    #[cfg(feature = "std")]
     #[inline]
     pub fn random_in_unit_disk() -> Vec3 {
-        let mut rng = rand::rng();
         loop {
             let p = Vec3::new(
-                rng.random_range(-1.0..1.0),
-                rng.random_range(-1.0..1.0),
+                random_double_range(-1.0, 1.0),
+                random_double_range(-1.0, 1.0),
                 0.0,
             );
             if p.length_squared() < 1.0 {
@@ -96,6 +100,7 @@ impl Vec3 {
     }
 
     /// Returns a random vector in the range [min, max).
+    #[cfg(feature = "std")]
     #[inline]
     pub fn random(min: f64, max: f64) -> Vec3 {
         Vec3::new(
@@ -106,6 +111,7 @@ impl Vec3 {
     }
 
     /// Returns a random vector in the unit sphere.
+    #[cfg(feature = "std")]
     #[inline]
     pub fn random_unit() -> Vec3 {
         loop {
@@ -118,6 +124,7 @@ impl Vec3 {
     }
 
     /// Returns a random vector on the hemisphere.
+    #[cfg(feature = "std")]
     #[inline]
     pub fn random_on_hemisphere(normal: &Vec3) -> Vec3 {
         let on_unit_sphere = Vec3::random_unit();
@@ -128,6 +135,22 @@ impl Vec3 {
         }
     }
 
+    /// Returns true if no component is NaN or infinite.
+    #[inline]
+    pub fn is_finite(&self) -> bool {
+        self.e.iter().all(|c| c.is_finite())
+    }
+
+    /// Returns which of the eight octants the vector points into, from the signs of its components.
+    #[inline]
+    pub fn octant(&self) -> u8 {
+        self.e
+            .iter()
+            .enumerate()
+            .map(|(axis, &c)| ((c < 0.0) as u8) << axis)
+            .sum()
+    }
+
     /// Returns true if the vector is near zero.
     #[inline]
     pub fn near_zero(&self) -> bool {
@@ -408,6 +431,7 @@ mod tests {
         assert_eq!(v.z(), 6.0);
     }
 
+    #[cfg(feature = "std")]
     #[test]
     fn test_vec3_display() {
         let v = Vec3::new(1.1, 2.2, 3.3);
@@ -416,4 +440,19 @@ mod tests {
         assert!(s.contains("2.2"));
         assert!(s.contains("3.3"));
     }
+
+    #[test]
+    fn test_vec3_is_finite() {
+        assert!(Vec3::new(1.0, -2.0, 0.0).is_finite());
+        assert!(!Vec3::new(f64::NAN, 0.0, 0.0).is_finite());
+        assert!(!Vec3::new(0.0, f64::INFINITY, 0.0).is_finite());
+    }
+
+    #[test]
+    fn test_vec3_octant() {
+        assert_eq!(Vec3::new(1.0, 2.0, 3.0).octant(), 0);
+        assert_eq!(Vec3::new(-1.0, 2.0, 3.0).octant(), 1);
+        assert_eq!(Vec3::new(1.0, 2.0, -3.0).octant(), 4);
+        assert_eq!(Vec3::new(-1.0, -2.0, -3.0).octant(), 7);
+    }
 }
@@ -0,0 +1,171 @@
+//! Per-object ray visibility.
+//!
+//! Lets an object be hidden from some kinds of rays while still taking part in the
+//! rest of the light transport, e.g. a bounce card that lights the scene but is
+//! not seen by the camera.
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::point3::Point3;
+use crate::ray::{Ray, RayKind};
+use crate::vec3::Vec3;
+use rand::RngCore;
+use std::sync::Arc;
+
+/// Flags controlling which kinds of rays can hit an object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RayVisibility {
+    /// Visible to primary rays from the camera
+    pub camera: bool,
+    /// Casts shadows, i.e. visible to shadow rays
+    pub shadow: bool,
+    /// Visible to reflected, refracted and diffusely scattered rays
+    pub secondary: bool,
+}
+
+impl RayVisibility {
+    /// Visible to every kind of ray.
+    pub const ALL: RayVisibility = RayVisibility {
+        camera: true,
+        shadow: true,
+        secondary: true,
+    };
+
+    /// Returns true if rays of the given kind can hit the object.
+    #[inline]
+    pub fn is_visible_to(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Shadow => self.shadow,
+            RayKind::Secondary => self.secondary,
+        }
+    }
+}
+
+impl Default for RayVisibility {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// Wraps a hittable so it is only hit by the kinds of rays it is visible to.
+pub struct WithVisibility {
+    object: Box<dyn Hittable>,
+    visibility: RayVisibility,
+}
+
+impl WithVisibility {
+    /// Wraps `object` with the given visibility flags.
+    pub fn new(object: Box<dyn Hittable>, visibility: RayVisibility) -> Self {
+        Self { object, visibility }
+    }
+}
+
+impl Hittable for WithVisibility {
+    #[inline]
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        if !self.visibility.is_visible_to(ray.kind()) {
+            return None;
+        }
+        self.object.hit(ray, ray_t)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.object.bounding_box(time0, time1)
+    }
+
+    fn visit_materials(&mut self, visit: &mut dyn FnMut(&mut Arc<Material>)) {
+        self.object.visit_materials(visit);
+    }
+
+    fn degenerate_reason(&self) -> Option<&'static str> {
+        self.object.degenerate_reason()
+    }
+
+    fn degenerate_primitives(&self) -> usize {
+        self.object.degenerate_primitives()
+    }
+
+    fn surface_at(&self, uv: (f64, f64)) -> Option<HitRecord<'_>> {
+        self.object.surface_at(uv)
+    }
+
+    fn has_texture_space(&self) -> bool {
+        self.object.has_texture_space()
+    }
+
+    fn solid_sphere(&self) -> Option<(Point3, f64)> {
+        self.object.solid_sphere()
+    }
+
+    fn is_sampleable(&self) -> bool {
+        self.object.is_sampleable()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.object.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        self.object.random(origin, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::TestMaterial;
+    use crate::sphere::SphereBuilder;
+    use crate::vec3::Vec3;
+
+    fn hidden_from_camera() -> WithVisibility {
+        let sphere = SphereBuilder::new()
+            .center(Point3::new(0.0, 0.0, -2.0))
+            .radius(0.5)
+            .material(TestMaterial::new())
+            .build()
+            .unwrap();
+        WithVisibility::new(
+            Box::new(sphere),
+            RayVisibility {
+                camera: false,
+                ..RayVisibility::ALL
+            },
+        )
+    }
+
+    #[test]
+    fn test_default_is_visible_to_all() {
+        let visibility = RayVisibility::default();
+        assert!(visibility.is_visible_to(RayKind::Camera));
+        assert!(visibility.is_visible_to(RayKind::Shadow));
+        assert!(visibility.is_visible_to(RayKind::Secondary));
+    }
+
+    #[test]
+    fn test_hidden_from_camera_rays() {
+        let object = hidden_from_camera();
+        let ray = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let interval = Interval::new(0.001, f64::INFINITY);
+
+        assert!(object.hit(&ray, interval).is_none());
+        assert!(
+            object
+                .hit(&ray.with_kind(RayKind::Secondary), interval)
+                .is_some()
+        );
+        assert!(
+            object
+                .hit(&ray.with_kind(RayKind::Shadow), interval)
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_bounding_box_is_unchanged() {
+        let object = hidden_from_camera();
+        assert!(object.bounding_box(0.0, 1.0).is_some());
+    }
+}